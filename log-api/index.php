@@ -36,6 +36,10 @@ if (!is_dir($req)) {
 
 if ($handle = opendir($req)) {
     while (false !== ($entry = readdir($handle))) {
+        if ($entry == "index.json" || substr($entry, 0, 1) == "."
+            || substr($entry, -strlen(".result.json")) == ".result.json") {
+            continue;
+        }
         if ($entry != "." && $entry != "..") {
             if (is_dir($req . '/' . $entry)) {
                 abrt("dir found");
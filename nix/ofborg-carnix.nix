@@ -89,6 +89,13 @@ rec {
     sha256 = "1x2wgaw603jcjwsfvc8s2rpaqjv0aqj8mvws2ahhkvfnwkdf7icw";
     inherit dependencies buildDependencies features;
   };
+  arc_swap_0_4_8_ = { dependencies?[], buildDependencies?[], features?[] }: buildRustCrate {
+    crateName = "arc-swap";
+    version = "0.4.8";
+    authors = [ "Michal 'vorner' Vaner <vorner@vorner.cz>" ];
+    sha256 = "022i67sq9laj3bzfc1y7bkddjmylp8d06rwacd0jw24bnjhs9dnr";
+    inherit dependencies buildDependencies features;
+  };
   backtrace_0_3_5_ = { dependencies?[], buildDependencies?[], features?[] }: buildRustCrate {
     crateName = "backtrace";
     version = "0.3.5";
@@ -146,11 +153,11 @@ rec {
     sha256 = "1wsxnqcscg4gchdmgdbwc78lw2qx2i6bnjd564xq7h7qc4fp2157";
     inherit dependencies buildDependencies features;
   };
-  cc_1_0_4_ = { dependencies?[], buildDependencies?[], features?[] }: buildRustCrate {
+  cc_1_0_25_ = { dependencies?[], buildDependencies?[], features?[] }: buildRustCrate {
     crateName = "cc";
-    version = "1.0.4";
+    version = "1.0.25";
     authors = [ "Alex Crichton <alex@alexcrichton.com>" ];
-    sha256 = "1bhxmq231c62amw14lghz5xi2cjvm0r7kp3ajsj6zqw9843zwkfw";
+    sha256 = "0pd8fhjlpr5qan984frkf1c8nxrqp6827wmmfzhm2840229z2hq0";
     inherit dependencies buildDependencies features;
   };
   cfg_if_0_1_2_ = { dependencies?[], buildDependencies?[], features?[] }: buildRustCrate {
@@ -175,6 +182,16 @@ rec {
     build = "build.rs";
     inherit dependencies buildDependencies features;
   };
+  curl_sys_0_4_16_ = { dependencies?[], buildDependencies?[], features?[] }: buildRustCrate {
+    crateName = "curl-sys";
+    version = "0.4.16";
+    authors = [ "Alex Crichton <alex@alexcrichton.com>" ];
+    sha256 = "03gv0rfaka8qn0sxq20f5l7x72nxyqsyx56waiwz3l13ssk3i1vx";
+    libName = "curl_sys";
+    libPath = "lib.rs";
+    build = "build.rs";
+    inherit dependencies buildDependencies features;
+  };
   dtoa_0_4_2_ = { dependencies?[], buildDependencies?[], features?[] }: buildRustCrate {
     crateName = "dtoa";
     version = "0.4.2";
@@ -217,6 +234,13 @@ rec {
     sha256 = "1xxbzd8cjlpzsb9fsih7mdnndhzrvykj0w77yg90qc85az1xwy5z";
     inherit dependencies buildDependencies features;
   };
+  flate2_1_0_1_ = { dependencies?[], buildDependencies?[], features?[] }: buildRustCrate {
+    crateName = "flate2";
+    version = "1.0.1";
+    authors = [ "Alex Crichton <alex@alexcrichton.com>" ];
+    sha256 = "0hi1r0sz8ca750hq9ym6d3n99g6rmmm8m8hadz2v49pfh6jd6svc";
+    inherit dependencies buildDependencies features;
+  };
   foreign_types_0_3_2_ = { dependencies?[], buildDependencies?[], features?[] }: buildRustCrate {
     crateName = "foreign-types";
     version = "0.3.2";
@@ -252,6 +276,21 @@ rec {
     sha256 = "08jp1zxrm9jbrr6l26bjal4dbm8bxfy57ickdgibsqxr1n9j3hf5";
     inherit dependencies buildDependencies features;
   };
+  git2_0_8_0_ = { dependencies?[], buildDependencies?[], features?[] }: buildRustCrate {
+    crateName = "git2";
+    version = "0.8.0";
+    authors = [ "Alex Crichton <alex@alexcrichton.com>" ];
+    sha256 = "0nkzglhq7lrdzv23jakygv6h5kks2mdr7xh73chnr7bqdc36mi43";
+    inherit dependencies buildDependencies features;
+  };
+  half_1_5_0_ = { dependencies?[], buildDependencies?[], features?[] }: buildRustCrate {
+    crateName = "half";
+    version = "1.5.0";
+    edition = "2018";
+    authors = [ "Kathryn Long <squeeself@gmail.com>" ];
+    sha256 = "076bdjrqyfby50g8p0vivrrgbv47bv2rxlzjhh9vg6yynmbjwiii";
+    inherit dependencies buildDependencies features;
+  };
   httparse_1_2_4_ = { dependencies?[], buildDependencies?[], features?[] }: buildRustCrate {
     crateName = "httparse";
     version = "1.2.4";
@@ -328,11 +367,39 @@ rec {
     sha256 = "0wfvqyr2nvx2mbsrscg5y7gfa9skhb8p72ayanl8vl49pw24v4fh";
     inherit dependencies buildDependencies features;
   };
-  libc_0_2_36_ = { dependencies?[], buildDependencies?[], features?[] }: buildRustCrate {
+  libc_0_2_43_ = { dependencies?[], buildDependencies?[], features?[] }: buildRustCrate {
     crateName = "libc";
-    version = "0.2.36";
+    version = "0.2.43";
     authors = [ "The Rust Project Developers" ];
-    sha256 = "01633h4yfqm0s302fm0dlba469bx8y6cs4nqc8bqrmjqxfxn515l";
+    sha256 = "0pshydmsq71kl9276zc2928ld50sp524ixcqkcqsgq410dx6c50b";
+    inherit dependencies buildDependencies features;
+  };
+  libgit2_sys_0_7_11_ = { dependencies?[], buildDependencies?[], features?[] }: buildRustCrate {
+    crateName = "libgit2-sys";
+    version = "0.7.11";
+    authors = [ "Alex Crichton <alex@alexcrichton.com>" ];
+    sha256 = "12wyfl7xl7lpz65s17j5rf9xfkn461792f67jqsz0ign3daaac9h";
+    libName = "libgit2_sys";
+    libPath = "lib.rs";
+    build = "build.rs";
+    inherit dependencies buildDependencies features;
+  };
+  libssh2_sys_0_2_11_ = { dependencies?[], buildDependencies?[], features?[] }: buildRustCrate {
+    crateName = "libssh2-sys";
+    version = "0.2.11";
+    authors = [ "Alex Crichton <alex@alexcrichton.com>" ];
+    sha256 = "1mjily9qjjjf31pzvlxyqnp1midjc77s6sd303j46d14igna7nhi";
+    libName = "libssh2_sys";
+    libPath = "lib.rs";
+    build = "build.rs";
+    inherit dependencies buildDependencies features;
+  };
+  libz_sys_1_0_25_ = { dependencies?[], buildDependencies?[], features?[] }: buildRustCrate {
+    crateName = "libz-sys";
+    version = "1.0.25";
+    authors = [ "Alex Crichton <alex@alexcrichton.com>" ];
+    sha256 = "195jzg8mgjbvmkbpx1rzkzrqm0g2fdivk79v44c9lzl64r3f9fym";
+    build = "build.rs";
     inherit dependencies buildDependencies features;
   };
   linked_hash_map_0_4_2_ = { dependencies?[], buildDependencies?[], features?[] }: buildRustCrate {
@@ -349,6 +416,13 @@ rec {
     sha256 = "1c43z4z85sxrsgir4s1hi84558ab5ic7jrn5qgmsiqcv90vvn006";
     inherit dependencies buildDependencies features;
   };
+  log_0_4_1_ = { dependencies?[], buildDependencies?[], features?[] }: buildRustCrate {
+    crateName = "log";
+    version = "0.4.1";
+    authors = [ "The Rust Project Developers" ];
+    sha256 = "01vm8yy3wngvyj6qp1x3xpcb4xq7v67yn9l7fsma8kz28mliz90d";
+    inherit dependencies buildDependencies features;
+  };
   lru_cache_0_1_1_ = { dependencies?[], buildDependencies?[], features?[] }: buildRustCrate {
     crateName = "lru-cache";
     version = "0.1.1";
@@ -392,6 +466,16 @@ rec {
     sha256 = "1skwwa0j3kqd8rm9387zgabjhp07zj99q71nzlhba4lrz9r911b3";
     inherit dependencies buildDependencies features;
   };
+  miniz_sys_0_1_10_ = { dependencies?[], buildDependencies?[], features?[] }: buildRustCrate {
+    crateName = "miniz-sys";
+    version = "0.1.10";
+    authors = [ "Alex Crichton <alex@alexcrichton.com>" ];
+    sha256 = "11vg6phafxil87nbxgrlhcx5hjr3145wsbwwkfmibvnmzxfdmvln";
+    libName = "miniz_sys";
+    libPath = "lib.rs";
+    build = "build.rs";
+    inherit dependencies buildDependencies features;
+  };
   native_tls_0_1_5_ = { dependencies?[], buildDependencies?[], features?[] }: buildRustCrate {
     crateName = "native-tls";
     version = "0.1.5";
@@ -429,12 +513,19 @@ rec {
     build = "build.rs";
     inherit dependencies buildDependencies features;
   };
-  openssl_sys_0_9_24_ = { dependencies?[], buildDependencies?[], features?[] }: buildRustCrate {
+  openssl_probe_0_1_1_ = { dependencies?[], buildDependencies?[], features?[] }: buildRustCrate {
+    crateName = "openssl-probe";
+    version = "0.1.1";
+    authors = [ "Alex Crichton <alex@alexcrichton.com>" ];
+    sha256 = "19ykmqfnbmq56nadir0kvap4q1rpcjpmrfpnbc1qkj195dnh66na";
+    inherit dependencies buildDependencies features;
+  };
+  openssl_sys_0_9_38_ = { dependencies?[], buildDependencies?[], features?[] }: buildRustCrate {
     crateName = "openssl-sys";
-    version = "0.9.24";
+    version = "0.9.38";
     authors = [ "Alex Crichton <alex@alexcrichton.com>" "Steven Fackler <sfackler@gmail.com>" ];
-    sha256 = "0f9dppj6x1ynwg1mim9d4y5nm9i2j92dmdf2zhh38dv8h3zhmxkg";
-    build = "build.rs";
+    sha256 = "1jlacadxbh7wi89prdfqyr9pclgvmmcsfviinbqz85sl2f49z0pg";
+    build = "build/main.rs";
     inherit dependencies buildDependencies features;
   };
   percent_encoding_1_0_1_ = { dependencies?[], buildDependencies?[], features?[] }: buildRustCrate {
@@ -445,11 +536,11 @@ rec {
     libPath = "lib.rs";
     inherit dependencies buildDependencies features;
   };
-  pkg_config_0_3_9_ = { dependencies?[], buildDependencies?[], features?[] }: buildRustCrate {
+  pkg_config_0_3_11_ = { dependencies?[], buildDependencies?[], features?[] }: buildRustCrate {
     crateName = "pkg-config";
-    version = "0.3.9";
+    version = "0.3.11";
     authors = [ "Alex Crichton <alex@alexcrichton.com>" ];
-    sha256 = "06k8fxgrsrxj8mjpjcq1n7mn2p1shpxif4zg9y5h09c7vy20s146";
+    sha256 = "177kbs465skvzmb2d9bh7aa5lqm0npfig12awcbd34c6k6nlyr5h";
     inherit dependencies buildDependencies features;
   };
   quote_0_3_15_ = { dependencies?[], buildDependencies?[], features?[] }: buildRustCrate {
@@ -545,6 +636,14 @@ rec {
     sha256 = "1mpx1p6rjhajhxwapxsswqi7fdh3ms2cz6aj0h615wx17ckl6cyw";
     inherit dependencies buildDependencies features;
   };
+  serde_cbor_0_11_1_ = { dependencies?[], buildDependencies?[], features?[] }: buildRustCrate {
+    crateName = "serde_cbor";
+    version = "0.11.1";
+    edition = "2018";
+    authors = [ "Pyfisch <pyfisch@gmail.com>" "Steven Fackler <sfackler@gmail.com>" ];
+    sha256 = "0q7jj8j6b9d1c3a7fbyl389d52h1jaw1f4z68qmxmw7my7mjjxfk";
+    inherit dependencies buildDependencies features;
+  };
   serde_derive_1_0_27_ = { dependencies?[], buildDependencies?[], features?[] }: buildRustCrate {
     crateName = "serde_derive";
     version = "1.0.27";
@@ -560,6 +659,14 @@ rec {
     sha256 = "12gg08kq295npvhrl1ryzgn0rn2az4kvmj7zlzdda4094qwqgrzz";
     inherit dependencies buildDependencies features;
   };
+  serde_ignored_0_1_2_ = { dependencies?[], buildDependencies?[], features?[] }: buildRustCrate {
+    crateName = "serde_ignored";
+    version = "0.1.2";
+    edition = "2018";
+    authors = [ "David Tolnay <dtolnay@gmail.com>" ];
+    sha256 = "0v0v7ki67l78dhn3qd4zvpn6vl37xfzkr9yg1c9bp15w4rkazmls";
+    inherit dependencies buildDependencies features;
+  };
   serde_json_1_0_9_ = { dependencies?[], buildDependencies?[], features?[] }: buildRustCrate {
     crateName = "serde_json";
     version = "1.0.9";
@@ -567,6 +674,21 @@ rec {
     sha256 = "1pcclssyndz54cncsizkqrblmqqr1p2g6xhkpwldbk6qc95m4yw3";
     inherit dependencies buildDependencies features;
   };
+  signal_hook_0_1_17_ = { dependencies?[], buildDependencies?[], features?[] }: buildRustCrate {
+    crateName = "signal-hook";
+    version = "0.1.17";
+    edition = "2018";
+    authors = [ "Michal 'vorner' Vaner <vorner@vorner.cz>" ];
+    sha256 = "0cfs94m9ka8p94whpqvazi543mhwf7fz1gw6llaw229sml6dg32k";
+    inherit dependencies buildDependencies features;
+  };
+  signal_hook_registry_1_2_0_ = { dependencies?[], buildDependencies?[], features?[] }: buildRustCrate {
+    crateName = "signal-hook-registry";
+    version = "1.2.0";
+    authors = [ "Michal 'vorner' Vaner <vorner@vorner.cz>" "Masaki Hara <ackie.h.gmai@gmail.com>" ];
+    sha256 = "0sap7brbb8fp3641nrvfm4rk024hjcmxix0y0zn9sc8vgvnfivxb";
+    inherit dependencies buildDependencies features;
+  };
   syn_0_11_11_ = { dependencies?[], buildDependencies?[], features?[] }: buildRustCrate {
     crateName = "syn";
     version = "0.11.11";
@@ -623,6 +745,13 @@ rec {
     sha256 = "1ryy3bwhvyzj6fym123il38mk9ranm4vradj2a47l5ij8jd7w5if";
     inherit dependencies buildDependencies features;
   };
+  toml_0_4_6_ = { dependencies?[], buildDependencies?[], features?[] }: buildRustCrate {
+    crateName = "toml";
+    version = "0.4.6";
+    authors = [ "Alex Crichton <alex@alexcrichton.com>" ];
+    sha256 = "0rfl7lyb5f67spk69s604nw87f97g7fvv36hj9v88qlr2bwyrn8v";
+    inherit dependencies buildDependencies features;
+  };
   traitobject_0_1_0_ = { dependencies?[], buildDependencies?[], features?[] }: buildRustCrate {
     crateName = "traitobject";
     version = "0.1.0";
@@ -814,11 +943,17 @@ rec {
   antidote_1_0_0_features = f: updateFeatures f (rec {
     antidote_1_0_0.default = (f.antidote_1_0_0.default or true);
   }) [];
+  arc_swap_0_4_8 = { features?(arc_swap_0_4_8_features {}) }: arc_swap_0_4_8_ {
+    features = mkFeatures (features.arc_swap_0_4_8 or {});
+  };
+  arc_swap_0_4_8_features = f: updateFeatures f (rec {
+    arc_swap_0_4_8.default = (f.arc_swap_0_4_8.default or true);
+  }) [];
   backtrace_0_3_5 = { features?(backtrace_0_3_5_features {}) }: backtrace_0_3_5_ {
     dependencies = mapFeatures features ([ cfg_if_0_1_2 rustc_demangle_0_1_5 ])
       ++ (if (kernel == "linux" || kernel == "darwin") && !(kernel == "fuchsia") && !(kernel == "emscripten") && !(kernel == "darwin") && !(kernel == "ios") then mapFeatures features ([ ]
       ++ (if features.backtrace_0_3_5.backtrace-sys or false then [ backtrace_sys_0_1_16 ] else [])) else [])
-      ++ (if (kernel == "linux" || kernel == "darwin") then mapFeatures features ([ libc_0_2_36 ]) else [])
+      ++ (if (kernel == "linux" || kernel == "darwin") then mapFeatures features ([ libc_0_2_43 ]) else [])
       ++ (if kernel == "windows" then mapFeatures features ([ ]
       ++ (if features.backtrace_0_3_5.winapi or false then [ winapi_0_3_4 ] else [])) else []);
     features = mkFeatures (features.backtrace_0_3_5 or {});
@@ -875,7 +1010,7 @@ rec {
       (backtrace_0_3_5.dbghelp or false);
     backtrace_sys_0_1_16.default = true;
     cfg_if_0_1_2.default = true;
-    libc_0_2_36.default = true;
+    libc_0_2_43.default = true;
     rustc_demangle_0_1_5.default = true;
     winapi_0_3_4.dbghelp = true;
     winapi_0_3_4.default = true;
@@ -883,16 +1018,16 @@ rec {
     winapi_0_3_4.processthreadsapi = true;
     winapi_0_3_4.std = true;
     winapi_0_3_4.winnt = true;
-  }) [ cfg_if_0_1_2_features rustc_demangle_0_1_5_features backtrace_sys_0_1_16_features libc_0_2_36_features winapi_0_3_4_features ];
+  }) [ cfg_if_0_1_2_features rustc_demangle_0_1_5_features backtrace_sys_0_1_16_features libc_0_2_43_features winapi_0_3_4_features ];
   backtrace_sys_0_1_16 = { features?(backtrace_sys_0_1_16_features {}) }: backtrace_sys_0_1_16_ {
-    dependencies = mapFeatures features ([ libc_0_2_36 ]);
-    buildDependencies = mapFeatures features ([ cc_1_0_4 ]);
+    dependencies = mapFeatures features ([ libc_0_2_43 ]);
+    buildDependencies = mapFeatures features ([ cc_1_0_25 ]);
   };
   backtrace_sys_0_1_16_features = f: updateFeatures f (rec {
     backtrace_sys_0_1_16.default = (f.backtrace_sys_0_1_16.default or true);
-    cc_1_0_4.default = true;
-    libc_0_2_36.default = true;
-  }) [ libc_0_2_36_features cc_1_0_4_features ];
+    cc_1_0_25.default = true;
+    libc_0_2_43.default = true;
+  }) [ libc_0_2_43_features cc_1_0_25_features ];
   base64_0_6_0 = { features?(base64_0_6_0_features {}) }: base64_0_6_0_ {
     dependencies = mapFeatures features ([ byteorder_1_2_1 safemem_0_2_0 ]);
   };
@@ -947,36 +1082,72 @@ rec {
       (f.byteorder_1_2_1.default or false) ||
       (byteorder_1_2_1.default or false);
   }) [];
-  cc_1_0_4 = { features?(cc_1_0_4_features {}) }: cc_1_0_4_ {
+  cc_1_0_25 = { features?(cc_1_0_25_features {}) }: cc_1_0_25_ {
     dependencies = mapFeatures features ([]);
-    features = mkFeatures (features.cc_1_0_4 or {});
-  };
-  cc_1_0_4_features = f: updateFeatures f (rec {
-    cc_1_0_4.default = (f.cc_1_0_4.default or true);
-    cc_1_0_4.rayon =
-      (f.cc_1_0_4.rayon or false) ||
-      (f.cc_1_0_4.parallel or false) ||
-      (cc_1_0_4.parallel or false);
+    features = mkFeatures (features.cc_1_0_25 or {});
+  };
+  cc_1_0_25_features = f: updateFeatures f (rec {
+    cc_1_0_25.default = (f.cc_1_0_25.default or true);
+    cc_1_0_25.rayon =
+      (f.cc_1_0_25.rayon or false) ||
+      (f.cc_1_0_25.parallel or false) ||
+      (cc_1_0_25.parallel or false);
   }) [];
   cfg_if_0_1_2 = { features?(cfg_if_0_1_2_features {}) }: cfg_if_0_1_2_ {};
   cfg_if_0_1_2_features = f: updateFeatures f (rec {
     cfg_if_0_1_2.default = (f.cfg_if_0_1_2.default or true);
   }) [];
   core_foundation_0_2_3 = { features?(core_foundation_0_2_3_features {}) }: core_foundation_0_2_3_ {
-    dependencies = mapFeatures features ([ core_foundation_sys_0_2_3 libc_0_2_36 ]);
+    dependencies = mapFeatures features ([ core_foundation_sys_0_2_3 libc_0_2_43 ]);
   };
   core_foundation_0_2_3_features = f: updateFeatures f (rec {
     core_foundation_0_2_3.default = (f.core_foundation_0_2_3.default or true);
     core_foundation_sys_0_2_3.default = true;
-    libc_0_2_36.default = true;
-  }) [ core_foundation_sys_0_2_3_features libc_0_2_36_features ];
+    libc_0_2_43.default = true;
+  }) [ core_foundation_sys_0_2_3_features libc_0_2_43_features ];
   core_foundation_sys_0_2_3 = { features?(core_foundation_sys_0_2_3_features {}) }: core_foundation_sys_0_2_3_ {
-    dependencies = mapFeatures features ([ libc_0_2_36 ]);
+    dependencies = mapFeatures features ([ libc_0_2_43 ]);
   };
   core_foundation_sys_0_2_3_features = f: updateFeatures f (rec {
     core_foundation_sys_0_2_3.default = (f.core_foundation_sys_0_2_3.default or true);
-    libc_0_2_36.default = true;
-  }) [ libc_0_2_36_features ];
+    libc_0_2_43.default = true;
+  }) [ libc_0_2_43_features ];
+  curl_sys_0_4_16 = { features?(curl_sys_0_4_16_features {}) }: curl_sys_0_4_16_ {
+    dependencies = mapFeatures features ([ libc_0_2_43 libz_sys_1_0_25 ])
+      ++ (if (kernel == "linux" || kernel == "darwin") && !(kernel == "darwin") then mapFeatures features ([ ]
+      ++ (if features.curl_sys_0_4_16.openssl-sys or false then [ openssl_sys_0_9_38 ] else [])) else [])
+      ++ (if abi == "msvc" then mapFeatures features ([]) else [])
+      ++ (if kernel == "windows" then mapFeatures features ([ winapi_0_3_4 ]) else []);
+    buildDependencies = mapFeatures features ([ cc_1_0_25 pkg_config_0_3_11 ]);
+    features = mkFeatures (features.curl_sys_0_4_16 or {});
+  };
+  curl_sys_0_4_16_features = f: updateFeatures f (rec {
+    cc_1_0_25.default = true;
+    curl_sys_0_4_16.default = (f.curl_sys_0_4_16.default or true);
+    curl_sys_0_4_16.libnghttp2-sys =
+      (f.curl_sys_0_4_16.libnghttp2-sys or false) ||
+      (f.curl_sys_0_4_16.http2 or false) ||
+      (curl_sys_0_4_16.http2 or false);
+    curl_sys_0_4_16.openssl-sys =
+      (f.curl_sys_0_4_16.openssl-sys or false) ||
+      (f.curl_sys_0_4_16.ssl or false) ||
+      (curl_sys_0_4_16.ssl or false);
+    curl_sys_0_4_16.ssl =
+      (f.curl_sys_0_4_16.ssl or false) ||
+      (f.curl_sys_0_4_16.default or false) ||
+      (curl_sys_0_4_16.default or false);
+    libc_0_2_43.default = true;
+    libz_sys_1_0_25.default = true;
+    openssl_sys_0_9_38.default = true;
+    openssl_sys_0_9_38.vendored =
+      (f.openssl_sys_0_9_38.vendored or false) ||
+      (curl_sys_0_4_16.static-ssl or false) ||
+      (f.curl_sys_0_4_16.static-ssl or false);
+    pkg_config_0_3_11.default = true;
+    winapi_0_3_4.default = true;
+    winapi_0_3_4.winsock2 = true;
+    winapi_0_3_4.ws2def = true;
+  }) [ libc_0_2_43_features libz_sys_1_0_25_features openssl_sys_0_9_38_features winapi_0_3_4_features cc_1_0_25_features pkg_config_0_3_11_features ];
   dtoa_0_4_2 = { features?(dtoa_0_4_2_features {}) }: dtoa_0_4_2_ {};
   dtoa_0_4_2_features = f: updateFeatures f (rec {
     dtoa_0_4_2.default = (f.dtoa_0_4_2.default or true);
@@ -1044,6 +1215,36 @@ rec {
       (f.error_chain_0_10_0.default or false) ||
       (error_chain_0_10_0.default or false);
   }) [ backtrace_0_3_5_features ];
+  flate2_1_0_1 = { features?(flate2_1_0_1_features {}) }: flate2_1_0_1_ {
+    dependencies = mapFeatures features ([ libc_0_2_43 ]
+      ++ (if features.flate2_1_0_1.miniz-sys or false then [ miniz_sys_0_1_10 ] else []));
+    features = mkFeatures (features.flate2_1_0_1 or {});
+  };
+  flate2_1_0_1_features = f: updateFeatures f (rec {
+    flate2_1_0_1.default = (f.flate2_1_0_1.default or true);
+    flate2_1_0_1.futures =
+      (f.flate2_1_0_1.futures or false) ||
+      (f.flate2_1_0_1.tokio or false) ||
+      (flate2_1_0_1.tokio or false);
+    flate2_1_0_1.libz-sys =
+      (f.flate2_1_0_1.libz-sys or false) ||
+      (f.flate2_1_0_1.zlib or false) ||
+      (flate2_1_0_1.zlib or false);
+    flate2_1_0_1.miniz-sys =
+      (f.flate2_1_0_1.miniz-sys or false) ||
+      (f.flate2_1_0_1.default or false) ||
+      (flate2_1_0_1.default or false);
+    flate2_1_0_1.miniz_oxide_c_api =
+      (f.flate2_1_0_1.miniz_oxide_c_api or false) ||
+      (f.flate2_1_0_1.rust_backend or false) ||
+      (flate2_1_0_1.rust_backend or false);
+    flate2_1_0_1.tokio-io =
+      (f.flate2_1_0_1.tokio-io or false) ||
+      (f.flate2_1_0_1.tokio or false) ||
+      (flate2_1_0_1.tokio or false);
+    libc_0_2_43.default = true;
+    miniz_sys_0_1_10.default = true;
+  }) [ libc_0_2_43_features miniz_sys_0_1_10_features ];
   foreign_types_0_3_2 = { features?(foreign_types_0_3_2_features {}) }: foreign_types_0_3_2_ {
     dependencies = mapFeatures features ([ foreign_types_shared_0_1_1 ]);
   };
@@ -1056,12 +1257,12 @@ rec {
     foreign_types_shared_0_1_1.default = (f.foreign_types_shared_0_1_1.default or true);
   }) [];
   fs2_0_4_3 = { features?(fs2_0_4_3_features {}) }: fs2_0_4_3_ {
-    dependencies = (if (kernel == "linux" || kernel == "darwin") then mapFeatures features ([ libc_0_2_36 ]) else [])
+    dependencies = (if (kernel == "linux" || kernel == "darwin") then mapFeatures features ([ libc_0_2_43 ]) else [])
       ++ (if kernel == "windows" then mapFeatures features ([ winapi_0_3_4 ]) else []);
   };
   fs2_0_4_3_features = f: updateFeatures f (rec {
     fs2_0_4_3.default = (f.fs2_0_4_3.default or true);
-    libc_0_2_36.default = true;
+    libc_0_2_43.default = true;
     winapi_0_3_4.default = true;
     winapi_0_3_4.fileapi = true;
     winapi_0_3_4.handleapi = true;
@@ -1069,7 +1270,7 @@ rec {
     winapi_0_3_4.std = true;
     winapi_0_3_4.winbase = true;
     winapi_0_3_4.winerror = true;
-  }) [ libc_0_2_36_features winapi_0_3_4_features ];
+  }) [ libc_0_2_43_features winapi_0_3_4_features ];
   fuchsia_zircon_0_3_3 = { features?(fuchsia_zircon_0_3_3_features {}) }: fuchsia_zircon_0_3_3_ {
     dependencies = mapFeatures features ([ bitflags_1_0_1 fuchsia_zircon_sys_0_3_3 ]);
   };
@@ -1082,6 +1283,82 @@ rec {
   fuchsia_zircon_sys_0_3_3_features = f: updateFeatures f (rec {
     fuchsia_zircon_sys_0_3_3.default = (f.fuchsia_zircon_sys_0_3_3.default or true);
   }) [];
+  git2_0_8_0 = { features?(git2_0_8_0_features {}) }: git2_0_8_0_ {
+    dependencies = mapFeatures features ([ bitflags_1_0_1 libc_0_2_43 libgit2_sys_0_7_11 log_0_4_1 url_1_6_0 ])
+      ++ (if (kernel == "linux" || kernel == "darwin") && !(kernel == "darwin") then mapFeatures features ([ ]
+      ++ (if features.git2_0_8_0.openssl-probe or false then [ openssl_probe_0_1_1 ] else [])
+      ++ (if features.git2_0_8_0.openssl-sys or false then [ openssl_sys_0_9_38 ] else [])) else []);
+    features = mkFeatures (features.git2_0_8_0 or {});
+  };
+  git2_0_8_0_features = f: updateFeatures f (rec {
+    bitflags_1_0_1.default = true;
+    git2_0_8_0.curl =
+      (f.git2_0_8_0.curl or false) ||
+      (f.git2_0_8_0.default or false) ||
+      (git2_0_8_0.default or false);
+    git2_0_8_0.default = (f.git2_0_8_0.default or true);
+    git2_0_8_0.https =
+      (f.git2_0_8_0.https or false) ||
+      (f.git2_0_8_0.default or false) ||
+      (git2_0_8_0.default or false);
+    git2_0_8_0.openssl-probe =
+      (f.git2_0_8_0.openssl-probe or false) ||
+      (f.git2_0_8_0.https or false) ||
+      (git2_0_8_0.https or false);
+    git2_0_8_0.openssl-sys =
+      (f.git2_0_8_0.openssl-sys or false) ||
+      (f.git2_0_8_0.https or false) ||
+      (git2_0_8_0.https or false);
+    git2_0_8_0.ssh =
+      (f.git2_0_8_0.ssh or false) ||
+      (f.git2_0_8_0.default or false) ||
+      (git2_0_8_0.default or false);
+    git2_0_8_0.ssh_key_from_memory =
+      (f.git2_0_8_0.ssh_key_from_memory or false) ||
+      (f.git2_0_8_0.default or false) ||
+      (git2_0_8_0.default or false);
+    libc_0_2_43.default = true;
+    libgit2_sys_0_7_11.curl =
+      (f.libgit2_sys_0_7_11.curl or false) ||
+      (git2_0_8_0.curl or false) ||
+      (f.git2_0_8_0.curl or false);
+    libgit2_sys_0_7_11.default = true;
+    libgit2_sys_0_7_11.https =
+      (f.libgit2_sys_0_7_11.https or false) ||
+      (git2_0_8_0.https or false) ||
+      (f.git2_0_8_0.https or false);
+    libgit2_sys_0_7_11.ssh =
+      (f.libgit2_sys_0_7_11.ssh or false) ||
+      (git2_0_8_0.ssh or false) ||
+      (f.git2_0_8_0.ssh or false);
+    libgit2_sys_0_7_11.ssh_key_from_memory =
+      (f.libgit2_sys_0_7_11.ssh_key_from_memory or false) ||
+      (git2_0_8_0.ssh_key_from_memory or false) ||
+      (f.git2_0_8_0.ssh_key_from_memory or false);
+    log_0_4_1.default = true;
+    openssl_probe_0_1_1.default = true;
+    openssl_sys_0_9_38.default = true;
+    openssl_sys_0_9_38.vendored =
+      (f.openssl_sys_0_9_38.vendored or false) ||
+      (git2_0_8_0.vendored-openssl or false) ||
+      (f.git2_0_8_0.vendored-openssl or false);
+    url_1_6_0.default = true;
+  }) [ bitflags_1_0_1_features libc_0_2_43_features libgit2_sys_0_7_11_features log_0_4_1_features url_1_6_0_features openssl_probe_0_1_1_features openssl_sys_0_9_38_features ];
+  half_1_5_0 = { features?(half_1_5_0_features {}) }: half_1_5_0_ {
+    dependencies = mapFeatures features ([]);
+    features = mkFeatures (features.half_1_5_0 or {});
+  };
+  half_1_5_0_features = f: updateFeatures f (rec {
+    half_1_5_0.alloc =
+      (f.half_1_5_0.alloc or false) ||
+      (f.half_1_5_0.std or false) ||
+      (half_1_5_0.std or false);
+    half_1_5_0.default = (f.half_1_5_0.default or true);
+    half_1_5_0.serde =
+      (f.half_1_5_0.serde or false) ||
+      (f.half_1_5_0.serialize or false) ||
+      (half_1_5_0.serialize or false);
+  }) [];
   httparse_1_2_4 = { features?(httparse_1_2_4_features {}) }: httparse_1_2_4_ {
     features = mkFeatures (features.httparse_1_2_4 or {});
   };
@@ -1209,16 +1486,73 @@ rec {
       (f.lazy_static_1_0_0.spin_no_std or false) ||
       (lazy_static_1_0_0.spin_no_std or false);
   }) [];
-  libc_0_2_36 = { features?(libc_0_2_36_features {}) }: libc_0_2_36_ {
-    features = mkFeatures (features.libc_0_2_36 or {});
-  };
-  libc_0_2_36_features = f: updateFeatures f (rec {
-    libc_0_2_36.default = (f.libc_0_2_36.default or true);
-    libc_0_2_36.use_std =
-      (f.libc_0_2_36.use_std or false) ||
-      (f.libc_0_2_36.default or false) ||
-      (libc_0_2_36.default or false);
+  libc_0_2_43 = { features?(libc_0_2_43_features {}) }: libc_0_2_43_ {
+    features = mkFeatures (features.libc_0_2_43 or {});
+  };
+  libc_0_2_43_features = f: updateFeatures f (rec {
+    libc_0_2_43.default = (f.libc_0_2_43.default or true);
+    libc_0_2_43.use_std =
+      (f.libc_0_2_43.use_std or false) ||
+      (f.libc_0_2_43.default or false) ||
+      (libc_0_2_43.default or false);
   }) [];
+  libgit2_sys_0_7_11 = { features?(libgit2_sys_0_7_11_features {}) }: libgit2_sys_0_7_11_ {
+    dependencies = mapFeatures features ([ libc_0_2_43 libz_sys_1_0_25 ]
+      ++ (if features.libgit2_sys_0_7_11.curl-sys or false then [ curl_sys_0_4_16 ] else [])
+      ++ (if features.libgit2_sys_0_7_11.libssh2-sys or false then [ libssh2_sys_0_2_11 ] else []))
+      ++ (if (kernel == "linux" || kernel == "darwin") then mapFeatures features ([ ]
+      ++ (if features.libgit2_sys_0_7_11.openssl-sys or false then [ openssl_sys_0_9_38 ] else [])) else []);
+    buildDependencies = mapFeatures features ([ cc_1_0_25 pkg_config_0_3_11 ]);
+    features = mkFeatures (features.libgit2_sys_0_7_11 or {});
+  };
+  libgit2_sys_0_7_11_features = f: updateFeatures f (rec {
+    cc_1_0_25.default = true;
+    curl_sys_0_4_16.default = true;
+    libc_0_2_43.default = true;
+    libgit2_sys_0_7_11.curl-sys =
+      (f.libgit2_sys_0_7_11.curl-sys or false) ||
+      (f.libgit2_sys_0_7_11.curl or false) ||
+      (libgit2_sys_0_7_11.curl or false);
+    libgit2_sys_0_7_11.default = (f.libgit2_sys_0_7_11.default or true);
+    libgit2_sys_0_7_11.libssh2-sys =
+      (f.libgit2_sys_0_7_11.libssh2-sys or false) ||
+      (f.libgit2_sys_0_7_11.ssh or false) ||
+      (libgit2_sys_0_7_11.ssh or false);
+    libgit2_sys_0_7_11.openssl-sys =
+      (f.libgit2_sys_0_7_11.openssl-sys or false) ||
+      (f.libgit2_sys_0_7_11.https or false) ||
+      (libgit2_sys_0_7_11.https or false);
+    libssh2_sys_0_2_11.default = true;
+    libz_sys_1_0_25.default = true;
+    openssl_sys_0_9_38.default = true;
+    pkg_config_0_3_11.default = true;
+  }) [ curl_sys_0_4_16_features libc_0_2_43_features libssh2_sys_0_2_11_features libz_sys_1_0_25_features openssl_sys_0_9_38_features cc_1_0_25_features pkg_config_0_3_11_features ];
+  libssh2_sys_0_2_11 = { features?(libssh2_sys_0_2_11_features {}) }: libssh2_sys_0_2_11_ {
+    dependencies = mapFeatures features ([ libc_0_2_43 libz_sys_1_0_25 ])
+      ++ (if abi == "msvc" then mapFeatures features ([]) else [])
+      ++ (if (kernel == "linux" || kernel == "darwin") then mapFeatures features ([ openssl_sys_0_9_38 ]) else []);
+    buildDependencies = mapFeatures features ([ cc_1_0_25 pkg_config_0_3_11 ]);
+  };
+  libssh2_sys_0_2_11_features = f: updateFeatures f (rec {
+    cc_1_0_25.default = true;
+    libc_0_2_43.default = true;
+    libssh2_sys_0_2_11.default = (f.libssh2_sys_0_2_11.default or true);
+    libz_sys_1_0_25.default = true;
+    openssl_sys_0_9_38.default = true;
+    pkg_config_0_3_11.default = true;
+  }) [ libc_0_2_43_features libz_sys_1_0_25_features openssl_sys_0_9_38_features cc_1_0_25_features pkg_config_0_3_11_features ];
+  libz_sys_1_0_25 = { features?(libz_sys_1_0_25_features {}) }: libz_sys_1_0_25_ {
+    dependencies = mapFeatures features ([ libc_0_2_43 ])
+      ++ (if abi == "msvc" then mapFeatures features ([]) else []);
+    buildDependencies = mapFeatures features ([ cc_1_0_25 pkg_config_0_3_11 ]);
+    features = mkFeatures (features.libz_sys_1_0_25 or {});
+  };
+  libz_sys_1_0_25_features = f: updateFeatures f (rec {
+    cc_1_0_25.default = true;
+    libc_0_2_43.default = true;
+    libz_sys_1_0_25.default = (f.libz_sys_1_0_25.default or true);
+    pkg_config_0_3_11.default = true;
+  }) [ libc_0_2_43_features cc_1_0_25_features pkg_config_0_3_11_features ];
   linked_hash_map_0_4_2 = { features?(linked_hash_map_0_4_2_features {}) }: linked_hash_map_0_4_2_ {
     dependencies = mapFeatures features ([]);
     features = mkFeatures (features.linked_hash_map_0_4_2 or {});
@@ -1248,6 +1582,14 @@ rec {
       (f.log_0_3_8.default or false) ||
       (log_0_3_8.default or false);
   }) [];
+  log_0_4_1 = { features?(log_0_4_1_features {}) }: log_0_4_1_ {
+    dependencies = mapFeatures features ([ cfg_if_0_1_2 ]);
+    features = mkFeatures (features.log_0_4_1 or {});
+  };
+  log_0_4_1_features = f: updateFeatures f (rec {
+    cfg_if_0_1_2.default = true;
+    log_0_4_1.default = (f.log_0_4_1.default or true);
+  }) [ cfg_if_0_1_2_features ];
   lru_cache_0_1_1 = { features?(lru_cache_0_1_1_features {}) }: lru_cache_0_1_1_ {
     dependencies = mapFeatures features ([ linked_hash_map_0_4_2 ]);
     features = mkFeatures (features.lru_cache_0_1_1 or {});
@@ -1273,21 +1615,21 @@ rec {
     md5_0_3_6.default = (f.md5_0_3_6.default or true);
   }) [];
   memchr_0_1_11 = { features?(memchr_0_1_11_features {}) }: memchr_0_1_11_ {
-    dependencies = mapFeatures features ([ libc_0_2_36 ]);
+    dependencies = mapFeatures features ([ libc_0_2_43 ]);
   };
   memchr_0_1_11_features = f: updateFeatures f (rec {
-    libc_0_2_36.default = true;
+    libc_0_2_43.default = true;
     memchr_0_1_11.default = (f.memchr_0_1_11.default or true);
-  }) [ libc_0_2_36_features ];
+  }) [ libc_0_2_43_features ];
   memchr_2_0_1 = { features?(memchr_2_0_1_features {}) }: memchr_2_0_1_ {
     dependencies = mapFeatures features ([ ]
-      ++ (if features.memchr_2_0_1.libc or false then [ libc_0_2_36 ] else []));
+      ++ (if features.memchr_2_0_1.libc or false then [ libc_0_2_43 ] else []));
     features = mkFeatures (features.memchr_2_0_1 or {});
   };
   memchr_2_0_1_features = f: updateFeatures f (rec {
-    libc_0_2_36.default = (f.libc_0_2_36.default or false);
-    libc_0_2_36.use_std =
-      (f.libc_0_2_36.use_std or false) ||
+    libc_0_2_43.default = (f.libc_0_2_43.default or false);
+    libc_0_2_43.use_std =
+      (f.libc_0_2_43.use_std or false) ||
       (memchr_2_0_1.use_std or false) ||
       (f.memchr_2_0_1.use_std or false);
     memchr_2_0_1.default = (f.memchr_2_0_1.default or true);
@@ -1301,7 +1643,7 @@ rec {
       (f.memchr_2_0_1.use_std or false) ||
       (f.memchr_2_0_1.default or false) ||
       (memchr_2_0_1.default or false);
-  }) [ libc_0_2_36_features ];
+  }) [ libc_0_2_43_features ];
   mime_0_2_6 = { features?(mime_0_2_6_features {}) }: mime_0_2_6_ {
     dependencies = mapFeatures features ([ log_0_3_8 ]);
     features = mkFeatures (features.mime_0_2_6 or {});
@@ -1314,15 +1656,24 @@ rec {
       (f.mime_0_2_6.heap_size or false) ||
       (mime_0_2_6.heap_size or false);
   }) [ log_0_3_8_features ];
+  miniz_sys_0_1_10 = { features?(miniz_sys_0_1_10_features {}) }: miniz_sys_0_1_10_ {
+    dependencies = mapFeatures features ([ libc_0_2_43 ]);
+    buildDependencies = mapFeatures features ([ cc_1_0_25 ]);
+  };
+  miniz_sys_0_1_10_features = f: updateFeatures f (rec {
+    cc_1_0_25.default = true;
+    libc_0_2_43.default = true;
+    miniz_sys_0_1_10.default = (f.miniz_sys_0_1_10.default or true);
+  }) [ libc_0_2_43_features cc_1_0_25_features ];
   native_tls_0_1_5 = { features?(native_tls_0_1_5_features {}) }: native_tls_0_1_5_ {
     dependencies = mapFeatures features ([ lazy_static_0_2_11 ])
-      ++ (if kernel == "darwin" || kernel == "ios" then mapFeatures features ([ libc_0_2_36 security_framework_0_1_16 security_framework_sys_0_1_16 tempdir_0_3_5 ]) else [])
+      ++ (if kernel == "darwin" || kernel == "ios" then mapFeatures features ([ libc_0_2_43 security_framework_0_1_16 security_framework_sys_0_1_16 tempdir_0_3_5 ]) else [])
       ++ (if !(kernel == "windows" || kernel == "darwin" || kernel == "ios") then mapFeatures features ([ openssl_0_9_23 ]) else [])
       ++ (if kernel == "windows" then mapFeatures features ([ schannel_0_1_10 ]) else []);
   };
   native_tls_0_1_5_features = f: updateFeatures f (rec {
     lazy_static_0_2_11.default = true;
-    libc_0_2_36.default = true;
+    libc_0_2_43.default = true;
     native_tls_0_1_5.default = (f.native_tls_0_1_5.default or true);
     openssl_0_9_23.default = true;
     schannel_0_1_10.default = true;
@@ -1330,26 +1681,29 @@ rec {
     security_framework_0_1_16.default = true;
     security_framework_sys_0_1_16.default = true;
     tempdir_0_3_5.default = true;
-  }) [ lazy_static_0_2_11_features libc_0_2_36_features security_framework_0_1_16_features security_framework_sys_0_1_16_features tempdir_0_3_5_features openssl_0_9_23_features schannel_0_1_10_features ];
+  }) [ lazy_static_0_2_11_features libc_0_2_43_features security_framework_0_1_16_features security_framework_sys_0_1_16_features tempdir_0_3_5_features openssl_0_9_23_features schannel_0_1_10_features ];
   num_traits_0_1_41 = { features?(num_traits_0_1_41_features {}) }: num_traits_0_1_41_ {};
   num_traits_0_1_41_features = f: updateFeatures f (rec {
     num_traits_0_1_41.default = (f.num_traits_0_1_41.default or true);
   }) [];
   num_cpus_1_8_0 = { features?(num_cpus_1_8_0_features {}) }: num_cpus_1_8_0_ {
-    dependencies = mapFeatures features ([ libc_0_2_36 ]);
+    dependencies = mapFeatures features ([ libc_0_2_43 ]);
   };
   num_cpus_1_8_0_features = f: updateFeatures f (rec {
-    libc_0_2_36.default = true;
+    libc_0_2_43.default = true;
     num_cpus_1_8_0.default = (f.num_cpus_1_8_0.default or true);
-  }) [ libc_0_2_36_features ];
+  }) [ libc_0_2_43_features ];
   ofborg_0_1_3 = { features?(ofborg_0_1_3_features {}) }: ofborg_0_1_3_ {
-    dependencies = mapFeatures features ([ amqp_0_1_0 either_1_4_0 env_logger_0_4_3 fs2_0_4_3 hubcaps_0_3_16 hyper_0_10_13 hyper_native_tls_0_2_4 log_0_3_8 lru_cache_0_1_1 md5_0_3_6 serde_1_0_27 serde_derive_1_0_27 serde_json_1_0_9 tempfile_2_2_0 uuid_0_4_0 ]);
+    dependencies = mapFeatures features ([ amqp_0_1_0 base64_0_6_0 either_1_4_0 env_logger_0_4_3 flate2_1_0_1 fs2_0_4_3 git2_0_8_0 hubcaps_0_3_16 hyper_0_10_13 hyper_native_tls_0_2_4 log_0_3_8 lru_cache_0_1_1 md5_0_3_6 openssl_0_9_23 serde_1_0_27 serde_cbor_0_11_1 serde_derive_1_0_27 serde_ignored_0_1_2 serde_json_1_0_9 signal_hook_0_1_17 tempfile_2_2_0 toml_0_4_6 uuid_0_4_0 ]);
   };
   ofborg_0_1_3_features = f: updateFeatures f (rec {
     amqp_0_1_0.default = true;
+    base64_0_6_0.default = true;
     either_1_4_0.default = true;
     env_logger_0_4_3.default = true;
+    flate2_1_0_1.default = true;
     fs2_0_4_3.default = true;
+    git2_0_8_0.default = true;
     hubcaps_0_3_16.default = true;
     hyper_0_10_13.default = true;
     hyper_native_tls_0_2_4.default = true;
@@ -1357,62 +1711,76 @@ rec {
     lru_cache_0_1_1.default = true;
     md5_0_3_6.default = true;
     ofborg_0_1_3.default = (f.ofborg_0_1_3.default or true);
+    openssl_0_9_23.default = true;
     serde_1_0_27.default = true;
+    serde_cbor_0_11_1.default = true;
     serde_derive_1_0_27.default = true;
+    serde_ignored_0_1_2.default = true;
     serde_json_1_0_9.default = true;
+    signal_hook_0_1_17.default = true;
     tempfile_2_2_0.default = true;
+    toml_0_4_6.default = true;
     uuid_0_4_0.default = true;
     uuid_0_4_0.v4 = true;
-  }) [ amqp_0_1_0_features either_1_4_0_features env_logger_0_4_3_features fs2_0_4_3_features hubcaps_0_3_16_features hyper_0_10_13_features hyper_native_tls_0_2_4_features log_0_3_8_features lru_cache_0_1_1_features md5_0_3_6_features serde_1_0_27_features serde_derive_1_0_27_features serde_json_1_0_9_features tempfile_2_2_0_features uuid_0_4_0_features ];
+  }) [ amqp_0_1_0_features base64_0_6_0_features either_1_4_0_features env_logger_0_4_3_features flate2_1_0_1_features fs2_0_4_3_features git2_0_8_0_features hubcaps_0_3_16_features hyper_0_10_13_features hyper_native_tls_0_2_4_features log_0_3_8_features lru_cache_0_1_1_features md5_0_3_6_features openssl_0_9_23_features serde_1_0_27_features serde_cbor_0_11_1_features serde_derive_1_0_27_features serde_ignored_0_1_2_features serde_json_1_0_9_features signal_hook_0_1_17_features tempfile_2_2_0_features toml_0_4_6_features uuid_0_4_0_features ];
   openssl_0_9_23 = { features?(openssl_0_9_23_features {}) }: openssl_0_9_23_ {
-    dependencies = mapFeatures features ([ bitflags_0_9_1 foreign_types_0_3_2 lazy_static_1_0_0 libc_0_2_36 openssl_sys_0_9_24 ]);
+    dependencies = mapFeatures features ([ bitflags_0_9_1 foreign_types_0_3_2 lazy_static_1_0_0 libc_0_2_43 openssl_sys_0_9_38 ]);
     features = mkFeatures (features.openssl_0_9_23 or {});
   };
   openssl_0_9_23_features = f: updateFeatures f (rec {
     bitflags_0_9_1.default = true;
     foreign_types_0_3_2.default = true;
     lazy_static_1_0_0.default = true;
-    libc_0_2_36.default = true;
+    libc_0_2_43.default = true;
     openssl_0_9_23.default = (f.openssl_0_9_23.default or true);
-    openssl_sys_0_9_24.default = true;
-  }) [ bitflags_0_9_1_features foreign_types_0_3_2_features lazy_static_1_0_0_features libc_0_2_36_features openssl_sys_0_9_24_features ];
-  openssl_sys_0_9_24 = { features?(openssl_sys_0_9_24_features {}) }: openssl_sys_0_9_24_ {
-    dependencies = mapFeatures features ([ libc_0_2_36 ])
+    openssl_sys_0_9_38.default = true;
+  }) [ bitflags_0_9_1_features foreign_types_0_3_2_features lazy_static_1_0_0_features libc_0_2_43_features openssl_sys_0_9_38_features ];
+  openssl_probe_0_1_1 = { features?(openssl_probe_0_1_1_features {}) }: openssl_probe_0_1_1_ {};
+  openssl_probe_0_1_1_features = f: updateFeatures f (rec {
+    openssl_probe_0_1_1.default = (f.openssl_probe_0_1_1.default or true);
+  }) [];
+  openssl_sys_0_9_38 = { features?(openssl_sys_0_9_38_features {}) }: openssl_sys_0_9_38_ {
+    dependencies = mapFeatures features ([ libc_0_2_43 ])
       ++ (if abi == "msvc" then mapFeatures features ([]) else []);
-    buildDependencies = mapFeatures features ([ cc_1_0_4 pkg_config_0_3_9 ]);
-  };
-  openssl_sys_0_9_24_features = f: updateFeatures f (rec {
-    cc_1_0_4.default = true;
-    libc_0_2_36.default = true;
-    openssl_sys_0_9_24.default = (f.openssl_sys_0_9_24.default or true);
-    pkg_config_0_3_9.default = true;
-  }) [ libc_0_2_36_features cc_1_0_4_features pkg_config_0_3_9_features ];
+    buildDependencies = mapFeatures features ([ cc_1_0_25 pkg_config_0_3_11 ]);
+    features = mkFeatures (features.openssl_sys_0_9_38 or {});
+  };
+  openssl_sys_0_9_38_features = f: updateFeatures f (rec {
+    cc_1_0_25.default = true;
+    libc_0_2_43.default = true;
+    openssl_sys_0_9_38.default = (f.openssl_sys_0_9_38.default or true);
+    openssl_sys_0_9_38.openssl-src =
+      (f.openssl_sys_0_9_38.openssl-src or false) ||
+      (f.openssl_sys_0_9_38.vendored or false) ||
+      (openssl_sys_0_9_38.vendored or false);
+    pkg_config_0_3_11.default = true;
+  }) [ libc_0_2_43_features cc_1_0_25_features pkg_config_0_3_11_features ];
   percent_encoding_1_0_1 = { features?(percent_encoding_1_0_1_features {}) }: percent_encoding_1_0_1_ {};
   percent_encoding_1_0_1_features = f: updateFeatures f (rec {
     percent_encoding_1_0_1.default = (f.percent_encoding_1_0_1.default or true);
   }) [];
-  pkg_config_0_3_9 = { features?(pkg_config_0_3_9_features {}) }: pkg_config_0_3_9_ {};
-  pkg_config_0_3_9_features = f: updateFeatures f (rec {
-    pkg_config_0_3_9.default = (f.pkg_config_0_3_9.default or true);
+  pkg_config_0_3_11 = { features?(pkg_config_0_3_11_features {}) }: pkg_config_0_3_11_ {};
+  pkg_config_0_3_11_features = f: updateFeatures f (rec {
+    pkg_config_0_3_11.default = (f.pkg_config_0_3_11.default or true);
   }) [];
   quote_0_3_15 = { features?(quote_0_3_15_features {}) }: quote_0_3_15_ {};
   quote_0_3_15_features = f: updateFeatures f (rec {
     quote_0_3_15.default = (f.quote_0_3_15.default or true);
   }) [];
   rand_0_3_20 = { features?(rand_0_3_20_features {}) }: rand_0_3_20_ {
-    dependencies = mapFeatures features ([ libc_0_2_36 ])
+    dependencies = mapFeatures features ([ libc_0_2_43 ])
       ++ (if kernel == "fuchsia" then mapFeatures features ([ fuchsia_zircon_0_3_3 ]) else []);
     features = mkFeatures (features.rand_0_3_20 or {});
   };
   rand_0_3_20_features = f: updateFeatures f (rec {
     fuchsia_zircon_0_3_3.default = true;
-    libc_0_2_36.default = true;
+    libc_0_2_43.default = true;
     rand_0_3_20.default = (f.rand_0_3_20.default or true);
     rand_0_3_20.i128_support =
       (f.rand_0_3_20.i128_support or false) ||
       (f.rand_0_3_20.nightly or false) ||
       (rand_0_3_20.nightly or false);
-  }) [ libc_0_2_36_features fuchsia_zircon_0_3_3_features ];
+  }) [ libc_0_2_43_features fuchsia_zircon_0_3_3_features ];
   redox_syscall_0_1_37 = { features?(redox_syscall_0_1_37_features {}) }: redox_syscall_0_1_37_ {};
   redox_syscall_0_1_37_features = f: updateFeatures f (rec {
     redox_syscall_0_1_37.default = (f.redox_syscall_0_1_37.default or true);
@@ -1483,13 +1851,13 @@ rec {
     winapi_0_3_4.winerror = true;
   }) [ lazy_static_1_0_0_features winapi_0_3_4_features ];
   security_framework_0_1_16 = { features?(security_framework_0_1_16_features {}) }: security_framework_0_1_16_ {
-    dependencies = mapFeatures features ([ core_foundation_0_2_3 core_foundation_sys_0_2_3 libc_0_2_36 security_framework_sys_0_1_16 ]);
+    dependencies = mapFeatures features ([ core_foundation_0_2_3 core_foundation_sys_0_2_3 libc_0_2_43 security_framework_sys_0_1_16 ]);
     features = mkFeatures (features.security_framework_0_1_16 or {});
   };
   security_framework_0_1_16_features = f: updateFeatures f (rec {
     core_foundation_0_2_3.default = true;
     core_foundation_sys_0_2_3.default = true;
-    libc_0_2_36.default = true;
+    libc_0_2_43.default = true;
     security_framework_0_1_16.OSX_10_10 =
       (f.security_framework_0_1_16.OSX_10_10 or false) ||
       (f.security_framework_0_1_16.OSX_10_11 or false) ||
@@ -1526,14 +1894,14 @@ rec {
       (security_framework_0_1_16.OSX_10_9 or false) ||
       (f.security_framework_0_1_16.OSX_10_9 or false);
     security_framework_sys_0_1_16.default = true;
-  }) [ core_foundation_0_2_3_features core_foundation_sys_0_2_3_features libc_0_2_36_features security_framework_sys_0_1_16_features ];
+  }) [ core_foundation_0_2_3_features core_foundation_sys_0_2_3_features libc_0_2_43_features security_framework_sys_0_1_16_features ];
   security_framework_sys_0_1_16 = { features?(security_framework_sys_0_1_16_features {}) }: security_framework_sys_0_1_16_ {
-    dependencies = mapFeatures features ([ core_foundation_sys_0_2_3 libc_0_2_36 ]);
+    dependencies = mapFeatures features ([ core_foundation_sys_0_2_3 libc_0_2_43 ]);
     features = mkFeatures (features.security_framework_sys_0_1_16 or {});
   };
   security_framework_sys_0_1_16_features = f: updateFeatures f (rec {
     core_foundation_sys_0_2_3.default = true;
-    libc_0_2_36.default = true;
+    libc_0_2_43.default = true;
     security_framework_sys_0_1_16.OSX_10_10 =
       (f.security_framework_sys_0_1_16.OSX_10_10 or false) ||
       (f.security_framework_sys_0_1_16.OSX_10_11 or false) ||
@@ -1551,7 +1919,7 @@ rec {
       (f.security_framework_sys_0_1_16.OSX_10_10 or false) ||
       (security_framework_sys_0_1_16.OSX_10_10 or false);
     security_framework_sys_0_1_16.default = (f.security_framework_sys_0_1_16.default or true);
-  }) [ core_foundation_sys_0_2_3_features libc_0_2_36_features ];
+  }) [ core_foundation_sys_0_2_3_features libc_0_2_43_features ];
   serde_1_0_27 = { features?(serde_1_0_27_features {}) }: serde_1_0_27_ {
     dependencies = mapFeatures features ([]);
     features = mkFeatures (features.serde_1_0_27 or {});
@@ -1573,6 +1941,27 @@ rec {
       (f.serde_1_0_27.alloc or false) ||
       (serde_1_0_27.alloc or false);
   }) [];
+  serde_cbor_0_11_1 = { features?(serde_cbor_0_11_1_features {}) }: serde_cbor_0_11_1_ {
+    dependencies = mapFeatures features ([ half_1_5_0 serde_1_0_27 ]);
+    features = mkFeatures (features.serde_cbor_0_11_1 or {});
+  };
+  serde_cbor_0_11_1_features = f: updateFeatures f (rec {
+    half_1_5_0.default = true;
+    serde_1_0_27.alloc =
+      (f.serde_1_0_27.alloc or false) ||
+      (serde_cbor_0_11_1.alloc or false) ||
+      (f.serde_cbor_0_11_1.alloc or false);
+    serde_1_0_27.default = (f.serde_1_0_27.default or false);
+    serde_1_0_27.std =
+      (f.serde_1_0_27.std or false) ||
+      (serde_cbor_0_11_1.std or false) ||
+      (f.serde_cbor_0_11_1.std or false);
+    serde_cbor_0_11_1.default = (f.serde_cbor_0_11_1.default or true);
+    serde_cbor_0_11_1.std =
+      (f.serde_cbor_0_11_1.std or false) ||
+      (f.serde_cbor_0_11_1.default or false) ||
+      (serde_cbor_0_11_1.default or false);
+  }) [ half_1_5_0_features serde_1_0_27_features ];
   serde_derive_1_0_27 = { features?(serde_derive_1_0_27_features {}) }: serde_derive_1_0_27_ {
     dependencies = mapFeatures features ([ quote_0_3_15 serde_derive_internals_0_19_0 syn_0_11_11 ]);
     features = mkFeatures (features.serde_derive_1_0_27 or {});
@@ -1593,6 +1982,13 @@ rec {
     syn_0_11_11.parsing = true;
     synom_0_11_3.default = true;
   }) [ syn_0_11_11_features synom_0_11_3_features ];
+  serde_ignored_0_1_2 = { features?(serde_ignored_0_1_2_features {}) }: serde_ignored_0_1_2_ {
+    dependencies = mapFeatures features ([ serde_1_0_27 ]);
+  };
+  serde_ignored_0_1_2_features = f: updateFeatures f (rec {
+    serde_1_0_27.default = true;
+    serde_ignored_0_1_2.default = (f.serde_ignored_0_1_2.default or true);
+  }) [ serde_1_0_27_features ];
   serde_json_1_0_9 = { features?(serde_json_1_0_9_features {}) }: serde_json_1_0_9_ {
     dependencies = mapFeatures features ([ dtoa_0_4_2 itoa_0_3_4 num_traits_0_1_41 serde_1_0_27 ]);
     features = mkFeatures (features.serde_json_1_0_9 or {});
@@ -1608,6 +2004,43 @@ rec {
       (f.serde_json_1_0_9.preserve_order or false) ||
       (serde_json_1_0_9.preserve_order or false);
   }) [ dtoa_0_4_2_features itoa_0_3_4_features num_traits_0_1_41_features serde_1_0_27_features ];
+  signal_hook_0_1_17 = { features?(signal_hook_0_1_17_features {}) }: signal_hook_0_1_17_ {
+    dependencies = mapFeatures features ([ libc_0_2_43 signal_hook_registry_1_2_0 ]);
+    features = mkFeatures (features.signal_hook_0_1_17 or {});
+  };
+  signal_hook_0_1_17_features = f: updateFeatures f (rec {
+    libc_0_2_43.default = true;
+    signal_hook_0_1_17.default = (f.signal_hook_0_1_17.default or true);
+    signal_hook_0_1_17.futures =
+      (f.signal_hook_0_1_17.futures or false) ||
+      (f.signal_hook_0_1_17.tokio-support or false) ||
+      (signal_hook_0_1_17.tokio-support or false);
+    signal_hook_0_1_17.mio =
+      (f.signal_hook_0_1_17.mio or false) ||
+      (f.signal_hook_0_1_17.mio-support or false) ||
+      (signal_hook_0_1_17.mio-support or false);
+    signal_hook_0_1_17.mio-support =
+      (f.signal_hook_0_1_17.mio-support or false) ||
+      (f.signal_hook_0_1_17.tokio-support or false) ||
+      (signal_hook_0_1_17.tokio-support or false);
+    signal_hook_0_1_17.mio_0_7 =
+      (f.signal_hook_0_1_17.mio_0_7 or false) ||
+      (f.signal_hook_0_1_17.mio-0_7-support or false) ||
+      (signal_hook_0_1_17.mio-0_7-support or false);
+    signal_hook_0_1_17.tokio-reactor =
+      (f.signal_hook_0_1_17.tokio-reactor or false) ||
+      (f.signal_hook_0_1_17.tokio-support or false) ||
+      (signal_hook_0_1_17.tokio-support or false);
+    signal_hook_registry_1_2_0.default = true;
+  }) [ libc_0_2_43_features signal_hook_registry_1_2_0_features ];
+  signal_hook_registry_1_2_0 = { features?(signal_hook_registry_1_2_0_features {}) }: signal_hook_registry_1_2_0_ {
+    dependencies = mapFeatures features ([ arc_swap_0_4_8 libc_0_2_43 ]);
+  };
+  signal_hook_registry_1_2_0_features = f: updateFeatures f (rec {
+    arc_swap_0_4_8.default = true;
+    libc_0_2_43.default = true;
+    signal_hook_registry_1_2_0.default = (f.signal_hook_registry_1_2_0.default or true);
+  }) [ arc_swap_0_4_8_features libc_0_2_43_features ];
   syn_0_11_11 = { features?(syn_0_11_11_features {}) }: syn_0_11_11_ {
     dependencies = mapFeatures features ([ ]
       ++ (if features.syn_0_11_11.quote or false then [ quote_0_3_15 ] else [])
@@ -1658,25 +2091,25 @@ rec {
   tempfile_2_2_0 = { features?(tempfile_2_2_0_features {}) }: tempfile_2_2_0_ {
     dependencies = mapFeatures features ([ rand_0_3_20 ])
       ++ (if kernel == "redox" then mapFeatures features ([ redox_syscall_0_1_37 ]) else [])
-      ++ (if (kernel == "linux" || kernel == "darwin") then mapFeatures features ([ libc_0_2_36 ]) else [])
+      ++ (if (kernel == "linux" || kernel == "darwin") then mapFeatures features ([ libc_0_2_43 ]) else [])
       ++ (if kernel == "windows" then mapFeatures features ([ kernel32_sys_0_2_2 winapi_0_2_8 ]) else []);
   };
   tempfile_2_2_0_features = f: updateFeatures f (rec {
     kernel32_sys_0_2_2.default = true;
-    libc_0_2_36.default = true;
+    libc_0_2_43.default = true;
     rand_0_3_20.default = true;
     redox_syscall_0_1_37.default = true;
     tempfile_2_2_0.default = (f.tempfile_2_2_0.default or true);
     winapi_0_2_8.default = true;
-  }) [ rand_0_3_20_features redox_syscall_0_1_37_features libc_0_2_36_features kernel32_sys_0_2_2_features winapi_0_2_8_features ];
+  }) [ rand_0_3_20_features redox_syscall_0_1_37_features libc_0_2_43_features kernel32_sys_0_2_2_features winapi_0_2_8_features ];
   thread_id_2_0_0 = { features?(thread_id_2_0_0_features {}) }: thread_id_2_0_0_ {
-    dependencies = mapFeatures features ([ kernel32_sys_0_2_2 libc_0_2_36 ]);
+    dependencies = mapFeatures features ([ kernel32_sys_0_2_2 libc_0_2_43 ]);
   };
   thread_id_2_0_0_features = f: updateFeatures f (rec {
     kernel32_sys_0_2_2.default = true;
-    libc_0_2_36.default = true;
+    libc_0_2_43.default = true;
     thread_id_2_0_0.default = (f.thread_id_2_0_0.default or true);
-  }) [ kernel32_sys_0_2_2_features libc_0_2_36_features ];
+  }) [ kernel32_sys_0_2_2_features libc_0_2_43_features ];
   thread_local_0_2_7 = { features?(thread_local_0_2_7_features {}) }: thread_local_0_2_7_ {
     dependencies = mapFeatures features ([ thread_id_2_0_0 ]);
   };
@@ -1693,12 +2126,12 @@ rec {
     unreachable_1_0_0.default = true;
   }) [ lazy_static_1_0_0_features unreachable_1_0_0_features ];
   time_0_1_39 = { features?(time_0_1_39_features {}) }: time_0_1_39_ {
-    dependencies = mapFeatures features ([ libc_0_2_36 ])
+    dependencies = mapFeatures features ([ libc_0_2_43 ])
       ++ (if kernel == "redox" then mapFeatures features ([ redox_syscall_0_1_37 ]) else [])
       ++ (if kernel == "windows" then mapFeatures features ([ winapi_0_3_4 ]) else []);
   };
   time_0_1_39_features = f: updateFeatures f (rec {
-    libc_0_2_36.default = true;
+    libc_0_2_43.default = true;
     redox_syscall_0_1_37.default = true;
     time_0_1_39.default = (f.time_0_1_39.default or true);
     winapi_0_3_4.default = true;
@@ -1709,7 +2142,14 @@ rec {
     winapi_0_3_4.std = true;
     winapi_0_3_4.sysinfoapi = true;
     winapi_0_3_4.timezoneapi = true;
-  }) [ libc_0_2_36_features redox_syscall_0_1_37_features winapi_0_3_4_features ];
+  }) [ libc_0_2_43_features redox_syscall_0_1_37_features winapi_0_3_4_features ];
+  toml_0_4_6 = { features?(toml_0_4_6_features {}) }: toml_0_4_6_ {
+    dependencies = mapFeatures features ([ serde_1_0_27 ]);
+  };
+  toml_0_4_6_features = f: updateFeatures f (rec {
+    serde_1_0_27.default = true;
+    toml_0_4_6.default = (f.toml_0_4_6.default or true);
+  }) [ serde_1_0_27_features ];
   traitobject_0_1_0 = { features?(traitobject_0_1_0_features {}) }: traitobject_0_1_0_ {};
   traitobject_0_1_0_features = f: updateFeatures f (rec {
     traitobject_0_1_0.default = (f.traitobject_0_1_0.default or true);
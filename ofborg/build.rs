@@ -7,6 +7,7 @@ use std::path::Path;
 enum MetricType {
     Ticker(Metric),
     Counter(Metric),
+    Histogram(Metric),
 }
 
 impl MetricType {
@@ -18,6 +19,9 @@ impl MetricType {
             &MetricType::Counter(_) => {
                 String::from("u64")
             }
+            &MetricType::Histogram(_) => {
+                String::from("Histogram")
+            }
         }
     }
 
@@ -39,6 +43,9 @@ impl MetricType {
             &MetricType::Counter(ref event) => {
                 event.variant.clone()
             }
+            &MetricType::Histogram(ref event) => {
+                event.variant.clone()
+            }
         }
     }
 
@@ -50,6 +57,9 @@ impl MetricType {
             &MetricType::Counter(_) => {
                 String::from("counter")
             }
+            &MetricType::Histogram(_) => {
+                String::from("histogram")
+            }
         }
     }
 
@@ -61,6 +71,9 @@ impl MetricType {
             &MetricType::Counter(ref event) => {
                 event.metric_name.clone()
             }
+            &MetricType::Histogram(ref event) => {
+                event.metric_name.clone()
+            }
         }
     }
 
@@ -72,6 +85,9 @@ impl MetricType {
             &MetricType::Counter(ref event) => {
                 event.description.clone()
             }
+            &MetricType::Histogram(ref event) => {
+                event.description.clone()
+            }
         }
     }
 
@@ -85,6 +101,9 @@ impl MetricType {
             &MetricType::Counter(ref i_event) => {
                 event = i_event;
             }
+            &MetricType::Histogram(ref i_event) => {
+                event = i_event;
+            }
         }
 
         let fields: Vec<String> = event.fields
@@ -103,6 +122,9 @@ impl MetricType {
             &MetricType::Counter(_) => {
                 extra_fields = vec![self.collector_type()];
             }
+            &MetricType::Histogram(_) => {
+                extra_fields = vec![String::from("u64")];
+            }
         }
 
         let mut fields: Vec<String> = self.enum_index_types();
@@ -121,6 +143,9 @@ impl MetricType {
             &MetricType::Counter(ref i_event) => {
                 event = i_event;
             }
+            &MetricType::Histogram(ref i_event) => {
+                event = i_event;
+            }
         }
 
         let fields: Vec<String> = event.fields
@@ -139,6 +164,9 @@ impl MetricType {
             &MetricType::Counter(_) => {
                 extra_fields = vec!["value".to_owned()];
             }
+            &MetricType::Histogram(_) => {
+                extra_fields = vec!["value".to_owned()];
+            }
         }
 
         let mut fields: Vec<String> = self.enum_index_names();
@@ -155,6 +183,45 @@ impl MetricType {
             &MetricType::Counter(_) => {
                 String::from("value")
             }
+            &MetricType::Histogram(_) => {
+                String::from("value")
+            }
+        }
+    }
+
+    fn initial_value(&self) -> String {
+        match self {
+            &MetricType::Histogram(_) => {
+                String::from("Histogram::new()")
+            }
+            _ => {
+                String::from("0")
+            }
+        }
+    }
+
+    fn record_statement(&self) -> String {
+        match self {
+            &MetricType::Histogram(_) => {
+                format!("accum.observe({})", self.record_value())
+            }
+            _ => {
+                format!("*accum += {}", self.record_value())
+            }
+        }
+    }
+
+    fn prometheus_sample(&self) -> String {
+        match self {
+            &MetricType::Histogram(_) => {
+                format!("value.prometheus_output(\"ofborg_{}\", &kvs)", self.metric_name())
+            }
+            _ => {
+                format!(
+                    "format!(\"ofborg_{}{{{{{{}}}}}} {{}}\", kvs.join(\",\"), value)",
+                    self.metric_name()
+                )
+            }
         }
     }
 }
@@ -222,6 +289,24 @@ impl Metric {
             description: desc.to_owned(),
         })
     }
+
+    pub fn histogram(name: &str, desc: &str, fields: Option<Vec<(&str,&str)>>) -> MetricType {
+        let parts = name_to_parts(name);
+
+        MetricType::Histogram(Metric {
+            variant: parts
+                .iter()
+                .map(|f| f.clone().to_owned())
+                .collect(),
+            fields: fields
+                .unwrap_or(vec![])
+                .iter()
+                .map(|&(ref fieldname, ref fieldtype)| (fieldname.clone().to_owned(), fieldtype.clone().to_owned()))
+                .collect(),
+            metric_name: parts.join("_").to_lowercase(),
+            description: desc.to_owned(),
+        })
+    }
 }
 
 fn events() -> Vec<MetricType> {
@@ -262,6 +347,75 @@ fn events() -> Vec<MetricType> {
                 ("branch", "String"),
             ]),
         ),
+        Metric::counter(
+            "EvaluationTotalDuration",
+            "Amount of time spent evaluating PRs, from start to finish",
+            Some(vec![
+                ("branch", "String"),
+            ]),
+        ),
+        Metric::ticker(
+            "EvaluationTotalDurationCount",
+            "Number of PR evaluations which ran to completion",
+            Some(vec![
+                ("branch", "String"),
+            ]),
+        ),
+        Metric::counter(
+            "EvaluationPeakMemory",
+            "Sum of the peak memory use (MiB) of completed PR evaluations",
+            Some(vec![
+                ("branch", "String"),
+            ]),
+        ),
+        Metric::counter(
+            "EvaluationAttrs",
+            "Number of attrs evaluated by completed PR evaluations",
+            Some(vec![
+                ("branch", "String"),
+            ]),
+        ),
+        Metric::counter(
+            "EvaluationChangedOutpaths",
+            "Number of changed out paths found by completed PR evaluations",
+            Some(vec![
+                ("branch", "String"),
+            ]),
+        ),
+        Metric::counter(
+            "EvalCheckDuration",
+            "Amount of time spent running each eval check",
+            Some(vec![
+                ("check", "String"),
+            ]),
+        ),
+        Metric::ticker(
+            "EvalCheckDurationCount",
+            "Number of timed eval check runs",
+            Some(vec![
+                ("check", "String"),
+            ]),
+        ),
+        Metric::counter(
+            "GithubApiRequests",
+            "Number of requests made to the GitHub API",
+            None,
+        ),
+        Metric::counter(
+            "GithubApiRetries",
+            "Number of GitHub API requests retried after failing",
+            None,
+        ),
+        Metric::counter(
+            "GithubApiRateLimitWait",
+            "Amount of time spent waiting for the GitHub rate limit to reset",
+            None,
+        ),
+        Metric::ticker(
+            "EvaluationExceededMemoryLimit",
+            "Number of evaluations killed for exceeding the memory limit",
+            None,
+        ),
         Metric::ticker(
             "JobDecodeSuccess",
             "Number of successfully decoded jobs",
@@ -287,6 +441,38 @@ fn events() -> Vec<MetricType> {
             "Number of completed evaluation tasks",
             None,
         ),
+        Metric::histogram(
+            "BuildDuration",
+            "Time spent building, by system and the top-level prefix of the attrs",
+            Some(vec![
+                ("system", "String"),
+                ("attr_prefix", "String"),
+            ]),
+        ),
+        Metric::ticker(
+            "UsageBuildRequested",
+            "Builds run, by the repository and the user who asked for them",
+            Some(vec![
+                ("repo", "String"),
+                ("user", "String"),
+            ]),
+        ),
+        Metric::counter(
+            "UsageBuilderSeconds",
+            "Time spent building, by the repository and the user who asked for it",
+            Some(vec![
+                ("repo", "String"),
+                ("user", "String"),
+            ]),
+        ),
+        Metric::counter(
+            "UsageEvalSeconds",
+            "Time spent evaluating, by the repository and the PR's author",
+            Some(vec![
+                ("repo", "String"),
+                ("user", "String"),
+            ]),
+        ),
         /*
         Metric::counter(
             "TimeElapsed",
@@ -536,15 +722,16 @@ impl MetricCollector {
           .expect(\"Failed to unwrap metric mutex for {}\");
         let accum = accum_table
           .entry(({}))
-          .or_insert(0);
-        *accum += {};
+          .or_insert({});
+        {};
       }}
  ",
                     variant_match,
                     &mtype.metric_name(),
                     &mtype.metric_name(),
                     index_fields.join(", "),
-                    &mtype.record_value(),
+                    &mtype.initial_value(),
+                    &mtype.record_statement(),
             )
         }).collect();
 
@@ -592,7 +779,7 @@ impl MetricCollector {
           let kvs: Vec<String> = vec![
 {}
           ];
-          format!(\"ofborg_{}{{{{{{}}}}}} {{}}\", kvs.join(\",\"), value)
+          {}
         }})
         .collect();
       output.push_str(&values.join(\"\n\"));
@@ -606,13 +793,77 @@ impl MetricCollector {
                     &mtype.metric_name(),
                     for_matcher,
                     &key_value_pairs.join(",\n"),
-                    &mtype.metric_name(),
+                    &mtype.prometheus_sample(),
             )
         }).collect();
 
 
     f.write_all(variants.join("\n").as_bytes()).unwrap();
     f.write_all("return output;\n  }".as_bytes()).unwrap();
+
+    // Save and load the values, so they survive a restart
+    f.write_all(b"
+  pub fn snapshot(&self) -> HashMap<String, serde_json::Value> {
+    let mut snapshot = HashMap::new();
+").unwrap();
+
+    let variants: Vec<String> = events()
+        .iter()
+        .map(|mtype| {
+            format!("
+    {{
+      let table = self.{}.lock()
+        .expect(\"Failed to unwrap metric mutex for {}\");
+      let entries: Vec<_> = table.iter().collect();
+      snapshot.insert(
+        String::from(\"{}\"),
+        serde_json::to_value(&entries).expect(\"Failed to snapshot {}\"),
+      );
+    }}
+ ",
+                    &mtype.metric_name(),
+                    &mtype.metric_name(),
+                    &mtype.metric_name(),
+                    &mtype.metric_name(),
+            )
+        }).collect();
+
+    f.write_all(variants.join("\n").as_bytes()).unwrap();
+    f.write_all("return snapshot;\n  }\n".as_bytes()).unwrap();
+
+    f.write_all(b"
+  pub fn restore(&self, snapshot: &HashMap<String, serde_json::Value>) {
+").unwrap();
+
+    let variants: Vec<String> = events()
+        .iter()
+        .map(|mtype| {
+            let mut fields: Vec<String> = mtype.enum_index_types();
+            fields.push("String".to_owned()); // Instance
+
+            format!("
+    if let Some(value) = snapshot.get(\"{}\") {{
+      match serde_json::from_value::<Vec<(({}), {})>>(value.clone()) {{
+        Ok(entries) => {{
+          let mut table = self.{}.lock()
+            .expect(\"Failed to unwrap metric mutex for {}\");
+          table.extend(entries);
+        }}
+        Err(e) => warn!(\"Not restoring {}: {{:?}}\", e),
+      }}
+    }}
+ ",
+                    &mtype.metric_name(),
+                    fields.join(", "),
+                    mtype.collector_type(),
+                    &mtype.metric_name(),
+                    &mtype.metric_name(),
+                    &mtype.metric_name(),
+            )
+        }).collect();
+
+    f.write_all(variants.join("\n").as_bytes()).unwrap();
+    f.write_all("  }\n".as_bytes()).unwrap();
     f.write_all("\n}".as_bytes()).unwrap();
 
 }
@@ -1,19 +1,240 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+use ofborg::githubapi::GithubApi;
+use ofborg::banlist;
+
+/// How long to trust a team membership lookup before asking again.
+const TEAM_CACHE_SECS: u64 = 15 * 60;
+
+/// How much trust a command needs, each tier includes the ones
+/// before it.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all="snake_case")]
+pub enum Tier {
+    Anyone,
+    /// The author of the PR the command is given on.
+    Author,
+    Known,
+    Trusted,
+    /// Operators of this ofborg instance.
+    Admin,
+}
+
+/// The tier commands and flags need unless configured otherwise.
+fn default_tier(command: &str) -> Tier {
+    match command {
+        "help" => Tier::Anyone,
+        "cancel" => Tier::Author,
+        "label" | "--timeout" | "--check" => Tier::Trusted,
+        "admin" => Tier::Admin,
+        _ => Tier::Known,
+    }
+}
+
+/// The users trusted and known on NixOS/nixpkgs, which may be
+/// swapped out while running.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct Users {
+    pub trusted_users: Vec<String>,
+    pub known_users: Vec<String>,
+}
 
 pub struct ACL {
-    trusted_users: Vec<String>,
-    known_users: Vec<String>,
+    users: Arc<RwLock<Users>>,
     repos: Vec<String>,
+    repo_users: HashMap<String, RepoUsers>,
+    trusted_teams: Vec<String>,
+    teams: Option<Box<TeamMembership>>,
+    team_cache: Mutex<HashMap<(String, String), (Instant, bool)>>,
+    admins: Vec<String>,
+    command_tiers: HashMap<String, Tier>,
+    banned: Arc<RwLock<Vec<String>>>,
+    ban_list: Option<String>,
+}
+
+/// Who may build on a repository configured on its own.
+struct RepoUsers {
+    trusted: Vec<String>,
+    known: Vec<String>,
+    /// Replaces the trusted teams, when given.
+    trusted_teams: Option<Vec<String>>,
+    /// Taking precedence over the instance wide tiers.
+    command_tiers: HashMap<String, Tier>,
+}
+
+/// Answers whether a user belongs to a GitHub team (`org/team-slug`)
+/// or, for a bare `org`, to the organization.
+pub trait TeamMembership {
+    fn is_member(&self, team: &str, user: &str) -> Result<bool, String>;
+}
+
+impl TeamMembership for GithubApi {
+    fn is_member(&self, team: &str, user: &str) -> Result<bool, String> {
+        let result = match team.find('/') {
+            Some(idx) => self.team_membership(&team[..idx], &team[idx + 1..], user),
+            None => self.org_membership(team, user),
+        };
+
+        return result.map_err(|e| format!("{:?}", e));
+    }
 }
 
 impl ACL {
     pub fn new(repos: Vec<String>, trusted_users: Vec<String>, known_users: Vec<String>) -> ACL {
         return ACL {
-            trusted_users: trusted_users,
-            known_users: known_users,
+            users: Arc::new(RwLock::new(Users {
+                trusted_users: trusted_users,
+                known_users: known_users,
+            })),
             repos: repos,
+            repo_users: HashMap::new(),
+            trusted_teams: vec![],
+            teams: None,
+            team_cache: Mutex::new(HashMap::new()),
+            admins: vec![],
+            command_tiers: HashMap::new(),
+            banned: Arc::new(RwLock::new(vec![])),
+            ban_list: None,
         };
     }
 
+    /// Where to swap in newly loaded users, see `aclsource::watch`.
+    pub fn users(&self) -> Arc<RwLock<Users>> {
+        self.users.clone()
+    }
+
+    /// Ignore the users listed in the file at `path`, where `ban`
+    /// adds to.
+    pub fn with_ban_list(mut self, path: &str, banned: Vec<String>) -> ACL {
+        self.ban_list = Some(path.to_owned());
+        *self.banned.write().unwrap() = banned.iter().map(|u| u.to_lowercase()).collect();
+        return self;
+    }
+
+    /// Where to swap in a newly loaded ban list, see `banlist::watch`.
+    pub fn banned(&self) -> Arc<RwLock<Vec<String>>> {
+        self.banned.clone()
+    }
+
+    pub fn is_banned(&self, user: &str) -> bool {
+        self.banned.read().unwrap().contains(&user.to_lowercase())
+    }
+
+    /// Ban `user`, recording it in the ban list so it lasts.
+    pub fn ban(&self, user: &str) -> Result<(), String> {
+        let user = user.to_lowercase();
+        let path = match self.ban_list {
+            Some(ref path) => path,
+            None => return Err(String::from("No ban list is configured")),
+        };
+
+        let mut banned = self.banned.write().unwrap();
+        if banned.contains(&user) {
+            return Ok(());
+        }
+        banlist::append(path, &user)?;
+        banned.push(user);
+        return Ok(());
+    }
+
+    pub fn with_admins(mut self, admins: Vec<String>) -> ACL {
+        self.admins = admins.iter().map(|u| u.to_lowercase()).collect();
+        return self;
+    }
+
+    /// Require these tiers for commands, like `eval`, and flags, like
+    /// `--on`, instead of the defaults.
+    pub fn with_command_tiers(mut self, command_tiers: HashMap<String, Tier>) -> ACL {
+        self.command_tiers = command_tiers
+            .into_iter()
+            .map(|(command, tier)| (command.to_lowercase(), tier))
+            .collect();
+        return self;
+    }
+
+    /// The most trusted tier `user` belongs to on `repo`.
+    pub fn tier(&self, user: &str, repo: &str, is_author: bool) -> Tier {
+        if self.admins.contains(&user.to_lowercase()) {
+            Tier::Admin
+        } else if self.can_build_unrestricted(user, repo) {
+            Tier::Trusted
+        } else if self.can_build_restricted(user, repo) {
+            Tier::Known
+        } else if is_author {
+            Tier::Author
+        } else {
+            Tier::Anyone
+        }
+    }
+
+    pub fn required_tier(&self, repo: &str, command: &str) -> Tier {
+        let command = command.to_lowercase();
+        if let Some(users) = self.repo_users.get(&repo.to_lowercase()) {
+            if let Some(tier) = users.command_tiers.get(&command) {
+                return *tier;
+            }
+        }
+
+        match self.command_tiers.get(&command) {
+            Some(tier) => *tier,
+            None => default_tier(&command),
+        }
+    }
+
+    pub fn build_job_destinations_for_tier(&self, tier: Tier) -> Vec<(Option<String>, Option<String>)> {
+        build_job_destinations(tier)
+    }
+
+    /// Also trust members of these teams, looking membership up through
+    /// `teams` and caching the answers for a while.
+    pub fn with_trusted_teams(mut self, trusted_teams: Vec<String>, teams: Box<TeamMembership>) -> ACL {
+        self.trusted_teams = trusted_teams.iter().map(|t| t.to_lowercase()).collect();
+        self.teams = Some(teams);
+        return self;
+    }
+
+    /// Use these users for `repo` instead of the defaults, which only
+    /// apply to NixOS/nixpkgs.
+    pub fn with_repo_users(mut self, repo: &str, trusted: Vec<String>, known: Vec<String>) -> ACL {
+        {
+            let users = self.repo_entry(repo);
+            users.trusted = trusted.iter().map(|u| u.to_lowercase()).collect();
+            users.known = known.iter().map(|u| u.to_lowercase()).collect();
+        }
+        return self;
+    }
+
+    /// Trust members of these teams on `repo`, instead of the ones
+    /// given to `with_trusted_teams`.
+    pub fn with_repo_teams(mut self, repo: &str, trusted_teams: Vec<String>) -> ACL {
+        self.repo_entry(repo).trusted_teams =
+            Some(trusted_teams.iter().map(|t| t.to_lowercase()).collect());
+        return self;
+    }
+
+    pub fn with_repo_command_tiers(mut self, repo: &str, command_tiers: HashMap<String, Tier>) -> ACL {
+        self.repo_entry(repo).command_tiers = command_tiers
+            .into_iter()
+            .map(|(command, tier)| (command.to_lowercase(), tier))
+            .collect();
+        return self;
+    }
+
+    fn repo_entry(&mut self, repo: &str) -> &mut RepoUsers {
+        let repo = repo.to_lowercase();
+        if !self.repos.contains(&repo) {
+            self.repos.push(repo.clone());
+        }
+
+        return self.repo_users.entry(repo).or_insert(RepoUsers {
+            trusted: vec![],
+            known: vec![],
+            trusted_teams: None,
+            command_tiers: HashMap::new(),
+        });
+    }
+
     pub fn is_repo_eligible(&self, name: &str) -> bool {
         self.repos.contains(&name.to_lowercase())
     }
@@ -24,32 +245,230 @@ impl ACL {
         repo: &str,
     ) -> Vec<(Option<String>, Option<String>)> {
         if self.can_build_unrestricted(user, repo) {
-            vec![(Some("build-jobs".to_owned()), None)]
+            self.build_job_destinations_for_tier(Tier::Trusted)
         } else if self.can_build_restricted(user, repo) {
-            vec![
-                (None, Some("build-inputs-x86_64-linux".to_owned())),
-                (None, Some("build-inputs-aarch64-linux".to_owned())),
-            ]
+            self.build_job_destinations_for_tier(Tier::Known)
         } else {
             vec![]
         }
     }
 
     pub fn can_build_restricted(&self, user: &str, repo: &str) -> bool {
+        if let Some(users) = self.repo_users.get(&repo.to_lowercase()) {
+            return users.known.contains(&user.to_lowercase());
+        }
+
         if repo.to_lowercase() != "nixos/nixpkgs" {
             return false;
         }
 
-        return self.known_users.contains(&user.to_lowercase());
+        return self.users.read().unwrap().known_users.contains(
+            &user.to_lowercase(),
+        );
     }
 
     pub fn can_build_unrestricted(&self, user: &str, repo: &str) -> bool {
+        if let Some(users) = self.repo_users.get(&repo.to_lowercase()) {
+            return users.trusted.contains(&user.to_lowercase()) ||
+                self.in_trusted_team(user, repo);
+        }
+
         if repo.to_lowercase() == "nixos/nixpkgs" {
-            return self.trusted_users.contains(&user.to_lowercase());
+            return self.users.read().unwrap().trusted_users.contains(
+                &user.to_lowercase(),
+            ) ||
+                self.in_trusted_team(user, repo);
         } else if user == "grahamc" {
             return true;
         } else {
             return false;
         }
     }
+
+    fn in_trusted_team(&self, user: &str, repo: &str) -> bool {
+        let teams = match self.teams {
+            Some(ref teams) => teams,
+            None => return false,
+        };
+
+        let trusted_teams = match self.repo_users.get(&repo.to_lowercase()) {
+            Some(&RepoUsers { trusted_teams: Some(ref trusted_teams), .. }) => trusted_teams,
+            _ => &self.trusted_teams,
+        };
+
+        let user = user.to_lowercase();
+        for team in trusted_teams.iter() {
+            if self.cached_membership(teams, team, &user) {
+                return true;
+            }
+        }
+
+        return false;
+    }
+
+    fn cached_membership(&self, teams: &Box<TeamMembership>, team: &str, user: &str) -> bool {
+        let key = (team.to_owned(), user.to_owned());
+        let ttl = Duration::from_secs(TEAM_CACHE_SECS);
+
+        let cached = self.team_cache.lock().unwrap().get(&key).cloned();
+        if let Some((at, member)) = cached {
+            if at.elapsed() < ttl {
+                return member;
+            }
+        }
+
+        match teams.is_member(team, user) {
+            Ok(member) => {
+                self.team_cache.lock().unwrap().insert(
+                    key,
+                    (Instant::now(), member),
+                );
+                return member;
+            }
+            Err(e) => {
+                warn!("Failed to check if {} is in {}: {}", user, team, e);
+                // Rather keep a stale answer than lock maintainers out
+                // while GitHub is having trouble.
+                return cached.map(|(_, member)| member).unwrap_or(false);
+            }
+        }
+    }
+}
+
+/// Where build jobs of users of `tier` are published: to every system
+/// for trusted users, only to the systems anyone may build on otherwise.
+pub fn build_job_destinations(tier: Tier) -> Vec<(Option<String>, Option<String>)> {
+    if tier >= Tier::Trusted {
+        vec![(Some("build-jobs".to_owned()), None)]
+    } else {
+        vec![
+            (None, Some("build-inputs-x86_64-linux".to_owned())),
+            (None, Some("build-inputs-aarch64-linux".to_owned())),
+        ]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    struct FakeTeams {
+        members: Vec<(&'static str, &'static str)>,
+        lookups: Rc<Cell<u32>>,
+    }
+
+    impl TeamMembership for FakeTeams {
+        fn is_member(&self, team: &str, user: &str) -> Result<bool, String> {
+            self.lookups.set(self.lookups.get() + 1);
+            return Ok(self.members.contains(&(team, user)));
+        }
+    }
+
+    #[test]
+    fn trusted_by_team() {
+        let lookups = Rc::new(Cell::new(0));
+        let acl = ACL::new(
+            vec![String::from("nixos/nixpkgs")],
+            vec![String::from("grahamc")],
+            vec![],
+        ).with_trusted_teams(
+            vec![String::from("NixOS/Nixpkgs-Maintainers")],
+            Box::new(FakeTeams {
+                members: vec![("nixos/nixpkgs-maintainers", "fpletz")],
+                lookups: lookups.clone(),
+            }),
+        );
+
+        assert!(acl.can_build_unrestricted("grahamc", "nixos/nixpkgs"));
+        assert_eq!(lookups.get(), 0);
+
+        assert!(acl.can_build_unrestricted("FPletz", "nixos/nixpkgs"));
+        assert!(acl.can_build_unrestricted("fpletz", "nixos/nixpkgs"));
+        assert_eq!(lookups.get(), 1);
+
+        assert!(!acl.can_build_unrestricted("someone", "nixos/nixpkgs"));
+        assert!(!acl.can_build_unrestricted("fpletz", "grahamc/nixpkgs"));
+        assert_eq!(lookups.get(), 2);
+    }
+
+    #[test]
+    fn command_tiers() {
+        let mut tiers = HashMap::new();
+        tiers.insert(String::from("Eval"), Tier::Anyone);
+        tiers.insert(String::from("--on"), Tier::Trusted);
+
+        let acl = ACL::new(
+            vec![String::from("nixos/nixpkgs")],
+            vec![String::from("grahamc")],
+            vec![String::from("someone")],
+        ).with_admins(vec![String::from("Operator")])
+            .with_command_tiers(tiers);
+
+        assert_eq!(acl.tier("operator", "nixos/nixpkgs", false), Tier::Admin);
+        assert_eq!(acl.tier("grahamc", "nixos/nixpkgs", false), Tier::Trusted);
+        assert_eq!(acl.tier("someone", "nixos/nixpkgs", true), Tier::Known);
+        assert_eq!(acl.tier("stranger", "nixos/nixpkgs", true), Tier::Author);
+        assert_eq!(acl.tier("stranger", "nixos/nixpkgs", false), Tier::Anyone);
+
+        assert_eq!(acl.required_tier("nixos/nixpkgs", "eval"), Tier::Anyone);
+        assert_eq!(acl.required_tier("nixos/nixpkgs", "--on"), Tier::Trusted);
+        assert_eq!(acl.required_tier("nixos/nixpkgs", "build"), Tier::Known);
+        assert_eq!(acl.required_tier("nixos/nixpkgs", "cancel"), Tier::Author);
+        assert!(Tier::Admin > Tier::Trusted && Tier::Author > Tier::Anyone);
+    }
+
+    #[test]
+    fn per_repo_users() {
+        let acl = ACL::new(
+            vec![String::from("nixos/nixpkgs")],
+            vec![String::from("grahamc")],
+            vec![String::from("someone")],
+        ).with_repo_users(
+            "Example/Packages",
+            vec![String::from("Maintainer")],
+            vec![String::from("contributor")],
+        );
+
+        assert!(acl.is_repo_eligible("example/packages"));
+        assert!(acl.can_build_unrestricted("maintainer", "example/packages"));
+        assert!(!acl.can_build_unrestricted("grahamc", "example/packages"));
+        assert!(acl.can_build_restricted("contributor", "example/packages"));
+        assert!(!acl.can_build_restricted("someone", "example/packages"));
+
+        assert!(acl.can_build_unrestricted("grahamc", "nixos/nixpkgs"));
+        assert!(!acl.can_build_unrestricted("maintainer", "nixos/nixpkgs"));
+        assert!(acl.can_build_unrestricted("grahamc", "grahamc/nixpkgs"));
+    }
+
+    #[test]
+    fn per_repo_teams_and_tiers() {
+        let lookups = Rc::new(Cell::new(0));
+        let mut tiers = HashMap::new();
+        tiers.insert(String::from("eval"), Tier::Anyone);
+
+        let acl = ACL::new(vec![String::from("nixos/nixpkgs")], vec![], vec![])
+            .with_trusted_teams(
+                vec![String::from("nixos/nixpkgs-committers")],
+                Box::new(FakeTeams {
+                    members: vec![
+                        ("nixos/nixpkgs-committers", "committer"),
+                        ("nixos/hardware", "hw-maintainer"),
+                    ],
+                    lookups: lookups.clone(),
+                }),
+            )
+            .with_repo_users("NixOS/nixos-hardware", vec![], vec![])
+            .with_repo_teams("NixOS/nixos-hardware", vec![String::from("NixOS/hardware")])
+            .with_repo_command_tiers("NixOS/nixos-hardware", tiers);
+
+        assert!(acl.can_build_unrestricted("committer", "nixos/nixpkgs"));
+        assert!(!acl.can_build_unrestricted("committer", "nixos/nixos-hardware"));
+        assert!(acl.can_build_unrestricted("hw-maintainer", "nixos/nixos-hardware"));
+        assert!(!acl.can_build_unrestricted("hw-maintainer", "nixos/nixpkgs"));
+
+        assert_eq!(acl.required_tier("nixos/nixos-hardware", "eval"), Tier::Anyone);
+        assert_eq!(acl.required_tier("nixos/nixpkgs", "eval"), Tier::Known);
+    }
 }
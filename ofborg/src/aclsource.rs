@@ -0,0 +1,159 @@
+//! Loading the trusted and known users from a file or URL, and
+//! reloading them while running, so trusting someone new doesn't need
+//! every frontend restarted.
+
+use std::fs::File;
+use std::io::Read;
+use std::sync::{Arc, RwLock};
+use std::thread;
+use std::time::Duration;
+use hyper::Client;
+use hyper::net::HttpsConnector;
+use hyper_native_tls::NativeTlsClient;
+use serde_json;
+use ofborg::acl::Users;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Source {
+    File(String),
+    Url(String),
+}
+
+impl Source {
+    /// A `https://` location is fetched, anything else is read as a
+    /// path. Plain `http://` is refused, as whoever is on the way could
+    /// make themselves trusted.
+    pub fn from_location(location: &str) -> Result<Source, String> {
+        if location.starts_with("https://") {
+            Ok(Source::Url(location.to_owned()))
+        } else if location.starts_with("http://") {
+            Err(format!("Refusing to load users over plain HTTP from {}", location))
+        } else {
+            Ok(Source::File(location.to_owned()))
+        }
+    }
+
+    fn read(&self) -> Result<String, String> {
+        let mut body = String::new();
+        match self {
+            &Source::File(ref path) => {
+                File::open(path)
+                    .and_then(|mut f| f.read_to_string(&mut body))
+                    .map_err(|e| format!("Failed to read {}: {:?}", path, e))?;
+            }
+            &Source::Url(ref url) => {
+                let client =
+                    Client::with_connector(HttpsConnector::new(NativeTlsClient::new().unwrap()));
+                let mut resp = client.get(url).send().map_err(|e| {
+                    format!("Failed to fetch {}: {:?}", url, e)
+                })?;
+                if !resp.status.is_success() {
+                    return Err(format!("Fetching {} returned {}", url, resp.status));
+                }
+                resp.read_to_string(&mut body).map_err(|e| {
+                    format!("Failed to read {}: {:?}", url, e)
+                })?;
+            }
+        }
+
+        return Ok(body);
+    }
+}
+
+/// Read and validate the users, a JSON object with `trusted_users` and
+/// `known_users` lists.
+pub fn load(source: &Source) -> Result<Users, String> {
+    let body = source.read()?;
+    let users: Users = serde_json::from_str(&body).map_err(|e| {
+        format!("Failed to parse {:?}: {:?}", source, e)
+    })?;
+
+    return validate(users);
+}
+
+/// Refuse lists which are more likely a broken file than a real
+/// change: an empty trusted list or names GitHub wouldn't allow.
+pub fn validate(users: Users) -> Result<Users, String> {
+    if users.trusted_users.len() == 0 {
+        return Err(String::from("No trusted users"));
+    }
+
+    for name in users.trusted_users.iter().chain(users.known_users.iter()) {
+        if !valid_login(name) {
+            return Err(format!("{:?} is not a GitHub login", name));
+        }
+    }
+
+    return Ok(Users {
+        trusted_users: users.trusted_users.iter().map(|u| u.to_lowercase()).collect(),
+        known_users: users.known_users.iter().map(|u| u.to_lowercase()).collect(),
+    });
+}
+
+fn valid_login(name: &str) -> bool {
+    name.len() > 0 && !name.starts_with('-') &&
+        name.chars().all(|c| (c.is_ascii() && c.is_alphanumeric()) || c == '-')
+}
+
+/// Reload the users from `source` every `every`, swapping them into
+/// `users` as a whole. The current users are kept while the source is
+/// unreadable or invalid.
+pub fn watch(source: Source, users: Arc<RwLock<Users>>, every: Duration) {
+    thread::spawn(move || loop {
+        thread::sleep(every);
+
+        match load(&source) {
+            Ok(loaded) => {
+                let mut current = users.write().unwrap();
+                if *current != loaded {
+                    info!(
+                        "Reloaded users from {:?}: {} trusted, {} known",
+                        source,
+                        loaded.trusted_users.len(),
+                        loaded.known_users.len()
+                    );
+                    *current = loaded;
+                }
+            }
+            Err(e) => {
+                warn!("Keeping the current users, reloading failed: {}", e);
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn users(trusted: Vec<&str>, known: Vec<&str>) -> Users {
+        Users {
+            trusted_users: trusted.into_iter().map(String::from).collect(),
+            known_users: known.into_iter().map(String::from).collect(),
+        }
+    }
+
+    #[test]
+    fn test_validate() {
+        assert_eq!(
+            validate(users(vec!["GrahamC"], vec!["some-one"])),
+            Ok(users(vec!["grahamc"], vec!["some-one"]))
+        );
+        assert!(validate(users(vec![], vec!["someone"])).is_err());
+        assert!(validate(users(vec!["grahamc"], vec!["some one"])).is_err());
+        assert!(validate(users(vec!["-grahamc"], vec![])).is_err());
+    }
+
+    #[test]
+    fn test_from_location() {
+        assert_eq!(
+            Source::from_location("https://example.com/users.json"),
+            Ok(Source::Url(String::from("https://example.com/users.json")))
+        );
+        assert_eq!(
+            Source::from_location("/etc/ofborg/users.json"),
+            Ok(Source::File(String::from("/etc/ofborg/users.json")))
+        );
+        assert!(Source::from_location("http://example.com/users.json").is_err());
+    }
+}
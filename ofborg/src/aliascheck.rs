@@ -0,0 +1,145 @@
+use std::collections::BTreeMap;
+use std::io::Read;
+use std::path::Path;
+use ofborg::nix;
+use ofborg::evalerrors;
+use ofborg::evalerrors::EvalError;
+
+/// Changed attrs which only evaluate while nixpkgs' deprecated aliases
+/// are available.
+#[derive(Debug)]
+pub struct AliasUsage {
+    offending: BTreeMap<String, String>,
+}
+
+impl AliasUsage {
+    /// The attrs which evaluate with aliases, but not without.
+    pub fn check(nix: &nix::Nix, checkout: &Path, attrs: &Vec<String>) -> AliasUsage {
+        let offending = attrs
+            .iter()
+            .filter(|attr| failure(nix, checkout, attr, true).is_none())
+            .filter_map(|attr| {
+                failure(nix, checkout, attr, false).map(|output| (attr.clone(), output))
+            })
+            .collect();
+
+        return AliasUsage { offending: offending };
+    }
+
+    /// Forget the attrs which fail without aliases on the base branch
+    /// too, which the PR isn't to blame for.
+    pub fn excluding_failing_on(mut self, nix: &nix::Nix, base: &Path) -> AliasUsage {
+        let already_failing: Vec<String> = self.offending
+            .keys()
+            .filter(|attr| failure(nix, base, attr, false).is_some())
+            .cloned()
+            .collect();
+        for attr in already_failing {
+            self.offending.remove(&attr);
+        }
+
+        return self;
+    }
+
+    pub fn is_clean(&self) -> bool {
+        self.offending.is_empty()
+    }
+
+    pub fn attr_count(&self) -> usize {
+        self.offending.len()
+    }
+
+    /// The locations nix blamed for each failure, to annotate.
+    pub fn errors(&self, checkout: &str) -> Vec<EvalError> {
+        let mut errors: Vec<EvalError> = self.offending
+            .values()
+            .flat_map(|output| evalerrors::parse(output, checkout))
+            .collect();
+        errors.dedup();
+
+        return errors;
+    }
+
+    pub fn report(&self) -> String {
+        self.offending
+            .iter()
+            .map(|(attr, output)| {
+                format!("{} fails to evaluate without aliases:\n\n{}\n", attr, output.trim())
+            })
+            .collect::<Vec<String>>()
+            .join("\n")
+    }
+}
+
+/// Like the config the evaluator uses, so attrs which are broken,
+/// unfree or insecure aren't taken for ones using aliases.
+fn config(allow_aliases: bool) -> String {
+    format!(
+        "{{ allowBroken = true; allowUnfree = true; allowInsecurePredicate = x: true; \
+         allowAliases = {}; }}",
+        allow_aliases
+    )
+}
+
+/// What nix said, if `attr` fails to instantiate.
+fn failure(nix: &nix::Nix, checkout: &Path, attr: &str, allow_aliases: bool) -> Option<String> {
+    let result = nix.safely(
+        nix::Operation::Instantiate,
+        checkout,
+        vec![
+            String::from("./default.nix"),
+            String::from("--arg"),
+            String::from("config"),
+            config(allow_aliases),
+            String::from("-A"),
+            attr.to_owned(),
+        ],
+        true,
+    );
+
+    match result {
+        Ok(_) => None,
+        Err(mut file) => {
+            let mut output = String::new();
+            file.read_to_string(&mut output).ok();
+            Some(output)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_report_and_errors() {
+        let mut offending = BTreeMap::new();
+        offending.insert(
+            "hello".to_owned(),
+            "error: undefined variable 'gnome_themes_standard' at /co/pkgs/hello/default.nix:3:20"
+                .to_owned(),
+        );
+        let usage = AliasUsage { offending: offending };
+
+        assert!(!usage.is_clean());
+        assert_eq!(usage.attr_count(), 1);
+        assert_eq!(
+            usage.report(),
+            "hello fails to evaluate without aliases:
+
+error: undefined variable 'gnome_themes_standard' at /co/pkgs/hello/default.nix:3:20
+"
+        );
+        assert_eq!(
+            usage.errors("/co"),
+            vec![
+                EvalError {
+                    path: "pkgs/hello/default.nix".to_owned(),
+                    line: 3,
+                    column: 20,
+                    message: "undefined variable 'gnome_themes_standard'".to_owned(),
+                },
+            ]
+        );
+    }
+}
@@ -0,0 +1,615 @@
+//! A JSON API of what ofborg is doing, for the status page: the queues,
+//! the active builders, the attempts of a PR and its checks, and badges
+//! of how PRs and branches fare. Trusted tools may also queue jobs
+//! through it.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use hyper::status::StatusCode;
+use serde::Serialize;
+use serde_json;
+
+use ofborg::badge;
+use ofborg::commandlimit::{CommandLimiter, Decision};
+use ofborg::correlation::Correlation;
+use ofborg::githubapi::{CheckRunSummary, GithubApi, StatusSummary};
+use ofborg::logviewer::{self, Attempt};
+use ofborg::nix::Capabilities;
+use ofborg::prmetadata;
+use ofborg::tasks::queuemonitor::QueueMonitor;
+use ofborg::timestamp;
+use ofborg::trigger::{self, Client, Replays};
+use ofborg::worker;
+
+/// The builders are found by walking the whole log root, so they're
+/// looked up again at most this often.
+const BUILDERS_CACHE_SECS: u64 = 60;
+
+#[derive(Debug, PartialEq)]
+pub enum Route {
+    Queues,
+    Builders,
+    /// `/repos/<owner>/<name>/pulls/<number>/attempts`
+    Attempts(String, u64),
+    /// `/repos/<owner>/<name>/pulls/<number>/checks`
+    Checks(String, u64),
+    /// `/repos/<owner>/<name>/pulls/<number>/badge.svg`
+    PullBadge(String, u64),
+    /// `/repos/<owner>/<name>/branches/<branch>/badge.svg`
+    BranchBadge(String, String),
+    NotFound,
+}
+
+pub fn route(path: &str) -> Route {
+    let path = path.splitn(2, '?').next().unwrap_or("");
+    let segments: Vec<&str> = path.trim_matches('/').split('/').collect();
+
+    if segments == ["queues"] {
+        return Route::Queues;
+    } else if segments == ["builders"] {
+        return Route::Builders;
+    } else if segments.len() == 6 && segments[0] == "repos" && segments[3] == "pulls" {
+        let full_name = format!("{}/{}", segments[1], segments[2]);
+        return match (segments[4].parse(), segments[5]) {
+            (Ok(number), "attempts") => Route::Attempts(full_name, number),
+            (Ok(number), "checks") => Route::Checks(full_name, number),
+            (Ok(number), "badge.svg") => Route::PullBadge(full_name, number),
+            _ => Route::NotFound,
+        };
+    } else if segments.len() >= 6 && segments[0] == "repos" && segments[3] == "branches" &&
+               segments[segments.len() - 1] == "badge.svg"
+    {
+        // Branch names may have slashes of their own.
+        return Route::BranchBadge(
+            format!("{}/{}", segments[1], segments[2]),
+            segments[4..segments.len() - 1].join("/"),
+        );
+    } else {
+        return Route::NotFound;
+    }
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+pub struct Builder {
+    pub identity: String,
+    pub system: Option<String>,
+    pub capabilities: Option<Capabilities>,
+    /// Seconds since it last started a build.
+    pub last_seen: u64,
+}
+
+/// The builders which started one of `attempts` within `window`, as of
+/// their latest attempt.
+pub fn builders(attempts: &[Attempt], now: SystemTime, window: Duration) -> Vec<Builder> {
+    let mut latest: HashMap<(String, Option<String>), &Attempt> = HashMap::new();
+    for attempt in attempts.iter() {
+        let identity = match attempt.identity {
+            Some(ref identity) => identity.clone(),
+            None => continue,
+        };
+        let newer = match latest.get(&(identity.clone(), attempt.system.clone())) {
+            Some(seen) => seen.modified < attempt.modified,
+            None => true,
+        };
+        if newer {
+            latest.insert((identity, attempt.system.clone()), attempt);
+        }
+    }
+
+    let mut builders: Vec<Builder> = latest
+        .into_iter()
+        .filter_map(|((identity, system), attempt)| {
+            let age = now.duration_since(attempt.modified).unwrap_or(Duration::from_secs(0));
+            if age > window {
+                return None;
+            }
+            Some(Builder {
+                identity: identity,
+                system: system,
+                capabilities: attempt.capabilities.clone(),
+                last_seen: age.as_secs(),
+            })
+        })
+        .collect();
+    builders.sort_by(|a, b| (&a.identity, &a.system).cmp(&(&b.identity, &b.system)));
+    return builders;
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+pub struct AttemptSummary {
+    pub attempt_id: String,
+    pub system: Option<String>,
+    pub identity: Option<String>,
+    pub attrs: Vec<String>,
+    pub log_url: String,
+    /// When its log was last written to, in seconds since the epoch.
+    pub updated_at: u64,
+}
+
+pub fn attempt_summary(attempt: &Attempt, log_url: &str) -> AttemptSummary {
+    AttemptSummary {
+        attempt_id: attempt.attempt_id.clone(),
+        system: attempt.system.clone(),
+        identity: attempt.identity.clone(),
+        attrs: attempt.attrs.clone(),
+        log_url: format!(
+            "{}?key={}&attempt_id={}",
+            log_url,
+            attempt.routing_key,
+            attempt.attempt_id
+        ),
+        updated_at: attempt
+            .modified
+            .duration_since(UNIX_EPOCH)
+            .map(|since| since.as_secs())
+            .unwrap_or(0),
+    }
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+pub struct Check {
+    pub name: String,
+    /// `success`, `failure`, `error` or `pending`.
+    pub state: String,
+    pub description: Option<String>,
+    pub url: Option<String>,
+}
+
+/// Our statuses and check runs as one list of checks.
+pub fn checks(statuses: Vec<StatusSummary>, runs: Vec<CheckRunSummary>) -> Vec<Check> {
+    let mut checks: Vec<Check> = statuses
+        .into_iter()
+        .filter(|status| status.context.starts_with("grahamcofborg"))
+        .map(|status| {
+            Check {
+                name: status.context,
+                state: status.state,
+                description: status.description,
+                url: status.target_url,
+            }
+        })
+        .collect();
+
+    checks.extend(runs.into_iter().map(|run| {
+        let state = match run.conclusion {
+            Some(ref conclusion) if conclusion == "success" || conclusion == "neutral" ||
+                                     conclusion == "skipped" => "success",
+            Some(ref conclusion) if conclusion == "failure" => "failure",
+            Some(_) => "error",
+            None => "pending",
+        };
+        Check {
+            name: run.name,
+            state: String::from(state),
+            description: None,
+            url: None,
+        }
+    }));
+
+    return checks;
+}
+
+pub struct Reply {
+    pub status: StatusCode,
+    pub content_type: &'static str,
+    pub body: String,
+}
+
+impl Reply {
+    fn json(status: StatusCode, body: String) -> Reply {
+        Reply {
+            status: status,
+            content_type: "application/json",
+            body: body,
+        }
+    }
+}
+
+pub struct Api {
+    log_root: Option<PathBuf>,
+    log_url: String,
+    active_builder_window: Duration,
+    queues: Option<QueueMonitor>,
+    github: Option<GithubApi>,
+    trigger_clients: Vec<Client>,
+    replays: Mutex<Replays>,
+    /// Clients named here may not trigger anything, like banned users.
+    banned: Option<Arc<RwLock<Vec<String>>>>,
+    command_limiter: Option<Mutex<CommandLimiter>>,
+    builders: Mutex<Option<(Instant, Vec<Attempt>)>>,
+}
+
+impl Api {
+    pub fn new(log_root: Option<PathBuf>, log_url: String, active_builder_window: Duration) -> Api {
+        Api {
+            log_root: log_root,
+            log_url: log_url,
+            active_builder_window: active_builder_window,
+            queues: None,
+            github: None,
+            trigger_clients: vec![],
+            replays: Mutex::new(Replays::new()),
+            banned: None,
+            command_limiter: None,
+            builders: Mutex::new(None),
+        }
+    }
+
+    pub fn with_queues(mut self, monitor: QueueMonitor) -> Api {
+        self.queues = Some(monitor);
+        return self;
+    }
+
+    pub fn with_github(mut self, github: GithubApi) -> Api {
+        self.github = Some(github);
+        return self;
+    }
+
+    pub fn with_trigger_clients(mut self, clients: Vec<Client>) -> Api {
+        self.trigger_clients = clients;
+        return self;
+    }
+
+    /// See `banlist::watch`.
+    pub fn with_ban_list(mut self, banned: Arc<RwLock<Vec<String>>>) -> Api {
+        self.banned = Some(banned);
+        return self;
+    }
+
+    /// Limit the clients which aren't trusted like commenters are.
+    pub fn with_command_limiter(mut self, limiter: CommandLimiter) -> Api {
+        self.command_limiter = Some(Mutex::new(limiter));
+        return self;
+    }
+
+    /// The answer to a `POST` to `/trigger`, and the jobs to publish.
+    pub fn trigger(
+        &self,
+        client: Option<&str>,
+        timestamp: Option<&str>,
+        signature: Option<&str>,
+        body: &[u8],
+    ) -> (Reply, Vec<worker::Action>) {
+        let authenticated = trigger::authenticate(
+            &self.trigger_clients,
+            &mut self.replays.lock().unwrap(),
+            client,
+            timestamp,
+            signature,
+            body,
+            timestamp::now(),
+        );
+        let (client, request) = match authenticated {
+            Ok(authenticated) => authenticated,
+            Err(e) => {
+                info!("Refused a trigger: {}", e);
+                return (
+                    Reply::json(StatusCode::Unauthorized, json!({ "error": e }).to_string()),
+                    vec![],
+                );
+            }
+        };
+
+        let banned = self.banned.as_ref().map_or(false, |banned| {
+            banned.read().unwrap().contains(&client.name.to_lowercase())
+        });
+        if banned {
+            info!("Refused a trigger from {}, it is banned", client.name);
+            return (
+                Reply::json(
+                    StatusCode::Forbidden,
+                    String::from("{\"error\":\"The client is banned\"}"),
+                ),
+                vec![],
+            );
+        }
+
+        if !client.trusted {
+            let decision = match self.command_limiter {
+                Some(ref limiter) => limiter.lock().unwrap().check(&client.name),
+                None => Decision::Allowed,
+            };
+            if decision != Decision::Allowed {
+                info!("Refused a trigger from {}, it is over its limit", client.name);
+                return (
+                    Reply::json(
+                        StatusCode::TooManyRequests,
+                        String::from("{\"error\":\"Too many requests, try again later\"}"),
+                    ),
+                    vec![],
+                );
+            }
+        }
+
+        let github = match self.github {
+            Some(ref github) => github,
+            None => {
+                return (
+                    Reply::json(
+                        StatusCode::ServiceUnavailable,
+                        String::from("{\"error\":\"No github configured\"}"),
+                    ),
+                    vec![],
+                );
+            }
+        };
+        let mut parts = request.repo.splitn(2, '/');
+        let owner = parts.next().unwrap_or("");
+        let name = parts.next().unwrap_or("");
+        let pr = match prmetadata::fetch(github, owner, name, request.pr) {
+            Ok(pr) => pr,
+            Err(e) => {
+                warn!("Failed to look up {}#{}: {:?}", request.repo, request.pr, e);
+                return (
+                    Reply::json(
+                        StatusCode::ServiceUnavailable,
+                        json!({ "error": format!("{:?}", e) }).to_string(),
+                    ),
+                    vec![],
+                );
+            }
+        };
+
+        let correlation = Correlation::new(None);
+        let jobs = trigger::jobs(client, &request, &pr, &correlation);
+        info!(
+            "{} queued {} jobs on {}#{} as {:?}",
+            client.name,
+            jobs.len(),
+            request.repo,
+            request.pr,
+            correlation.id
+        );
+        return (
+            Reply::json(StatusCode::Accepted, json!({ "queued": jobs.len() }).to_string()),
+            jobs,
+        );
+    }
+
+    /// The answer to a `GET` of `path`.
+    pub fn respond(&self, path: &str) -> Reply {
+        let response = match route(path) {
+            Route::Queues => {
+                match self.queues {
+                    Some(ref monitor) => monitor.poll().and_then(|depths| json(&depths)),
+                    None => Err(String::from("The queues aren't monitored")),
+                }
+            }
+            Route::Builders => {
+                self.recent_attempts().and_then(|attempts| {
+                    json(&builders(&attempts, SystemTime::now(), self.active_builder_window))
+                })
+            }
+            Route::Attempts(full_name, number) => {
+                self.attempts_of(&full_name, number).and_then(|attempts| json(&attempts))
+            }
+            Route::Checks(full_name, number) => {
+                self.checks_of(&full_name, number).and_then(|checks| json(&checks))
+            }
+            Route::PullBadge(full_name, number) => {
+                return self.badge(path, self.checks_of(&full_name, number));
+            }
+            Route::BranchBadge(full_name, branch) => {
+                return self.badge(path, self.checks_of_ref(&full_name, &branch));
+            }
+            Route::NotFound => {
+                return Reply::json(StatusCode::NotFound, String::from("{\"error\":\"Not found\"}"));
+            }
+        };
+
+        match response {
+            Ok(body) => Reply::json(StatusCode::Ok, body),
+            Err(e) => {
+                warn!("Failed to answer {}: {}", path, e);
+                Reply::json(StatusCode::ServiceUnavailable, json!({ "error": e }).to_string())
+            }
+        }
+    }
+
+    /// A badge is shown even when the checks can't be looked up, as
+    /// unknown, rather than as a broken image.
+    fn badge(&self, path: &str, checks: Result<Vec<Check>, String>) -> Reply {
+        let state = match checks {
+            Ok(checks) => badge::summarize(&checks),
+            Err(e) => {
+                warn!("Failed to answer {}: {}", path, e);
+                badge::State::Unknown
+            }
+        };
+
+        return Reply {
+            status: StatusCode::Ok,
+            content_type: "image/svg+xml",
+            body: badge::svg("ofborg", state),
+        };
+    }
+
+    fn log_root(&self) -> Result<&Path, String> {
+        self.log_root.as_ref().map(|root| root.as_path()).ok_or(String::from(
+            "No log_storage configured",
+        ))
+    }
+
+    fn recent_attempts(&self) -> Result<Vec<Attempt>, String> {
+        let mut cached = self.builders.lock().unwrap();
+        if let Some((ref at, ref attempts)) = *cached {
+            if at.elapsed() < Duration::from_secs(BUILDERS_CACHE_SECS) {
+                return Ok(attempts.clone());
+            }
+        }
+
+        let attempts = logviewer::recent(self.log_root()?, usize::max_value())?;
+        *cached = Some((Instant::now(), attempts.clone()));
+        return Ok(attempts);
+    }
+
+    fn attempts_of(&self, full_name: &str, number: u64) -> Result<Vec<AttemptSummary>, String> {
+        let root = self.log_root()?;
+        let routing_key = format!("{}.{}", full_name, number).to_lowercase();
+        if !root.join(&routing_key).is_dir() {
+            return Ok(vec![]);
+        }
+
+        let attempts = logviewer::recent_under(root, Path::new(&routing_key), usize::max_value())?;
+        return Ok(
+            attempts
+                .iter()
+                .map(|attempt| attempt_summary(attempt, &self.log_url))
+                .collect(),
+        );
+    }
+
+    fn checks_of(&self, full_name: &str, number: u64) -> Result<Vec<Check>, String> {
+        let github = self.github.as_ref().ok_or(
+            String::from("No github configured"),
+        )?;
+        let mut parts = full_name.splitn(2, '/');
+        let owner = parts.next().unwrap_or("");
+        let name = parts.next().unwrap_or("");
+
+        let pr = prmetadata::fetch(github, owner, name, number).map_err(
+            |e| format!("{:?}", e),
+        )?;
+        return self.checks_of_ref(full_name, &pr.head_sha);
+    }
+
+    /// The checks of a commit, or of a branch's head.
+    fn checks_of_ref(&self, full_name: &str, git_ref: &str) -> Result<Vec<Check>, String> {
+        let github = self.github.as_ref().ok_or(
+            String::from("No github configured"),
+        )?;
+        let statuses = github.commit_statuses(full_name, git_ref).map_err(
+            |e| format!("{:?}", e),
+        )?;
+        let runs = github.check_runs(full_name, git_ref).map_err(
+            |e| format!("{:?}", e),
+        )?;
+        return Ok(checks(statuses, runs));
+    }
+}
+
+fn json<T: Serialize>(value: &T) -> Result<String, String> {
+    serde_json::to_string(value).map_err(|e| format!("{:?}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn attempt(identity: &str, system: &str, age: u64, now: SystemTime) -> Attempt {
+        Attempt {
+            routing_key: String::from("nixos/nixpkgs.1"),
+            attempt_id: format!("{}-{}", identity, age),
+            system: Some(system.to_owned()),
+            identity: Some(identity.to_owned()),
+            capabilities: None,
+            attrs: vec![String::from("hello")],
+            path: PathBuf::from("nixos/nixpkgs.1/attempt"),
+            modified: now - Duration::from_secs(age),
+        }
+    }
+
+    #[test]
+    fn test_route() {
+        assert_eq!(route("/queues"), Route::Queues);
+        assert_eq!(route("/builders/"), Route::Builders);
+        assert_eq!(
+            route("/repos/NixOS/nixpkgs/pulls/12345/attempts?x=1"),
+            Route::Attempts(String::from("NixOS/nixpkgs"), 12345)
+        );
+        assert_eq!(
+            route("/repos/NixOS/nixpkgs/pulls/12345/checks"),
+            Route::Checks(String::from("NixOS/nixpkgs"), 12345)
+        );
+        assert_eq!(
+            route("/repos/NixOS/nixpkgs/pulls/12345/badge.svg"),
+            Route::PullBadge(String::from("NixOS/nixpkgs"), 12345)
+        );
+        assert_eq!(
+            route("/repos/NixOS/nixpkgs/branches/release/18.03/badge.svg"),
+            Route::BranchBadge(String::from("NixOS/nixpkgs"), String::from("release/18.03"))
+        );
+        assert_eq!(route("/repos/NixOS/nixpkgs/branches/badge.svg"), Route::NotFound);
+        assert_eq!(route("/repos/NixOS/nixpkgs/pulls/abc/checks"), Route::NotFound);
+        assert_eq!(route("/"), Route::NotFound);
+    }
+
+    #[test]
+    fn test_builders() {
+        let now = UNIX_EPOCH + Duration::from_secs(1_000_000);
+        let attempts = vec![
+            attempt("builder-1", "x86_64-linux", 100, now),
+            attempt("builder-1", "x86_64-linux", 30, now),
+            attempt("builder-2", "aarch64-linux", 7200, now),
+        ];
+
+        assert_eq!(
+            builders(&attempts, now, Duration::from_secs(3600)),
+            vec![
+                Builder {
+                    identity: String::from("builder-1"),
+                    system: Some(String::from("x86_64-linux")),
+                    capabilities: None,
+                    last_seen: 30,
+                },
+            ]
+        );
+
+        let summary = attempt_summary(&attempts[1], "https://logs.nix.ci/");
+        assert_eq!(
+            summary.log_url,
+            "https://logs.nix.ci/?key=nixos/nixpkgs.1&attempt_id=builder-1-30"
+        );
+        assert_eq!(summary.updated_at, 1_000_000 - 30);
+    }
+
+    #[test]
+    fn test_checks() {
+        let statuses = vec![
+            StatusSummary {
+                state: String::from("success"),
+                context: String::from("grahamcofborg-eval"),
+                description: Some(String::from("^.^!")),
+                target_url: None,
+                updated_at: String::from("2018-01-01T00:00:00Z"),
+            },
+            StatusSummary {
+                state: String::from("failure"),
+                context: String::from("someone-else"),
+                description: None,
+                target_url: None,
+                updated_at: String::from("2018-01-01T00:00:00Z"),
+            },
+        ];
+        let runs = vec![
+            CheckRunSummary {
+                id: 1,
+                name: String::from("hello on x86_64-linux"),
+                status: String::from("in_progress"),
+                started_at: None,
+                conclusion: None,
+            },
+        ];
+
+        assert_eq!(
+            checks(statuses, runs),
+            vec![
+                Check {
+                    name: String::from("grahamcofborg-eval"),
+                    state: String::from("success"),
+                    description: Some(String::from("^.^!")),
+                    url: None,
+                },
+                Check {
+                    name: String::from("hello on x86_64-linux"),
+                    state: String::from("pending"),
+                    description: None,
+                    url: None,
+                },
+            ]
+        );
+    }
+}
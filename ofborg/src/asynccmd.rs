@@ -29,6 +29,7 @@ pub struct AsyncCmd {
 pub struct SpawnedAsyncCmd {
     waiter: JoinHandle<(Option<Result<ExitStatus, io::Error>>)>,
     rx: Receiver<String>,
+    pid: u32,
 }
 
 #[derive(Debug, Hash, PartialEq, Eq)]
@@ -107,6 +108,7 @@ impl AsyncCmd {
             .stdout(Stdio::piped())
             .spawn()
             .unwrap();
+        let pid = child.id();
 
         let (monitor_tx, monitor_rx) = sync_channel(WAITER_CHANNEL_BUFFER_SIZE);
         let (proc_tx, proc_rx) = sync_channel(OUT_CHANNEL_BUFFER_SIZE);
@@ -182,16 +184,27 @@ impl AsyncCmd {
         SpawnedAsyncCmd {
             waiter: head_waiter,
             rx: proc_rx,
+            pid: pid,
         }
     }
 }
 
 
 impl SpawnedAsyncCmd {
-    pub fn lines<'a>(&'a mut self) -> &'a Receiver<String> {
+    pub fn lines<'a>(&'a self) -> &'a Receiver<String> {
         &self.rx
     }
 
+    /// Ask the command to stop, its output ends once it has.
+    pub fn kill(&self) {
+        let result = Command::new("kill")
+            .arg(format!("{}", self.pid))
+            .status();
+        if let Err(e) = result {
+            warn!("Failed to kill {}: {:?}", self.pid, e);
+        }
+    }
+
     pub fn wait(self) -> thread::Result<Option<Result<ExitStatus, io::Error>>> {
         self.waiter.join()
     }
@@ -0,0 +1,305 @@
+//! Expanding the patterns of `@grahamcofborg build`, like
+//! `python3Packages.requests*` or `haskellPackages.{aeson,lens}`, to
+//! the attributes they match.
+
+/// Stop expanding a pattern after this many attributes, so a sloppy
+/// `*` doesn't build half of nixpkgs.
+pub const MAX_EXPANSION: usize = 50;
+
+/// Give up after listing this many attribute sets, each of which is
+/// a nix evaluation, so `*.*` doesn't evaluate all of nixpkgs.
+pub const MAX_LOOKUPS: usize = 20;
+
+#[derive(Debug, PartialEq)]
+pub struct Expansion {
+    pub attrs: Vec<String>,
+    /// Patterns which matched more than the cap, only partially
+    /// included in `attrs`.
+    pub truncated: Vec<String>,
+}
+
+pub fn is_pattern(attr: &str) -> bool {
+    attr.contains('*') || attr.contains('?') || attr.contains('{')
+}
+
+/// Expand `attrs`, listing the attribute names of a set with
+/// `lookup`. Patterns which don't match anything stay as they are,
+/// so they are reported as not buildable. Fails once the patterns
+/// needed more than `max_lookups` lookups.
+pub fn expand<F>(
+    attrs: Vec<String>,
+    cap: usize,
+    max_lookups: usize,
+    lookup: F,
+) -> Result<Expansion, String>
+where
+    F: FnMut(&str) -> Option<Vec<String>>,
+{
+    let mut expansion = Expansion {
+        attrs: vec![],
+        truncated: vec![],
+    };
+    let mut lookup = Lookup {
+        lookup: lookup,
+        remaining: max_lookups,
+    };
+
+    for attr in attrs {
+        if !is_pattern(&attr) {
+            push_unique(&mut expansion.attrs, attr);
+            continue;
+        }
+
+        let mut matched: Vec<String> = vec![];
+        let mut truncated = false;
+        for alternative in expand_braces(&attr) {
+            let segments: Vec<&str> = alternative.split('.').collect();
+            truncated |= expand_segments(&segments, vec![], cap, &mut lookup, &mut matched)
+                .map_err(|_| {
+                    format!("{} needs more than {} attribute set lookups", attr, max_lookups)
+                })?;
+        }
+
+        if matched.len() == 0 {
+            push_unique(&mut expansion.attrs, attr);
+            continue;
+        }
+
+        if truncated {
+            expansion.truncated.push(attr);
+        }
+        for name in matched {
+            push_unique(&mut expansion.attrs, name);
+        }
+    }
+
+    return Ok(expansion);
+}
+
+/// Counts down the lookups a request may still do.
+struct Lookup<F> {
+    lookup: F,
+    remaining: usize,
+}
+
+/// All the lookups were used up.
+struct Exhausted;
+
+impl<F> Lookup<F>
+where
+    F: FnMut(&str) -> Option<Vec<String>>,
+{
+    fn names(&mut self, path: &str) -> Result<Option<Vec<String>>, Exhausted> {
+        if self.remaining == 0 {
+            return Err(Exhausted);
+        }
+        self.remaining -= 1;
+
+        return Ok((self.lookup)(path));
+    }
+}
+
+fn push_unique(attrs: &mut Vec<String>, attr: String) {
+    if !attrs.contains(&attr) {
+        attrs.push(attr);
+    }
+}
+
+/// Returns whether the cap was hit.
+fn expand_segments<F>(
+    segments: &[&str],
+    prefix: Vec<String>,
+    cap: usize,
+    lookup: &mut Lookup<F>,
+    matched: &mut Vec<String>,
+) -> Result<bool, Exhausted>
+where
+    F: FnMut(&str) -> Option<Vec<String>>,
+{
+    let (segment, rest) = match segments.split_first() {
+        Some(split) => split,
+        None => {
+            if matched.len() >= cap {
+                return Ok(true);
+            }
+            push_unique(matched, prefix.join("."));
+            return Ok(false);
+        }
+    };
+
+    if !segment.contains('*') && !segment.contains('?') {
+        let mut path = prefix;
+        path.push(segment.to_string());
+        return expand_segments(rest, path, cap, lookup, matched);
+    }
+
+    let mut names = match lookup.names(&prefix.join("."))? {
+        Some(names) => names,
+        None => return Ok(false),
+    };
+    names.sort();
+
+    for name in names.into_iter().filter(|name| glob_matches(segment, name)) {
+        let mut path = prefix.clone();
+        path.push(name);
+        if expand_segments(rest, path, cap, lookup, matched)? {
+            return Ok(true);
+        }
+    }
+
+    return Ok(false);
+}
+
+/// `a.{b,c}.d` is `a.b.d` and `a.c.d`, several groups multiply.
+pub fn expand_braces(pattern: &str) -> Vec<String> {
+    let open = match pattern.find('{') {
+        Some(open) => open,
+        None => return vec![pattern.to_owned()],
+    };
+    let close = match pattern[open..].find('}') {
+        Some(close) => open + close,
+        None => return vec![pattern.to_owned()],
+    };
+
+    let (before, after) = (&pattern[..open], &pattern[close + 1..]);
+    let mut expanded = vec![];
+    for alternative in pattern[open + 1..close].split(',') {
+        for rest in expand_braces(after) {
+            expanded.push(format!("{}{}{}", before, alternative, rest));
+        }
+    }
+
+    return expanded;
+}
+
+/// Shell-like matching of a single attribute name, `*` matches any
+/// characters and `?` a single one.
+pub fn glob_matches(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+
+    let (mut p, mut n) = (0, 0);
+    let mut star: Option<(usize, usize)> = None;
+    while n < name.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == name[n]) {
+            p += 1;
+            n += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            star = Some((p, n));
+            p += 1;
+        } else if let Some((star_p, star_n)) = star {
+            p = star_p + 1;
+            n = star_n + 1;
+            star = Some((star_p, star_n + 1));
+        } else {
+            return false;
+        }
+    }
+
+    while p < pattern.len() && pattern[p] == '*' {
+        p += 1;
+    }
+
+    return p == pattern.len();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lookup(path: &str) -> Option<Vec<String>> {
+        let names: Vec<&str> = match path {
+            "python3Packages" => vec!["requests-toolbelt", "requests", "flask", "requests_oauthlib"],
+            "haskellPackages" => vec!["aeson", "lens"],
+            "" => vec!["python3Packages", "haskellPackages", "hello"],
+            _ => return None,
+        };
+
+        Some(names.into_iter().map(|name| name.to_owned()).collect())
+    }
+
+    fn strings(attrs: Vec<&str>) -> Vec<String> {
+        attrs.into_iter().map(|attr| attr.to_owned()).collect()
+    }
+
+    #[test]
+    fn test_glob_matches() {
+        assert!(glob_matches("requests*", "requests"));
+        assert!(glob_matches("requests*", "requests-toolbelt"));
+        assert!(glob_matches("*lens", "lens"));
+        assert!(glob_matches("a?so*", "aeson"));
+        assert!(!glob_matches("requests*", "flask"));
+        assert!(!glob_matches("a?n", "aeson"));
+    }
+
+    #[test]
+    fn test_expand_braces() {
+        assert_eq!(expand_braces("hello"), strings(vec!["hello"]));
+        assert_eq!(
+            expand_braces("haskellPackages.{aeson,lens}"),
+            strings(vec!["haskellPackages.aeson", "haskellPackages.lens"])
+        );
+        assert_eq!(
+            expand_braces("{a,b}.{c,d}"),
+            strings(vec!["a.c", "a.d", "b.c", "b.d"])
+        );
+    }
+
+    #[test]
+    fn test_expand() {
+        let expansion = expand(
+            strings(vec!["hello", "python3Packages.requests*", "haskellPackages.{aeson,lens}"]),
+            MAX_EXPANSION,
+            MAX_LOOKUPS,
+            lookup,
+        ).unwrap();
+
+        assert_eq!(
+            expansion.attrs,
+            strings(vec![
+                "hello",
+                "python3Packages.requests",
+                "python3Packages.requests-toolbelt",
+                "python3Packages.requests_oauthlib",
+                "haskellPackages.aeson",
+                "haskellPackages.lens",
+            ])
+        );
+        assert_eq!(expansion.truncated, strings(vec![]));
+    }
+
+    #[test]
+    fn test_expand_unmatched() {
+        let expansion =
+            expand(strings(vec!["nodePackages.*"]), MAX_EXPANSION, MAX_LOOKUPS, lookup).unwrap();
+        assert_eq!(expansion.attrs, strings(vec!["nodePackages.*"]));
+    }
+
+    #[test]
+    fn test_expand_cap() {
+        let expansion =
+            expand(strings(vec!["python3Packages.*"]), 2, MAX_LOOKUPS, lookup).unwrap();
+        assert_eq!(
+            expansion.attrs,
+            strings(vec!["python3Packages.flask", "python3Packages.requests"])
+        );
+        assert_eq!(expansion.truncated, strings(vec!["python3Packages.*"]));
+    }
+
+    #[test]
+    fn test_expand_lookup_limit() {
+        let mut lookups = 0;
+        let expansion = expand(strings(vec!["*.*"]), MAX_EXPANSION, 2, |path| {
+            lookups += 1;
+            lookup(path)
+        });
+        assert_eq!(
+            expansion,
+            Err(String::from("*.* needs more than 2 attribute set lookups"))
+        );
+        assert_eq!(lookups, 2);
+
+        let expansion = expand(strings(vec!["*.*"]), MAX_EXPANSION, 4, lookup).unwrap();
+        assert_eq!(expansion.attrs.len(), 6);
+    }
+}
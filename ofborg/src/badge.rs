@@ -0,0 +1,118 @@
+//! An SVG badge of how a PR or branch fares, for dashboards and READMEs
+//! to embed.
+
+use ofborg::api::Check;
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum State {
+    Passing,
+    Failing,
+    Building,
+    Unknown,
+}
+
+impl State {
+    fn message(&self) -> &'static str {
+        match self {
+            &State::Passing => "passing",
+            &State::Failing => "failing",
+            &State::Building => "building",
+            &State::Unknown => "unknown",
+        }
+    }
+
+    fn color(&self) -> &'static str {
+        match self {
+            &State::Passing => "#4c1",
+            &State::Failing => "#e05d44",
+            &State::Building => "#dfb317",
+            &State::Unknown => "#9f9f9f",
+        }
+    }
+}
+
+/// Failing if any check failed, building while any is pending.
+pub fn summarize(checks: &[Check]) -> State {
+    if checks.len() == 0 {
+        return State::Unknown;
+    }
+    if checks.iter().any(|check| check.state == "failure" || check.state == "error") {
+        return State::Failing;
+    }
+    if checks.iter().any(|check| check.state == "pending") {
+        return State::Building;
+    }
+    return State::Passing;
+}
+
+/// Roughly how wide `text` is in 11px Verdana, like other badges.
+fn text_width(text: &str) -> usize {
+    return text.chars().count() * 7 + 10;
+}
+
+pub fn svg(label: &str, state: State) -> String {
+    let label_width = text_width(label);
+    let message_width = text_width(state.message());
+    let width = label_width + message_width;
+
+    return format!(
+        r##"<svg xmlns="http://www.w3.org/2000/svg" width="{width}" height="20" role="img" aria-label="{label}: {message}">
+<title>{label}: {message}</title>
+<linearGradient id="s" x2="0" y2="100%"><stop offset="0" stop-color="#bbb" stop-opacity=".1"/><stop offset="1" stop-opacity=".1"/></linearGradient>
+<clipPath id="r"><rect width="{width}" height="20" rx="3" fill="#fff"/></clipPath>
+<g clip-path="url(#r)"><rect width="{label_width}" height="20" fill="#555"/><rect x="{label_width}" width="{message_width}" height="20" fill="{color}"/><rect width="{width}" height="20" fill="url(#s)"/></g>
+<g fill="#fff" text-anchor="middle" font-family="Verdana,Geneva,DejaVu Sans,sans-serif" font-size="11"><text x="{label_x}" y="14">{label}</text><text x="{message_x}" y="14">{message}</text></g>
+</svg>
+"##,
+        width = width,
+        label_width = label_width,
+        message_width = message_width,
+        label_x = label_width / 2,
+        message_x = label_width + message_width / 2,
+        label = escape(label),
+        message = state.message(),
+        color = state.color()
+    );
+}
+
+fn escape(text: &str) -> String {
+    text.replace("&", "&amp;")
+        .replace("<", "&lt;")
+        .replace(">", "&gt;")
+        .replace("\"", "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check(state: &str) -> Check {
+        Check {
+            name: String::from("grahamcofborg-eval"),
+            state: state.to_owned(),
+            description: None,
+            url: None,
+        }
+    }
+
+    #[test]
+    fn test_summarize() {
+        assert_eq!(summarize(&[]), State::Unknown);
+        assert_eq!(summarize(&[check("success")]), State::Passing);
+        assert_eq!(summarize(&[check("success"), check("pending")]), State::Building);
+        assert_eq!(
+            summarize(&[check("pending"), check("error"), check("success")]),
+            State::Failing
+        );
+    }
+
+    #[test]
+    fn test_svg() {
+        let badge = svg("ofborg", State::Failing);
+        assert!(badge.starts_with("<svg "));
+        assert!(badge.contains(r#"width="111""#));
+        assert!(badge.contains(r##"fill="#e05d44""##));
+        assert!(badge.contains("<title>ofborg: failing</title>"));
+        assert!(svg("<b>", State::Passing).contains("&lt;b&gt;: passing"));
+    }
+}
@@ -0,0 +1,78 @@
+//! The users banned from giving commands, kept in a file with one
+//! GitHub login per line. Lines starting with `#` are comments.
+
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Write};
+use std::sync::{Arc, RwLock};
+use std::thread;
+use std::time::Duration;
+
+/// How often to re-read the ban list by default.
+pub const RELOAD_SECS: u64 = 60;
+
+/// A missing file is an empty ban list.
+pub fn load(path: &str) -> Result<Vec<String>, String> {
+    let mut contents = String::new();
+    match File::open(path) {
+        Ok(mut f) => {
+            f.read_to_string(&mut contents).map_err(|e| {
+                format!("Failed to read {}: {:?}", path, e)
+            })?;
+        }
+        Err(ref e) if e.kind() == ::std::io::ErrorKind::NotFound => {}
+        Err(e) => return Err(format!("Failed to open {}: {:?}", path, e)),
+    }
+
+    return Ok(parse(&contents));
+}
+
+fn parse(contents: &str) -> Vec<String> {
+    contents
+        .lines()
+        .map(|line| line.trim())
+        .filter(|line| line.len() > 0 && !line.starts_with('#'))
+        .map(|line| line.to_lowercase())
+        .collect()
+}
+
+pub fn append(path: &str, user: &str) -> Result<(), String> {
+    let mut f = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .map_err(|e| format!("Failed to open {}: {:?}", path, e))?;
+
+    return writeln!(f, "{}", user).map_err(|e| format!("Failed to write {}: {:?}", path, e));
+}
+
+/// Re-read the ban list every `every`, so bans given through another
+/// frontend apply here too.
+pub fn watch(path: String, banned: Arc<RwLock<Vec<String>>>, every: Duration) {
+    thread::spawn(move || loop {
+        thread::sleep(every);
+
+        match load(&path) {
+            Ok(loaded) => {
+                let mut current = banned.write().unwrap();
+                if *current != loaded {
+                    info!("Reloaded the ban list, {} banned", loaded.len());
+                    *current = loaded;
+                }
+            }
+            Err(e) => warn!("Keeping the current ban list: {}", e),
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(
+            parse("# spam, 2018-05\nSpammer\n\n  other  \n"),
+            vec![String::from("spammer"), String::from("other")]
+        );
+    }
+}
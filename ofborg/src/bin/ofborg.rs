@@ -0,0 +1,5 @@
+extern crate ofborg;
+
+fn main() {
+    ofborg::cli::main();
+}
@@ -0,0 +1,78 @@
+/// How PRs against a particular target branch are evaluated.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct BranchPolicy {
+    /// The branch name, or a prefix followed by `*` (like `release-*`).
+    pub branch: String,
+    /// Names of the eval checks to run (like `nixos-manual`). All
+    /// checks run if unset.
+    pub checks: Option<Vec<String>>,
+    pub rebuild_label_thresholds: Option<Vec<u64>>,
+    pub auto_build: Option<bool>,
+}
+
+impl BranchPolicy {
+    pub fn matches(&self, branch: &str) -> bool {
+        if self.branch.ends_with("*") {
+            return branch.starts_with(self.branch.trim_right_matches('*'));
+        } else {
+            return branch == self.branch;
+        }
+    }
+
+    pub fn runs_check(&self, name: &str) -> bool {
+        match self.checks {
+            Some(ref checks) => checks.iter().any(|check| check == name),
+            None => true,
+        }
+    }
+
+    pub fn auto_builds(&self) -> bool {
+        self.auto_build.unwrap_or(true)
+    }
+}
+
+/// The first policy matching the branch, or the default policy if
+/// none does.
+pub fn policy_for(policies: &Vec<BranchPolicy>, branch: &str) -> BranchPolicy {
+    return policies
+        .iter()
+        .find(|policy| policy.matches(branch))
+        .cloned()
+        .unwrap_or_default();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_policy_for() {
+        let policies = vec![
+            BranchPolicy {
+                branch: "staging".to_owned(),
+                checks: None,
+                rebuild_label_thresholds: Some(vec![500, 10000]),
+                auto_build: Some(false),
+            },
+            BranchPolicy {
+                branch: "release-*".to_owned(),
+                checks: Some(vec!["package-list".to_owned()]),
+                rebuild_label_thresholds: None,
+                auto_build: None,
+            },
+        ];
+
+        let staging = policy_for(&policies, "staging");
+        assert_eq!(staging.rebuild_label_thresholds, Some(vec![500, 10000]));
+        assert!(!staging.auto_builds());
+        assert!(staging.runs_check("nixos-manual"));
+
+        let release = policy_for(&policies, "release-18.03");
+        assert!(release.auto_builds());
+        assert!(release.runs_check("package-list"));
+        assert!(!release.runs_check("nixos-manual"));
+
+        assert_eq!(policy_for(&policies, "staging-next"), BranchPolicy::default());
+        assert_eq!(policy_for(&policies, "master"), BranchPolicy::default());
+    }
+}
@@ -0,0 +1,663 @@
+use std::collections::BTreeMap;
+use serde_json;
+use ofborg::message::{Pr, Repo};
+use ofborg::message::buildresult::BuildResult;
+use ofborg::githubapi::GithubApi;
+
+pub const REPORT_COMMENT_MARKER: &'static str = "<!-- ofborg-build-report ";
+
+/// Failure logs longer than this are uploaded separately. The log is
+/// also part of the embedded state, so it counts twice towards the
+/// 65536 character limit of a comment, and every platform may fail.
+pub const MAX_OUTPUT_CHARS: usize = 4000;
+
+/// The evaluation summary and every platform's most recent build of
+/// a PR, rendered as the single comment we keep on each PR and update
+/// in place as results arrive.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct BuildReport {
+    pub head_sha: String,
+    pub results: BTreeMap<String, ReportEntry>,
+    #[serde(default)]
+    pub evaluation: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ReportEntry {
+    pub success: Option<bool>,
+    pub attempt_id: String,
+    pub attempted: Vec<String>,
+    pub skipped: Vec<String>,
+    /// The tail of the log, only kept for failures.
+    #[serde(default)]
+    pub output: Vec<String>,
+    /// Where the whole failure log went when `output` is only its end.
+    #[serde(default)]
+    pub full_output_url: Option<String>,
+}
+
+impl BuildReport {
+    pub fn new(head_sha: String) -> BuildReport {
+        BuildReport {
+            head_sha: head_sha,
+            results: BTreeMap::new(),
+            evaluation: None,
+        }
+    }
+
+    /// Recover the report embedded in one of our previous comments.
+    pub fn from_comment(body: &str) -> Option<BuildReport> {
+        let start = body.find(REPORT_COMMENT_MARKER)? + REPORT_COMMENT_MARKER.len();
+        let end = body[start..].find(" -->")? + start;
+
+        return serde_json::from_str(&body[start..end]).ok();
+    }
+
+    /// Forget everything about an older push of the PR.
+    fn reset_for(&mut self, head_sha: &str) {
+        if head_sha != self.head_sha {
+            self.head_sha = head_sha.to_owned();
+            self.results.clear();
+            self.evaluation = None;
+        }
+    }
+
+    /// Every push is evaluated, so an evaluation is what moves the
+    /// report on to a new head.
+    pub fn record_evaluation(&mut self, head_sha: &str, summary: String) {
+        self.reset_for(head_sha);
+        self.evaluation = Some(summary);
+    }
+
+    pub fn record(&mut self, result: &BuildResult) {
+        self.record_with_full_output(result, None);
+    }
+
+    /// Record a result whose log was uploaded to `full_output_url`
+    /// because it is too long, keeping only its end in the comment.
+    /// Results of any other head than the report's are dropped, they
+    /// are of an older push which finished building late.
+    pub fn record_with_full_output(&mut self, result: &BuildResult, full_output_url: Option<String>) {
+        if result.pr.head_sha != self.head_sha {
+            info!(
+                "Not reporting {} of {}, the PR is at {}",
+                result.attempt_id,
+                result.pr.head_sha,
+                self.head_sha
+            );
+            return;
+        }
+
+        let failed = result.success() == Some(false);
+
+        self.results.insert(
+            result.system.clone(),
+            ReportEntry {
+                success: result.success(),
+                attempt_id: result.attempt_id.clone(),
+                attempted: result.attempted_attrs(),
+                skipped: result.skipped_attrs(),
+                output: if failed {
+                    output_tail(&result.output, MAX_OUTPUT_CHARS)
+                } else {
+                    vec![]
+                },
+                full_output_url: if failed { full_output_url } else { None },
+            },
+        );
+    }
+
+    /// The attributes each platform failed to build on `head_sha`,
+    /// like `("x86_64-darwin", ["hello"])`.
+    pub fn failed_builds(&self, head_sha: &str) -> Vec<(String, Vec<String>)> {
+        if self.head_sha != head_sha {
+            return vec![];
+        }
+
+        return self.results
+            .iter()
+            .filter(|&(_, entry)| entry.success == Some(false) && entry.attempted.len() > 0)
+            .map(|(system, entry)| (system.clone(), entry.attempted.clone()))
+            .collect();
+    }
+
+    pub fn render(&self, repo: &Repo, pr: &Pr) -> String {
+        let mut reply: Vec<String> = vec![];
+
+        // Escaping `>` keeps the embedded state from closing the
+        // HTML comment early.
+        reply.push(format!(
+            "{}{} -->",
+            REPORT_COMMENT_MARKER,
+            serde_json::to_string(&self).unwrap().replace(">", "\\u003e")
+        ));
+        if let Some(ref evaluation) = self.evaluation {
+            reply.push(format!("Evaluation of {}", self.head_sha));
+            reply.push("".to_owned());
+            reply.push(evaluation.clone());
+            reply.push("".to_owned());
+
+            if self.results.is_empty() {
+                return reply.join("\n");
+            }
+        }
+
+        reply.push(format!("Build report for {}", self.head_sha));
+        reply.push("".to_owned());
+        reply.push("| Platform | Result | Built | Failed | Skipped | Log |".to_owned());
+        reply.push("| --- | --- | --- | --- | --- | --- |".to_owned());
+
+        for (system, entry) in self.results.iter() {
+            let (result, built, failed) = match entry.success {
+                Some(true) => ("Success", entry.attempted.len(), 0),
+                Some(false) => ("Failure", 0, entry.attempted.len()),
+                None => ("No attempt", 0, 0),
+            };
+
+            let log = match entry.success {
+                Some(_) => format!("[log]({})", log_url(repo, pr, &entry.attempt_id)),
+                None => "".to_owned(),
+            };
+
+            reply.push(format!(
+                "| {} | {} | {} | {} | {} | {} |",
+                system,
+                result,
+                built,
+                failed,
+                entry.skipped.len(),
+                log
+            ));
+        }
+        reply.push("".to_owned());
+
+        for (system, entry) in self.results.iter() {
+            if entry.attempted.len() == 0 && entry.skipped.len() == 0 &&
+                entry.output.len() == 0
+            {
+                continue;
+            }
+
+            reply.push(format!("<details><summary>Attributes on {}</summary><p>", system));
+            reply.push("".to_owned());
+            if entry.attempted.len() > 0 {
+                reply.push(format!("Attempted: {}", entry.attempted.join(", ")));
+                reply.push("".to_owned());
+            }
+            if entry.skipped.len() > 0 {
+                reply.push(format!("Skipped: {}", entry.skipped.join(", ")));
+                reply.push("".to_owned());
+            }
+            if let Some(ref url) = entry.full_output_url {
+                reply.push(format!(
+                    "The log is too long to include, see [the full log]({}). Its end:",
+                    url
+                ));
+                reply.push("".to_owned());
+                reply.push("```".to_owned());
+                reply.extend(entry.output.clone());
+                reply.push("```".to_owned());
+                reply.push("".to_owned());
+            } else if entry.output.len() > 0 {
+                reply.push("Partial log:".to_owned());
+                reply.push("".to_owned());
+                reply.push("```".to_owned());
+                reply.extend(entry.output.clone());
+                reply.push("```".to_owned());
+                reply.push("".to_owned());
+            }
+            reply.push("</p></details>".to_owned());
+            reply.push("".to_owned());
+        }
+
+        return reply.join("\n");
+    }
+}
+
+/// The evaluator and the comment poster both write the report, so
+/// `update` reads it again right before each write and, if another
+/// writer replaced it in the meantime, makes its change again on top of
+/// theirs, this many times.
+const MAX_UPDATE_ATTEMPTS: usize = 3;
+
+/// The report currently on the PR, if we wrote one.
+pub fn find(api: &GithubApi, repo: &str, number: u64) -> Option<BuildReport> {
+    return match find_comment(api, repo, number) {
+        Ok(existing) => existing.map(|(_, report)| report),
+        Err(()) => None,
+    };
+}
+
+fn find_comment(api: &GithubApi, repo: &str, number: u64) -> Result<Option<(u64, BuildReport)>, ()> {
+    let comments = match api.issue_comments(repo, number) {
+        Ok(comments) => comments,
+        Err(err) => {
+            warn!("Failed to list comments on {}: {:?}", number, err);
+            return Err(());
+        }
+    };
+
+    return Ok(
+        comments
+            .into_iter()
+            .filter_map(|c| BuildReport::from_comment(&c.body).map(|report| (c.id, report)))
+            .last(),
+    );
+}
+
+/// Apply `change` to the PR's report comment, creating it if needed.
+/// `change` may run more than once, on different copies of the report.
+pub fn update<F>(api: &GithubApi, repo: &Repo, pr: &Pr, change: F)
+where
+    F: Fn(&mut BuildReport),
+{
+    let id = match find_comment(api, &repo.full_name, pr.number) {
+        Ok(Some((id, _))) => id,
+        Ok(None) => {
+            let mut report = BuildReport::new(pr.head_sha.clone());
+            change(&mut report);
+            let body = report.render(repo, pr);
+            if let Err(err) = api.create_issue_comment(&repo.full_name, pr.number, &body) {
+                warn!("Failed to create the report on {}: {:?}", pr.number, err);
+            }
+            return;
+        }
+        Err(()) => return,
+    };
+
+    // The first pass writes the change, the others make sure no other
+    // writer replaced the comment without it before they wrote theirs.
+    for _ in 0..MAX_UPDATE_ATTEMPTS {
+        let current = match api.issue_comment(&repo.full_name, id) {
+            Ok(comment) => BuildReport::from_comment(&comment.body),
+            Err(err) => {
+                warn!("Failed to read the report on {}: {:?}", pr.number, err);
+                return;
+            }
+        };
+
+        let mut report = match current {
+            Some(ref report) => report.clone(),
+            None => BuildReport::new(pr.head_sha.clone()),
+        };
+        change(&mut report);
+        if current.as_ref() == Some(&report) {
+            return;
+        }
+
+        let body = report.render(repo, pr);
+        if let Err(err) = api.update_issue_comment(&repo.full_name, id, &body) {
+            warn!("Failed to update the report on {}: {:?}", pr.number, err);
+            return;
+        }
+    }
+
+    warn!(
+        "Gave up updating the report on {} after {} attempts",
+        pr.number,
+        MAX_UPDATE_ATTEMPTS
+    );
+}
+
+pub fn output_too_long(output: &Vec<String>) -> bool {
+    output_length(output) > MAX_OUTPUT_CHARS
+}
+
+fn output_length(output: &[String]) -> usize {
+    output.iter().map(|line| line.len() + 1).sum()
+}
+
+/// The last lines of `output` which fit in `max` characters.
+fn output_tail(output: &Vec<String>, max: usize) -> Vec<String> {
+    let mut start = output.len();
+    while start > 0 && output_length(&output[start - 1..]) <= max {
+        start -= 1;
+    }
+
+    return output[start..].to_vec();
+}
+
+pub fn log_url(repo: &Repo, pr: &Pr, attempt_id: &str) -> String {
+    format!(
+        "https://logs.nix.ci/?key={}/{}.{}&attempt_id={}",
+        &repo.owner.to_lowercase(),
+        &repo.name.to_lowercase(),
+        pr.number,
+        attempt_id,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ofborg::message::buildresult::{AttrResult, AttrStatus, LegacyBuildResult};
+
+    fn result(system: &str, head_sha: &str, success: Option<bool>) -> BuildResult {
+        let status = match success {
+            Some(true) => AttrStatus::Success,
+            Some(false) => AttrStatus::Failure,
+            None => AttrStatus::Cancelled,
+        };
+        BuildResult {
+            repo: Repo {
+                clone_url: "https://github.com/nixos/nixpkgs.git".to_owned(),
+                full_name: "NixOS/nixpkgs".to_owned(),
+                owner: "NixOS".to_owned(),
+                name: "nixpkgs".to_owned(),
+            },
+            pr: Pr {
+                head_sha: head_sha.to_owned(),
+                number: 2345,
+                target_branch: Some("master".to_owned()),
+            },
+            output: vec![],
+            attempt_id: format!("{}-attempt", system),
+            system: system.to_owned(),
+            attrs: vec![
+                AttrResult::new("hello".to_owned(), status),
+                AttrResult::new("broken".to_owned(), AttrStatus::Skipped),
+            ],
+            merge_failed: false,
+            passed_without_attrs: false,
+            realisations: None,
+            correlation_id: None,
+            caused_by: None,
+        }
+    }
+
+    fn legacy_result(
+        attempted: Option<Vec<&str>>,
+        skipped: Option<Vec<&str>>,
+        success: Option<bool>,
+    ) -> BuildResult {
+        let output = match success {
+            Some(_) => gdb_log(),
+            None => vec![],
+        };
+        BuildResult::from_legacy(LegacyBuildResult {
+            repo: Repo {
+                clone_url: "https://github.com/nixos/nixpkgs.git".to_owned(),
+                full_name: "NixOS/nixpkgs".to_owned(),
+                owner: "NixOS".to_owned(),
+                name: "nixpkgs".to_owned(),
+            },
+            pr: Pr {
+                head_sha: "abc123".to_owned(),
+                number: 2345,
+                target_branch: Some("master".to_owned()),
+            },
+            output: output,
+            attempt_id: "neatattemptid".to_owned(),
+            system: "x86_64-linux".to_owned(),
+            attempted_attrs: attempted.map(|attrs| attrs.iter().map(|&a| a.to_owned()).collect()),
+            skipped_attrs: skipped.map(|attrs| attrs.iter().map(|&a| a.to_owned()).collect()),
+            success: success,
+            realisations: None,
+            correlation_id: None,
+            caused_by: None,
+        })
+    }
+
+    fn gdb_log() -> Vec<String> {
+        vec![
+                "make[2]: Entering directory '/private/tmp/nix-build-gdb-8.1.drv-0/gdb-8.1/readline'".to_owned(),
+                "make[2]: Nothing to be done for 'install'.".to_owned(),
+                "make[2]: Leaving directory '/private/tmp/nix-build-gdb-8.1.drv-0/gdb-8.1/readline'".to_owned(),
+                "make[1]: Nothing to be done for 'install-target'.".to_owned(),
+                "make[1]: Leaving directory '/private/tmp/nix-build-gdb-8.1.drv-0/gdb-8.1'".to_owned(),
+                "removed '/nix/store/pcja75y9isdvgz5i00pkrpif9rxzxc29-gdb-8.1/share/info/bfd.info'".to_owned(),
+                "post-installation fixup".to_owned(),
+                "strip is /nix/store/5a88zk3jgimdmzg8rfhvm93kxib3njf9-cctools-binutils-darwin/bin/strip".to_owned(),
+                "patching script interpreter paths in /nix/store/pcja75y9isdvgz5i00pkrpif9rxzxc29-gdb-8.1".to_owned(),
+                "/nix/store/pcja75y9isdvgz5i00pkrpif9rxzxc29-gdb-8.1".to_owned(),
+        ]
+    }
+
+    /// The rendered report for `result` alone, without its embedded state.
+    fn render_alone(result: &BuildResult) -> String {
+        let mut report = BuildReport::new("abc123".to_owned());
+        report.record(result);
+        return report
+            .render(&result.repo, &result.pr)
+            .lines()
+            .skip(1)
+            .collect::<Vec<&str>>()
+            .join("\n");
+    }
+
+    #[test]
+    fn test_render_and_recover() {
+        let linux = result("x86_64-linux", "abc123", Some(true));
+        let darwin = result("x86_64-darwin", "abc123", Some(false));
+
+        let mut report = BuildReport::new("abc123".to_owned());
+        report.record(&linux);
+        report.record(&darwin);
+
+        let rendered = report.render(&linux.repo, &linux.pr);
+        assert_eq!(BuildReport::from_comment(&rendered), Some(report));
+        assert_eq!(
+            rendered.lines().skip(1).collect::<Vec<&str>>().join("\n"),
+            "Build report for abc123
+
+| Platform | Result | Built | Failed | Skipped | Log |
+| --- | --- | --- | --- | --- | --- |
+| x86_64-darwin | Failure | 0 | 1 | 1 | [log](https://logs.nix.ci/?key=nixos/nixpkgs.2345&attempt_id=x86_64-darwin-attempt) |
+| x86_64-linux | Success | 1 | 0 | 1 | [log](https://logs.nix.ci/?key=nixos/nixpkgs.2345&attempt_id=x86_64-linux-attempt) |
+
+<details><summary>Attributes on x86_64-darwin</summary><p>
+
+Attempted: hello
+
+Skipped: broken
+
+</p></details>
+
+<details><summary>Attributes on x86_64-linux</summary><p>
+
+Attempted: hello
+
+Skipped: broken
+
+</p></details>"
+        );
+    }
+
+    #[test]
+    fn test_evaluation_and_failure_log() {
+        let mut failure = result("x86_64-linux", "abc123", Some(false));
+        failure.output = vec!["error: build failed".to_owned()];
+
+        let mut report = BuildReport::new("abc123".to_owned());
+        report.record_evaluation("abc123", "| Package set | Changed |".to_owned());
+        assert_eq!(
+            report.render(&failure.repo, &failure.pr).lines().skip(1).collect::<Vec<&str>>(),
+            vec!["Evaluation of abc123", "", "| Package set | Changed |"]
+        );
+
+        report.record(&failure);
+        let rendered = report.render(&failure.repo, &failure.pr);
+        assert_eq!(BuildReport::from_comment(&rendered), Some(report.clone()));
+        assert!(rendered.contains("Evaluation of abc123"));
+        assert!(rendered.contains("```\nerror: build failed\n```"));
+
+        let before = report.clone();
+        report.record(&result("x86_64-linux", "def456", Some(true)));
+        assert_eq!(report, before);
+    }
+
+    #[test]
+    fn test_long_failure_log() {
+        let mut failure = result("x86_64-linux", "abc123", Some(false));
+        failure.output = (0..1000).map(|i| format!("line {}", i)).collect();
+        assert!(output_too_long(&failure.output));
+
+        let mut report = BuildReport::new("abc123".to_owned());
+        report.record_with_full_output(&failure, Some("https://gist.github.com/1".to_owned()));
+
+        let entry = &report.results["x86_64-linux"];
+        assert!(!output_too_long(&entry.output));
+        assert_eq!(entry.output.last(), Some(&"line 999".to_owned()));
+        assert!(
+            report
+                .render(&failure.repo, &failure.pr)
+                .contains("see [the full log](https://gist.github.com/1)")
+        );
+    }
+
+    #[test]
+    fn test_new_push_resets_report() {
+        let mut report = BuildReport::new("abc123".to_owned());
+        report.record(&result("x86_64-linux", "abc123", Some(true)));
+        report.record_evaluation("def456", "| Package set | Changed |".to_owned());
+
+        assert_eq!(report.head_sha, "def456");
+        assert!(report.results.is_empty());
+
+        // The older push finishing late is dropped.
+        report.record(&result("aarch64-linux", "abc123", Some(false)));
+        report.record(&result("x86_64-linux", "def456", Some(true)));
+        assert_eq!(
+            report.results.keys().collect::<Vec<&String>>(),
+            vec!["x86_64-linux"]
+        );
+    }
+
+    #[test]
+    fn test_failed_builds() {
+        let mut report = BuildReport::new("abc123".to_owned());
+        report.record(&result("x86_64-linux", "abc123", Some(true)));
+        report.record(&result("x86_64-darwin", "abc123", Some(false)));
+        report.record(&result("aarch64-linux", "abc123", None));
+
+        assert_eq!(
+            report.failed_builds("abc123"),
+            vec![("x86_64-darwin".to_owned(), vec!["hello".to_owned()])]
+        );
+        assert_eq!(report.failed_builds("def456"), vec![]);
+    }
+
+    #[test]
+    fn test_passing_build() {
+        let result = legacy_result(Some(vec!["foo"]), Some(vec!["bar"]), Some(true));
+        assert_eq!(
+            render_alone(&result),
+            "Build report for abc123
+
+| Platform | Result | Built | Failed | Skipped | Log |
+| --- | --- | --- | --- | --- | --- |
+| x86_64-linux | Success | 1 | 0 | 1 | [log](https://logs.nix.ci/?key=nixos/nixpkgs.2345&attempt_id=neatattemptid) |
+
+<details><summary>Attributes on x86_64-linux</summary><p>
+
+Attempted: foo
+
+Skipped: bar
+
+</p></details>"
+        );
+    }
+
+    #[test]
+    fn test_failing_build() {
+        let result = legacy_result(Some(vec!["foo"]), None, Some(false));
+        assert_eq!(
+            render_alone(&result),
+            "Build report for abc123
+
+| Platform | Result | Built | Failed | Skipped | Log |
+| --- | --- | --- | --- | --- | --- |
+| x86_64-linux | Failure | 0 | 1 | 0 | [log](https://logs.nix.ci/?key=nixos/nixpkgs.2345&attempt_id=neatattemptid) |
+
+<details><summary>Attributes on x86_64-linux</summary><p>
+
+Attempted: foo
+
+Partial log:
+
+```
+make[2]: Entering directory '/private/tmp/nix-build-gdb-8.1.drv-0/gdb-8.1/readline'
+make[2]: Nothing to be done for 'install'.
+make[2]: Leaving directory '/private/tmp/nix-build-gdb-8.1.drv-0/gdb-8.1/readline'
+make[1]: Nothing to be done for 'install-target'.
+make[1]: Leaving directory '/private/tmp/nix-build-gdb-8.1.drv-0/gdb-8.1'
+removed '/nix/store/pcja75y9isdvgz5i00pkrpif9rxzxc29-gdb-8.1/share/info/bfd.info'
+post-installation fixup
+strip is /nix/store/5a88zk3jgimdmzg8rfhvm93kxib3njf9-cctools-binutils-darwin/bin/strip
+patching script interpreter paths in /nix/store/pcja75y9isdvgz5i00pkrpif9rxzxc29-gdb-8.1
+/nix/store/pcja75y9isdvgz5i00pkrpif9rxzxc29-gdb-8.1
+```
+
+</p></details>"
+        );
+    }
+
+    #[test]
+    fn test_passing_build_unspecified_attributes() {
+        let result = legacy_result(None, None, Some(true));
+        assert_eq!(
+            render_alone(&result),
+            "Build report for abc123
+
+| Platform | Result | Built | Failed | Skipped | Log |
+| --- | --- | --- | --- | --- | --- |
+| x86_64-linux | Success | 0 | 0 | 0 | [log](https://logs.nix.ci/?key=nixos/nixpkgs.2345&attempt_id=neatattemptid) |"
+        );
+    }
+
+    #[test]
+    fn test_failing_build_unspecified_attributes() {
+        let result = legacy_result(None, None, Some(false));
+        assert_eq!(
+            render_alone(&result),
+            "Build report for abc123
+
+| Platform | Result | Built | Failed | Skipped | Log |
+| --- | --- | --- | --- | --- | --- |
+| x86_64-linux | Failure | 0 | 0 | 0 | [log](https://logs.nix.ci/?key=nixos/nixpkgs.2345&attempt_id=neatattemptid) |
+
+<details><summary>Attributes on x86_64-linux</summary><p>
+
+Partial log:
+
+```
+make[2]: Entering directory '/private/tmp/nix-build-gdb-8.1.drv-0/gdb-8.1/readline'
+make[2]: Nothing to be done for 'install'.
+make[2]: Leaving directory '/private/tmp/nix-build-gdb-8.1.drv-0/gdb-8.1/readline'
+make[1]: Nothing to be done for 'install-target'.
+make[1]: Leaving directory '/private/tmp/nix-build-gdb-8.1.drv-0/gdb-8.1'
+removed '/nix/store/pcja75y9isdvgz5i00pkrpif9rxzxc29-gdb-8.1/share/info/bfd.info'
+post-installation fixup
+strip is /nix/store/5a88zk3jgimdmzg8rfhvm93kxib3njf9-cctools-binutils-darwin/bin/strip
+patching script interpreter paths in /nix/store/pcja75y9isdvgz5i00pkrpif9rxzxc29-gdb-8.1
+/nix/store/pcja75y9isdvgz5i00pkrpif9rxzxc29-gdb-8.1
+```
+
+</p></details>"
+        );
+    }
+
+    #[test]
+    fn test_no_attempt() {
+        let result = legacy_result(None, Some(vec!["not-attempted"]), None);
+        assert_eq!(
+            render_alone(&result),
+            "Build report for abc123
+
+| Platform | Result | Built | Failed | Skipped | Log |
+| --- | --- | --- | --- | --- | --- |
+| x86_64-linux | No attempt | 0 | 0 | 1 |  |
+
+<details><summary>Attributes on x86_64-linux</summary><p>
+
+Skipped: not-attempted
+
+</p></details>"
+        );
+    }
+
+    #[test]
+    fn test_from_unrelated_comment() {
+        assert_eq!(BuildReport::from_comment("Success on x86_64-linux"), None);
+    }
+}
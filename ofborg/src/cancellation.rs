@@ -0,0 +1,309 @@
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use amqp::Basic;
+use amqp::protocol::basic::{Deliver, BasicProperties};
+use serde_json;
+use uuid::Uuid;
+
+use ofborg::config::RabbitMQConfig;
+use ofborg::easyamqp;
+use ofborg::easyamqp::TypedWrappers;
+use ofborg::message::{Pr, Repo};
+use ofborg::message::cancellation::{self, Cancellation};
+use ofborg::timestamp;
+use ofborg::worker;
+
+/// Every worker receives every cancellation published here.
+pub const EXCHANGE: &'static str = "build-cancellations";
+
+/// Forget cancellations after a day, no job stays queued that long.
+const MAX_AGE_SECS: u64 = 24 * 60 * 60;
+
+/// The PR commits cancelled by `@grahamcofborg cancel`, as heard
+/// from the cancellation exchange. Cheap to clone, all clones share
+/// the same cancellations.
+#[derive(Clone)]
+pub struct Cancellations {
+    /// When each was cancelled, in seconds since the epoch.
+    cancelled: Arc<Mutex<HashMap<(String, u64, String), u64>>>,
+    store: Option<PathBuf>,
+}
+
+/// A cancellation as it's kept in the store.
+#[derive(Serialize, Deserialize, Debug)]
+struct Stored {
+    repo: String,
+    number: u64,
+    head_sha: String,
+    at: u64,
+}
+
+impl Cancellations {
+    pub fn new() -> Cancellations {
+        return Cancellations {
+            cancelled: Arc::new(Mutex::new(HashMap::new())),
+            store: None,
+        };
+    }
+
+    /// Keep the cancellations in `store` too, starting with the ones
+    /// kept there, so jobs cancelled before the process started are
+    /// skipped as well.
+    pub fn stored_in(store: PathBuf) -> Cancellations {
+        let mut cancelled = HashMap::new();
+        match load(&store) {
+            Ok(stored) => {
+                let now = timestamp::now();
+                for entry in stored {
+                    if now.saturating_sub(entry.at) < MAX_AGE_SECS {
+                        cancelled.insert((entry.repo, entry.number, entry.head_sha), entry.at);
+                    }
+                }
+            }
+            Err(e) => warn!("Not restoring the cancellations: {}", e),
+        }
+
+        return Cancellations {
+            cancelled: Arc::new(Mutex::new(cancelled)),
+            store: Some(store),
+        };
+    }
+
+    pub fn record(&self, cancellation: &Cancellation) {
+        let key = key(&cancellation.repo, &cancellation.pr);
+        let mut cancelled = self.cancelled.lock().unwrap();
+
+        let now = timestamp::now();
+        cancelled.retain(|_, at| now.saturating_sub(*at) < MAX_AGE_SECS);
+
+        if cancellation.lifted {
+            cancelled.remove(&key);
+        } else {
+            cancelled.insert(key, now);
+        }
+
+        if let Some(ref store) = self.store {
+            if let Err(e) = save(store, &cancelled) {
+                warn!("Failed to keep the cancellations: {}", e);
+            }
+        }
+    }
+
+    pub fn is_cancelled(&self, repo: &Repo, pr: &Pr) -> bool {
+        self.cancelled.lock().unwrap().contains_key(&key(repo, pr))
+    }
+}
+
+fn key(repo: &Repo, pr: &Pr) -> (String, u64, String) {
+    (repo.full_name.to_lowercase(), pr.number, pr.head_sha.clone())
+}
+
+fn load(path: &Path) -> Result<Vec<Stored>, String> {
+    match File::open(path) {
+        Ok(file) => {
+            serde_json::from_reader(file).map_err(|e| {
+                format!("Failed to parse {}: {:?}", path.display(), e)
+            })
+        }
+        Err(ref e) if e.kind() == ErrorKind::NotFound => Ok(vec![]),
+        Err(e) => Err(format!("Failed to open {}: {:?}", path.display(), e)),
+    }
+}
+
+/// Write the cancellations to `path`, replacing it at once.
+fn save(path: &Path, cancelled: &HashMap<(String, u64, String), u64>) -> Result<(), String> {
+    let stored: Vec<Stored> = cancelled
+        .iter()
+        .map(|(&(ref repo, number, ref head_sha), &at)| {
+            Stored {
+                repo: repo.clone(),
+                number: number,
+                head_sha: head_sha.clone(),
+                at: at,
+            }
+        })
+        .collect();
+    let contents = serde_json::to_string(&stored).map_err(|e| format!("{:?}", e))?;
+
+    let partial = path.with_extension("partial");
+    File::create(&partial)
+        .and_then(|mut file| {
+            file.write_all(contents.as_bytes())?;
+            file.sync_all()
+        })
+        .and_then(|_| fs::rename(&partial, path))
+        .map_err(|e| format!("Failed to write {}: {:?}", path.display(), e))?;
+
+    return Ok(());
+}
+
+pub struct CancellationWorker {
+    cancellations: Cancellations,
+}
+
+impl worker::SimpleWorker for CancellationWorker {
+    type J = Cancellation;
+
+    fn msg_to_job(
+        &mut self,
+        _: &Deliver,
+        props: &BasicProperties,
+        body: &Vec<u8>,
+    ) -> Result<Self::J, String> {
+        return cancellation::from(body, worker::content_type(props))
+            .map_err(|e| format!("{:?}", e));
+    }
+
+    fn consumer(&mut self, job: &Cancellation) -> worker::Actions {
+        info!(
+            "{}#{} at {} {} by {}",
+            job.repo.full_name,
+            job.pr.number,
+            job.pr.head_sha,
+            if job.lifted { "no longer cancelled" } else { "cancelled" },
+            job.requested_by
+        );
+        self.cancellations.record(job);
+        return vec![worker::Action::Ack];
+    }
+}
+
+/// Follow the cancellation exchange on a connection of its own, as
+/// the worker's channel is busy for as long as a job runs. The
+/// cancellations are kept in `store_dir`, named after `consumer_tag`.
+pub fn listen(cfg: &RabbitMQConfig, consumer_tag: String, store_dir: &Path) -> Cancellations {
+    let cancellations = Cancellations::stored_in(store_dir.join(format!("{}.json", consumer_tag)));
+    let recorder = CancellationWorker { cancellations: cancellations.clone() };
+    let cfg = cfg.clone();
+
+    thread::spawn(move || {
+        let mut session = match easyamqp::session_from_config(&cfg) {
+            Ok(session) => session,
+            Err(e) => {
+                error!("Not listening for cancellations: {:?}", e);
+                return;
+            }
+        };
+        let mut channel = session.open_channel(1).unwrap();
+        let queue = format!("{}-{}", EXCHANGE, Uuid::new_v4());
+
+        channel
+            .declare_exchange(easyamqp::ExchangeConfig {
+                exchange: EXCHANGE.to_owned(),
+                exchange_type: easyamqp::ExchangeType::Fanout,
+                passive: false,
+                durable: true,
+                auto_delete: false,
+                no_wait: false,
+                internal: false,
+                arguments: None,
+            })
+            .unwrap();
+
+        channel
+            .declare_queue(easyamqp::QueueConfig {
+                queue: queue.clone(),
+                passive: false,
+                durable: false,
+                exclusive: true,
+                auto_delete: true,
+                no_wait: false,
+                arguments: None,
+            })
+            .unwrap();
+
+        channel
+            .bind_queue(easyamqp::BindQueueConfig {
+                queue: queue.clone(),
+                exchange: EXCHANGE.to_owned(),
+                routing_key: None,
+                no_wait: false,
+                arguments: None,
+            })
+            .unwrap();
+
+        channel
+            .consume(
+                worker::new(recorder).with_signer(cfg.signer()),
+                easyamqp::ConsumeConfig {
+                    queue: queue,
+                    consumer_tag: consumer_tag,
+                    no_local: false,
+                    no_ack: false,
+                    no_wait: false,
+                    exclusive: true,
+                    arguments: None,
+                },
+            )
+            .unwrap();
+
+        channel.start_consuming();
+        channel.close(200, "Bye").unwrap();
+        session.close(200, "Good Bye");
+    });
+
+    return cancellations;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ofborg::test_scratch::TestScratch;
+
+    fn cancellation(sha: &str, lifted: bool) -> Cancellation {
+        Cancellation {
+            repo: Repo {
+                owner: String::from("NixOS"),
+                name: String::from("nixpkgs"),
+                full_name: String::from("NixOS/nixpkgs"),
+                clone_url: String::from("https://github.com/nixos/nixpkgs.git"),
+            },
+            pr: Pr {
+                target_branch: Some(String::from("master")),
+                number: 42,
+                head_sha: String::from(sha),
+            },
+            requested_by: String::from("grahamc"),
+            lifted: lifted,
+            correlation_id: None,
+            caused_by: None,
+        }
+    }
+
+    #[test]
+    fn cancel_and_lift() {
+        let cancellations = Cancellations::new();
+        let cancelled = cancellation("abc123", false);
+        assert!(!cancellations.is_cancelled(&cancelled.repo, &cancelled.pr));
+
+        cancellations.clone().record(&cancelled);
+        assert!(cancellations.is_cancelled(&cancelled.repo, &cancelled.pr));
+
+        let pushed = cancellation("def456", false);
+        assert!(!cancellations.is_cancelled(&pushed.repo, &pushed.pr));
+
+        cancellations.record(&cancellation("abc123", true));
+        assert!(!cancellations.is_cancelled(&cancelled.repo, &cancelled.pr));
+    }
+
+    #[test]
+    fn stored_cancellations() {
+        let store = TestScratch::new_file("cancellations-store");
+        let cancelled = cancellation("abc123", false);
+        let other = cancellation("def456", false);
+
+        let cancellations = Cancellations::stored_in(store.path());
+        cancellations.record(&cancelled);
+        cancellations.record(&other);
+        cancellations.record(&cancellation("def456", true));
+
+        let restarted = Cancellations::stored_in(store.path());
+        assert!(restarted.is_cancelled(&cancelled.repo, &cancelled.pr));
+        assert!(!restarted.is_cancelled(&other.repo, &other.pr));
+    }
+}
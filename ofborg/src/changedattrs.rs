@@ -0,0 +1,83 @@
+use std::fs;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Write};
+use std::path::Path;
+use serde_json;
+use ofborg::nix;
+
+const ATTRS_FILE: &'static str = ".gc-of-borg-changed-attrs.json";
+
+/// Evaluate a helper expression (like maintainers.nix) taking a
+/// `changedattrsjson` argument against a nixpkgs checkout, and return
+/// its JSON output. The expression and the list of attributes are
+/// placed inside the checkout so they are visible under restrict-eval.
+pub fn evaluate(
+    nix: &nix::Nix,
+    checkout: &Path,
+    expr_name: &str,
+    expr: &str,
+    attrs: &Vec<String>,
+) -> Result<String, String> {
+    let attrs_path = checkout.join(ATTRS_FILE);
+    let expr_file = format!(".gc-of-borg-{}", expr_name);
+    let expr_path = checkout.join(&expr_file);
+
+    let mut file = File::create(&attrs_path).expect("Failed to create changed attrs list");
+    file.write_all(serde_json::to_string(attrs).unwrap().as_bytes())
+        .expect("Failed to write changed attrs list");
+
+    let mut file = File::create(&expr_path).expect("Failed to create the nix expression");
+    file.write_all(expr.as_bytes())
+        .expect("Failed to place the nix expression");
+
+    let ret = nix.safely(
+        nix::Operation::Evaluate,
+        checkout,
+        vec![
+            expr_file,
+            String::from("--arg"),
+            String::from("changedattrsjson"),
+            format!("./{}", ATTRS_FILE),
+        ],
+        true,
+    );
+
+    fs::remove_file(&attrs_path).expect("Failed to delete changed attrs list");
+    fs::remove_file(&expr_path).expect("Failed to delete the nix expression");
+
+    match ret {
+        Ok(file) => Ok(last_line(&mut BufReader::new(file))),
+        Err(file) => Err(
+            BufReader::new(file)
+                .lines()
+                .filter_map(|line| line.ok())
+                .collect::<Vec<String>>()
+                .join("\n"),
+        ),
+    }
+}
+
+/// Warnings and traces end up in the same output as the result, so
+/// only the final line is the JSON.
+pub fn last_line(data: &mut BufRead) -> String {
+    data.lines()
+        .filter_map(|line| line.ok())
+        .filter(|line| line.trim().len() > 0)
+        .last()
+        .unwrap_or(String::from("{}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_last_line() {
+        assert_eq!(
+            last_line(&mut Cursor::new("trace: warning\n{\"a\":1}\n\n")),
+            "{\"a\":1}"
+        );
+        assert_eq!(last_line(&mut Cursor::new("")), "{}");
+    }
+}
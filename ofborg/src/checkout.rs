@@ -2,37 +2,89 @@ use std::path::{Path, PathBuf};
 use md5;
 use std::fs;
 use std::io::{Error, ErrorKind};
+use std::thread;
+use std::time::{Duration, SystemTime};
 use ofborg::clone;
-use ofborg::clone::GitClonable;
+use ofborg::clone::{CloneOptions, GitClonable};
+use ofborg::stats;
+use git2::{Diff, Signature, Sort};
+use std::collections::HashMap;
 use std::ffi::OsStr;
 use std::ffi::OsString;
 use std::process::Command;
 
+/// How many times a shallow checkout is deepened looking for a merge
+/// base, before fetching all of its history.
+const MAX_DEEPENINGS: u32 = 5;
+
+#[derive(Clone)]
 pub struct CachedCloner {
     root: PathBuf,
+    options: CloneOptions,
+    metrics: Option<clone::TransferMetrics>,
+    /// The repositories with submodules, by lowercase full name.
+    submodules: Vec<String>,
 }
 
 pub fn cached_cloner(path: &Path) -> CachedCloner {
-    return CachedCloner { root: path.to_path_buf() };
+    return CachedCloner {
+        root: path.to_path_buf(),
+        options: CloneOptions::default(),
+        metrics: None,
+        submodules: vec![],
+    };
 }
 
+#[derive(Clone)]
 pub struct CachedProject {
     root: PathBuf,
     clone_url: String,
+    options: CloneOptions,
+    metrics: Option<clone::TransferMetrics>,
+    submodules: bool,
+}
+
+/// What collecting the garbage of the checkouts did.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct GcReport {
+    pub removed_checkouts: u64,
+    pub bytes_before: u64,
+    pub bytes_after: u64,
 }
 
+/// A worktree of the project's clone, so every checkout of a project
+/// shares one object database and its refs.
 pub struct CachedProjectCo {
     root: PathBuf,
     id: String,
     clone_url: String,
-    local_reference: PathBuf,
+    project: CachedProject,
 }
 
 impl CachedCloner {
+    /// Clone shallow or partial clones, as configured under `checkout`.
+    pub fn with_options(mut self, options: CloneOptions) -> CachedCloner {
+        self.options = options;
+        return self;
+    }
+
+    /// Count what clones and fetches receive.
+    pub fn with_metrics(mut self, registry: &stats::Registry) -> CachedCloner {
+        self.metrics = Some(clone::TransferMetrics::new(registry));
+        return self;
+    }
+
+    /// Check out the submodules of these repositories, like
+    /// `nixos/nixpkgs`, recursively.
+    pub fn with_submodules(mut self, repos: Vec<String>) -> CachedCloner {
+        self.submodules = repos.iter().map(|repo| repo.to_lowercase()).collect();
+        return self;
+    }
+
     pub fn project(&self, name: String, clone_url: String) -> CachedProject {
         // <root>/repo/<hash>/clone
         // <root>/repo/<hash>/clone.lock
-        // <root>/repo/<hash>/<type>/<id>
+        // <root>/repo/<hash>/<type>/<id>, a worktree of the clone
         // <root>/repo/<hash>/<type>/<id>.lock
 
         let mut new_root = self.root.clone();
@@ -42,8 +94,118 @@ impl CachedCloner {
         return CachedProject {
             root: new_root,
             clone_url: clone_url,
+            options: self.options.clone(),
+            metrics: self.metrics.clone(),
+            submodules: self.submodules.contains(&name.to_lowercase()),
         };
     }
+
+    /// Delete the checkouts unused for `max_age` and the refs of PRs,
+    /// and repack the clone of every project.
+    pub fn collect_garbage(&self, max_age: Duration) -> Result<GcReport, Error> {
+        let repos = self.root.join("repo");
+        let mut report = GcReport::default();
+        if !repos.is_dir() {
+            return Ok(report);
+        }
+
+        report.bytes_before = disk_usage(&repos)?;
+        for entry in fs::read_dir(&repos)? {
+            let root = entry?.path();
+            if !root.is_dir() {
+                continue;
+            }
+
+            let project = CachedProject {
+                root: root,
+                clone_url: String::new(),
+                options: self.options.clone(),
+                metrics: None,
+                submodules: false,
+            };
+            report.removed_checkouts += project.collect_garbage(max_age)?;
+        }
+        report.bytes_after = disk_usage(&repos)?;
+
+        return Ok(report);
+    }
+
+    /// Collect garbage every `every`, exporting the disk usage before
+    /// and after.
+    pub fn maintain(&self, max_age: Duration, every: Duration, metrics: &stats::Registry) {
+        let cloner = self.clone();
+        let disk_usage = metrics.gauge(
+            "ofborg_checkout_disk_bytes",
+            "The disk usage of the checkouts, before and after collecting their garbage",
+        );
+        let removed = metrics.counter(
+            "ofborg_checkout_gc_removed_total",
+            "Checkouts deleted for being unused",
+        );
+
+        thread::spawn(move || loop {
+            thread::sleep(every);
+
+            match cloner.collect_garbage(max_age) {
+                Ok(report) => {
+                    info!(
+                        "Deleted {} unused checkouts, from {} to {} bytes",
+                        report.removed_checkouts,
+                        report.bytes_before,
+                        report.bytes_after
+                    );
+                    disk_usage.set(&[("when", "before_gc")], report.bytes_before as f64);
+                    disk_usage.set(&[("when", "after_gc")], report.bytes_after as f64);
+                    removed.add(&[], report.removed_checkouts as f64);
+                }
+                Err(e) => warn!("Failed to collect the garbage of the checkouts: {:?}", e),
+            }
+        });
+    }
+}
+
+fn git(dir: &Path, args: &[&str]) -> Result<String, Error> {
+    let output = Command::new("git").args(args).current_dir(dir).output()?;
+
+    if output.status.success() {
+        return Ok(String::from_utf8_lossy(&output.stdout).into_owned());
+    } else {
+        return Err(Error::new(
+            ErrorKind::Other,
+            String::from_utf8_lossy(&output.stderr).into_owned(),
+        ));
+    }
+}
+
+fn disk_usage(path: &Path) -> Result<u64, Error> {
+    let metadata = match fs::symlink_metadata(path) {
+        Ok(metadata) => metadata,
+        // Deleted while it's counted.
+        Err(ref e) if e.kind() == ErrorKind::NotFound => return Ok(0),
+        Err(e) => return Err(e),
+    };
+
+    if !metadata.is_dir() {
+        return Ok(metadata.len());
+    }
+
+    let mut total = 0;
+    for entry in fs::read_dir(path)? {
+        total += disk_usage(&entry?.path())?;
+    }
+    return Ok(total);
+}
+
+/// Checkouts take their lock for everything they do, and creating it
+/// marks it modified.
+fn unused_for(lock_path: &Path, max_age: Duration) -> Result<bool, Error> {
+    let modified = fs::metadata(lock_path)?.modified()?;
+    return Ok(
+        SystemTime::now()
+            .duration_since(modified)
+            .map(|age| age > max_age)
+            .unwrap_or(false),
+    );
 }
 
 impl CachedProject {
@@ -57,7 +219,7 @@ impl CachedProject {
             root: new_root,
             id: id,
             clone_url: self.clone_from().clone(),
-            local_reference: self.clone_to().clone(),
+            project: self.clone(),
         });
     }
 
@@ -65,10 +227,123 @@ impl CachedProject {
         fs::create_dir_all(&self.root)?;
 
         self.clone_repo()?;
+        self.track_branches()?;
         self.fetch_repo()?;
 
         return Ok(self.clone_to());
     }
+
+    fn collect_garbage(&self, max_age: Duration) -> Result<u64, Error> {
+        let mut lock = self.lock()?;
+
+        let mut removed = 0;
+        for entry in fs::read_dir(&self.root)? {
+            let category = entry?.path();
+            if !category.is_dir() || category == self.clone_to() || category == self.pulls() {
+                continue;
+            }
+
+            for entry in fs::read_dir(&category)? {
+                let lock_path = entry?.path();
+                if lock_path.extension() != Some(OsStr::new("lock")) ||
+                    !unused_for(&lock_path, max_age)?
+                {
+                    continue;
+                }
+
+                // Leave the checkouts in use alone.
+                let mut co_lock = match clone::try_lock_file(&lock_path)? {
+                    Some(co_lock) => co_lock,
+                    None => continue,
+                };
+
+                let co = lock_path.with_extension("");
+                info!("Deleting the unused checkout {:?}", co);
+                if co.exists() {
+                    fs::remove_dir_all(&co)?;
+                }
+                fs::remove_file(&lock_path)?;
+                co_lock.unlock();
+                removed += 1;
+            }
+        }
+
+        if self.clone_to().is_dir() {
+            let clone = self.clone_to();
+            git(&clone, &["worktree", "prune"])?;
+
+            // PRs are merged by their head commit right after they're
+            // fetched, so their refs are only needed for a moment.
+            for pr_ref in git(&clone, &["for-each-ref", "--format=%(refname)", "refs/pull/"])?
+                .lines()
+            {
+                // Like refs/pull/1234/head.
+                let pr_id = pr_ref.split('/').nth(2).and_then(|n| n.parse().ok());
+                let pr_lock = match pr_id {
+                    Some(pr_id) => clone::try_lock_file(&self.pr_lock_path(pr_id))?,
+                    None => None,
+                };
+                if let Some(mut pr_lock) = pr_lock {
+                    git(&clone, &["update-ref", "-d", pr_ref])?;
+                    pr_lock.unlock();
+                }
+            }
+
+            if self.pulls().is_dir() {
+                for entry in fs::read_dir(self.pulls())? {
+                    let lock_path = entry?.path();
+                    if let Some(mut pr_lock) = clone::try_lock_file(&lock_path)? {
+                        fs::remove_file(&lock_path)?;
+                        pr_lock.unlock();
+                    }
+                }
+            }
+
+            if let Err(e) = git(&clone, &["remote", "prune", "origin"]) {
+                warn!("Failed to prune the deleted branches of {:?}: {:?}", clone, e);
+            }
+
+            info!("Collecting the garbage of {:?}", clone);
+            git(&clone, &["gc", "--quiet"])?;
+        }
+
+        lock.unlock();
+
+        return Ok(removed);
+    }
+
+    /// Where the locks of PRs are.
+    fn pulls(&self) -> PathBuf {
+        self.root.join("pulls")
+    }
+
+    fn pr_lock_path(&self, pr_id: u64) -> PathBuf {
+        self.pulls().join(format!("{}.lock", pr_id))
+    }
+
+    /// Lock the refs of a PR, so checkouts of different PRs fetch them
+    /// at the same time.
+    fn lock_pr(&self, pr_id: u64) -> Result<clone::Lock, Error> {
+        fs::create_dir_all(self.pulls())?;
+        clone::lock_file(&self.pr_lock_path(pr_id))
+    }
+
+    /// Bare clones don't fetch branches, but checkouts check out
+    /// `origin/<branch>`.
+    fn track_branches(&self) -> Result<(), Error> {
+        let mut lock = self.lock()?;
+
+        clone::open(&self.clone_to())?
+            .config()
+            .and_then(|mut config| {
+                config.set_str("remote.origin.fetch", "+refs/heads/*:refs/remotes/origin/*")
+            })
+            .map_err(clone::git_error)?;
+
+        lock.unlock();
+
+        return Ok(());
+    }
 }
 
 impl CachedProjectCo {
@@ -86,120 +361,419 @@ impl CachedProjectCo {
         self.fetch_repo()?;
         self.clean()?;
         self.checkout(git_ref)?;
+        self.update_submodules()?;
 
         // let build_dir = self.build_dir();
 
         return Ok(self.clone_to().to_str().unwrap().to_string());
     }
 
-    pub fn fetch_pr(&self, pr_id: u64) -> Result<(), Error> {
-        let mut lock = self.lock()?;
+    /// Fetch the PR's `refs/pull/<n>/head`, and `refs/pull/<n>/merge`
+    /// if GitHub could merge it, failing with `InvalidData` when its
+    /// head isn't `head_sha`, the commit the event was about.
+    pub fn fetch_pr(&self, pr_id: u64, head_sha: &str) -> Result<(), Error> {
+        // Every fetch in to a shallow clone updates its list of shallow
+        // commits, so those fetch one at a time.
+        let mut lock = if self.project.options.depth.is_some() {
+            self.project.lock()?
+        } else {
+            self.project.lock_pr(pr_id)?
+        };
 
-        let result = Command::new("git")
-            .arg("fetch")
-            .arg("origin")
-            .arg(format!("+refs/pull/{}/head:pr", pr_id))
-            .current_dir(self.clone_to())
-            .status()?;
+        let head_ref = format!("refs/pull/{}/head", pr_id);
+        let merge_ref = format!("refs/pull/{}/merge", pr_id);
+        self.fetch_ref(&head_ref)?;
+        if let Err(e) = self.fetch_ref(&merge_ref) {
+            // There's none while the PR conflicts with its base.
+            debug!("Didn't fetch {}: {:?}", merge_ref, e);
+        }
+
+        let fetched = clone::find_commit(&clone::open(&self.project.clone_to())?, &head_ref)?
+            .id();
 
         lock.unlock();
 
-        if result.success() {
-            return Ok(());
-        } else {
-            return Err(Error::new(ErrorKind::Other, "Failed to fetch PR"));
+        if format!("{}", fetched) != head_sha.to_lowercase() {
+            warn!(
+                "Fetched {} at {}, but the event was about {}",
+                head_ref,
+                fetched,
+                head_sha
+            );
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("{} is {}, not {}", head_ref, fetched, head_sha),
+            ));
         }
+
+        return Ok(());
+    }
+
+    /// Fetch `git_ref` of origin to the same ref of the project's clone.
+    fn fetch_ref(&self, git_ref: &str) -> Result<(), Error> {
+        let refspec = format!("+{0}:{0}", git_ref);
+        let clone = self.project.clone_to();
+        match self.project.options.depth {
+            // libgit2 can't fetch in to shallow clones.
+            Some(depth) => {
+                clone::git(
+                    &clone,
+                    &[
+                        OsStr::new("fetch"),
+                        OsStr::new(&format!("--depth={}", depth)),
+                        OsStr::new("origin"),
+                        OsStr::new(&refspec),
+                    ],
+                )?
+            }
+            None => clone::fetch(&clone::open(&clone)?, &[&refspec], &self.project.metrics)?,
+        }
+
+        return Ok(());
     }
 
     pub fn commit_exists(&self, commit: &OsStr) -> bool {
         let mut lock = self.lock().expect("Failed to lock");
 
-        let result = Command::new("git")
-            .arg("--no-pager")
-            .arg("show")
-            .arg(commit)
-            .current_dir(self.clone_to())
-            .status()
-            .expect("git show <commit> failed");
+        let exists = clone::open(&self.clone_to())
+            .map(|repo| repo.revparse_single(&commit.to_string_lossy()).is_ok())
+            .unwrap_or(false);
+
+        lock.unlock();
+
+        return exists;
+    }
+
+    fn is_shallow(&self) -> Result<bool, Error> {
+        return Ok(clone::open(&self.clone_to())?.is_shallow());
+    }
+
+    fn has_merge_base(&self, commit: &OsStr) -> Result<bool, Error> {
+        let repo = clone::open(&self.clone_to())?;
+        let head = clone::find_commit(&repo, "HEAD")?;
+        let commit = match clone::find_commit(&repo, &commit.to_string_lossy()) {
+            Ok(commit) => commit,
+            Err(_) => return Ok(false),
+        };
+
+        return Ok(repo.merge_base(head.id(), commit.id()).is_ok());
+    }
+
+    /// Deepen a shallow checkout until HEAD and `commit` have a merge
+    /// base, fetching all of the history as a last resort.
+    pub fn deepen_to(&self, commit: &OsStr) -> Result<(), Error> {
+        let depth = match self.project.options.depth {
+            Some(depth) => depth,
+            None => return Ok(()),
+        };
+
+        // Deepening fetches in to the project's clone.
+        let mut lock = self.project.lock()?;
+
+        let mut deepenings = 0;
+        while self.is_shallow()? && !self.has_merge_base(commit)? {
+            let how = if deepenings < MAX_DEEPENINGS {
+                // Twice as deep every time.
+                let by = depth.saturating_mul(1u32 << deepenings);
+                info!("Deepening {:?} by {} looking for a merge base", self.clone_to(), by);
+                format!("--deepen={}", by)
+            } else {
+                info!("Fetching all of the history of {:?}", self.clone_to());
+                String::from("--unshallow")
+            };
+
+            clone::git(
+                &self.clone_to(),
+                &[OsStr::new("fetch"), OsStr::new(&how), OsStr::new("origin")],
+            )?;
+            deepenings += 1;
+        }
 
         lock.unlock();
 
-        return result.success();
+        return Ok(());
     }
 
+    /// Merge `commit` in to HEAD, and check out the submodules of the
+    /// result.
     pub fn merge_commit(&self, commit: &OsStr) -> Result<(), Error> {
+        self.merge(commit)?;
+        return self.update_submodules();
+    }
+
+    /// Check out the submodules of HEAD, if the project has them.
+    fn update_submodules(&self) -> Result<(), Error> {
+        if !self.project.submodules {
+            return Ok(());
+        }
+
         let mut lock = self.lock()?;
 
-        let result = Command::new("git")
-            .arg("merge")
-            .arg("--no-gpg-sign")
-            .arg("-m")
-            .arg("Automatic merge for GrahamCOfBorg")
-            .arg(commit)
-            .current_dir(self.clone_to())
-            .status()?;
+        info!("Updating the submodules of {:?}", self.clone_to());
+        // PRs pick the submodules' URLs, which mustn't be paths on the
+        // builder.
+        let submodule = |args: &[&str]| {
+            let mut all = vec![OsStr::new("-c"), OsStr::new("protocol.file.allow=never")];
+            all.push(OsStr::new("submodule"));
+            all.extend(args.iter().map(|arg| OsStr::new(arg)));
+            clone::git(&self.clone_to(), &all)
+        };
+        submodule(&["sync", "--recursive"])?;
+        submodule(&["update", "--init", "--recursive", "--force"])?;
 
         lock.unlock();
 
-        if result.success() {
+        return Ok(());
+    }
+
+    fn merge(&self, commit: &OsStr) -> Result<(), Error> {
+        self.deepen_to(commit)?;
+        let mut lock = self.lock()?;
+
+        let repo = clone::open(&self.clone_to())?;
+        let head = clone::find_commit(&repo, "HEAD")?;
+        let theirs = clone::find_commit(&repo, &commit.to_string_lossy())?;
+
+        let annotated = repo.find_annotated_commit(theirs.id()).map_err(
+            clone::git_error,
+        )?;
+        let (analysis, _) = repo.merge_analysis(&[&annotated]).map_err(
+            clone::git_error,
+        )?;
+
+        if analysis.is_up_to_date() {
+            lock.unlock();
             return Ok(());
-        } else {
+        }
+
+        if analysis.is_fast_forward() {
+            clone::checkout_detached(&repo, &theirs)?;
+            lock.unlock();
+            return Ok(());
+        }
+
+        let mut index = repo.merge_commits(&head, &theirs, None).map_err(
+            clone::git_error,
+        )?;
+        if index.has_conflicts() {
             return Err(Error::new(ErrorKind::Other, "Failed to merge"));
         }
+
+        let tree_id = index.write_tree_to(&repo).map_err(clone::git_error)?;
+        let tree = repo.find_tree(tree_id).map_err(clone::git_error)?;
+        let signature = repo.signature()
+            .or_else(|_| Signature::now("GrahamCOfBorg", "graham+cofborg@example.com"))
+            .map_err(clone::git_error)?;
+        let merged = repo.commit(
+            None,
+            &signature,
+            &signature,
+            "Automatic merge for GrahamCOfBorg",
+            &tree,
+            &[&head, &theirs],
+        ).map_err(clone::git_error)?;
+        let merged = repo.find_commit(merged).map_err(clone::git_error)?;
+        clone::checkout_detached(&repo, &merged)?;
+
+        lock.unlock();
+
+        return Ok(());
     }
 
-    pub fn commit_messages_from_head(&self, commit: &str) -> Result<Vec<String>, Error> {
+    /// Check whether the commit merges cleanly in to HEAD, without
+    /// changing the checkout.
+    pub fn can_merge(&self, commit: &OsStr) -> Result<bool, Error> {
+        self.deepen_to(commit)?;
         let mut lock = self.lock()?;
 
-        let result = Command::new("git")
-            .arg("log")
-            .arg("--format=format:%s")
-            .arg(format!("HEAD..{}", commit))
-            .current_dir(self.clone_to())
-            .output()?;
+        let repo = clone::open(&self.clone_to())?;
+        let head = clone::find_commit(&repo, "HEAD")?;
+        let theirs = clone::find_commit(&repo, &commit.to_string_lossy())?;
+        let index = repo.merge_commits(&head, &theirs, None).map_err(
+            clone::git_error,
+        )?;
 
         lock.unlock();
 
-        if result.status.success() {
-            return Ok(
-                String::from_utf8_lossy(&result.stdout)
-                    .lines()
-                    .map(|l| l.to_owned())
-                    .collect(),
-            );
-        } else {
-            return Err(Error::new(
-                ErrorKind::Other,
-                String::from_utf8_lossy(&result.stderr).to_lowercase(),
-            ));
+        return Ok(!index.has_conflicts());
+    }
+
+    pub fn commit_messages_from_head(&self, commit: &str) -> Result<Vec<String>, Error> {
+        self.deepen_to(OsStr::new(commit))?;
+        let mut lock = self.lock()?;
+
+        let repo = clone::open(&self.clone_to())?;
+        let head = clone::find_commit(&repo, "HEAD")?;
+        let commit = clone::find_commit(&repo, commit)?;
+
+        let mut walk = repo.revwalk().map_err(clone::git_error)?;
+        walk.set_sorting(Sort::TOPOLOGICAL | Sort::TIME);
+        walk.push(commit.id()).map_err(clone::git_error)?;
+        walk.hide(head.id()).map_err(clone::git_error)?;
+
+        let mut messages: Vec<String> = vec![];
+        for id in walk {
+            let id = id.map_err(clone::git_error)?;
+            let commit = repo.find_commit(id).map_err(clone::git_error)?;
+            messages.push(commit.summary().unwrap_or("").to_owned());
         }
+
+        lock.unlock();
+
+        return Ok(messages);
     }
 
     pub fn files_changed_from_head(&self, commit: &str) -> Result<Vec<String>, Error> {
+        return self.diff_from_head(commit, |diff| {
+            let files: Vec<String> = diff.deltas()
+                .filter_map(|delta| {
+                    delta.new_file().path().or(delta.old_file().path()).map(
+                        |path| {
+                            path.to_string_lossy().into_owned()
+                        },
+                    )
+                })
+                .collect();
+            return Ok(files);
+        });
+    }
+
+    /// The lines of each changed file in the hunks of its diff, first
+    /// and last inclusive, as GitHub shows them and lets them be
+    /// commented on.
+    pub fn lines_changed_from_head(
+        &self,
+        commit: &str,
+    ) -> Result<HashMap<String, Vec<(u64, u64)>>, Error> {
+        return self.diff_from_head(commit, |diff| {
+            let mut hunks: HashMap<String, Vec<(u64, u64)>> = HashMap::new();
+            diff.foreach(
+                &mut |_, _| true,
+                None,
+                Some(&mut |delta, hunk| {
+                    if let Some(path) = delta.new_file().path() {
+                        if hunk.new_lines() > 0 {
+                            let first = hunk.new_start() as u64;
+                            let last = first + hunk.new_lines() as u64 - 1;
+                            hunks
+                                .entry(path.to_string_lossy().into_owned())
+                                .or_insert(vec![])
+                                .push((first, last));
+                        }
+                    }
+                    true
+                }),
+                None,
+            ).map_err(clone::git_error)?;
+            return Ok(hunks);
+        });
+    }
+
+    /// The diff from where `commit` forked from HEAD, like
+    /// HEAD...commit.
+    fn diff_from_head<T, F>(&self, commit: &str, f: F) -> Result<T, Error>
+    where
+        F: FnOnce(&Diff) -> Result<T, Error>,
+    {
+        self.deepen_to(OsStr::new(commit))?;
         let mut lock = self.lock()?;
 
-        let result = Command::new("git")
-            .arg("diff")
-            .arg("--name-only")
-            .arg(format!("HEAD...{}", commit))
-            .current_dir(self.clone_to())
-            .output()?;
+        let repo = clone::open(&self.clone_to())?;
+        let head = clone::find_commit(&repo, "HEAD")?;
+        let commit = clone::find_commit(&repo, commit)?;
+        let base = repo.merge_base(head.id(), commit.id()).map_err(
+            clone::git_error,
+        )?;
+        let base = repo.find_commit(base).map_err(clone::git_error)?;
+
+        let base_tree = base.tree().map_err(clone::git_error)?;
+        let tree = commit.tree().map_err(clone::git_error)?;
+        let mut diff = repo.diff_tree_to_tree(Some(&base_tree), Some(&tree), None)
+            .map_err(clone::git_error)?;
+        // Renames are listed by their new name, as git does.
+        diff.find_similar(None).map_err(clone::git_error)?;
+
+        let result = f(&diff);
 
         lock.unlock();
 
-        if result.status.success() {
-            return Ok(
-                String::from_utf8_lossy(&result.stdout)
-                    .lines()
-                    .map(|l| l.to_owned())
-                    .collect(),
-            );
-        } else {
-            return Err(Error::new(
-                ErrorKind::Other,
-                String::from_utf8_lossy(&result.stderr).to_lowercase(),
-            ));
-        }
+        return result;
+    }
+
+    /// Whether `commit` is in the history of `head`. A commit which
+    /// isn't in the clone isn't, unless the clone is shallow and it may
+    /// be hidden below the cut off history.
+    pub fn contains_commit(&self, head: &str, commit: &str) -> bool {
+        let mut lock = self.lock().expect("Failed to lock");
+
+        let contains = clone::open(&self.clone_to()).map(|repo| {
+            let head = match clone::find_commit(&repo, head) {
+                Ok(head) => head,
+                Err(_) => return false,
+            };
+            match clone::find_commit(&repo, commit) {
+                Ok(commit) => {
+                    repo.merge_base(head.id(), commit.id()).ok() == Some(commit.id())
+                }
+                Err(_) => repo.is_shallow(),
+            }
+        });
+
+        lock.unlock();
+
+        return contains.unwrap_or(false);
+    }
+
+    /// The files `commit` changes, compared to its first parent.
+    pub fn files_changed_by(&self, commit: &str) -> Result<Vec<String>, Error> {
+        let mut lock = self.lock()?;
+
+        let repo = clone::open(&self.clone_to())?;
+        let commit = clone::find_commit(&repo, commit)?;
+        let tree = commit.tree().map_err(clone::git_error)?;
+        let parent_tree = match commit.parent(0) {
+            Ok(parent) => Some(parent.tree().map_err(clone::git_error)?),
+            // A root commit adds everything.
+            Err(_) => None,
+        };
+        let diff = repo.diff_tree_to_tree(parent_tree.as_ref(), Some(&tree), None)
+            .map_err(clone::git_error)?;
+
+        let files: Vec<String> = diff.deltas()
+            .filter_map(|delta| {
+                delta.new_file().path().or(delta.old_file().path()).map(
+                    |path| {
+                        path.to_string_lossy().into_owned()
+                    },
+                )
+            })
+            .collect();
+
+        lock.unlock();
+
+        return Ok(files);
+    }
+
+    pub fn commit_hash(&self, commit: &str) -> Result<String, Error> {
+        let mut lock = self.lock()?;
+
+        let repo = clone::open(&self.clone_to())?;
+        let id = clone::find_commit(&repo, commit)?.id();
+
+        lock.unlock();
+
+        return Ok(format!("{}", id));
+    }
+
+    pub fn tree_hash(&self, commit: &str) -> Result<String, Error> {
+        let mut lock = self.lock()?;
+
+        let repo = clone::open(&self.clone_to())?;
+        let tree = clone::find_commit(&repo, commit)?.tree_id();
+
+        lock.unlock();
+
+        return Ok(format!("{}", tree));
     }
 }
 
@@ -220,13 +794,58 @@ impl clone::GitClonable for CachedProjectCo {
         return lock_path;
     }
 
-    fn extra_clone_args(&self) -> Vec<&OsStr> {
-        let local_ref = self.local_reference.as_ref();
-        return vec![
-            OsStr::new("--shared"),
-            OsStr::new("--reference-if-able"),
-            local_ref,
-        ];
+    fn bare(&self) -> bool {
+        return false;
+    }
+
+    fn clone_repo(&self) -> Result<(), Error> {
+        let mut lock = self.lock()?;
+
+        // A worktree's .git is a file pointing at the project's clone.
+        if self.clone_to().join(".git").is_file() {
+            debug!("Found a worktree at {:?}", self.clone_to());
+            return Ok(());
+        }
+
+        if self.clone_to().is_dir() {
+            info!(
+                "Replacing the independent clone at {:?} with a worktree",
+                self.clone_to()
+            );
+            fs::remove_dir_all(self.clone_to())?;
+        }
+
+        let mut project_lock = self.project.lock()?;
+
+        // Forget the worktrees which were deleted.
+        Command::new("git")
+            .arg("worktree")
+            .arg("prune")
+            .current_dir(self.project.clone_to())
+            .status()?;
+
+        info!("Adding a worktree at {:?}", self.clone_to());
+        let result = Command::new("git")
+            .arg("worktree")
+            .arg("add")
+            .arg("--detach")
+            .arg(self.clone_to())
+            .current_dir(self.project.clone_to())
+            .status()?;
+
+        project_lock.unlock();
+        lock.unlock();
+
+        if result.success() {
+            return Ok(());
+        } else {
+            return Err(Error::new(ErrorKind::Other, "Failed to add a worktree"));
+        }
+    }
+
+    /// Fetch in to the project's clone, which every worktree shares.
+    fn fetch_repo(&self) -> Result<(), Error> {
+        self.project.fetch_repo()
     }
 }
 
@@ -247,8 +866,16 @@ impl clone::GitClonable for CachedProject {
         return clone_path;
     }
 
-    fn extra_clone_args(&self) -> Vec<&OsStr> {
-        return vec![OsStr::new("--bare")];
+    fn bare(&self) -> bool {
+        return true;
+    }
+
+    fn clone_options(&self) -> CloneOptions {
+        self.options.clone()
+    }
+
+    fn transfer_metrics(&self) -> Option<clone::TransferMetrics> {
+        self.metrics.clone()
     }
 }
 
@@ -259,6 +886,7 @@ mod tests {
     use std::path::{Path, PathBuf};
     use std::process::{Command, Stdio};
     use ofborg::test_scratch::TestScratch;
+    use std::sync::mpsc;
 
     fn tpath(component: &str) -> PathBuf {
         return Path::new(env!("CARGO_MANIFEST_DIR")).join(component);
@@ -330,4 +958,283 @@ mod tests {
             expect
         );
     }
+
+    #[test]
+    pub fn test_lines_changed() {
+        let workingdir = TestScratch::new_dir("test-test-lines-changed");
+
+        let bare = TestScratch::new_dir("bare-lines-changed");
+        let mk_co = TestScratch::new_dir("mk-lines-changed");
+        let hash = make_pr_repo(&bare.path(), &mk_co.path());
+
+        let cloner = cached_cloner(&workingdir.path());
+        let project = cloner.project("commit-lines-changed".to_owned(), bare.string());
+        let working_co = project
+            .clone_for("testing-lines-changed".to_owned(), "123".to_owned())
+            .expect("clone should work");
+        working_co
+            .checkout_origin_ref(OsStr::new("master"))
+            .unwrap();
+
+        let mut expect: HashMap<String, Vec<(u64, u64)>> = HashMap::new();
+        // The file added is empty, there's nothing to comment on.
+        expect.insert("default.nix".to_owned(), vec![(7, 13)]);
+
+        assert_eq!(
+            working_co.lines_changed_from_head(&hash).expect(
+                "fetching lines changed should work",
+            ),
+            expect
+        );
+    }
+
+    #[test]
+    pub fn test_can_merge() {
+        let workingdir = TestScratch::new_dir("test-test-can-merge");
+
+        let bare = TestScratch::new_dir("bare-can-merge");
+        let mk_co = TestScratch::new_dir("mk-can-merge");
+        let hash = make_pr_repo(&bare.path(), &mk_co.path());
+
+        let cloner = cached_cloner(&workingdir.path());
+        let project = cloner.project("can-merge".to_owned(), bare.string());
+        let working_co = project
+            .clone_for("testing-can-merge".to_owned(), "123".to_owned())
+            .expect("clone should work");
+        working_co
+            .checkout_origin_ref(OsStr::new("master"))
+            .unwrap();
+
+        let before = working_co.tree_hash("HEAD").unwrap();
+        assert_eq!(working_co.can_merge(OsStr::new(&hash)).unwrap(), true);
+        assert_eq!(working_co.tree_hash("HEAD").unwrap(), before);
+    }
+
+    #[test]
+    pub fn test_tree_hash() {
+        let workingdir = TestScratch::new_dir("test-test-tree-hash");
+
+        let bare = TestScratch::new_dir("bare-tree-hash");
+        let mk_co = TestScratch::new_dir("mk-tree-hash");
+        let hash = make_pr_repo(&bare.path(), &mk_co.path());
+
+        let cloner = cached_cloner(&workingdir.path());
+        let project = cloner.project("tree-hash".to_owned(), bare.string());
+        let working_co = project
+            .clone_for("testing-tree-hash".to_owned(), "123".to_owned())
+            .expect("clone should work");
+        working_co
+            .checkout_origin_ref(OsStr::new("master"))
+            .unwrap();
+
+        let head_tree = working_co.tree_hash("HEAD").expect("HEAD has a tree");
+        let pr_tree = working_co.tree_hash(&hash).expect("the PR has a tree");
+
+        assert_eq!(head_tree.len(), 40);
+        assert_eq!(pr_tree.len(), 40);
+        assert!(head_tree != pr_tree);
+        assert!(working_co.tree_hash("not-a-commit").is_err());
+    }
+
+    #[test]
+    pub fn test_contains_commit() {
+        let workingdir = TestScratch::new_dir("test-test-contains-commit");
+
+        let bare = TestScratch::new_dir("bare-contains-commit");
+        let mk_co = TestScratch::new_dir("mk-contains-commit");
+        let hash = make_pr_repo(&bare.path(), &mk_co.path());
+
+        let cloner = cached_cloner(&workingdir.path());
+        let project = cloner.project("contains-commit".to_owned(), bare.string());
+        let working_co = project
+            .clone_for("testing-contains-commit".to_owned(), "123".to_owned())
+            .expect("clone should work");
+        working_co
+            .checkout_origin_ref(OsStr::new("master"))
+            .unwrap();
+        working_co.fetch_pr(1, &hash).unwrap();
+
+        assert!(working_co.contains_commit(&hash, "origin/master"));
+        assert!(!working_co.contains_commit("origin/master", &hash));
+        assert!(!working_co.contains_commit(
+            &hash,
+            "0000000000000000000000000000000000000000",
+        ));
+
+        assert_eq!(
+            working_co.files_changed_by(&hash).unwrap(),
+            vec!["default.nix".to_owned(), "hi another file".to_owned()]
+        );
+    }
+
+    #[test]
+    pub fn test_submodules_by_repo() {
+        let workingdir = TestScratch::new_dir("test-test-submodules-by-repo");
+        let cloner = cached_cloner(&workingdir.path())
+            .with_submodules(vec![String::from("NixOS/nixos-hardware")]);
+
+        assert!(
+            cloner
+                .project(
+                    String::from("nixos/NixOS-Hardware"),
+                    String::from("https://github.com/NixOS/nixos-hardware.git"),
+                )
+                .submodules
+        );
+        assert!(
+            !cloner
+                .project(
+                    String::from("NixOS/nixpkgs"),
+                    String::from("https://github.com/NixOS/nixpkgs.git"),
+                )
+                .submodules
+        );
+    }
+
+    #[test]
+    pub fn test_fetch_pr_verifies_head() {
+        let workingdir = TestScratch::new_dir("test-test-fetch-pr-verifies-head");
+
+        let bare = TestScratch::new_dir("bare-fetch-pr-verifies-head");
+        let mk_co = TestScratch::new_dir("mk-fetch-pr-verifies-head");
+        let hash = make_pr_repo(&bare.path(), &mk_co.path());
+
+        let cloner = cached_cloner(&workingdir.path());
+        let project = cloner.project("fetch-pr-verifies-head".to_owned(), bare.string());
+        let working_co = project
+            .clone_for("testing-fetch-pr".to_owned(), "123".to_owned())
+            .expect("clone should work");
+        working_co
+            .checkout_origin_ref(OsStr::new("master"))
+            .unwrap();
+
+        working_co.fetch_pr(1, &hash).expect("the head is the PR's");
+        working_co.fetch_pr(1, &hash.to_uppercase()).expect(
+            "the head is the PR's, in any case",
+        );
+
+        let master = working_co.tree_hash("HEAD").unwrap();
+        let moved = working_co.fetch_pr(1, &master).unwrap_err();
+        assert_eq!(moved.kind(), ErrorKind::InvalidData);
+
+        assert!(working_co.fetch_pr(2, &hash).is_err());
+    }
+
+    #[test]
+    pub fn test_fetch_pr_while_project_locked() {
+        let workingdir = TestScratch::new_dir("test-test-fetch-pr-while-project-locked");
+
+        let bare = TestScratch::new_dir("bare-fetch-pr-while-project-locked");
+        let mk_co = TestScratch::new_dir("mk-fetch-pr-while-project-locked");
+        let hash = make_pr_repo(&bare.path(), &mk_co.path());
+
+        let cloner = cached_cloner(&workingdir.path());
+        let project = cloner.project("fetch-pr-while-project-locked".to_owned(), bare.string());
+        let working_co = project
+            .clone_for("testing-fetch-pr-locked".to_owned(), "123".to_owned())
+            .expect("clone should work");
+        working_co
+            .checkout_origin_ref(OsStr::new("master"))
+            .unwrap();
+
+        // Like another checkout fetching its base branch.
+        let mut project_lock = project.lock().unwrap();
+
+        let (tx, rx) = mpsc::channel();
+        thread::spawn(move || {
+            tx.send(working_co.fetch_pr(1, &hash).is_ok()).unwrap();
+        });
+        assert_eq!(rx.recv_timeout(Duration::from_secs(60)), Ok(true));
+
+        project_lock.unlock();
+    }
+
+    #[test]
+    pub fn test_shallow_deepening() {
+        let workingdir = TestScratch::new_dir("test-test-shallow-deepening");
+
+        let bare = TestScratch::new_dir("bare-shallow-deepening");
+        let mk_co = TestScratch::new_dir("mk-shallow-deepening");
+        let hash = make_pr_repo(&bare.path(), &mk_co.path());
+
+        // Move master on, so the PR's base is deeper than the clone.
+        for n in 1..3 {
+            let committed = Command::new("sh")
+                .arg("-c")
+                .arg(format!(
+                    "git checkout -q master && echo {0} > more-{0} && git add . && \
+                     git commit -q --no-gpg-sign -m 'more {0}' && git push -q origin master",
+                    n
+                ))
+                .current_dir(mk_co.path())
+                .status()
+                .unwrap();
+            assert!(committed.success());
+        }
+
+        // Git ignores --depth when cloning a path.
+        let cloner = cached_cloner(&workingdir.path()).with_options(CloneOptions {
+            depth: Some(1),
+            filter: None,
+        });
+        let project = cloner.project(
+            "shallow-deepening".to_owned(),
+            format!("file://{}", bare.string()),
+        );
+        let working_co = project
+            .clone_for("testing-shallow-deepening".to_owned(), "123".to_owned())
+            .expect("clone should work");
+        working_co
+            .checkout_origin_ref(OsStr::new("master"))
+            .unwrap();
+        working_co.fetch_pr(1, &hash).unwrap();
+        assert!(working_co.is_shallow().unwrap());
+
+        let expect: Vec<String> = vec!["check out this cool PR".to_owned()];
+        assert_eq!(
+            working_co.commit_messages_from_head(&hash).expect(
+                "fetching messages should work",
+            ),
+            expect
+        );
+    }
+
+    #[test]
+    pub fn test_collect_garbage() {
+        let workingdir = TestScratch::new_dir("test-test-collect-garbage");
+
+        let bare = TestScratch::new_dir("bare-collect-garbage");
+        let mk_co = TestScratch::new_dir("mk-collect-garbage");
+        let hash = make_pr_repo(&bare.path(), &mk_co.path());
+
+        let cloner = cached_cloner(&workingdir.path());
+        let project = cloner.project("collect-garbage".to_owned(), bare.string());
+        let working_co = project
+            .clone_for("testing-collect-garbage".to_owned(), "123".to_owned())
+            .expect("clone should work");
+        working_co
+            .checkout_origin_ref(OsStr::new("master"))
+            .unwrap();
+        working_co.fetch_pr(1, &hash).unwrap();
+
+        let report = cloner.collect_garbage(Duration::from_secs(3600)).unwrap();
+        assert_eq!(report.removed_checkouts, 0);
+        assert!(working_co.clone_to().is_dir());
+        assert_eq!(
+            git(&project.clone_to(), &["for-each-ref", "refs/pull/"]).unwrap(),
+            ""
+        );
+
+        thread::sleep(Duration::from_millis(10));
+        let report = cloner.collect_garbage(Duration::from_secs(0)).unwrap();
+        assert_eq!(report.removed_checkouts, 1);
+        assert!(!working_co.clone_to().exists());
+        assert!(report.bytes_after < report.bytes_before);
+
+        // The checkout comes back the next time it's needed.
+        working_co
+            .checkout_origin_ref(OsStr::new("master"))
+            .unwrap();
+        assert!(working_co.clone_to().is_dir());
+    }
 }
@@ -0,0 +1,153 @@
+use std::fs::File;
+use std::io::Read;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+
+use amqp::Channel;
+use hyper::header::{AccessControlAllowOrigin, CacheControl, CacheDirective, ContentType};
+use hyper::method::Method;
+use hyper::server::{Request, Response, Server};
+use hyper::status::StatusCode;
+use hyper::uri::RequestUri;
+
+use ofborg::api::{Api, Reply};
+use ofborg::banlist;
+use ofborg::config;
+use ofborg::msgsign::Signer;
+use ofborg::easyamqp;
+use ofborg::tasks::queuemonitor::QueueMonitor;
+use ofborg::trigger::Client;
+use ofborg::worker;
+
+fn header(req: &Request, name: &str) -> Option<String> {
+    req.headers
+        .get_raw(name)
+        .and_then(|values| values.first())
+        .and_then(|value| String::from_utf8(value.clone()).ok())
+}
+
+fn trigger_clients(api_cfg: &config::ApiConfig) -> Vec<Client> {
+    api_cfg
+        .trigger_clients
+        .clone()
+        .unwrap_or(vec![])
+        .into_iter()
+        .map(|client| {
+            let mut secret = String::new();
+            File::open(&client.secret_file)
+                .and_then(|mut f| f.read_to_string(&mut secret))
+                .expect("reading a trigger client's secret");
+            Client {
+                name: client.name,
+                secret: secret.trim().to_owned(),
+                repos: client.repos,
+                trusted: client.trusted.unwrap_or(false),
+            }
+        })
+        .collect()
+}
+
+fn publish(channel: &Mutex<Channel>, signer: &Option<Signer>, actions: Vec<worker::Action>) {
+    let mut channel = channel.lock().unwrap();
+    for action in actions {
+        if let worker::Action::Publish(msg) = action {
+            easyamqp::publish(&mut channel, signer, msg).unwrap();
+        }
+    }
+}
+
+/// Serve the JSON API the status page reads, on `api.listen`, and take
+/// signed `POST`s to `/trigger` from `api.trigger_clients`.
+pub fn main() {
+    let cfg = config::load_from_args();
+    cfg.setup_log("api");
+
+    let api_cfg = cfg.api.clone().expect("fetching config's api");
+    let clients = trigger_clients(&api_cfg);
+    let mut api = Api::new(
+        cfg.log_storage.as_ref().map(|storage| PathBuf::from(&storage.path)),
+        api_cfg.log_url.clone().unwrap_or(String::from("https://logs.nix.ci/")),
+        Duration::from_secs(api_cfg.active_builder_seconds.unwrap_or(60 * 60)),
+    ).with_queues(QueueMonitor::new(
+        &cfg.rabbitmq,
+        cfg.queue_monitor.clone().unwrap_or_default(),
+    ));
+    if cfg.github.is_some() {
+        api = api.with_github(cfg.github_api());
+    }
+    if let Some(ref path) = cfg.runner.ban_list {
+        let banned = Arc::new(RwLock::new(
+            banlist::load(path).expect("loading config's runner.ban_list"),
+        ));
+        banlist::watch(
+            path.clone(),
+            banned.clone(),
+            Duration::from_secs(banlist::RELOAD_SECS),
+        );
+        api = api.with_ban_list(banned);
+    }
+    if let Some(limiter) = cfg.command_limiter() {
+        api = api.with_command_limiter(limiter);
+    }
+
+    // Jobs are only published when someone may trigger them.
+    let mut session = if clients.len() > 0 {
+        let session = easyamqp::session_from_config(&cfg.rabbitmq).unwrap();
+        info!("Connected to rabbitmq");
+        Some(session)
+    } else {
+        None
+    };
+    let channel = session.as_mut().map(
+        |session| Mutex::new(session.open_channel(1).unwrap()),
+    );
+    let api = api.with_trigger_clients(clients);
+    let signer = cfg.rabbitmq.signer();
+
+    info!("listening addr {:?}", api_cfg.listen);
+    Server::http(api_cfg.listen.as_str())
+        .unwrap()
+        .handle(move |mut req: Request, mut res: Response| {
+            let path = match req.uri {
+                RequestUri::AbsolutePath(ref path) => Some(path.clone()),
+                _ => None,
+            };
+
+            let reply = match (&req.method.clone(), path) {
+                (&Method::Get, Some(path)) => api.respond(&path),
+                (&Method::Post, Some(ref path)) if path == "/trigger" && channel.is_some() => {
+                    let client = header(&req, "X-Ofborg-Client");
+                    let timestamp = header(&req, "X-Ofborg-Timestamp");
+                    let signature = header(&req, "X-Ofborg-Signature");
+                    let mut body = vec![];
+                    let _ = req.read_to_end(&mut body);
+
+                    let (reply, jobs) = api.trigger(
+                        client.as_ref().map(|c| c.as_str()),
+                        timestamp.as_ref().map(|t| t.as_str()),
+                        signature.as_ref().map(|s| s.as_str()),
+                        &body,
+                    );
+                    publish(channel.as_ref().unwrap(), &signer, jobs);
+                    reply
+                }
+                _ => {
+                    Reply {
+                        status: StatusCode::MethodNotAllowed,
+                        content_type: "application/json",
+                        body: String::from("{\"error\":\"Method not allowed\"}"),
+                    }
+                }
+            };
+
+            *res.status_mut() = reply.status;
+            res.headers_mut().set(ContentType(reply.content_type.parse().unwrap()));
+            res.headers_mut().set(AccessControlAllowOrigin::Any);
+            // Embedded badges are proxied by GitHub, which caches them
+            // unless told not to.
+            res.headers_mut().set(CacheControl(vec![CacheDirective::NoCache]));
+            let _ = res.send(reply.body.as_bytes());
+        })
+        .unwrap();
+}
@@ -0,0 +1,85 @@
+use ofborg::config;
+use ofborg::worker;
+use ofborg::tasks;
+use ofborg::easyamqp;
+use ofborg::easyamqp::TypedWrappers;
+use ofborg::message::audit;
+
+
+pub fn main() {
+    let cfg = config::load_from_args();
+    cfg.setup_log("audit-logger");
+    let metrics = cfg.metrics("audit-logger");
+    let tracer = cfg.tracer("audit-logger");
+
+    let mut session = easyamqp::session_from_config(&cfg.rabbitmq).unwrap();
+    info!("Connected to rabbitmq");
+
+    let mut channel = session.open_channel(1).unwrap();
+
+    channel
+        .declare_exchange(easyamqp::ExchangeConfig {
+            exchange: audit::EXCHANGE.to_owned(),
+            exchange_type: easyamqp::ExchangeType::Fanout,
+            passive: false,
+            durable: true,
+            auto_delete: false,
+            no_wait: false,
+            internal: false,
+            arguments: None,
+        })
+        .unwrap();
+
+    // Durable, so no event is lost while the logger is down.
+    channel
+        .declare_queue(easyamqp::QueueConfig {
+            queue: "audit-log".to_owned(),
+            passive: false,
+            durable: true,
+            exclusive: false,
+            auto_delete: false,
+            no_wait: false,
+            arguments: None,
+        })
+        .unwrap();
+
+    channel
+        .bind_queue(easyamqp::BindQueueConfig {
+            queue: "audit-log".to_owned(),
+            exchange: audit::EXCHANGE.to_owned(),
+            routing_key: None,
+            no_wait: false,
+            arguments: None,
+        })
+        .unwrap();
+
+    let path = cfg.audit_log.clone().expect("fetching config's audit_log").path;
+    channel
+        .consume(
+            worker::new(tasks::auditlogger::AuditLogger::new(&path).unwrap())
+                .with_metrics(&metrics)
+                .with_tracer(&tracer)
+                .with_signer(cfg.rabbitmq.signer()),
+            easyamqp::ConsumeConfig {
+                queue: "audit-log".to_owned(),
+                consumer_tag: format!("{}-audit-logger", cfg.whoami()),
+                no_local: false,
+                no_ack: false,
+                no_wait: false,
+                exclusive: false,
+                arguments: None,
+            },
+        )
+        .unwrap();
+
+
+    channel.start_consuming();
+
+    info!("Finished consuming");
+
+    channel.close(200, "Bye").unwrap();
+    info!("Closed the channel");
+    session.close(200, "Good Bye");
+    info!("Closed the session... EOF");
+
+}
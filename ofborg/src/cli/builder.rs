@@ -0,0 +1,276 @@
+use std::process;
+use std::sync::Arc;
+use std::thread;
+
+use std::path::Path;
+use amqp::Basic;
+use ofborg::cli;
+use ofborg::config;
+use ofborg::commentparser::Subset;
+use ofborg::message::{buildjob, Pr, Repo};
+use ofborg::message::envelope::Encoding;
+use ofborg::prmetadata;
+use ofborg::checkout;
+use ofborg::cancellation;
+use ofborg::nix::{self, Nix};
+use ofborg::nixpath;
+use ofborg::stats;
+use ofborg::tracing;
+use ofborg::notifyworker::{self, SimpleNotifyWorker};
+use ofborg::tasks;
+use ofborg::easyamqp;
+use ofborg::easyamqp::TypedWrappers;
+
+
+pub fn main() {
+    let cfg = config::load_from_args();
+
+    cfg.setup_log("builder");
+
+    let args = cli::args();
+    if cli::flag(&args, "--once") {
+        once(&cfg, &args);
+        return;
+    }
+
+    let metrics = cfg.metrics("builder");
+    let tracer = cfg.tracer("builder");
+    cfg.maintain_checkouts(&metrics);
+
+    let nix = cfg.nix("builder");
+    let capabilities = nix::Capabilities::probe(nix.cli());
+    info!("Running nix {:?}", capabilities);
+    let pins = cfg.nixpkgs_pins();
+
+    let full_logs: bool = match &cfg.feedback {
+        &Some(ref feedback) => feedback.full_logs,
+        &None => {
+            warn!("Please define feedback.full_logs in your configuration to true or false!");
+            warn!("feedback.full_logs when true will cause the full build log to be sent back");
+            warn!("to the server, and be viewable by everyone.");
+            warn!("I strongly encourage everybody turn this on!");
+            false
+        }
+    };
+
+    let cancellations = cancellation::listen(
+        &cfg.rabbitmq,
+        format!("{}-builder-cancellations", cfg.whoami()),
+        Path::new(&cfg.checkout.root),
+    );
+
+    let remote_stores = cfg.nix.remote_stores.clone().unwrap_or(vec![]);
+    if remote_stores.len() == 0 {
+        consume(
+            &cfg,
+            nix,
+            cfg.runner.identity.clone(),
+            full_logs,
+            cancellations,
+            capabilities,
+            pins,
+            &metrics,
+            &tracer,
+        );
+        return;
+    }
+
+    // Coordinate builds on remote stores, each consuming on its own so
+    // every machine runs one build at a time.
+    let cfg = Arc::new(cfg);
+    let handles: Vec<thread::JoinHandle<()>> = remote_stores
+        .into_iter()
+        .enumerate()
+        .map(|(i, store)| {
+            info!("Building on {}", store);
+            let cfg = cfg.clone();
+            let nix = nix.with_store(Some(store));
+            let identity = format!("{}-remote-{}", cfg.runner.identity, i);
+            let cancellations = cancellations.clone();
+            let capabilities = capabilities.clone();
+            let pins = pins.clone();
+            let metrics = metrics.clone();
+            let tracer = tracer.clone();
+            thread::spawn(move || {
+                consume(
+                    &cfg,
+                    nix,
+                    identity,
+                    full_logs,
+                    cancellations,
+                    capabilities,
+                    pins,
+                    &metrics,
+                    &tracer,
+                )
+            })
+        })
+        .collect();
+
+    for handle in handles {
+        handle.join().unwrap();
+    }
+}
+
+/// Build attrs of a PR, like `--once --pr 12345 --attr hello`, without
+/// RabbitMQ: the job is made up from GitHub, and what would be published
+/// is printed instead.
+fn once(cfg: &config::Config, args: &[String]) {
+    let number: u64 = match cli::value(args, "--pr").and_then(|n| n.parse().ok()) {
+        Some(number) => number,
+        None => {
+            eprintln!("--once needs the number of the PR to build, like --pr 12345");
+            process::exit(1);
+        }
+    };
+    let attrs = cli::values(args, "--attr");
+    let full_name = cli::value(args, "--repo").unwrap_or(String::from("NixOS/nixpkgs"));
+    let (owner, name) = match full_name.find('/') {
+        Some(i) => (full_name[..i].to_owned(), full_name[i + 1..].to_owned()),
+        None => {
+            eprintln!("--repo needs the owner too, like NixOS/nixpkgs");
+            process::exit(1);
+        }
+    };
+
+    let meta = match prmetadata::fetch(&cfg.github_api(), &owner, &name, number) {
+        Ok(meta) => meta,
+        Err(e) => {
+            eprintln!("Failed to fetch {}#{}: {:?}", full_name, number, e);
+            process::exit(1);
+        }
+    };
+
+    let job = buildjob::BuildJob::new(
+        Repo {
+            clone_url: meta.clone_url,
+            full_name: full_name.clone(),
+            owner: owner,
+            name: name,
+        },
+        Pr {
+            number: number,
+            head_sha: meta.head_sha,
+            target_branch: Some(meta.base_ref),
+        },
+        Subset::Nixpkgs,
+        attrs,
+        None,
+        None,
+    );
+
+    let nix = cfg.nix("builder");
+    let capabilities = nix::Capabilities::probe(nix.cli());
+    let cloner = checkout::cached_cloner(Path::new(&cfg.checkout.root))
+        .with_options(cfg.checkout.clone_options())
+        .with_submodules(cfg.submodule_repos());
+
+    let worker = tasks::build::BuildWorker::new(
+        cloner,
+        nix,
+        cfg.nix.system.clone(),
+        cfg.runner.identity.clone(),
+        true,
+        cancellation::Cancellations::new(),
+        capabilities,
+    ).with_pins(cfg.nixpkgs_pins());
+
+    worker.consumer(&job, &mut notifyworker::StdoutNotificationReceiver::new());
+}
+
+fn consume(
+    cfg: &config::Config,
+    nix: Nix,
+    identity: String,
+    full_logs: bool,
+    cancellations: cancellation::Cancellations,
+    capabilities: nix::Capabilities,
+    pins: Option<nixpath::Pins>,
+    metrics: &stats::Registry,
+    tracer: &tracing::Tracer,
+) {
+    let cloner = checkout::cached_cloner(Path::new(&cfg.checkout.root))
+        .with_options(cfg.checkout.clone_options())
+        .with_submodules(cfg.submodule_repos())
+        .with_metrics(metrics);
+
+    let log_encoding = cfg.feedback
+        .as_ref()
+        .and_then(|feedback| feedback.log_encoding)
+        .unwrap_or(Encoding::Json);
+    let legacy_build_results = cfg.feedback
+        .as_ref()
+        .map(|feedback| feedback.legacy_build_results)
+        .unwrap_or(false);
+
+    let mut session = easyamqp::session_from_config(&cfg.rabbitmq).unwrap();
+    let mut channel = session.open_channel(1).unwrap();
+    channel.basic_prefetch(1).unwrap();
+    channel
+        .declare_exchange(easyamqp::ExchangeConfig {
+            exchange: "build-jobs".to_owned(),
+            exchange_type: easyamqp::ExchangeType::Fanout,
+            passive: false,
+            durable: true,
+            auto_delete: false,
+            no_wait: false,
+            internal: false,
+            arguments: None,
+        })
+        .unwrap();
+
+    channel
+        .declare_queue(easyamqp::QueueConfig {
+            queue: format!("build-inputs-{}", cfg.nix.system.clone()),
+            passive: false,
+            durable: true,
+            exclusive: false,
+            auto_delete: false,
+            no_wait: false,
+            arguments: Some(easyamqp::max_priority(buildjob::MAX_PRIORITY)),
+        })
+        .unwrap();
+
+    channel
+        .bind_queue(easyamqp::BindQueueConfig {
+            queue: format!("build-inputs-{}", cfg.nix.system.clone()),
+            exchange: "build-jobs".to_owned(),
+            routing_key: None,
+            no_wait: false,
+            arguments: None,
+        })
+        .unwrap();
+
+    channel
+        .consume(
+            notifyworker::new(tasks::build::BuildWorker::new(
+                cloner,
+                nix,
+                cfg.nix.system.clone(),
+                identity.clone(),
+                full_logs,
+                cancellations,
+                capabilities,
+            ).with_pins(pins)
+                .with_log_encoding(log_encoding)
+                .with_legacy_results(legacy_build_results)).with_metrics(metrics)
+                .with_tracer(tracer)
+                .with_signer(cfg.rabbitmq.signer()),
+            easyamqp::ConsumeConfig {
+                queue: format!("build-inputs-{}", cfg.nix.system.clone()),
+                consumer_tag: format!("{}-{}-builder", identity, cfg.nix.system),
+                no_local: false,
+                no_ack: false,
+                no_wait: false,
+                exclusive: false,
+                arguments: None,
+            },
+        )
+        .unwrap();
+
+    channel.start_consuming();
+    channel.close(200, "Bye").unwrap();
+    info!("Closed the channel");
+    session.close(200, "Good Bye");
+    info!("Closed the session... EOF");
+}
@@ -0,0 +1,94 @@
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process;
+
+use ofborg::branchpolicy;
+use ofborg::checkout;
+use ofborg::cli;
+use ofborg::config;
+use ofborg::dryrun;
+use ofborg::logging;
+use ofborg::nix::Nix;
+
+const USAGE: &'static str = "Usage: ofborg eval --path <nixpkgs> --head <branch or commit> \
+     [--base <branch>] [--system <system>] [<config>]";
+
+/// Evaluate a branch of a local nixpkgs checkout like the evaluator
+/// evaluates a PR, and print what it would say about it. A configuration
+/// is optional, the labels and branch policies come from it when given.
+pub fn main() {
+    let cfg = config::load_optional_from_args();
+    match cfg {
+        Some(ref cfg) => cfg.setup_log("eval"),
+        None => logging::init("eval", None),
+    }
+
+    let args = cli::args();
+    let path = match cli::value(&args, "--path") {
+        Some(path) => path,
+        None => {
+            eprintln!("{}", USAGE);
+            process::exit(1);
+        }
+    };
+    let head = match cli::value(&args, "--head") {
+        Some(head) => head,
+        None => {
+            eprintln!("{}", USAGE);
+            process::exit(1);
+        }
+    };
+    let base = cli::value(&args, "--base").unwrap_or(String::from("master"));
+
+    let path = match fs::canonicalize(&path) {
+        Ok(path) => path,
+        Err(e) => {
+            eprintln!("Failed to find {}: {}", path, e);
+            process::exit(1);
+        }
+    };
+
+    let (nix, root, tag_paths, policies) = match cfg {
+        Some(ref cfg) => {
+            (
+                cfg.nix("eval"),
+                PathBuf::from(&cfg.checkout.root),
+                cfg.tag_paths.clone().unwrap_or(HashMap::new()),
+                cfg.branch_policies.clone().unwrap_or(vec![]),
+            )
+        }
+        None => {
+            let system = cli::value(&args, "--system").unwrap_or(String::from("x86_64-linux"));
+            (
+                Nix::new(system, String::from("daemon"), 1800, None),
+                env::temp_dir().join("ofborg-eval"),
+                HashMap::new(),
+                vec![],
+            )
+        }
+    };
+
+    // The local repository is cloned like any other, so only what's
+    // committed in it is evaluated.
+    let project = checkout::cached_cloner(Path::new(&root)).project(
+        format!("local:{}", path.display()),
+        path.display().to_string(),
+    );
+    let co = match project.clone_for(String::from("eval-dry-run"), String::from("local")) {
+        Ok(co) => co,
+        Err(e) => {
+            eprintln!("Failed to clone {}: {}", path.display(), e);
+            process::exit(1);
+        }
+    };
+
+    let policy = branchpolicy::policy_for(&policies, &base);
+    let report = dryrun::run(&nix, &co, &base, &head, tag_paths, &policy);
+    print!("{}", report.text());
+
+    if report.failure.is_some() || report.checks.iter().any(|&(_, passed)| !passed) {
+        process::exit(1);
+    }
+}
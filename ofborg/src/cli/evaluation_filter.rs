@@ -0,0 +1,162 @@
+use amqp::Basic;
+
+use ofborg::config;
+use ofborg::reload;
+use ofborg::worker;
+use ofborg::tasks;
+use ofborg::easyamqp;
+use ofborg::easyamqp::TypedWrappers;
+
+
+pub fn main() {
+    let cfg = config::load_from_args();
+    cfg.setup_log("evaluation-filter");
+    let metrics = cfg.metrics("evaluation-filter");
+    let tracer = cfg.tracer("evaluation-filter");
+
+
+
+    let mut session = easyamqp::session_from_config(&cfg.rabbitmq).unwrap();
+    info!("Connected to rabbitmq");
+
+    let mut channel = session.open_channel(1).unwrap();
+
+    channel
+        .declare_exchange(easyamqp::ExchangeConfig {
+            exchange: "github-events".to_owned(),
+            exchange_type: easyamqp::ExchangeType::Topic,
+            passive: false,
+            durable: true,
+            auto_delete: false,
+            no_wait: false,
+            internal: false,
+            arguments: None,
+        })
+        .unwrap();
+
+    channel
+        .declare_queue(easyamqp::QueueConfig {
+            queue: "mass-rebuild-check-jobs".to_owned(),
+            passive: false,
+            durable: true,
+            exclusive: false,
+            auto_delete: false,
+            no_wait: false,
+            arguments: None,
+        })
+        .unwrap();
+
+    channel
+        .declare_queue(easyamqp::QueueConfig {
+            queue: "mass-rebuild-check-inputs".to_owned(),
+            passive: false,
+            durable: true,
+            exclusive: false,
+            auto_delete: false,
+            no_wait: false,
+            arguments: None,
+        })
+        .unwrap();
+
+    for repo in cfg.repo_names() {
+        channel
+            .bind_queue(easyamqp::BindQueueConfig {
+                queue: "mass-rebuild-check-inputs".to_owned(),
+                exchange: "github-events".to_owned(),
+                routing_key: Some(format!("pull_request.{}", repo)),
+                no_wait: false,
+                arguments: None,
+            })
+            .unwrap();
+    }
+
+    channel
+        .declare_queue(easyamqp::QueueConfig {
+            queue: "check-run-inputs".to_owned(),
+            passive: false,
+            durable: true,
+            exclusive: false,
+            auto_delete: false,
+            no_wait: false,
+            arguments: None,
+        })
+        .unwrap();
+
+    for repo in cfg.repo_names() {
+        channel
+            .bind_queue(easyamqp::BindQueueConfig {
+                queue: "check-run-inputs".to_owned(),
+                exchange: "github-events".to_owned(),
+                routing_key: Some(format!("check_run.{}", repo)),
+                no_wait: false,
+                arguments: None,
+            })
+            .unwrap();
+    }
+
+    for repo in cfg.repo_names() {
+        channel
+            .bind_queue(easyamqp::BindQueueConfig {
+                queue: "check-run-inputs".to_owned(),
+                exchange: "github-events".to_owned(),
+                routing_key: Some(format!("check_suite.{}", repo)),
+                no_wait: false,
+                arguments: None,
+            })
+            .unwrap();
+    }
+
+    let acl = cfg.acl();
+    let check_run_acl = cfg.acl();
+    reload::Reloadable::new().with_acl(&acl).on_sighup(config::Location::from_args());
+    reload::Reloadable::new().with_acl(&check_run_acl).on_sighup(config::Location::from_args());
+
+    channel.basic_prefetch(1).unwrap();
+    channel
+        .consume(
+            worker::new(tasks::evaluationfilter::EvaluationFilterWorker::new(
+                acl,
+            )).with_metrics(&metrics)
+                .with_tracer(&tracer)
+                .with_signer(cfg.rabbitmq.signer()),
+            easyamqp::ConsumeConfig {
+                queue: "mass-rebuild-check-inputs".to_owned(),
+                consumer_tag: format!("{}-evaluation-filter", cfg.whoami()),
+                no_local: false,
+                no_ack: false,
+                no_wait: false,
+                exclusive: false,
+                arguments: None,
+            },
+        )
+        .unwrap();
+
+    channel
+        .consume(
+            worker::new(tasks::checkrunfilter::CheckRunFilterWorker::new(
+                check_run_acl,
+                cfg.eval_queue(),
+            )).with_metrics(&metrics)
+                .with_tracer(&tracer)
+                .with_signer(cfg.rabbitmq.signer()),
+            easyamqp::ConsumeConfig {
+                queue: "check-run-inputs".to_owned(),
+                consumer_tag: format!("{}-check-run-filter", cfg.whoami()),
+                no_local: false,
+                no_ack: false,
+                no_wait: false,
+                exclusive: false,
+                arguments: None,
+            },
+        )
+        .unwrap();
+
+    channel.start_consuming();
+
+    info!("Finished consuming");
+
+    channel.close(200, "Bye").unwrap();
+    info!("Closed the channel");
+    session.close(200, "Good Bye");
+    info!("Closed the session... EOF");
+}
@@ -0,0 +1,94 @@
+use std::path::PathBuf;
+
+use ofborg::config;
+use ofborg::worker;
+use ofborg::tasks;
+use ofborg::easyamqp;
+use ofborg::easyamqp::TypedWrappers;
+
+
+pub fn main() {
+    let cfg = config::load_from_args();
+    cfg.setup_log("failure-reporter");
+    let metrics = cfg.metrics("failure-reporter");
+    let tracer = cfg.tracer("failure-reporter");
+
+    let report_cfg = cfg.failure_report.clone().expect(
+        "fetching config's failure_report",
+    );
+
+    let mut session = easyamqp::session_from_config(&cfg.rabbitmq).unwrap();
+    info!("Connected to rabbitmq");
+
+    let mut channel = session.open_channel(1).unwrap();
+
+    channel
+        .declare_exchange(easyamqp::ExchangeConfig {
+            exchange: "build-results".to_owned(),
+            exchange_type: easyamqp::ExchangeType::Fanout,
+            passive: false,
+            durable: true,
+            auto_delete: false,
+            no_wait: false,
+            internal: false,
+            arguments: None,
+        })
+        .unwrap();
+
+    // Durable, so no result is missed while the reporter is down.
+    channel
+        .declare_queue(easyamqp::QueueConfig {
+            queue: "failure-report".to_owned(),
+            passive: false,
+            durable: true,
+            exclusive: false,
+            auto_delete: false,
+            no_wait: false,
+            arguments: None,
+        })
+        .unwrap();
+
+    channel
+        .bind_queue(easyamqp::BindQueueConfig {
+            queue: "failure-report".to_owned(),
+            exchange: "build-results".to_owned(),
+            routing_key: None,
+            no_wait: false,
+            arguments: None,
+        })
+        .unwrap();
+
+    let reporter = tasks::failurereport::FailureReporter::new(
+        PathBuf::from(&report_cfg.path),
+        report_cfg.window_days.unwrap_or(7) * 86400,
+        report_cfg.interval_seconds.unwrap_or(3600),
+        report_cfg.top.unwrap_or(100),
+    );
+    channel
+        .consume(
+            worker::new(reporter)
+                .with_metrics(&metrics)
+                .with_tracer(&tracer)
+                .with_signer(cfg.rabbitmq.signer()),
+            easyamqp::ConsumeConfig {
+                queue: "failure-report".to_owned(),
+                consumer_tag: format!("{}-failure-reporter", cfg.whoami()),
+                no_local: false,
+                no_ack: false,
+                no_wait: false,
+                exclusive: false,
+                arguments: None,
+            },
+        )
+        .unwrap();
+
+
+    channel.start_consuming();
+
+    info!("Finished consuming");
+
+    channel.close(200, "Bye").unwrap();
+    info!("Closed the channel");
+    session.close(200, "Good Bye");
+    info!("Closed the session... EOF");
+}
@@ -0,0 +1,158 @@
+use amqp::Basic;
+
+use ofborg::config;
+use ofborg::reload;
+use ofborg::cancellation;
+use ofborg::message::audit;
+use ofborg::worker;
+use ofborg::tasks;
+use ofborg::easyamqp;
+use ofborg::easyamqp::TypedWrappers;
+
+
+pub fn main() {
+    let cfg = config::load_from_args();
+    cfg.setup_log("github-comment-filter");
+    let metrics = cfg.metrics("github-comment-filter");
+    let tracer = cfg.tracer("github-comment-filter");
+
+
+
+    let mut session = easyamqp::session_from_config(&cfg.rabbitmq).unwrap();
+    info!("Connected to rabbitmq");
+
+    let mut channel = session.open_channel(1).unwrap();
+    channel
+        .declare_exchange(easyamqp::ExchangeConfig {
+            exchange: "github-events".to_owned(),
+            exchange_type: easyamqp::ExchangeType::Topic,
+            passive: false,
+            durable: true,
+            auto_delete: false,
+            no_wait: false,
+            internal: false,
+            arguments: None,
+        })
+        .unwrap();
+
+    channel
+        .declare_exchange(easyamqp::ExchangeConfig {
+            exchange: "build-jobs".to_owned(),
+            exchange_type: easyamqp::ExchangeType::Fanout,
+            passive: false,
+            durable: true,
+            auto_delete: false,
+            no_wait: false,
+            internal: false,
+            arguments: None,
+        })
+        .unwrap();
+
+    channel
+        .declare_exchange(easyamqp::ExchangeConfig {
+            exchange: cancellation::EXCHANGE.to_owned(),
+            exchange_type: easyamqp::ExchangeType::Fanout,
+            passive: false,
+            durable: true,
+            auto_delete: false,
+            no_wait: false,
+            internal: false,
+            arguments: None,
+        })
+        .unwrap();
+
+    channel
+        .declare_exchange(easyamqp::ExchangeConfig {
+            exchange: audit::EXCHANGE.to_owned(),
+            exchange_type: easyamqp::ExchangeType::Fanout,
+            passive: false,
+            durable: true,
+            auto_delete: false,
+            no_wait: false,
+            internal: false,
+            arguments: None,
+        })
+        .unwrap();
+
+    channel
+        .declare_queue(easyamqp::QueueConfig {
+            queue: "build-inputs".to_owned(),
+            passive: false,
+            durable: true,
+            exclusive: false,
+            auto_delete: false,
+            no_wait: false,
+            arguments: None,
+        })
+        .unwrap();
+
+    channel
+        .bind_queue(easyamqp::BindQueueConfig {
+            queue: "build-inputs".to_owned(),
+            exchange: "github-events".to_owned(),
+            routing_key: Some("issue_comment.*".to_owned()),
+            no_wait: false,
+            arguments: None,
+        })
+        .unwrap();
+
+    channel
+        .bind_queue(easyamqp::BindQueueConfig {
+            queue: "build-inputs".to_owned(),
+            exchange: "github-events".to_owned(),
+            routing_key: Some("pull_request_review_comment.*".to_owned()),
+            no_wait: false,
+            arguments: None,
+        })
+        .unwrap();
+
+    channel
+        .bind_queue(easyamqp::BindQueueConfig {
+            queue: "build-inputs".to_owned(),
+            exchange: "github-events".to_owned(),
+            routing_key: Some("pull_request.*".to_owned()),
+            no_wait: false,
+            arguments: None,
+        })
+        .unwrap();
+
+    let acl = cfg.acl();
+    let command_limiter = cfg.command_limiter();
+    reload::Reloadable::new()
+        .with_acl(&acl)
+        .with_command_limiter(&command_limiter)
+        .on_sighup(config::Location::from_args());
+
+    channel.basic_prefetch(1).unwrap();
+    channel
+        .consume(
+            worker::new(tasks::githubcommentfilter::GitHubCommentWorker::new(
+                acl,
+                cfg.github_api(),
+                cfg.controllable_labels.clone().unwrap_or(vec![]),
+                cfg.triggers(),
+                command_limiter,
+            )).with_metrics(&metrics)
+                .with_tracer(&tracer)
+                .with_signer(cfg.rabbitmq.signer()),
+            easyamqp::ConsumeConfig {
+                queue: "build-inputs".to_owned(),
+                consumer_tag: format!("{}-github-comment-filter", cfg.whoami()),
+                no_local: false,
+                no_ack: false,
+                no_wait: false,
+                exclusive: false,
+                arguments: None,
+            },
+        )
+        .unwrap();
+
+    channel.start_consuming();
+
+    info!("Finished consuming");
+
+    channel.close(200, "Bye").unwrap();
+    info!("Closed the channel");
+    session.close(200, "Good Bye");
+    info!("Closed the session... EOF");
+}
@@ -0,0 +1,160 @@
+use std::io::{self, BufRead, Write};
+use std::path::PathBuf;
+use std::process;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+use ofborg::cli;
+use ofborg::config;
+use ofborg::logging;
+use ofborg::logviewer::{self, Attempt, Tail};
+
+const HELP: &'static str = "<number> opens an attempt, /<text> filters them, \
+     r refreshes, q quits";
+const ATTEMPT_HELP: &'static str = "f follows the log until enter is pressed, \
+     /<text> searches it, b goes back, q quits";
+
+/// Browse the logs under `--root`, the configured `log_storage` by
+/// default, for the log host where there's no browser.
+pub fn main() {
+    let cfg = config::load_optional_from_args();
+    match cfg {
+        Some(ref cfg) => cfg.setup_log("logs"),
+        None => logging::init("logs", None),
+    }
+
+    let args = cli::args();
+    let root = match cli::value(&args, "--root") {
+        Some(root) => PathBuf::from(root),
+        None => {
+            match cfg.as_ref().and_then(|cfg| cfg.log_storage.as_ref()) {
+                Some(storage) => PathBuf::from(&storage.path),
+                None => {
+                    eprintln!("Usage: ofborg logs [--root <dir>] [--count <attempts>] [<config>]");
+                    process::exit(1);
+                }
+            }
+        }
+    };
+    let count: usize = cli::value(&args, "--count")
+        .and_then(|n| n.parse().ok())
+        .unwrap_or(20);
+
+    let input = read_input();
+    let mut filter = String::new();
+    loop {
+        let attempts: Vec<Attempt> = match logviewer::recent(&root, count) {
+            Ok(attempts) => attempts.into_iter().filter(|a| a.matches(&filter)).collect(),
+            Err(e) => {
+                eprintln!("{}", e);
+                process::exit(1);
+            }
+        };
+
+        let now = SystemTime::now();
+        println!();
+        for (i, attempt) in attempts.iter().enumerate() {
+            println!("{:>3}. {}", i + 1, attempt.summary(now));
+        }
+        if attempts.len() == 0 {
+            println!("No attempts");
+        }
+
+        let command = match prompt(&input, HELP) {
+            Some(command) => command,
+            None => return,
+        };
+        if command == "q" {
+            return;
+        } else if command == "r" {
+            continue;
+        } else if command.starts_with("/") {
+            filter = command[1..].to_owned();
+        } else if let Some(attempt) = command.parse::<usize>().ok().and_then(|n| {
+            attempts.get(n.wrapping_sub(1))
+        })
+        {
+            if !open(attempt, &input) {
+                return;
+            }
+        } else {
+            println!("{}", HELP);
+        }
+    }
+}
+
+/// Show `attempt` until going back, or false to quit.
+fn open(attempt: &Attempt, input: &Receiver<String>) -> bool {
+    println!("\n{}", attempt.summary(SystemTime::now()));
+    let mut tail = Tail::new(attempt.path.clone());
+    print_lines(&mut tail);
+
+    loop {
+        let command = match prompt(input, ATTEMPT_HELP) {
+            Some(command) => command,
+            None => return false,
+        };
+        if command == "q" {
+            return false;
+        } else if command == "b" {
+            return true;
+        } else if command == "f" {
+            loop {
+                print_lines(&mut tail);
+                match input.recv_timeout(Duration::from_secs(1)) {
+                    Ok(_) => break,
+                    Err(RecvTimeoutError::Timeout) => continue,
+                    Err(RecvTimeoutError::Disconnected) => return false,
+                }
+            }
+        } else if command.starts_with("/") {
+            match logviewer::read_lines(&attempt.path) {
+                Ok(lines) => {
+                    for (number, line) in logviewer::search(&lines, &command[1..]) {
+                        println!("{:>6}: {}", number, line);
+                    }
+                }
+                Err(e) => println!("{}", e),
+            }
+        } else {
+            println!("{}", ATTEMPT_HELP);
+        }
+    }
+}
+
+fn print_lines(tail: &mut Tail) {
+    match tail.poll() {
+        Ok(lines) => {
+            for line in lines {
+                println!("{}", line);
+            }
+        }
+        Err(e) => println!("{}", e),
+    }
+}
+
+/// The lines typed, read aside so following a log can stop on one.
+fn read_input() -> Receiver<String> {
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        let stdin = io::stdin();
+        for line in stdin.lock().lines() {
+            match line {
+                Ok(line) => {
+                    if tx.send(line.trim().to_owned()).is_err() {
+                        return;
+                    }
+                }
+                Err(_) => return,
+            }
+        }
+    });
+    return rx;
+}
+
+fn prompt(input: &Receiver<String>, help: &str) -> Option<String> {
+    print!("{}\n> ", help);
+    io::stdout().flush().unwrap();
+    return input.recv().ok();
+}
@@ -0,0 +1,127 @@
+use std::path::Path;
+use std::time::Duration;
+use ofborg::tasks;
+use ofborg::config;
+use ofborg::reload;
+use ofborg::checkout;
+use ofborg::cancellation;
+use ofborg::evalcache::EvalCache;
+
+use ofborg::stats;
+use ofborg::worker;
+use amqp::Basic;
+use ofborg::easyamqp;
+use ofborg::easyamqp::TypedWrappers;
+
+pub fn main() {
+    let cfg = config::load_from_args();
+
+    cfg.setup_log("mass-rebuilder");
+    let metrics = cfg.metrics("mass-rebuilder");
+    let tracer = cfg.tracer("mass-rebuilder");
+    cfg.maintain_checkouts(&metrics);
+
+
+
+    let mut session = easyamqp::session_from_config(&cfg.rabbitmq).unwrap();
+    info!("Connected to rabbitmq");
+
+    let mut channel = session.open_channel(1).unwrap();
+
+    let cloner = checkout::cached_cloner(Path::new(&cfg.checkout.root))
+        .with_options(cfg.checkout.clone_options())
+        .with_submodules(cfg.submodule_repos())
+        .with_metrics(&metrics);
+    let nix = cfg.nix("mass-rebuilder").with_memory_limit(cfg.nix.memory_limit_mb);
+
+    let eval_cache = EvalCache::new(&Path::new(&cfg.checkout.root).join("eval-cache"));
+    eval_cache.prune(Duration::from_secs(14 * 24 * 60 * 60));
+
+    let events = metrics.count_events(stats::RabbitMQ::new(
+        &format!("{}-{}", cfg.runner.identity.clone(), cfg.nix.system.clone()),
+        session.open_channel(3).unwrap()
+    ).with_signer(cfg.rabbitmq.signer()));
+
+    let acl = cfg.acl();
+    reload::Reloadable::new().with_acl(&acl).on_sighup(config::Location::from_args());
+
+    let mrw = tasks::massrebuilder::MassRebuildWorker::new(
+        cloner,
+        nix,
+        cfg.github_api(),
+        acl,
+        cfg.runner.identity.clone(),
+        events,
+        cfg.tag_paths.clone().unwrap(),
+        cfg.branch_policies.clone().unwrap_or(vec![]),
+        cfg.maintainer_ping.clone(),
+        eval_cache,
+        cfg.security_feed.clone(),
+        cfg.high_memory_eval_queue(),
+        cfg.format_checks.clone().unwrap_or(vec![]),
+        cfg.outpath_parallelism(),
+        cfg.outpath_systems(),
+        cfg.use_checks_api(),
+        cfg.min_pending_interval(),
+        cfg.draft_mode(),
+        cfg.repositories(),
+        cancellation::listen(
+            &cfg.rabbitmq,
+            format!("{}-mass-rebuild-cancellations", cfg.whoami()),
+            Path::new(&cfg.checkout.root),
+        ),
+    );
+
+    channel
+        .declare_queue(easyamqp::QueueConfig {
+            queue: cfg.eval_queue(),
+            passive: false,
+            durable: true,
+            exclusive: false,
+            auto_delete: false,
+            no_wait: false,
+            arguments: None,
+        })
+        .unwrap();
+
+    if let Some(queue) = cfg.high_memory_eval_queue() {
+        channel
+            .declare_queue(easyamqp::QueueConfig {
+                queue: queue,
+                passive: false,
+                durable: true,
+                exclusive: false,
+                auto_delete: false,
+                no_wait: false,
+                arguments: None,
+            })
+            .unwrap();
+    }
+
+    channel.basic_prefetch(1).unwrap();
+    channel
+        .consume(
+            worker::new(mrw).with_metrics(&metrics)
+                .with_tracer(&tracer)
+                .with_signer(cfg.rabbitmq.signer()),
+            easyamqp::ConsumeConfig {
+                queue: cfg.eval_queue(),
+                consumer_tag: format!("{}-mass-rebuild-checker", cfg.whoami()),
+                no_local: false,
+                no_ack: false,
+                no_wait: false,
+                exclusive: false,
+                arguments: None,
+            },
+        )
+        .unwrap();
+
+    channel.start_consuming();
+
+    info!("Finished consuming");
+
+    channel.close(200, "Bye").unwrap();
+    info!("Closed the channel");
+    session.close(200, "Good Bye");
+    info!("Closed the session... EOF");
+}
@@ -0,0 +1,92 @@
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::process;
+
+use md5;
+
+use ofborg::cli;
+use ofborg::config;
+use ofborg::logging;
+use ofborg::logmigrate::{self, Directory, Journal, Layout, ObjectStore, Store};
+
+const USAGE: &'static str = "Usage: ofborg migrate-logs [--from <dir>] [--from-layout <layout>] \
+     (--to <dir> | --to-url <url> [--token-file <file>]) [--layout <layout>] \
+     [--journal <file>] [<config>]";
+
+fn fail(message: &str) -> ! {
+    eprintln!("{}", message);
+    process::exit(1);
+}
+
+/// Copy the logs under `--from`, the configured `log_storage` by default,
+/// to `--to` or `--to-url` in `--layout`, like `per-system+gzip`. Run it
+/// again after an interruption to pick up where it stopped.
+pub fn main() {
+    let cfg = config::load_optional_from_args();
+    match cfg {
+        Some(ref cfg) => cfg.setup_log("migrate-logs"),
+        None => logging::init("migrate-logs", None),
+    }
+
+    let args = cli::args();
+    let layout = |flag: &str| -> Layout {
+        let spec = cli::value(&args, flag).unwrap_or(String::from("flat"));
+        Layout::parse(&spec).unwrap_or_else(|e| fail(&e))
+    };
+    let from_layout = layout("--from-layout");
+    let to_layout = layout("--layout");
+
+    let from = match cli::value(&args, "--from") {
+        Some(from) => PathBuf::from(from),
+        None => {
+            match cfg.as_ref().and_then(|cfg| cfg.log_storage.as_ref()) {
+                Some(storage) => PathBuf::from(&storage.path),
+                None => fail(USAGE),
+            }
+        }
+    };
+
+    let (store, target): (Box<Store>, String) = if let Some(to) = cli::value(&args, "--to") {
+        (Box::new(Directory::new(PathBuf::from(&to))), to)
+    } else if let Some(url) = cli::value(&args, "--to-url") {
+        let token = cli::value(&args, "--token-file").map(|path| read_token(&path));
+        (Box::new(ObjectStore::new(&url, token)), url)
+    } else {
+        fail(USAGE)
+    };
+
+    // One journal per target and layout, kept with the logs.
+    let journal_path = cli::value(&args, "--journal").map(PathBuf::from).unwrap_or_else(|| {
+        from.join(format!(
+            ".migrated-{:x}",
+            md5::compute(format!("{} {:?}", target, to_layout))
+        ))
+    });
+    let mut journal = Journal::open(&journal_path).unwrap_or_else(|e| fail(&e));
+
+    let logs = logmigrate::scan(&from, &from_layout).unwrap_or_else(|e| fail(&e));
+    info!("Migrating {} logs from {} to {}", logs.len(), from.display(), target);
+    let summary = logmigrate::migrate(Path::new(&from), &logs, &to_layout, &*store, &mut journal);
+
+    println!(
+        "Migrated {}, skipped {} already migrated, {} failed",
+        summary.migrated,
+        summary.skipped,
+        summary.failed.len()
+    );
+    for &(ref path, ref error) in summary.failed.iter() {
+        println!("  {}: {}", path, error);
+    }
+    if summary.failed.len() > 0 {
+        process::exit(1);
+    }
+}
+
+fn read_token(path: &str) -> String {
+    let mut token = String::new();
+    if let Err(e) = File::open(path).and_then(|mut f| f.read_to_string(&mut token)) {
+        fail(&format!("Failed to read {}: {}", path, e));
+    }
+    return token.trim().to_owned();
+}
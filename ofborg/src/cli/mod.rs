@@ -0,0 +1,331 @@
+//! The `ofborg` command, running one of the tasks as a subcommand, like
+//! `ofborg builder config.json`. Each task's binary, like `builder`, is
+//! kept as an alias of its subcommand.
+
+use std::env;
+use std::process;
+
+pub mod api;
+pub mod audit_logger;
+pub mod base_branch_watcher;
+pub mod build_faker;
+pub mod builder;
+pub mod eval;
+pub mod evaluation_filter;
+pub mod failure_reporter;
+pub mod github_comment_filter;
+pub mod github_comment_poster;
+pub mod log_message_collector;
+pub mod log_message_generator;
+pub mod logs;
+pub mod mass_rebuilder;
+pub mod migrate_logs;
+pub mod queue_monitor;
+pub mod queues;
+pub mod replay;
+pub mod simple_build;
+pub mod simulate;
+pub mod stale_status_reaper;
+pub mod stats;
+pub mod webhook_receiver;
+
+/// The flags of any task which take a value, so it isn't mistaken for
+/// the configuration.
+pub const VALUE_FLAGS: &'static [&'static str] = &[
+    "--instance",
+    "--pr",
+    "--attr",
+    "--repo",
+    "--dump",
+    "--match",
+    "--publish",
+    "--comment",
+    "--pull-request",
+    "--sha",
+    "--base",
+    "--author",
+    "--fixture",
+    "--event",
+    "--peek",
+    "--count",
+    "--path",
+    "--head",
+    "--system",
+    "--from",
+    "--from-layout",
+    "--to",
+    "--to-url",
+    "--token-file",
+    "--layout",
+    "--journal",
+    "--root",
+];
+
+pub struct Command {
+    /// The name of the task's binary.
+    pub name: &'static str,
+    pub aliases: &'static [&'static str],
+    pub about: &'static str,
+    pub main: fn(),
+}
+
+pub const COMMANDS: &'static [Command] = &[
+    Command {
+        name: "api",
+        aliases: &[],
+        about: "Serve the JSON API of queues, builders and PRs",
+        main: api::main,
+    },
+    Command {
+        name: "audit-logger",
+        aliases: &[],
+        about: "Record who asked for what in the audit log",
+        main: audit_logger::main,
+    },
+    Command {
+        name: "base-branch-watcher",
+        aliases: &[],
+        about: "Re-evaluate open PRs when their base branch moves",
+        main: base_branch_watcher::main,
+    },
+    Command {
+        name: "build-faker",
+        aliases: &[],
+        about: "Publish a fake build job, for testing",
+        main: build_faker::main,
+    },
+    Command {
+        name: "builder",
+        aliases: &[],
+        about: "Build the attrs of build jobs",
+        main: builder::main,
+    },
+    Command {
+        name: "eval",
+        aliases: &[],
+        about: "Evaluate a branch of a local nixpkgs like a PR",
+        main: eval::main,
+    },
+    Command {
+        name: "evaluation-filter",
+        aliases: &[],
+        about: "Pick the PR events which need an evaluation",
+        main: evaluation_filter::main,
+    },
+    Command {
+        name: "failure-reporter",
+        aliases: &[],
+        about: "Report the most frequently failing attrs",
+        main: failure_reporter::main,
+    },
+    Command {
+        name: "github-comment-filter",
+        aliases: &["comment-filter"],
+        about: "Turn @grahamcofborg comments into jobs",
+        main: github_comment_filter::main,
+    },
+    Command {
+        name: "github-comment-poster",
+        aliases: &["comment-poster"],
+        about: "Post build results to GitHub",
+        main: github_comment_poster::main,
+    },
+    Command {
+        name: "log-message-collector",
+        aliases: &["log-collector"],
+        about: "Write build logs to disk",
+        main: log_message_collector::main,
+    },
+    Command {
+        name: "log-message-generator",
+        aliases: &["log-generator"],
+        about: "Publish fake build logs, for testing",
+        main: log_message_generator::main,
+    },
+    Command {
+        name: "logs",
+        aliases: &[],
+        about: "Browse and follow build logs from a terminal",
+        main: logs::main,
+    },
+    Command {
+        name: "mass-rebuilder",
+        aliases: &["evaluator"],
+        about: "Evaluate PRs",
+        main: mass_rebuilder::main,
+    },
+    Command {
+        name: "migrate-logs",
+        aliases: &[],
+        about: "Copy build logs to another layout or store",
+        main: migrate_logs::main,
+    },
+    Command {
+        name: "queue-monitor",
+        aliases: &[],
+        about: "Export the depth of the queues as metrics",
+        main: queue_monitor::main,
+    },
+    Command {
+        name: "queues",
+        aliases: &[],
+        about: "List the queues, or peek at the messages of one",
+        main: queues::main,
+    },
+    Command {
+        name: "replay",
+        aliases: &[],
+        about: "Dump dead letters, and publish them again",
+        main: replay::main,
+    },
+    Command {
+        name: "simple-build",
+        aliases: &[],
+        about: "Build hello in the current directory, for testing",
+        main: simple_build::main,
+    },
+    Command {
+        name: "simulate",
+        aliases: &[],
+        about: "Publish a made up webhook, for testing",
+        main: simulate::main,
+    },
+    Command {
+        name: "stale-status-reaper",
+        aliases: &[],
+        about: "Fail the statuses of jobs which were lost",
+        main: stale_status_reaper::main,
+    },
+    Command {
+        name: "stats",
+        aliases: &[],
+        about: "Collect and export the events of every task",
+        main: stats::main,
+    },
+    Command {
+        name: "webhook-receiver",
+        aliases: &[],
+        about: "Receive GitHub's webhooks",
+        main: webhook_receiver::main,
+    },
+];
+
+pub fn find(name: &str) -> Option<&'static Command> {
+    COMMANDS.iter().find(|command| {
+        command.name == name || command.aliases.iter().any(|alias| *alias == name)
+    })
+}
+
+/// The arguments of a task, after the program and, when it's run by
+/// `ofborg`, the subcommand.
+pub fn args() -> Vec<String> {
+    let mut args: Vec<String> = env::args().skip(1).collect();
+    if args.first().and_then(|arg| find(arg)).is_some() {
+        args.remove(0);
+    }
+    return args;
+}
+
+pub fn flag(args: &[String], name: &str) -> bool {
+    args.iter().any(|arg| arg == name)
+}
+
+/// The values given to a flag, like `--attr` in `--attr a --attr b`.
+pub fn values(args: &[String], name: &str) -> Vec<String> {
+    args.iter()
+        .zip(args.iter().skip(1))
+        .filter(|&(flag, _)| flag == name)
+        .map(|(_, value)| value.clone())
+        .collect()
+}
+
+pub fn value(args: &[String], name: &str) -> Option<String> {
+    values(args, name).into_iter().last()
+}
+
+pub fn usage() -> String {
+    let mut lines: Vec<String> = vec![
+        String::from(
+            "Usage: ofborg <command> [--check-config [--check-github]] [--instance <name>] <config>",
+        ),
+        String::new(),
+        String::from("Commands:"),
+    ];
+
+    for command in COMMANDS.iter() {
+        let mut name = String::from(command.name);
+        if command.aliases.len() > 0 {
+            name = format!("{} ({})", name, command.aliases.join(", "));
+        }
+        lines.push(format!("    {:<45} {}", name, command.about));
+    }
+
+    return lines.join("\n");
+}
+
+/// Run the subcommand named on the command line.
+pub fn main() {
+    let name = env::args().nth(1).unwrap_or(String::new());
+
+    if name == "help" || name == "--help" || name == "-h" {
+        println!("{}", usage());
+        return;
+    }
+
+    match find(&name) {
+        Some(command) => (command.main)(),
+        None => {
+            if name != "" {
+                eprintln!("Unknown command {:?}", name);
+            }
+            eprintln!("{}", usage());
+            process::exit(1);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_find() {
+        assert_eq!(find("builder").map(|c| c.name), Some("builder"));
+        assert_eq!(find("evaluator").map(|c| c.name), Some("mass-rebuilder"));
+        assert_eq!(
+            find("log-collector").map(|c| c.name),
+            Some("log-message-collector")
+        );
+        assert!(find("config.json").is_none());
+    }
+
+    #[test]
+    fn test_values() {
+        let args: Vec<String> = vec!["--once", "--attr", "hello", "--attr", "git", "--pr", "1"]
+            .into_iter()
+            .map(String::from)
+            .collect();
+
+        assert!(flag(&args, "--once"));
+        assert!(!flag(&args, "--check-config"));
+        assert_eq!(values(&args, "--attr"), vec!["hello", "git"]);
+        assert_eq!(value(&args, "--pr"), Some(String::from("1")));
+        assert_eq!(value(&args, "--repo"), None);
+    }
+
+    #[test]
+    fn test_names_are_unique() {
+        let mut names: Vec<&str> = COMMANDS
+            .iter()
+            .flat_map(|c| {
+                let mut names = vec![c.name];
+                names.extend(c.aliases.iter());
+                names
+            })
+            .collect();
+        let count = names.len();
+        names.sort();
+        names.dedup();
+        assert_eq!(names.len(), count);
+    }
+}
@@ -0,0 +1,80 @@
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use ofborg::config;
+use ofborg::tasks;
+use ofborg::easyamqp;
+use ofborg::easyamqp::TypedWrappers;
+use ofborg::message::envelope;
+use ofborg::message::queuealert;
+use ofborg::worker::QueueMsg;
+
+pub fn main() {
+    let cfg = config::load_from_args();
+    cfg.setup_log("queue-monitor");
+    let metrics = cfg.metrics("queue-monitor");
+
+    let monitor_cfg = match cfg.queue_monitor.clone() {
+        Some(monitor_cfg) => monitor_cfg,
+        None => {
+            info!("No queue_monitor configured, nothing to monitor");
+            return;
+        }
+    };
+    let interval = Duration::from_secs(monitor_cfg.interval_seconds.unwrap_or(60));
+
+    let mut session = easyamqp::session_from_config(&cfg.rabbitmq).unwrap();
+    info!("Connected to rabbitmq");
+
+    let mut channel = session.open_channel(1).unwrap();
+
+    channel
+        .declare_exchange(easyamqp::ExchangeConfig {
+            exchange: queuealert::EXCHANGE.to_owned(),
+            exchange_type: easyamqp::ExchangeType::Fanout,
+            passive: false,
+            durable: true,
+            auto_delete: false,
+            no_wait: false,
+            internal: false,
+            arguments: None,
+        })
+        .unwrap();
+
+    let mut monitor = tasks::queuemonitor::QueueMonitor::new(&cfg.rabbitmq, monitor_cfg);
+    monitor.register_metrics(&metrics);
+    let signer = cfg.rabbitmq.signer();
+
+    loop {
+        match monitor.poll() {
+            Ok(depths) => {
+                monitor.record(&depths);
+
+                let now = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap()
+                    .as_secs();
+                for alert in monitor.alerts(&depths, now) {
+                    warn!(
+                        "{} is backed up: {} messages wait for {} consumers",
+                        alert.queue,
+                        alert.messages,
+                        alert.consumers
+                    );
+                    let msg = QueueMsg {
+                        exchange: Some(queuealert::EXCHANGE.to_owned()),
+                        routing_key: None,
+                        mandatory: false,
+                        immediate: false,
+                        properties: Some(easyamqp::persistent_message()),
+                        content: envelope::to_vec(&alert),
+                    };
+                    easyamqp::publish(&mut channel, &signer, msg).unwrap();
+                }
+            }
+            Err(e) => warn!("Failed to poll the queue depths: {}", e),
+        }
+
+        thread::sleep(interval);
+    }
+}
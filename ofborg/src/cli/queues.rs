@@ -0,0 +1,62 @@
+use std::process;
+
+use amqp::Basic;
+use serde_json;
+
+use ofborg::cli;
+use ofborg::config;
+use ofborg::easyamqp;
+use ofborg::replay;
+use ofborg::tasks::queuemonitor::{self, QueueMonitor};
+
+/// List the queues with how deep they are and who consumes them, or
+/// with `--peek <queue>`, print the next `--count` messages of one,
+/// decoded, leaving them in the queue.
+pub fn main() {
+    let cfg = config::load_from_args();
+    cfg.setup_log("queues");
+
+    let args = cli::args();
+    if let Some(queue) = cli::value(&args, "--peek") {
+        let count: usize = match cli::value(&args, "--count").map(|n| n.parse()) {
+            Some(Ok(count)) => count,
+            Some(Err(_)) => {
+                eprintln!("--count needs a number of messages");
+                process::exit(1);
+            }
+            None => 10,
+        };
+        peek(&cfg, &queue, count);
+        return;
+    }
+
+    let monitor = QueueMonitor::new(&cfg.rabbitmq, cfg.queue_monitor.clone().unwrap_or_default());
+    match monitor.poll() {
+        Ok(depths) => println!("{}", queuemonitor::table(&depths)),
+        Err(e) => {
+            eprintln!("Failed to list the queues: {}", e);
+            process::exit(1);
+        }
+    }
+}
+
+fn peek(cfg: &config::Config, queue: &str, count: usize) {
+    let mut session = easyamqp::session_from_config(&cfg.rabbitmq).unwrap();
+    let mut channel = session.open_channel(1).unwrap();
+
+    // Nothing is acknowledged, closing the channel puts them back.
+    let messages: Vec<_> = channel.basic_get(queue, false).take(count).collect();
+    for message in messages.iter() {
+        let message_dump = replay::dump_got(
+            &message.reply.exchange,
+            &message.reply.routing_key,
+            &message.headers,
+            &message.body,
+        );
+        println!("{}", serde_json::to_string_pretty(&message_dump).unwrap());
+    }
+
+    info!("Peeked at {} messages of {}", messages.len(), queue);
+    channel.close(200, "Bye").unwrap();
+    session.close(200, "Good Bye");
+}
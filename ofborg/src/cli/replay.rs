@@ -0,0 +1,133 @@
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::process;
+
+use amqp::Basic;
+use serde_json;
+
+use ofborg::cli;
+use ofborg::config;
+use ofborg::easyamqp;
+use ofborg::replay::{self, Dumped};
+use ofborg::worker::QueueMsg;
+
+/// Dump a queue of dead letters with `--dump <queue>`, or publish a
+/// dump again with `--publish <file>`.
+pub fn main() {
+    let cfg = config::load_from_args();
+    cfg.setup_log("replay");
+
+    let args = cli::args();
+    if let Some(queue) = cli::value(&args, "--dump") {
+        dump(
+            &cfg,
+            &queue,
+            &cli::value(&args, "--match"),
+            cli::flag(&args, "--take"),
+        );
+    } else if let Some(path) = cli::value(&args, "--publish") {
+        publish(&cfg, &path, &cli::value(&args, "--match"));
+    } else {
+        eprintln!(
+            "Usage: ofborg replay (--dump <queue> [--take] | --publish <file>) \
+             [--match <text>] <config>"
+        );
+        process::exit(1);
+    }
+}
+
+/// Print the messages of `queue` matching `filter`, taking them out of
+/// it with `take`. Messages which aren't taken are left as they were.
+fn dump(cfg: &config::Config, queue: &str, filter: &Option<String>, take: bool) {
+    let mut session = easyamqp::session_from_config(&cfg.rabbitmq).unwrap();
+    let mut channel = session.open_channel(1).unwrap();
+
+    // Nothing is acknowledged until every message was seen, so none of
+    // them is fetched twice; closing the channel returns the rest.
+    let messages: Vec<_> = channel.basic_get(queue, false).collect();
+    let mut dumped = 0;
+    for message in messages.iter() {
+        let message_dump = replay::dump_got(
+            &message.reply.exchange,
+            &message.reply.routing_key,
+            &message.headers,
+            &message.body,
+        );
+        if !message_dump.matches(filter) {
+            continue;
+        }
+        if let Some(ref error) = message_dump.error {
+            warn!("Message {} {}", message.reply.delivery_tag, error);
+        }
+
+        println!("{}", serde_json::to_string(&message_dump).unwrap());
+        dumped += 1;
+        if take {
+            channel.basic_ack(message.reply.delivery_tag, false).unwrap();
+        }
+    }
+
+    info!("Dumped {} of {} messages in {}", dumped, messages.len(), queue);
+    channel.close(200, "Bye").unwrap();
+    session.close(200, "Good Bye");
+}
+
+/// Publish the messages dumped to `path`, matching `filter`, to where
+/// they were first published. Messages which don't decode as what's
+/// published there are skipped.
+fn publish(cfg: &config::Config, path: &str, filter: &Option<String>) {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(e) => {
+            eprintln!("Failed to open {}: {}", path, e);
+            process::exit(1);
+        }
+    };
+
+    let mut session = easyamqp::session_from_config(&cfg.rabbitmq).unwrap();
+    let mut channel = session.open_channel(1).unwrap();
+    let signer = cfg.rabbitmq.signer();
+
+    let mut published = 0;
+    for (i, line) in BufReader::new(file).lines().enumerate() {
+        let line = line.unwrap();
+        if line.trim() == "" {
+            continue;
+        }
+
+        let message: Dumped = match serde_json::from_str(&line) {
+            Ok(message) => message,
+            Err(e) => {
+                warn!("Skipping line {}, it isn't a dumped message: {}", i + 1, e);
+                continue;
+            }
+        };
+        if !message.matches(filter) {
+            continue;
+        }
+        let body = match message.republishable() {
+            Ok(body) => body,
+            Err(e) => {
+                warn!("Skipping line {}: {}", i + 1, e);
+                continue;
+            }
+        };
+
+        let mut props = easyamqp::persistent_json();
+        props.content_type = Some(message.content_type().to_owned());
+        let msg = QueueMsg {
+            exchange: Some(message.exchange.clone()),
+            routing_key: Some(message.routing_key.clone()),
+            mandatory: false,
+            immediate: false,
+            properties: Some(props),
+            content: body,
+        };
+        easyamqp::publish(&mut channel, &signer, msg).unwrap();
+        published += 1;
+    }
+
+    info!("Published {} messages from {}", published, path);
+    channel.close(200, "Bye").unwrap();
+    session.close(200, "Good Bye");
+}
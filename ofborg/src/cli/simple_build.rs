@@ -0,0 +1,30 @@
+use std::path::Path;
+use std::fs::File;
+use std::io::Read;
+use ofborg::config;
+
+
+pub fn main() {
+    let cfg = config::load_from_args();
+
+    cfg.setup_log("simple-build");
+
+    let nix = cfg.nix("simple-build");
+
+    match nix.safely_build_attrs(
+        &Path::new("./"),
+        "./default.nix",
+        vec![String::from("hello")],
+    ) {
+        Ok(mut out) => {
+            print!("{}", file_to_str(&mut out));
+        }
+        Err(mut out) => print!("{}", file_to_str(&mut out)),
+    }
+}
+
+fn file_to_str(f: &mut File) -> String {
+    let mut buffer = Vec::new();
+    f.read_to_end(&mut buffer).expect("Reading eval output");
+    return String::from(String::from_utf8_lossy(&buffer));
+}
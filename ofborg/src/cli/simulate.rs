@@ -0,0 +1,104 @@
+use std::fs::File;
+use std::io::Read;
+use std::process;
+
+use serde_json::{self, Value};
+
+use ofborg::cli;
+use ofborg::config;
+use ofborg::easyamqp;
+use ofborg::simulate;
+use ofborg::worker::QueueMsg;
+
+const USAGE: &'static str = "Usage: ofborg simulate [--repo <owner/name>] [--author <login>] [--print] \
+     (--comment <body> --pr <number> | \
+     --pull-request <action> --pr <number> --sha <head> [--base <branch>] | \
+     --fixture <file> --event <type>) <config>";
+
+fn usage() -> ! {
+    eprintln!("{}", USAGE);
+    process::exit(1);
+}
+
+/// Publish a made up webhook to the `github-events` exchange, or print
+/// it with `--print`.
+pub fn main() {
+    let cfg = config::load_from_args();
+    cfg.setup_log("simulate");
+
+    let args = cli::args();
+    let full_name = cli::value(&args, "--repo").unwrap_or(String::from("NixOS/nixpkgs"));
+    let author = cli::value(&args, "--author").unwrap_or(String::from("ofborg-simulator"));
+    let number = || -> u64 {
+        match cli::value(&args, "--pr").and_then(|n| n.parse().ok()) {
+            Some(number) => number,
+            None => usage(),
+        }
+    };
+
+    let (event_type, payload) = if let Some(body) = cli::value(&args, "--comment") {
+        (
+            String::from("issue_comment"),
+            simulate::issue_comment(&full_name, number(), &author, &body),
+        )
+    } else if let Some(action) = cli::value(&args, "--pull-request") {
+        let head_sha = cli::value(&args, "--sha").unwrap_or_else(|| usage());
+        let base_ref = cli::value(&args, "--base").unwrap_or(String::from("master"));
+        (
+            String::from("pull_request"),
+            simulate::pull_request(&full_name, number(), &action, &author, &head_sha, &base_ref),
+        )
+    } else if let Some(path) = cli::value(&args, "--fixture") {
+        let event_type = cli::value(&args, "--event").unwrap_or_else(|| usage());
+        (event_type, read_fixture(&path))
+    } else {
+        usage()
+    };
+
+    let delivery = match simulate::delivery(&event_type, &payload) {
+        Ok(delivery) => delivery,
+        Err(e) => {
+            eprintln!("GitHub's webhook would be rejected: {}", e);
+            process::exit(1);
+        }
+    };
+
+    if cli::flag(&args, "--print") {
+        println!("{}", delivery.routing_key);
+        println!("{}", String::from_utf8_lossy(&delivery.body));
+        return;
+    }
+
+    let mut session = easyamqp::session_from_config(&cfg.rabbitmq).unwrap();
+    let mut channel = session.open_channel(1).unwrap();
+    let msg = QueueMsg {
+        exchange: Some(String::from("github-events")),
+        routing_key: Some(delivery.routing_key.clone()),
+        mandatory: false,
+        immediate: false,
+        properties: Some(easyamqp::persistent_json()),
+        content: delivery.body,
+    };
+    easyamqp::publish(&mut channel, &cfg.rabbitmq.signer(), msg).unwrap();
+    info!("Published a {} event", delivery.routing_key);
+
+    channel.close(200, "Bye").unwrap();
+    session.close(200, "Good Bye");
+}
+
+/// A captured webhook's payload.
+fn read_fixture(path: &str) -> Value {
+    let mut contents = String::new();
+    if let Err(e) = File::open(path).and_then(|mut file| file.read_to_string(&mut contents)) {
+        eprintln!("Failed to read {}: {}", path, e);
+        process::exit(1);
+    }
+
+    match serde_json::from_str(&contents) {
+        Ok(payload) => payload,
+        Err(e) => {
+            eprintln!("Failed to parse {}: {}", path, e);
+            process::exit(1);
+        }
+    }
+}
@@ -0,0 +1,79 @@
+use std::path::PathBuf;
+use std::thread;
+use std::time::Duration;
+
+use ofborg::config;
+use ofborg::tasks;
+use ofborg::easyamqp;
+use ofborg::easyamqp::TypedWrappers;
+use ofborg::message::envelope;
+use ofborg::worker::QueueMsg;
+
+
+pub fn main() {
+    let cfg = config::load_from_args();
+    cfg.setup_log("stale-status-reaper");
+    let metrics = cfg.metrics("stale-status-reaper");
+
+    let reaper_cfg = match cfg.status_reaper.clone() {
+        Some(reaper_cfg) => reaper_cfg,
+        None => {
+            info!("No status_reaper configured, nothing to reap");
+            return;
+        }
+    };
+    let interval = Duration::from_secs(reaper_cfg.interval_seconds.unwrap_or(60 * 60));
+    let repos = cfg.repo_names();
+
+    let mut session = easyamqp::session_from_config(&cfg.rabbitmq).unwrap();
+    info!("Connected to rabbitmq");
+
+    let mut channel = session.open_channel(1).unwrap();
+
+    channel
+        .declare_queue(easyamqp::QueueConfig {
+            queue: "mass-rebuild-check-jobs".to_owned(),
+            passive: false,
+            durable: true,
+            exclusive: false,
+            auto_delete: false,
+            no_wait: false,
+            arguments: None,
+        })
+        .unwrap();
+
+    let mut reaper = tasks::statusreaper::StatusReaper::new(
+        cfg.github_api(),
+        reaper_cfg,
+        cfg.use_checks_api(),
+    );
+    if let Some(ref storage) = cfg.log_storage {
+        reaper = reaper.with_log_root(PathBuf::from(&storage.path));
+    }
+
+    let requeued = metrics.counter(
+        "ofborg_stale_statuses_requeued_total",
+        "Evaluations re-queued for statuses which went stale",
+    );
+    let signer = cfg.rabbitmq.signer();
+
+    loop {
+        for repo in repos.iter() {
+            for job in reaper.reap(repo) {
+                info!("Re-queueing {}#{}", job.repo.full_name, job.pr.number);
+                requeued.inc(&[("repo", &job.repo.full_name)]);
+                let msg = QueueMsg {
+                    exchange: None,
+                    routing_key: Some(String::from("mass-rebuild-check-jobs")),
+                    mandatory: false,
+                    immediate: false,
+                    properties: Some(easyamqp::persistent_message()),
+                    content: envelope::to_vec(&job),
+                };
+                easyamqp::publish(&mut channel, &signer, msg).unwrap();
+            }
+        }
+
+        thread::sleep(interval);
+    }
+}
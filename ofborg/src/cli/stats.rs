@@ -0,0 +1,157 @@
+use ofborg::{easyamqp, tasks, worker, config, stats};
+use ofborg::message::usagesummary;
+
+use amqp::Basic;
+use ofborg::easyamqp::TypedWrappers;
+use hyper::server::{Request, Response, Server};
+
+use std::path::PathBuf;
+use std::thread;
+use std::time::Duration;
+
+pub fn main() {
+    let cfg = config::load_from_args();
+    cfg.setup_log("stats");
+    let exported = cfg.metrics("stats");
+    let tracer = cfg.tracer("stats");
+
+    let mut session = easyamqp::session_from_config(&cfg.rabbitmq).unwrap();
+    info!("Connected to rabbitmq");
+
+    let events = stats::RabbitMQ::new(
+        &format!("{}-{}", cfg.runner.identity.clone(), cfg.nix.system.clone()),
+        session.open_channel(3).unwrap()
+    ).with_signer(cfg.rabbitmq.signer());
+
+    let metrics = stats::MetricCollector::new();
+
+    let stats_cfg = cfg.stats.clone();
+    let snapshot_file = stats_cfg.as_ref().and_then(|stats_cfg| {
+        stats_cfg.snapshot_file.as_ref().map(|snapshot_file| {
+            let path = PathBuf::from(snapshot_file);
+            if let Err(e) = stats::restore_snapshot(&metrics, &path) {
+                warn!("Starting the stats over: {}", e);
+            }
+            stats::save_snapshots(
+                metrics.clone(),
+                path.clone(),
+                Duration::from_secs(stats_cfg.snapshot_interval_seconds.unwrap_or(60)),
+            );
+            path
+        })
+    });
+
+    let usage = stats::UsageLedger::new();
+    let collector = tasks::statscollector::StatCollectorWorker::new(
+        events,
+        metrics.clone(),
+    ).with_usage(usage.clone());
+
+    if let Some(interval) = stats_cfg.as_ref().and_then(
+        |stats_cfg| stats_cfg.usage_summary_interval_seconds,
+    )
+    {
+        let mut summaries = session.open_channel(2).unwrap();
+        summaries
+            .declare_exchange(easyamqp::ExchangeConfig {
+                exchange: usagesummary::EXCHANGE.to_owned(),
+                exchange_type: easyamqp::ExchangeType::Fanout,
+                passive: false,
+                durable: true,
+                auto_delete: false,
+                no_wait: false,
+                internal: false,
+                arguments: None,
+            })
+            .unwrap();
+        stats::publish_usage_summaries(
+            usage,
+            summaries,
+            Duration::from_secs(interval),
+            cfg.rabbitmq.signer(),
+        );
+    }
+
+    let mut channel = session.open_channel(1).unwrap();
+    channel
+        .declare_exchange(easyamqp::ExchangeConfig {
+            exchange: "stats".to_owned(),
+            exchange_type: easyamqp::ExchangeType::Fanout,
+            passive: false,
+            durable: true,
+            auto_delete: false,
+            no_wait: false,
+            internal: false,
+            arguments: None,
+        })
+        .unwrap();
+
+    channel
+        .declare_queue(easyamqp::QueueConfig {
+            queue: "stats-events".to_owned(),
+            passive: false,
+            durable: true,
+            exclusive: false,
+            auto_delete: false,
+            no_wait: false,
+            arguments: None,
+        })
+        .unwrap();
+
+    channel
+        .bind_queue(easyamqp::BindQueueConfig {
+            queue: "stats-events".to_owned(),
+            exchange: "stats".to_owned(),
+            routing_key: None,
+            no_wait: false,
+            arguments: None,
+        })
+        .unwrap();
+
+    channel.basic_prefetch(1).unwrap();
+    channel
+        .consume(
+            worker::new(collector).with_metrics(&exported)
+                .with_tracer(&tracer)
+                .with_signer(cfg.rabbitmq.signer()),
+            easyamqp::ConsumeConfig {
+                queue: "stats-events".to_owned(),
+                consumer_tag: format!("{}-prometheus-stats-collector", cfg.whoami()),
+                no_local: false,
+                no_ack: false,
+                no_wait: false,
+                exclusive: false,
+                arguments: None,
+            },
+        )
+        .unwrap();
+
+
+    let served = metrics.clone();
+    thread::spawn(||{
+        let addr = "0.0.0.0:9898";
+        info!("listening addr {:?}", addr);
+        Server::http(addr)
+            .unwrap()
+            .handle(move |_: Request, res: Response| {
+                res.send(served.prometheus_output().as_bytes()).unwrap();
+            })
+            .unwrap();
+    });
+
+
+    channel.start_consuming();
+
+    info!("Finished consuming");
+
+    if let Some(path) = snapshot_file {
+        if let Err(e) = stats::save_snapshot(&metrics, &path) {
+            warn!("Failed to save the stats: {}", e);
+        }
+    }
+
+    channel.close(200, "Bye").unwrap();
+    info!("Closed the channel");
+    session.close(200, "Good Bye");
+    info!("Closed the session... EOF");
+}
@@ -0,0 +1,181 @@
+use std::fs::File;
+use std::io::Read;
+use std::sync::Mutex;
+
+use hyper::server::{Request, Response, Server};
+use hyper::status::StatusCode;
+
+use ofborg::config;
+use ofborg::correlation::Correlation;
+use ofborg::easyamqp;
+use ofborg::easyamqp::TypedWrappers;
+use ofborg::tracing;
+use ofborg::webhook;
+use ofborg::worker::QueueMsg;
+
+/// GitHub caps the payloads it sends at 25MB.
+const MAX_PAYLOAD_BYTES: u64 = 25 * 1024 * 1024;
+
+fn header(req: &Request, name: &str) -> Option<String> {
+    req.headers
+        .get_raw(name)
+        .and_then(|values| values.first())
+        .and_then(|value| String::from_utf8(value.clone()).ok())
+}
+
+pub fn main() {
+    let cfg = config::load_from_args();
+    cfg.setup_log("webhook-receiver");
+    let metrics = cfg.metrics("webhook-receiver");
+    let tracer = cfg.tracer("webhook-receiver");
+
+    let webhook_cfg = cfg.webhook.clone().expect("fetching config's webhook");
+    let mut secret = String::new();
+    File::open(&webhook_cfg.secret_file)
+        .and_then(|mut f| f.read_to_string(&mut secret))
+        .expect("reading the webhook secret");
+    let secret = secret.trim().to_owned();
+    let audience = cfg.webhook_audience();
+
+    let mut session = easyamqp::session_from_config(&cfg.rabbitmq).unwrap();
+    info!("Connected to rabbitmq");
+
+    let mut channel = session.open_channel(1).unwrap();
+
+    channel
+        .declare_exchange(easyamqp::ExchangeConfig {
+            exchange: "github-events".to_owned(),
+            exchange_type: easyamqp::ExchangeType::Topic,
+            passive: false,
+            durable: true,
+            auto_delete: false,
+            no_wait: false,
+            internal: false,
+            arguments: None,
+        })
+        .unwrap();
+
+    channel
+        .declare_queue(easyamqp::QueueConfig {
+            queue: "github-events-unknown".to_owned(),
+            passive: false,
+            durable: true,
+            exclusive: false,
+            auto_delete: false,
+            no_wait: false,
+            arguments: None,
+        })
+        .unwrap();
+
+    channel
+        .bind_queue(easyamqp::BindQueueConfig {
+            queue: "github-events-unknown".to_owned(),
+            exchange: "github-events".to_owned(),
+            routing_key: Some("unknown.*".to_owned()),
+            no_wait: false,
+            arguments: None,
+        })
+        .unwrap();
+
+    let channel = Mutex::new(channel);
+    let signer = cfg.rabbitmq.signer();
+    let webhooks = metrics.counter("ofborg_webhooks_total", "Webhooks received, by their event");
+
+    info!("listening addr {:?}", webhook_cfg.listen);
+    Server::http(webhook_cfg.listen.as_str())
+        .unwrap()
+        .handle(move |mut req: Request, mut res: Response| {
+            let mut span = tracer.start(tracing::Kind::Server, None);
+            let correlation = Correlation::new(header(&req, "X-GitHub-Delivery"));
+            if let Some(ref id) = correlation.caused_by {
+                span.attribute("github.delivery", id);
+            }
+            if let Some(ref id) = correlation.id {
+                span.attribute("ofborg.correlation_id", id);
+            }
+
+            let mut payload = vec![];
+            let signature = header(&req, "X-Hub-Signature");
+            let event_type = header(&req, "X-GitHub-Event");
+            let enterprise_host = header(&req, "X-GitHub-Enterprise-Host");
+
+            let delivery = req.by_ref()
+                .take(MAX_PAYLOAD_BYTES + 1)
+                .read_to_end(&mut payload)
+                .map_err(|e| format!("Failed to read the payload: {:?}", e))
+                .and_then(|_| {
+                    if payload.len() as u64 > MAX_PAYLOAD_BYTES {
+                        return Err(format!("Payload over {} bytes", MAX_PAYLOAD_BYTES));
+                    }
+
+                    let signature = signature.ok_or(String::from("X-Hub-Signature missing"))?;
+                    if !webhook::valid_signature(secret.as_bytes(), &payload, &signature) {
+                        return Err(String::from("Failed to validate signature"));
+                    }
+
+                    if !webhook::valid_audience(
+                        audience.as_ref().map(|a| a.as_str()),
+                        enterprise_host.as_ref().map(|h| h.as_str()),
+                    )
+                    {
+                        return Err(format!("Unexpected webhook host {:?}", enterprise_host));
+                    }
+
+                    let event_type = event_type.ok_or(String::from("X-GitHub-Event missing"))?;
+                    webhook::normalize(event_type.trim(), &payload)
+                });
+
+            match delivery {
+                Ok(delivery) => {
+                    webhooks.inc(&[
+                        ("event", delivery.routing_key.split('.').next().unwrap_or("")),
+                    ]);
+
+                    span.attribute("messaging.rabbitmq.routing_key", &delivery.routing_key);
+
+                    let mut props = easyamqp::persistent_json();
+                    tracing::inject(&mut props, &span.context);
+                    correlation.inject(&mut props);
+                    let msg = QueueMsg {
+                        exchange: Some(String::from("github-events")),
+                        routing_key: Some(delivery.routing_key),
+                        mandatory: false,
+                        immediate: false,
+                        properties: Some(props),
+                        content: delivery.body,
+                    };
+
+                    // Failing to publish one webhook mustn't fail every
+                    // later one, so neither panic nor mind a poisoned lock.
+                    let published = easyamqp::publish(
+                        &mut channel.lock().unwrap_or_else(|poisoned| poisoned.into_inner()),
+                        &signer,
+                        msg,
+                    );
+
+                    match published {
+                        Ok(_) => {
+                            let _ = res.send(b"ok");
+                        }
+                        Err(e) => {
+                            warn!("Failed to publish webhook {:?}: {:?}", correlation.caused_by, e);
+                            span.fail();
+                            *res.status_mut() = StatusCode::ServiceUnavailable;
+                            let _ = res.send(b"failed to queue the event");
+                        }
+                    }
+                }
+                Err(e) => {
+                    webhooks.inc(&[("event", "rejected")]);
+                    warn!("Rejecting webhook {:?}: {}", correlation.caused_by, e);
+                    span.attribute("rejected", &e);
+                    span.fail();
+                    *res.status_mut() = StatusCode::BadRequest;
+                    let _ = res.send(e.as_bytes());
+                }
+            }
+
+            tracer.finish(span);
+        })
+        .unwrap();
+}
@@ -1,9 +1,14 @@
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::cell::Cell;
+use std::rc::Rc;
 use fs2::FileExt;
+use git2::{self, FetchOptions, ObjectType, RemoteCallbacks, Repository, ResetType};
+use git2::build::{CheckoutBuilder, RepoBuilder};
 use std::fs;
 use std::io::{Error, ErrorKind};
 use std::process::Command;
 use std::ffi::OsStr;
+use ofborg::stats;
 
 pub struct Lock {
     lock: Option<fs::File>,
@@ -15,39 +20,200 @@ impl Lock {
     }
 }
 
+/// Wait for an exclusive lock on `path`, creating it, which marks it
+/// modified.
+pub fn lock_file(path: &Path) -> Result<Lock, Error> {
+    info!("Locking {:?}", path);
+
+    match fs::File::create(path) {
+        Err(e) => {
+            warn!("Failed to create lock file {:?}: {}", path, e);
+            return Err(e);
+        }
+        Ok(lock) => {
+            match lock.lock_exclusive() {
+                Err(e) => {
+                    warn!("Failed to get exclusive lock on file {:?}: {}", path, e);
+                    return Err(e);
+                }
+                Ok(_) => {
+                    debug!("Got lock on {:?}", path);
+                    return Ok(Lock { lock: Some(lock) });
+                }
+            }
+        }
+
+    }
+}
+
+/// Lock `path` if nobody else holds it.
+pub fn try_lock_file(path: &Path) -> Result<Option<Lock>, Error> {
+    let lock = fs::File::create(path)?;
+    match lock.try_lock_exclusive() {
+        Ok(_) => return Ok(Some(Lock { lock: Some(lock) })),
+        Err(_) => return Ok(None),
+    }
+}
+
+/// How much of a repository is cloned. By default, all of it.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CloneOptions {
+    /// Clone only this many commits of every branch, deepening it when
+    /// a merge base isn't reachable.
+    pub depth: Option<u32>,
+    /// Like `blob:none`, for a partial clone which fetches what's left
+    /// out once it's needed.
+    pub filter: Option<String>,
+}
+
+impl CloneOptions {
+    pub fn args(&self) -> Vec<String> {
+        let mut args: Vec<String> = vec![];
+
+        if let Some(depth) = self.depth {
+            args.push(format!("--depth={}", depth));
+            // --depth implies --single-branch, but every branch is
+            // checked out.
+            args.push(String::from("--no-single-branch"));
+        }
+
+        if let Some(ref filter) = self.filter {
+            args.push(format!("--filter={}", filter));
+        }
+
+        return args;
+    }
+
+    /// libgit2 can only clone and fetch all of the history.
+    fn needs_git(&self) -> bool {
+        self.depth.is_some() || self.filter.is_some()
+    }
+}
+
+/// A libgit2 error, kept as the inner error for its class and code.
+pub fn git_error(e: git2::Error) -> Error {
+    Error::new(ErrorKind::Other, e)
+}
+
+pub fn open(path: &Path) -> Result<Repository, Error> {
+    Repository::open(path).map_err(git_error)
+}
+
+/// Run the git binary, for what libgit2 can't do.
+pub fn git(dir: &Path, args: &[&OsStr]) -> Result<(), Error> {
+    let result = Command::new("git").args(args).current_dir(dir).status()?;
+
+    if result.success() {
+        return Ok(());
+    } else {
+        return Err(Error::new(
+            ErrorKind::Other,
+            format!("git {:?} failed", args),
+        ));
+    }
+}
+
+/// Counts what's received by clones and fetches.
+#[derive(Clone)]
+pub struct TransferMetrics {
+    bytes: stats::Counter,
+    objects: stats::Counter,
+}
+
+impl TransferMetrics {
+    pub fn new(registry: &stats::Registry) -> TransferMetrics {
+        return TransferMetrics {
+            bytes: registry.counter(
+                "ofborg_git_received_bytes_total",
+                "Bytes received by clones and fetches",
+            ),
+            objects: registry.counter(
+                "ofborg_git_received_objects_total",
+                "Objects received by clones and fetches",
+            ),
+        };
+    }
+}
+
+/// Fetch options counting what's received in `received`, as
+/// (bytes, objects).
+fn fetch_options<'a>(received: Rc<Cell<(usize, usize)>>) -> FetchOptions<'a> {
+    let mut callbacks = RemoteCallbacks::new();
+    callbacks.transfer_progress(move |progress| {
+        received.set((progress.received_bytes(), progress.received_objects()));
+        true
+    });
+
+    let mut options = FetchOptions::new();
+    options.remote_callbacks(callbacks);
+    // Fetches of different PRs run at the same time, and nothing reads
+    // FETCH_HEAD.
+    options.update_fetchhead(false);
+    return options;
+}
+
+fn count_received(
+    metrics: &Option<TransferMetrics>,
+    operation: &str,
+    received: Rc<Cell<(usize, usize)>>,
+) {
+    let (bytes, objects) = received.get();
+    debug!("Received {} objects, {} bytes for a {}", objects, bytes, operation);
+
+    if let &Some(ref metrics) = metrics {
+        metrics.bytes.add(&[("operation", operation)], bytes as f64);
+        metrics.objects.add(&[("operation", operation)], objects as f64);
+    }
+}
+
+/// Fetch `refspecs` from origin, or what it's configured to fetch
+/// without any.
+pub fn fetch(
+    repo: &Repository,
+    refspecs: &[&str],
+    metrics: &Option<TransferMetrics>,
+) -> Result<(), Error> {
+    let received = Rc::new(Cell::new((0, 0)));
+    let mut remote = repo.find_remote("origin").map_err(git_error)?;
+    remote
+        .fetch(refspecs, Some(&mut fetch_options(received.clone())), None)
+        .map_err(git_error)?;
+
+    count_received(metrics, "fetch", received);
+    return Ok(());
+}
+
+/// The commit `spec`, like `origin/master` or a hash, refers to.
+pub fn find_commit<'r>(repo: &'r Repository, spec: &str) -> Result<git2::Commit<'r>, Error> {
+    let object = repo.revparse_single(spec).map_err(git_error)?;
+    let commit = object.peel(ObjectType::Commit).map_err(git_error)?;
+    return repo.find_commit(commit.id()).map_err(git_error);
+}
+
+/// Check out `commit`, throwing away any changes, with a detached HEAD.
+pub fn checkout_detached(repo: &Repository, commit: &git2::Commit) -> Result<(), Error> {
+    repo.checkout_tree(commit.as_object(), Some(CheckoutBuilder::new().force()))
+        .map_err(git_error)?;
+    return repo.set_head_detached(commit.id()).map_err(git_error);
+}
+
 pub trait GitClonable {
     fn clone_from(&self) -> String;
     fn clone_to(&self) -> PathBuf;
-    fn extra_clone_args(&self) -> Vec<&OsStr>;
+    fn bare(&self) -> bool;
 
-    fn lock_path(&self) -> PathBuf;
+    fn clone_options(&self) -> CloneOptions {
+        CloneOptions::default()
+    }
 
-    fn lock(&self) -> Result<Lock, Error> {
-        info!("Locking {:?}", self.lock_path());
+    fn transfer_metrics(&self) -> Option<TransferMetrics> {
+        None
+    }
 
-        match fs::File::create(self.lock_path()) {
-            Err(e) => {
-                warn!("Failed to create lock file {:?}: {}", self.lock_path(), e);
-                return Err(e);
-            }
-            Ok(lock) => {
-                match lock.lock_exclusive() {
-                    Err(e) => {
-                        warn!(
-                            "Failed to get exclusive lock on file {:?}: {}",
-                            self.lock_path(),
-                            e
-                        );
-                        return Err(e);
-                    }
-                    Ok(_) => {
-                        debug!("Got lock on {:?}", self.lock_path());
-                        return Ok(Lock { lock: Some(lock) });
-                    }
-                }
-            }
+    fn lock_path(&self) -> PathBuf;
 
-        }
+    fn lock(&self) -> Result<Lock, Error> {
+        lock_file(&self.lock_path())
     }
 
     fn clone_repo(&self) -> Result<(), Error> {
@@ -64,64 +230,64 @@ pub trait GitClonable {
             self.clone_to()
         );
 
-        let result = Command::new("git")
-            .arg("clone")
-            .args(self.extra_clone_args())
-            .arg(&self.clone_from())
-            .arg(&self.clone_to())
-            .status()?;
-
-        lock.unlock();
+        let options = self.clone_options();
+        if options.needs_git() {
+            let mut args: Vec<String> = vec![String::from("clone")];
+            if self.bare() {
+                args.push(String::from("--bare"));
+            }
+            args.extend(options.args());
+            args.push(self.clone_from());
+            args.push(self.clone_to().to_string_lossy().into_owned());
 
-        if result.success() {
-            return Ok(());
+            let args: Vec<&OsStr> = args.iter().map(|arg| OsStr::new(arg)).collect();
+            git(Path::new("."), &args)?;
         } else {
-            return Err(Error::new(ErrorKind::Other, "Failed to clone"));
+            let received = Rc::new(Cell::new((0, 0)));
+            RepoBuilder::new()
+                .bare(self.bare())
+                .fetch_options(fetch_options(received.clone()))
+                .clone(&self.clone_from(), &self.clone_to())
+                .map_err(git_error)?;
+            count_received(&self.transfer_metrics(), "clone", received);
         }
+
+        lock.unlock();
+
+        return Ok(());
     }
 
     fn fetch_repo(&self) -> Result<(), Error> {
         let mut lock = self.lock()?;
 
         info!("Fetching from origin in {:?}", self.clone_to());
-        let result = Command::new("git")
-            .arg("fetch")
-            .arg("origin")
-            .current_dir(self.clone_to())
-            .status()?;
+        let repo = open(&self.clone_to())?;
+        if repo.is_shallow() || self.clone_options().needs_git() {
+            git(&self.clone_to(), &[OsStr::new("fetch"), OsStr::new("origin")])?;
+        } else {
+            fetch(&repo, &[], &self.transfer_metrics())?;
+        }
 
         lock.unlock();
 
-        if result.success() {
-            return Ok(());
-        } else {
-            return Err(Error::new(ErrorKind::Other, "Failed to fetch"));
-        }
+        return Ok(());
     }
 
+    /// Abort any merge or `git am` in progress, and reset to HEAD.
     fn clean(&self) -> Result<(), Error> {
         let mut lock = self.lock()?;
 
-        info!("git am --abort");
-        Command::new("git")
-            .arg("am")
-            .arg("--abort")
-            .current_dir(self.clone_to())
-            .status()?;
-
-        info!("git merge --abort");
-        Command::new("git")
-            .arg("merge")
-            .arg("--abort")
-            .current_dir(self.clone_to())
-            .status()?;
-
-        info!("git reset --hard");
-        Command::new("git")
-            .arg("reset")
-            .arg("--hard")
-            .current_dir(self.clone_to())
-            .status()?;
+        let repo = open(&self.clone_to())?;
+        repo.cleanup_state().map_err(git_error)?;
+
+        info!("Resetting {:?}", self.clone_to());
+        let head = repo.head().and_then(|head| head.peel(ObjectType::Commit));
+        match head {
+            Ok(head) => repo.reset(&head, ResetType::Hard, None).map_err(git_error)?,
+            // Nothing was checked out yet.
+            Err(ref e) if e.code() == git2::ErrorCode::UnbornBranch => {}
+            Err(e) => return Err(git_error(e)),
+        }
 
         lock.unlock();
 
@@ -131,20 +297,14 @@ pub trait GitClonable {
     fn checkout(&self, git_ref: &OsStr) -> Result<(), Error> {
         let mut lock = self.lock()?;
 
-        debug!("git checkout {:?}", git_ref);
+        debug!("Checking out {:?}", git_ref);
 
-        let result = Command::new("git")
-            .arg("checkout")
-            .arg(git_ref)
-            .current_dir(self.clone_to())
-            .status()?;
+        let repo = open(&self.clone_to())?;
+        let commit = find_commit(&repo, &git_ref.to_string_lossy())?;
+        checkout_detached(&repo, &commit)?;
 
         lock.unlock();
 
-        if result.success() {
-            return Ok(());
-        } else {
-            return Err(Error::new(ErrorKind::Other, "Failed to checkout"));
-        }
+        return Ok(());
     }
 }
@@ -0,0 +1,128 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::{Arc, RwLock};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[derive(Debug, PartialEq)]
+pub enum Decision {
+    Allowed,
+    /// Over the limit, and the requester wasn't told yet.
+    Limited,
+    /// Still over the limit, the requester was already told.
+    StillLimited,
+}
+
+/// Caps how many commands each requester may give in a sliding
+/// window, so a flood of commands, accidental or not, can't take up
+/// every builder.
+pub struct CommandLimiter {
+    limits: Arc<RwLock<Limits>>,
+    commands: HashMap<String, VecDeque<u64>>,
+    told: HashSet<String>,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Limits {
+    pub max_commands: usize,
+    pub window_secs: u64,
+}
+
+impl CommandLimiter {
+    pub fn new(max_commands: usize, window_secs: u64) -> CommandLimiter {
+        return CommandLimiter {
+            limits: Arc::new(RwLock::new(Limits {
+                max_commands: max_commands,
+                window_secs: window_secs,
+            })),
+            commands: HashMap::new(),
+            told: HashSet::new(),
+        };
+    }
+
+    /// Where to swap in new limits, the commands given so far count
+    /// towards them.
+    pub fn limits(&self) -> Arc<RwLock<Limits>> {
+        self.limits.clone()
+    }
+
+    pub fn max_commands(&self) -> usize {
+        self.limits.read().unwrap().max_commands
+    }
+
+    pub fn window_secs(&self) -> u64 {
+        self.limits.read().unwrap().window_secs
+    }
+
+    pub fn check(&mut self, requester: &str) -> Decision {
+        self.check_at(requester, now())
+    }
+
+    fn check_at(&mut self, requester: &str, now: u64) -> Decision {
+        let requester = requester.to_lowercase();
+        let Limits { max_commands, window_secs } = *self.limits.read().unwrap();
+
+        // Forget everyone who went quiet, so the map doesn't grow
+        // with every requester ever seen.
+        self.commands.retain(|_, times| {
+            while times.front().map_or(false, |t| t + window_secs <= now) {
+                times.pop_front();
+            }
+            times.len() > 0
+        });
+
+        let times = self.commands.entry(requester.clone()).or_insert(
+            VecDeque::new(),
+        );
+        if times.len() >= max_commands {
+            if self.told.insert(requester) {
+                return Decision::Limited;
+            }
+            return Decision::StillLimited;
+        }
+
+        times.push_back(now);
+        self.told.remove(&requester);
+        return Decision::Allowed;
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check() {
+        let mut limiter = CommandLimiter::new(2, 3600);
+
+        assert_eq!(limiter.check_at("alice", 1000), Decision::Allowed);
+        assert_eq!(limiter.check_at("Alice", 1500), Decision::Allowed);
+        assert_eq!(limiter.check_at("alice", 2000), Decision::Limited);
+        assert_eq!(limiter.check_at("alice", 2500), Decision::StillLimited);
+        assert_eq!(limiter.check_at("bob", 2500), Decision::Allowed);
+
+        // The first command left the window.
+        assert_eq!(limiter.check_at("alice", 4600), Decision::Allowed);
+        assert_eq!(limiter.check_at("alice", 4700), Decision::Limited);
+    }
+
+    #[test]
+    fn test_changed_limits() {
+        let mut limiter = CommandLimiter::new(1, 3600);
+        assert_eq!(limiter.check_at("alice", 1000), Decision::Allowed);
+        assert_eq!(limiter.check_at("alice", 1100), Decision::Limited);
+
+        *limiter.limits().write().unwrap() = Limits {
+            max_commands: 2,
+            window_secs: 3600,
+        };
+        assert_eq!(limiter.max_commands(), 2);
+        assert_eq!(limiter.check_at("alice", 1200), Decision::Allowed);
+        assert_eq!(limiter.check_at("alice", 1300), Decision::Limited);
+    }
+}
@@ -1,74 +1,343 @@
-
 pub fn parse(text: &str) -> Option<Vec<Instruction>> {
-    let instructions: Vec<Instruction> = text.lines()
-        .map(|s| match parse_line(s) {
-            Some(instructions) => instructions,
-            None => vec![],
-        })
-        .fold(vec![], |mut collector, mut inst| {
-            collector.append(&mut inst);
-            collector
-        });
+    Triggers::default().parse(text)
+}
 
-    if instructions.len() == 0 {
-        return None;
-    } else {
-        return Some(instructions);
-    }
+pub fn parse_comment(text: &str) -> ParsedComment {
+    Triggers::default().parse_comment(text)
+}
+
+pub fn mentions_bot(text: &str) -> bool {
+    Triggers::default().mentions_bot(text)
 }
 
 pub fn parse_line(text: &str) -> Option<Vec<Instruction>> {
-    let tokens: Vec<String> = text.split_whitespace().map(|s| s.to_owned()).collect();
+    Triggers::default().parse_line(text)
+}
 
-    if tokens.len() < 2 {
-        return None;
+/// Everything a comment asked of us, and the commands we couldn't
+/// make sense of.
+#[derive(PartialEq, Debug)]
+pub struct ParsedComment {
+    pub instructions: Vec<Instruction>,
+    /// The commands understood, as written.
+    pub accepted: Vec<String>,
+    /// The commands not understood, as written.
+    pub failed: Vec<String>,
+}
+
+/// The mentions commands start with, like `@grahamcofborg`, matched
+/// case-insensitively. The first is the one we call ourselves.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Triggers {
+    names: Vec<String>,
+}
+
+impl Default for Triggers {
+    fn default() -> Triggers {
+        Triggers::new(vec![String::from("@grahamcofborg")])
+    }
+}
+
+impl Triggers {
+    pub fn new(names: Vec<String>) -> Triggers {
+        let names: Vec<String> = names
+            .iter()
+            .map(|name| name.trim().trim_left_matches('@'))
+            .filter(|name| name.len() > 0)
+            .map(|name| format!("@{}", name))
+            .collect();
+
+        if names.len() == 0 {
+            return Triggers::default();
+        }
+
+        return Triggers { names: names };
     }
 
-    if tokens[0].to_lowercase() != "@grahamcofborg" {
-        return None;
+    pub fn primary(&self) -> &str {
+        &self.names[0]
     }
 
-    let commands: Vec<&[String]> = tokens
-        .split(|token| token.to_lowercase() == "@grahamcofborg")
-        .filter(|token| token.len() > 0)
-        .collect();
+    fn is_trigger(&self, token: &str) -> bool {
+        let token = token.to_lowercase();
+        self.names.iter().any(|name| name.to_lowercase() == token)
+    }
+
+    /// Whether a code block's info string marks it as ours.
+    fn is_fence(&self, info: &str) -> bool {
+        info == "ofborg" || self.is_trigger(&format!("@{}", info))
+    }
 
-    let mut instructions: Vec<Instruction> = vec![];
-    for command in commands {
-        let (left, right) = command.split_at(1);
-        match left[0].as_ref() {
-            "build" => {
-                let attrs = right.to_vec();
+    pub fn parse(&self, text: &str) -> Option<Vec<Instruction>> {
+        let parsed = self.parse_comment(text);
+
+        if parsed.instructions.len() == 0 {
+            return None;
+        } else {
+            return Some(parsed.instructions);
+        }
+    }
 
-                if attrs.len() > 0 {
-                    instructions.push(Instruction::Build(Subset::Nixpkgs, attrs));
+    /// Commands are given one per line, or one per line of a code
+    /// block fenced with ` ```grahamcofborg `, where they don't need
+    /// to mention us:
+    ///
+    /// ```grahamcofborg
+    /// build hello
+    /// eval
+    /// ```
+    pub fn parse_comment(&self, text: &str) -> ParsedComment {
+        let mut parsed = ParsedComment {
+            instructions: vec![],
+            accepted: vec![],
+            failed: vec![],
+        };
+
+        let mut fence: Option<bool> = None;
+        for line in text.lines() {
+            let trimmed = line.trim();
+            if trimmed.starts_with("```") {
+                fence = match fence {
+                    Some(_) => None,
+                    None => {
+                        let info = trimmed.trim_left_matches('`').trim().to_lowercase();
+                        Some(self.is_fence(&info))
+                    }
+                };
+                continue;
+            }
+
+            let commands = if fence == Some(true) && !self.mentions_bot(trimmed) {
+                if trimmed.len() == 0 {
+                    continue;
+                }
+                self.parse_commands(&format!("{} {}", self.primary(), trimmed))
+            } else {
+                self.parse_commands(line)
+            };
+
+            for (command, instructions) in commands {
+                match instructions {
+                    Some(mut instructions) => {
+                        parsed.instructions.append(&mut instructions);
+                        parsed.accepted.push(command);
+                    }
+                    None => parsed.failed.push(command),
                 }
             }
-            "test" => {
-                instructions.push(Instruction::Build(
-                    Subset::NixOS,
-                    right
-                        .into_iter()
-                        .map(|attr| format!("tests.{}", attr))
-                        .collect(),
-                ));
+        }
+
+        return parsed;
+    }
+
+    /// Whether any line of the comment is addressed to us, even if it
+    /// doesn't contain a command we understand.
+    pub fn mentions_bot(&self, text: &str) -> bool {
+        text.lines().any(|line| {
+            line.split_whitespace()
+                .next()
+                .map(|token| self.is_trigger(token))
+                .unwrap_or(false)
+        })
+    }
+
+    pub fn parse_line(&self, text: &str) -> Option<Vec<Instruction>> {
+        let tokens: Vec<&str> = text.split_whitespace().collect();
+        if tokens.len() < 2 || !self.is_trigger(tokens[0]) {
+            return None;
+        }
+
+        let mut instructions: Vec<Instruction> = vec![];
+        for (_, command) in self.parse_commands(text) {
+            if let Some(mut command) = command {
+                instructions.append(&mut command);
+            }
+        }
 
+        return Some(instructions);
+    }
+
+    /// Each command on the line, with its instructions if we
+    /// understood it.
+    fn parse_commands(&self, text: &str) -> Vec<(String, Option<Vec<Instruction>>)> {
+        let tokens: Vec<String> = text.split_whitespace().map(|s| s.to_owned()).collect();
+
+        if tokens.len() < 2 {
+            return vec![];
+        }
+
+        if !self.is_trigger(&tokens[0]) {
+            return vec![];
+        }
+
+        return tokens
+            .split(|token| self.is_trigger(token))
+            .filter(|token| token.len() > 0)
+            .map(|command| {
+                (format!("{} {}", self.primary(), command.join(" ")), parse_command(command))
+            })
+            .collect();
+    }
+}
+
+fn parse_command(command: &[String]) -> Option<Vec<Instruction>> {
+    let (left, right) = command.split_at(1);
+    let instruction = match left[0].as_ref() {
+        "build" => {
+            let (attrs, flags) = parse_flags(right)?;
+            if attrs.len() == 0 {
+                return None;
             }
-            "eval" => instructions.push(Instruction::Eval),
-            _ => {}
+            Instruction::Build(Subset::Nixpkgs, attrs, flags)
+        }
+        "test" => {
+            let (attrs, flags) = parse_flags(right)?;
+            Instruction::Build(
+                Subset::NixOS,
+                attrs
+                    .into_iter()
+                    .map(|attr| format!("tests.{}", attr))
+                    .collect(),
+                flags,
+            )
+        }
+        "eval" => Instruction::Eval,
+        "help" => Instruction::Help,
+        "cancel" => Instruction::Cancel,
+        "retry" => Instruction::Retry,
+        "admin" => {
+            if right.len() != 2 || right[0].to_lowercase() != "ban" {
+                return None;
+            }
+            Instruction::Ban(right[1].trim_left_matches('@').to_owned())
+        }
+        "label" => {
+            // The rest of the command is the label's name, as
+            // label names often contain spaces.
+            if right.len() < 2 {
+                return None;
+            }
+            let label = right[1..].join(" ");
+            match right[0].to_lowercase().as_ref() {
+                "add" => Instruction::Label(LabelAction::Add, label),
+                "remove" => Instruction::Label(LabelAction::Remove, label),
+                _ => return None,
+            }
+        }
+        _ => return None,
+    };
+
+    return Some(vec![instruction]);
+}
+
+/// Split a command's arguments into its attributes and flags. Any
+/// flag we don't know, or one missing its value, makes the whole
+/// command invalid rather than silently building something else.
+fn parse_flags(args: &[String]) -> Option<(Vec<String>, BuildFlags)> {
+    let mut attrs: Vec<String> = vec![];
+    let mut flags = BuildFlags::default();
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if !arg.starts_with("--") {
+            attrs.push(arg.clone());
+            continue;
+        }
+
+        match arg.to_lowercase().as_ref() {
+            "--on" => flags.on.push(args.next()?.clone()),
+            "--timeout" => flags.timeout = Some(parse_duration(args.next()?)?),
+            "--keep-going" => flags.keep_going = true,
+            "--check" => flags.check = true,
+            _ => return None,
         }
     }
 
-    return Some(instructions);
+    return Some((attrs, flags));
+}
+
+/// The longest duration accepted, which still fits the builders' u16
+/// of seconds.
+const MAX_DURATION: u64 = 18 * 60 * 60;
+
+/// Seconds in a duration like `90`, `30m` or `2h`.
+fn parse_duration(text: &str) -> Option<u64> {
+    let (number, multiplier) = match text.chars().last()? {
+        's' => (&text[..text.len() - 1], 1),
+        'm' => (&text[..text.len() - 1], 60),
+        'h' => (&text[..text.len() - 1], 60 * 60),
+        _ => (text, 1),
+    };
+
+    let number: u64 = number.parse().ok()?;
+    if number == 0 {
+        return None;
+    }
+
+    let seconds = number.checked_mul(multiplier)?;
+    if seconds > MAX_DURATION {
+        return None;
+    }
+
+    return Some(seconds);
 }
 
 #[derive(PartialEq, Debug)]
 pub enum Instruction {
-    Build(Subset, Vec<String>),
+    Build(Subset, Vec<String>, BuildFlags),
     Eval,
+    Label(LabelAction, String),
+    Help,
+    Cancel,
+    Retry,
+    /// Ignore everything from this user from now on.
+    Ban(String),
+}
+
+#[derive(PartialEq, Debug)]
+pub enum LabelAction {
+    Add,
+    Remove,
+}
+
+/// Options of a build or test command, like `--on aarch64-linux`.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Default)]
+#[serde(default)]
+pub struct BuildFlags {
+    /// Only build on these systems.
+    pub on: Vec<String>,
+    /// Seconds a build may go without output, instead of the
+    /// builder's own timeout.
+    pub timeout: Option<u64>,
+    /// Keep building the other attributes when one fails, which
+    /// builders already do.
+    pub keep_going: bool,
+    /// Build again to check the outputs are reproducible.
+    pub check: bool,
+}
+
+impl BuildFlags {
+    /// The flags given, as each may need more trust than the command.
+    pub fn names(&self) -> Vec<&'static str> {
+        let mut flags = vec![];
+        if self.on.len() > 0 {
+            flags.push("--on");
+        }
+        if self.timeout.is_some() {
+            flags.push("--timeout");
+        }
+        if self.keep_going {
+            flags.push("--keep-going");
+        }
+        if self.check {
+            flags.push("--check");
+        }
+
+        return flags;
+    }
 }
 
-#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
 pub enum Subset {
     Nixpkgs,
     NixOS,
@@ -84,6 +353,14 @@ mod tests {
         assert_eq!(None, parse(""));
     }
 
+    #[test]
+    fn mentions() {
+        assert!(mentions_bot("thanks!\n@GrahamcOfBorg biuld hello"));
+        assert!(mentions_bot("@grahamcofborg"));
+        assert!(!mentions_bot("thanks @grahamcofborg"));
+        assert!(!mentions_bot(""));
+    }
+
     #[test]
     fn valid_trailing_instruction() {
         assert_eq!(
@@ -117,7 +394,8 @@ mod tests {
                 Instruction::Eval,
                 Instruction::Build(
                     Subset::Nixpkgs,
-                    vec![String::from("foo")]
+                    vec![String::from("foo")],
+                    BuildFlags::default()
                 ),
             ]),
             parse("@grahamcofborg eval @grahamcofborg build foo")
@@ -130,12 +408,14 @@ mod tests {
             Some(vec![
                 Instruction::Build(
                     Subset::Nixpkgs,
-                    vec![String::from("bar")]
+                    vec![String::from("bar")],
+                    BuildFlags::default()
                 ),
                 Instruction::Eval,
                 Instruction::Build(
                     Subset::Nixpkgs,
-                    vec![String::from("foo")]
+                    vec![String::from("foo")],
+                    BuildFlags::default()
                 ),
             ]),
             parse(
@@ -153,12 +433,14 @@ mod tests {
             Some(vec![
                 Instruction::Build(
                     Subset::Nixpkgs,
-                    vec![String::from("bar")]
+                    vec![String::from("bar")],
+                    BuildFlags::default()
                 ),
                 Instruction::Eval,
                 Instruction::Build(
                     Subset::Nixpkgs,
-                    vec![String::from("foo")]
+                    vec![String::from("foo")],
+                    BuildFlags::default()
                 ),
             ]),
             parse(
@@ -178,13 +460,60 @@ Also, just in case, let's try foo
     }
 
 
+    #[test]
+    fn help_comment() {
+        assert_eq!(Some(vec![Instruction::Help]), parse("@GrahamCOfBorg help"));
+    }
+
+    #[test]
+    fn cancel_comment() {
+        assert_eq!(
+            Some(vec![Instruction::Cancel]),
+            parse("Sorry, pushed too early.\n@grahamcofborg cancel")
+        );
+    }
+
+    #[test]
+    fn retry_comment() {
+        assert_eq!(Some(vec![Instruction::Retry]), parse("@grahamcofborg retry"));
+    }
+
+    #[test]
+    fn ban_comment() {
+        assert_eq!(
+            Some(vec![Instruction::Ban(String::from("spammer"))]),
+            parse(
+                "@grahamcofborg admin ban @spammer
+@grahamcofborg admin ban
+@grahamcofborg admin unban spammer",
+            )
+        );
+    }
+
+    #[test]
+    fn label_comment() {
+        assert_eq!(
+            Some(vec![
+                Instruction::Label(LabelAction::Add, String::from("backport release-18.03")),
+                Instruction::Label(LabelAction::Remove, String::from("1.severity: security")),
+            ]),
+            parse(
+                "@grahamcofborg label add backport release-18.03
+@grahamcofborg label Remove 1.severity: security
+@grahamcofborg label add
+@grahamcofborg label rename foo",
+            )
+        );
+    }
+
     #[test]
     fn build_and_eval_comment() {
         assert_eq!(
             Some(vec![
                 Instruction::Build(
                     Subset::Nixpkgs,
-                    vec![String::from("foo")]
+                    vec![String::from("foo")],
+                    BuildFlags::default()
                 ),
                 Instruction::Eval,
             ]),
@@ -198,7 +527,8 @@ Also, just in case, let's try foo
             Some(vec![
                 Instruction::Build(
                     Subset::Nixpkgs,
-                    vec![String::from("foo"), String::from("bar")]
+                    vec![String::from("foo"), String::from("bar")],
+                    BuildFlags::default()
                 ),
             ]),
             parse(
@@ -219,7 +549,8 @@ baz",
                         String::from("tests.foo"),
                         String::from("tests.bar"),
                         String::from("tests.baz"),
-                    ]
+                    ],
+                    BuildFlags::default()
                 ),
             ]),
             parse("@GrahamCOfBorg test foo bar baz")
@@ -236,7 +567,8 @@ baz",
                         String::from("foo"),
                         String::from("bar"),
                         String::from("baz"),
-                    ]
+                    ],
+                    BuildFlags::default()
                 ),
             ]),
             parse("@GrahamCOfBorg build foo bar baz")
@@ -253,7 +585,8 @@ baz",
                         String::from("foo"),
                         String::from("bar"),
                         String::from("baz"),
-                    ]
+                    ],
+                    BuildFlags::default()
                 ),
             ]),
             parse("@grahamcofborg build foo bar baz")
@@ -270,11 +603,136 @@ baz",
                         String::from("foo"),
                         String::from("bar"),
                         String::from("baz.Baz"),
-                    ]
+                    ],
+                    BuildFlags::default()
                 ),
             ]),
             parse("@grahamcofborg build foo bar baz.Baz")
         );
     }
 
+    #[test]
+    fn fenced_comment() {
+        let parsed = parse_comment(
+            "Let's try these:
+
+```grahamcofborg
+build hello
+
+eval
+frobnicate
+```
+
+```
+build not-a-command
+```
+@grahamcofborg label add",
+        );
+
+        assert_eq!(
+            parsed.instructions,
+            vec![
+                Instruction::Build(
+                    Subset::Nixpkgs,
+                    vec![String::from("hello")],
+                    BuildFlags::default()
+                ),
+                Instruction::Eval,
+            ]
+        );
+        assert_eq!(
+            parsed.accepted,
+            vec![
+                String::from("@grahamcofborg build hello"),
+                String::from("@grahamcofborg eval"),
+            ]
+        );
+        assert_eq!(
+            parsed.failed,
+            vec![
+                String::from("@grahamcofborg frobnicate"),
+                String::from("@grahamcofborg label add"),
+            ]
+        );
+    }
+
+    #[test]
+    fn custom_triggers() {
+        let triggers = Triggers::new(vec![String::from("ofborg"), String::from("@GrahamcOfBorg")]);
+        assert_eq!(triggers.primary(), "@ofborg");
+        assert!(triggers.mentions_bot("@OfBorg eval"));
+        assert!(!Triggers::default().mentions_bot("@ofborg eval"));
+
+        assert_eq!(
+            Some(vec![Instruction::Eval, Instruction::Help]),
+            triggers.parse("@ofborg eval @grahamcofborg help")
+        );
+
+        let parsed = triggers.parse_comment("```ofborg\nbiuld hello\n```");
+        assert_eq!(parsed.failed, vec![String::from("@ofborg biuld hello")]);
+    }
+
+    #[test]
+    fn build_flags_comment() {
+        assert_eq!(
+            Some(vec![
+                Instruction::Build(
+                    Subset::Nixpkgs,
+                    vec![String::from("foo"), String::from("bar")],
+                    BuildFlags {
+                        on: vec![String::from("aarch64-linux")],
+                        timeout: Some(2 * 60 * 60),
+                        keep_going: false,
+                        check: true,
+                    }
+                ),
+                Instruction::Build(
+                    Subset::NixOS,
+                    vec![String::from("tests.login")],
+                    BuildFlags {
+                        on: vec![],
+                        timeout: None,
+                        keep_going: true,
+                        check: false,
+                    }
+                ),
+            ]),
+            parse(
+                "@grahamcofborg build foo --on aarch64-linux --timeout 2h bar --check
+@grahamcofborg test --keep-going login",
+            )
+        );
+    }
+
+    #[test]
+    fn bogus_build_flags_comment() {
+        assert_eq!(None, parse("@grahamcofborg build foo --frobnicate"));
+        assert_eq!(None, parse("@grahamcofborg build foo --on"));
+        assert_eq!(None, parse("@grahamcofborg build foo --timeout soon"));
+    }
+
+    #[test]
+    fn overlong_durations() {
+        assert_eq!(parse_duration("18h"), Some(18 * 60 * 60));
+        assert_eq!(parse_duration("19h"), None);
+        assert_eq!(parse_duration("99999999999999999h"), None);
+        assert_eq!(parse_duration("99999999999999999999"), None);
+        assert_eq!(
+            None,
+            parse("@grahamcofborg build foo --timeout 99999999999999999h")
+        );
+    }
+
+    #[test]
+    fn flag_names() {
+        let mut flags = BuildFlags::default();
+        assert_eq!(flags.names(), Vec::<&str>::new());
+
+        flags.on = vec![String::from("x86_64-darwin")];
+        flags.timeout = parse_duration("90m");
+        flags.check = true;
+        assert_eq!(flags.names(), vec!["--on", "--timeout", "--check"]);
+        assert_eq!(flags.timeout, Some(90 * 60));
+    }
+
 }
@@ -1,14 +1,52 @@
 extern crate amqp;
 extern crate env_logger;
 
+use std::cell::{Cell, RefCell};
+use std::time::{Duration, Instant};
 use hubcaps;
+use ofborg::githubapi::{GithubApi, CheckAnnotation, CheckRunOptions, CheckRunOutput};
+
+/// The GitHub Checks API accepts at most this many annotations per request.
+const MAX_CHECK_ANNOTATIONS: usize = 50;
+
+/// Progress updates closer together than this are coalesced, unless
+/// `evaluation.min_pending_interval_secs` says otherwise.
+pub const MIN_PENDING_INTERVAL_SECS: u64 = 10;
+
+enum Target<'a> {
+    Status(hubcaps::statuses::Statuses<'a>),
+    CheckRun {
+        api: &'a GithubApi,
+        repo: String,
+        id: Cell<Option<u64>>,
+    },
+    /// What would have been sent, to test against.
+    #[cfg(test)]
+    Recorded(RefCell<Vec<(String, String)>>),
+}
+
+/// What a pending update said, to send it later if it was coalesced.
+#[derive(Debug, Clone, PartialEq)]
+struct Progress {
+    description: String,
+    url: String,
+    summary: Option<String>,
+}
 
 pub struct CommitStatus<'a> {
-    api: hubcaps::statuses::Statuses<'a>,
+    target: Target<'a>,
     sha: String,
     context: String,
     description: String,
     url: String,
+    summary: Option<String>,
+    annotations: Vec<CheckAnnotation>,
+    external_id: Option<String>,
+    min_pending_interval: Duration,
+    /// When we last talked to GitHub, and what we told it.
+    last_sent: RefCell<Option<(Instant, String)>>,
+    /// The latest pending update which was coalesced and not yet sent.
+    unsent: RefCell<Option<Progress>>,
 }
 
 impl<'a> CommitStatus<'a> {
@@ -18,13 +56,52 @@ impl<'a> CommitStatus<'a> {
         context: String,
         description: String,
         url: Option<String>,
+    ) -> CommitStatus<'a> {
+        return CommitStatus::with_target(Target::Status(api), sha, context, description, url);
+    }
+
+    /// Report through a check run instead of a commit status, which
+    /// also carries a markdown summary and annotations.
+    pub fn check_run(
+        api: &'a GithubApi,
+        repo: String,
+        sha: String,
+        context: String,
+        description: String,
+        url: Option<String>,
+    ) -> CommitStatus<'a> {
+        return CommitStatus::with_target(
+            Target::CheckRun {
+                api: api,
+                repo: repo,
+                id: Cell::new(None),
+            },
+            sha,
+            context,
+            description,
+            url,
+        );
+    }
+
+    fn with_target(
+        target: Target<'a>,
+        sha: String,
+        context: String,
+        description: String,
+        url: Option<String>,
     ) -> CommitStatus<'a> {
         let mut stat = CommitStatus {
-            api: api,
+            target: target,
             sha: sha,
             context: context,
             description: description,
             url: "".to_owned(),
+            summary: None,
+            annotations: vec![],
+            external_id: None,
+            min_pending_interval: Duration::from_secs(MIN_PENDING_INTERVAL_SECS),
+            last_sent: RefCell::new(None),
+            unsent: RefCell::new(None),
         };
 
         stat.set_url(url);
@@ -32,29 +109,353 @@ impl<'a> CommitStatus<'a> {
         return stat;
     }
 
+    pub fn set_min_pending_interval(&mut self, interval: Duration) {
+        self.min_pending_interval = interval;
+    }
+
+    pub fn is_check_run(&self) -> bool {
+        match self.target {
+            Target::CheckRun { .. } => true,
+            _ => false,
+        }
+    }
+
     pub fn set_url(&mut self, url: Option<String>) {
         self.url = url.unwrap_or(String::from(""))
     }
 
+    /// Markdown shown on the check run's page. Commit statuses have
+    /// nowhere to put it, so it is dropped for them.
+    pub fn set_summary(&mut self, summary: String) {
+        self.summary = Some(summary);
+    }
+
+    /// Lets a re-run requested from the GitHub UI find the task behind
+    /// the check run, see `checkrunfilter::RerunTarget`. Dropped for
+    /// commit statuses.
+    pub fn set_external_id(&mut self, external_id: String) {
+        self.external_id = Some(external_id);
+    }
+
+    /// `(path, line, message)` annotations for the check run, dropped
+    /// for commit statuses.
+    pub fn set_annotations(&mut self, annotations: Vec<(String, u64, String)>) {
+        self.annotations = annotations
+            .into_iter()
+            .take(MAX_CHECK_ANNOTATIONS)
+            .map(|(path, line, message)| {
+                CheckAnnotation {
+                    path: path,
+                    start_line: line,
+                    end_line: line,
+                    annotation_level: String::from("failure"),
+                    message: message,
+                }
+            })
+            .collect();
+    }
+
     pub fn set_with_description(&mut self, description: &str, state: hubcaps::statuses::State) {
         self.set_description(description.to_owned());
         self.set(state);
     }
 
+    /// Neither passed nor failed, like checks skipped on a draft PR.
+    /// Commit statuses have no such state, so they report success.
+    pub fn set_neutral(&mut self, description: &str) {
+        self.set_description(description.to_owned());
+        match self.target {
+            Target::CheckRun { .. } => {
+                self.flush_before_final();
+                if !self.should_send("Neutral", false) {
+                    return;
+                }
+                let mut options =
+                    self.check_run_options(hubcaps::statuses::State::Success, &self.progress());
+                options.conclusion = Some(String::from("neutral"));
+                self.send_check_run(options);
+            }
+            _ => self.set(hubcaps::statuses::State::Success),
+        }
+    }
+
     pub fn set_description(&mut self, description: String) {
         self.description = description;
     }
 
+    /// Report `state`, unless it repeats the previous update or is a
+    /// pending update following another one too closely. A coalesced
+    /// pending update is sent by `flush` once the interval passed, or
+    /// right before the final state.
     pub fn set(&self, state: hubcaps::statuses::State) {
-        self.api
-            .create(
-                self.sha.as_ref(),
-                &hubcaps::statuses::StatusOptions::builder(state)
-                    .context(self.context.clone())
-                    .description(self.description.clone())
-                    .target_url(self.url.clone())
-                    .build(),
-            )
-            .expect("Failed to mark final status on commit");
+        let pending = match state {
+            hubcaps::statuses::State::Pending => true,
+            _ => false,
+        };
+        if !pending {
+            self.flush_before_final();
+        }
+
+        if !self.should_send(&format!("{:?}", state), pending) {
+            if pending {
+                debug!("Coalescing {} update: {}", self.context, self.description);
+                *self.unsent.borrow_mut() = Some(self.progress());
+            }
+            return;
+        }
+
+        *self.unsent.borrow_mut() = None;
+        self.send(state, &self.progress());
+    }
+
+    /// Send the coalesced pending update, if there is one and the
+    /// interval since the last update passed. Called between steps
+    /// which don't update the status themselves, so it doesn't stay
+    /// behind for as long as they run.
+    pub fn flush(&self) {
+        let due = match *self.last_sent.borrow() {
+            Some((at, _)) => at.elapsed() >= self.min_pending_interval,
+            None => true,
+        };
+        if due {
+            self.send_unsent();
+        }
+    }
+
+    /// The final state replaces every pending update, so the latest
+    /// progress is only seen if it is sent first.
+    fn flush_before_final(&self) {
+        self.send_unsent();
+    }
+
+    fn send_unsent(&self) {
+        let progress = match self.unsent.borrow_mut().take() {
+            Some(progress) => progress,
+            None => return,
+        };
+
+        *self.last_sent.borrow_mut() = Some((
+            Instant::now(),
+            update_key("Pending", &progress),
+        ));
+        self.send(hubcaps::statuses::State::Pending, &progress);
+    }
+
+    fn progress(&self) -> Progress {
+        return Progress {
+            description: self.description.clone(),
+            url: self.url.clone(),
+            summary: self.summary.clone(),
+        };
+    }
+
+    fn send(&self, state: hubcaps::statuses::State, progress: &Progress) {
+        match self.target {
+            Target::Status(ref api) => {
+                api.create(
+                    self.sha.as_ref(),
+                    &hubcaps::statuses::StatusOptions::builder(state)
+                        .context(self.context.clone())
+                        .description(progress.description.clone())
+                        .target_url(progress.url.clone())
+                        .build(),
+                ).expect("Failed to mark final status on commit");
+            }
+            Target::CheckRun { .. } => {
+                self.send_check_run(self.check_run_options(state, progress));
+            }
+            #[cfg(test)]
+            Target::Recorded(ref sent) => {
+                sent.borrow_mut().push((format!("{:?}", state), progress.description.clone()));
+            }
+        }
+    }
+
+    fn should_send(&self, state: &str, pending: bool) -> bool {
+        let update = update_key(state, &self.progress());
+        let now = Instant::now();
+
+        let send = should_send(
+            self.last_sent.borrow().as_ref(),
+            &update,
+            pending,
+            now,
+            self.min_pending_interval,
+        );
+        if send {
+            *self.last_sent.borrow_mut() = Some((now, update));
+        }
+
+        return send;
+    }
+
+    fn send_check_run(&self, mut options: CheckRunOptions) {
+        if let Target::CheckRun {
+            ref api,
+            ref repo,
+            ref id,
+        } = self.target
+        {
+            let run = match id.get() {
+                Some(id) => api.update_check_run(repo, id, &options),
+                None => {
+                    options.name = Some(self.context.clone());
+                    options.head_sha = Some(self.sha.clone());
+                    api.create_check_run(repo, &options)
+                }
+            };
+
+            id.set(Some(run.expect("Failed to update check run on commit").id));
+        }
+    }
+
+    fn check_run_options(
+        &self,
+        state: hubcaps::statuses::State,
+        progress: &Progress,
+    ) -> CheckRunOptions {
+        let (status, conclusion) = match state {
+            hubcaps::statuses::State::Pending => ("in_progress", None),
+            hubcaps::statuses::State::Success => ("completed", Some("success")),
+            hubcaps::statuses::State::Failure => ("completed", Some("failure")),
+            // Check runs have no error, and cancelled ones read as if
+            // someone stopped them rather than as broken.
+            hubcaps::statuses::State::Error => ("completed", Some("failure")),
+        };
+
+        return CheckRunOptions {
+            details_url: if progress.url.is_empty() {
+                None
+            } else {
+                Some(progress.url.clone())
+            },
+            external_id: self.external_id.clone(),
+            status: String::from(status),
+            conclusion: conclusion.map(String::from),
+            output: Some(CheckRunOutput {
+                title: progress.description.clone(),
+                summary: progress.summary.clone().unwrap_or(progress.description.clone()),
+                // Annotations accumulate over updates, so only send
+                // them with the conclusion.
+                annotations: if conclusion.is_some() {
+                    self.annotations.clone()
+                } else {
+                    vec![]
+                },
+            }),
+            ..Default::default()
+        };
+    }
+}
+
+/// What tells an update apart from the previous one.
+fn update_key(state: &str, progress: &Progress) -> String {
+    return format!(
+        "{}\n{}\n{}\n{:?}",
+        state,
+        progress.description,
+        progress.url,
+        progress.summary
+    );
+}
+
+fn should_send(
+    last_sent: Option<&(Instant, String)>,
+    update: &str,
+    pending: bool,
+    now: Instant,
+    min_pending_interval: Duration,
+) -> bool {
+    match last_sent {
+        None => true,
+        Some(&(_, ref last)) if last == update => false,
+        Some(&(at, _)) if pending => now.duration_since(at) >= min_pending_interval,
+        Some(_) => true,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    fn sent(status: &CommitStatus) -> Vec<(String, String)> {
+        match status.target {
+            Target::Recorded(ref sent) => sent.borrow().clone(),
+            _ => panic!("Not recorded"),
+        }
+    }
+
+    fn update(state: &str, description: &str) -> (String, String) {
+        return (String::from(state), String::from(description));
+    }
+
+    #[test]
+    fn test_flush_sends_coalesced_progress() {
+        let mut status = CommitStatus::with_target(
+            Target::Recorded(RefCell::new(vec![])),
+            String::from("abc"),
+            String::from("grahamcofborg-eval"),
+            String::from("Starting"),
+            None,
+        );
+        status.set_min_pending_interval(Duration::from_millis(50));
+
+        status.set(hubcaps::statuses::State::Pending);
+        status.set_with_description("Beginning Evaluations", hubcaps::statuses::State::Pending);
+        status.flush();
+        assert_eq!(sent(&status), vec![update("Pending", "Starting")]);
+
+        thread::sleep(Duration::from_millis(60));
+        status.flush();
+        status.flush();
+        assert_eq!(
+            sent(&status),
+            vec![
+                update("Pending", "Starting"),
+                update("Pending", "Beginning Evaluations"),
+            ]
+        );
+
+        status.set_with_description("^.^!", hubcaps::statuses::State::Success);
+        assert_eq!(sent(&status).last(), Some(&update("Success", "^.^!")));
+    }
+
+    #[test]
+    fn test_should_send() {
+        let start = Instant::now();
+        let interval = Duration::from_secs(10);
+        let last = (start, String::from("Pending\nCloning project"));
+
+        assert!(should_send(None, "Pending\nStarting", true, start, interval));
+        assert!(!should_send(Some(&last), "Pending\nCloning project", true, start, interval));
+        assert!(!should_send(
+            Some(&last),
+            "Pending\nCloning project",
+            false,
+            start + interval,
+            interval,
+        ));
+        assert!(!should_send(
+            Some(&last),
+            "Pending\nFetching PR",
+            true,
+            start + Duration::from_secs(3),
+            interval,
+        ));
+        assert!(should_send(
+            Some(&last),
+            "Pending\nFetching PR",
+            true,
+            start + interval,
+            interval,
+        ));
+        assert!(should_send(
+            Some(&last),
+            "Success\n^.^!",
+            false,
+            start + Duration::from_secs(1),
+            interval,
+        ));
     }
 }
@@ -1,13 +1,36 @@
 use serde_json;
+use serde_ignored;
+use toml;
+use std::env;
+use std::fmt;
 use std::fs::File;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::io::Read;
-use hyper::Client;
-use hyper::net::HttpsConnector;
-use hyper_native_tls::NativeTlsClient;
-use hubcaps::{Credentials, Github};
-use nix::Nix;
-use std::collections::HashMap;
+use std::process;
+use nix::{self, Nix};
+use githubapi::{GithubApi, GithubEndpoints, Credentials};
+use githubapp::GithubAppAuth;
+use branchpolicy::BranchPolicy;
+use pushpolicy::PushPolicy;
+use commentparser::Triggers;
+use commandlimit::CommandLimiter;
+use commitstatus;
+use aclsource;
+use banlist;
+use configcheck;
+use nixpath;
+use message::envelope::{self, Encoding};
+use msgsign::Signer;
+use checkout;
+use cli;
+use clone;
+use secrets::{Secret, Secrets};
+use logging;
+use stats;
+use tracing;
+use std::sync::Arc;
+use std::time::Duration;
+use std::collections::{BTreeMap, HashMap};
 
 
 use ofborg::acl;
@@ -22,11 +45,296 @@ pub struct Config {
     pub github: Option<GithubConfig>,
     pub log_storage: Option<LogStorage>,
     pub tag_paths: Option<HashMap<String, Vec<String>>>,
+    pub branch_policies: Option<Vec<BranchPolicy>>,
+    /// Deprecated, the thresholds by target branch, read into
+    /// `branch_policies`.
+    pub rebuild_label_thresholds: Option<HashMap<String, Vec<u64>>>,
+    pub maintainer_ping: Option<MaintainerPingConfig>,
+    pub security_feed: Option<SecurityFeedConfig>,
+    pub evaluation: Option<EvaluationConfig>,
+    pub format_checks: Option<Vec<FormatCheckConfig>>,
+    pub reevaluation: Option<ReevaluationConfig>,
+    pub webhook: Option<WebhookConfig>,
+    pub status_reaper: Option<StatusReaperConfig>,
+    pub queue_monitor: Option<QueueMonitorConfig>,
+    pub api: Option<ApiConfig>,
+    /// Repositories configured on their own, by their full name.
+    pub repositories: Option<HashMap<String, RepoConfig>>,
+    /// Labels trusted users may add and remove with `label` commands.
+    pub controllable_labels: Option<Vec<String>>,
+    /// The mentions commands start with, `@grahamcofborg` by default.
+    /// The first is the name we go by, the others are aliases.
+    pub triggers: Option<Vec<String>>,
+    /// Limit how much work users who aren't trusted may start.
+    pub command_limit: Option<CommandLimitConfig>,
+    /// The tier commands and flags need, like `eval: anyone` or
+    /// `--on: trusted`, overriding the defaults.
+    pub command_tiers: Option<HashMap<String, acl::Tier>>,
+    pub audit_log: Option<AuditLogConfig>,
+    /// Pin nixpkgs for the `<nixpkgs>` of other repositories.
+    pub nixpkgs_pins: Option<NixpkgsPinsConfig>,
+    /// Overrides of the rest of the configuration by instance, like
+    /// `builder-x86_64-linux-1`, picked with `--instance`.
+    pub instances: Option<HashMap<String, serde_json::Value>>,
+    /// Where `password_secret` and `token_secret` are kept.
+    pub secrets: Option<SecretsConfig>,
+    /// Export Prometheus metrics over HTTP.
+    pub metrics: Option<MetricsConfig>,
+    /// Export traces to an OpenTelemetry collector.
+    pub tracing: Option<TracingConfig>,
+    pub logging: Option<LogConfig>,
+    /// Keep the collected stats across restarts.
+    pub stats: Option<StatsConfig>,
+    /// Report which attrs failed most often.
+    pub failure_report: Option<FailureReportConfig>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct MetricsConfig {
+    /// Like `0.0.0.0:9899`.
+    pub listen: Option<String>,
+    /// Where a task, like `builder`, listens instead.
+    pub task_listen: Option<HashMap<String, String>>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct StatsConfig {
+    /// Like `/var/lib/ofborg/stats.json`.
+    pub snapshot_file: Option<String>,
+    /// How often the stats are saved, every minute by default.
+    pub snapshot_interval_seconds: Option<u64>,
+    /// How often to publish a summary of the usage by repository and
+    /// user, like every day.
+    pub usage_summary_interval_seconds: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct FailureReportConfig {
+    /// The directory `failures.json` and `failures.md` are written to.
+    pub path: String,
+    /// How far back builds are counted, a week by default.
+    pub window_days: Option<u64>,
+    /// How often the report is written, every hour by default.
+    pub interval_seconds: Option<u64>,
+    /// How many attrs are reported, 100 by default.
+    pub top: Option<usize>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct LogConfig {
+    /// Like `info`, or `info,hyper=warn`. `RUST_LOG` overrides it.
+    pub level: Option<String>,
+    /// The levels of modules, like `ofborg::tasks::build: debug`.
+    pub modules: Option<HashMap<String, String>>,
+    /// Log JSON lines, for log shippers.
+    pub json: Option<bool>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TracingConfig {
+    /// The collector's OTLP/HTTP endpoint, like `http://localhost:4318`.
+    pub otlp_endpoint: String,
+    /// Sent along with the spans, like a collector's API key.
+    pub headers: Option<HashMap<String, String>>,
+    /// Headers whose value is in a file instead, by their name.
+    pub header_files: Option<HashMap<String, String>>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SecretsConfig {
+    pub vault: Option<VaultConfig>,
+    pub aws_secrets_manager: Option<AwsSecretsConfig>,
+    /// How often to fetch secrets without a lease again, every 15
+    /// minutes by default.
+    pub refresh_seconds: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct VaultConfig {
+    /// Like `https://vault.example.com:8200`.
+    pub address: String,
+    /// A file containing the token, `VAULT_TOKEN` by default.
+    pub token_file: Option<String>,
+    pub namespace: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct AwsSecretsConfig {
+    pub region: String,
+    /// Derived from `region` unless set.
+    pub endpoint: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct NixpkgsPinsConfig {
+    /// `NixOS/nixpkgs` by default.
+    pub repo: Option<String>,
+    /// Pinned branches, targets of other branches use the first.
+    pub branches: Vec<String>,
+    pub update_seconds: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct AuditLogConfig {
+    /// Audit events are appended here, one JSON object per line.
+    pub path: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct MaintainerPingConfig {
+    pub opt_out: Vec<String>,
+    pub max_changed_attrs: usize,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SecurityFeedConfig {
+    pub path: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct EvaluationConfig {
+    /// The queue to consume evaluation jobs from.
+    pub queue: Option<String>,
+    /// Where to send jobs which exceed the memory limit.
+    pub high_memory_queue: Option<String>,
+    /// How many systems' out paths to query at the same time.
+    pub outpath_parallelism: Option<usize>,
+    /// The systems whose out paths are compared, like `aarch64-darwin`.
+    pub outpath_systems: Option<Vec<String>>,
+    /// How to treat draft PRs, evaluating them like any other by default.
+    pub draft_prs: Option<DraftMode>,
+    /// How often at most to report an evaluation's progress.
+    pub min_pending_interval_secs: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all="snake_case")]
+pub enum DraftMode {
+    Evaluate,
+    /// Wait until the PR is marked ready for review.
+    Skip,
+    /// Only run the checks which don't need an evaluation.
+    Quick,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct FormatCheckConfig {
+    pub name: String,
+    /// The command to run, the changed files are appended to it.
+    pub command: Vec<String>,
+    /// Only check files ending in one of these, like `.nix`.
+    pub extensions: Option<Vec<String>>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct ReevaluationConfig {
+    /// Re-evaluate open PRs after this many commits land on their base.
+    pub commits: Option<u64>,
+    /// Re-evaluate open PRs once their base has moved and this many
+    /// seconds passed since the last re-evaluation.
+    pub seconds: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RepoConfig {
+    /// Replace `runner.trusted_users` on this repository.
+    pub trusted_users: Option<Vec<String>>,
+    /// Replace `runner.known_users` on this repository.
+    pub known_users: Option<Vec<String>>,
+    /// Replace `runner.trusted_teams` on this repository.
+    pub trusted_teams: Option<Vec<String>>,
+    /// Take precedence over `command_tiers` on this repository.
+    pub command_tiers: Option<HashMap<String, acl::Tier>>,
+    /// The evaluation checks to run, all of them by default.
+    pub checks: Option<Vec<String>>,
+    /// The repository is a flake, check it with `nix flake check`
+    /// and its `checks` instead of nixpkgs' evaluations.
+    pub flake: Option<bool>,
+    /// Check out its submodules, recursively.
+    pub submodules: Option<bool>,
+    /// Point out force pushes and unsigned commits it doesn't allow,
+    /// instead of evaluating them.
+    pub push_policy: Option<PushPolicy>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct StatusReaperConfig {
+    /// Our statuses pending for longer than this were abandoned by a
+    /// crashed worker or a lost message.
+    pub max_pending_hours: u64,
+    /// How often to look for them, hourly by default.
+    pub interval_seconds: Option<u64>,
+    /// Queue the evaluation of PRs with an abandoned status again.
+    pub requeue: Option<bool>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct QueueMonitorConfig {
+    /// RabbitMQ's management API, like `https://events.nix.ci:15671`,
+    /// on the broker's host by default.
+    pub management_url: Option<String>,
+    /// How often to poll, every minute by default.
+    pub interval_seconds: Option<u64>,
+    /// Prefixes of the queues to watch, every queue of the vhost by
+    /// default.
+    pub queues: Option<Vec<String>>,
+    /// Alert when more messages than this wait in a queue, by the
+    /// queue's prefix, like `build-inputs-`.
+    pub backlog_alerts: Option<HashMap<String, u64>>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ApiConfig {
+    /// Like `0.0.0.0:9900`.
+    pub listen: String,
+    /// Where the logs are browsed, `https://logs.nix.ci/` by default.
+    pub log_url: Option<String>,
+    /// Builders which started a build this recently are active, within
+    /// the last hour by default.
+    pub active_builder_seconds: Option<u64>,
+    /// Tools which may queue builds and evaluations through the API.
+    pub trigger_clients: Option<Vec<TriggerClientConfig>>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TriggerClientConfig {
+    /// Like `nixpkgs-update`, which it names in `X-Ofborg-Client`.
+    pub name: String,
+    /// The key its requests are signed with.
+    pub secret_file: String,
+    /// The repositories it may trigger on, any by default.
+    pub repos: Option<Vec<String>>,
+    /// Build on every system, like for trusted users, instead of only
+    /// on the systems anyone may build on.
+    pub trusted: Option<bool>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CommandLimitConfig {
+    /// How many evaluations and builds one user may start in the window.
+    pub max_commands: usize,
+    /// An hour by default.
+    pub window_seconds: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct WebhookConfig {
+    /// The address to accept webhooks on, like `127.0.0.1:9899`.
+    pub listen: String,
+    /// A file containing the webhook's secret.
+    pub secret_file: String,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct FeedbackConfig {
     pub full_logs: bool,
+    /// How log lines are sent to the log collector: `json`, by default,
+    /// or `cbor`, which is smaller and quicker to encode.
+    pub log_encoding: Option<Encoding>,
+    /// Publish build results the way they were before they had the
+    /// result of each attr, until every consumer reads the new ones.
+    #[serde(default)]
+    pub legacy_build_results: bool,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -35,7 +343,28 @@ pub struct RabbitMQConfig {
     pub host: String,
     pub virtualhost: Option<String>,
     pub username: String,
+    #[serde(default)]
     pub password: String,
+    /// A file containing the password instead, like a systemd
+    /// credential.
+    pub password_file: Option<String>,
+    /// A secret of the `secrets` provider instead, like
+    /// `vault:secret/data/ofborg#rabbitmq`.
+    pub password_secret: Option<String>,
+    #[serde(skip)]
+    pub secret: Option<Arc<Secret>>,
+    /// A file containing a key every task shares, to sign the messages
+    /// they publish and drop the ones they consume which aren't.
+    pub signing_key_file: Option<String>,
+    #[serde(skip)]
+    pub signing_key: Option<String>,
+    /// Signed messages older than this are dropped as replayed, see
+    /// `msgsign::DEFAULT_MAX_AGE_SECS`.
+    pub signature_max_age_secs: Option<u64>,
+    /// Publish messages without their envelope, the way they were
+    /// before it, until every consumer reads it.
+    #[serde(default)]
+    pub legacy_messages: bool,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -43,12 +372,100 @@ pub struct NixConfig {
     pub system: String,
     pub remote: String,
     pub build_timeout_seconds: u16,
-    pub initial_heap_size: Option<String>
+    pub initial_heap_size: Option<String>,
+    pub memory_limit_mb: Option<u64>,
+    /// Which CLI to run nix with, detected from `nix --version` by
+    /// default.
+    pub cli: Option<nix::Cli>,
+    /// Build on these stores, like `ssh-ng://ofborg@mac-mini`, one
+    /// build at a time each, instead of locally. The remote machines
+    /// only need nix, this builder evaluates, dispatches and streams
+    /// their logs back.
+    pub remote_stores: Option<Vec<String>>,
+    /// Options for every nix command, instead of the host's nix.conf.
+    pub options: Option<NixOptions>,
+    /// Options for a task's commands, like `builder` or
+    /// `mass-rebuilder`, over the ones above.
+    pub task_options: Option<HashMap<String, NixOptions>>,
+    /// Options for the commands of a system, like `aarch64-linux`,
+    /// over the ones above.
+    pub system_options: Option<HashMap<String, NixOptions>>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct NixOptions {
+    pub substituters: Option<Vec<String>>,
+    pub trusted_public_keys: Option<Vec<String>>,
+    pub cores: Option<u64>,
+    pub max_jobs: Option<u64>,
+    /// Made available in the sandbox, next to the host's
+    /// `sandbox-paths`.
+    pub sandbox_paths: Option<Vec<String>>,
+    /// Any other options, by their name in nix.conf.
+    pub extra: Option<BTreeMap<String, String>>,
+}
+
+impl NixOptions {
+    /// The options by their name in nix.conf.
+    pub fn by_name(&self) -> BTreeMap<String, String> {
+        let mut options = self.extra.clone().unwrap_or(BTreeMap::new());
+
+        {
+            let mut set = |name: &str, value: Option<String>| if let Some(value) = value {
+                options.insert(String::from(name), value);
+            };
+            set("substituters", self.substituters.as_ref().map(|s| s.join(" ")));
+            set(
+                "trusted-public-keys",
+                self.trusted_public_keys.as_ref().map(|keys| keys.join(" ")),
+            );
+            set("cores", self.cores.map(|cores| format!("{}", cores)));
+            set("max-jobs", self.max_jobs.map(|jobs| format!("{}", jobs)));
+            set(
+                "extra-sandbox-paths",
+                self.sandbox_paths.as_ref().map(|paths| paths.join(" ")),
+            );
+        }
+
+        return options;
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct GithubConfig {
-    pub token: String,
+    /// A personal access token, unless authenticating as an `app`.
+    pub token: Option<String>,
+    /// A file containing the token instead.
+    pub token_file: Option<String>,
+    /// A secret of the `secrets` provider instead, like
+    /// `aws:ofborg/github#token`.
+    pub token_secret: Option<String>,
+    #[serde(skip)]
+    pub secret: Option<Arc<Secret>>,
+    pub app: Option<GithubAppConfig>,
+    /// More personal access tokens, of other accounts, to spread
+    /// requests over so a single account's rate limit isn't exhausted.
+    pub extra_tokens: Option<Vec<String>>,
+    /// Files containing more tokens instead.
+    pub extra_token_files: Option<Vec<String>>,
+    /// Report through check runs instead of commit statuses, which
+    /// only works with an `app`.
+    pub checks_api: Option<bool>,
+    /// The REST API, like `https://github.example.com/api/v3` for
+    /// GitHub Enterprise. Defaults to `https://api.github.com`.
+    pub api_base: Option<String>,
+    /// Derived from `api_base` unless set.
+    pub uploads_base: Option<String>,
+    /// Only accept webhooks sent by this GitHub Enterprise host.
+    pub webhook_audience: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct GithubAppConfig {
+    pub app_id: u64,
+    pub installation_id: u64,
+    /// The PEM encoded private key GitHub generated for the app.
+    pub private_key_file: String,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -62,11 +479,53 @@ pub struct RunnerConfig {
     pub repos: Option<Vec<String>>,
     pub trusted_users: Option<Vec<String>>,
     pub known_users: Option<Vec<String>>,
+    /// Members of these teams (`org/team-slug`) or organizations (`org`)
+    /// are trusted as well, as looked up through the GitHub API.
+    pub trusted_teams: Option<Vec<String>>,
+    /// Operators of this instance, trusted on every repository.
+    pub admins: Option<Vec<String>>,
+    /// Load `trusted_users` and `known_users` from elsewhere instead.
+    pub users_from: Option<UsersSourceConfig>,
+    /// The file of users whose commands are ignored, which
+    /// `admin ban` adds to.
+    pub ban_list: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct UsersSourceConfig {
+    /// A path or an `https://` URL to a JSON object with
+    /// `trusted_users` and `known_users`.
+    pub location: String,
+    /// Five minutes by default.
+    pub reload_seconds: Option<u64>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct CheckoutConfig {
     pub root: String,
+    /// Clone only this many commits, deepening checkouts as needed.
+    pub depth: Option<u32>,
+    /// Like `blob:none`, for partial clones.
+    pub filter: Option<String>,
+    /// Delete checkouts which aren't used anymore.
+    pub gc: Option<CheckoutGcConfig>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct CheckoutGcConfig {
+    /// Checkouts unused for this long are deleted, a week by default.
+    pub max_age_days: Option<u64>,
+    /// How often, every day by default.
+    pub interval_hours: Option<u64>,
+}
+
+impl CheckoutConfig {
+    pub fn clone_options(&self) -> clone::CloneOptions {
+        return clone::CloneOptions {
+            depth: self.depth,
+            filter: self.filter.clone(),
+        };
+    }
 }
 
 impl Config {
@@ -75,29 +534,222 @@ impl Config {
     }
 
     pub fn acl(&self) -> acl::ACL {
-        return acl::ACL::new(
+        let (trusted_users, known_users) = match self.runner.users_from {
+            Some(ref from) => {
+                let users = aclsource::Source::from_location(&from.location)
+                    .and_then(|source| aclsource::load(&source))
+                    .expect("loading config's runner.users_from");
+                (users.trusted_users, users.known_users)
+            }
+            None => (
+                self.runner.trusted_users.clone().expect(
+                    "fetching config's runner.trusted_users",
+                ),
+                self.runner.known_users.clone().expect(
+                    "fetching config's runner.known_users",
+                ),
+            ),
+        };
+
+        let mut acl = acl::ACL::new(
             self.runner.repos.clone().expect(
                 "fetching config's runner.repos",
             ),
-            self.runner.trusted_users.clone().expect(
-                "fetching config's runner.trusted_users",
-            ),
-            self.runner.known_users.clone().expect(
-                "fetching config's runner.known_users",
-            ),
+            trusted_users,
+            known_users,
+        ).with_admins(self.runner.admins.clone().unwrap_or(vec![]))
+            .with_command_tiers(self.command_tiers.clone().unwrap_or(HashMap::new()));
+
+        if let Some(ref path) = self.runner.ban_list {
+            let banned = banlist::load(path).expect("loading config's runner.ban_list");
+            acl = acl.with_ban_list(path, banned);
+            banlist::watch(
+                path.clone(),
+                acl.banned(),
+                Duration::from_secs(banlist::RELOAD_SECS),
+            );
+        }
+
+        if let Some(ref from) = self.runner.users_from {
+            aclsource::watch(
+                aclsource::Source::from_location(&from.location).unwrap(),
+                acl.users(),
+                Duration::from_secs(from.reload_seconds.unwrap_or(5 * 60)),
+            );
+        }
+
+        let mut any_teams = false;
+        for (name, repo) in self.repositories() {
+            acl = acl.with_repo_users(
+                &name,
+                repo.trusted_users.unwrap_or(vec![]),
+                repo.known_users.unwrap_or(vec![]),
+            );
+            if let Some(teams) = repo.trusted_teams {
+                any_teams = any_teams || teams.len() > 0;
+                acl = acl.with_repo_teams(&name, teams);
+            }
+            if let Some(tiers) = repo.command_tiers {
+                acl = acl.with_repo_command_tiers(&name, tiers);
+            }
+        }
+
+        let teams = self.runner.trusted_teams.clone().unwrap_or(vec![]);
+        if teams.len() > 0 || any_teams {
+            acl = acl.with_trusted_teams(teams, Box::new(self.github_api()));
+        }
+
+        return acl;
+    }
+
+    /// Per repository configuration, by lowercase full name.
+    pub fn repositories(&self) -> HashMap<String, RepoConfig> {
+        self.repositories
+            .clone()
+            .unwrap_or(HashMap::new())
+            .into_iter()
+            .map(|(name, repo)| (name.to_lowercase(), repo))
+            .collect()
+    }
+
+    /// The repositories whose submodules are checked out.
+    pub fn submodule_repos(&self) -> Vec<String> {
+        self.repositories()
+            .into_iter()
+            .filter(|&(_, ref repo)| repo.submodules == Some(true))
+            .map(|(name, _)| name)
+            .collect()
+    }
+
+    /// Every repository we work on, by lowercase full name.
+    pub fn repo_names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.runner
+            .repos
+            .clone()
+            .unwrap_or(vec![])
+            .iter()
+            .map(|name| name.to_lowercase())
+            .collect();
+        for name in self.repositories().keys() {
+            if !names.contains(name) {
+                names.push(name.clone());
+            }
+        }
+
+        return names;
+    }
+
+    pub fn triggers(&self) -> Triggers {
+        match self.triggers {
+            Some(ref names) => Triggers::new(names.clone()),
+            None => Triggers::default(),
+        }
+    }
+
+    pub fn command_limiter(&self) -> Option<CommandLimiter> {
+        self.command_limit.as_ref().map(|limit| {
+            CommandLimiter::new(limit.max_commands, limit.window_seconds.unwrap_or(60 * 60))
+        })
+    }
+
+    pub fn github_endpoints(&self) -> GithubEndpoints {
+        let github = self.github.as_ref();
+        let endpoints = GithubEndpoints::for_api(
+            github
+                .and_then(|github| github.api_base.as_ref())
+                .map(|base| base.as_str())
+                .unwrap_or("https://api.github.com"),
         );
+
+        return match github.and_then(|github| github.uploads_base.clone()) {
+            Some(uploads) => endpoints.with_uploads(uploads),
+            None => endpoints,
+        };
     }
 
-    pub fn github(&self) -> Github {
-        Github::new(
-            "github.com/grahamc/ofborg",
-            // tls configured hyper client
-            Client::with_connector(HttpsConnector::new(NativeTlsClient::new().unwrap())),
-            Credentials::Token(self.github.clone().unwrap().token),
+    pub fn webhook_audience(&self) -> Option<String> {
+        self.github.as_ref().and_then(
+            |github| github.webhook_audience.clone(),
         )
     }
 
-    pub fn nix(&self) -> Nix {
+    pub fn github_api(&self) -> GithubApi {
+        let endpoints = self.github_endpoints();
+        let github = self.github.clone().expect("fetching config's github");
+
+        let credentials = match github.app {
+            Some(app) => {
+                Credentials::App(
+                    GithubAppAuth::from_key_file(
+                        endpoints.api.clone(),
+                        app.app_id,
+                        app.installation_id,
+                        &app.private_key_file,
+                    ).unwrap(),
+                )
+            }
+            None => {
+                match github.secret {
+                    Some(secret) => Credentials::Secret(secret),
+                    None => {
+                        Credentials::Token(github.token.expect(
+                            "fetching config's github.token or github.app",
+                        ))
+                    }
+                }
+            }
+        };
+
+        let mut pool = vec![credentials];
+        for token in github.extra_tokens.unwrap_or(vec![]) {
+            pool.push(Credentials::Token(token));
+        }
+
+        GithubApi::with_pool(endpoints, pool)
+    }
+
+    pub fn use_checks_api(&self) -> bool {
+        self.github
+            .as_ref()
+            .and_then(|github| github.checks_api)
+            .unwrap_or(false)
+    }
+
+    /// The metrics of `task`, like `builder`, exported when it has an
+    /// address to listen on.
+    pub fn metrics(&self, task: &str) -> stats::Registry {
+        let registry = stats::Registry::new(task, &self.runner.identity, &self.nix.system);
+
+        let listen = self.metrics.as_ref().and_then(|metrics| {
+            metrics
+                .task_listen
+                .as_ref()
+                .and_then(|listen| listen.get(task).cloned())
+                .or(metrics.listen.clone())
+        });
+        if let Some(listen) = listen {
+            registry.serve(&listen);
+        }
+
+        return registry;
+    }
+
+    /// Log as `task`, like `builder`.
+    pub fn setup_log(&self, task: &str) {
+        logging::init(task, self.logging.as_ref());
+    }
+
+    /// The tracer of `task`, like `builder`, exporting its spans when
+    /// there's a collector to export to.
+    pub fn tracer(&self, task: &str) -> tracing::Tracer {
+        return match self.tracing {
+            Some(ref tracing) => tracing::Tracer::new(task, &self.runner.identity, tracing),
+            None => tracing::Tracer::disabled(task),
+        };
+    }
+
+    /// Nix for `task`, like `builder`, with its options.
+    pub fn nix(&self, task: &str) -> Nix {
         if self.nix.build_timeout_seconds < 1200 {
             error!(
                 "Note: {} is way too low for build_timeout_seconds!",
@@ -112,29 +764,935 @@ impl Config {
             self.nix.remote.clone(),
             self.nix.build_timeout_seconds,
             self.nix.initial_heap_size.clone(),
-        );
+        ).with_cli(self.nix.cli.unwrap_or_else(nix::Cli::detect))
+            .with_options(self.nix_options(task), self.nix_system_options());
+    }
+
+    fn nix_options(&self, task: &str) -> BTreeMap<String, String> {
+        let mut options = self.nix
+            .options
+            .as_ref()
+            .map(|options| options.by_name())
+            .unwrap_or(BTreeMap::new());
+
+        if let Some(task_options) = self.nix.task_options.as_ref().and_then(
+            |tasks| tasks.get(task),
+        )
+        {
+            options.extend(task_options.by_name());
+        }
+
+        return options;
+    }
+
+    fn nix_system_options(&self) -> BTreeMap<String, BTreeMap<String, String>> {
+        self.nix
+            .system_options
+            .as_ref()
+            .map(|systems| {
+                systems
+                    .iter()
+                    .map(|(system, options)| (system.clone(), options.by_name()))
+                    .collect()
+            })
+            .unwrap_or(BTreeMap::new())
+    }
+
+    /// The pins, kept up to date, when configured.
+    /// Collect the garbage of the checkouts, if configured to.
+    pub fn maintain_checkouts(&self, metrics: &stats::Registry) {
+        if let Some(ref gc) = self.checkout.gc {
+            checkout::cached_cloner(Path::new(&self.checkout.root)).maintain(
+                Duration::from_secs(gc.max_age_days.unwrap_or(7) * 24 * 60 * 60),
+                Duration::from_secs(gc.interval_hours.unwrap_or(24) * 60 * 60),
+                metrics,
+            );
+        }
+    }
+
+    pub fn nixpkgs_pins(&self) -> Option<nixpath::Pins> {
+        self.nixpkgs_pins.as_ref().map(|config| {
+            let repo = config.repo.clone().unwrap_or(String::from("NixOS/nixpkgs"));
+            let mut root = Path::new(&self.checkout.root).to_path_buf();
+            root.push("nixpkgs-pins");
+
+            let pins = nixpath::Pins::new(
+                &root,
+                repo.clone(),
+                format!("https://github.com/{}.git", repo),
+                config.branches.clone(),
+            );
+            pins.watch(Duration::from_secs(
+                config.update_seconds.unwrap_or(nixpath::UPDATE_SECS),
+            ));
+            pins
+        })
+    }
+
+    pub fn eval_queue(&self) -> String {
+        self.evaluation
+            .as_ref()
+            .and_then(|evaluation| evaluation.queue.clone())
+            .unwrap_or(String::from("mass-rebuild-check-jobs"))
+    }
+
+    pub fn high_memory_eval_queue(&self) -> Option<String> {
+        self.evaluation
+            .as_ref()
+            .and_then(|evaluation| evaluation.high_memory_queue.clone())
+    }
+
+    pub fn draft_mode(&self) -> DraftMode {
+        self.evaluation
+            .as_ref()
+            .and_then(|evaluation| evaluation.draft_prs)
+            .unwrap_or(DraftMode::Evaluate)
+    }
+
+    pub fn outpath_systems(&self) -> Vec<String> {
+        self.evaluation
+            .as_ref()
+            .and_then(|evaluation| evaluation.outpath_systems.clone())
+            .unwrap_or(vec![])
+    }
+
+    pub fn outpath_parallelism(&self) -> usize {
+        self.evaluation
+            .as_ref()
+            .and_then(|evaluation| evaluation.outpath_parallelism)
+            .unwrap_or(1)
+    }
+
+    pub fn min_pending_interval(&self) -> Duration {
+        Duration::from_secs(
+            self.evaluation
+                .as_ref()
+                .and_then(|evaluation| evaluation.min_pending_interval_secs)
+                .unwrap_or(commitstatus::MIN_PENDING_INTERVAL_SECS),
+        )
     }
 }
 
 impl RabbitMQConfig {
+    /// The password, fetched again from the secrets provider once it
+    /// expires.
+    pub fn current_password(&self) -> String {
+        match self.secret {
+            Some(ref secret) => {
+                secret.get().unwrap_or_else(|e| {
+                    warn!("Using the last password: {}", e);
+                    self.password.clone()
+                })
+            }
+            None => self.password.clone(),
+        }
+    }
+
+    /// What messages are signed with, if they are.
+    pub fn signer(&self) -> Option<Signer> {
+        return self.signing_key.as_ref().map(|key| {
+            let signer = Signer::new(key.as_bytes());
+            match self.signature_max_age_secs {
+                Some(secs) => signer.with_max_age(secs),
+                None => signer,
+            }
+        });
+    }
+
     pub fn as_uri(&self) -> String {
         return format!(
             "{}://{}:{}@{}/{}",
             if self.ssl { "amqps" } else { "amqp" },
             self.username,
-            self.password,
+            self.current_password(),
             self.host,
             self.virtualhost.clone().unwrap_or("/".to_owned()),
         );
     }
 }
 
-pub fn load(filename: &Path) -> Config {
-    let mut file = File::open(filename).unwrap();
+/// Load the configuration named on the command line. With
+/// `--check-config` it's checked instead and the program exits,
+/// `--check-github` checks the GitHub credentials as well.
+pub fn load_from_args() -> Config {
+    let args: Vec<String> = cli::args();
+    let flag = |name: &str| args.iter().any(|arg| arg == name);
+    let location = Location::from_args();
+
+    if flag("--check-config") {
+        let fit = configcheck::run(&location, flag("--check-github"));
+        process::exit(if fit { 0 } else { 1 });
+    }
+
+    return load(&location);
+}
+
+/// Load the configuration named on the command line, if there is one,
+/// for tools which also work without one.
+pub fn load_optional_from_args() -> Option<Config> {
+    return Location::parse_args(cli::args()).map(|location| load(&location));
+}
+
+/// Where the configuration is: a file, and the instance in it.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Location {
+    pub path: PathBuf,
+    pub instance: Option<String>,
+}
+
+impl Location {
+    /// The file and `--instance` named on the command line.
+    pub fn from_args() -> Location {
+        match Location::parse_args(cli::args()) {
+            Some(location) => location,
+            None => {
+                eprintln!(
+                    "Usage: {} [--check-config [--check-github]] [--instance <name>] <config>",
+                    env::args().next().unwrap_or(String::from("ofborg"))
+                );
+                process::exit(1);
+            }
+        }
+    }
+
+    fn parse_args(args: Vec<String>) -> Option<Location> {
+        let mut path: Option<PathBuf> = None;
+        let mut instance: Option<String> = None;
+
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            if arg == "--instance" {
+                instance = Some(args.next()?);
+            } else if cli::VALUE_FLAGS.contains(&arg.as_str()) {
+                args.next()?;
+            } else if !arg.starts_with("--") && path.is_none() {
+                path = Some(PathBuf::from(arg));
+            }
+        }
+
+        return path.map(|path| {
+            Location {
+                path: path,
+                instance: instance,
+            }
+        });
+    }
+
+    pub fn read(&self) -> Result<(Config, Vec<Problem>), Problem> {
+        read(&self.path, self.instance.as_ref().map(|i| i.as_str()))
+    }
+}
+
+/// Load the configuration, JSON or TOML by its extension, warning of
+/// keys which aren't used and exiting when it isn't valid.
+pub fn load(location: &Location) -> Config {
+    match location.read() {
+        Ok((config, unknown)) => {
+            for problem in unknown {
+                eprintln!("Warning: {}", problem);
+            }
+            envelope::publish_legacy(config.rabbitmq.legacy_messages);
+            return config;
+        }
+        Err(problem) => {
+            eprintln!("Invalid configuration: {}", problem);
+            process::exit(1);
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Format {
+    Json,
+    Toml,
+}
+
+impl Format {
+    pub fn of(filename: &Path) -> Format {
+        match filename.extension().and_then(|ext| ext.to_str()) {
+            Some("toml") => Format::Toml,
+            _ => Format::Json,
+        }
+    }
+}
+
+/// What's wrong with a configuration file, and where.
+#[derive(Debug, PartialEq)]
+pub struct Problem {
+    pub file: String,
+    pub line: Option<usize>,
+    pub message: String,
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.line {
+            Some(line) => write!(f, "{}:{}: {}", self.file, line, self.message),
+            None => write!(f, "{}: {}", self.file, self.message),
+        }
+    }
+}
+
+/// The configuration and its unknown keys, or why it isn't valid.
+/// `${NAME}` is replaced by the environment variable `NAME`, except in
+/// TOML comments, `$${` is a literal `${`. The overrides of `instance` apply on top of the
+/// rest, objects merged key by key.
+pub fn read(filename: &Path, instance: Option<&str>) -> Result<(Config, Vec<Problem>), Problem> {
+    let file = filename.display().to_string();
     let mut contents = String::new();
-    file.read_to_string(&mut contents).unwrap();
+    File::open(filename)
+        .and_then(|mut f| f.read_to_string(&mut contents))
+        .map_err(|e| {
+            Problem {
+                file: file.clone(),
+                line: None,
+                message: format!("Failed to read: {}", e),
+            }
+        })?;
 
-    let deserialized: Config = serde_json::from_str(&contents).unwrap();
+    return parse(&file, &contents, Format::of(filename), instance);
+}
 
-    return deserialized;
+pub fn parse(
+    file: &str,
+    contents: &str,
+    format: Format,
+    instance: Option<&str>,
+) -> Result<(Config, Vec<Problem>), Problem> {
+    let mut unknown: Vec<String> = vec![];
+    let problem = |line: Option<usize>, message: String| {
+        Problem {
+            file: file.to_owned(),
+            line: line,
+            message: message,
+        }
+    };
+
+    let contents = &interpolate(contents, format).map_err(|(line, message)| {
+        problem(Some(line), message)
+    })?;
+
+    let mut config: Config = match (format, instance) {
+        (format, Some(name)) => {
+            let whole = match format {
+                Format::Json => {
+                    serde_json::from_str(contents).map_err(|e| {
+                        problem(Some(e.line()), format!("{}", e))
+                    })?
+                }
+                Format::Toml => {
+                    let whole: toml::Value = toml::from_str(contents).map_err(|e| {
+                        problem(e.line_col().map(|(line, _)| line + 1), format!("{}", e))
+                    })?;
+                    serde_json::to_value(whole).map_err(|e| problem(None, format!("{}", e)))?
+                }
+            };
+            let picked = pick_instance(whole, name).map_err(|e| {
+                problem(key_line(contents, "instances", format), e)
+            })?;
+
+            serde_ignored::deserialize(picked, |path| unknown.push(path.to_string()))
+                .map_err(|e| problem(None, format!("in instance {}: {}", name, e)))?
+        }
+        (Format::Json, None) => {
+            let mut de = serde_json::Deserializer::from_str(contents);
+            let config = serde_ignored::deserialize(&mut de, |path| unknown.push(path.to_string()))
+                .and_then(|config| de.end().map(|_| config))
+                .map_err(|e| problem(Some(e.line()), format!("{}", e)))?;
+            config
+        }
+        (Format::Toml, None) => {
+            let mut de = toml::Deserializer::new(contents);
+            serde_ignored::deserialize(&mut de, |path| unknown.push(path.to_string()))
+                .map_err(|e| {
+                    problem(e.line_col().map(|(line, _)| line + 1), format!("{}", e))
+                })?
+        }
+    };
+
+    let mut unknown: Vec<Problem> = unknown
+        .into_iter()
+        .map(|path| {
+            problem(
+                key_line(contents, &path, format),
+                format!("unknown key `{}`", path),
+            )
+        })
+        .collect();
+
+    if let Some(thresholds) = config.rebuild_label_thresholds.take() {
+        unknown.push(problem(
+            key_line(contents, "rebuild_label_thresholds", format),
+            String::from(
+                "`rebuild_label_thresholds` is deprecated, set the \
+                 `rebuild_label_thresholds` of `branch_policies` instead",
+            ),
+        ));
+        let policies = config.branch_policies.take().unwrap_or(vec![]);
+        config.branch_policies = Some(with_rebuild_label_thresholds(policies, thresholds));
+    }
+
+    if let Some(path) = config.rabbitmq.password_file.clone() {
+        config.rabbitmq.password = read_secret(&path).map_err(|e| {
+            problem(key_line(contents, "password_file", format), e)
+        })?;
+    } else if config.rabbitmq.password_secret.is_none() && config.rabbitmq.password.len() == 0 {
+        return Err(problem(
+            key_line(contents, "rabbitmq", format),
+            String::from(
+                "rabbitmq needs a password, a password_file or a password_secret",
+            ),
+        ));
+    }
+
+    if let Some(path) = config.rabbitmq.signing_key_file.clone() {
+        config.rabbitmq.signing_key = Some(read_secret(&path).map_err(|e| {
+            problem(key_line(contents, "signing_key_file", format), e)
+        })?);
+    }
+
+    if let Some(ref mut github) = config.github {
+        // Only GitHub Apps may create check runs.
+        if github.checks_api == Some(true) && github.app.is_none() {
+            unknown.push(problem(
+                key_line(contents, "checks_api", format),
+                String::from(
+                    "checks_api needs the credentials of a GitHub app, reporting \
+                     through commit statuses instead",
+                ),
+            ));
+            github.checks_api = Some(false);
+        }
+
+        if let Some(path) = github.token_file.clone() {
+            github.token = Some(read_secret(&path).map_err(|e| {
+                problem(key_line(contents, "token_file", format), e)
+            })?);
+        }
+
+        if let Some(paths) = github.extra_token_files.clone() {
+            let mut tokens = github.extra_tokens.take().unwrap_or(vec![]);
+            for path in paths {
+                tokens.push(read_secret(&path).map_err(|e| {
+                    problem(key_line(contents, "extra_token_files", format), e)
+                })?);
+            }
+            github.extra_tokens = Some(tokens);
+        }
+    }
+
+    if let Some(ref mut tracing) = config.tracing {
+        if let Some(paths) = tracing.header_files.clone() {
+            let mut headers = tracing.headers.take().unwrap_or(HashMap::new());
+            for (name, path) in paths {
+                let value = read_secret(&path).map_err(|e| {
+                    problem(key_line(contents, "header_files", format), e)
+                })?;
+                headers.insert(name, value);
+            }
+            tracing.headers = Some(headers);
+        }
+    }
+
+    let secrets = config.secrets.as_ref().map(
+        |secrets| Arc::new(Secrets::new(secrets)),
+    );
+    if let Some(reference) = config.rabbitmq.password_secret.clone() {
+        let (secret, password) = fetch_secret(&secrets, &reference).map_err(|e| {
+            problem(key_line(contents, "password_secret", format), e)
+        })?;
+        config.rabbitmq.password = password;
+        config.rabbitmq.secret = Some(secret);
+    }
+    if let Some(ref mut github) = config.github {
+        if let Some(reference) = github.token_secret.clone() {
+            let (secret, token) = fetch_secret(&secrets, &reference).map_err(|e| {
+                problem(key_line(contents, "token_secret", format), e)
+            })?;
+            github.token = Some(token);
+            github.secret = Some(secret);
+        }
+    }
+
+    return Ok((config, unknown));
+}
+
+/// The policies with the thresholds of the deprecated
+/// `rebuild_label_thresholds`, for the branches whose policy doesn't
+/// set its own.
+fn with_rebuild_label_thresholds(
+    mut policies: Vec<BranchPolicy>,
+    thresholds: HashMap<String, Vec<u64>>,
+) -> Vec<BranchPolicy> {
+    let mut branches: Vec<String> = thresholds.keys().cloned().collect();
+    branches.sort();
+    for branch in branches {
+        let limits = thresholds[&branch].clone();
+        match policies.iter().position(|policy| policy.branch == branch) {
+            Some(i) => {
+                if policies[i].rebuild_label_thresholds.is_none() {
+                    policies[i].rebuild_label_thresholds = Some(limits);
+                }
+            }
+            None => {
+                policies.push(BranchPolicy {
+                    branch: branch,
+                    rebuild_label_thresholds: Some(limits),
+                    ..BranchPolicy::default()
+                });
+            }
+        }
+    }
+    return policies;
+}
+
+/// The configuration with the overrides of the instance `name`.
+fn pick_instance(mut whole: serde_json::Value, name: &str) -> Result<serde_json::Value, String> {
+    let instances = match whole {
+        serde_json::Value::Object(ref mut whole) => whole.remove("instances"),
+        _ => None,
+    };
+
+    let overrides = match instances {
+        Some(serde_json::Value::Object(mut instances)) => {
+            let names: Vec<String> = instances.keys().cloned().collect();
+            instances.remove(name).ok_or_else(|| {
+                format!("there's no instance {}, only {}", name, names.join(", "))
+            })?
+        }
+        _ => return Err(format!("there are no instances to pick {} from", name)),
+    };
+
+    merge(&mut whole, overrides);
+    return Ok(whole);
+}
+
+fn merge(base: &mut serde_json::Value, overrides: serde_json::Value) {
+    match (base, overrides) {
+        (&mut serde_json::Value::Object(ref mut base), serde_json::Value::Object(overrides)) => {
+            for (key, value) in overrides {
+                let merged = match base.remove(&key) {
+                    Some(mut existing) => {
+                        merge(&mut existing, value);
+                        existing
+                    }
+                    None => value,
+                };
+                base.insert(key, merged);
+            }
+        }
+        (base, overrides) => *base = overrides,
+    }
+}
+
+fn interpolate(contents: &str, format: Format) -> Result<String, (usize, String)> {
+    let mut interpolated = String::with_capacity(contents.len());
+
+    for (index, line) in contents.split('\n').enumerate() {
+        if index > 0 {
+            interpolated.push('\n');
+        }
+
+        // Comments are left alone, a variable mentioned in one needn't
+        // be set.
+        let (line, comment) = match format {
+            Format::Toml => line.split_at(toml_comment_start(line).unwrap_or(line.len())),
+            Format::Json => (line, ""),
+        };
+
+        let mut rest = line;
+        while let Some(start) = rest.find("${") {
+            if rest[..start].ends_with('$') {
+                interpolated.push_str(&rest[..start - 1]);
+                interpolated.push_str("${");
+                rest = &rest[start + 2..];
+                continue;
+            }
+
+            let end = rest[start..].find('}').ok_or_else(|| {
+                (index + 1, String::from("`${` isn't closed by a `}`"))
+            })?;
+            let name = &rest[start + 2..start + end];
+            let value = env::var(name).map_err(|_| {
+                (index + 1, format!("the environment variable {} isn't set", name))
+            })?;
+
+            // Escaped like a JSON string, also valid in a TOML one.
+            let escaped = serde_json::to_string(&value).unwrap();
+            interpolated.push_str(&rest[..start]);
+            interpolated.push_str(&escaped[1..escaped.len() - 1]);
+            rest = &rest[start + end + 1..];
+        }
+        interpolated.push_str(rest);
+        interpolated.push_str(comment);
+    }
+
+    return Ok(interpolated);
+}
+
+/// Where the comment of a TOML line starts, at a `#` outside of any
+/// string.
+fn toml_comment_start(line: &str) -> Option<usize> {
+    let mut quote: Option<char> = None;
+    let mut escaped = false;
+    for (index, c) in line.char_indices() {
+        match quote {
+            Some('"') if escaped => escaped = false,
+            Some('"') if c == '\\' => escaped = true,
+            Some(q) if c == q => quote = None,
+            Some(_) => {}
+            None if c == '"' || c == '\'' => quote = Some(c),
+            None if c == '#' => return Some(index),
+            None => {}
+        }
+    }
+
+    return None;
+}
+
+/// A secret kept in a file, without its trailing newline.
+fn read_secret(path: &str) -> Result<String, String> {
+    let mut secret = String::new();
+    File::open(path)
+        .and_then(|mut f| f.read_to_string(&mut secret))
+        .map_err(|e| format!("Failed to read the secret in {}: {}", path, e))?;
+
+    return Ok(secret.trim_right_matches(|c| c == '\n' || c == '\r').to_owned());
+}
+
+/// The secret `reference` points to, fetched once to check it.
+fn fetch_secret(
+    secrets: &Option<Arc<Secrets>>,
+    reference: &str,
+) -> Result<(Arc<Secret>, String), String> {
+    let secrets = secrets.clone().ok_or_else(|| {
+        format!("there are no `secrets` configured to fetch {} from", reference)
+    })?;
+    let secret = Arc::new(Secret::new(secrets, reference)?);
+    let value = secret.get()?;
+
+    return Ok((secret, value));
+}
+
+/// Where the key of `path`, like `nix.options.cores`, probably is.
+fn key_line(contents: &str, path: &str, format: Format) -> Option<usize> {
+    let key = path.rsplit('.').next().unwrap_or(path);
+    let quoted = format!("\"{}\"", key);
+
+    contents
+        .lines()
+        .position(|line| {
+            let line = line.trim_left();
+            match format {
+                Format::Json => line.contains(&format!("{}:", quoted)) ||
+                    line.contains(&format!("{} :", quoted)),
+                Format::Toml => {
+                    let name = line.trim_left_matches('[').split(|c| c == '=' || c == ']').next();
+                    name.map(|name| {
+                        let name = name.trim();
+                        name == key || name == quoted || name.ends_with(&format!(".{}", key))
+                    }).unwrap_or(false)
+                }
+            }
+        })
+        .map(|index| index + 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use ofborg::test_scratch::TestScratch;
+
+    const JSON: &'static str = r#"{
+  "runner": { "identity": "my-builder", "colour": "blue" },
+  "checkout": { "root": "/var/lib/ofborg/checkout" },
+  "nix": { "system": "x86_64-linux", "remote": "daemon", "build_timeout_seconds": 3600 },
+  "rabbitmq": {
+    "ssl": true,
+    "host": "events.nix.ci",
+    "virtualhost": "ofborg",
+    "username": "ofborg",
+    "password": "secret"
+  }
+}"#;
+
+    const TOML: &'static str = r#"
+[runner]
+identity = "my-builder"
+colour = "blue"
+
+[checkout]
+root = "/var/lib/ofborg/checkout"
+
+[nix]
+system = "x86_64-linux"
+remote = "daemon"
+build_timeout_seconds = 3600
+
+[rabbitmq]
+ssl = true
+host = "events.nix.ci"
+virtualhost = "ofborg"
+username = "ofborg"
+password = "secret"
+"#;
+
+    #[test]
+    fn test_parse_json_and_toml() {
+        for &(contents, format, line) in [(JSON, Format::Json, 2), (TOML, Format::Toml, 4)].iter() {
+            let (config, unknown) = parse("ofborg.conf", contents, format, None).unwrap();
+            assert_eq!(config.runner.identity, "my-builder");
+            assert_eq!(config.nix.build_timeout_seconds, 3600);
+            assert_eq!(
+                unknown,
+                vec![Problem {
+                    file: String::from("ofborg.conf"),
+                    line: Some(line),
+                    message: String::from("unknown key `runner.colour`"),
+                }]
+            );
+        }
+    }
+
+    #[test]
+    fn test_invalid() {
+        let missing = parse("ofborg.toml", "[runner]\nidentity = \"x\"\n", Format::Toml, None)
+            .unwrap_err();
+        assert!(missing.message.contains("missing field"), "{}", missing);
+
+        let mistyped = parse(
+            "ofborg.json",
+            &JSON.replace("3600", "\"an hour\""),
+            Format::Json,
+            None,
+        ).unwrap_err();
+        assert_eq!(mistyped.line, Some(4));
+        assert!(mistyped.message.contains("invalid type"), "{}", mistyped);
+        assert!(format!("{}", mistyped).starts_with("ofborg.json:4: invalid type"));
+    }
+
+    #[test]
+    fn test_interpolate() {
+        env::set_var("OFBORG_TEST_CONFIG_PASSWORD", "hunter\"2");
+        assert_eq!(
+            interpolate(
+                "\"password\": \"${OFBORG_TEST_CONFIG_PASSWORD}\",\n\"x\": \"$${HOME}\"",
+                Format::Json,
+            ),
+            Ok(String::from("\"password\": \"hunter\\\"2\",\n\"x\": \"${HOME}\""))
+        );
+        assert_eq!(
+            interpolate("a\nb = \"${OFBORG_TEST_CONFIG_UNSET}\"", Format::Toml),
+            Err((2, String::from("the environment variable OFBORG_TEST_CONFIG_UNSET isn't set")))
+        );
+        assert_eq!(
+            interpolate("b = \"${OFBORG\"", Format::Toml),
+            Err((1, String::from("`${` isn't closed by a `}`")))
+        );
+        assert_eq!(
+            interpolate(
+                "# b = \"${OFBORG_TEST_CONFIG_UNSET}\"\nc = \"#${OFBORG_TEST_CONFIG_PASSWORD}\" # ${X}",
+                Format::Toml,
+            ),
+            Ok(String::from("# b = \"${OFBORG_TEST_CONFIG_UNSET}\"\nc = \"#hunter\\\"2\" # ${X}"))
+        );
+
+        env::set_var("OFBORG_TEST_CONFIG_IDENTITY", "from-env");
+        let (config, _) = parse(
+            "ofborg.toml",
+            &TOML.replace("\"my-builder\"", "\"${OFBORG_TEST_CONFIG_IDENTITY}\""),
+            Format::Toml,
+            None,
+        ).unwrap();
+        assert_eq!(config.runner.identity, "from-env");
+    }
+
+    #[test]
+    fn test_secret_files() {
+        let password = TestScratch::new_file("config-secret-files-password");
+        {
+            let mut f = File::create(password.path()).unwrap();
+            writeln!(f, "from-a-file").unwrap();
+        }
+
+        let toml = TOML.replace(
+            "password = \"secret\"",
+            &format!(
+                "password_file = \"{}\"\nsigning_key_file = \"{}\"",
+                password.string(),
+                password.string()
+            ),
+        ) +
+            &format!(
+                "\n[github]\ntoken = \"abc\"\nextra_tokens = [\"def\"]\n\
+                 extra_token_files = [\"{}\"]\n\n\
+                 [tracing]\notlp_endpoint = \"http://localhost:4318\"\n\n\
+                 [tracing.header_files]\nx-api-key = \"{}\"\n",
+                password.string(),
+                password.string()
+            );
+        let (config, unknown) = parse("ofborg.toml", &toml, Format::Toml, None).unwrap();
+        assert_eq!(config.rabbitmq.password, "from-a-file");
+        assert_eq!(config.rabbitmq.signing_key, Some(String::from("from-a-file")));
+        assert_eq!(
+            config.github.unwrap().extra_tokens,
+            Some(vec![String::from("def"), String::from("from-a-file")])
+        );
+        assert_eq!(
+            config.tracing.unwrap().headers.unwrap().get("x-api-key"),
+            Some(&String::from("from-a-file"))
+        );
+        assert_eq!(unknown.len(), 1);
+
+        let missing = parse(
+            "ofborg.toml",
+            &TOML.replace("password = \"secret\"", "password_file = \"/nonexistent\""),
+            Format::Toml,
+            None,
+        ).unwrap_err();
+        assert_eq!(missing.line, Some(19));
+        assert!(missing.message.starts_with("Failed to read the secret in /nonexistent"));
+
+        let neither = parse(
+            "ofborg.toml",
+            &TOML.replace("password = \"secret\"", ""),
+            Format::Toml,
+            None,
+        ).unwrap_err();
+        assert_eq!(
+            neither.message,
+            "rabbitmq needs a password, a password_file or a password_secret"
+        );
+
+        let unconfigured = parse(
+            "ofborg.toml",
+            &TOML.replace(
+                "password = \"secret\"",
+                "password_secret = \"vault:secret/data/ofborg#rabbitmq\"",
+            ),
+            Format::Toml,
+            None,
+        ).unwrap_err();
+        assert_eq!(unconfigured.line, Some(19));
+        assert_eq!(
+            unconfigured.message,
+            "there are no `secrets` configured to fetch vault:secret/data/ofborg#rabbitmq from"
+        );
+    }
+
+    #[test]
+    fn test_instances() {
+        let toml = format!(
+            "{}
+[instances.builder-1.runner]
+identity = \"builder-1\"
+
+[instances.builder-darwin.runner]
+identity = \"builder-darwin\"
+
+[instances.builder-darwin.nix]
+system = \"x86_64-darwin\"
+",
+            TOML.replace("colour = \"blue\"\n", "")
+        );
+
+        let (base, unknown) = parse("ofborg.toml", &toml, Format::Toml, None).unwrap();
+        assert_eq!(base.runner.identity, "my-builder");
+        assert_eq!(unknown, vec![]);
+
+        let (darwin, unknown) = parse("ofborg.toml", &toml, Format::Toml, Some("builder-darwin"))
+            .unwrap();
+        assert_eq!(darwin.runner.identity, "builder-darwin");
+        assert_eq!(darwin.nix.system, "x86_64-darwin");
+        assert_eq!(darwin.nix.build_timeout_seconds, 3600);
+        assert_eq!(darwin.rabbitmq.host, "events.nix.ci");
+        assert!(darwin.instances.is_none());
+        assert_eq!(unknown, vec![]);
+
+        let missing = parse("ofborg.toml", &toml, Format::Toml, Some("builder-2")).unwrap_err();
+        assert_eq!(
+            missing.message,
+            "there's no instance builder-2, only builder-1, builder-darwin"
+        );
+
+        let none = parse("ofborg.json", JSON, Format::Json, Some("builder-1")).unwrap_err();
+        assert_eq!(none.message, "there are no instances to pick builder-1 from");
+    }
+
+    #[test]
+    fn test_deprecated_rebuild_label_thresholds() {
+        let toml = format!(
+            "{}\n[rebuild_label_thresholds]\nstaging = [500]\nmaster = [1, 2]\n\n\
+             [[branch_policies]]\nbranch = \"master\"\nauto_build = false\n",
+            TOML
+        );
+        let (config, unknown) = parse("ofborg.toml", &toml, Format::Toml, None).unwrap();
+        assert_eq!(config.rebuild_label_thresholds, None);
+        assert_eq!(
+            config.branch_policies,
+            Some(vec![
+                BranchPolicy {
+                    branch: String::from("master"),
+                    rebuild_label_thresholds: Some(vec![1, 2]),
+                    auto_build: Some(false),
+                    ..BranchPolicy::default()
+                },
+                BranchPolicy {
+                    branch: String::from("staging"),
+                    rebuild_label_thresholds: Some(vec![500]),
+                    ..BranchPolicy::default()
+                },
+            ])
+        );
+        assert_eq!(unknown.len(), 2);
+        assert_eq!(unknown[1].line, Some(21));
+        assert!(unknown[1].message.contains("deprecated"), "{}", unknown[1]);
+    }
+
+    #[test]
+    fn test_checks_api_needs_an_app() {
+        let toml = format!("{}\n[github]\ntoken = \"abc\"\nchecks_api = true\n", TOML);
+        let (config, unknown) = parse("ofborg.toml", &toml, Format::Toml, None).unwrap();
+        assert!(!config.use_checks_api());
+        assert_eq!(unknown.len(), 2);
+        assert_eq!(unknown[1].line, Some(23));
+        assert!(unknown[1].message.starts_with("checks_api needs"), "{}", unknown[1]);
+    }
+
+    #[test]
+    fn test_location_args() {
+        let args = |args: Vec<&str>| args.into_iter().map(String::from).collect();
+
+        assert_eq!(
+            Location::parse_args(args(vec!["--check-config", "/etc/ofborg.toml"])),
+            Some(Location {
+                path: PathBuf::from("/etc/ofborg.toml"),
+                instance: None,
+            })
+        );
+        assert_eq!(
+            Location::parse_args(args(vec!["--instance", "builder-1", "/etc/ofborg.toml"])),
+            Some(Location {
+                path: PathBuf::from("/etc/ofborg.toml"),
+                instance: Some(String::from("builder-1")),
+            })
+        );
+        assert_eq!(
+            Location::parse_args(args(vec!["--once", "--pr", "1", "/etc/ofborg.toml"])),
+            Some(Location {
+                path: PathBuf::from("/etc/ofborg.toml"),
+                instance: None,
+            })
+        );
+        assert_eq!(Location::parse_args(args(vec!["--instance"])), None);
+        assert_eq!(Location::parse_args(args(vec![])), None);
+    }
+
+    #[test]
+    fn test_format_of() {
+        assert_eq!(Format::of(Path::new("/etc/ofborg.toml")), Format::Toml);
+        assert_eq!(Format::of(Path::new("/etc/ofborg.json")), Format::Json);
+        assert_eq!(Format::of(Path::new("/etc/ofborg")), Format::Json);
+    }
 }
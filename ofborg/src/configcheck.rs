@@ -0,0 +1,136 @@
+//! `--check-config`: load and check a configuration, print it as it was
+//! understood with its secrets masked, and tell whether the broker and
+//! optionally GitHub accept their credentials.
+
+use serde_json;
+use ofborg::config;
+use ofborg::easyamqp;
+
+const MASK: &'static str = "********";
+
+/// Check the configuration at `location`, whether it's fit to run with.
+pub fn run(location: &config::Location, check_github: bool) -> bool {
+    let cfg = match location.read() {
+        Ok((cfg, unknown)) => {
+            for problem in unknown {
+                println!("Warning: {}", problem);
+            }
+            cfg
+        }
+        Err(problem) => {
+            println!("Invalid configuration: {}", problem);
+            return false;
+        }
+    };
+
+    match serde_json::to_value(&cfg) {
+        Ok(value) => {
+            println!(
+                "{}",
+                serde_json::to_string_pretty(&masked(value)).unwrap_or(String::new())
+            );
+        }
+        Err(e) => println!("Failed to show the configuration: {:?}", e),
+    }
+
+    let mut fit = true;
+
+    match easyamqp::session_from_config(&cfg.rabbitmq) {
+        Ok(mut session) => {
+            println!("Connected to RabbitMQ at {}", cfg.rabbitmq.host);
+            session.close(200, "Good Bye");
+        }
+        Err(e) => {
+            println!("Failed to connect to RabbitMQ at {}: {:?}", cfg.rabbitmq.host, e);
+            fit = false;
+        }
+    }
+
+    if check_github {
+        if cfg.github.is_none() {
+            println!("No github is configured to check");
+            fit = false;
+        } else {
+            match cfg.github_api().get::<serde_json::Value>("/rate_limit") {
+                Ok(_) => println!("GitHub accepted the credentials"),
+                Err(e) => {
+                    println!("GitHub didn't accept the credentials: {:?}", e);
+                    fit = false;
+                }
+            }
+        }
+    }
+
+    if fit {
+        println!("The configuration is fine");
+    }
+    return fit;
+}
+
+fn is_secret(key: &str) -> bool {
+    key == "password" || key == "token" || key == "extra_tokens" || key.ends_with("_password") ||
+        key.ends_with("_token")
+}
+
+/// The configuration with the values of its secrets masked.
+pub fn masked(value: serde_json::Value) -> serde_json::Value {
+    match value {
+        serde_json::Value::Object(map) => serde_json::Value::Object(
+            map.into_iter()
+                .map(|(key, value)| if is_secret(&key) {
+                    (key, mask(value))
+                } else {
+                    (key, masked(value))
+                })
+                .collect(),
+        ),
+        serde_json::Value::Array(values) => {
+            serde_json::Value::Array(values.into_iter().map(masked).collect())
+        }
+        other => other,
+    }
+}
+
+fn mask(value: serde_json::Value) -> serde_json::Value {
+    match value {
+        serde_json::Value::Null => serde_json::Value::Null,
+        serde_json::Value::Array(values) => {
+            serde_json::Value::Array(values.into_iter().map(mask).collect())
+        }
+        _ => serde_json::Value::String(String::from(MASK)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_masked() {
+        let value: serde_json::Value = serde_json::from_str(
+            r#"{
+  "rabbitmq": { "host": "events.nix.ci", "password": "hunter2", "password_file": null },
+  "github": {
+    "token": "abc",
+    "extra_tokens": ["def", "ghi"],
+    "app": { "private_key_file": "/run/keys/app.pem" }
+  },
+  "webhook": { "secret_file": "/run/keys/webhook" }
+}"#,
+        ).unwrap();
+
+        let expect: serde_json::Value = serde_json::from_str(
+            r#"{
+  "rabbitmq": { "host": "events.nix.ci", "password": "********", "password_file": null },
+  "github": {
+    "token": "********",
+    "extra_tokens": ["********", "********"],
+    "app": { "private_key_file": "/run/keys/app.pem" }
+  },
+  "webhook": { "secret_file": "/run/keys/webhook" }
+}"#,
+        ).unwrap();
+
+        assert_eq!(masked(value), expect);
+    }
+}
@@ -0,0 +1,138 @@
+//! Join up what the services do because of one webhook. The webhook
+//! receiver gives every webhook a correlation ID, and every message
+//! published because of it carries that ID and the GitHub delivery ID
+//! of the webhook which caused it, so their logs, stats and traces can
+//! be found together.
+//!
+//! ofborg's own messages carry them in their `correlation_id` and
+//! `caused_by` fields. GitHub's events, and every message the workers
+//! publish, also carry them in the AMQP `correlation_id` property and
+//! the `x-ofborg-caused-by` header.
+
+use std::ops::Deref;
+use amqp;
+use amqp::protocol::basic::BasicProperties;
+use uuid::Uuid;
+
+pub const CAUSED_BY_HEADER: &'static str = "x-ofborg-caused-by";
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Correlation {
+    pub id: Option<String>,
+    /// The `X-GitHub-Delivery` of the webhook.
+    pub caused_by: Option<String>,
+}
+
+impl Correlation {
+    /// A correlation of a webhook just received.
+    pub fn new(caused_by: Option<String>) -> Correlation {
+        return Correlation {
+            id: Some(format!("{}", Uuid::new_v4().simple())),
+            caused_by: caused_by,
+        };
+    }
+
+    pub fn of(correlation_id: &Option<String>, caused_by: &Option<String>) -> Correlation {
+        return Correlation {
+            id: correlation_id.clone(),
+            caused_by: caused_by.clone(),
+        };
+    }
+
+    /// The correlation of a message, empty if it carries none.
+    pub fn extract(props: &BasicProperties) -> Correlation {
+        let caused_by = match props.headers.as_ref().and_then(
+            |headers| headers.get(CAUSED_BY_HEADER),
+        ) {
+            Some(&amqp::TableEntry::LongString(ref caused_by)) => Some(caused_by.clone()),
+            _ => None,
+        };
+
+        return Correlation {
+            id: props.correlation_id.clone(),
+            caused_by: caused_by,
+        };
+    }
+
+    /// Carry the correlation in the properties of a message about to be
+    /// published, unless it carries its own.
+    pub fn inject(&self, props: &mut BasicProperties) {
+        if props.correlation_id.is_some() || self.id.is_none() {
+            return;
+        }
+
+        props.correlation_id = self.id.clone();
+        if let Some(ref caused_by) = self.caused_by {
+            let mut headers = props.headers.take().unwrap_or(amqp::Table::new());
+            headers.insert(
+                String::from(CAUSED_BY_HEADER),
+                amqp::TableEntry::LongString(caused_by.clone()),
+            );
+            props.headers = Some(headers);
+        }
+    }
+
+    /// Fields to log and trace with.
+    pub fn fields(&self) -> Vec<(&'static str, &str)> {
+        let mut fields = vec![];
+        if let Some(ref id) = self.id {
+            fields.push(("correlation_id", id.as_str()));
+        }
+        if let Some(ref caused_by) = self.caused_by {
+            fields.push(("caused_by", caused_by.as_str()));
+        }
+        return fields;
+    }
+}
+
+/// A GitHub event and the correlation it was published with, for the
+/// tasks consuming events to pass it on in the messages they publish.
+#[derive(Debug)]
+pub struct Correlated<T> {
+    pub correlation: Correlation,
+    pub event: T,
+}
+
+impl<T> Correlated<T> {
+    pub fn new(correlation: Correlation, event: T) -> Correlated<T> {
+        return Correlated {
+            correlation: correlation,
+            event: event,
+        };
+    }
+}
+
+impl<T> From<T> for Correlated<T> {
+    fn from(event: T) -> Correlated<T> {
+        return Correlated::new(Correlation::default(), event);
+    }
+}
+
+impl<T> Deref for Correlated<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.event
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_headers() {
+        let mut props = BasicProperties {
+            content_type: Some("application/json".to_owned()),
+            ..Default::default()
+        };
+        assert_eq!(Correlation::extract(&props), Correlation::default());
+
+        let correlation = Correlation::new(Some(String::from("72d3162e-cc78-11e3-81ab-4c9367dc0958")));
+        correlation.inject(&mut props);
+        assert_eq!(Correlation::extract(&props), correlation);
+
+        Correlation::new(None).inject(&mut props);
+        assert_eq!(Correlation::extract(&props), correlation);
+    }
+}
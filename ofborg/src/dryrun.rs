@@ -0,0 +1,277 @@
+//! Evaluate a branch of a local nixpkgs checkout like a PR, to predict
+//! what the evaluator would say about it without opening one.
+
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::path::PathBuf;
+
+use ofborg::branchpolicy::BranchPolicy;
+use ofborg::checkout::CachedProjectCo;
+use ofborg::maintainers::ImpactedMaintainers;
+use ofborg::nix;
+use ofborg::outpathdiff::{OutPathDiff, OutPaths};
+use ofborg::tagger::{PathsTagger, PkgsAddedRemovedTagger, RebuildTagger, StdenvTagger};
+use ofborg::tasks::massrebuilder::{self, Stdenvs, System};
+
+/// Maintainers aren't pinged about more changed attrs than this.
+const MAX_PINGED_ATTRS: usize = 100;
+
+#[derive(Debug, Default, PartialEq)]
+pub struct Report {
+    pub base: String,
+    pub head: String,
+    pub changed_files: Vec<String>,
+    /// Why the evaluation stopped early, like the base not evaluating.
+    pub failure: Option<String>,
+    /// Systems whose stdenv changes, which rebuilds everything.
+    pub stdenv_changed: Vec<String>,
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+    /// Like `x86_64-linux hello`.
+    pub rebuilds: Vec<String>,
+    /// The checks and whether they passed.
+    pub checks: Vec<(String, bool)>,
+    pub labels_added: Vec<String>,
+    pub labels_removed: Vec<String>,
+    pub maintainers: Vec<String>,
+}
+
+fn system_name(system: &System) -> String {
+    String::from(match system {
+        &System::X8664Linux => "x86_64-linux",
+        &System::X8664Darwin => "x86_64-darwin",
+    })
+}
+
+impl Report {
+    fn label(&mut self, add: Vec<String>, remove: Vec<String>) {
+        self.labels_added.extend(add);
+        self.labels_removed.extend(remove);
+    }
+
+    pub fn text(&self) -> String {
+        let mut lines: Vec<String> = vec![
+            format!("Evaluation of {} merged into {}", self.head, self.base),
+            String::new(),
+        ];
+
+        if let Some(ref failure) = self.failure {
+            lines.push(format!("Failed: {}", failure));
+            return lines.join("\n") + "\n";
+        }
+
+        lines.push(String::from("Checks:"));
+        for &(ref name, passed) in self.checks.iter() {
+            lines.push(format!("  {} {}", if passed { "pass" } else { "FAIL" }, name));
+        }
+        lines.push(String::new());
+
+        if self.stdenv_changed.len() > 0 {
+            lines.push(format!(
+                "The stdenv changes on {}, everything rebuilds.",
+                self.stdenv_changed.join(", ")
+            ));
+        } else {
+            lines.push(format!("Changed outputs: {}", self.rebuilds.len()));
+            lines.extend(self.rebuilds.iter().map(|r| format!("  {}", r)));
+        }
+        if self.added.len() > 0 {
+            lines.push(format!("Added: {}", self.added.join(", ")));
+        }
+        if self.removed.len() > 0 {
+            lines.push(format!("Removed: {}", self.removed.join(", ")));
+        }
+        lines.push(String::new());
+
+        lines.push(format!("Labels it would add: {}", self.labels_added.join(", ")));
+        lines.push(format!("Labels it would remove: {}", self.labels_removed.join(", ")));
+        lines.push(format!(
+            "Maintainers it would ping: {}",
+            self.maintainers
+                .iter()
+                .map(|m| format!("@{}", m))
+                .collect::<Vec<String>>()
+                .join(", ")
+        ));
+
+        return lines.join("\n") + "\n";
+    }
+}
+
+/// Evaluate `head` merged into `base` in `co`, which checks out the
+/// local repository, like the evaluator evaluates a PR.
+pub fn run(
+    nix: &nix::Nix,
+    co: &CachedProjectCo,
+    base: &str,
+    head: &str,
+    tag_paths: HashMap<String, Vec<String>>,
+    policy: &BranchPolicy,
+) -> Report {
+    let mut report = Report {
+        base: base.to_owned(),
+        head: head.to_owned(),
+        ..Default::default()
+    };
+
+    let refpath = match co.checkout_origin_ref(OsStr::new(base)) {
+        Ok(refpath) => PathBuf::from(refpath),
+        Err(e) => {
+            report.failure = Some(format!("checking out {} failed: {}", base, e));
+            return report;
+        }
+    };
+
+    // Branches of the local repository are branches of origin in the
+    // checkout, anything else is a commit.
+    let origin_head = format!("origin/{}", head);
+    let head = if co.commit_exists(OsStr::new(&origin_head)) {
+        origin_head
+    } else {
+        head.to_owned()
+    };
+
+    let mut stdenvs = Stdenvs::new(nix.clone(), refpath.clone());
+    stdenvs.identify_before();
+    let mut rebuildsniff = OutPathDiff::new(nix.clone(), refpath.clone());
+    if let Err(_) = rebuildsniff.find_before() {
+        report.failure = Some(format!("{} doesn't evaluate", base));
+        return report;
+    }
+
+    report.changed_files = co.files_changed_from_head(&head).unwrap_or(vec![]);
+    let mut paths_tagger = PathsTagger::new(tag_paths);
+    for path in report.changed_files.iter() {
+        paths_tagger.path_changed(path);
+    }
+    report.label(paths_tagger.tags_to_add(), paths_tagger.tags_to_remove());
+
+    if let Err(e) = co.merge_commit(OsStr::new(&head)) {
+        report.failure = Some(format!("merging {} failed: {}", head, e));
+        return report;
+    }
+
+    stdenvs.identify_after();
+    let treewide = !stdenvs.are_same();
+    report.stdenv_changed = stdenvs.changed().iter().map(system_name).collect();
+    if !treewide {
+        if let Err(_) = rebuildsniff.find_after() {
+            report.failure = Some(format!("{} doesn't evaluate", head));
+            return report;
+        }
+    }
+
+    let checks = massrebuilder::eval_checks(nix, &report.changed_files);
+    for check in checks.iter().filter(|check| policy.runs_check(&check.short_name())) {
+        report.checks.push((check.name(), check.execute(&refpath).is_ok()));
+    }
+    let meta_check = OutPaths::new(nix.clone(), refpath.clone(), true).find();
+    report.checks.push((
+        String::from("grahamcofborg-eval-check-meta"),
+        meta_check.is_ok(),
+    ));
+    if report.checks.iter().any(|&(_, passed)| !passed) {
+        return report;
+    }
+
+    let mut stdenv_tagger = StdenvTagger::new();
+    if treewide {
+        stdenv_tagger.changed(stdenvs.changed());
+    }
+    report.label(stdenv_tagger.tags_to_add(), stdenv_tagger.tags_to_remove());
+
+    if let Some((removed, added)) = rebuildsniff.package_diff() {
+        report.removed = removed.iter().map(|p| p.package.clone()).collect();
+        report.added = added.iter().map(|p| p.package.clone()).collect();
+        let mut tagger = PkgsAddedRemovedTagger::new();
+        tagger.changed(removed, added);
+        report.label(tagger.tags_to_add(), tagger.tags_to_remove());
+    }
+
+    let mut rebuild_tagger = match policy.rebuild_label_thresholds {
+        Some(ref thresholds) => RebuildTagger::with_thresholds(thresholds.clone()),
+        None => RebuildTagger::new(),
+    };
+    let mut changed_packages: Vec<String> = vec![];
+    if treewide {
+        rebuild_tagger.treewide(stdenvs.changed());
+    } else if let Some(attrs) = rebuildsniff.calculate_rebuild() {
+        report.rebuilds = attrs
+            .iter()
+            .map(|attr| format!("{} {}", attr.architecture, attr.package))
+            .collect();
+        changed_packages = attrs.iter().map(|attr| attr.package.clone()).collect();
+        changed_packages.sort();
+        changed_packages.dedup();
+        rebuild_tagger.parse_attrs(attrs);
+    }
+    report.label(rebuild_tagger.tags_to_add(), rebuild_tagger.tags_to_remove());
+
+    if changed_packages.len() > 0 && changed_packages.len() <= MAX_PINGED_ATTRS {
+        if let Ok(impacted) = ImpactedMaintainers::calculate(nix, &refpath, &changed_packages) {
+            report.maintainers = impacted.maintainers();
+        }
+    }
+
+    return report;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_text() {
+        let report = Report {
+            base: String::from("master"),
+            head: String::from("origin/my-branch"),
+            changed_files: vec![String::from("pkgs/tools/misc/hello/default.nix")],
+            checks: vec![
+                (String::from("grahamcofborg-eval-package-list"), true),
+                (String::from("grahamcofborg-eval-check-meta"), true),
+            ],
+            rebuilds: vec![
+                String::from("x86_64-linux hello"),
+                String::from("x86_64-darwin hello"),
+            ],
+            added: vec![String::from("hello-unfree")],
+            labels_added: vec![
+                String::from("10.rebuild-darwin: 1-10"),
+                String::from("10.rebuild-linux: 1-10"),
+            ],
+            labels_removed: vec![String::from("10.rebuild-linux: 0")],
+            maintainers: vec![String::from("someone")],
+            ..Default::default()
+        };
+
+        assert_eq!(
+            report.text(),
+            "Evaluation of origin/my-branch merged into master
+
+Checks:
+  pass grahamcofborg-eval-package-list
+  pass grahamcofborg-eval-check-meta
+
+Changed outputs: 2
+  x86_64-linux hello
+  x86_64-darwin hello
+Added: hello-unfree
+
+Labels it would add: 10.rebuild-darwin: 1-10, 10.rebuild-linux: 1-10
+Labels it would remove: 10.rebuild-linux: 0
+Maintainers it would ping: @someone
+"
+        );
+
+        let failed = Report {
+            base: String::from("master"),
+            head: String::from("abc"),
+            failure: Some(String::from("master doesn't evaluate")),
+            ..Default::default()
+        };
+        assert_eq!(
+            failed.text(),
+            "Evaluation of abc merged into master\n\nFailed: master doesn't evaluate\n"
+        );
+    }
+}
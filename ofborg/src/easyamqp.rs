@@ -1,8 +1,12 @@
 
 use ofborg;
 use ofborg::config::RabbitMQConfig;
+use ofborg::message::envelope::{Encoding, Format};
+use ofborg::msgsign::Signer;
+use ofborg::worker::QueueMsg;
 use amqp;
 use amqp::Basic;
+use amqp::protocol::basic::BasicProperties;
 
 pub struct ConsumeConfig {
     /// Specifies the name of the queue to consume from.
@@ -303,7 +307,7 @@ pub fn session_from_config(config: &RabbitMQConfig) -> Result<amqp::Session, amq
         },
         vhost: config.virtualhost.clone().unwrap_or("/".to_owned()),
         login: config.username.clone(),
-        password: config.password.clone(),
+        password: config.current_password(),
         scheme: scheme,
         properties: properties,
         ..amqp::Options::default()
@@ -315,6 +319,57 @@ pub fn session_from_config(config: &RabbitMQConfig) -> Result<amqp::Session, amq
     return Ok(session);
 }
 
+/// Properties of a JSON message which survives a broker restart.
+pub fn persistent_json() -> BasicProperties {
+    return BasicProperties {
+        content_type: Some("application/json".to_owned()),
+        delivery_mode: Some(2), // persistent
+        ..Default::default()
+    };
+}
+
+/// Like `persistent_json`, for a message encoded with
+/// `envelope::to_vec`.
+pub fn persistent_message() -> BasicProperties {
+    let mut props = persistent_json();
+    props.content_type = Some(Format::published(Encoding::Json).content_type().to_owned());
+    return props;
+}
+
+/// Arguments of a queue whose messages are taken by their `priority`,
+/// from 0 up to `max`.
+pub fn max_priority(max: u8) -> amqp::Table {
+    let mut arguments = amqp::Table::new();
+    arguments.insert(
+        "x-max-priority".to_owned(),
+        amqp::TableEntry::LongInt(max as i32),
+    );
+    return arguments;
+}
+
+/// Publish `msg`, signed if there is a `signer`, see `msgsign`.
+pub fn publish(
+    channel: &mut amqp::Channel,
+    signer: &Option<Signer>,
+    msg: QueueMsg,
+) -> Result<(), amqp::AMQPError> {
+    let exchange = msg.exchange.unwrap_or(String::new());
+    let routing_key = msg.routing_key.unwrap_or(String::new());
+    let mut props = msg.properties.unwrap_or(BasicProperties { ..Default::default() });
+    if let Some(ref signer) = *signer {
+        signer.sign(&mut props, &exchange, &routing_key, &msg.content);
+    }
+
+    return channel.basic_publish(
+        exchange,
+        routing_key,
+        msg.mandatory,
+        msg.immediate,
+        props,
+        msg.content,
+    );
+}
+
 pub trait TypedWrappers {
     fn consume<T>(&mut self, callback: T, config: ConsumeConfig) -> Result<String, amqp::AMQPError>
     where
@@ -0,0 +1,157 @@
+use std::fs;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+use md5;
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use serde_json;
+use ofborg;
+
+/// Evaluation outcomes are a pure function of the evaluated git tree,
+/// so they are stored keyed by the tree hash and the check's name.
+/// Force pushes which don't change the tree, and re-requested evals,
+/// can reuse them.
+pub struct EvalCache {
+    root: PathBuf,
+}
+
+impl EvalCache {
+    pub fn new(root: &Path) -> EvalCache {
+        EvalCache { root: root.to_path_buf() }
+    }
+
+    pub fn get<T>(&self, tree: &str, check: &str) -> Option<T>
+    where
+        T: DeserializeOwned,
+    {
+        let path = self.path_for(tree, check)?;
+        let mut contents = String::new();
+        File::open(&path).ok()?.read_to_string(&mut contents).ok()?;
+
+        match serde_json::from_str(&contents) {
+            Ok(value) => {
+                debug!("Eval cache hit for {} in {}", check, tree);
+                Some(value)
+            }
+            Err(e) => {
+                warn!("Ignoring corrupt eval cache entry {:?}: {:?}", path, e);
+                None
+            }
+        }
+    }
+
+    pub fn put<T>(&self, tree: &str, check: &str, value: &T)
+    where
+        T: Serialize,
+    {
+        let path = match self.path_for(tree, check) {
+            Some(path) => path,
+            None => {
+                warn!("Not caching {} for invalid tree {:?}", check, tree);
+                return;
+            }
+        };
+
+        // Write to a temporary file and rename it in place so a
+        // concurrent reader never sees a partial entry.
+        let tmp = path.with_extension("tmp");
+        let result = fs::create_dir_all(path.parent().unwrap())
+            .and_then(|_| File::create(&tmp))
+            .and_then(|mut file| {
+                file.write_all(serde_json::to_string(value).unwrap().as_bytes())
+            })
+            .and_then(|_| fs::rename(&tmp, &path));
+
+        if let Err(e) = result {
+            warn!("Failed to write eval cache entry {:?}: {:?}", path, e);
+        }
+    }
+
+    /// Delete entries which were written more than `max_age` ago.
+    pub fn prune(&self, max_age: Duration) {
+        let now = SystemTime::now();
+
+        let trees = match fs::read_dir(&self.root) {
+            Ok(trees) => trees,
+            Err(_) => return,
+        };
+
+        for tree in trees.filter_map(|entry| entry.ok()) {
+            let modified = tree.metadata().and_then(|meta| meta.modified());
+            let expired = match modified {
+                Ok(modified) => now.duration_since(modified).map(|age| age > max_age).unwrap_or(false),
+                Err(_) => false,
+            };
+
+            if expired {
+                info!("Pruning eval cache entry {:?}", tree.path());
+                if let Err(e) = fs::remove_dir_all(tree.path()) {
+                    warn!("Failed to prune {:?}: {:?}", tree.path(), e);
+                }
+            }
+        }
+    }
+
+    fn path_for(&self, tree: &str, check: &str) -> Option<PathBuf> {
+        if tree.len() != 40 || !tree.chars().all(|c| c.is_digit(16)) {
+            return None;
+        }
+
+        // The cache is invalidated on upgrades, as ofborg's own
+        // expressions (like outpaths.nix) may change what is computed.
+        let key = format!("{}-{}", ofborg::VERSION, check);
+
+        let mut path = self.root.clone();
+        path.push(tree);
+        path.push(format!("{:x}.json", md5::compute(&key)));
+        return Some(path);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ofborg::test_scratch::TestScratch;
+
+    const TREE: &'static str = "4b825dc642cb6eb9a060e54bf8d69288fbee4904";
+
+    #[test]
+    fn test_roundtrip() {
+        let p = TestScratch::new_dir("eval-cache-roundtrip");
+        let cache = EvalCache::new(&p.path());
+
+        assert_eq!(cache.get::<Vec<String>>(TREE, "outpaths"), None);
+
+        cache.put(TREE, "outpaths", &vec!["foo".to_owned()]);
+        assert_eq!(
+            cache.get::<Vec<String>>(TREE, "outpaths"),
+            Some(vec!["foo".to_owned()])
+        );
+        assert_eq!(cache.get::<Vec<String>>(TREE, "check-meta"), None);
+    }
+
+    #[test]
+    fn test_invalid_tree() {
+        let p = TestScratch::new_dir("eval-cache-invalid-tree");
+        let cache = EvalCache::new(&p.path());
+
+        cache.put("../../etc", "outpaths", &true);
+        assert_eq!(cache.get::<bool>("../../etc", "outpaths"), None);
+    }
+
+    #[test]
+    fn test_prune() {
+        let p = TestScratch::new_dir("eval-cache-prune");
+        let cache = EvalCache::new(&p.path());
+
+        cache.put(TREE, "outpaths", &true);
+        cache.prune(Duration::from_secs(3600));
+        assert_eq!(cache.get::<bool>(TREE, "outpaths"), Some(true));
+
+        ::std::thread::sleep(Duration::from_millis(10));
+        cache.prune(Duration::from_millis(1));
+        assert_eq!(cache.get::<bool>(TREE, "outpaths"), None);
+    }
+}
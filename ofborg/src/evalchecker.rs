@@ -26,6 +26,10 @@ impl EvalChecker {
         format!("grahamcofborg-eval-{}", self.name)
     }
 
+    pub fn short_name(&self) -> String {
+        self.name.clone()
+    }
+
     pub fn execute(&self, path: &Path) -> Result<File, File> {
         self.nix.safely(self.op.clone(), path, self.args.clone(), false)
     }
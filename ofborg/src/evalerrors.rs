@@ -0,0 +1,212 @@
+use std::collections::HashMap;
+
+/// An evaluation error which nix attributed to a specific location.
+#[derive(Debug, PartialEq, Clone)]
+pub struct EvalError {
+    pub path: String,
+    pub line: u64,
+    pub column: u64,
+    pub message: String,
+}
+
+/// Find the `error: ... at /path/to/file.nix:line:col` lines of nix's
+/// output and make their paths relative to the checkout. Errors
+/// outside the checkout, or without a location, are dropped.
+///
+/// Nix 2.4 and later put the location on a line of its own after the
+/// message, `at /path/to/file.nix:line:col:`, which is understood too.
+pub fn parse(output: &str, checkout: &str) -> Vec<EvalError> {
+    let root = format!("{}/", checkout.trim_right_matches('/'));
+
+    let mut errors: Vec<EvalError> = vec![];
+    // The message of an error whose location may follow.
+    let mut pending: Option<String> = None;
+    for line in output.lines().map(|line| line.trim()) {
+        if line.starts_with("error:") {
+            pending = None;
+            match parse_line(line, &root) {
+                Some(error) => errors.push(error),
+                None => {
+                    let message = clean_message(&line["error:".len()..]);
+                    if message.len() > 0 {
+                        pending = Some(message);
+                    }
+                }
+            }
+        } else if line.starts_with("at /") {
+            if let Some(message) = pending.take() {
+                errors.extend(parse_location(&line["at ".len()..], &root, message));
+            }
+        } else if line.starts_with('\u{2026}') {
+            // A trace, `… while evaluating`, whose location isn't the
+            // error's.
+            pending = None;
+        }
+    }
+    errors.dedup();
+
+    return errors;
+}
+
+/// The errors on lines in the hunks of the PR's diff, which can be
+/// annotated, and the others.
+pub fn partition_by_hunks(
+    errors: Vec<EvalError>,
+    hunks: &HashMap<String, Vec<(u64, u64)>>,
+) -> (Vec<EvalError>, Vec<EvalError>) {
+    return errors.into_iter().partition(|error| {
+        hunks.get(&error.path).map_or(false, |ranges| {
+            ranges.iter().any(|&(first, last)| {
+                first <= error.line && error.line <= last
+            })
+        })
+    });
+}
+
+/// The errors as a list, for a status' summary.
+pub fn summary(errors: &Vec<EvalError>) -> String {
+    return errors
+        .iter()
+        .map(|error| {
+            format!("- `{}:{}:{}`: {}", error.path, error.line, error.column, error.message)
+        })
+        .collect::<Vec<String>>()
+        .join("\n");
+}
+
+fn parse_line(line: &str, root: &str) -> Option<EvalError> {
+    let at = line.rfind(" at /")?;
+    let (message, location) = line.split_at(at);
+
+    parse_location(
+        &location[" at ".len()..],
+        root,
+        clean_message(&message["error:".len()..]),
+    )
+}
+
+fn parse_location(location: &str, root: &str, message: String) -> Option<EvalError> {
+    let location = location.trim_right_matches(|c| c == ':' || c == ',');
+
+    let mut parts = location.rsplitn(3, ':');
+    let column: u64 = parts.next()?.parse().ok()?;
+    let line_number: u64 = parts.next()?.parse().ok()?;
+    let path = parts.next()?;
+
+    if !path.starts_with(root) {
+        return None;
+    }
+
+    Some(EvalError {
+        path: path[root.len()..].to_owned(),
+        line: line_number,
+        column: column,
+        message: message,
+    })
+}
+
+fn clean_message(message: &str) -> String {
+    message.trim().trim_right_matches(',').to_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_errors() {
+        let output = "
+while evaluating the attribute 'hello' at /var/lib/co/pkgs/top-level/all-packages.nix:100:3:
+error: undefined variable 'fetchurll' at /var/lib/co/pkgs/applications/misc/hello/default.nix:5:9
+error: attribute 'foo' missing, at /var/lib/co/pkgs/top-level/all-packages.nix:200:12
+error: while evaluating 'callPackage' at /nix/store/abc-lib/customisation.nix:3:1
+error: something without a location
+";
+
+        assert_eq!(
+            parse(output, "/var/lib/co"),
+            vec![
+                EvalError {
+                    path: "pkgs/applications/misc/hello/default.nix".to_owned(),
+                    line: 5,
+                    column: 9,
+                    message: "undefined variable 'fetchurll'".to_owned(),
+                },
+                EvalError {
+                    path: "pkgs/top-level/all-packages.nix".to_owned(),
+                    line: 200,
+                    column: 12,
+                    message: "attribute 'foo' missing".to_owned(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_partition_by_hunks() {
+        let error = |path: &str, line: u64| {
+            EvalError {
+                path: path.to_owned(),
+                line: line,
+                column: 1,
+                message: "undefined variable 'fetchurll'".to_owned(),
+            }
+        };
+        let mut hunks: HashMap<String, Vec<(u64, u64)>> = HashMap::new();
+        hunks.insert("default.nix".to_owned(), vec![(1, 3), (10, 12)]);
+
+        let (inside, outside) = partition_by_hunks(
+            vec![
+                error("default.nix", 3),
+                error("default.nix", 5),
+                error("default.nix", 10),
+                error("other.nix", 1),
+            ],
+            &hunks,
+        );
+        assert_eq!(inside, vec![error("default.nix", 3), error("default.nix", 10)]);
+        assert_eq!(outside, vec![error("default.nix", 5), error("other.nix", 1)]);
+        assert_eq!(
+            summary(&outside),
+            "- `default.nix:5:1`: undefined variable 'fetchurll'\n\
+             - `other.nix:1:1`: undefined variable 'fetchurll'"
+        );
+    }
+
+    #[test]
+    fn test_parse_new_style_errors() {
+        let output = "
+error:
+       \u{2026} while evaluating the attribute 'hello'
+
+         at /var/lib/co/pkgs/top-level/all-packages.nix:100:3:
+
+           99|
+          100|   hello = callPackage ../applications/misc/hello { };
+             |   ^
+
+       error: undefined variable 'fetchurll'
+
+       at /var/lib/co/pkgs/applications/misc/hello/default.nix:5:9:
+
+            4|
+            5|   src = fetchurll {
+             |         ^
+error: attribute 'foo' missing
+
+       at /nix/store/abc-lib/customisation.nix:3:1:
+";
+
+        assert_eq!(
+            parse(output, "/var/lib/co"),
+            vec![
+                EvalError {
+                    path: "pkgs/applications/misc/hello/default.nix".to_owned(),
+                    line: 5,
+                    column: 9,
+                    message: "undefined variable 'fetchurll'".to_owned(),
+                },
+            ]
+        );
+    }
+}
@@ -0,0 +1,104 @@
+use std::collections::{BTreeMap, BTreeSet};
+use ofborg::outpathdiff::PackageArch;
+
+/// Attrs which aren't in a nested package set.
+pub const TOP_LEVEL: &'static str = "top-level";
+
+/// The changed packages of a PR, broken down by the package set they
+/// belong to, so reviewers can route the PR to the right team.
+#[derive(Debug, PartialEq)]
+pub struct EvalSummary {
+    pub head_sha: String,
+    pub package_sets: BTreeMap<String, u64>,
+}
+
+/// The package set an attr belongs to, with versions removed so
+/// `python27Packages.foo` and `python36Packages.foo` are both in
+/// `pythonPackages`.
+pub fn package_set(attr: &str) -> String {
+    let mut parts = attr.splitn(2, '.');
+    let first = parts.next().unwrap_or("");
+    if parts.next().is_none() {
+        return String::from(TOP_LEVEL);
+    }
+
+    return first
+        .chars()
+        .filter(|c| !c.is_digit(10) && *c != '_')
+        .collect();
+}
+
+impl EvalSummary {
+    pub fn new(head_sha: String, attrs: &Vec<PackageArch>) -> EvalSummary {
+        let packages: BTreeSet<&str> = attrs.iter().map(|attr| attr.package.as_ref()).collect();
+
+        let mut package_sets: BTreeMap<String, u64> = BTreeMap::new();
+        for package in packages {
+            *package_sets.entry(package_set(package)).or_insert(0) += 1;
+        }
+
+        return EvalSummary {
+            head_sha: head_sha,
+            package_sets: package_sets,
+        };
+    }
+
+    pub fn render(&self) -> String {
+        let mut sets: Vec<(&String, &u64)> = self.package_sets.iter().collect();
+        sets.sort_by(|a, b| b.1.cmp(a.1).then(a.0.cmp(b.0)));
+
+        let mut lines: Vec<String> = vec![
+            String::from("| Package set | Changed |"),
+            String::from("| --- | ---: |"),
+        ];
+        lines.extend(sets.into_iter().map(|(set, count)| {
+            format!("| {} | {} |", set, count)
+        }));
+
+        return lines.join("\n");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn attr(arch: &str, package: &str) -> PackageArch {
+        PackageArch {
+            package: package.to_owned(),
+            architecture: arch.to_owned(),
+        }
+    }
+
+    #[test]
+    fn test_package_set() {
+        assert_eq!(package_set("hello"), "top-level");
+        assert_eq!(package_set("python27Packages.requests"), "pythonPackages");
+        assert_eq!(package_set("linuxPackages_4_14.zfs"), "linuxPackages");
+        assert_eq!(package_set("haskellPackages.lens"), "haskellPackages");
+        assert_eq!(package_set("nodePackages.npm.out"), "nodePackages");
+    }
+
+    #[test]
+    fn test_render() {
+        let summary = EvalSummary::new(
+            String::from("abc123"),
+            &vec![
+                attr("x86_64-linux", "hello"),
+                attr("x86_64-darwin", "hello"),
+                attr("x86_64-linux", "python27Packages.requests"),
+                attr("x86_64-linux", "python36Packages.requests"),
+                attr("x86_64-linux", "haskellPackages.lens"),
+            ],
+        );
+
+        assert_eq!(
+            summary.render(),
+            "| Package set | Changed |
+| --- | ---: |
+| pythonPackages | 2 |
+| haskellPackages | 1 |
+| top-level | 1 |"
+        );
+    }
+}
@@ -0,0 +1,102 @@
+//! Checking repositories which are flakes: `nix flake check` to
+//! evaluate the whole flake, then each of its `checks.<system>.*`
+//! built on its own so it can be reported on its own.
+
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+use serde_json;
+use ofborg::nix::Nix;
+
+/// Evaluate every output of the flake, without building anything.
+pub fn evaluate(nix: &Nix, flake: &Path) -> Result<File, File> {
+    let cmd = nix.flake_command(
+        flake,
+        vec![
+            String::from("flake"),
+            String::from("check"),
+            String::from("--no-build"),
+            String::from("--keep-going"),
+            String::from("--show-trace"),
+        ],
+    );
+
+    return nix.run(cmd, true);
+}
+
+/// The names of the flake's checks for `system`.
+pub fn check_names(nix: &Nix, flake: &Path, system: &str) -> Result<Vec<String>, String> {
+    let cmd = nix.flake_command(
+        flake,
+        vec![
+            String::from("eval"),
+            String::from("--json"),
+            format!(".#checks.{}", system),
+            String::from("--apply"),
+            String::from("builtins.attrNames"),
+        ],
+    );
+
+    let mut output = String::new();
+    match nix.run(cmd, true) {
+        Ok(mut out) => {
+            out.read_to_string(&mut output).map_err(
+                |e| format!("{:?}", e),
+            )?;
+        }
+        Err(mut out) => {
+            out.read_to_string(&mut output).map_err(
+                |e| format!("{:?}", e),
+            )?;
+            // A flake without checks for the system has none to run.
+            if output.contains("does not provide attribute") {
+                return Ok(vec![]);
+            }
+            return Err(output);
+        }
+    }
+
+    return parse_check_names(&output);
+}
+
+/// The output also carries stderr, the names are its last line.
+fn parse_check_names(output: &str) -> Result<Vec<String>, String> {
+    let json = output.lines().filter(|line| line.starts_with('[')).last();
+    return match json {
+        Some(json) => serde_json::from_str(json).map_err(|e| format!("{:?}", e)),
+        None => Err(format!("No check names in: {}", output)),
+    };
+}
+
+pub fn build(nix: &Nix, flake: &Path, system: &str, name: &str) -> Result<File, File> {
+    let cmd = nix.flake_command(
+        flake,
+        vec![
+            String::from("build"),
+            String::from("--no-link"),
+            String::from("--print-build-logs"),
+            format!(".#checks.{}.\"{}\"", system, name),
+        ],
+    );
+
+    return nix.run(cmd, true);
+}
+
+/// The check's commit status context.
+pub fn context(name: &str) -> String {
+    format!("grahamcofborg-flake-check-{}", name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_check_names() {
+        assert_eq!(
+            parse_check_names("warning: Git tree is dirty\n[\"format\",\"tests\"]\n"),
+            Ok(vec![String::from("format"), String::from("tests")])
+        );
+        assert!(parse_check_names("error: oops").is_err());
+    }
+}
@@ -0,0 +1,158 @@
+use std::path::Path;
+use std::process::Command;
+use ofborg::config::FormatCheckConfig;
+
+/// A formatter or linter, like `editorconfig-checker` or
+/// `nixpkgs-fmt --check`, run against the files a PR changes.
+pub struct FormatCheck {
+    name: String,
+    command: Vec<String>,
+    extensions: Option<Vec<String>>,
+}
+
+/// A problem a formatter reported at a specific location.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Violation {
+    pub path: String,
+    pub line: u64,
+    pub message: String,
+}
+
+impl FormatCheck {
+    pub fn new(config: &FormatCheckConfig) -> FormatCheck {
+        FormatCheck {
+            name: config.name.clone(),
+            command: config.command.clone(),
+            extensions: config.extensions.clone(),
+        }
+    }
+
+    pub fn name(&self) -> String {
+        format!("grahamcofborg-format-{}", self.name)
+    }
+
+    pub fn cli_cmd(&self) -> String {
+        self.command.join(" ")
+    }
+
+    /// The changed files this check applies to. Deleted files are
+    /// skipped, as there is nothing left to check.
+    pub fn files_to_check(&self, checkout: &Path, changed: &Vec<String>) -> Vec<String> {
+        changed
+            .iter()
+            .filter(|file| match self.extensions {
+                Some(ref extensions) => extensions.iter().any(|ext| file.ends_with(ext)),
+                None => true,
+            })
+            .filter(|file| checkout.join(file).is_file())
+            .cloned()
+            .collect()
+    }
+
+    /// Run the check, returning its output if it reports problems.
+    pub fn run(&self, checkout: &Path, files: &Vec<String>) -> Result<(), String> {
+        let (program, args) = match self.command.split_first() {
+            Some(split) => split,
+            None => return Err(String::from("No command configured")),
+        };
+
+        let result = Command::new(program)
+            .args(args)
+            .args(files)
+            .current_dir(checkout)
+            .output()
+            .map_err(|e| format!("Failed to run {}: {:?}", program, e))?;
+
+        if result.status.success() {
+            return Ok(());
+        } else {
+            return Err(format!(
+                "{}{}",
+                String::from_utf8_lossy(&result.stdout),
+                String::from_utf8_lossy(&result.stderr)
+            ));
+        }
+    }
+}
+
+/// Find the `path:line: message` and `path:line:column: message` lines
+/// in a formatter's output. Lines without a location are dropped.
+pub fn parse_violations(output: &str) -> Vec<Violation> {
+    output
+        .lines()
+        .filter_map(|line| {
+            let mut parts = line.splitn(3, ':');
+            let path = parts.next()?.trim();
+            let line_number: u64 = parts.next()?.trim().parse().ok()?;
+            let rest = parts.next()?;
+
+            // Skip over the column, if there is one.
+            let message = match rest.find(':') {
+                Some(pos) if rest[..pos].trim().parse::<u64>().is_ok() => &rest[pos + 1..],
+                _ => rest,
+            };
+
+            if path.len() == 0 {
+                return None;
+            }
+
+            Some(Violation {
+                path: path.to_owned(),
+                line: line_number,
+                message: message.trim().to_owned(),
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_violations() {
+        let output = "
+pkgs/tools/misc/hello/default.nix:12:5: error: Wrong indent style found (tabs instead of spaces)
+pkgs/tools/misc/hello/default.nix:20: Trailing whitespace
+2 errors found
+";
+
+        assert_eq!(
+            parse_violations(output),
+            vec![
+                Violation {
+                    path: "pkgs/tools/misc/hello/default.nix".to_owned(),
+                    line: 12,
+                    message: "error: Wrong indent style found (tabs instead of spaces)".to_owned(),
+                },
+                Violation {
+                    path: "pkgs/tools/misc/hello/default.nix".to_owned(),
+                    line: 20,
+                    message: "Trailing whitespace".to_owned(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_files_to_check() {
+        let check = FormatCheck::new(&FormatCheckConfig {
+            name: "nixpkgs-fmt".to_owned(),
+            command: vec!["nixpkgs-fmt".to_owned(), "--check".to_owned()],
+            extensions: Some(vec![".nix".to_owned()]),
+        });
+
+        let checkout = Path::new(env!("CARGO_MANIFEST_DIR")).join("test-srcs/eval");
+        assert_eq!(
+            check.files_to_check(
+                &checkout,
+                &vec![
+                    "default.nix".to_owned(),
+                    "deleted.nix".to_owned(),
+                    "README.md".to_owned(),
+                ],
+            ),
+            vec!["default.nix".to_owned()]
+        );
+    }
+}
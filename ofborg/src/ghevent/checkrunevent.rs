@@ -0,0 +1,85 @@
+use ofborg::ghevent::{Repository, PullRequestRef, User};
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct CheckRunEvent {
+    pub action: CheckRunAction,
+    pub check_run: CheckRun,
+    pub repository: Repository,
+    pub sender: User,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[serde(rename_all="snake_case")]
+pub enum CheckRunAction {
+    Created,
+    Completed,
+    Rerequested,
+    RequestedAction,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct CheckRun {
+    pub name: String,
+    pub head_sha: String,
+    /// What we stored to find the task behind the check run again.
+    #[serde(default)]
+    pub external_id: Option<String>,
+    pub pull_requests: Vec<CheckRunPullRequest>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct CheckSuiteEvent {
+    pub action: CheckSuiteAction,
+    pub check_suite: CheckSuite,
+    pub repository: Repository,
+    pub sender: User,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[serde(rename_all="snake_case")]
+pub enum CheckSuiteAction {
+    Completed,
+    Requested,
+    Rerequested,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct CheckSuite {
+    pub head_sha: String,
+    pub pull_requests: Vec<CheckRunPullRequest>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct CheckRunPullRequest {
+    pub number: u64,
+    pub head: PullRequestRef,
+    pub base: PullRequestRef,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json;
+
+    #[test]
+    fn test_parse_check_run_event() {
+        let data = include_str!("../../test-srcs/events/check-run-rerequested.json");
+
+        let event: CheckRunEvent = serde_json::from_str(&data.to_string())
+            .expect("Should properly deserialize");
+        assert_eq!(event.action, CheckRunAction::Rerequested);
+        assert_eq!(event.check_run.name, "grahamcofborg-eval");
+        assert_eq!(event.check_run.pull_requests[0].number, 33299);
+        assert_eq!(event.check_run.pull_requests[0].base.git_ref, "staging");
+    }
+
+    #[test]
+    fn test_parse_check_suite_event() {
+        let data = include_str!("../../test-srcs/events/check-suite-rerequested.json");
+
+        let event: CheckSuiteEvent = serde_json::from_str(&data.to_string())
+            .expect("Should properly deserialize");
+        assert_eq!(event.action, CheckSuiteAction::Rerequested);
+        assert_eq!(event.check_suite.pull_requests[0].number, 33299);
+    }
+}
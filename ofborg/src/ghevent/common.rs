@@ -1,5 +1,6 @@
 #[derive(Serialize, Deserialize, Debug)]
 pub struct Comment {
+    pub id: u64,
     pub body: String,
     pub user: User,
 }
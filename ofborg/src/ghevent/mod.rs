@@ -1,7 +1,16 @@
+mod checkrunevent;
 mod common;
 mod issuecomment;
 mod pullrequestevent;
+mod pushevent;
+mod reviewcomment;
 
 pub use self::issuecomment::IssueComment;
-pub use self::pullrequestevent::{PullRequest, PullRequestEvent, PullRequestAction, PullRequestState};
+pub use self::pullrequestevent::{PullRequest, PullRequestEvent, PullRequestAction, PullRequestState,
+                                 PullRequestRef};
+pub use self::checkrunevent::{CheckRunEvent, CheckRunAction, CheckRun, CheckRunPullRequest,
+                              CheckSuiteEvent, CheckSuiteAction, CheckSuite};
+pub use self::reviewcomment::{PullRequestReviewCommentEvent,
+                              PullRequestReviewCommentAction};
+pub use self::pushevent::{PushEvent, PushCommit};
 pub use self::common::{Issue, Repository, User, Comment};
@@ -1,17 +1,19 @@
-use ofborg::ghevent::{Repository};
+use ofborg::ghevent::{Repository, User};
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Debug)]
 pub struct PullRequestEvent {
     pub action: PullRequestAction,
     pub number: u64,
     pub repository: Repository,
     pub pull_request: PullRequest,
     pub changes: Option<PullRequestChanges>,
+    pub sender: User,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct PullRequestChanges {
     pub base: Option<BaseChange>,
+    pub body: Option<ChangeWas>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -47,6 +49,8 @@ pub enum PullRequestAction {
     Closed,
     Reopened,
     Synchronize,
+    ReadyForReview,
+    ConvertedToDraft,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -58,7 +62,11 @@ pub struct PullRequestRef {
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct PullRequest {
+    pub number: u64,
     pub state: PullRequestState,
+    #[serde(default)]
+    pub draft: bool,
+    pub body: Option<String>,
     pub base: PullRequestRef,
     pub head: PullRequestRef,
 }
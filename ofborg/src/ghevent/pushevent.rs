@@ -0,0 +1,43 @@
+use ofborg::ghevent::Repository;
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PushEvent {
+    #[serde(rename="ref")]
+    pub git_ref: String,
+    pub before: String,
+    pub after: String,
+    pub repository: Repository,
+    pub commits: Vec<PushCommit>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PushCommit {
+    pub id: String,
+}
+
+impl PushEvent {
+    /// The pushed branch, if a branch (and not a tag) was pushed.
+    pub fn branch(&self) -> Option<&str> {
+        if self.git_ref.starts_with("refs/heads/") {
+            return Some(&self.git_ref["refs/heads/".len()..]);
+        }
+
+        return None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json;
+
+    #[test]
+    fn test_parse_push_event() {
+        let data = include_str!("../../test-srcs/events/push-master.json");
+
+        let push: PushEvent = serde_json::from_str(&data.to_string())
+            .expect("Should properly deserialize");
+        assert_eq!(push.branch(), Some("master"));
+        assert_eq!(push.commits.len(), 2);
+    }
+}
@@ -0,0 +1,34 @@
+use ofborg::ghevent::{Comment, Repository, PullRequest};
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PullRequestReviewCommentEvent {
+    pub action: PullRequestReviewCommentAction,
+    pub comment: Comment,
+    pub pull_request: PullRequest,
+    pub repository: Repository,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[serde(rename_all="snake_case")]
+pub enum PullRequestReviewCommentAction {
+    Created,
+    Edited,
+    Deleted,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json;
+
+    #[test]
+    fn test_parse_review_comment_event() {
+        let data = include_str!("../../test-srcs/events/pr-review-comment.json");
+
+        let event: PullRequestReviewCommentEvent = serde_json::from_str(&data.to_string())
+            .expect("Should properly deserialize");
+        assert_eq!(event.action, PullRequestReviewCommentAction::Created);
+        assert_eq!(event.comment.id, 167293013);
+        assert_eq!(event.pull_request.number, 33299);
+    }
+}
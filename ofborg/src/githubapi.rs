@@ -0,0 +1,865 @@
+use std::collections::HashMap;
+use std::io::Read;
+use std::thread;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+use hubcaps;
+use hyper::Client;
+use hyper::method::Method;
+use hyper::header::{Headers, Authorization, UserAgent, ContentType};
+use hyper::net::HttpsConnector;
+use hyper_native_tls::NativeTlsClient;
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use serde_json;
+use ofborg::ghevent::{Repository, User};
+use ofborg::githubapp::GithubAppAuth;
+use ofborg::ratelimit::{self, RateLimiter, RateLimitUsage};
+use ofborg::secrets::Secret;
+
+/// Hold requests back once fewer than this many remain.
+const MIN_REMAINING_REQUESTS: u64 = 100;
+
+/// Don't use an identity for this long after it ran into a secondary
+/// rate limit.
+const SECONDARY_LIMIT_COOL_DOWN_SECS: u64 = 60;
+
+/// The longest a job holds on to a hubcaps client, which App tokens
+/// handed to it stay valid for.
+const MAX_JOB_SECS: u64 = 50 * 60;
+
+/// A thin client for GitHub API endpoints hubcaps doesn't cover.
+pub struct GithubApi {
+    client: Client,
+    endpoints: GithubEndpoints,
+    identities: Vec<Identity>,
+    next_identity: AtomicUsize,
+}
+
+/// One account of the pool, each with its own rate limit.
+struct Identity {
+    credentials: Credentials,
+    rate_limiter: RateLimiter,
+}
+
+/// Where to find a GitHub instance's APIs.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GithubEndpoints {
+    pub api: String,
+    pub graphql: String,
+    pub uploads: String,
+}
+
+impl GithubEndpoints {
+    /// Guess the other endpoints from the REST API's, which works for
+    /// both github.com and GitHub Enterprise's `https://host/api/v3`.
+    pub fn for_api(api_base: &str) -> GithubEndpoints {
+        let api = api_base.trim_right_matches('/');
+
+        if api.ends_with("/api/v3") {
+            let root = &api[..api.len() - "/v3".len()];
+            return GithubEndpoints {
+                api: api.to_owned(),
+                graphql: format!("{}/graphql", root),
+                uploads: format!("{}/uploads", root),
+            };
+        }
+
+        return GithubEndpoints {
+            api: api.to_owned(),
+            graphql: format!("{}/graphql", api),
+            uploads: api.replacen("://api.", "://uploads.", 1),
+        };
+    }
+
+    pub fn with_uploads(mut self, uploads: String) -> GithubEndpoints {
+        self.uploads = uploads.trim_right_matches('/').to_owned();
+        return self;
+    }
+
+    /// The host name hubcaps talks to, like `api.github.com` or
+    /// `github.example.com/api/v3`.
+    fn hubcaps_host(&self) -> &str {
+        self.api.splitn(2, "://").nth(1).unwrap_or(&self.api)
+    }
+}
+
+pub enum Credentials {
+    /// A personal access token of the bot user.
+    Token(String),
+    /// A GitHub App installation, whose tokens are refreshed as needed.
+    App(GithubAppAuth),
+    /// A personal access token kept by a secrets provider, fetched
+    /// again once it expires.
+    Secret(Arc<Secret>),
+}
+
+impl Credentials {
+    pub fn token(&self) -> Result<String, ApiError> {
+        match self {
+            &Credentials::Token(ref token) => Ok(token.clone()),
+            &Credentials::App(ref app) => app.token().map_err(ApiError::Auth),
+            &Credentials::Secret(ref secret) => secret.get().map_err(ApiError::Auth),
+        }
+    }
+
+    /// A token which stays valid for at least `valid_for`, as far as
+    /// it's known when it expires.
+    pub fn token_valid_for(&self, valid_for: Duration) -> Result<String, ApiError> {
+        match self {
+            &Credentials::App(ref app) => app.token_valid_for(valid_for).map_err(ApiError::Auth),
+            _ => self.token(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ReviewComment {
+    pub path: String,
+    pub line: Option<u64>,
+    pub body: String,
+}
+
+#[derive(Serialize, Debug)]
+pub struct NewReviewComment {
+    pub body: String,
+    pub commit_id: String,
+    pub path: String,
+    pub line: u64,
+    pub side: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct IssueComment {
+    pub id: u64,
+    pub body: String,
+    #[serde(default)]
+    pub user: Option<User>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PullSummary {
+    pub number: u64,
+    pub head: PullHead,
+    pub base: PullBase,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PullHead {
+    pub sha: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PullBase {
+    #[serde(rename="ref")]
+    pub git_ref: String,
+    pub repo: Repository,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Review {
+    /// The PR's head when it was reviewed.
+    pub commit_id: Option<String>,
+    /// Like `APPROVED` or `CHANGES_REQUESTED`.
+    pub state: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PullCommit {
+    pub sha: String,
+    pub commit: PullCommitDetails,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PullCommitDetails {
+    pub verification: Option<Verification>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Verification {
+    pub verified: bool,
+}
+
+impl PullCommit {
+    pub fn is_verified(&self) -> bool {
+        self.commit
+            .verification
+            .as_ref()
+            .map(|v| v.verified)
+            .unwrap_or(false)
+    }
+}
+
+#[derive(Deserialize, Debug)]
+struct CombinedStatus {
+    statuses: Vec<StatusSummary>,
+}
+
+/// The latest status of a context on a commit.
+#[derive(Deserialize, Debug, Clone)]
+pub struct StatusSummary {
+    pub state: String,
+    pub context: String,
+    pub description: Option<String>,
+    pub target_url: Option<String>,
+    pub updated_at: String,
+}
+
+#[derive(Serialize, Debug)]
+pub struct NewStatus<'a> {
+    pub state: &'a str,
+    pub context: &'a str,
+    pub description: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub target_url: Option<&'a str>,
+}
+
+#[derive(Deserialize, Debug)]
+struct CheckRunList {
+    check_runs: Vec<CheckRunSummary>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct CheckRunSummary {
+    pub id: u64,
+    pub name: String,
+    pub status: String,
+    pub started_at: Option<String>,
+    #[serde(default)]
+    pub conclusion: Option<String>,
+}
+
+#[derive(Serialize, Debug)]
+struct NewGist<'a> {
+    description: &'a str,
+    public: bool,
+    files: HashMap<&'a str, GistContent<'a>>,
+}
+
+#[derive(Serialize, Debug)]
+struct GistContent<'a> {
+    content: &'a str,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct Gist {
+    pub html_url: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct TeamMembership {
+    state: String,
+}
+
+#[derive(Serialize, Debug)]
+pub struct CommentBody<'a> {
+    pub body: &'a str,
+}
+
+/// Things GitHub lets us react to.
+#[derive(Debug, PartialEq)]
+pub enum Reactable {
+    Issue(u64),
+    IssueComment(u64),
+    ReviewComment(u64),
+}
+
+#[derive(Serialize, Debug)]
+struct NewReaction<'a> {
+    content: &'a str,
+}
+
+#[derive(Serialize, Debug, Default)]
+pub struct CheckRunOptions {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub head_sha: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub details_url: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub external_id: Option<String>,
+    pub status: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub conclusion: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub output: Option<CheckRunOutput>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct CheckRunOutput {
+    pub title: String,
+    pub summary: String,
+    pub annotations: Vec<CheckAnnotation>,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct CheckAnnotation {
+    pub path: String,
+    pub start_line: u64,
+    pub end_line: u64,
+    pub annotation_level: String,
+    pub message: String,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct CheckRun {
+    pub id: u64,
+}
+
+#[derive(Serialize, Debug)]
+struct GraphQLRequest<'a, V: 'a> {
+    query: &'a str,
+    variables: &'a V,
+}
+
+#[derive(Deserialize, Debug)]
+struct GraphQLResponse<R> {
+    data: Option<R>,
+    errors: Option<Vec<GraphQLError>>,
+}
+
+#[derive(Deserialize, Debug)]
+struct GraphQLError {
+    message: String,
+}
+
+#[derive(Debug)]
+pub enum ApiError {
+    Http(String),
+    Status(u16, String),
+    Decode(String),
+    Auth(String),
+    GraphQL(String),
+}
+
+impl GithubApi {
+    pub fn new(api_base: String, token: String) -> GithubApi {
+        GithubApi::with_credentials(api_base, Credentials::Token(token))
+    }
+
+    pub fn with_credentials(api_base: String, credentials: Credentials) -> GithubApi {
+        GithubApi::with_endpoints(GithubEndpoints::for_api(&api_base), credentials)
+    }
+
+    pub fn with_endpoints(endpoints: GithubEndpoints, credentials: Credentials) -> GithubApi {
+        GithubApi::with_pool(endpoints, vec![credentials])
+    }
+
+    /// Spread requests over several accounts, skipping those whose
+    /// rate limit is nearly used up.
+    pub fn with_pool(endpoints: GithubEndpoints, pool: Vec<Credentials>) -> GithubApi {
+        assert!(pool.len() > 0, "At least one set of GitHub credentials is needed");
+
+        GithubApi {
+            client: Client::with_connector(HttpsConnector::new(NativeTlsClient::new().unwrap())),
+            endpoints: endpoints,
+            identities: pool.into_iter()
+                .map(|credentials| {
+                    Identity {
+                        credentials: credentials,
+                        rate_limiter: RateLimiter::new(MIN_REMAINING_REQUESTS),
+                    }
+                })
+                .collect(),
+            next_identity: AtomicUsize::new(0),
+        }
+    }
+
+    /// A hubcaps client for one job. hubcaps sends the token it's made
+    /// with on every request, so unlike `request`, which fetches one
+    /// per request, it's given an App token which stays valid for as
+    /// long as a job may take. Its requests bypass the rate limiter,
+    /// which can only hold back handing out a new client.
+    pub fn hubcaps(&self) -> Result<hubcaps::Github, ApiError> {
+        let identity = self.identity();
+        identity.rate_limiter.wait();
+        let token = identity.credentials.token_valid_for(
+            Duration::from_secs(MAX_JOB_SECS),
+        )?;
+
+        Ok(hubcaps::Github::host(
+            self.endpoints.hubcaps_host(),
+            "github.com/grahamc/ofborg",
+            Client::with_connector(HttpsConnector::new(NativeTlsClient::new().unwrap())),
+            hubcaps::Credentials::Token(token),
+        ))
+    }
+
+    pub fn get<R>(&self, path: &str) -> Result<R, ApiError>
+    where
+        R: DeserializeOwned,
+    {
+        let body = self.request(Method::Get, path, None)?;
+        decode(&body)
+    }
+
+    pub fn post<T: ?Sized, R>(&self, path: &str, data: &T) -> Result<R, ApiError>
+    where
+        T: Serialize,
+        R: DeserializeOwned,
+    {
+        let body = self.request(Method::Post, path, Some(serde_json::to_string(data).unwrap()))?;
+        decode(&body)
+    }
+
+    pub fn patch<T: ?Sized, R>(&self, path: &str, data: &T) -> Result<R, ApiError>
+    where
+        T: Serialize,
+        R: DeserializeOwned,
+    {
+        let body = self.request(Method::Patch, path, Some(serde_json::to_string(data).unwrap()))?;
+        decode(&body)
+    }
+
+    pub fn graphql<V, R>(&self, query: &str, variables: &V) -> Result<R, ApiError>
+    where
+        V: Serialize,
+        R: DeserializeOwned,
+    {
+        let request = GraphQLRequest {
+            query: query,
+            variables: variables,
+        };
+        let body = self.request(
+            Method::Post,
+            &self.endpoints.graphql,
+            Some(serde_json::to_string(&request).unwrap()),
+        )?;
+        let response: GraphQLResponse<R> = decode(&body)?;
+
+        if let Some(errors) = response.errors {
+            if errors.len() > 0 {
+                return Err(ApiError::GraphQL(
+                    errors
+                        .into_iter()
+                        .map(|e| e.message)
+                        .collect::<Vec<String>>()
+                        .join("; "),
+                ));
+            }
+        }
+
+        return response.data.ok_or(ApiError::GraphQL(String::from("No data returned")));
+    }
+
+    pub fn review_comments(&self, repo: &str, pr: u64) -> Result<Vec<ReviewComment>, ApiError> {
+        self.get_all(&format!("/repos/{}/pulls/{}/comments?per_page=100", repo, pr))
+    }
+
+    pub fn create_review_comment(
+        &self,
+        repo: &str,
+        pr: u64,
+        comment: &NewReviewComment,
+    ) -> Result<ReviewComment, ApiError> {
+        self.post(&format!("/repos/{}/pulls/{}/comments", repo, pr), comment)
+    }
+
+    pub fn pull_reviews(&self, repo: &str, pr: u64) -> Result<Vec<Review>, ApiError> {
+        self.get_all(&format!("/repos/{}/pulls/{}/reviews?per_page=100", repo, pr))
+    }
+
+    pub fn pull_commits(&self, repo: &str, pr: u64) -> Result<Vec<PullCommit>, ApiError> {
+        self.get_all(&format!("/repos/{}/pulls/{}/commits?per_page=100", repo, pr))
+    }
+
+    pub fn issue_comments(&self, repo: &str, number: u64) -> Result<Vec<IssueComment>, ApiError> {
+        self.get_all(&format!("/repos/{}/issues/{}/comments?per_page=100", repo, number))
+    }
+
+    pub fn issue_comment(&self, repo: &str, id: u64) -> Result<IssueComment, ApiError> {
+        self.get(&format!("/repos/{}/issues/comments/{}", repo, id))
+    }
+
+    pub fn create_issue_comment(
+        &self,
+        repo: &str,
+        number: u64,
+        body: &str,
+    ) -> Result<IssueComment, ApiError> {
+        self.post(
+            &format!("/repos/{}/issues/{}/comments", repo, number),
+            &CommentBody { body: body },
+        )
+    }
+
+    pub fn update_issue_comment(
+        &self,
+        repo: &str,
+        id: u64,
+        body: &str,
+    ) -> Result<IssueComment, ApiError> {
+        self.patch(
+            &format!("/repos/{}/issues/comments/{}", repo, id),
+            &CommentBody { body: body },
+        )
+    }
+
+    pub fn open_pulls(&self, repo: &str, base: &str) -> Result<Vec<PullSummary>, ApiError> {
+        self.get_all(&format!(
+            "/repos/{}/pulls?state=open&base={}&per_page=100",
+            repo,
+            base
+        ))
+    }
+
+    pub fn all_open_pulls(&self, repo: &str) -> Result<Vec<PullSummary>, ApiError> {
+        self.get_all(&format!("/repos/{}/pulls?state=open&per_page=100", repo))
+    }
+
+    /// The latest status of each context on `sha`.
+    pub fn commit_statuses(&self, repo: &str, sha: &str) -> Result<Vec<StatusSummary>, ApiError> {
+        let combined: CombinedStatus = self.get(&format!(
+            "/repos/{}/commits/{}/status?per_page=100",
+            repo,
+            sha
+        ))?;
+
+        return Ok(combined.statuses);
+    }
+
+    pub fn create_status(&self, repo: &str, sha: &str, status: &NewStatus) -> Result<(), ApiError> {
+        let _: serde_json::Value = self.post(&format!("/repos/{}/statuses/{}", repo, sha), status)?;
+        return Ok(());
+    }
+
+    pub fn check_runs(&self, repo: &str, sha: &str) -> Result<Vec<CheckRunSummary>, ApiError> {
+        let list: CheckRunList = self.get(&format!(
+            "/repos/{}/commits/{}/check-runs?per_page=100",
+            repo,
+            sha
+        ))?;
+
+        return Ok(list.check_runs);
+    }
+
+    /// React to an issue or comment, `content` is one of GitHub's
+    /// reaction names like `+1` or `confused`.
+    pub fn add_reaction(&self, repo: &str, target: &Reactable, content: &str) -> Result<(), ApiError> {
+        let path = match target {
+            &Reactable::Issue(number) => format!("/repos/{}/issues/{}/reactions", repo, number),
+            &Reactable::IssueComment(id) => {
+                format!("/repos/{}/issues/comments/{}/reactions", repo, id)
+            }
+            &Reactable::ReviewComment(id) => {
+                format!("/repos/{}/pulls/comments/{}/reactions", repo, id)
+            }
+        };
+
+        let _: serde_json::Value = self.post(&path, &NewReaction { content: content })?;
+
+        return Ok(());
+    }
+
+    pub fn create_check_run(
+        &self,
+        repo: &str,
+        options: &CheckRunOptions,
+    ) -> Result<CheckRun, ApiError> {
+        self.post(&format!("/repos/{}/check-runs", repo), options)
+    }
+
+    pub fn update_check_run(
+        &self,
+        repo: &str,
+        id: u64,
+        options: &CheckRunOptions,
+    ) -> Result<CheckRun, ApiError> {
+        self.patch(&format!("/repos/{}/check-runs/{}", repo, id), options)
+    }
+
+    /// Upload `content` as a secret gist.
+    pub fn create_gist(&self, description: &str, filename: &str, content: &str) -> Result<Gist, ApiError> {
+        let mut files = HashMap::new();
+        files.insert(filename, GistContent { content: content });
+
+        self.post(
+            "/gists",
+            &NewGist {
+                description: description,
+                public: false,
+                files: files,
+            },
+        )
+    }
+
+    /// Whether `user` is an active member of the `org/team` team.
+    pub fn team_membership(&self, org: &str, team: &str, user: &str) -> Result<bool, ApiError> {
+        let path = format!("/orgs/{}/teams/{}/memberships/{}", org, team, user);
+        match self.get::<TeamMembership>(&path) {
+            Ok(membership) => Ok(membership.state == "active"),
+            Err(ApiError::Status(404, _)) => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Whether `user` is a member of the `org` organization.
+    pub fn org_membership(&self, org: &str, user: &str) -> Result<bool, ApiError> {
+        match self.request(Method::Get, &format!("/orgs/{}/members/{}", org, user), None) {
+            Ok(_) => Ok(true),
+            Err(ApiError::Status(404, _)) |
+            Err(ApiError::Status(302, _)) => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    pub fn endpoints(&self) -> &GithubEndpoints {
+        &self.endpoints
+    }
+
+    /// The GitHub API consumption since this was last called.
+    pub fn take_usage(&self) -> RateLimitUsage {
+        let mut usage = RateLimitUsage::default();
+        for identity in self.identities.iter() {
+            usage.add(&identity.rate_limiter.take_usage());
+        }
+
+        return usage;
+    }
+
+    fn identity(&self) -> &Identity {
+        let cooling_down: Vec<_> = self.identities
+            .iter()
+            .map(|identity| identity.rate_limiter.cooling_down())
+            .collect();
+        let start = self.next_identity.fetch_add(1, Ordering::Relaxed);
+
+        return &self.identities[ratelimit::pick(&cooling_down, start)];
+    }
+
+    /// Every page of a listing, following the `Link` headers.
+    pub fn get_all<R>(&self, path: &str) -> Result<Vec<R>, ApiError>
+    where
+        R: DeserializeOwned,
+    {
+        let mut items: Vec<R> = vec![];
+        let mut next = Some(path.to_owned());
+        while let Some(path) = next {
+            let page = self.request_page(Method::Get, &path, None)?;
+            items.extend(decode::<Vec<R>>(&page.body)?);
+            next = page.next;
+        }
+
+        return Ok(items);
+    }
+
+    fn request(&self, method: Method, path: &str, body: Option<String>) -> Result<String, ApiError> {
+        return self.request_page(method, path, body).map(|page| page.body);
+    }
+
+    fn request_page(
+        &self,
+        method: Method,
+        path: &str,
+        body: Option<String>,
+    ) -> Result<Page, ApiError> {
+        let mut attempt = 0;
+        loop {
+            let identity = self.identity();
+            identity.rate_limiter.wait();
+
+            let result = self.send(identity, method.clone(), path, &body);
+            let remaining = identity.rate_limiter.remaining();
+            if let Err(ApiError::Status(status, ref contents)) = result {
+                if status == 403 && remaining != Some(0) &&
+                    ratelimit::should_retry(status, remaining, contents)
+                {
+                    // Let the other identities take over for a while.
+                    identity.rate_limiter.cool_down(SECONDARY_LIMIT_COOL_DOWN_SECS);
+                }
+            }
+            let retry = match result {
+                Err(ref e) => may_retry(&method, e, remaining),
+                Ok(_) => false,
+            };
+
+            if !retry || attempt >= ratelimit::MAX_RETRIES {
+                return result;
+            }
+
+            let delay = ratelimit::backoff(attempt, ratelimit::jitter());
+            info!(
+                "Retrying GitHub API {} {} in {}ms after {:?}",
+                method,
+                path,
+                delay.as_secs() * 1000 + delay.subsec_nanos() as u64 / 1_000_000,
+                result
+            );
+            identity.rate_limiter.retried();
+            thread::sleep(delay);
+            attempt += 1;
+        }
+    }
+
+    fn send(
+        &self,
+        identity: &Identity,
+        method: Method,
+        path: &str,
+        body: &Option<String>,
+    ) -> Result<Page, ApiError> {
+        // GraphQL lives outside of the REST API on GitHub Enterprise, and
+        // the pages after the first come as full URLs.
+        let url = if path.starts_with("/") {
+            format!("{}{}", self.endpoints.api, path)
+        } else {
+            path.to_owned()
+        };
+
+        let mut headers = Headers::new();
+        headers.set(Authorization(format!("token {}", identity.credentials.token()?)));
+        headers.set(UserAgent("github.com/grahamc/ofborg".to_owned()));
+        headers.set(ContentType::json());
+        // The Checks and Reactions APIs are still previews.
+        headers.set_raw(
+            "Accept",
+            vec![
+                b"application/vnd.github.antiope-preview+json".to_vec(),
+                b"application/vnd.github.squirrel-girl-preview+json".to_vec(),
+                b"application/vnd.github.v3+json".to_vec(),
+            ],
+        );
+
+        debug!("GitHub API {} {}", method, url);
+        let request = self.client.request(method, url.as_str()).headers(headers);
+        let sent = match body {
+            &Some(ref body) => request.body(body.as_str()).send(),
+            &None => request.send(),
+        };
+
+        let mut response = sent.map_err(|e| ApiError::Http(format!("{:?}", e)))?;
+        identity.rate_limiter.record(
+            numeric_header(&response.headers, "X-RateLimit-Remaining"),
+            numeric_header(&response.headers, "X-RateLimit-Reset"),
+        );
+
+        let mut contents = String::new();
+        response
+            .read_to_string(&mut contents)
+            .map_err(|e| ApiError::Http(format!("{:?}", e)))?;
+
+        if response.status.is_success() {
+            return Ok(Page {
+                body: contents,
+                next: next_link(&response.headers),
+            });
+        } else {
+            return Err(ApiError::Status(response.status.to_u16(), contents));
+        }
+    }
+}
+
+/// Whether a `method` request which failed with `error` may be sent
+/// again. A POST or PATCH may have been acted on already, unless the
+/// rate limit rejected it, and repeating it would comment twice.
+fn may_retry(method: &Method, error: &ApiError, remaining: Option<u64>) -> bool {
+    let idempotent = match *method {
+        Method::Get | Method::Head | Method::Options | Method::Put | Method::Delete => true,
+        _ => false,
+    };
+
+    return match error {
+        &ApiError::Http(_) => idempotent,
+        &ApiError::Status(status, ref contents) => {
+            ratelimit::should_retry(status, remaining, contents) && (idempotent || status == 403)
+        }
+        _ => false,
+    };
+}
+
+/// A response, and where the rest of a listing is.
+struct Page {
+    body: String,
+    next: Option<String>,
+}
+
+fn next_link(headers: &Headers) -> Option<String> {
+    let link = headers.get_raw("Link").and_then(|values| values.first())?;
+    return parse_next_link(&String::from_utf8_lossy(link));
+}
+
+/// The next page of a `Link` header, like
+/// `<https://api.github.com/...&page=2>; rel="next", <...>; rel="last"`.
+fn parse_next_link(link: &str) -> Option<String> {
+    let next = link.split(',').find(|part| part.contains("rel=\"next\""))?;
+    let start = next.find('<')? + 1;
+    let end = next.find('>')?;
+    return Some(next[start..end].to_owned());
+}
+
+fn numeric_header(headers: &Headers, name: &str) -> Option<u64> {
+    headers
+        .get_raw(name)
+        .and_then(|values| values.first())
+        .and_then(|value| String::from_utf8_lossy(value).trim().parse().ok())
+}
+
+fn decode<R>(body: &str) -> Result<R, ApiError>
+where
+    R: DeserializeOwned,
+{
+    let body = if body.trim().len() == 0 { "null" } else { body };
+    serde_json::from_str(body).map_err(|e| ApiError::Decode(format!("{:?}", e)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_endpoints() {
+        let github = GithubEndpoints::for_api("https://api.github.com/");
+        assert_eq!(github.api, "https://api.github.com");
+        assert_eq!(github.graphql, "https://api.github.com/graphql");
+        assert_eq!(github.uploads, "https://uploads.github.com");
+        assert_eq!(github.hubcaps_host(), "api.github.com");
+
+        let enterprise = GithubEndpoints::for_api("https://github.example.com/api/v3");
+        assert_eq!(enterprise.graphql, "https://github.example.com/api/graphql");
+        assert_eq!(enterprise.uploads, "https://github.example.com/api/uploads");
+        assert_eq!(enterprise.hubcaps_host(), "github.example.com/api/v3");
+    }
+
+    #[test]
+    fn test_parse_next_link() {
+        assert_eq!(
+            parse_next_link(
+                "<https://api.github.com/repositories/4542716/issues/1/comments?page=2>; \
+                 rel=\"next\", \
+                 <https://api.github.com/repositories/4542716/issues/1/comments?page=5>; \
+                 rel=\"last\"",
+            ),
+            Some(String::from(
+                "https://api.github.com/repositories/4542716/issues/1/comments?page=2",
+            ))
+        );
+        assert_eq!(
+            parse_next_link(
+                "<https://api.github.com/repositories/4542716/issues/1/comments?page=1>; \
+                 rel=\"prev\"",
+            ),
+            None
+        );
+    }
+
+    #[test]
+    fn test_may_retry() {
+        let unavailable = ApiError::Status(502, String::from("Bad Gateway"));
+        let limited = ApiError::Status(403, String::from("API rate limit exceeded"));
+        let disconnected = ApiError::Http(String::from("Io(ConnectionReset)"));
+        let forbidden = ApiError::Status(403, String::from("Resource not accessible"));
+
+        assert!(may_retry(&Method::Get, &unavailable, Some(100)));
+        assert!(may_retry(&Method::Get, &disconnected, Some(100)));
+        assert!(may_retry(&Method::Get, &limited, Some(0)));
+        assert!(!may_retry(&Method::Get, &forbidden, Some(100)));
+
+        assert!(!may_retry(&Method::Post, &unavailable, Some(100)));
+        assert!(!may_retry(&Method::Post, &disconnected, Some(100)));
+        assert!(may_retry(&Method::Post, &limited, Some(100)));
+        assert!(may_retry(&Method::Patch, &limited, Some(0)));
+        assert!(!may_retry(&Method::Patch, &forbidden, Some(100)));
+    }
+}
@@ -0,0 +1,207 @@
+use std::fs::File;
+use std::io::Read;
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use base64;
+use hyper::Client;
+use hyper::header::{Headers, Authorization, UserAgent, ContentType};
+use hyper::net::HttpsConnector;
+use hyper_native_tls::NativeTlsClient;
+use openssl::hash::MessageDigest;
+use openssl::pkey::PKey;
+use openssl::sign::Signer;
+use serde_json;
+
+/// Installation tokens are valid for an hour.
+const TOKEN_LIFETIME_SECS: u64 = 60 * 60;
+
+/// A token handed out for a single request is good for this long at
+/// least.
+const MIN_REMAINING_SECS: u64 = 10 * 60;
+
+/// Authenticates as a GitHub App installation, fetching a new
+/// installation token whenever the previous one is about to expire.
+pub struct GithubAppAuth {
+    client: Client,
+    api_base: String,
+    app_id: u64,
+    installation_id: u64,
+    private_key: PKey,
+    token: Mutex<Option<(String, Instant)>>,
+}
+
+#[derive(Serialize, Debug)]
+struct Claims {
+    iat: u64,
+    exp: u64,
+    iss: u64,
+}
+
+#[derive(Deserialize, Debug)]
+struct AccessToken {
+    token: String,
+}
+
+impl GithubAppAuth {
+    pub fn new(
+        api_base: String,
+        app_id: u64,
+        installation_id: u64,
+        private_key_pem: &[u8],
+    ) -> Result<GithubAppAuth, String> {
+        let private_key = PKey::private_key_from_pem(private_key_pem).map_err(|e| {
+            format!("Failed to parse the app's private key: {:?}", e)
+        })?;
+
+        return Ok(GithubAppAuth {
+            client: Client::with_connector(HttpsConnector::new(NativeTlsClient::new().unwrap())),
+            api_base: api_base.trim_right_matches('/').to_owned(),
+            app_id: app_id,
+            installation_id: installation_id,
+            private_key: private_key,
+            token: Mutex::new(None),
+        });
+    }
+
+    pub fn from_key_file(
+        api_base: String,
+        app_id: u64,
+        installation_id: u64,
+        private_key_file: &str,
+    ) -> Result<GithubAppAuth, String> {
+        let mut pem = vec![];
+        File::open(private_key_file)
+            .and_then(|mut f| f.read_to_end(&mut pem))
+            .map_err(|e| format!("Failed to read {}: {:?}", private_key_file, e))?;
+
+        return GithubAppAuth::new(api_base, app_id, installation_id, &pem);
+    }
+
+    /// A currently valid installation token.
+    pub fn token(&self) -> Result<String, String> {
+        return self.token_valid_for(Duration::from_secs(MIN_REMAINING_SECS));
+    }
+
+    /// An installation token which stays valid for at least `valid_for`,
+    /// for clients which can't ask for a new one partway.
+    pub fn token_valid_for(&self, valid_for: Duration) -> Result<String, String> {
+        let mut cached = self.token.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        if let Some((ref token, fetched)) = *cached {
+            if fetched.elapsed() + valid_for < Duration::from_secs(TOKEN_LIFETIME_SECS) {
+                return Ok(token.clone());
+            }
+        }
+
+        debug!("Fetching a new installation token");
+        let fetched = Instant::now();
+        let token = self.fetch_token()?;
+        *cached = Some((token.clone(), fetched));
+
+        return Ok(token);
+    }
+
+    fn fetch_token(&self) -> Result<String, String> {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+
+        let mut headers = Headers::new();
+        headers.set(Authorization(format!("Bearer {}", self.jwt(now)?)));
+        headers.set(UserAgent("github.com/grahamc/ofborg".to_owned()));
+        headers.set(ContentType::json());
+        headers.set_raw(
+            "Accept",
+            vec![b"application/vnd.github.machine-man-preview+json".to_vec()],
+        );
+
+        let url = format!(
+            "{}/installations/{}/access_tokens",
+            self.api_base,
+            self.installation_id
+        );
+        let mut response = self.client
+            .post(url.as_str())
+            .headers(headers)
+            .send()
+            .map_err(|e| format!("{:?}", e))?;
+
+        let mut contents = String::new();
+        response.read_to_string(&mut contents).map_err(
+            |e| format!("{:?}", e),
+        )?;
+        if !response.status.is_success() {
+            return Err(format!("{}: {}", response.status, contents));
+        }
+
+        let token: AccessToken = serde_json::from_str(&contents).map_err(
+            |e| format!("{:?}", e),
+        )?;
+
+        return Ok(token.token);
+    }
+
+    /// The JSON Web Token identifying the app itself, which is only
+    /// good for requesting installation tokens.
+    fn jwt(&self, now: u64) -> Result<String, String> {
+        let header = base64_url(br#"{"alg":"RS256","typ":"JWT"}"#);
+        let claims = base64_url(&serde_json::to_vec(&Claims {
+            // Allow for some clock drift.
+            iat: now - 60,
+            exp: now + 9 * 60,
+            iss: self.app_id,
+        }).unwrap());
+        let message = format!("{}.{}", header, claims);
+
+        let mut signer = Signer::new(MessageDigest::sha256(), &self.private_key)
+            .map_err(|e| format!("{:?}", e))?;
+        signer.update(message.as_bytes()).map_err(
+            |e| format!("{:?}", e),
+        )?;
+        let signature = signer.finish().map_err(|e| format!("{:?}", e))?;
+
+        return Ok(format!("{}.{}", message, base64_url(&signature)));
+    }
+}
+
+fn base64_url(data: &[u8]) -> String {
+    base64::encode_config(data, base64::URL_SAFE_NO_PAD)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use openssl::rsa::Rsa;
+
+    #[test]
+    fn test_jwt() {
+        let key = Rsa::generate(2048).unwrap().private_key_to_pem().unwrap();
+        let auth = GithubAppAuth::new(
+            String::from("https://api.github.invalid"),
+            1234,
+            5678,
+            &key,
+        ).unwrap();
+
+        let jwt = auth.jwt(1500000000).unwrap();
+        let parts: Vec<&str> = jwt.split('.').collect();
+
+        assert_eq!(parts.len(), 3);
+        assert_eq!(
+            base64::decode_config(parts[0], base64::URL_SAFE_NO_PAD).unwrap(),
+            br#"{"alg":"RS256","typ":"JWT"}"#.to_vec()
+        );
+        assert_eq!(
+            String::from_utf8(
+                base64::decode_config(parts[1], base64::URL_SAFE_NO_PAD).unwrap(),
+            ).unwrap(),
+            r#"{"iat":1499999940,"exp":1500000540,"iss":1234}"#
+        );
+        assert_eq!(
+            base64::decode_config(parts[2], base64::URL_SAFE_NO_PAD)
+                .unwrap()
+                .len(),
+            256
+        );
+    }
+}
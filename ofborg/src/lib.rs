@@ -5,7 +5,11 @@
 extern crate serde_derive;
 extern crate serde;
 
+#[macro_use]
 extern crate serde_json;
+extern crate serde_ignored;
+extern crate toml;
+extern crate signal_hook;
 
 #[macro_use]
 extern crate log;
@@ -21,11 +25,16 @@ extern crate fs2;
 extern crate md5;
 extern crate uuid;
 extern crate env_logger;
+extern crate openssl;
+extern crate base64;
+extern crate git2;
+extern crate flate2;
+extern crate serde_cbor;
 
-use std::env;
 
 pub mod acl;
 pub mod checkout;
+pub mod cli;
 pub mod locks;
 pub mod clone;
 pub mod worker;
@@ -40,17 +49,59 @@ pub mod commentparser;
 pub mod commitstatus;
 pub mod outpathdiff;
 pub mod tagger;
+pub mod maintainers;
+pub mod metalint;
+pub mod aliascheck;
+pub mod evalsummary;
+pub mod changedattrs;
+pub mod evalerrors;
+pub mod githubapi;
+pub mod githubapp;
+pub mod webhook;
+pub mod prmetadata;
+pub mod ratelimit;
+pub mod replay;
+pub mod evalcache;
+pub mod buildreport;
+pub mod branchpolicy;
+pub mod pushpolicy;
+pub mod securityfeed;
+pub mod formatcheck;
 pub mod asynccmd;
 pub mod notifyworker;
 pub mod writetoline;
 pub mod test_scratch;
 pub mod easyamqp;
+pub mod cancellation;
+pub mod attrpattern;
+pub mod commandlimit;
+pub mod aclsource;
+pub mod banlist;
+pub mod flakecheck;
+pub mod nixpath;
+pub mod configcheck;
+pub mod reload;
+pub mod secrets;
+pub mod simulate;
+pub mod dryrun;
+pub mod logmigrate;
+pub mod logviewer;
+pub mod logindex;
+pub mod msgsign;
+pub mod api;
+pub mod badge;
+pub mod trigger;
+pub mod tracing;
+pub mod logging;
+pub mod correlation;
+pub mod timestamp;
 
 pub mod ofborg {
     pub use asynccmd;
     pub use stats;
     pub use config;
     pub use checkout;
+    pub use cli;
     pub use locks;
     pub use clone;
     pub use worker;
@@ -65,19 +116,55 @@ pub mod ofborg {
     pub use commentparser;
     pub use outpathdiff;
     pub use tagger;
+    pub use maintainers;
+    pub use metalint;
+    pub use aliascheck;
+    pub use evalsummary;
+    pub use changedattrs;
+    pub use evalerrors;
+    pub use githubapi;
+    pub use githubapp;
+    pub use webhook;
+    pub use prmetadata;
+    pub use ratelimit;
+    pub use replay;
+    pub use evalcache;
+    pub use buildreport;
+    pub use branchpolicy;
+    pub use pushpolicy;
+    pub use securityfeed;
+    pub use formatcheck;
     pub use writetoline;
     pub use test_scratch;
     pub use easyamqp;
+    pub use cancellation;
+    pub use attrpattern;
+    pub use commandlimit;
+    pub use aclsource;
+    pub use banlist;
+    pub use flakecheck;
+    pub use nixpath;
+    pub use configcheck;
+    pub use reload;
+    pub use secrets;
+    pub use simulate;
+    pub use dryrun;
+    pub use logmigrate;
+    pub use logviewer;
+    pub use logindex;
+    pub use msgsign;
+    pub use api;
+    pub use badge;
+    pub use trigger;
+    pub use tracing;
+    pub use logging;
+    pub use correlation;
+    pub use timestamp;
 
     pub const VERSION: &'static str = env!("CARGO_PKG_VERSION");
 }
 
+/// Log at info, or as `RUST_LOG` says, without a configuration.
 pub fn setup_log() {
-    if let Err(_) = env::var("RUST_LOG") {
-        env::set_var("RUST_LOG", "info");
-        env_logger::init().unwrap();
-        info!("Defaulting RUST_LOG environment variable to info");
-    } else {
-        env_logger::init().unwrap();
-    }
+    logging::init("ofborg", None);
 }
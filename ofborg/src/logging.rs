@@ -0,0 +1,308 @@
+//! Leveled logging with the task and the job being worked on as fields,
+//! as text or as JSON lines for log shippers.
+//!
+//! Levels are configured by module, like `ofborg::tasks::build=debug`,
+//! under `logging` and overridden with `RUST_LOG`.
+
+use std::cell::RefCell;
+use std::env;
+use std::io::{self, Write};
+use std::str::FromStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use log::{self, Log, LogLevelFilter, LogMetadata, LogRecord};
+use serde_json;
+
+use ofborg::config::LogConfig;
+
+thread_local! {
+    static FIELDS: RefCell<Vec<(String, String)>> = RefCell::new(vec![]);
+}
+
+/// Logs fields, like the routing key of the job at hand, with every
+/// line the current thread logs until it's dropped.
+pub struct Fields {
+    count: usize,
+}
+
+pub fn fields(fields: &[(&str, &str)]) -> Fields {
+    FIELDS.with(|current| {
+        current.borrow_mut().extend(fields.iter().map(|&(key, value)| {
+            (key.to_owned(), value.to_owned())
+        }))
+    });
+
+    return Fields { count: fields.len() };
+}
+
+impl Drop for Fields {
+    fn drop(&mut self) {
+        let count = self.count;
+        FIELDS.with(|current| {
+            let mut current = current.borrow_mut();
+            let keep = current.len().saturating_sub(count);
+            current.truncate(keep);
+        });
+    }
+}
+
+fn current_fields() -> Vec<(String, String)> {
+    FIELDS.with(|current| current.borrow().clone())
+}
+
+/// Which levels are logged, by module.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Filter {
+    default: LogLevelFilter,
+    modules: Vec<(String, LogLevelFilter)>,
+}
+
+impl Filter {
+    pub fn new(default: LogLevelFilter) -> Filter {
+        return Filter {
+            default: default,
+            modules: vec![],
+        };
+    }
+
+    /// Add the directives of `spec`, like `info,hyper=warn`, where the
+    /// later ones win.
+    pub fn parse(&mut self, spec: &str) -> Result<(), String> {
+        for directive in spec.split(',').map(|d| d.trim()).filter(|d| d.len() > 0) {
+            let mut parts = directive.splitn(2, '=');
+            let first = parts.next().unwrap();
+            match parts.next() {
+                Some(level) => self.module(first, level)?,
+                None => {
+                    // Like `RUST_LOG=info`, or `RUST_LOG=hyper` for
+                    // everything of a module.
+                    match LogLevelFilter::from_str(first) {
+                        Ok(level) => self.default = level,
+                        Err(_) => self.modules.push((first.to_owned(), LogLevelFilter::Trace)),
+                    }
+                }
+            }
+        }
+
+        return Ok(());
+    }
+
+    pub fn module(&mut self, module: &str, level: &str) -> Result<(), String> {
+        let level = LogLevelFilter::from_str(level.trim()).map_err(|_| {
+            format!("{} isn't a log level, for {}", level, module)
+        })?;
+        self.modules.push((module.trim().to_owned(), level));
+        return Ok(());
+    }
+
+    /// The level of `target`, by the longest module it's in.
+    pub fn level(&self, target: &str) -> LogLevelFilter {
+        let mut level = self.default;
+        let mut longest = 0;
+        for &(ref module, module_level) in self.modules.iter() {
+            if target.starts_with(module.as_str()) && module.len() >= longest {
+                level = module_level;
+                longest = module.len();
+            }
+        }
+
+        return level;
+    }
+
+    fn max(&self) -> LogLevelFilter {
+        self.modules.iter().map(|&(_, level)| level).fold(
+            self.default,
+            |max, level| if level > max { level } else { max },
+        )
+    }
+}
+
+/// The filter configured by `config`, overridden by `RUST_LOG`.
+pub fn filter(config: Option<&LogConfig>, rust_log: Option<String>) -> Result<Filter, String> {
+    let mut filter = Filter::new(LogLevelFilter::Info);
+
+    if let Some(config) = config {
+        if let Some(ref level) = config.level {
+            filter.parse(level)?;
+        }
+        if let Some(ref modules) = config.modules {
+            let mut modules: Vec<(&String, &String)> = modules.iter().collect();
+            modules.sort();
+            for (module, level) in modules {
+                filter.module(module, level)?;
+            }
+        }
+    }
+
+    if let Some(spec) = rust_log {
+        filter.parse(&spec)?;
+    }
+
+    return Ok(filter);
+}
+
+struct Logger {
+    task: String,
+    filter: Filter,
+    json: bool,
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &LogMetadata) -> bool {
+        metadata.level() <= self.filter.level(metadata.target())
+    }
+
+    fn log(&self, record: &LogRecord) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+
+        let message = format!("{}", record.args());
+        let line = if self.json {
+            json_line(&self.task, record.level(), record.target(), &message, now())
+        } else {
+            text_line(&self.task, record.level(), record.target(), &message)
+        };
+
+        let stderr = io::stderr();
+        let _ = writeln!(stderr.lock(), "{}", line);
+    }
+}
+
+fn now() -> f64 {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or(
+        Duration::from_secs(0),
+    );
+    return now.as_secs() as f64 + now.subsec_nanos() as f64 / 1_000_000_000.0;
+}
+
+fn text_line(task: &str, level: log::LogLevel, target: &str, message: &str) -> String {
+    let fields: Vec<String> = current_fields()
+        .into_iter()
+        .map(|(key, value)| format!("{}={}", key, value))
+        .collect();
+
+    if fields.len() == 0 {
+        return format!("{}:{}:{}: {}", level, task, target, message);
+    }
+    return format!(
+        "{}:{}:{}: {} [{}]",
+        level,
+        task,
+        target,
+        message,
+        fields.join(" ")
+    );
+}
+
+fn json_line(task: &str, level: log::LogLevel, target: &str, message: &str, at: f64) -> String {
+    let mut line = serde_json::Map::new();
+    line.insert(String::from("ts"), json!(at));
+    line.insert(String::from("level"), json!(level.to_string()));
+    line.insert(String::from("task"), json!(task));
+    line.insert(String::from("target"), json!(target));
+    line.insert(String::from("message"), json!(message));
+    for (key, value) in current_fields() {
+        line.insert(key, json!(value));
+    }
+
+    return serde_json::Value::Object(line).to_string();
+}
+
+/// Log as `task`, like `builder`, as configured.
+pub fn init(task: &str, config: Option<&LogConfig>) {
+    let filter = match filter(config, env::var("RUST_LOG").ok()) {
+        Ok(filter) => filter,
+        Err(e) => {
+            eprintln!("Logging at info, the log levels are invalid: {}", e);
+            Filter::new(LogLevelFilter::Info)
+        }
+    };
+    let json = config.and_then(|config| config.json).unwrap_or(false);
+
+    let logger = Logger {
+        task: task.to_owned(),
+        filter: filter,
+        json: json,
+    };
+    let set = log::set_logger(|max_level| {
+        max_level.set(logger.filter.max());
+        Box::new(logger)
+    });
+    if let Err(e) = set {
+        eprintln!("Failed to set up logging: {:?}", e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_filter() {
+        let config = LogConfig {
+            level: Some(String::from("warn")),
+            modules: Some(
+                vec![
+                    (String::from("ofborg::tasks"), String::from("info")),
+                    (String::from("ofborg::tasks::build"), String::from("debug")),
+                ].into_iter()
+                    .collect::<HashMap<String, String>>(),
+            ),
+            json: None,
+        };
+
+        let filter = filter(Some(&config), None).unwrap();
+        assert_eq!(filter.level("hyper::client"), LogLevelFilter::Warn);
+        assert_eq!(filter.level("ofborg::tasks::massrebuilder"), LogLevelFilter::Info);
+        assert_eq!(filter.level("ofborg::tasks::build"), LogLevelFilter::Debug);
+        assert_eq!(filter.max(), LogLevelFilter::Debug);
+
+        let overridden = super::filter(
+            Some(&config),
+            Some(String::from("error,ofborg::tasks::build=trace,amqp")),
+        ).unwrap();
+        assert_eq!(overridden.level("hyper::client"), LogLevelFilter::Error);
+        assert_eq!(overridden.level("ofborg::tasks::build"), LogLevelFilter::Trace);
+        assert_eq!(overridden.level("amqp::session"), LogLevelFilter::Trace);
+
+        assert!(super::filter(None, Some(String::from("ofborg=loud"))).is_err());
+    }
+
+    #[test]
+    fn test_lines() {
+        {
+            let _job = fields(&[("routing_key", "build-inputs")]);
+            let _build = fields(&[("pr", "NixOS/nixpkgs#1234")]);
+            assert_eq!(
+                text_line("builder", log::LogLevel::Info, "ofborg::tasks::build", "Building"),
+                "INFO:builder:ofborg::tasks::build: Building [routing_key=build-inputs pr=NixOS/nixpkgs#1234]"
+            );
+
+            let line: serde_json::Value = serde_json::from_str(&json_line(
+                "builder",
+                log::LogLevel::Warn,
+                "ofborg::tasks::build",
+                "Failed",
+                1500000000.5,
+            )).unwrap();
+            assert_eq!(
+                line,
+                json!({
+                    "ts": 1500000000.5,
+                    "level": "WARN",
+                    "task": "builder",
+                    "target": "ofborg::tasks::build",
+                    "message": "Failed",
+                    "routing_key": "build-inputs",
+                    "pr": "NixOS/nixpkgs#1234",
+                })
+            );
+        }
+
+        assert_eq!(
+            text_line("builder", log::LogLevel::Info, "ofborg::tasks::build", "Done"),
+            "INFO:builder:ofborg::tasks::build: Done"
+        );
+    }
+}
@@ -0,0 +1,276 @@
+//! The `index.json` files the static log viewer reads instead of listing
+//! directories: one per PR, of its attempts and how they went, and one
+//! at the root, of the PRs built most recently.
+
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::Path;
+use std::time::UNIX_EPOCH;
+
+use serde::Serialize;
+use serde_json;
+
+use ofborg::logviewer::{self, Attempt};
+use ofborg::message::buildresult::{AttrResult, BuildResult};
+
+/// How many PRs the root index lists.
+const GLOBAL_LIMIT: usize = 1000;
+
+/// What the collector keeps of a build's result, next to its log as
+/// `<attempt id>.result.json`.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct AttemptResult {
+    pub success: Option<bool>,
+    #[serde(default)]
+    pub attempted_attrs: Vec<String>,
+    #[serde(default)]
+    pub skipped_attrs: Vec<String>,
+    /// How each attr went, and which lines of the log are its build.
+    #[serde(default)]
+    pub attrs: Vec<AttrResult>,
+}
+
+impl<'a> From<&'a BuildResult> for AttemptResult {
+    fn from(result: &BuildResult) -> AttemptResult {
+        AttemptResult {
+            success: result.success(),
+            attempted_attrs: result.attempted_attrs(),
+            skipped_attrs: result.skipped_attrs(),
+            attrs: result.attrs.clone(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct AttemptEntry {
+    pub attempt_id: String,
+    pub system: Option<String>,
+    pub identity: Option<String>,
+    pub attempted_attrs: Vec<String>,
+    pub skipped_attrs: Vec<String>,
+    /// Whether it's finished.
+    pub finished: bool,
+    pub success: Option<bool>,
+    /// Seconds since the epoch its log was last written to.
+    pub updated_at: u64,
+    /// Relative to the root.
+    pub log: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct PrIndex {
+    /// Like `nixos/nixpkgs.12345`.
+    pub routing_key: String,
+    /// Most recent first.
+    pub attempts: Vec<AttemptEntry>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct PrSummary {
+    pub routing_key: String,
+    pub attempts: usize,
+    pub succeeded: usize,
+    pub failed: usize,
+    /// Not finished yet.
+    pub building: usize,
+    pub updated_at: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Default)]
+pub struct GlobalIndex {
+    /// Most recently updated first.
+    pub prs: Vec<PrSummary>,
+}
+
+/// Regenerates the index of the PR `routing_key` under `root` and its
+/// line in the root index.
+pub fn update(root: &Path, routing_key: &str) -> Result<(), String> {
+    let attempts = logviewer::recent_under(root, Path::new(routing_key), usize::max_value())?;
+    let index = PrIndex {
+        routing_key: routing_key.to_owned(),
+        attempts: attempts.iter().map(|a| entry(root, a)).collect(),
+    };
+    write_json(&root.join(routing_key).join("index.json"), &index)?;
+
+    let global_path = root.join("index.json");
+    let mut global: GlobalIndex = File::open(&global_path)
+        .ok()
+        .and_then(|f| serde_json::from_reader(f).ok())
+        .unwrap_or_default();
+    global.prs.retain(|pr| pr.routing_key != routing_key);
+    global.prs.push(summarize(&index));
+    global.prs.sort_by(|a, b| b.updated_at.cmp(&a.updated_at));
+    global.prs.truncate(GLOBAL_LIMIT);
+    return write_json(&global_path, &global);
+}
+
+/// Whether an attempt on the PR `routing_key` under `root` hasn't sent
+/// its result yet, but wrote to its log since `since`, in seconds since
+/// the epoch.
+pub fn has_live_attempt(root: &Path, routing_key: &str, since: u64) -> bool {
+    if !root.join(routing_key).is_dir() {
+        return false;
+    }
+
+    match logviewer::recent_under(root, Path::new(routing_key), usize::max_value()) {
+        Ok(attempts) => {
+            attempts.iter().map(|attempt| entry(root, attempt)).any(|attempt| {
+                !attempt.finished && attempt.updated_at >= since
+            })
+        }
+        Err(e) => {
+            warn!("Failed to list the attempts of {}: {}", routing_key, e);
+            false
+        }
+    }
+}
+
+fn entry(root: &Path, attempt: &Attempt) -> AttemptEntry {
+    let result_path = attempt.path.with_file_name(
+        format!("{}.result.json", attempt.attempt_id),
+    );
+    let result: Option<AttemptResult> = File::open(result_path).ok().and_then(|f| {
+        serde_json::from_reader(f).ok()
+    });
+
+    let mut attempted_attrs = attempt.attrs.clone();
+    let mut skipped_attrs = vec![];
+    if let Some(ref result) = result {
+        if attempted_attrs.len() == 0 {
+            attempted_attrs = result.attempted_attrs.clone();
+        }
+        skipped_attrs = result.skipped_attrs.clone();
+    }
+
+    AttemptEntry {
+        attempt_id: attempt.attempt_id.clone(),
+        system: attempt.system.clone(),
+        identity: attempt.identity.clone(),
+        attempted_attrs: attempted_attrs,
+        skipped_attrs: skipped_attrs,
+        finished: result.is_some(),
+        success: result.and_then(|r| r.success),
+        updated_at: attempt
+            .modified
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0),
+        log: attempt
+            .path
+            .strip_prefix(root)
+            .unwrap_or(&attempt.path)
+            .to_string_lossy()
+            .into_owned(),
+    }
+}
+
+fn summarize(index: &PrIndex) -> PrSummary {
+    let attempts = &index.attempts;
+    PrSummary {
+        routing_key: index.routing_key.clone(),
+        attempts: attempts.len(),
+        succeeded: attempts.iter().filter(|a| a.success == Some(true)).count(),
+        failed: attempts.iter().filter(|a| a.success == Some(false)).count(),
+        building: attempts.iter().filter(|a| !a.finished).count(),
+        updated_at: attempts.iter().map(|a| a.updated_at).max().unwrap_or(0),
+    }
+}
+
+/// Writes next to `path` then renames, so the viewer never fetches half
+/// an index.
+fn write_json<T: Serialize>(path: &Path, value: &T) -> Result<(), String> {
+    let data = serde_json::to_vec(value).map_err(
+        |e| format!("Failed to serialize {}: {:?}", path.display(), e),
+    )?;
+    let partial = path.with_file_name(format!(
+        ".{}.partial",
+        path.file_name().unwrap().to_string_lossy()
+    ));
+    File::create(&partial)
+        .and_then(|mut f| f.write_all(&data))
+        .and_then(|_| fs::rename(&partial, path))
+        .map_err(|e| format!("Failed to write {}: {:?}", path.display(), e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ofborg::test_scratch::TestScratch;
+    use ofborg::timestamp;
+
+    fn write(root: &Path, path: &str, data: &str) {
+        let path = root.join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        File::create(path).unwrap().write_all(data.as_bytes()).unwrap();
+    }
+
+    #[test]
+    fn test_update() {
+        let scratch = TestScratch::new_dir("logindex-update");
+        let root = scratch.path();
+        write(&root, "nixos/nixpkgs.1/attempt-a", "building hello\n");
+        write(
+            &root,
+            "nixos/nixpkgs.1/attempt-a.metadata.json",
+            r#"{"system":"x86_64-linux","identity":"builder-1","attempt_id":"attempt-a","attempted_attrs":["hello"],"skipped_attrs":null}"#,
+        );
+        write(
+            &root,
+            "nixos/nixpkgs.1/attempt-a.result.json",
+            r#"{"success":false,"attempted_attrs":["hello"],"skipped_attrs":["world"]}"#,
+        );
+        write(&root, "nixos/nixpkgs.1/attempt-b", "building hello\n");
+        write(&root, "nixos/nixpkgs.2/attempt-c", "building world\n");
+
+        update(&root, "nixos/nixpkgs.1").unwrap();
+        update(&root, "nixos/nixpkgs.2").unwrap();
+        update(&root, "nixos/nixpkgs.1").unwrap();
+
+        let pr: PrIndex = serde_json::from_reader(
+            File::open(root.join("nixos/nixpkgs.1/index.json")).unwrap(),
+        ).unwrap();
+        assert_eq!(pr.routing_key, "nixos/nixpkgs.1");
+        assert_eq!(pr.attempts.len(), 2);
+        let a = pr.attempts.iter().find(|a| a.attempt_id == "attempt-a").unwrap();
+        assert_eq!(a.system, Some(String::from("x86_64-linux")));
+        assert_eq!(a.identity, Some(String::from("builder-1")));
+        assert_eq!(a.attempted_attrs, vec![String::from("hello")]);
+        assert_eq!(a.skipped_attrs, vec![String::from("world")]);
+        assert!(a.finished);
+        assert_eq!(a.success, Some(false));
+        assert_eq!(a.log, "nixos/nixpkgs.1/attempt-a");
+        let b = pr.attempts.iter().find(|a| a.attempt_id == "attempt-b").unwrap();
+        assert!(!b.finished);
+        assert_eq!(b.success, None);
+
+        let global: GlobalIndex = serde_json::from_reader(File::open(root.join("index.json")).unwrap())
+            .unwrap();
+        let mut keys: Vec<&str> = global.prs.iter().map(|pr| pr.routing_key.as_str()).collect();
+        keys.sort();
+        assert_eq!(keys, vec!["nixos/nixpkgs.1", "nixos/nixpkgs.2"]);
+        let one = global.prs.iter().find(|pr| pr.routing_key == "nixos/nixpkgs.1").unwrap();
+        assert_eq!((one.attempts, one.succeeded, one.failed, one.building), (2, 0, 1, 1));
+
+        // The indexes aren't logs.
+        assert_eq!(logviewer::recent(&root, 10).unwrap().len(), 3);
+    }
+
+    #[test]
+    fn test_has_live_attempt() {
+        let scratch = TestScratch::new_dir("logindex-live-attempt");
+        let root = scratch.path();
+        let an_hour_ago = timestamp::now() - 60 * 60;
+        write(&root, "nixos/nixpkgs.1/attempt-a", "building hello\n");
+        write(&root, "nixos/nixpkgs.2/attempt-b", "building hello\n");
+        write(
+            &root,
+            "nixos/nixpkgs.2/attempt-b.result.json",
+            r#"{"success":true,"attempted_attrs":["hello"],"skipped_attrs":[]}"#,
+        );
+
+        assert!(has_live_attempt(&root, "nixos/nixpkgs.1", an_hour_ago));
+        assert!(!has_live_attempt(&root, "nixos/nixpkgs.1", timestamp::now() + 60));
+        assert!(!has_live_attempt(&root, "nixos/nixpkgs.2", an_hour_ago));
+        assert!(!has_live_attempt(&root, "nixos/nixpkgs.3", an_hour_ago));
+    }
+}
@@ -0,0 +1,522 @@
+//! Copy a tree of build logs to another layout or store: from one
+//! directory per PR to one per system, compressed, or into an object
+//! store. Every copy is read back and compared before it's recorded in a
+//! journal, so an interrupted migration picks up where it stopped.
+
+use std::collections::HashSet;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::path::{Component, Path, PathBuf};
+
+use flate2::Compression;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use hyper::Client;
+use hyper::header::{Authorization, Headers, UserAgent};
+use hyper::net::HttpsConnector;
+use hyper_native_tls::NativeTlsClient;
+use serde_json::{self, Value};
+
+/// Where logs go under a root: `<routing key>/<attempt>` when flat, like
+/// the collector writes them, or `<routing key>/<system>/<attempt>` per
+/// system, gzipped with a `.gz` suffix when compressed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Layout {
+    pub per_system: bool,
+    pub compressed: bool,
+}
+
+impl Layout {
+    /// `flat` or `per-system`, followed by `+gzip` to compress.
+    pub fn parse(spec: &str) -> Result<Layout, String> {
+        let mut parts = spec.splitn(2, '+');
+        let per_system = match parts.next() {
+            Some("flat") => false,
+            Some("per-system") => true,
+            _ => return Err(format!("Unknown layout {:?}", spec)),
+        };
+        let compressed = match parts.next() {
+            None => false,
+            Some("gzip") => true,
+            Some(other) => return Err(format!("Unknown compression {:?}", other)),
+        };
+
+        return Ok(Layout {
+            per_system: per_system,
+            compressed: compressed,
+        });
+    }
+
+    /// Where `log`, or its metadata, goes under a root of this layout.
+    pub fn key(&self, log: &Log, metadata: bool) -> String {
+        let mut key = log.routing_key.clone();
+        if self.per_system {
+            key.push('/');
+            key.push_str(log.system.as_ref().map(|s| s.as_str()).unwrap_or("unknown"));
+        }
+        key.push('/');
+        key.push_str(&log.attempt_id);
+        if metadata {
+            key.push_str(".metadata.json");
+        }
+        if self.compressed {
+            key.push_str(".gz");
+        }
+        return key;
+    }
+
+    fn encode(&self, data: &[u8]) -> Result<Vec<u8>, String> {
+        if !self.compressed {
+            return Ok(data.to_vec());
+        }
+
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(data).map_err(|e| format!("{:?}", e))?;
+        return encoder.finish().map_err(|e| format!("{:?}", e));
+    }
+
+    fn decode(&self, data: Vec<u8>) -> Result<Vec<u8>, String> {
+        if !self.compressed {
+            return Ok(data);
+        }
+
+        let mut decoded = Vec::new();
+        GzDecoder::new(&data[..]).read_to_end(&mut decoded).map_err(
+            |e| format!("Not gzipped: {:?}", e),
+        )?;
+        return Ok(decoded);
+    }
+}
+
+/// A build log found under a root, with its metadata if it has any.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Log {
+    /// Like `nixos/nixpkgs.12345`.
+    pub routing_key: String,
+    pub system: Option<String>,
+    pub attempt_id: String,
+    /// Relative to the root.
+    pub path: PathBuf,
+    pub metadata: Option<PathBuf>,
+}
+
+/// The logs under `root`, written in `layout`. Compressed logs are
+/// recognized by their `.gz` suffix, whatever the layout says.
+pub fn scan(root: &Path, layout: &Layout) -> Result<Vec<Log>, String> {
+    return scan_under(root, Path::new(""), layout);
+}
+
+/// The logs under `under`, relative to `root`, like the logs of one PR.
+pub fn scan_under(root: &Path, under: &Path, layout: &Layout) -> Result<Vec<Log>, String> {
+    let mut files: Vec<PathBuf> = vec![];
+    walk(root, under, &mut files)?;
+
+    let mut logs: Vec<Log> = vec![];
+    for path in files.iter() {
+        let name = path.file_name().unwrap().to_string_lossy().into_owned();
+        if name.ends_with(".metadata.json") || name.ends_with(".metadata.json.gz") ||
+            name.ends_with(".result.json") || name.ends_with(".result.json.gz") ||
+            name == "index.json"
+        {
+            continue;
+        }
+        let attempt_id = name.trim_right_matches(".gz").to_owned();
+
+        let mut dirs: Vec<String> = path.parent()
+            .unwrap()
+            .components()
+            .map(|c| c.as_os_str().to_string_lossy().into_owned())
+            .collect();
+        let system = if layout.per_system { dirs.pop() } else { None };
+        if dirs.len() == 0 {
+            return Err(format!("{} isn't a log of a {:?} tree", path.display(), layout));
+        }
+
+        let metadata = [".metadata.json", ".metadata.json.gz"]
+            .iter()
+            .map(|suffix| path.with_file_name(format!("{}{}", attempt_id, suffix)))
+            .find(|metadata| root.join(metadata).is_file());
+
+        let mut log = Log {
+            routing_key: dirs.join("/"),
+            system: system,
+            attempt_id: attempt_id,
+            path: path.clone(),
+            metadata: metadata,
+        };
+        if log.system.is_none() {
+            log.system = system_from_metadata(root, &log);
+        }
+        logs.push(log);
+    }
+
+    return Ok(logs);
+}
+
+fn walk(root: &Path, relative: &Path, files: &mut Vec<PathBuf>) -> Result<(), String> {
+    let dir = root.join(relative);
+    let mut entries: Vec<_> = fs::read_dir(&dir)
+        .map_err(|e| format!("Failed to list {}: {:?}", dir.display(), e))?
+        .filter_map(|entry| entry.ok())
+        .collect();
+    entries.sort_by_key(|entry| entry.file_name());
+
+    for entry in entries {
+        // Journals, and files being written, are hidden.
+        if entry.file_name().to_string_lossy().starts_with(".") {
+            continue;
+        }
+        let path = relative.join(entry.file_name());
+        if entry.path().is_dir() {
+            walk(root, &path, files)?;
+        } else {
+            files.push(path);
+        }
+    }
+
+    return Ok(());
+}
+
+fn read_source(root: &Path, path: &Path) -> Result<Vec<u8>, String> {
+    let mut data = Vec::new();
+    File::open(root.join(path))
+        .and_then(|mut f| f.read_to_end(&mut data))
+        .map_err(|e| format!("Failed to read {}: {:?}", path.display(), e))?;
+
+    let compressed = Layout {
+        per_system: false,
+        compressed: path.extension().map(|ext| ext == "gz").unwrap_or(false),
+    };
+    return compressed.decode(data);
+}
+
+fn system_from_metadata(root: &Path, log: &Log) -> Option<String> {
+    let metadata = read_source(root, log.metadata.as_ref()?).ok()?;
+    let metadata: Value = serde_json::from_slice(&metadata).ok()?;
+    return metadata["system"].as_str().map(|s| s.to_owned());
+}
+
+/// Where logs are migrated to.
+pub trait Store {
+    fn put(&self, key: &str, data: &[u8]) -> Result<(), String>;
+    fn get(&self, key: &str) -> Result<Vec<u8>, String>;
+}
+
+/// A log root on disk.
+pub struct Directory {
+    root: PathBuf,
+}
+
+impl Directory {
+    pub fn new(root: PathBuf) -> Directory {
+        Directory { root: root }
+    }
+
+    fn path(&self, key: &str) -> Result<PathBuf, String> {
+        let key = Path::new(key);
+        if !key.components().all(|c| match c {
+            Component::Normal(_) => true,
+            _ => false,
+        })
+        {
+            return Err(format!("Invalid key {}", key.display()));
+        }
+        return Ok(self.root.join(key));
+    }
+}
+
+impl Store for Directory {
+    fn put(&self, key: &str, data: &[u8]) -> Result<(), String> {
+        let path = self.path(key)?;
+        let dir = path.parent().unwrap();
+        fs::create_dir_all(dir).map_err(|e| format!("{:?}", e))?;
+
+        // Written aside and moved in place, so a log is never half there.
+        let partial = dir.join(format!(".{}.partial", path.file_name().unwrap().to_string_lossy()));
+        File::create(&partial)
+            .and_then(|mut f| f.write_all(data))
+            .and_then(|_| fs::rename(&partial, &path))
+            .map_err(|e| format!("Failed to write {}: {:?}", path.display(), e))
+    }
+
+    fn get(&self, key: &str) -> Result<Vec<u8>, String> {
+        let path = self.path(key)?;
+        let mut data = Vec::new();
+        File::open(&path)
+            .and_then(|mut f| f.read_to_end(&mut data))
+            .map_err(|e| format!("Failed to read {}: {:?}", path.display(), e))?;
+        return Ok(data);
+    }
+}
+
+/// An object store taking `PUT`s and `GET`s of keys under a URL, like a
+/// bucket behind an authenticating proxy.
+pub struct ObjectStore {
+    client: Client,
+    url: String,
+    token: Option<String>,
+}
+
+impl ObjectStore {
+    pub fn new(url: &str, token: Option<String>) -> ObjectStore {
+        ObjectStore {
+            client: Client::with_connector(HttpsConnector::new(NativeTlsClient::new().unwrap())),
+            url: url.trim_right_matches('/').to_owned(),
+            token: token,
+        }
+    }
+
+    fn headers(&self) -> Headers {
+        let mut headers = Headers::new();
+        headers.set(UserAgent("github.com/grahamc/ofborg".to_owned()));
+        if let Some(ref token) = self.token {
+            headers.set(Authorization(format!("Bearer {}", token)));
+        }
+        return headers;
+    }
+}
+
+impl Store for ObjectStore {
+    fn put(&self, key: &str, data: &[u8]) -> Result<(), String> {
+        let mut response = self.client
+            .put(&format!("{}/{}", self.url, key))
+            .headers(self.headers())
+            .body(data)
+            .send()
+            .map_err(|e| format!("{:?}", e))?;
+        if !response.status.is_success() {
+            let mut contents = String::new();
+            let _ = response.read_to_string(&mut contents);
+            return Err(format!("{}: {}", response.status, contents));
+        }
+        return Ok(());
+    }
+
+    fn get(&self, key: &str) -> Result<Vec<u8>, String> {
+        let mut response = self.client
+            .get(&format!("{}/{}", self.url, key))
+            .headers(self.headers())
+            .send()
+            .map_err(|e| format!("{:?}", e))?;
+        let mut data = Vec::new();
+        response.read_to_end(&mut data).map_err(|e| format!("{:?}", e))?;
+        if !response.status.is_success() {
+            return Err(format!("{}", response.status));
+        }
+        return Ok(data);
+    }
+}
+
+/// The logs already migrated, by their path under the source root, one
+/// per line.
+pub struct Journal {
+    done: HashSet<String>,
+    file: File,
+}
+
+impl Journal {
+    pub fn open(path: &Path) -> Result<Journal, String> {
+        let mut done = HashSet::new();
+        if path.exists() {
+            let file = File::open(path).map_err(|e| format!("{:?}", e))?;
+            for line in BufReader::new(file).lines() {
+                done.insert(line.map_err(|e| format!("{:?}", e))?);
+            }
+        }
+
+        let file = OpenOptions::new()
+            .append(true)
+            .create(true)
+            .open(path)
+            .map_err(|e| format!("Failed to open {}: {:?}", path.display(), e))?;
+        return Ok(Journal {
+            done: done,
+            file: file,
+        });
+    }
+
+    pub fn contains(&self, log: &Log) -> bool {
+        self.done.contains(&log.path.to_string_lossy().into_owned())
+    }
+
+    fn record(&mut self, log: &Log) -> Result<(), String> {
+        let path = log.path.to_string_lossy().into_owned();
+        writeln!(self.file, "{}", path).map_err(|e| format!("{:?}", e))?;
+        self.done.insert(path);
+        return Ok(());
+    }
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub struct Summary {
+    pub migrated: usize,
+    pub skipped: usize,
+    /// The logs which failed, by their path, and why.
+    pub failed: Vec<(String, String)>,
+}
+
+/// Copy `logs`, found under `from`, into `store` in `layout`. Logs in
+/// the journal are skipped, and the rest recorded once their copy reads
+/// back the same. The source is left as it was.
+pub fn migrate(
+    from: &Path,
+    logs: &Vec<Log>,
+    layout: &Layout,
+    store: &Store,
+    journal: &mut Journal,
+) -> Summary {
+    let mut summary = Summary::default();
+
+    for log in logs.iter() {
+        if journal.contains(log) {
+            summary.skipped += 1;
+            continue;
+        }
+
+        let copied = copy(from, &log.path, &layout.key(log, false), layout, store)
+            .and_then(|_| match log.metadata {
+                Some(ref metadata) => copy(from, metadata, &layout.key(log, true), layout, store),
+                None => Ok(()),
+            })
+            .and_then(|_| journal.record(log));
+        match copied {
+            Ok(()) => summary.migrated += 1,
+            Err(e) => {
+                warn!("Failed to migrate {}: {}", log.path.display(), e);
+                summary.failed.push((log.path.to_string_lossy().into_owned(), e));
+            }
+        }
+    }
+
+    return summary;
+}
+
+fn copy(from: &Path, path: &Path, key: &str, layout: &Layout, store: &Store) -> Result<(), String> {
+    let data = read_source(from, path)?;
+    store.put(key, &layout.encode(&data)?)?;
+
+    let copied = layout.decode(store.get(key)?)?;
+    if copied != data {
+        return Err(format!("{} doesn't read back the same", key));
+    }
+    return Ok(());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ofborg::test_scratch::TestScratch;
+
+    fn write(root: &Path, path: &str, data: &[u8]) {
+        let path = root.join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        File::create(path).unwrap().write_all(data).unwrap();
+    }
+
+    fn flat_tree(root: &Path) {
+        write(root, "nixos/nixpkgs.1/attempt-a", b"building hello\n");
+        write(
+            root,
+            "nixos/nixpkgs.1/attempt-a.metadata.json",
+            br#"{"system":"x86_64-linux","identity":"builder"}"#,
+        );
+        write(root, "nixos/nixpkgs.2/attempt-b", b"building world\n");
+    }
+
+    #[test]
+    fn test_parse_layout() {
+        assert_eq!(
+            Layout::parse("flat"),
+            Ok(Layout {
+                per_system: false,
+                compressed: false,
+            })
+        );
+        assert_eq!(
+            Layout::parse("per-system+gzip"),
+            Ok(Layout {
+                per_system: true,
+                compressed: true,
+            })
+        );
+        assert!(Layout::parse("per-pr").is_err());
+        assert!(Layout::parse("flat+zstd").is_err());
+    }
+
+    #[test]
+    fn test_scan() {
+        let scratch = TestScratch::new_dir("logmigrate-scan");
+        flat_tree(&scratch.path());
+
+        let logs = scan(&scratch.path(), &Layout::parse("flat").unwrap()).unwrap();
+        assert_eq!(
+            logs,
+            vec![
+                Log {
+                    routing_key: String::from("nixos/nixpkgs.1"),
+                    system: Some(String::from("x86_64-linux")),
+                    attempt_id: String::from("attempt-a"),
+                    path: PathBuf::from("nixos/nixpkgs.1/attempt-a"),
+                    metadata: Some(PathBuf::from("nixos/nixpkgs.1/attempt-a.metadata.json")),
+                },
+                Log {
+                    routing_key: String::from("nixos/nixpkgs.2"),
+                    system: None,
+                    attempt_id: String::from("attempt-b"),
+                    path: PathBuf::from("nixos/nixpkgs.2/attempt-b"),
+                    metadata: None,
+                },
+            ]
+        );
+
+        let layout = Layout::parse("per-system+gzip").unwrap();
+        assert_eq!(layout.key(&logs[0], false), "nixos/nixpkgs.1/x86_64-linux/attempt-a.gz");
+        assert_eq!(
+            layout.key(&logs[0], true),
+            "nixos/nixpkgs.1/x86_64-linux/attempt-a.metadata.json.gz"
+        );
+        assert_eq!(layout.key(&logs[1], false), "nixos/nixpkgs.2/unknown/attempt-b.gz");
+    }
+
+    #[test]
+    fn test_migrate() {
+        let from = TestScratch::new_dir("logmigrate-from");
+        let to = TestScratch::new_dir("logmigrate-to");
+        let journal_file = TestScratch::new_file("logmigrate-journal");
+        flat_tree(&from.path());
+
+        let layout = Layout::parse("per-system+gzip").unwrap();
+        let store = Directory::new(to.path());
+        let logs = scan(&from.path(), &Layout::parse("flat").unwrap()).unwrap();
+        let mut journal = Journal::open(&journal_file.path()).unwrap();
+        assert_eq!(
+            migrate(&from.path(), &logs, &layout, &store, &mut journal),
+            Summary {
+                migrated: 2,
+                skipped: 0,
+                failed: vec![],
+            }
+        );
+
+        let migrated = scan(&to.path(), &Layout::parse("per-system").unwrap()).unwrap();
+        assert_eq!(migrated.len(), 2);
+        assert_eq!(migrated[0].system, Some(String::from("x86_64-linux")));
+        assert_eq!(
+            read_source(&to.path(), &migrated[0].path).unwrap(),
+            b"building hello\n".to_vec()
+        );
+
+        // Picking up again skips what's in the journal.
+        write(&from.path(), "nixos/nixpkgs.3/attempt-c", b"building more\n");
+        let logs = scan(&from.path(), &Layout::parse("flat").unwrap()).unwrap();
+        let mut journal = Journal::open(&journal_file.path()).unwrap();
+        assert_eq!(
+            migrate(&from.path(), &logs, &layout, &store, &mut journal),
+            Summary {
+                migrated: 1,
+                skipped: 2,
+                failed: vec![],
+            }
+        );
+    }
+}
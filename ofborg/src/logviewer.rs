@@ -0,0 +1,218 @@
+//! Browse the build logs under a log root from a terminal: list the
+//! recent attempts, open one, follow it as the collector writes it, and
+//! search it.
+
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use serde_json;
+
+use ofborg::logmigrate::{self, Layout};
+use ofborg::message::buildlogmsg::BuildLogStart;
+use ofborg::nix::Capabilities;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Attempt {
+    /// Like `nixos/nixpkgs.12345`.
+    pub routing_key: String,
+    pub attempt_id: String,
+    pub system: Option<String>,
+    pub identity: Option<String>,
+    pub capabilities: Option<Capabilities>,
+    pub attrs: Vec<String>,
+    pub path: PathBuf,
+    pub modified: SystemTime,
+}
+
+impl Attempt {
+    /// One line of the list of attempts, at `now`.
+    pub fn summary(&self, now: SystemTime) -> String {
+        let age = now.duration_since(self.modified).unwrap_or(Duration::from_secs(0));
+        let mut line = format!(
+            "{} {} {} {}",
+            ago(age),
+            self.routing_key,
+            self.system.as_ref().map(|s| s.as_str()).unwrap_or("?"),
+            self.attempt_id
+        );
+        if self.attrs.len() > 0 {
+            line.push_str(&format!(" ({})", self.attrs.join(", ")));
+        }
+        return line;
+    }
+
+    pub fn matches(&self, filter: &str) -> bool {
+        let filter = filter.to_lowercase();
+        return self.routing_key.to_lowercase().contains(&filter) ||
+            self.attempt_id.to_lowercase().contains(&filter) ||
+            self.attrs.iter().any(|attr| attr.to_lowercase().contains(&filter));
+    }
+}
+
+fn ago(age: Duration) -> String {
+    let secs = age.as_secs();
+    if secs < 60 {
+        return format!("{:>3}s", secs);
+    } else if secs < 60 * 60 {
+        return format!("{:>3}m", secs / 60);
+    } else if secs < 60 * 60 * 24 {
+        return format!("{:>3}h", secs / (60 * 60));
+    } else {
+        return format!("{:>3}d", secs / (60 * 60 * 24));
+    }
+}
+
+/// The `limit` attempts under `root` written to last, most recent first.
+pub fn recent(root: &Path, limit: usize) -> Result<Vec<Attempt>, String> {
+    return recent_under(root, Path::new(""), limit);
+}
+
+/// Like `recent`, of the attempts of the routing key `under`, like
+/// `nixos/nixpkgs.12345`.
+pub fn recent_under(root: &Path, under: &Path, limit: usize) -> Result<Vec<Attempt>, String> {
+    let flat = Layout {
+        per_system: false,
+        compressed: false,
+    };
+
+    let mut attempts: Vec<Attempt> = vec![];
+    for log in logmigrate::scan_under(root, under, &flat)? {
+        let path = root.join(&log.path);
+        let modified = match fs::metadata(&path).and_then(|m| m.modified()) {
+            Ok(modified) => modified,
+            Err(_) => continue,
+        };
+        let start: Option<BuildLogStart> = log.metadata.as_ref().and_then(|metadata| {
+            File::open(root.join(metadata)).ok().and_then(|f| serde_json::from_reader(f).ok())
+        });
+
+        attempts.push(Attempt {
+            routing_key: log.routing_key,
+            attempt_id: log.attempt_id,
+            system: log.system,
+            identity: start.as_ref().map(|start| start.identity.clone()),
+            capabilities: start.as_ref().and_then(|start| start.capabilities.clone()),
+            attrs: start
+                .and_then(|start| start.attempted_attrs)
+                .unwrap_or(vec![]),
+            path: path,
+            modified: modified,
+        });
+    }
+
+    attempts.sort_by(|a, b| b.modified.cmp(&a.modified));
+    attempts.truncate(limit);
+    return Ok(attempts);
+}
+
+/// Follows a log as it's written. The collector rewrites a log when a
+/// line arrives out of order, so it's read again from the start rather
+/// than from where it was last read.
+pub struct Tail {
+    path: PathBuf,
+    seen: usize,
+}
+
+impl Tail {
+    pub fn new(path: PathBuf) -> Tail {
+        Tail {
+            path: path,
+            seen: 0,
+        }
+    }
+
+    /// The lines written since the last call, or every line the first
+    /// time.
+    pub fn poll(&mut self) -> Result<Vec<String>, String> {
+        let lines = read_lines(&self.path)?;
+        let new = lines.into_iter().skip(self.seen).collect::<Vec<String>>();
+        self.seen += new.len();
+        return Ok(new);
+    }
+}
+
+pub fn read_lines(path: &Path) -> Result<Vec<String>, String> {
+    let file = File::open(path).map_err(|e| format!("Failed to open {}: {:?}", path.display(), e))?;
+    return Ok(
+        BufReader::new(file)
+            .lines()
+            .map(|line| line.unwrap_or_else(|e| format!("UTF-8 Decode err: {:?}", e)))
+            .collect(),
+    );
+}
+
+/// The lines containing `pattern`, ignoring case, by their number
+/// counting from 1.
+pub fn search<'a>(lines: &'a [String], pattern: &str) -> Vec<(usize, &'a str)> {
+    let pattern = pattern.to_lowercase();
+    return lines
+        .iter()
+        .enumerate()
+        .filter(|&(_, line)| line.to_lowercase().contains(&pattern))
+        .map(|(i, line)| (i + 1, line.as_str()))
+        .collect();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use ofborg::test_scratch::TestScratch;
+
+    fn write(root: &Path, path: &str, data: &str) {
+        let path = root.join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        File::create(path).unwrap().write_all(data.as_bytes()).unwrap();
+    }
+
+    #[test]
+    fn test_recent() {
+        let scratch = TestScratch::new_dir("logviewer-recent");
+        let root = scratch.path();
+        write(&root, "nixos/nixpkgs.1/attempt-a", "building hello\n");
+        write(
+            &root,
+            "nixos/nixpkgs.1/attempt-a.metadata.json",
+            r#"{"system":"x86_64-linux","identity":"builder-1","attempt_id":"attempt-a","attempted_attrs":["hello"],"skipped_attrs":null}"#,
+        );
+        write(&root, "nixos/nixpkgs.2/attempt-b", "building world\n");
+
+        let attempts = recent(&root, 10).unwrap();
+        assert_eq!(attempts.len(), 2);
+        let hello = attempts.iter().find(|a| a.attempt_id == "attempt-a").unwrap();
+        assert_eq!(hello.routing_key, "nixos/nixpkgs.1");
+        assert_eq!(hello.system, Some(String::from("x86_64-linux")));
+        assert_eq!(hello.identity, Some(String::from("builder-1")));
+        assert_eq!(hello.attrs, vec![String::from("hello")]);
+        assert!(hello.matches("HELLO"));
+        assert!(!hello.matches("world"));
+        assert_eq!(
+            hello.summary(hello.modified + Duration::from_secs(125)),
+            "  2m nixos/nixpkgs.1 x86_64-linux attempt-a (hello)"
+        );
+
+        assert_eq!(recent(&root, 1).unwrap().len(), 1);
+        let pr = recent_under(&root, Path::new("nixos/nixpkgs.2"), 10).unwrap();
+        assert_eq!(pr.len(), 1);
+        assert_eq!(pr[0].routing_key, "nixos/nixpkgs.2");
+    }
+
+    #[test]
+    fn test_tail_and_search() {
+        let scratch = TestScratch::new_file("logviewer-tail");
+        File::create(scratch.path()).unwrap().write_all(b"one\ntwo\n").unwrap();
+
+        let mut tail = Tail::new(scratch.path());
+        assert_eq!(tail.poll().unwrap(), vec!["one", "two"]);
+        assert_eq!(tail.poll().unwrap(), Vec::<String>::new());
+
+        File::create(scratch.path()).unwrap().write_all(b"one\ntwo\nthree: error\n").unwrap();
+        assert_eq!(tail.poll().unwrap(), vec!["three: error"]);
+
+        let lines = read_lines(&scratch.path()).unwrap();
+        assert_eq!(search(&lines, "ERROR"), vec![(3, "three: error")]);
+        assert_eq!(search(&lines, "t"), vec![(2, "two"), (3, "three: error")]);
+    }
+}
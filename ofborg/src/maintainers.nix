@@ -0,0 +1,33 @@
+{ changedattrsjson }:
+let
+  pkgs = import ./. {
+    config = {
+      allowBroken = true;
+      allowUnfree = true;
+      allowInsecurePredicate = x: true;
+    };
+  };
+  lib = pkgs.lib;
+
+  changedattrs = builtins.fromJSON (builtins.readFile changedattrsjson);
+
+  githubHandle = maintainer:
+    if builtins.isAttrs maintainer
+      then maintainer.github or null
+      else null;
+
+  # Attributes which fail to evaluate are treated as unmaintained,
+  # the evaluation checks are responsible for reporting those.
+  maintainersOf = attrpath:
+    let
+      pkg = builtins.tryEval
+        (lib.attrByPath (lib.splitString "." attrpath) null pkgs);
+      maintainers = if pkg.success && pkg.value != null
+        then builtins.tryEval (pkg.value.meta.maintainers or [])
+        else { success = true; value = []; };
+    in if maintainers.success
+      then builtins.filter (handle: handle != null)
+        (map githubHandle maintainers.value)
+      else [];
+in
+  lib.genAttrs changedattrs maintainersOf
@@ -0,0 +1,203 @@
+use std::collections::HashMap;
+use std::path::Path;
+use serde_json;
+use ofborg::nix;
+use ofborg::changedattrs;
+
+pub const PING_COMMENT_MARKER: &'static str = "<!-- ofborg-maintainer-ping -->";
+
+type Maintainer = String;
+type Package = String;
+
+#[derive(Debug, PartialEq)]
+pub struct ImpactedMaintainers(HashMap<Maintainer, Vec<Package>>);
+
+#[derive(Debug)]
+pub enum CalculationError {
+    Evaluation(String),
+    Deserialize(serde_json::Error),
+}
+
+impl ImpactedMaintainers {
+    pub fn calculate(
+        nix: &nix::Nix,
+        checkout: &Path,
+        attrs: &Vec<Package>,
+    ) -> Result<ImpactedMaintainers, CalculationError> {
+        let output = changedattrs::evaluate(
+            nix,
+            checkout,
+            "maintainers.nix",
+            include_str!("maintainers.nix"),
+            attrs,
+        ).map_err(|e| CalculationError::Evaluation(e))?;
+
+        ImpactedMaintainers::parse(&output)
+    }
+
+    fn parse(output: &str) -> Result<ImpactedMaintainers, CalculationError> {
+        let by_package: HashMap<Package, Vec<Maintainer>> =
+            serde_json::from_str(output).map_err(|e| CalculationError::Deserialize(e))?;
+
+        let mut by_maintainer: HashMap<Maintainer, Vec<Package>> = HashMap::new();
+        for (package, maintainers) in by_package {
+            for maintainer in maintainers {
+                by_maintainer
+                    .entry(maintainer.to_lowercase())
+                    .or_insert(vec![])
+                    .push(package.clone());
+            }
+        }
+
+        for packages in by_maintainer.values_mut() {
+            packages.sort();
+            packages.dedup();
+        }
+
+        return Ok(ImpactedMaintainers(by_maintainer));
+    }
+
+    pub fn maintainers(&self) -> Vec<Maintainer> {
+        let mut maintainers: Vec<Maintainer> = self.0.keys().cloned().collect();
+        maintainers.sort();
+        return maintainers;
+    }
+
+    pub fn packages_for(&self, maintainer: &str) -> Vec<Package> {
+        self.0.get(maintainer).cloned().unwrap_or(vec![])
+    }
+
+    pub fn excluding(&self, users: &Vec<String>) -> ImpactedMaintainers {
+        let users: Vec<String> = users.iter().map(|u| u.to_lowercase()).collect();
+
+        ImpactedMaintainers(
+            self.0
+                .iter()
+                .filter(|&(maintainer, _)| !users.contains(maintainer))
+                .map(|(maintainer, packages)| (maintainer.clone(), packages.clone()))
+                .collect(),
+        )
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+pub fn ping_comment(maintainers: &ImpactedMaintainers) -> String {
+    let mut reply: Vec<String> = vec![
+        PING_COMMENT_MARKER.to_owned(),
+        "This PR changes packages maintained by:".to_owned(),
+        "".to_owned(),
+    ];
+
+    reply.extend(ping_lines(maintainers));
+    reply.push("".to_owned());
+
+    reply.join("\n")
+}
+
+/// The ping comment `body`, mentioning `maintainers` too.
+pub fn extend_ping_comment(body: &str, maintainers: &ImpactedMaintainers) -> String {
+    let mut reply: Vec<String> = vec![body.trim_right().to_owned()];
+    reply.extend(ping_lines(maintainers));
+    reply.push("".to_owned());
+
+    reply.join("\n")
+}
+
+fn ping_lines(maintainers: &ImpactedMaintainers) -> Vec<String> {
+    maintainers
+        .maintainers()
+        .into_iter()
+        .map(|maintainer| {
+            format!(
+                " - @{}: {}",
+                maintainer,
+                maintainers.packages_for(&maintainer).join(", ")
+            )
+        })
+        .collect()
+}
+
+pub fn is_ping_comment(body: &str) -> bool {
+    body.contains(PING_COMMENT_MARKER)
+}
+
+/// Find the maintainers a previous ping comment already mentioned.
+pub fn previously_pinged(body: &str) -> Vec<Maintainer> {
+    if !is_ping_comment(body) {
+        return vec![];
+    }
+
+    body.lines()
+        .filter(|line| line.starts_with(" - @"))
+        .filter_map(|line| line[4..].splitn(2, ":").next())
+        .map(|maintainer| maintainer.trim().to_lowercase())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEST_OUTPUT: &'static str = "{\"firefox\":[\"Grahamc\",\"edolstra\"],\"hello\":[\"grahamc\"],\"jq\":[]}";
+
+    #[test]
+    fn test_parse_and_invert() {
+        let maintainers = ImpactedMaintainers::parse(TEST_OUTPUT).unwrap();
+
+        assert_eq!(maintainers.maintainers(), vec!["edolstra", "grahamc"]);
+        assert_eq!(maintainers.packages_for("grahamc"), vec!["firefox", "hello"]);
+        assert_eq!(maintainers.packages_for("edolstra"), vec!["firefox"]);
+    }
+
+    #[test]
+    fn test_excluding_participants() {
+        let maintainers = ImpactedMaintainers::parse(TEST_OUTPUT).unwrap();
+
+        let remaining = maintainers.excluding(&vec!["GrahamC".to_owned()]);
+        assert_eq!(remaining.maintainers(), vec!["edolstra"]);
+        assert!(maintainers.excluding(&vec!["grahamc".to_owned(), "edolstra".to_owned()]).is_empty());
+    }
+
+    #[test]
+    fn test_ping_comment_round_trip() {
+        let maintainers = ImpactedMaintainers::parse(TEST_OUTPUT).unwrap();
+        let comment = ping_comment(&maintainers);
+
+        assert_eq!(
+            &comment,
+            "<!-- ofborg-maintainer-ping -->
+This PR changes packages maintained by:
+
+ - @edolstra: firefox
+ - @grahamc: firefox, hello
+"
+        );
+
+        assert_eq!(previously_pinged(&comment), vec!["edolstra", "grahamc"]);
+        assert_eq!(previously_pinged(" - @grahamc: hello"), Vec::<String>::new());
+    }
+
+    #[test]
+    fn test_extend_ping_comment() {
+        let maintainers = ImpactedMaintainers::parse(TEST_OUTPUT).unwrap();
+        let first = ping_comment(&maintainers.excluding(&vec!["edolstra".to_owned()]));
+        let extended = extend_ping_comment(
+            &first,
+            &maintainers.excluding(&previously_pinged(&first)),
+        );
+        assert_eq!(
+            &extended,
+            "<!-- ofborg-maintainer-ping -->
+This PR changes packages maintained by:
+
+ - @grahamc: firefox, hello
+ - @edolstra: firefox
+"
+        );
+        assert!(is_ping_comment(&extended));
+        assert_eq!(previously_pinged(&extended), vec!["grahamc", "edolstra"]);
+    }
+}
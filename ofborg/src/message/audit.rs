@@ -0,0 +1,76 @@
+use serde_json;
+
+use ofborg::correlation::Correlation;
+use ofborg::message::envelope::{self, Versioned};
+
+/// Every authorization decision is published here, for operators to
+/// find out who started what.
+pub const EXCHANGE: &'static str = "audit-events";
+
+pub fn from(
+    data: &Vec<u8>,
+    content_type: Option<&str>,
+) -> Result<AuditEvent, serde_json::error::Error> {
+    return envelope::from_slice(&data, content_type);
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all="snake_case")]
+pub enum Decision {
+    Allowed,
+    Denied,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct AuditEvent {
+    pub who: String,
+    pub command: String,
+    pub repo: String,
+    pub pr: u64,
+    pub decision: Decision,
+    #[serde(default)]
+    pub reason: String,
+    /// Seconds since the epoch.
+    #[serde(default)]
+    pub at: u64,
+    /// See `correlation`.
+    #[serde(default)]
+    pub correlation_id: Option<String>,
+    #[serde(default)]
+    pub caused_by: Option<String>,
+}
+
+impl AuditEvent {
+    pub fn new(
+        who: String,
+        command: String,
+        repo: String,
+        pr: u64,
+        decision: Decision,
+        reason: String,
+        at: u64,
+    ) -> AuditEvent {
+        return AuditEvent {
+            who: who,
+            command: command,
+            repo: repo,
+            pr: pr,
+            decision: decision,
+            reason: reason,
+            at: at,
+            correlation_id: None,
+            caused_by: None,
+        };
+    }
+
+    pub fn correlated(mut self, correlation: &Correlation) -> AuditEvent {
+        self.correlation_id = correlation.id.clone();
+        self.caused_by = correlation.caused_by.clone();
+        return self;
+    }
+}
+
+impl Versioned for AuditEvent {
+    const SCHEMA: &'static str = "audit-event";
+    const VERSION: u32 = 1;
+}
@@ -1,15 +1,46 @@
 use ofborg::message::{Pr, Repo};
-use ofborg::commentparser::Subset;
+use ofborg::message::envelope::{self, Versioned};
+use ofborg::commentparser::{BuildFlags, Subset};
+use ofborg::correlation::Correlation;
 use serde_json;
 
+/// The `x-max-priority` builders declare their queues with. Builds of
+/// security updates are published with it, to be taken before the
+/// others waiting.
+pub const MAX_PRIORITY: u8 = 1;
+
 #[derive(Serialize, Deserialize, Debug)]
 pub struct BuildJob {
     pub repo: Repo,
     pub pr: Pr,
+    #[serde(default)]
     pub subset: Option<Subset>,
+    #[serde(default)]
     pub attrs: Vec<String>,
+    #[serde(default)]
     pub logs: Option<ExchangeQueue>, // (Exchange, Routing Key)
+    #[serde(default)]
     pub statusreport: Option<ExchangeQueue>, // (Exchange, Routing Key)
+    #[serde(default)]
+    pub flags: BuildFlags,
+    /// Features the builder's nix must have, see
+    /// `nix::Capabilities::missing`.
+    #[serde(default)]
+    pub requires: Vec<String>,
+    /// Who the builds are accounted to, like the commenter who asked
+    /// for them.
+    #[serde(default)]
+    pub requested_by: Option<String>,
+    /// See `correlation`.
+    #[serde(default)]
+    pub correlation_id: Option<String>,
+    #[serde(default)]
+    pub caused_by: Option<String>,
+}
+
+impl Versioned for BuildJob {
+    const SCHEMA: &'static str = "build-job";
+    const VERSION: u32 = 1;
 }
 
 pub type ExchangeQueue = (Option<Exchange>, Option<RoutingKey>);
@@ -41,12 +72,55 @@ impl BuildJob {
             statusreport: Some(statusreport.unwrap_or(
                 (Some("build-results".to_owned()), None),
             )),
+            flags: BuildFlags::default(),
+            requires: vec![],
+            requested_by: None,
+            correlation_id: None,
+            caused_by: None,
+        }
+    }
+
+    pub fn with_flags(mut self, flags: BuildFlags) -> BuildJob {
+        self.flags = flags;
+        return self;
+    }
+
+    pub fn with_requirements(mut self, requires: Vec<String>) -> BuildJob {
+        self.requires = requires;
+        return self;
+    }
+
+    pub fn requested_by(mut self, user: &str) -> BuildJob {
+        self.requested_by = Some(user.to_owned());
+        return self;
+    }
+
+    pub fn correlated(mut self, correlation: &Correlation) -> BuildJob {
+        self.correlation_id = correlation.id.clone();
+        self.caused_by = correlation.caused_by.clone();
+        return self;
+    }
+
+    pub fn correlation(&self) -> Correlation {
+        return Correlation::of(&self.correlation_id, &self.caused_by);
+    }
+
+    /// What the job needs of the builder: what it asked for, and the
+    /// `nixos-test` system feature to run NixOS tests.
+    pub fn requirements(&self) -> Vec<String> {
+        let mut requirements = self.requires.clone();
+        if self.subset == Some(Subset::NixOS) && !requirements.iter().any(|r| r == "nixos-test") {
+            requirements.push(String::from("nixos-test"));
         }
+        return requirements;
     }
 }
 
-pub fn from(data: &Vec<u8>) -> Result<BuildJob, serde_json::error::Error> {
-    return serde_json::from_slice(&data);
+pub fn from(
+    data: &Vec<u8>,
+    content_type: Option<&str>,
+) -> Result<BuildJob, serde_json::error::Error> {
+    return envelope::from_slice(&data, content_type);
 }
 
 pub struct Actions {
@@ -1,4 +1,9 @@
 
+use ofborg::correlation::Correlation;
+use ofborg::message::envelope::Versioned;
+use ofborg::nix;
+use ofborg::nixpath;
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct BuildLogMsg {
     pub system: String,
@@ -6,6 +11,42 @@ pub struct BuildLogMsg {
     pub attempt_id: String,
     pub line_number: u64,
     pub output: String,
+    /// See `correlation`.
+    #[serde(default)]
+    pub correlation_id: Option<String>,
+    #[serde(default)]
+    pub caused_by: Option<String>,
+}
+
+impl BuildLogMsg {
+    pub fn new(
+        system: String,
+        identity: String,
+        attempt_id: String,
+        line_number: u64,
+        output: String,
+    ) -> BuildLogMsg {
+        return BuildLogMsg {
+            system: system,
+            identity: identity,
+            attempt_id: attempt_id,
+            line_number: line_number,
+            output: output,
+            correlation_id: None,
+            caused_by: None,
+        };
+    }
+
+    pub fn correlated(mut self, correlation: &Correlation) -> BuildLogMsg {
+        self.correlation_id = correlation.id.clone();
+        self.caused_by = correlation.caused_by.clone();
+        return self;
+    }
+}
+
+impl Versioned for BuildLogMsg {
+    const SCHEMA: &'static str = "build-log-msg";
+    const VERSION: u32 = 1;
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -13,6 +54,62 @@ pub struct BuildLogStart {
     pub system: String,
     pub identity: String,
     pub attempt_id: String,
+    #[serde(default)]
     pub attempted_attrs: Option<Vec<String>>,
+    #[serde(default)]
     pub skipped_attrs: Option<Vec<String>>,
+    /// What the builder's nix can do.
+    #[serde(default)]
+    pub capabilities: Option<nix::Capabilities>,
+    /// The nixpkgs `<nixpkgs>` was, if it wasn't the checkout.
+    #[serde(default)]
+    pub pin: Option<nixpath::Pin>,
+    /// See `correlation`.
+    #[serde(default)]
+    pub correlation_id: Option<String>,
+    #[serde(default)]
+    pub caused_by: Option<String>,
+}
+
+impl BuildLogStart {
+    pub fn new(
+        system: String,
+        identity: String,
+        attempt_id: String,
+        attempted_attrs: Vec<String>,
+        skipped_attrs: Vec<String>,
+    ) -> BuildLogStart {
+        return BuildLogStart {
+            system: system,
+            identity: identity,
+            attempt_id: attempt_id,
+            attempted_attrs: Some(attempted_attrs),
+            skipped_attrs: Some(skipped_attrs),
+            capabilities: None,
+            pin: None,
+            correlation_id: None,
+            caused_by: None,
+        };
+    }
+
+    pub fn with_capabilities(mut self, capabilities: nix::Capabilities) -> BuildLogStart {
+        self.capabilities = Some(capabilities);
+        return self;
+    }
+
+    pub fn with_pin(mut self, pin: Option<nixpath::Pin>) -> BuildLogStart {
+        self.pin = pin;
+        return self;
+    }
+
+    pub fn correlated(mut self, correlation: &Correlation) -> BuildLogStart {
+        self.correlation_id = correlation.id.clone();
+        self.caused_by = correlation.caused_by.clone();
+        return self;
+    }
+}
+
+impl Versioned for BuildLogStart {
+    const SCHEMA: &'static str = "build-log-start";
+    const VERSION: u32 = 1;
 }
@@ -1,13 +1,336 @@
 use ofborg::message::{Pr, Repo};
+use ofborg::correlation::Correlation;
+use ofborg::message::envelope::{self, Versioned};
+use ofborg::nix::Realisation;
+use serde_json::{self, Value};
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all="snake_case")]
+pub enum AttrStatus {
+    Success,
+    /// It failed, or was built alongside one which did.
+    Failure,
+    /// Not attempted, it can't be built on this system or builder.
+    Skipped,
+    /// Not attempted, or stopped, because the job was cancelled.
+    Cancelled,
+    /// Not attempted, the PR didn't merge into its target branch.
+    MergeFailed,
+}
+
+impl AttrStatus {
+    pub fn attempted(&self) -> bool {
+        match self {
+            &AttrStatus::Success | &AttrStatus::Failure => true,
+            _ => false,
+        }
+    }
+}
+
+/// The lines of the attempt's log its build wrote, both inclusive.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct LogExcerpt {
+    pub first_line: u64,
+    pub last_line: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct AttrResult {
+    pub attr: String,
+    pub status: AttrStatus,
+    /// How long the build it was part of took.
+    #[serde(default)]
+    pub duration_secs: Option<u64>,
+    /// Its outputs, when the builder could tell them apart from the
+    /// other attrs'.
+    #[serde(default)]
+    pub out_paths: Vec<String>,
+    /// `None` when the builder doesn't send full logs.
+    #[serde(default)]
+    pub log: Option<LogExcerpt>,
+}
+
+impl AttrResult {
+    pub fn new(attr: String, status: AttrStatus) -> AttrResult {
+        return AttrResult {
+            attr: attr,
+            status: status,
+            duration_secs: None,
+            out_paths: vec![],
+            log: None,
+        };
+    }
+}
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct BuildResult {
     pub repo: Repo,
     pub pr: Pr,
     pub system: String,
+    #[serde(default)]
     pub output: Vec<String>,
     pub attempt_id: String,
+    #[serde(default)]
+    pub attrs: Vec<AttrResult>,
+    /// The PR didn't merge, which version 1 results say without
+    /// naming any attrs.
+    #[serde(default)]
+    pub merge_failed: bool,
+    /// It passed, which version 1 results from builders that didn't
+    /// report attrs say without naming any.
+    #[serde(default)]
+    pub passed_without_attrs: bool,
+    /// Where the content-addressed derivations built went.
+    #[serde(default)]
+    pub realisations: Option<Vec<Realisation>>,
+    /// See `correlation`.
+    #[serde(default)]
+    pub correlation_id: Option<String>,
+    #[serde(default)]
+    pub caused_by: Option<String>,
+}
+
+impl Versioned for BuildResult {
+    const SCHEMA: &'static str = "build-result";
+    const VERSION: u32 = 2;
+
+    fn upgrade(_version: u32, payload: Value) -> Result<Value, String> {
+        let legacy: LegacyBuildResult = serde_json::from_value(payload).map_err(
+            |e| format!("{}", e),
+        )?;
+        return serde_json::to_value(BuildResult::from_legacy(legacy)).map_err(
+            |e| format!("{}", e),
+        );
+    }
+}
+
+impl BuildResult {
+    pub fn correlation(&self) -> Correlation {
+        return Correlation::of(&self.correlation_id, &self.caused_by);
+    }
+
+    /// Whether the build failed, `None` if nothing was built.
+    pub fn success(&self) -> Option<bool> {
+        let failed = self.merge_failed || self.attrs.iter().any(|attr| match attr.status {
+            AttrStatus::Failure | AttrStatus::MergeFailed => true,
+            _ => false,
+        });
+        if failed {
+            return Some(false);
+        }
+
+        if self.passed_without_attrs ||
+            self.attrs.iter().any(|attr| attr.status == AttrStatus::Success)
+        {
+            return Some(true);
+        }
+
+        return None;
+    }
+
+    pub fn attempted_attrs(&self) -> Vec<String> {
+        return self.attrs
+            .iter()
+            .filter(|attr| attr.status.attempted())
+            .map(|attr| attr.attr.clone())
+            .collect();
+    }
+
+    /// The attrs which weren't attempted, but not because the merge
+    /// failed, as version 1 had it.
+    pub fn skipped_attrs(&self) -> Vec<String> {
+        return self.attrs
+            .iter()
+            .filter(|attr| match attr.status {
+                AttrStatus::Skipped | AttrStatus::Cancelled => true,
+                _ => false,
+            })
+            .map(|attr| attr.attr.clone())
+            .collect();
+    }
+
+    /// Version 1, for consumers which haven't been upgraded yet.
+    pub fn legacy(&self) -> LegacyBuildResult {
+        let attempted = self.attempted_attrs();
+        let skipped = self.skipped_attrs();
+        return LegacyBuildResult {
+            repo: self.repo.clone(),
+            pr: self.pr.clone(),
+            system: self.system.clone(),
+            output: self.output.clone(),
+            attempt_id: self.attempt_id.clone(),
+            success: self.success(),
+            skipped_attrs: if skipped.is_empty() { None } else { Some(skipped) },
+            attempted_attrs: if attempted.is_empty() { None } else { Some(attempted) },
+            realisations: self.realisations.clone(),
+            correlation_id: self.correlation_id.clone(),
+            caused_by: self.caused_by.clone(),
+        };
+    }
+
+    pub fn from_legacy(legacy: LegacyBuildResult) -> BuildResult {
+        // Builders only fail without attempting or skipping anything
+        // when the merge failed.
+        let no_attrs = legacy.attempted_attrs.as_ref().map_or(true, |attrs| attrs.is_empty()) &&
+            legacy.skipped_attrs.as_ref().map_or(true, |attrs| attrs.is_empty());
+        let merge_failed = no_attrs && legacy.success == Some(false);
+        let passed_without_attrs = no_attrs && legacy.success == Some(true);
+
+        let attempted = match legacy.success {
+            Some(true) => AttrStatus::Success,
+            _ => AttrStatus::Failure,
+        };
+        // Cancelled builds only said so in their output.
+        let skipped = match legacy.output.first() {
+            Some(line) if line == "Cancelled by request" => AttrStatus::Cancelled,
+            _ => AttrStatus::Skipped,
+        };
+
+        let mut attrs: Vec<AttrResult> = vec![];
+        for attr in legacy.attempted_attrs.unwrap_or(vec![]) {
+            attrs.push(AttrResult::new(attr, attempted));
+        }
+        for attr in legacy.skipped_attrs.unwrap_or(vec![]) {
+            attrs.push(AttrResult::new(attr, skipped));
+        }
+
+        return BuildResult {
+            repo: legacy.repo,
+            pr: legacy.pr,
+            system: legacy.system,
+            output: legacy.output,
+            attempt_id: legacy.attempt_id,
+            attrs: attrs,
+            merge_failed: merge_failed,
+            passed_without_attrs: passed_without_attrs,
+            realisations: legacy.realisations,
+            correlation_id: legacy.correlation_id,
+            caused_by: legacy.caused_by,
+        };
+    }
+}
+
+/// `BuildResult` as it was before it had a result per attr.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct LegacyBuildResult {
+    pub repo: Repo,
+    pub pr: Pr,
+    pub system: String,
+    #[serde(default)]
+    pub output: Vec<String>,
+    pub attempt_id: String,
+    #[serde(default)]
     pub success: Option<bool>,
+    #[serde(default)]
     pub skipped_attrs: Option<Vec<String>>,
+    #[serde(default)]
     pub attempted_attrs: Option<Vec<String>>,
+    #[serde(default)]
+    pub realisations: Option<Vec<Realisation>>,
+    #[serde(default)]
+    pub correlation_id: Option<String>,
+    #[serde(default)]
+    pub caused_by: Option<String>,
+}
+
+impl Versioned for LegacyBuildResult {
+    const SCHEMA: &'static str = "build-result";
+    const VERSION: u32 = 1;
+}
+
+pub fn from(
+    data: &Vec<u8>,
+    content_type: Option<&str>,
+) -> Result<BuildResult, serde_json::error::Error> {
+    return envelope::from_slice(&data, content_type);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn legacy(success: Option<bool>) -> LegacyBuildResult {
+        LegacyBuildResult {
+            repo: Repo {
+                clone_url: "https://github.com/nixos/nixpkgs.git".to_owned(),
+                full_name: "NixOS/nixpkgs".to_owned(),
+                owner: "NixOS".to_owned(),
+                name: "nixpkgs".to_owned(),
+            },
+            pr: Pr {
+                head_sha: "abc123".to_owned(),
+                number: 42,
+                target_branch: Some("master".to_owned()),
+            },
+            system: "x86_64-linux".to_owned(),
+            output: vec![],
+            attempt_id: "attempt".to_owned(),
+            success: success,
+            skipped_attrs: Some(vec!["broken".to_owned()]),
+            attempted_attrs: Some(vec!["hello".to_owned()]),
+            realisations: None,
+            correlation_id: None,
+            caused_by: None,
+        }
+    }
+
+    #[test]
+    fn test_upgrades_legacy() {
+        let body = envelope::to_vec(&legacy(Some(false)));
+        let result: BuildResult = envelope::from_slice(&body, Some(envelope::JSON_ENVELOPE))
+            .unwrap();
+        assert_eq!(
+            result.attrs,
+            vec![
+                AttrResult::new("hello".to_owned(), AttrStatus::Failure),
+                AttrResult::new("broken".to_owned(), AttrStatus::Skipped),
+            ]
+        );
+        assert_eq!(result.success(), Some(false));
+    }
+
+    #[test]
+    fn test_round_trips_legacy() {
+        for success in vec![Some(true), Some(false)] {
+            let back = BuildResult::from_legacy(legacy(success)).legacy();
+            assert_eq!(back.success, success);
+            assert_eq!(back.attempted_attrs, Some(vec!["hello".to_owned()]));
+            assert_eq!(back.skipped_attrs, Some(vec!["broken".to_owned()]));
+        }
+
+        let mut merge_failed = BuildResult::from_legacy(legacy(None));
+        for attr in merge_failed.attrs.iter_mut() {
+            attr.status = AttrStatus::MergeFailed;
+        }
+        let back = merge_failed.legacy();
+        assert_eq!(back.success, Some(false));
+        assert_eq!(back.attempted_attrs, None);
+        assert_eq!(back.skipped_attrs, None);
+    }
+
+    #[test]
+    fn test_upgrades_legacy_merge_failure() {
+        let mut failure = legacy(Some(false));
+        failure.attempted_attrs = None;
+        failure.skipped_attrs = None;
+
+        let result = BuildResult::from_legacy(failure);
+        assert_eq!(result.attrs, vec![]);
+        assert!(result.merge_failed);
+        assert_eq!(result.success(), Some(false));
+        assert_eq!(result.legacy().success, Some(false));
+    }
+
+    #[test]
+    fn test_upgrades_legacy_success_without_attrs() {
+        let mut success = legacy(Some(true));
+        success.attempted_attrs = None;
+        success.skipped_attrs = None;
+
+        let result = BuildResult::from_legacy(success);
+        assert_eq!(result.attrs, vec![]);
+        assert!(!result.merge_failed);
+        assert_eq!(result.success(), Some(true));
+        assert_eq!(result.legacy().success, Some(true));
+    }
 }
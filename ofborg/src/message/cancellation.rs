@@ -0,0 +1,60 @@
+use ofborg::message::{Pr, Repo};
+use ofborg::correlation::Correlation;
+use ofborg::message::envelope::{self, Versioned};
+use serde_json;
+
+pub fn from(
+    data: &Vec<u8>,
+    content_type: Option<&str>,
+) -> Result<Cancellation, serde_json::error::Error> {
+    return envelope::from_slice(&data, content_type);
+}
+
+/// Asks every worker to stop the evaluations and builds of a PR's
+/// head commit, queued or in progress.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Cancellation {
+    pub repo: Repo,
+    pub pr: Pr,
+    #[serde(default)]
+    pub requested_by: String,
+    /// Work on the commit was requested again, so it is no longer
+    /// cancelled.
+    #[serde(default)]
+    pub lifted: bool,
+    /// See `correlation`.
+    #[serde(default)]
+    pub correlation_id: Option<String>,
+    #[serde(default)]
+    pub caused_by: Option<String>,
+}
+
+impl Versioned for Cancellation {
+    const SCHEMA: &'static str = "cancellation";
+    const VERSION: u32 = 1;
+}
+
+impl Cancellation {
+    pub fn new(repo: Repo, pr: Pr, requested_by: &str) -> Cancellation {
+        return Cancellation {
+            repo: repo,
+            pr: pr,
+            requested_by: requested_by.to_owned(),
+            lifted: false,
+            correlation_id: None,
+            caused_by: None,
+        };
+    }
+
+    /// Lifts an earlier cancellation instead.
+    pub fn lift(mut self) -> Cancellation {
+        self.lifted = true;
+        return self;
+    }
+
+    pub fn correlated(mut self, correlation: &Correlation) -> Cancellation {
+        self.correlation_id = correlation.id.clone();
+        self.caused_by = correlation.caused_by.clone();
+        return self;
+    }
+}
@@ -9,6 +9,7 @@ pub struct Repo {
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Pr {
+    #[serde(default)]
     pub target_branch: Option<String>,
     pub number: u64,
     pub head_sha: String,
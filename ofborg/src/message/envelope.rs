@@ -0,0 +1,296 @@
+//! Messages are published in an envelope naming their schema and its
+//! version:
+//!
+//! ```json
+//! {"schema":"build-job","version":1,"payload":{...}}
+//! ```
+//!
+//! so a consumer can tell what it was sent, and bring what an older
+//! publisher sent up to date, instead of every publisher and consumer
+//! having to be upgraded at once. The content type of an enveloped
+//! message says so, like `application/vnd.ofborg.envelope+json`.
+//! Messages with any other content type, or none, are from before the
+//! envelope and taken as version 0 of whatever the consumer expects.
+//!
+//! Consumers which predate the envelope can't read it, so until they
+//! are all upgraded, `rabbitmq.legacy_messages` keeps publishing the
+//! messages without it.
+//!
+//! Envelopes are JSON, or CBOR when their content type says so, for
+//! the log lines builders send, which are most of the messages.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use serde::Serialize;
+use serde::de::{DeserializeOwned, Error};
+use serde_cbor;
+use serde_json::{self, Value};
+
+pub const JSON_ENVELOPE: &'static str = "application/vnd.ofborg.envelope+json";
+pub const CBOR_ENVELOPE: &'static str = "application/vnd.ofborg.envelope+cbor";
+
+/// Set once the configuration is loaded, see `publish_legacy`.
+static LEGACY: AtomicBool = AtomicBool::new(false);
+
+/// Publish messages without the envelope, for consumers which don't
+/// know of it yet.
+pub fn publish_legacy(legacy: bool) {
+    LEGACY.store(legacy, Ordering::Relaxed);
+}
+
+pub trait Versioned {
+    /// Like `build-job`.
+    const SCHEMA: &'static str;
+    /// Bumped when the payload changes in a way the previous version's
+    /// consumers can't read. Adding a field doesn't, as long as it's
+    /// `#[serde(default)]` for what older publishers send, and consumers
+    /// ignore the fields they don't know of.
+    const VERSION: u32;
+
+    /// Brings a payload of an older `version` up to `VERSION`.
+    fn upgrade(_version: u32, payload: Value) -> Result<Value, String> {
+        return Ok(payload);
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all="snake_case")]
+pub enum Encoding {
+    Json,
+    Cbor,
+}
+
+/// How a message is encoded, and whether it's in the envelope.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Format {
+    pub encoding: Encoding,
+    pub enveloped: bool,
+}
+
+impl Format {
+    /// How messages in `encoding` are published: in the envelope,
+    /// unless `publish_legacy` said otherwise.
+    pub fn published(encoding: Encoding) -> Format {
+        return Format {
+            encoding: encoding,
+            enveloped: !LEGACY.load(Ordering::Relaxed),
+        };
+    }
+
+    pub fn content_type(&self) -> &'static str {
+        match (self.encoding, self.enveloped) {
+            (Encoding::Json, true) => JSON_ENVELOPE,
+            (Encoding::Cbor, true) => CBOR_ENVELOPE,
+            (Encoding::Json, false) => "application/json",
+            (Encoding::Cbor, false) => "application/cbor",
+        }
+    }
+
+    /// Legacy JSON when there is no content type, like for messages
+    /// published before the envelope.
+    pub fn of(content_type: Option<&str>) -> Result<Format, String> {
+        let (encoding, enveloped) = match content_type {
+            None | Some("application/json") => (Encoding::Json, false),
+            Some("application/cbor") => (Encoding::Cbor, false),
+            Some(JSON_ENVELOPE) => (Encoding::Json, true),
+            Some(CBOR_ENVELOPE) => (Encoding::Cbor, true),
+            Some(other) => return Err(format!("Unknown content type {}", other)),
+        };
+
+        return Ok(Format {
+            encoding: encoding,
+            enveloped: enveloped,
+        });
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct Envelope<T> {
+    pub schema: String,
+    pub version: u32,
+    pub payload: T,
+}
+
+pub fn wrap<T: Versioned + Serialize>(msg: &T) -> Envelope<&T> {
+    return Envelope {
+        schema: T::SCHEMA.to_owned(),
+        version: T::VERSION,
+        payload: msg,
+    };
+}
+
+/// `msg` as JSON, published with `Format::published(Encoding::Json)`.
+pub fn to_vec<T: Versioned + Serialize>(msg: &T) -> Vec<u8> {
+    return encode(msg, Format::published(Encoding::Json));
+}
+
+pub fn encode<T: Versioned + Serialize>(msg: &T, format: Format) -> Vec<u8> {
+    match (format.encoding, format.enveloped) {
+        (Encoding::Json, true) => serde_json::to_vec(&wrap(msg)).unwrap(),
+        (Encoding::Cbor, true) => serde_cbor::to_vec(&wrap(msg)).unwrap(),
+        (Encoding::Json, false) => serde_json::to_vec(msg).unwrap(),
+        (Encoding::Cbor, false) => serde_cbor::to_vec(msg).unwrap(),
+    }
+}
+
+/// `body`, in the envelope or not, to look into before it's decoded.
+pub fn parse(body: &[u8], encoding: Encoding) -> Result<Value, String> {
+    match encoding {
+        Encoding::Json => serde_json::from_slice(body).map_err(|e| format!("Not JSON: {}", e)),
+        Encoding::Cbor => serde_cbor::from_slice(body).map_err(|e| format!("Not CBOR: {}", e)),
+    }
+}
+
+/// The schema and version an enveloped `value` says it is.
+pub fn peek(value: &Value) -> Option<(String, u32)> {
+    let schema = value["schema"].as_str()?;
+    let version = value["version"].as_u64()?;
+    return Some((schema.to_owned(), version as u32));
+}
+
+/// Decode `body`, published with `content_type`.
+pub fn from_slice<T>(body: &[u8], content_type: Option<&str>) -> Result<T, serde_json::Error>
+where
+    T: Versioned + DeserializeOwned,
+{
+    let format = Format::of(content_type).map_err(serde_json::Error::custom)?;
+    let value = parse(body, format.encoding).map_err(serde_json::Error::custom)?;
+    return from_value(value, format.enveloped);
+}
+
+pub fn from_value<T>(value: Value, enveloped: bool) -> Result<T, serde_json::Error>
+where
+    T: Versioned + DeserializeOwned,
+{
+    let (schema, version, payload) = split(value, enveloped)?;
+    if let Some(schema) = schema {
+        if schema != T::SCHEMA {
+            return Err(Error::custom(
+                format!("a {} message, not a {}", schema, T::SCHEMA),
+            ));
+        }
+    }
+    if version > T::VERSION {
+        return Err(Error::custom(format!(
+            "version {} of {} is newer than the {} understood",
+            version,
+            T::SCHEMA,
+            T::VERSION
+        )));
+    }
+
+    let payload = if version < T::VERSION {
+        T::upgrade(version, payload).map_err(serde_json::Error::custom)?
+    } else {
+        payload
+    };
+    return serde_json::from_value(payload);
+}
+
+/// The schema, version and payload of an enveloped message, or a legacy
+/// message as version 0 of no schema in particular.
+fn split(value: Value, enveloped: bool) -> Result<(Option<String>, u32, Value), serde_json::Error> {
+    if !enveloped {
+        return Ok((None, 0, value));
+    }
+
+    let envelope: Envelope<Value> = serde_json::from_value(value)?;
+    return Ok((Some(envelope.schema), envelope.version, envelope.payload));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    struct Greeting {
+        name: String,
+    }
+
+    impl Versioned for Greeting {
+        const SCHEMA: &'static str = "greeting";
+        const VERSION: u32 = 2;
+
+        fn upgrade(version: u32, mut payload: Value) -> Result<Value, String> {
+            if version < 2 {
+                let who = payload.as_object_mut().and_then(|fields| fields.remove("who"));
+                if let Some(who) = who {
+                    payload["name"] = who;
+                }
+            }
+            return Ok(payload);
+        }
+    }
+
+    fn hello() -> Greeting {
+        Greeting { name: String::from("hello") }
+    }
+
+    fn enveloped(encoding: Encoding) -> Format {
+        return Format {
+            encoding: encoding,
+            enveloped: true,
+        };
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let body = encode(&hello(), enveloped(Encoding::Json));
+        assert_eq!(
+            String::from_utf8(body.clone()).unwrap(),
+            r#"{"schema":"greeting","version":2,"payload":{"name":"hello"}}"#
+        );
+        let value = parse(&body, Encoding::Json).unwrap();
+        assert_eq!(peek(&value), Some((String::from("greeting"), 2)));
+        assert_eq!(from_slice::<Greeting>(&body, Some(JSON_ENVELOPE)).unwrap(), hello());
+    }
+
+    #[test]
+    fn test_legacy() {
+        let legacy = Format {
+            encoding: Encoding::Json,
+            enveloped: false,
+        };
+        let body = encode(&hello(), legacy);
+        assert_eq!(String::from_utf8(body.clone()).unwrap(), r#"{"name":"hello"}"#);
+        assert_eq!(legacy.content_type(), "application/json");
+        assert_eq!(from_slice::<Greeting>(&body, None).unwrap(), hello());
+
+        // The content type tells an envelope apart, not its fields.
+        let body = br#"{"schema":"greeting","version":2,"payload":{"name":"hello"}}"#;
+        assert!(from_slice::<Greeting>(body, Some("application/json")).is_err());
+        assert_eq!(from_slice::<Greeting>(body, Some(JSON_ENVELOPE)).unwrap(), hello());
+    }
+
+    #[test]
+    fn test_cbor() {
+        let body = encode(&hello(), enveloped(Encoding::Cbor));
+        assert!(body.len() < encode(&hello(), enveloped(Encoding::Json)).len());
+        assert!(from_slice::<Greeting>(&body, Some(JSON_ENVELOPE)).is_err());
+        let value = parse(&body, Encoding::Cbor).unwrap();
+        assert_eq!(peek(&value), Some((String::from("greeting"), 2)));
+        assert_eq!(from_slice::<Greeting>(&body, Some(CBOR_ENVELOPE)).unwrap(), hello());
+
+        assert_eq!(Format::of(None), Ok(Format { encoding: Encoding::Json, enveloped: false }));
+        assert_eq!(Format::of(Some(CBOR_ENVELOPE)), Ok(enveloped(Encoding::Cbor)));
+        assert_eq!(enveloped(Encoding::Cbor).content_type(), CBOR_ENVELOPE);
+        assert!(Format::of(Some("text/plain")).is_err());
+    }
+
+    #[test]
+    fn test_upgrade() {
+        let legacy = br#"{"who":"hello"}"#;
+        assert_eq!(from_slice::<Greeting>(legacy, None).unwrap(), hello());
+
+        let older = br#"{"schema":"greeting","version":1,"payload":{"who":"hello"}}"#;
+        assert_eq!(from_slice::<Greeting>(older, Some(JSON_ENVELOPE)).unwrap(), hello());
+    }
+
+    #[test]
+    fn test_rejects() {
+        let newer = br#"{"schema":"greeting","version":3,"payload":{"name":"hello"}}"#;
+        assert!(from_slice::<Greeting>(newer, Some(JSON_ENVELOPE)).is_err());
+
+        let other = br#"{"schema":"farewell","version":2,"payload":{"name":"hello"}}"#;
+        assert!(from_slice::<Greeting>(other, Some(JSON_ENVELOPE)).is_err());
+    }
+}
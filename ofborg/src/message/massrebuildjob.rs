@@ -1,16 +1,67 @@
 use ofborg::message::{Pr, Repo};
+use ofborg::correlation::Correlation;
+use ofborg::message::envelope::{self, Versioned};
 use ofborg::worker;
 use serde_json;
 
 
-pub fn from(data: &Vec<u8>) -> Result<MassRebuildJob, serde_json::error::Error> {
-    return serde_json::from_slice(&data);
+pub fn from(
+    data: &Vec<u8>,
+    content_type: Option<&str>,
+) -> Result<MassRebuildJob, serde_json::error::Error> {
+    return envelope::from_slice(&data, content_type);
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct MassRebuildJob {
     pub repo: Repo,
     pub pr: Pr,
+    /// How often the job was handed on for exceeding the memory limit.
+    #[serde(default)]
+    pub memory_retries: u32,
+    /// See `correlation`.
+    #[serde(default)]
+    pub correlation_id: Option<String>,
+    #[serde(default)]
+    pub caused_by: Option<String>,
+}
+
+impl Versioned for MassRebuildJob {
+    const SCHEMA: &'static str = "mass-rebuild-job";
+    const VERSION: u32 = 1;
+}
+
+impl MassRebuildJob {
+    pub fn new(repo: Repo, pr: Pr) -> MassRebuildJob {
+        return MassRebuildJob {
+            repo: repo,
+            pr: pr,
+            memory_retries: 0,
+            correlation_id: None,
+            caused_by: None,
+        };
+    }
+
+    pub fn correlated(mut self, correlation: &Correlation) -> MassRebuildJob {
+        self.correlation_id = correlation.id.clone();
+        self.caused_by = correlation.caused_by.clone();
+        return self;
+    }
+
+    pub fn correlation(&self) -> Correlation {
+        return Correlation::of(&self.correlation_id, &self.caused_by);
+    }
+
+    /// The job to hand to an evaluator with more memory.
+    pub fn memory_retry(&self) -> MassRebuildJob {
+        return MassRebuildJob {
+            repo: self.repo.clone(),
+            pr: self.pr.clone(),
+            memory_retries: self.memory_retries + 1,
+            correlation_id: self.correlation_id.clone(),
+            caused_by: self.caused_by.clone(),
+        };
+    }
 }
 
 pub struct Actions {}
@@ -1,7 +1,72 @@
 mod common;
+pub mod envelope;
 pub mod buildjob;
 pub mod buildresult;
 pub mod massrebuildjob;
 pub mod buildlogmsg;
+pub mod cancellation;
+pub mod audit;
+pub mod queuealert;
+pub mod usagesummary;
 
 pub use self::common::{Pr, Repo};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const REPO: &'static str = r#"{"owner":"NixOS","name":"nixpkgs","full_name":"NixOS/nixpkgs",
+        "clone_url":"https://github.com/NixOS/nixpkgs.git"}"#;
+    const PR: &'static str = r#"{"number":42,"head_sha":"abc"}"#;
+    const ENVELOPE: Option<&'static str> = Some(envelope::JSON_ENVELOPE);
+
+    /// `payload` as a version 1 message, with a field added by some
+    /// later publisher.
+    fn v1(schema: &str, payload: &str) -> Vec<u8> {
+        let payload = payload.replace("$REPO", REPO).replace("$PR", PR);
+        let mut payload: ::serde_json::Value = ::serde_json::from_str(&payload).unwrap();
+        payload["added_later"] = json!(true);
+        return ::serde_json::to_vec(&json!({
+            "schema": schema,
+            "version": 1,
+            "payload": payload,
+        })).unwrap();
+    }
+
+    #[test]
+    fn test_decodes_what_older_and_newer_publishers_send() {
+        let job = buildjob::from(&v1("build-job", r#"{"repo":$REPO,"pr":$PR,
+            "flags":{"on":["aarch64-linux"]}}"#), ENVELOPE).unwrap();
+        assert_eq!(job.attrs, Vec::<String>::new());
+        assert_eq!(job.flags.on, vec![String::from("aarch64-linux")]);
+        assert_eq!(job.pr.target_branch, None);
+
+        let result = buildresult::from(&v1("build-result", r#"{"repo":$REPO,"pr":$PR,
+            "system":"x86_64-linux","attempt_id":"attempt"}"#), ENVELOPE).unwrap();
+        assert_eq!(result.success(), None);
+        assert_eq!(result.output, Vec::<String>::new());
+
+        let start: buildlogmsg::BuildLogStart = envelope::from_slice(&v1("build-log-start",
+            r#"{"system":"x86_64-linux","identity":"builder","attempt_id":"attempt",
+            "capabilities":{"version":"nix (Nix) 2.0"}}"#), ENVELOPE).unwrap();
+        assert_eq!(start.capabilities.unwrap().sandbox, false);
+
+        let msg: buildlogmsg::BuildLogMsg = envelope::from_slice(&v1("build-log-msg",
+            r#"{"system":"x86_64-linux","identity":"builder","attempt_id":"attempt",
+            "line_number":1,"output":"hi"}"#), ENVELOPE).unwrap();
+        assert_eq!(msg.correlation_id, None);
+
+        let job = massrebuildjob::from(&v1("mass-rebuild-job", r#"{"repo":$REPO,"pr":$PR}"#),
+            ENVELOPE);
+        assert!(job.is_ok());
+        let cancelled = cancellation::from(&v1("cancellation", r#"{"repo":$REPO,"pr":$PR}"#),
+            ENVELOPE);
+        assert!(cancelled.is_ok());
+        assert!(audit::from(&v1("audit-event", r#"{"who":"grahamc","command":"eval",
+            "repo":"NixOS/nixpkgs","pr":42,"decision":"allowed"}"#), ENVELOPE).is_ok());
+        assert!(queuealert::from(&v1("queue-alert", r#"{"queue":"build-jobs","messages":30,
+            "threshold":20}"#), ENVELOPE).is_ok());
+        assert!(usagesummary::from(&v1("usage-summary", r#"{"from":0,"to":60,
+            "usage":[{"repo":"NixOS/nixpkgs","user":"grahamc"}]}"#), ENVELOPE).is_ok());
+    }
+}
@@ -0,0 +1,49 @@
+use serde_json;
+
+use ofborg::message::envelope::{self, Versioned};
+
+/// Queues which back up past their threshold are announced here.
+pub const EXCHANGE: &'static str = "queue-alerts";
+
+pub fn from(
+    data: &Vec<u8>,
+    content_type: Option<&str>,
+) -> Result<QueueAlert, serde_json::error::Error> {
+    return envelope::from_slice(&data, content_type);
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct QueueAlert {
+    pub queue: String,
+    pub messages: u64,
+    #[serde(default)]
+    pub consumers: u64,
+    pub threshold: u64,
+    /// Seconds since the epoch.
+    #[serde(default)]
+    pub at: u64,
+    /// See `correlation`.
+    #[serde(default)]
+    pub correlation_id: Option<String>,
+    #[serde(default)]
+    pub caused_by: Option<String>,
+}
+
+impl QueueAlert {
+    pub fn new(queue: String, messages: u64, consumers: u64, threshold: u64, at: u64) -> QueueAlert {
+        return QueueAlert {
+            queue: queue,
+            messages: messages,
+            consumers: consumers,
+            threshold: threshold,
+            at: at,
+            correlation_id: None,
+            caused_by: None,
+        };
+    }
+}
+
+impl Versioned for QueueAlert {
+    const SCHEMA: &'static str = "queue-alert";
+    const VERSION: u32 = 1;
+}
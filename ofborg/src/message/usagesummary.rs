@@ -0,0 +1,57 @@
+use serde_json;
+
+use ofborg::message::envelope::{self, Versioned};
+
+/// What the builders and evaluators were used for is summarized here
+/// periodically.
+pub const EXCHANGE: &'static str = "usage-summaries";
+
+pub fn from(
+    data: &Vec<u8>,
+    content_type: Option<&str>,
+) -> Result<UsageSummary, serde_json::error::Error> {
+    return envelope::from_slice(&data, content_type);
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct UsageSummary {
+    /// Seconds since the epoch the summary starts at.
+    pub from: u64,
+    pub to: u64,
+    #[serde(default)]
+    pub usage: Vec<Usage>,
+    /// See `correlation`.
+    #[serde(default)]
+    pub correlation_id: Option<String>,
+    #[serde(default)]
+    pub caused_by: Option<String>,
+}
+
+impl UsageSummary {
+    pub fn new(from: u64, to: u64, usage: Vec<Usage>) -> UsageSummary {
+        return UsageSummary {
+            from: from,
+            to: to,
+            usage: usage,
+            correlation_id: None,
+            caused_by: None,
+        };
+    }
+}
+
+impl Versioned for UsageSummary {
+    const SCHEMA: &'static str = "usage-summary";
+    const VERSION: u32 = 1;
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Usage {
+    pub repo: String,
+    pub user: String,
+    #[serde(default)]
+    pub builds: u64,
+    #[serde(default)]
+    pub builder_minutes: u64,
+    #[serde(default)]
+    pub eval_minutes: u64,
+}
@@ -0,0 +1,51 @@
+{ changedattrsjson }:
+let
+  pkgs = import ./. {
+    config = {
+      allowBroken = true;
+      allowUnfree = true;
+      allowInsecurePredicate = x: true;
+    };
+  };
+  lib = pkgs.lib;
+
+  changedattrs = builtins.fromJSON (builtins.readFile changedattrsjson);
+
+  knownLicenses = map (license: license.shortName or null)
+    (builtins.attrValues lib.licenses);
+
+  describeLicense = license:
+    if builtins.isAttrs license
+      then { name = license.shortName or "<unnamed>"; valid = license ? shortName; }
+    else if builtins.isString license
+      then { name = license; valid = builtins.elem license knownLicenses; }
+    else { name = "<not a license>"; valid = false; };
+
+  metaOf = attrpath:
+    let
+      pkg = builtins.tryEval
+        (lib.attrByPath (lib.splitString "." attrpath) null pkgs);
+      meta = if pkg.success && pkg.value != null
+        then pkg.value.meta or {}
+        else null;
+      description = meta.description or null;
+      license = meta.license or null;
+      platforms = meta.platforms or null;
+      position = meta.position or null;
+    in if meta == null
+      then {
+        evaluates = false;
+        description = null;
+        licenses = [];
+        platforms = 0;
+        position = null;
+      }
+      else {
+        evaluates = true;
+        position = if builtins.isString position then position else null;
+        description = if builtins.isString description then description else null;
+        licenses = if license == null then [] else map describeLicense (lib.toList license);
+        platforms = if builtins.isList platforms then builtins.length platforms else 0;
+      };
+in
+  lib.genAttrs changedattrs metaOf
@@ -0,0 +1,211 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::Path;
+use serde_json;
+use ofborg::nix;
+use ofborg::changedattrs;
+
+#[derive(Deserialize, Debug)]
+struct LicenseInfo {
+    name: String,
+    valid: bool,
+}
+
+#[derive(Deserialize, Debug)]
+struct AttrMeta {
+    evaluates: bool,
+    description: Option<String>,
+    licenses: Vec<LicenseInfo>,
+    platforms: u64,
+    /// Where the attr's meta is set, like `/co/pkgs/hello/default.nix:20`.
+    #[serde(default)]
+    position: Option<String>,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum MetaProblem {
+    DoesNotEvaluate,
+    MissingDescription,
+    InvalidLicense(String),
+    MissingPlatforms,
+}
+
+impl fmt::Display for MetaProblem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            MetaProblem::DoesNotEvaluate => write!(f, "meta could not be evaluated"),
+            MetaProblem::MissingDescription => write!(f, "meta.description is missing"),
+            MetaProblem::InvalidLicense(ref name) => {
+                write!(f, "meta.license {:?} is not a known license", name)
+            }
+            MetaProblem::MissingPlatforms => write!(f, "meta.platforms is missing or empty"),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct MetaLint {
+    problems: BTreeMap<String, Vec<MetaProblem>>,
+    positions: BTreeMap<String, String>,
+}
+
+impl MetaLint {
+    pub fn check(nix: &nix::Nix, checkout: &Path, attrs: &Vec<String>) -> Result<MetaLint, String> {
+        let output = changedattrs::evaluate(
+            nix,
+            checkout,
+            "metalint.nix",
+            include_str!("metalint.nix"),
+            attrs,
+        )?;
+
+        MetaLint::parse(&output).map_err(|e| format!("Failed to parse meta: {:?}", e))
+    }
+
+    fn parse(output: &str) -> Result<MetaLint, serde_json::Error> {
+        let metas: BTreeMap<String, AttrMeta> = serde_json::from_str(output)?;
+
+        let positions = metas
+            .iter()
+            .filter_map(|(attr, meta)| {
+                meta.position.clone().map(|position| (attr.clone(), position))
+            })
+            .collect();
+        let problems = metas
+            .into_iter()
+            .map(|(attr, meta)| (attr, lint(&meta)))
+            .filter(|&(_, ref problems)| problems.len() > 0)
+            .collect();
+
+        return Ok(MetaLint {
+            problems: problems,
+            positions: positions,
+        });
+    }
+
+    pub fn is_clean(&self) -> bool {
+        self.problems.is_empty()
+    }
+
+    pub fn problem_count(&self) -> usize {
+        self.problems.values().map(|problems| problems.len()).sum()
+    }
+
+    pub fn attr_count(&self) -> usize {
+        self.problems.len()
+    }
+
+    /// One line per problem, prefixed with the attribute it applies to.
+    pub fn report(&self) -> String {
+        self.problems
+            .iter()
+            .flat_map(|(attr, problems)| {
+                problems
+                    .iter()
+                    .map(|problem| format!("{}: {}", attr, problem))
+                    .collect::<Vec<String>>()
+            })
+            .collect::<Vec<String>>()
+            .join("\n")
+    }
+
+    /// A `(path, line, message)` annotation of each attr's problems
+    /// where its meta is set, for the attrs whose meta is in the
+    /// checkout.
+    pub fn annotations(&self, checkout: &str) -> Vec<(String, u64, String)> {
+        let root = format!("{}/", checkout.trim_right_matches('/'));
+
+        self.problems
+            .iter()
+            .filter_map(|(attr, problems)| {
+                let position = self.positions.get(attr)?;
+                let mut parts = position.rsplitn(2, ':');
+                let line: u64 = parts.next()?.parse().ok()?;
+                let path = parts.next()?;
+                if !path.starts_with(&root) {
+                    return None;
+                }
+
+                let message = problems
+                    .iter()
+                    .map(|problem| format!("{}: {}", attr, problem))
+                    .collect::<Vec<String>>()
+                    .join("\n");
+                Some((path[root.len()..].to_owned(), line, message))
+            })
+            .collect()
+    }
+}
+
+fn lint(meta: &AttrMeta) -> Vec<MetaProblem> {
+    if !meta.evaluates {
+        return vec![MetaProblem::DoesNotEvaluate];
+    }
+
+    let mut problems: Vec<MetaProblem> = vec![];
+
+    match meta.description {
+        Some(ref description) if description.trim().len() > 0 => {}
+        _ => problems.push(MetaProblem::MissingDescription),
+    }
+
+    for license in meta.licenses.iter().filter(|license| !license.valid) {
+        problems.push(MetaProblem::InvalidLicense(license.name.clone()));
+    }
+
+    if meta.platforms == 0 {
+        problems.push(MetaProblem::MissingPlatforms);
+    }
+
+    return problems;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEST_OUTPUT: &'static str = r#"{
+  "hello": {"evaluates": true, "description": "A program that produces a familiar, friendly greeting", "licenses": [{"name": "gpl3Plus", "valid": true}], "platforms": 12},
+  "bogus": {"evaluates": true, "description": null, "licenses": [{"name": "MIT-ish", "valid": false}], "platforms": 0, "position": "/co/pkgs/bogus/default.nix:12"},
+  "broken": {"evaluates": false, "description": null, "licenses": [], "platforms": 0},
+  "whitespace": {"evaluates": true, "description": "  ", "licenses": [], "platforms": 3, "position": "/nix/store/abc-whitespace/default.nix:3"}
+}"#;
+
+    #[test]
+    fn test_lint_problems() {
+        let lint = MetaLint::parse(TEST_OUTPUT).unwrap();
+
+        assert!(!lint.is_clean());
+        assert_eq!(lint.attr_count(), 3);
+        assert_eq!(lint.problem_count(), 5);
+        assert_eq!(
+            lint.report(),
+            "bogus: meta.description is missing
+bogus: meta.license \"MIT-ish\" is not a known license
+bogus: meta.platforms is missing or empty
+broken: meta could not be evaluated
+whitespace: meta.description is missing"
+        );
+        assert_eq!(
+            lint.annotations("/co/"),
+            vec![
+                (
+                    String::from("pkgs/bogus/default.nix"),
+                    12,
+                    String::from(
+                        "bogus: meta.description is missing
+bogus: meta.license \"MIT-ish\" is not a known license
+bogus: meta.platforms is missing or empty",
+                    ),
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_lint_clean() {
+        let lint = MetaLint::parse(r#"{"hello": {"evaluates": true, "description": "hi", "licenses": [], "platforms": 1}}"#).unwrap();
+        assert!(lint.is_clean());
+        assert_eq!(lint.report(), "");
+    }
+}
@@ -0,0 +1,184 @@
+//! Sign the messages the tasks publish, and drop those consumed which
+//! aren't signed, for when the broker is reachable by builders which
+//! aren't trusted with everything. Every task shares the key, and signs
+//! a message like GitHub signs webhooks, in the `x-ofborg-signature`
+//! AMQP header. The exchange, routing key and time of publishing are
+//! signed along with the body, so a message can neither be sent to
+//! another queue nor replayed much later.
+
+use amqp;
+use amqp::protocol::basic::BasicProperties;
+
+use ofborg::timestamp;
+use ofborg::webhook;
+
+pub const HEADER: &'static str = "x-ofborg-signature";
+/// When the message was signed, in seconds since the epoch.
+pub const TIMESTAMP_HEADER: &'static str = "x-ofborg-signed-at";
+
+/// Messages can wait in a backed up queue for a long time, so only
+/// those older than this are dropped as replayed.
+pub const DEFAULT_MAX_AGE_SECS: u64 = 2 * 24 * 60 * 60;
+
+/// How far ahead of ours a publisher's clock may be.
+const MAX_CLOCK_SKEW_SECS: u64 = 5 * 60;
+
+#[derive(Clone)]
+pub struct Signer {
+    key: Vec<u8>,
+    max_age_secs: u64,
+}
+
+impl Signer {
+    pub fn new(key: &[u8]) -> Signer {
+        return Signer {
+            key: key.to_vec(),
+            max_age_secs: DEFAULT_MAX_AGE_SECS,
+        };
+    }
+
+    pub fn with_max_age(mut self, secs: u64) -> Signer {
+        self.max_age_secs = secs;
+        return self;
+    }
+
+    /// Sign the message about to be published in its headers.
+    pub fn sign(
+        &self,
+        props: &mut BasicProperties,
+        exchange: &str,
+        routing_key: &str,
+        body: &[u8],
+    ) {
+        self.sign_at(props, exchange, routing_key, body, timestamp::now());
+    }
+
+    fn sign_at(
+        &self,
+        props: &mut BasicProperties,
+        exchange: &str,
+        routing_key: &str,
+        body: &[u8],
+        now: u64,
+    ) {
+        let signature = webhook::sign(&self.key, &signed(exchange, routing_key, now, body))
+            .expect("signing a message");
+        let mut headers = props.headers.take().unwrap_or(amqp::Table::new());
+        headers.insert(
+            String::from(HEADER),
+            amqp::TableEntry::LongString(signature),
+        );
+        headers.insert(
+            String::from(TIMESTAMP_HEADER),
+            amqp::TableEntry::LongString(format!("{}", now)),
+        );
+        props.headers = Some(headers);
+    }
+
+    pub fn verify(
+        &self,
+        props: &BasicProperties,
+        exchange: &str,
+        routing_key: &str,
+        body: &[u8],
+    ) -> Result<(), String> {
+        return self.verify_at(props, exchange, routing_key, body, timestamp::now());
+    }
+
+    fn verify_at(
+        &self,
+        props: &BasicProperties,
+        exchange: &str,
+        routing_key: &str,
+        body: &[u8],
+        now: u64,
+    ) -> Result<(), String> {
+        let headers = props.headers.as_ref();
+        let signature = match headers.and_then(|headers| headers.get(HEADER)) {
+            Some(&amqp::TableEntry::LongString(ref signature)) => signature,
+            _ => return Err(String::from("The message isn't signed")),
+        };
+        let signed_at = match headers.and_then(|headers| headers.get(TIMESTAMP_HEADER)) {
+            Some(&amqp::TableEntry::LongString(ref signed_at)) => signed_at.parse::<u64>().ok(),
+            _ => None,
+        };
+        let signed_at = match signed_at {
+            Some(signed_at) => signed_at,
+            None => return Err(String::from("The message's signature has no time")),
+        };
+
+        if !signature.starts_with("sha256=") ||
+            !webhook::valid_signature(
+                &self.key,
+                &signed(exchange, routing_key, signed_at, body),
+                signature,
+            )
+        {
+            return Err(String::from("The message's signature is wrong"));
+        }
+
+        if signed_at > now + MAX_CLOCK_SKEW_SECS {
+            return Err(format!("The message was signed {}s in the future", signed_at - now));
+        }
+        if now > signed_at && now - signed_at > self.max_age_secs {
+            return Err(format!("The message was signed {}s ago", now - signed_at));
+        }
+
+        return Ok(());
+    }
+}
+
+/// What is signed: where the message goes, when it was sent, and what
+/// it says.
+fn signed(exchange: &str, routing_key: &str, signed_at: u64, body: &[u8]) -> Vec<u8> {
+    let mut signed = format!("{}\n{}\n{}\n", exchange, routing_key, signed_at).into_bytes();
+    signed.extend_from_slice(body);
+    return signed;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sign_and_verify() {
+        let signer = Signer::new(b"key");
+        let mut props = BasicProperties {
+            content_type: Some(String::from("application/json")),
+            ..Default::default()
+        };
+        assert!(signer.verify(&props, "", "queue", b"{}").is_err());
+
+        signer.sign(&mut props, "", "queue", b"{}");
+        assert!(signer.verify(&props, "", "queue", b"{}").is_ok());
+        assert!(signer.verify(&props, "", "queue", b"{\"evil\":true}").is_err());
+        assert!(signer.verify(&props, "", "other-queue", b"{}").is_err());
+        assert!(signer.verify(&props, "exchange", "queue", b"{}").is_err());
+        assert!(Signer::new(b"other key").verify(&props, "", "queue", b"{}").is_err());
+    }
+
+    #[test]
+    fn test_rejects_stale() {
+        let signer = Signer::new(b"key").with_max_age(60 * 60);
+        let mut props = BasicProperties { ..Default::default() };
+        signer.sign_at(&mut props, "", "queue", b"{}", 1515425400);
+
+        assert!(signer.verify_at(&props, "", "queue", b"{}", 1515425400).is_ok());
+        assert!(signer.verify_at(&props, "", "queue", b"{}", 1515425400 + 30 * 60).is_ok());
+        assert!(signer.verify_at(&props, "", "queue", b"{}", 1515425400 + 2 * 60 * 60).is_err());
+        assert!(signer.verify_at(&props, "", "queue", b"{}", 1515425400 - 60 * 60).is_err());
+    }
+
+    #[test]
+    fn test_rejects_changed_timestamp() {
+        let signer = Signer::new(b"key");
+        let mut props = BasicProperties { ..Default::default() };
+        signer.sign_at(&mut props, "", "queue", b"{}", 1515425400);
+
+        props.headers.as_mut().unwrap().insert(
+            String::from(TIMESTAMP_HEADER),
+            amqp::TableEntry::LongString(String::from("1515429000")),
+        );
+        assert!(signer.verify_at(&props, "", "queue", b"{}", 1515429000).is_err());
+    }
+}
@@ -1,16 +1,179 @@
+use std::collections::BTreeMap;
 use std::env;
 use std::fmt;
 use std::fs::File;
-use std::io::Seek;
-use std::io::SeekFrom;
+use std::io::{Read, Seek, SeekFrom, Write};
 use std::path::Path;
-use std::process::{Command, Stdio};
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use std::time::Duration;
 use tempfile::tempfile;
+use ofborg::nixpath::Pin;
+use serde_json;
+use serde::de::DeserializeOwned;
+
+pub const MEMORY_LIMIT_EXCEEDED: &'static str = "ofborg: evaluation exceeded memory limit";
+
+static PEAK_MEMORY_MB: AtomicUsize = AtomicUsize::new(0);
+
+/// Which of nix's command line interfaces to run.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all="snake_case")]
+pub enum Cli {
+    /// `nix-build`, `nix-instantiate` and friends.
+    Legacy,
+    /// `nix build`, `nix eval` and `nix log` of nix 2.4 and later.
+    New,
+}
+
+impl Cli {
+    /// Pick the interface by `nix --version`, the legacy one when
+    /// that doesn't tell.
+    pub fn detect() -> Cli {
+        match Command::new("nix").arg("--version").output() {
+            Ok(ref output) if output.status.success() => {
+                Cli::for_version(&String::from_utf8_lossy(&output.stdout))
+            }
+            _ => Cli::Legacy,
+        }
+    }
+
+    /// `version` is like `nix (Nix) 2.4.1`.
+    pub fn for_version(version: &str) -> Cli {
+        let number = version.split_whitespace().last().unwrap_or("");
+        let mut parts = number.split('.').map(|part| {
+            part.chars()
+                .take_while(|c| c.is_digit(10))
+                .collect::<String>()
+                .parse::<u64>()
+                .unwrap_or(0)
+        });
+        let major = parts.next().unwrap_or(0);
+        let minor = parts.next().unwrap_or(0);
+
+        if (major, minor) >= (2, 4) {
+            Cli::New
+        } else {
+            Cli::Legacy
+        }
+    }
+}
+
+/// What the installed nix can do, probed when a builder starts.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Default)]
+#[serde(default)]
+pub struct Capabilities {
+    pub version: String,
+    pub experimental_features: Vec<String>,
+    pub system_features: Vec<String>,
+    pub sandbox: bool,
+}
+
+impl Capabilities {
+    pub fn probe(cli: Cli) -> Capabilities {
+        let version = match Command::new("nix").arg("--version").output() {
+            Ok(ref output) if output.status.success() => {
+                String::from_utf8_lossy(&output.stdout).into_owned()
+            }
+            _ => String::new(),
+        };
+
+        let mut cmd = Command::new("nix");
+        if cli == Cli::New {
+            cmd.args(&["--extra-experimental-features", "nix-command"]);
+        }
+        let config = match cmd.arg("show-config").output() {
+            Ok(ref output) if output.status.success() => {
+                String::from_utf8_lossy(&output.stdout).into_owned()
+            }
+            _ => {
+                warn!("Failed to read nix's configuration, assuming no features");
+                String::new()
+            }
+        };
+
+        return Capabilities::parse(&version, &config);
+    }
+
+    /// `version` is `nix --version`'s output, `config` is the
+    /// `key = value` lines of `nix show-config`.
+    pub fn parse(version: &str, config: &str) -> Capabilities {
+        let mut capabilities = Capabilities {
+            version: String::from(version.split_whitespace().last().unwrap_or("")),
+            ..Capabilities::default()
+        };
+
+        for line in config.lines() {
+            let mut parts = line.splitn(2, '=');
+            let key = parts.next().unwrap_or("").trim();
+            let value = parts.next().unwrap_or("").trim();
+            let words = || value.split_whitespace().map(String::from).collect();
+
+            match key {
+                "experimental-features" => capabilities.experimental_features = words(),
+                "system-features" => capabilities.system_features = words(),
+                "sandbox" => capabilities.sandbox = value == "true" || value == "relaxed",
+                _ => {}
+            }
+        }
+
+        return capabilities;
+    }
+
+    /// The requirements this nix doesn't meet. A requirement is
+    /// `sandbox`, an experimental feature or a system feature.
+    pub fn missing(&self, requirements: &[String]) -> Vec<String> {
+        requirements
+            .iter()
+            .filter(|requirement| if *requirement == "sandbox" {
+                !self.sandbox
+            } else {
+                !self.experimental_features.contains(requirement) &&
+                    !self.system_features.contains(requirement)
+            })
+            .cloned()
+            .collect()
+    }
+}
+
+/// Where a content-addressed derivation's output went, `id` is like
+/// `sha256:<derivation hash>!out`.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Realisation {
+    pub id: String,
+    #[serde(rename = "outPath")]
+    pub out_path: String,
+}
+
+/// A store path as `nix path-info --json` describes it. The closure
+/// size is only known when asked for.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct PathInfo {
+    pub path: String,
+    pub nar_size: u64,
+    #[serde(default)]
+    pub closure_size: Option<u64>,
+    #[serde(default)]
+    pub references: Vec<String>,
+    #[serde(default)]
+    pub signatures: Vec<String>,
+    #[serde(default)]
+    pub deriver: Option<String>,
+}
 
 #[derive(Clone, Debug)]
 pub enum Operation {
+    Evaluate,
     Instantiate,
     Build,
+    /// Print the log of a derivation built before.
+    Log,
+    /// Print the outputs content-addressed derivations were realised to.
+    Realisations,
+    /// Describe store paths: their size, references and signatures.
+    PathInfo,
     QueryPackagesJSON,
     QueryPackagesOutputs,
     NoOp { operation: Box<Operation> },
@@ -18,32 +181,137 @@ pub enum Operation {
 }
 
 impl Operation {
-    fn command(&self) -> Command {
-        match *self {
-            Operation::Instantiate => Command::new("nix-instantiate"),
-            Operation::Build => Command::new("nix-build"),
-            Operation::QueryPackagesJSON => Command::new("nix-env"),
-            Operation::QueryPackagesOutputs => Command::new("nix-env"),
-            Operation::NoOp { operation: _ } => Command::new("echo"),
-            Operation::Unknown { ref program } => Command::new(program),
+    fn command(&self, cli: Cli) -> Command {
+        match (self, cli) {
+            (&Operation::Evaluate, Cli::New) => Command::new("nix"),
+            (&Operation::Instantiate, Cli::New) => Command::new("nix"),
+            (&Operation::Build, Cli::New) => Command::new("nix"),
+            (&Operation::Log, Cli::New) => Command::new("nix"),
+            (&Operation::Realisations, _) => Command::new("nix"),
+            (&Operation::PathInfo, _) => Command::new("nix"),
+            (&Operation::Evaluate, _) => Command::new("nix-instantiate"),
+            (&Operation::Instantiate, _) => Command::new("nix-instantiate"),
+            (&Operation::Build, _) => Command::new("nix-build"),
+            (&Operation::Log, _) => Command::new("nix-store"),
+            (&Operation::QueryPackagesJSON, _) => Command::new("nix-env"),
+            (&Operation::QueryPackagesOutputs, _) => Command::new("nix-env"),
+            (&Operation::NoOp { operation: _ }, _) => Command::new("echo"),
+            (&Operation::Unknown { ref program }, _) => Command::new(program),
         }
     }
 
-    fn args(&self, command: &mut Command) {
-        match *self {
-            Operation::Build => {
+    fn args(&self, command: &mut Command, cli: Cli) {
+        if cli == Cli::New && self.has_new_cli() {
+            command.args(&["--extra-experimental-features", "nix-command"]);
+        }
+
+        match (self, cli) {
+            (&Operation::Build, Cli::New) => {
+                command.args(&["build", "--no-link", "--keep-going", "--print-build-logs"]);
+            },
+            (&Operation::Evaluate, Cli::New) => {
+                command.args(&["eval", "--json"]);
+            },
+            (&Operation::Instantiate, Cli::New) => {
+                command.args(&["path-info", "--derivation"]);
+            },
+            (&Operation::Log, Cli::New) => {
+                command.args(&["log"]);
+            },
+            (&Operation::Realisations, _) => {
+                command.args(&[
+                    "--extra-experimental-features",
+                    "ca-derivations",
+                    "realisation",
+                    "info",
+                    "--json",
+                ]);
+            },
+            (&Operation::PathInfo, _) => {
+                command.args(&["path-info", "--json"]);
+            },
+            (&Operation::Build, _) => {
                 command.args(&["--no-out-link", "--keep-going"]);
             },
-            Operation::QueryPackagesJSON => {
+            (&Operation::Evaluate, _) => {
+                command.args(&["--eval", "--strict", "--json"]);
+            },
+            (&Operation::Log, _) => {
+                command.args(&["--read-log"]);
+            },
+            (&Operation::QueryPackagesJSON, _) => {
                 command.args(&["--query", "--available", "--json"]);
             },
-            Operation::QueryPackagesOutputs => {
-                command.args(&["--query", "--available", "--no-name", "--attr-path", "--out-path"]);
+            (&Operation::QueryPackagesOutputs, _) => {
+                command.args(&[
+                    "--query",
+                    "--available",
+                    "--attr-path",
+                    "--out-path",
+                    "--drv-path",
+                    "--json",
+                ]);
             },
-            Operation::NoOp { ref operation } => { operation.args(command); },
+            (&Operation::NoOp { ref operation }, _) => { operation.args(command, cli); },
             _ => (),
         };
     }
+
+    fn is_build(&self) -> bool {
+        match *self {
+            Operation::Build => true,
+            Operation::NoOp { ref operation } => operation.is_build(),
+            _ => false,
+        }
+    }
+
+    /// Whether the new CLI has a command for this, querying packages
+    /// is only done by nix-env.
+    fn has_new_cli(&self) -> bool {
+        match *self {
+            Operation::Evaluate | Operation::Instantiate | Operation::Build | Operation::Log |
+            Operation::Realisations | Operation::PathInfo => true,
+            Operation::NoOp { ref operation } => operation.has_new_cli(),
+            _ => false,
+        }
+    }
+}
+
+/// Translate nix-build style arguments, `file -A attr` and `-E expr`,
+/// to the new CLI's `--file file attr` and `--expr expr`.
+fn new_cli_args(args: Vec<String>) -> Vec<String> {
+    let mut translated: Vec<String> = vec![];
+    let mut attrs: Vec<String> = vec![];
+    let mut file = false;
+
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        match arg.as_ref() {
+            "-A" | "--attr" => attrs.extend(args.next()),
+            "-E" | "--expr" => {
+                translated.push(String::from("--expr"));
+                translated.extend(args.next());
+            }
+            "--arg" | "--argstr" | "--option" => {
+                translated.push(arg.clone());
+                translated.extend(args.by_ref().take(2));
+            }
+            _ if arg.starts_with('-') => translated.push(arg),
+            _ => {
+                file = true;
+                translated.push(String::from("--file"));
+                translated.push(arg);
+            }
+        }
+    }
+
+    // The whole file, like nix-build without any -A.
+    if file && attrs.len() == 0 {
+        attrs.push(String::new());
+    }
+
+    translated.extend(attrs);
+    return translated;
 }
 
 impl fmt::Display for Operation {
@@ -51,8 +319,14 @@ impl fmt::Display for Operation {
         match *self {
             Operation::Build => write!(f, "{}", "nix-build"),
             Operation::Instantiate => write!(f, "{}", "nix-instantiate"),
+            Operation::Evaluate => write!(f, "{}", "nix-instantiate --eval --strict --json"),
+            Operation::Log => write!(f, "{}", "nix-store --read-log"),
+            Operation::Realisations => write!(f, "{}", "nix realisation info --json"),
+            Operation::PathInfo => write!(f, "{}", "nix path-info --json"),
             Operation::QueryPackagesJSON => write!(f, "{}", "nix-env -qa --json"),
-            Operation::QueryPackagesOutputs => write!(f, "{}", "nix-env -qaP --no-name --out-path"),
+            Operation::QueryPackagesOutputs => {
+                write!(f, "{}", "nix-env -qaP --out-path --drv-path --json")
+            }
             Operation::NoOp { ref operation } => operation.fmt(f),
             Operation::Unknown { ref program } => write!(f, "{}", program),
         }
@@ -66,6 +340,13 @@ pub struct Nix {
     build_timeout: u16,
     limit_supported_systems: bool,
     initial_heap_size: Option<String>,
+    watch_memory: bool,
+    memory_limit_mb: Option<u64>,
+    cli: Cli,
+    store: Option<String>,
+    options: BTreeMap<String, String>,
+    system_options: BTreeMap<String, BTreeMap<String, String>>,
+    pin: Option<Pin>,
 }
 
 impl Nix {
@@ -76,9 +357,69 @@ impl Nix {
             build_timeout: build_timeout,
             initial_heap_size: initial_heap_size,
             limit_supported_systems: true,
+            watch_memory: false,
+            memory_limit_mb: None,
+            cli: Cli::Legacy,
+            store: None,
+            options: BTreeMap::new(),
+            system_options: BTreeMap::new(),
+            pin: None,
         };
     }
 
+    /// Build in `store`, like `ssh-ng://host`, evaluating locally.
+    pub fn with_store(&self, store: Option<String>) -> Nix {
+        let mut n = self.clone();
+        n.store = store;
+        return n;
+    }
+
+    pub fn with_cli(&self, cli: Cli) -> Nix {
+        let mut n = self.clone();
+        n.cli = cli;
+        return n;
+    }
+
+    /// Pass these `--option`s to every command, and the ones of the
+    /// system commands run for over those.
+    pub fn with_options(
+        &self,
+        options: BTreeMap<String, String>,
+        system_options: BTreeMap<String, BTreeMap<String, String>>,
+    ) -> Nix {
+        let mut n = self.clone();
+        n.options = options;
+        n.system_options = system_options;
+        return n;
+    }
+
+    pub fn cli(&self) -> Cli {
+        return self.cli;
+    }
+
+    /// Watch how much memory commands use, for the evaluator, and kill
+    /// any whose resident memory grows past the limit. Watching polls
+    /// each command, so builds aren't watched.
+    pub fn with_memory_limit(&self, memory_limit_mb: Option<u64>) -> Nix {
+        let mut n = self.clone();
+        n.watch_memory = true;
+        n.memory_limit_mb = memory_limit_mb;
+        return n;
+    }
+
+    pub fn with_build_timeout(&self, build_timeout: u16) -> Nix {
+        let mut n = self.clone();
+        n.build_timeout = build_timeout;
+        return n;
+    }
+
+    /// Find `<nixpkgs>` at the pin instead of the checkout.
+    pub fn with_pin(&self, pin: Option<Pin>) -> Nix {
+        let mut n = self.clone();
+        n.pin = pin;
+        return n;
+    }
+
     pub fn with_system(&self, system: String) -> Nix {
         let mut n = self.clone();
         n.system = system;
@@ -114,6 +455,51 @@ impl Nix {
             })
     }
 
+    /// The attribute names of the set at `attr_path` in `file`, like
+    /// the packages in `python3Packages`. The path's components must
+    /// already be valid attribute names.
+    pub fn attr_names(&self, nixpkgs: &Path, file: &str, attr_path: &str) -> Option<Vec<String>> {
+        let mut select = String::new();
+        for component in attr_path.split('.').filter(|c| c.len() > 0) {
+            let valid = component.chars().all(|c| {
+                c.is_alphanumeric() || c == '_' || c == '-' || c == '\''
+            });
+            if !valid {
+                return None;
+            }
+            select.push_str(&format!(".\"{}\"", component));
+        }
+
+        // Only hand the file the arguments it takes, nixos/release.nix
+        // doesn't take a system.
+        let expr = format!(
+            "{{ ... }}@args: let f = import {}; in \
+             builtins.attrNames (f (builtins.intersectAttrs (builtins.functionArgs f) args)){}",
+            file,
+            select
+        );
+
+        return match self.evaluate_json(nixpkgs, vec![String::from("-E"), expr]) {
+            Ok(names) => Some(names),
+            Err(mut output) => {
+                let mut reason = String::new();
+                output.read_to_string(&mut reason).ok();
+                info!("Failed to list the attributes of {}: {}", attr_path, reason);
+                None
+            }
+        };
+    }
+
+    /// Evaluate with `--json`, decoding the result. The error carries
+    /// nix's output, or why the result didn't decode.
+    pub fn evaluate_json<T>(&self, nixpkgs: &Path, args: Vec<String>) -> Result<T, File>
+    where
+        T: DeserializeOwned,
+    {
+        let stdout = self.run_for_stdout(self.safe_command(Operation::Evaluate, nixpkgs, args))?;
+        return decode_json(stdout);
+    }
+
     pub fn safely_instantiate_attrs(
         &self,
         nixpkgs: &Path,
@@ -168,6 +554,65 @@ impl Nix {
         return self.safe_command(Operation::Build, nixpkgs, attrargs);
     }
 
+    /// The realisations of `attrs` built before, only known to the
+    /// new CLI.
+    pub fn realisations(
+        &self,
+        nixpkgs: &Path,
+        file: &str,
+        attrs: Vec<String>,
+    ) -> Result<Vec<Realisation>, File> {
+        if self.cli != Cli::New {
+            return Err(note("Realisations need nix 2.4 or later"));
+        }
+
+        let mut attrargs: Vec<String> = vec![file.to_owned()];
+        for attr in attrs {
+            attrargs.push(String::from("-A"));
+            attrargs.push(attr);
+        }
+
+        let stdout = self.run_for_stdout(
+            self.safe_command(Operation::Realisations, nixpkgs, attrargs),
+        )?;
+        return decode_json(stdout);
+    }
+
+    /// Describe `paths`, which must be valid.
+    pub fn path_info(&self, paths: Vec<String>) -> Result<Vec<PathInfo>, File> {
+        return self.query_paths(vec![], paths);
+    }
+
+    /// Describe every path in the closure of `paths`, with their
+    /// closure sizes.
+    pub fn closure(&self, paths: Vec<String>) -> Result<Vec<PathInfo>, File> {
+        return self.query_paths(vec!["--recursive", "--closure-size"], paths);
+    }
+
+    pub fn closure_size(&self, path: &str) -> Result<u64, File> {
+        let infos = self.query_paths(vec!["--closure-size"], vec![path.to_owned()])?;
+        return infos
+            .into_iter()
+            .find(|info| info.path == path)
+            .and_then(|info| info.closure_size)
+            .ok_or_else(|| note(&format!("nix didn't tell the closure size of {}", path)));
+    }
+
+    fn query_paths(&self, args: Vec<&str>, paths: Vec<String>) -> Result<Vec<PathInfo>, File> {
+        let mut command = Operation::PathInfo.command(self.cli);
+        Operation::PathInfo.args(&mut command, self.cli);
+        self.clear_env(&mut command);
+        if let Some(ref store) = self.store {
+            command.args(&["--store", store]);
+        }
+        command.args(self.option_args());
+        command.args(args);
+        command.args(paths);
+
+        let output: serde_json::Value = decode_json(self.run_for_stdout(command)?)?;
+        return parse_path_infos(output).map_err(|e| note(&e));
+    }
+
     pub fn safely(
         &self,
         op: Operation,
@@ -178,9 +623,8 @@ impl Nix {
         return self.run(self.safe_command(op, nixpkgs, args), keep_stdout);
     }
 
-    pub fn run(&self, mut cmd: Command, keep_stdout: bool) -> Result<File, File> {
+    pub fn run(&self, cmd: Command, keep_stdout: bool) -> Result<File, File> {
         let stderr = tempfile().expect("Fetching a stderr tempfile");
-        let mut reader = stderr.try_clone().expect("Cloning stderr to the reader");
 
         let stdout: Stdio;
 
@@ -191,32 +635,109 @@ impl Nix {
             stdout = Stdio::null();
         }
 
-        let status = cmd.stdout(Stdio::from(stdout))
+        return self.run_with(cmd, stdout, stderr);
+    }
+
+    /// Like `run`, but keeping stdout apart from stderr, for results
+    /// to decode. The error carries stderr.
+    pub fn run_for_stdout(&self, cmd: Command) -> Result<File, File> {
+        let stdout = tempfile().expect("Fetching a stdout tempfile");
+        let mut reader = stdout.try_clone().expect("Cloning stdout to the reader");
+        let stderr = tempfile().expect("Fetching a stderr tempfile");
+
+        self.run_with(cmd, Stdio::from(stdout), stderr)?;
+
+        reader.seek(SeekFrom::Start(0)).expect(
+            "Seeking to Start(0)",
+        );
+        return Ok(reader);
+    }
+
+    fn run_with(&self, mut cmd: Command, stdout: Stdio, stderr: File) -> Result<File, File> {
+        let mut reader = stderr.try_clone().expect("Cloning stderr to the reader");
+
+        let mut child = cmd.stdout(stdout)
             .stderr(Stdio::from(stderr))
-            .status()
+            .spawn()
             .expect(format!("Running a program ...").as_ref());
 
+        let (status, exceeded) = if self.watch_memory {
+            wait_within_memory_limit(&mut child, self.memory_limit_mb)
+        } else {
+            (child.wait().expect("Waiting on a program"), false)
+        };
+
+        if exceeded {
+            reader.seek(SeekFrom::End(0)).expect("Seeking to End(0)");
+            writeln!(reader, "{}", MEMORY_LIMIT_EXCEEDED).expect("Noting the memory limit");
+        }
+
         reader.seek(SeekFrom::Start(0)).expect(
             "Seeking to Start(0)",
         );
 
-        if status.success() {
+        if status.success() && !exceeded {
             return Ok(reader);
         } else {
             return Err(reader);
         }
     }
 
-    pub fn safe_command(&self, op: Operation, nixpkgs: &Path, args: Vec<String>) -> Command {
-        let nixpath = format!("nixpkgs={}", nixpkgs.display());
+    /// The log of a derivation built before, here, in the store we
+    /// build in or by a substituter.
+    pub fn log(&self, drv_path: &str) -> Result<File, File> {
+        let mut command = Operation::Log.command(self.cli);
+        Operation::Log.args(&mut command, self.cli);
+        self.clear_env(&mut command);
+        if let Some(ref store) = self.store {
+            command.args(&["--store", store]);
+        }
+        command.args(self.option_args());
+        command.arg(drv_path);
+
+        return self.run(command, true);
+    }
 
-        let mut command = op.command();
-        op.args(&mut command);
+    /// Run the new CLI with flakes enabled on the flake in `flake`,
+    /// whichever CLI is configured otherwise.
+    pub fn flake_command(&self, flake: &Path, args: Vec<String>) -> Command {
+        let mut command = Command::new("nix");
+        command.args(&["--extra-experimental-features", "nix-command flakes"]);
+        command.args(args);
+
+        self.clear_env(&mut command);
+        command.current_dir(flake);
+        command.args(
+            &[
+                "--option",
+                "build-timeout",
+                &format!("{}", self.build_timeout),
+            ],
+        );
+        command.args(self.option_args());
+
+        return command;
+    }
+
+    pub fn system(&self) -> &str {
+        &self.system
+    }
 
+    fn option_args(&self) -> Vec<String> {
+        let mut options = self.options.clone();
+        if let Some(system_options) = self.system_options.get(&self.system) {
+            options.extend(system_options.clone());
+        }
+
+        options
+            .into_iter()
+            .flat_map(|(name, value)| vec![String::from("--option"), name, value])
+            .collect()
+    }
+
+    fn clear_env(&self, command: &mut Command) {
         command.env_clear();
-        command.current_dir(nixpkgs);
         command.env("HOME", "/homeless-shelter");
-        command.env("NIX_PATH", nixpath);
         command.env("NIX_REMOTE", &self.remote);
 
         if let Some(ref initial_heap_size) = self.initial_heap_size {
@@ -225,6 +746,20 @@ impl Nix {
 
         let path = env::var("PATH").unwrap();
         command.env("PATH", path);
+    }
+
+    pub fn safe_command(&self, op: Operation, nixpkgs: &Path, args: Vec<String>) -> Command {
+        let nixpath = match self.pin {
+            Some(ref pin) => pin.nix_path(),
+            None => format!("nixpkgs={}", nixpkgs.display()),
+        };
+
+        let mut command = op.command(self.cli);
+        op.args(&mut command, self.cli);
+
+        self.clear_env(&mut command);
+        command.current_dir(nixpkgs);
+        command.env("NIX_PATH", nixpath);
 
         command.args(&["--show-trace"]);
         command.args(&["--option", "restrict-eval", "true"]);
@@ -235,8 +770,16 @@ impl Nix {
                 &format!("{}", self.build_timeout),
             ],
         );
+        command.args(self.option_args());
         command.args(&["--argstr", "system", &self.system]);
 
+        if let (true, &Some(ref store)) = (op.is_build(), &self.store) {
+            if self.cli == Cli::New {
+                command.args(&["--eval-store", "auto"]);
+            }
+            command.args(&["--store", store]);
+        }
+
         if self.limit_supported_systems {
             command.args(
                 &[
@@ -247,12 +790,124 @@ impl Nix {
             );
         }
 
-        command.args(args);
+        if self.cli == Cli::New && op.has_new_cli() {
+            command.args(new_cli_args(args));
+        } else {
+            command.args(args);
+        }
 
         return command;
     }
 }
 
+/// Decode a command's JSON output, the error carries why it didn't.
+pub fn decode_json<T>(output: File) -> Result<T, File>
+where
+    T: DeserializeOwned,
+{
+    return serde_json::from_reader(output).map_err(|e| {
+        note(&format!("Failed to decode nix's output: {:?}", e))
+    });
+}
+
+/// `nix path-info --json` prints a list of path infos, and since nix
+/// 2.19 an object of them by path, with `null` for invalid paths.
+fn parse_path_infos(output: serde_json::Value) -> Result<Vec<PathInfo>, String> {
+    let described: Vec<serde_json::Value> = match output {
+        serde_json::Value::Array(infos) => infos
+            .into_iter()
+            .filter(|info| info.get("valid") != Some(&serde_json::Value::Bool(false)))
+            .collect(),
+        serde_json::Value::Object(infos) => infos
+            .into_iter()
+            .filter_map(|(path, info)| match info {
+                serde_json::Value::Object(mut info) => {
+                    info.insert(String::from("path"), serde_json::Value::String(path));
+                    Some(serde_json::Value::Object(info))
+                }
+                _ => None,
+            })
+            .collect(),
+        other => return Err(format!("Unexpected path infos: {}", other)),
+    };
+
+    return described
+        .into_iter()
+        .map(|info| {
+            serde_json::from_value(info).map_err(|e| format!("Failed to decode a path info: {:?}", e))
+        })
+        .collect();
+}
+
+/// A file with `message`, for errors to carry like nix's output.
+fn note(message: &str) -> File {
+    let mut note = tempfile().expect("Fetching a tempfile for the note");
+    writeln!(note, "{}", message).expect("Writing the note");
+    note.seek(SeekFrom::Start(0)).expect("Seeking to Start(0)");
+    return note;
+}
+
+pub fn exceeded_memory_limit(output: &str) -> bool {
+    output.contains(MEMORY_LIMIT_EXCEEDED)
+}
+
+/// The highest peak memory use of any command run since the last
+/// call, in MiB.
+pub fn take_peak_memory_mb() -> u64 {
+    PEAK_MEMORY_MB.swap(0, Ordering::SeqCst) as u64
+}
+
+fn record_peak_memory_mb(peak: usize) {
+    let mut current = PEAK_MEMORY_MB.load(Ordering::SeqCst);
+    while peak > current {
+        match PEAK_MEMORY_MB.compare_exchange(current, peak, Ordering::SeqCst, Ordering::SeqCst) {
+            Ok(_) => return,
+            Err(actual) => current = actual,
+        }
+    }
+}
+
+fn wait_within_memory_limit(child: &mut Child, limit_mb: Option<u64>) -> (ExitStatus, bool) {
+    loop {
+        if let Some(status) = child.try_wait().expect("Waiting on a program") {
+            return (status, false);
+        }
+
+        if let Some(peak) = memory_mb(child.id(), "VmHWM:") {
+            record_peak_memory_mb(peak as usize);
+        }
+
+        if let Some(limit_mb) = limit_mb {
+            if memory_mb(child.id(), "VmRSS:").map_or(false, |mb| mb > limit_mb) {
+                warn!("Process {} exceeded the {}MiB memory limit, killing it", child.id(), limit_mb);
+                if let Err(e) = child.kill() {
+                    warn!("Failed to kill process {}: {:?}", child.id(), e);
+                }
+                return (child.wait().expect("Waiting on a killed program"), true);
+            }
+        }
+
+        thread::sleep(Duration::from_millis(100));
+    }
+}
+
+fn memory_mb(pid: u32, field: &str) -> Option<u64> {
+    let mut status = String::new();
+    File::open(format!("/proc/{}/status", pid))
+        .and_then(|mut file| file.read_to_string(&mut status))
+        .ok()?;
+
+    let kb: u64 = status
+        .lines()
+        .find(|line| line.starts_with(field))?
+        .split_whitespace()
+        .nth(1)?
+        .parse()
+        .ok()?;
+
+    return Some(kb / 1024);
+}
+
 #[cfg(test)]
 mod tests {
     fn nix() -> Nix {
@@ -398,6 +1053,245 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_new_cli_build_operation() {
+        let nix = nix().with_cli(Cli::New);
+        let op = noop(Operation::Build);
+
+        let ret: Result<File, File> = nix.run(
+            nix.safe_command(
+                op,
+                build_path().as_path(),
+                vec![
+                    String::from("./default.nix"),
+                    String::from("-A"),
+                    String::from("success"),
+                ],
+            ),
+            true,
+        );
+
+        assert_run(
+            ret,
+            Expect::Pass,
+            vec![
+                "--extra-experimental-features nix-command build --no-link --keep-going",
+                "--file ./default.nix success",
+            ],
+        );
+    }
+
+    #[test]
+    fn test_remote_store_build() {
+        let nix = nix()
+            .with_cli(Cli::New)
+            .with_store(Some(String::from("ssh-ng://builder@mac")));
+
+        let ret: Result<File, File> = nix.run(
+            nix.safe_command(noop(Operation::Build), build_path().as_path(), vec![]),
+            true,
+        );
+
+        assert_run(
+            ret,
+            Expect::Pass,
+            vec!["--eval-store auto --store ssh-ng://builder@mac"],
+        );
+    }
+
+    #[test]
+    fn test_options() {
+        let mut options = BTreeMap::new();
+        options.insert(String::from("cores"), String::from("4"));
+        options.insert(String::from("max-jobs"), String::from("2"));
+        let mut aarch64 = BTreeMap::new();
+        aarch64.insert(String::from("cores"), String::from("16"));
+        let mut system_options = BTreeMap::new();
+        system_options.insert(String::from("aarch64-linux"), aarch64);
+
+        let nix = nix().with_options(options, system_options);
+
+        let ret: Result<File, File> = nix.run(
+            nix.safe_command(noop(Operation::Build), build_path().as_path(), vec![]),
+            true,
+        );
+        assert_run(
+            ret,
+            Expect::Pass,
+            vec!["--option cores 4 --option max-jobs 2 --argstr system x86_64-linux"],
+        );
+
+        let nix = nix.with_system(String::from("aarch64-linux"));
+        let ret: Result<File, File> = nix.run(
+            nix.safe_command(noop(Operation::Build), build_path().as_path(), vec![]),
+            true,
+        );
+        assert_run(
+            ret,
+            Expect::Pass,
+            vec!["--option cores 16 --option max-jobs 2 --argstr system aarch64-linux"],
+        );
+    }
+
+    #[test]
+    fn test_new_cli_args() {
+        let args = |args: Vec<&str>| args.into_iter().map(String::from).collect::<Vec<String>>();
+
+        assert_eq!(
+            new_cli_args(args(vec!["./default.nix", "-A", "hello", "-A", "tests.foo"])),
+            args(vec!["--file", "./default.nix", "hello", "tests.foo"])
+        );
+        assert_eq!(
+            new_cli_args(args(vec!["-E", "1 + 1", "--arg", "config", "{ }"])),
+            args(vec!["--expr", "1 + 1", "--arg", "config", "{ }"])
+        );
+        assert_eq!(
+            new_cli_args(args(vec!["./nixos/release.nix", "--show-trace"])),
+            args(vec!["--file", "./nixos/release.nix", "--show-trace", ""])
+        );
+    }
+
+    #[test]
+    fn test_cli_for_version() {
+        assert_eq!(Cli::for_version("nix-env (Nix) 2.0.4"), Cli::Legacy);
+        assert_eq!(Cli::for_version("nix (Nix) 2.3.16"), Cli::Legacy);
+        assert_eq!(Cli::for_version("nix (Nix) 2.4"), Cli::New);
+        assert_eq!(Cli::for_version("nix (Nix) 2.11.0pre20220801_dirty"), Cli::New);
+        assert_eq!(Cli::for_version(""), Cli::Legacy);
+    }
+
+    #[test]
+    fn test_capabilities() {
+        let capabilities = Capabilities::parse(
+            "nix (Nix) 2.4\n",
+            "cores = 0
+experimental-features = flakes nix-command
+sandbox = true
+system-features = benchmark big-parallel kvm nixos-test
+",
+        );
+
+        assert_eq!(
+            capabilities,
+            Capabilities {
+                version: String::from("2.4"),
+                experimental_features: vec![String::from("flakes"), String::from("nix-command")],
+                system_features: vec![
+                    String::from("benchmark"),
+                    String::from("big-parallel"),
+                    String::from("kvm"),
+                    String::from("nixos-test"),
+                ],
+                sandbox: true,
+            }
+        );
+
+        assert_eq!(
+            capabilities.missing(&vec![
+                String::from("flakes"),
+                String::from("sandbox"),
+                String::from("nixos-test"),
+                String::from("ca-derivations"),
+            ]),
+            vec![String::from("ca-derivations")]
+        );
+
+        let legacy = Capabilities::parse("nix-env (Nix) 2.0.4", "sandbox = false\n");
+        assert_eq!(legacy.version, "2.0.4");
+        assert_eq!(
+            legacy.missing(&vec![String::from("sandbox"), String::from("kvm")]),
+            vec![String::from("sandbox"), String::from("kvm")]
+        );
+    }
+
+    #[test]
+    fn test_realisations_operation() {
+        let nix = nix().with_cli(Cli::New);
+        let op = noop(Operation::Realisations);
+        assert_eq!(op.to_string(), "nix realisation info --json");
+
+        let ret: Result<File, File> = nix.run(
+            nix.safe_command(op, build_path().as_path(), vec![
+                String::from("./default.nix"),
+                String::from("-A"),
+                String::from("hello"),
+            ]),
+            true,
+        );
+
+        assert_run(
+            ret,
+            Expect::Pass,
+            vec![
+                "--extra-experimental-features nix-command --extra-experimental-features \
+                 ca-derivations realisation info --json",
+                "--file ./default.nix hello",
+            ],
+        );
+
+        assert!(nix().realisations(build_path().as_path(), "./default.nix", vec![]).is_err());
+
+        let realisations: Vec<Realisation> = serde_json::from_str(
+            r#"[{"dependentRealisations":{},"id":"sha256:1c2e!out","outPath":"/nix/store/abc-hello","signatures":[]}]"#,
+        ).unwrap();
+        assert_eq!(
+            realisations,
+            vec![Realisation {
+                id: String::from("sha256:1c2e!out"),
+                out_path: String::from("/nix/store/abc-hello"),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_parse_path_infos() {
+        let hello = PathInfo {
+            path: String::from("/nix/store/abc-hello-2.10"),
+            nar_size: 206016,
+            closure_size: Some(30283824),
+            references: vec![
+                String::from("/nix/store/abc-hello-2.10"),
+                String::from("/nix/store/def-glibc-2.27"),
+            ],
+            signatures: vec![String::from("cache.nixos.org-1:abc==")],
+            deriver: Some(String::from("/nix/store/ghi-hello-2.10.drv")),
+        };
+
+        let listed = serde_json::from_str(
+            r#"[
+  {
+    "path": "/nix/store/abc-hello-2.10",
+    "narHash": "sha256:1hq8y0rd5kh5xw2ykilmn6znh0bgvxp1q1wvkskqgl0zihl6cz5x",
+    "narSize": 206016,
+    "closureSize": 30283824,
+    "references": ["/nix/store/abc-hello-2.10", "/nix/store/def-glibc-2.27"],
+    "deriver": "/nix/store/ghi-hello-2.10.drv",
+    "signatures": ["cache.nixos.org-1:abc=="]
+  },
+  { "path": "/nix/store/jkl-missing", "valid": false }
+]"#,
+        ).unwrap();
+        assert_eq!(parse_path_infos(listed), Ok(vec![hello.clone()]));
+
+        let keyed = serde_json::from_str(
+            r#"{
+  "/nix/store/abc-hello-2.10": {
+    "narHash": "sha256-abc=",
+    "narSize": 206016,
+    "closureSize": 30283824,
+    "references": ["/nix/store/abc-hello-2.10", "/nix/store/def-glibc-2.27"],
+    "deriver": "/nix/store/ghi-hello-2.10.drv",
+    "signatures": ["cache.nixos.org-1:abc=="]
+  },
+  "/nix/store/jkl-missing": null
+}"#,
+        ).unwrap();
+        assert_eq!(parse_path_infos(keyed), Ok(vec![hello]));
+
+        assert!(parse_path_infos(serde_json::from_str(r#""nope""#).unwrap()).is_err());
+        assert!(parse_path_infos(serde_json::from_str(r#"[{"path": "/nix/store/x"}]"#).unwrap()).is_err());
+    }
+
     #[test]
     fn test_instantiate_operation() {
         let nix = nix();
@@ -417,6 +1311,25 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_evaluate_operation() {
+        let nix = nix();
+        let op = noop(Operation::Evaluate);
+        assert_eq!(op.to_string(), "nix-instantiate --eval --strict --json");
+
+        let ret: Result<File, File> =
+            nix.run(
+                nix.safe_command(op, build_path().as_path(), vec![String::from("--version")]),
+                true,
+            );
+
+        assert_run(
+            ret,
+            Expect::Pass,
+            vec!["--eval --strict --json", "--version"],
+        );
+    }
+
     #[test]
     fn test_query_packages_json() {
         let nix = nix();
@@ -440,7 +1353,7 @@ mod tests {
     fn test_query_packages_outputs() {
         let nix = nix();
         let op = noop(Operation::QueryPackagesOutputs);
-        assert_eq!(op.to_string(), "nix-env -qaP --no-name --out-path");
+        assert_eq!(op.to_string(), "nix-env -qaP --out-path --drv-path --json");
 
         let ret: Result<File, File> =
             nix.run(
@@ -452,7 +1365,7 @@ mod tests {
             ret,
             Expect::Pass,
             vec![
-                "--query --available --no-name --attr-path --out-path",
+                "--query --available --attr-path --out-path --drv-path --json",
                 "--version"
             ],
         );
@@ -635,6 +1548,21 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_memory_limit() {
+        let nix = nix().with_memory_limit(Some(10));
+
+        let mut cmd = Command::new("sh");
+        cmd.args(&["-c", "x=$(head -c 50000000 /dev/zero | tr '\\0' a); sleep 30"]);
+        let ret: Result<File, File> = nix.run(cmd, true);
+        assert_run(ret, Expect::Fail, vec![MEMORY_LIMIT_EXCEEDED]);
+
+        let mut cmd = Command::new("echo");
+        cmd.arg("within the limit");
+        let ret: Result<File, File> = nix.run(cmd, true);
+        assert_run(ret, Expect::Pass, vec!["within the limit"]);
+    }
+
     #[test]
     fn instantiation() {
         let ret: Result<File, File> = nix().safely(
@@ -0,0 +1,184 @@
+//! Pinned nixpkgs checkouts, one per branch, for the `<nixpkgs>` of
+//! what isn't nixpkgs itself. A pin's checkout never changes, updates
+//! check the branch's new head out next to it, so evaluations and
+//! builds started on a pin can be repeated.
+
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::fs;
+use std::io::{Error, ErrorKind};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::{Arc, RwLock};
+use std::thread;
+use std::time::Duration;
+use ofborg::checkout;
+
+/// How often to update the pins by default.
+pub const UPDATE_SECS: u64 = 3600;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Pin {
+    pub branch: String,
+    pub commit: String,
+    pub path: String,
+}
+
+impl Pin {
+    pub fn nix_path(&self) -> String {
+        format!("nixpkgs={}", self.path)
+    }
+}
+
+#[derive(Clone)]
+pub struct Pins {
+    root: PathBuf,
+    repo: String,
+    clone_url: String,
+    branches: Vec<String>,
+    pins: Arc<RwLock<HashMap<String, Pin>>>,
+}
+
+impl Pins {
+    /// Pin `branches` of `repo`, like `NixOS/nixpkgs`, checked out
+    /// under `root`.
+    pub fn new(root: &Path, repo: String, clone_url: String, branches: Vec<String>) -> Pins {
+        return Pins {
+            root: root.to_path_buf(),
+            repo: repo,
+            clone_url: clone_url,
+            branches: branches,
+            pins: Arc::new(RwLock::new(HashMap::new())),
+        };
+    }
+
+    /// Whether `full_name` is the pinned repository, which is its own
+    /// `<nixpkgs>`.
+    pub fn is_pinned_repo(&self, full_name: &str) -> bool {
+        self.repo.to_lowercase() == full_name.to_lowercase()
+    }
+
+    /// The pin of `branch`, or of the first branch for branches which
+    /// aren't pinned.
+    pub fn get(&self, branch: &str) -> Option<Pin> {
+        let pins = self.pins.read().unwrap();
+        return pins.get(branch)
+            .or_else(|| self.branches.first().and_then(|first| pins.get(first)))
+            .cloned();
+    }
+
+    pub fn update_all(&self) {
+        for branch in self.branches.iter() {
+            match self.update(branch) {
+                Ok(pin) => info!("Pinned {} to {}", branch, pin.commit),
+                Err(e) => warn!("Failed to update the pin of {}: {:?}", branch, e),
+            }
+        }
+    }
+
+    pub fn update(&self, branch: &str) -> Result<Pin, Error> {
+        let project = checkout::cached_cloner(&self.root).project(
+            self.repo.clone(),
+            self.clone_url.clone(),
+        );
+        let co = project.clone_for("channel".to_owned(), branch.to_owned())?;
+        let head = co.checkout_origin_ref(OsStr::new(branch))?;
+        let commit = git(Path::new(&head), &["rev-parse", "HEAD"])?;
+
+        let mut path = self.root.clone();
+        path.push("pins");
+        path.push(&commit);
+        if !path.exists() {
+            fs::create_dir_all(path.parent().unwrap())?;
+            git(
+                Path::new(&head),
+                &["worktree", "add", "--detach", &path.to_string_lossy(), &commit],
+            )?;
+        }
+
+        let pin = Pin {
+            branch: branch.to_owned(),
+            commit: commit,
+            path: path.to_string_lossy().into_owned(),
+        };
+        self.pins.write().unwrap().insert(branch.to_owned(), pin.clone());
+
+        return Ok(pin);
+    }
+
+    /// Update the pins now, and then every `every`.
+    pub fn watch(&self, every: Duration) {
+        self.update_all();
+
+        let pins = self.clone();
+        thread::spawn(move || loop {
+            thread::sleep(every);
+            pins.update_all();
+        });
+    }
+}
+
+fn git(dir: &Path, args: &[&str]) -> Result<String, Error> {
+    let output = Command::new("git").args(args).current_dir(dir).output()?;
+
+    if output.status.success() {
+        return Ok(String::from_utf8_lossy(&output.stdout).trim().to_owned());
+    } else {
+        return Err(Error::new(
+            ErrorKind::Other,
+            String::from_utf8_lossy(&output.stderr).into_owned(),
+        ));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::{Path, PathBuf};
+    use std::process::{Command, Stdio};
+    use ofborg::test_scratch::TestScratch;
+
+    fn tpath(component: &str) -> PathBuf {
+        return Path::new(env!("CARGO_MANIFEST_DIR")).join(component);
+    }
+
+    fn make_pr_repo(bare: &Path, co: &Path) {
+        Command::new("./make-pr.sh")
+            .current_dir(tpath("./test-srcs"))
+            .arg(bare)
+            .arg(co)
+            .stderr(Stdio::null())
+            .stdout(Stdio::null())
+            .status()
+            .expect("building the test PR failed");
+    }
+
+    #[test]
+    pub fn test_pin_branch() {
+        let working = TestScratch::new_dir("nixpath-pin-branch-working");
+        let bare = TestScratch::new_dir("nixpath-pin-branch-bare");
+        let co = TestScratch::new_dir("nixpath-pin-branch-co");
+        make_pr_repo(&bare.path(), &co.path());
+        let master = git(&co.path(), &["rev-parse", "master"]).unwrap();
+
+        let pins = Pins::new(
+            &working.path(),
+            String::from("NixOS/nixpkgs"),
+            bare.path().to_str().unwrap().to_owned(),
+            vec![String::from("master")],
+        );
+        assert_eq!(pins.get("master"), None);
+
+        let pin = pins.update("master").unwrap();
+        assert_eq!(pin.commit, master);
+        assert!(Path::new(&pin.path).join("default.nix").exists());
+        assert_eq!(pin.nix_path(), format!("nixpkgs={}", pin.path));
+
+        assert_eq!(pins.get("master"), Some(pin.clone()));
+        assert_eq!(pins.get("release-18.03"), Some(pin.clone()));
+        assert_eq!(pins.update("master").unwrap(), pin);
+
+        assert!(pins.is_pinned_repo("nixos/nixpkgs"));
+        assert!(!pins.is_pinned_repo("NixOS/ofborg"));
+    }
+}
@@ -2,10 +2,19 @@ use amqp::Basic;
 use amqp::{Consumer, Channel};
 use amqp::protocol::basic::{Deliver, BasicProperties};
 use std::marker::Send;
-use worker::Action;
+use worker::{self, Action};
+use ofborg::correlation::Correlation;
+use ofborg::easyamqp;
+use ofborg::msgsign::Signer;
+use ofborg::stats;
+use ofborg::tracing;
+use ofborg::logging;
 
 pub struct NotifyWorker<T: SimpleNotifyWorker> {
     internal: T,
+    jobs: Option<stats::Counter>,
+    tracer: Option<tracing::Tracer>,
+    signer: Option<Signer>,
 }
 
 pub trait SimpleNotifyWorker {
@@ -19,6 +28,9 @@ pub trait SimpleNotifyWorker {
         headers: &BasicProperties,
         body: &Vec<u8>,
     ) -> Result<Self::J, String>;
+
+    /// Register the metrics of this task, if it has any.
+    fn register_metrics(&mut self, _registry: &stats::Registry) {}
 }
 
 pub trait NotificationReceiver {
@@ -41,9 +53,28 @@ impl NotificationReceiver for DummyNotificationReceiver {
     }
 }
 
+/// Prints what would be done, instead of doing it.
+pub struct StdoutNotificationReceiver {}
+
+impl StdoutNotificationReceiver {
+    pub fn new() -> StdoutNotificationReceiver {
+        StdoutNotificationReceiver {}
+    }
+}
+
+impl NotificationReceiver for StdoutNotificationReceiver {
+    fn tell(&mut self, action: Action) {
+        println!("{}", worker::describe(&action));
+    }
+}
+
 pub struct ChannelNotificationReceiver<'a> {
     channel: &'a mut Channel,
     delivery_tag: u64,
+    jobs: Option<stats::Counter>,
+    span: Option<tracing::Span>,
+    correlation: Correlation,
+    signer: Option<Signer>,
 }
 
 impl<'a> ChannelNotificationReceiver<'a> {
@@ -51,12 +82,55 @@ impl<'a> ChannelNotificationReceiver<'a> {
         return ChannelNotificationReceiver {
             channel: channel,
             delivery_tag: delivery_tag,
+            jobs: None,
+            span: None,
+            correlation: Correlation::default(),
+            signer: None,
         };
     }
+
+    /// Count the jobs by their outcome in `jobs`.
+    pub fn with_jobs(mut self, jobs: Option<stats::Counter>) -> ChannelNotificationReceiver<'a> {
+        self.jobs = jobs;
+        return self;
+    }
+
+    /// Record the outcome in `span`, and carry its context along with
+    /// the messages published.
+    pub fn with_span(mut self, span: Option<tracing::Span>) -> ChannelNotificationReceiver<'a> {
+        self.span = span;
+        return self;
+    }
+
+    /// Carry `correlation` along with the messages published.
+    pub fn with_correlation(mut self, correlation: Correlation) -> ChannelNotificationReceiver<'a> {
+        self.correlation = correlation;
+        return self;
+    }
+
+    /// Sign the messages published with `signer`.
+    pub fn with_signer(mut self, signer: Option<Signer>) -> ChannelNotificationReceiver<'a> {
+        self.signer = signer;
+        return self;
+    }
+
+    pub fn span(self) -> Option<tracing::Span> {
+        return self.span;
+    }
 }
 
 impl<'a> NotificationReceiver for ChannelNotificationReceiver<'a> {
     fn tell(&mut self, action: Action) {
+        if let (Some(jobs), Some(ended)) = (self.jobs.as_ref(), worker::outcome(&action)) {
+            jobs.inc(&[("outcome", ended)]);
+        }
+        if let (Some(span), Some(ended)) = (self.span.as_mut(), worker::outcome(&action)) {
+            span.attribute("outcome", ended);
+            if ended == "nack_dump" {
+                span.fail();
+            }
+        }
+
         match action {
             Action::Ack => {
                 self.channel.basic_ack(self.delivery_tag, false).unwrap();
@@ -71,23 +145,50 @@ impl<'a> NotificationReceiver for ChannelNotificationReceiver<'a> {
                     .basic_nack(self.delivery_tag, false, false)
                     .unwrap();
             }
-            Action::Publish(msg) => {
-                let exch = msg.exchange.clone().unwrap_or("".to_owned());
-                let key = msg.routing_key.clone().unwrap_or("".to_owned());
-
-                let props = msg.properties.unwrap_or(
+            Action::Publish(mut msg) => {
+                let mut props = msg.properties.take().unwrap_or(
                     BasicProperties { ..Default::default() },
                 );
-                self.channel
-                    .basic_publish(exch, key, msg.mandatory, msg.immediate, props, msg.content)
-                    .unwrap();
+                if let Some(ref span) = self.span {
+                    tracing::inject(&mut props, &span.context);
+                }
+                self.correlation.inject(&mut props);
+                msg.properties = Some(props);
+                easyamqp::publish(&mut *self.channel, &self.signer, msg).unwrap();
             }
         }
     }
 }
 
 pub fn new<T: SimpleNotifyWorker>(worker: T) -> NotifyWorker<T> {
-    return NotifyWorker { internal: worker };
+    return NotifyWorker {
+        internal: worker,
+        jobs: None,
+        tracer: None,
+        signer: None,
+    };
+}
+
+impl<T: SimpleNotifyWorker> NotifyWorker<T> {
+    /// Export how many jobs were handled and the task's own metrics.
+    pub fn with_metrics(mut self, registry: &stats::Registry) -> NotifyWorker<T> {
+        self.internal.register_metrics(registry);
+        self.jobs = Some(worker::jobs_counter(registry));
+        return self;
+    }
+
+    /// Trace the jobs, continuing the traces of their messages.
+    pub fn with_tracer(mut self, tracer: &tracing::Tracer) -> NotifyWorker<T> {
+        self.tracer = Some(tracer.clone());
+        return self;
+    }
+
+    /// Drop messages which aren't signed by `signer`, and sign the ones
+    /// published.
+    pub fn with_signer(mut self, signer: Option<Signer>) -> NotifyWorker<T> {
+        self.signer = signer;
+        return self;
+    }
 }
 
 impl<T: SimpleNotifyWorker + Send> Consumer for NotifyWorker<T> {
@@ -98,9 +199,33 @@ impl<T: SimpleNotifyWorker + Send> Consumer for NotifyWorker<T> {
         headers: BasicProperties,
         body: Vec<u8>,
     ) {
-        let mut receiver = ChannelNotificationReceiver::new(channel, method.delivery_tag);
+        let _fields = logging::fields(&[("routing_key", method.routing_key.as_str())]);
+        let correlation = Correlation::extract(&headers);
+        let _correlation_fields = logging::fields(&correlation.fields());
+        if let Some(ref signer) = self.signer {
+            let verified =
+                signer.verify(&headers, &method.exchange, &method.routing_key, &body);
+            if let Err(e) = verified {
+                error!("Dropping the message: {}", e);
+                if let Some(ref jobs) = self.jobs {
+                    jobs.inc(&[("outcome", "bad_signature")]);
+                }
+                channel.basic_nack(method.delivery_tag, false, false).unwrap();
+                return;
+            }
+        }
+
+        let mut receiver = ChannelNotificationReceiver::new(channel, method.delivery_tag)
+            .with_jobs(self.jobs.clone())
+            .with_span(worker::start_span(&self.tracer, &method, &headers))
+            .with_correlation(correlation.clone())
+            .with_signer(self.signer.clone());
 
         let job = self.internal.msg_to_job(&method, &headers, &body).unwrap();
         self.internal.consumer(&job, &mut receiver);
+
+        if let (Some(tracer), Some(span)) = (self.tracer.as_ref(), receiver.span()) {
+            tracer.finish(span);
+        }
     }
 }
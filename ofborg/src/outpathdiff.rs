@@ -1,15 +1,18 @@
 extern crate amqp;
 extern crate env_logger;
 
-use std::collections::{HashSet, HashMap};
+use std::collections::{BTreeMap, HashSet, HashMap};
 use std::fs::File;
 use std::fs;
-use std::io::BufRead;
-use std::io::BufReader;
 use std::path::PathBuf;
+use std::sync::mpsc;
+use std::thread;
 use ofborg::nix;
 use std::io::Write;
 
+const DEFAULT_SYSTEMS: [&'static str; 4] =
+    ["aarch64-linux", "i686-linux", "x86_64-linux", "x86_64-darwin"];
+
 pub struct OutPathDiff {
     calculator: OutPaths,
     pub original: Option<PackageOutPaths>,
@@ -25,6 +28,16 @@ impl OutPathDiff {
         }
     }
 
+    pub fn with_parallelism(mut self, parallelism: usize) -> OutPathDiff {
+        self.calculator = self.calculator.with_parallelism(parallelism);
+        return self;
+    }
+
+    pub fn with_systems(mut self, systems: Vec<String>) -> OutPathDiff {
+        self.calculator = self.calculator.with_systems(systems);
+        return self;
+    }
+
     pub fn find_before(&mut self) -> Result<bool, File> {
         let x = self.run();
         match x {
@@ -102,9 +115,9 @@ impl OutPathDiff {
     }
 }
 
-type PackageOutPaths = HashMap<PackageArch, OutPath>;
+pub type PackageOutPaths = HashMap<PackageArch, OutPath>;
 
-#[derive(Debug, PartialEq, Hash, Eq, Clone)]
+#[derive(Serialize, Deserialize, Debug, PartialEq, Hash, Eq, Clone)]
 pub struct PackageArch {
     pub package: Package,
     pub architecture: Architecture,
@@ -117,6 +130,8 @@ pub struct OutPaths {
     path: PathBuf,
     nix: nix::Nix,
     check_meta: bool,
+    systems: Vec<String>,
+    parallelism: usize,
 }
 
 impl OutPaths {
@@ -125,7 +140,24 @@ impl OutPaths {
             nix: nix,
             path: path,
             check_meta: check_meta,
+            systems: DEFAULT_SYSTEMS.iter().map(|s| s.to_string()).collect(),
+            parallelism: 1,
+        }
+    }
+
+    /// Query up to `parallelism` systems' out paths at the same time.
+    pub fn with_parallelism(mut self, parallelism: usize) -> OutPaths {
+        self.parallelism = if parallelism > 0 { parallelism } else { 1 };
+        return self;
+    }
+
+    /// Query the out paths of these systems, instead of the default
+    /// Linux and x86_64-darwin ones.
+    pub fn with_systems(mut self, systems: Vec<String>) -> OutPaths {
+        if systems.len() > 0 {
+            self.systems = systems;
         }
+        return self;
     }
 
     pub fn find(&self) -> Result<PackageOutPaths, File> {
@@ -134,12 +166,63 @@ impl OutPaths {
 
     fn run(&self) -> Result<PackageOutPaths, File> {
         self.place_nix();
-        let ret = self.execute();
+        let ret = self.execute_all();
         self.remove_nix();
 
-        match ret {
-            Ok(file) => Ok(parse_lines(&mut BufReader::new(file))),
-            Err(e) => Err(e),
+        return ret;
+    }
+
+    /// Query every system with one nix-env, or with a parallelism above
+    /// one, each system separately, merging each system's out paths in as
+    /// soon as its query finishes. Evaluating nixpkgs once per system
+    /// costs more in total, so it only pays off when they run at once.
+    fn execute_all(&self) -> Result<PackageOutPaths, File> {
+        if self.parallelism <= 1 {
+            return execute(&self.nix, &self.path, self.check_meta, &self.systems);
+        }
+
+        let (tx, rx) = mpsc::channel();
+        let mut pending = self.systems.clone().into_iter();
+        let mut running: usize = 0;
+        let mut paths: PackageOutPaths = HashMap::new();
+        let mut failure: Option<File> = None;
+
+        loop {
+            while running < self.parallelism && failure.is_none() {
+                let system = match pending.next() {
+                    Some(system) => system,
+                    None => break,
+                };
+
+                let tx = tx.clone();
+                let nix = self.nix.clone();
+                let path = self.path.clone();
+                let check_meta = self.check_meta;
+                thread::spawn(move || {
+                    let result = execute(&nix, &path, check_meta, &[system]);
+                    tx.send(result).expect("Failed to send out paths");
+                });
+                running += 1;
+            }
+
+            if running == 0 {
+                break;
+            }
+
+            match rx.recv().expect("Failed to receive out paths") {
+                Ok(system_paths) => paths.extend(system_paths),
+                Err(file) => {
+                    if failure.is_none() {
+                        failure = Some(file);
+                    }
+                }
+            }
+            running -= 1;
+        }
+
+        match failure {
+            Some(file) => Err(file),
+            None => Ok(paths),
         }
     }
 
@@ -160,60 +243,109 @@ impl OutPaths {
         dest
     }
 
-    fn execute(&self) -> Result<File, File> {
-        let check_meta: String;
+}
 
-        if self.check_meta {
-            check_meta = String::from("true");
-        } else {
-            check_meta = String::from("false");
-        }
+/// A package as `nix-env --query --json --out-path --drv-path`
+/// describes it. The outputs of content-addressed derivations aren't
+/// known before they're built.
+#[derive(Deserialize, Debug)]
+struct QueriedPackage {
+    #[serde(default)]
+    outputs: BTreeMap<String, Option<String>>,
+    #[serde(rename = "drvPath")]
+    drv_path: Option<String>,
+}
 
-        self.nix.safely(
-            nix::Operation::QueryPackagesOutputs,
-            &self.path,
-            vec![
-                String::from("-f"),
-                String::from(".gc-of-borg-outpaths.nix"),
-                String::from("--arg"),
-                String::from("checkMeta"),
-                check_meta,
-            ],
-            true,
-        )
+fn execute(
+    nix: &nix::Nix,
+    path: &PathBuf,
+    check_meta: bool,
+    systems: &[String],
+) -> Result<PackageOutPaths, File> {
+    let check_meta_arg: String;
+
+    if check_meta {
+        check_meta_arg = String::from("true");
+    } else {
+        check_meta_arg = String::from("false");
     }
+
+    let output = nix.run_for_stdout(nix.safe_command(
+        nix::Operation::QueryPackagesOutputs,
+        path,
+        vec![
+            String::from("-f"),
+            String::from(".gc-of-borg-outpaths.nix"),
+            String::from("--arg"),
+            String::from("checkMeta"),
+            check_meta_arg,
+            String::from("--arg"),
+            String::from("supportedSystems"),
+            supported_systems(systems),
+        ],
+    ))?;
+
+    let packages: HashMap<String, QueriedPackage> = nix::decode_json(output)?;
+    return Ok(out_paths(packages));
 }
 
+/// A Nix list of `systems`, like `[ "x86_64-linux" "aarch64-linux" ]`.
+fn supported_systems(systems: &[String]) -> String {
+    let quoted: Vec<String> = systems
+        .iter()
+        .map(|system| format!("\"{}\"", system))
+        .collect();
+    return format!("[ {} ]", quoted.join(" "));
+}
 
-fn parse_lines(data: &mut BufRead) -> PackageOutPaths {
-    data.lines()
-        .filter_map(|line| match line {
-            Ok(line) => Some(line),
-            Err(_) => None,
-        })
-        .filter_map(|line| {
-            let split: Vec<&str> = line.split_whitespace().collect();
-            if split.len() == 2 {
-                let outpaths = String::from(split[1]);
-
-                let path: Vec<&str> = split[0].rsplitn(2, ".").collect();
-                if path.len() == 2 {
-                    Some((
-                        PackageArch {
-                            package: String::from(path[1]),
-                            architecture: String::from(path[0]),
-                        },
-                        outpaths,
-                    ))
-                } else {
-                    info!("Warning: Didn't detect an architecture for {:?}", path);
-                    None
-                }
-            } else {
-                info!("Warning: not 2 word segments in {:?}", split);
-                None
+/// Key the packages by their attribute and system, which the attribute
+/// ends with, and join their outputs like nix-env's text output does,
+/// `out` bare and the others named: `/nix/store/...;dev=/nix/store/...`.
+/// Content-addressed derivations, with outputs not known yet, are
+/// compared by their derivation instead: `drv=/nix/store/....drv`.
+fn out_paths(packages: HashMap<String, QueriedPackage>) -> PackageOutPaths {
+    packages
+        .into_iter()
+        .filter_map(|(attr, package)| {
+            let path: Vec<&str> = attr.rsplitn(2, ".").collect();
+            if path.len() != 2 {
+                info!("Warning: Didn't detect an architecture for {:?}", path);
+                return None;
             }
 
+            let floating = package.outputs.values().any(|out| out.is_none());
+            let identity = match (floating, package.drv_path) {
+                (true, Some(drv)) => format!("drv={}", drv),
+                (true, None) => {
+                    info!("Warning: neither outputs nor a derivation for {}", attr);
+                    return None;
+                }
+                (false, _) if package.outputs.len() == 0 => {
+                    info!("Warning: no outputs for {}", attr);
+                    return None;
+                }
+                (false, _) => {
+                    let outpaths: Vec<String> = package
+                        .outputs
+                        .iter()
+                        .filter_map(|(name, out)| out.as_ref().map(|out| (name, out)))
+                        .map(|(name, out)| if name == "out" {
+                            out.clone()
+                        } else {
+                            format!("{}={}", name, out)
+                        })
+                        .collect();
+                    outpaths.join(";")
+                }
+            };
+
+            Some((
+                PackageArch {
+                    package: String::from(path[1]),
+                    architecture: String::from(path[0]),
+                },
+                identity,
+            ))
         })
         .collect()
 }
@@ -222,14 +354,46 @@ fn parse_lines(data: &mut BufRead) -> PackageOutPaths {
 mod tests {
 
     use super::*;
-    use std::io::Cursor;
+    use serde_json;
+
+    const TEST_JSON: &'static str = r#"{
+  "kindlegen.x86_64-darwin": {
+    "name": "kindlegen-2.9",
+    "outputs": { "out": "/nix/store/sgabv7byhan6b0rjspd3p1bd7yw91f30-kindlegen-2.9" }
+  },
+  "python27Packages.pyinotify.i686-linux": {
+    "name": "python2.7-pyinotify-0.9.6",
+    "outputs": { "out": "/nix/store/rba0hbq6i4camvhpj9723dvs4b511ryn-python2.7-pyinotify-0.9.6" }
+  },
+  "pan.i686-linux": {
+    "name": "pan-0.139",
+    "outputs": { "out": "/nix/store/6djnw9s2z5iy0c741qa8yk0k2v6bxrra-pan-0.139" }
+  },
+  "gnome3.evolution_data_server.aarch64-linux": {
+    "name": "evolution-data-server-3.26.3",
+    "outputs": {
+      "out": "/nix/store/fmxf25kyxb62v9arc64fypb2ilxifsh0-evolution-data-server-3.26.3",
+      "dev": "/nix/store/l1k8pk0l3dj1ndsrsq9fj3y1dl9zvpgx-evolution-data-server-3.26.3-dev"
+    }
+  },
+  "hello.x86_64-linux": {
+    "name": "hello-2.10",
+    "drvPath": "/nix/store/9bpbkg5jk2c5cdx5fhzxg9f8jbd5vwm4-hello-2.10.drv",
+    "outputs": { "out": null }
+  }
+}"#;
 
-    const TEST_LINES: &'static str = "
-kindlegen.x86_64-darwin                                                    /nix/store/sgabv7byhan6b0rjspd3p1bd7yw91f30-kindlegen-2.9
-python27Packages.pyinotify.i686-linux                                      /nix/store/rba0hbq6i4camvhpj9723dvs4b511ryn-python2.7-pyinotify-0.9.6
-pan.i686-linux                                                             /nix/store/6djnw9s2z5iy0c741qa8yk0k2v6bxrra-pan-0.139
-gnome3.evolution_data_server.aarch64-linux                                 /nix/store/fmxf25kyxb62v9arc64fypb2ilxifsh0-evolution-data-server-3.26.3
-";
+    #[test]
+    fn test_supported_systems() {
+        assert_eq!(
+            supported_systems(&[String::from("x86_64-linux")]),
+            "[ \"x86_64-linux\" ]"
+        );
+        assert_eq!(
+            supported_systems(&[String::from("x86_64-linux"), String::from("aarch64-linux")]),
+            "[ \"x86_64-linux\" \"aarch64-linux\" ]"
+        );
+    }
 
     #[test]
     fn test_parse_outputs() {
@@ -247,7 +411,9 @@ gnome3.evolution_data_server.aarch64-linux                                 /nix/
                 architecture: "aarch64-linux".to_owned(),
                 package: "gnome3.evolution_data_server".to_owned(),
             },
-            "/nix/store/fmxf25kyxb62v9arc64fypb2ilxifsh0-evolution-data-server-3.26.3".to_owned(),
+            "dev=/nix/store/l1k8pk0l3dj1ndsrsq9fj3y1dl9zvpgx-evolution-data-server-3.26.3-dev;\
+             /nix/store/fmxf25kyxb62v9arc64fypb2ilxifsh0-evolution-data-server-3.26.3"
+                .to_owned(),
         );
 
         expect.insert(
@@ -265,7 +431,15 @@ gnome3.evolution_data_server.aarch64-linux                                 /nix/
             },
             "/nix/store/6djnw9s2z5iy0c741qa8yk0k2v6bxrra-pan-0.139".to_owned(),
         );
-        assert_eq!(parse_lines(&mut Cursor::new(TEST_LINES)), expect);
+
+        expect.insert(
+            PackageArch {
+                architecture: "x86_64-linux".to_owned(),
+                package: "hello".to_owned(),
+            },
+            "drv=/nix/store/9bpbkg5jk2c5cdx5fhzxg9f8jbd5vwm4-hello-2.10.drv".to_owned(),
+        );
+        assert_eq!(out_paths(serde_json::from_str(TEST_JSON).unwrap()), expect);
     }
 
 }
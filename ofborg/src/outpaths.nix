@@ -1,15 +1,17 @@
-{ checkMeta }:
+{ checkMeta
+, supportedSystems ? [
+    "aarch64-linux"
+    "i686-linux"
+    "x86_64-linux"
+    "x86_64-darwin"
+  ]
+}:
 let
   lib = import ./lib;
   hydraJobs = import ./pkgs/top-level/release.nix
     # Compromise: accuracy vs. resources needed for evaluation.
     {
-      supportedSystems = [
-        "aarch64-linux"
-        "i686-linux"
-        "x86_64-linux"
-        "x86_64-darwin"
-      ];
+      inherit supportedSystems;
 
       nixpkgsArgs = {
         config = {
@@ -0,0 +1,241 @@
+use ofborg::githubapi::{GithubApi, ApiError};
+
+const QUERY: &'static str = "
+query($owner: String!, $name: String!, $number: Int!) {
+  repository(owner: $owner, name: $name) {
+    url
+    pullRequest(number: $number) {
+      state
+      title
+      baseRefName
+      headRefOid
+      mergeable
+      isDraft
+      authorAssociation
+      author { login }
+      labels(first: 100) { nodes { name } }
+      files(first: 100) { nodes { path } }
+    }
+  }
+}";
+
+/// Everything the evaluator and comment filter need to know about a
+/// PR, fetched in a single GraphQL request.
+#[derive(Debug, PartialEq)]
+pub struct PullRequestMetadata {
+    /// The base repository's, like the webhooks' `clone_url`.
+    pub clone_url: String,
+    pub state: PullRequestState,
+    pub title: String,
+    pub base_ref: String,
+    pub head_sha: String,
+    /// `None` while GitHub is still calculating it.
+    pub mergeable: Option<bool>,
+    pub is_draft: bool,
+    pub author: String,
+    pub author_association: String,
+    pub labels: Vec<String>,
+    /// Only the first 100 files are included.
+    pub files: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[serde(rename_all="SCREAMING_SNAKE_CASE")]
+pub enum PullRequestState {
+    Open,
+    Closed,
+    Merged,
+}
+
+#[derive(Serialize, Debug)]
+struct Variables<'a> {
+    owner: &'a str,
+    name: &'a str,
+    number: u64,
+}
+
+#[derive(Deserialize, Debug)]
+struct Data {
+    repository: Option<Repository>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all="camelCase")]
+struct Repository {
+    url: String,
+    pull_request: Option<PullRequest>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all="camelCase")]
+struct PullRequest {
+    state: PullRequestState,
+    title: String,
+    base_ref_name: String,
+    head_ref_oid: String,
+    mergeable: String,
+    #[serde(default)]
+    is_draft: bool,
+    author_association: String,
+    author: Option<Author>,
+    labels: Nodes<Label>,
+    files: Option<Nodes<File>>,
+}
+
+#[derive(Deserialize, Debug)]
+struct Author {
+    login: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct Nodes<T> {
+    nodes: Vec<T>,
+}
+
+#[derive(Deserialize, Debug)]
+struct Label {
+    name: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct File {
+    path: String,
+}
+
+pub fn fetch(
+    api: &GithubApi,
+    owner: &str,
+    name: &str,
+    number: u64,
+) -> Result<PullRequestMetadata, ApiError> {
+    let data: Data = api.graphql(
+        QUERY,
+        &Variables {
+            owner: owner,
+            name: name,
+            number: number,
+        },
+    )?;
+
+    return from_data(data).ok_or(ApiError::Decode(
+        format!("No pull request {}/{}#{}", owner, name, number),
+    ));
+}
+
+fn from_data(data: Data) -> Option<PullRequestMetadata> {
+    let repository = data.repository?;
+    let pr = repository.pull_request?;
+
+    return Some(PullRequestMetadata {
+        clone_url: format!("{}.git", repository.url),
+        state: pr.state,
+        title: pr.title,
+        base_ref: pr.base_ref_name,
+        head_sha: pr.head_ref_oid,
+        mergeable: match pr.mergeable.as_ref() {
+            "MERGEABLE" => Some(true),
+            "CONFLICTING" => Some(false),
+            _ => None,
+        },
+        is_draft: pr.is_draft,
+        // Deleted users show up as ghost.
+        author: pr.author.map(|a| a.login).unwrap_or(String::from("ghost")),
+        author_association: pr.author_association,
+        labels: pr.labels.nodes.into_iter().map(|l| l.name).collect(),
+        files: pr.files
+            .map(|files| files.nodes.into_iter().map(|f| f.path).collect())
+            .unwrap_or(vec![]),
+    });
+}
+
+impl PullRequestMetadata {
+    pub fn is_open(&self) -> bool {
+        self.state == PullRequestState::Open
+    }
+
+    pub fn has_label(&self, label: &str) -> bool {
+        self.labels.iter().any(|l| l == label)
+    }
+
+    pub fn is_wip(&self) -> bool {
+        if self.title.contains("[WIP]") {
+            return true;
+        }
+
+        if self.title.starts_with("WIP:") {
+            return true;
+        }
+
+        self.labels.iter().any(|label| indicates_wip(label))
+    }
+}
+
+fn indicates_wip(text: &str) -> bool {
+    let text = text.to_lowercase();
+
+    if text.contains("work in progress") {
+        return true;
+    }
+
+    if text.contains("work-in-progress") {
+        return true;
+    }
+
+    return false;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json;
+
+    #[test]
+    fn test_from_data() {
+        let data: Data = serde_json::from_str(
+            r#"{
+              "repository": {
+                "url": "https://github.com/NixOS/nixpkgs",
+                "pullRequest": {
+                  "state": "OPEN",
+                  "title": "hello: 2.10 -> 2.11",
+                  "baseRefName": "staging",
+                  "headRefOid": "887e8b460a7d45ddb3bbdebe01447b251b3229e8",
+                  "mergeable": "CONFLICTING",
+                  "isDraft": true,
+                  "authorAssociation": "CONTRIBUTOR",
+                  "author": { "login": "example" },
+                  "labels": { "nodes": [ { "name": "2.status: work-in-progress" } ] },
+                  "files": { "nodes": [ { "path": "pkgs/applications/misc/hello/default.nix" } ] }
+                }
+              }
+            }"#,
+        ).unwrap();
+
+        let meta = from_data(data).unwrap();
+        assert_eq!(
+            meta,
+            PullRequestMetadata {
+                clone_url: String::from("https://github.com/NixOS/nixpkgs.git"),
+                state: PullRequestState::Open,
+                title: String::from("hello: 2.10 -> 2.11"),
+                base_ref: String::from("staging"),
+                head_sha: String::from("887e8b460a7d45ddb3bbdebe01447b251b3229e8"),
+                mergeable: Some(false),
+                is_draft: true,
+                author: String::from("example"),
+                author_association: String::from("CONTRIBUTOR"),
+                labels: vec![String::from("2.status: work-in-progress")],
+                files: vec![String::from("pkgs/applications/misc/hello/default.nix")],
+            }
+        );
+        assert!(meta.is_open());
+        assert!(meta.is_wip());
+    }
+
+    #[test]
+    fn test_missing_pull_request() {
+        let data: Data = serde_json::from_str(r#"{ "repository": { "url": "https://github.com/NixOS/nixpkgs", "pullRequest": null } }"#)
+            .unwrap();
+        assert_eq!(from_data(data), None);
+    }
+}
@@ -0,0 +1,224 @@
+//! Catch pushes to PRs which throw away reviewed commits, or change
+//! protected paths in commits without a verified signature, so they're
+//! pointed out instead of evaluated as if nothing happened.
+
+use ofborg::githubapi::{PullCommit, Review};
+
+pub const LABEL: &'static str = "2.status: push policy violation";
+
+/// What's checked of the pushes to a repository's PRs.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct PushPolicy {
+    /// Warn when a force push drops commits which were approved.
+    pub reviewed_force_pushes: Option<bool>,
+    /// Paths, or directories ending in `/`, which commits only change
+    /// with a signature GitHub verified.
+    pub signed_paths: Option<Vec<String>>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Violation {
+    /// Approved commits which aren't in the PR anymore.
+    RewroteReviewed(Vec<String>),
+    /// A commit changing protected paths without a verified signature.
+    Unsigned { commit: String, paths: Vec<String> },
+}
+
+impl PushPolicy {
+    pub fn protects(&self, path: &str) -> bool {
+        match self.signed_paths {
+            Some(ref paths) => {
+                paths.iter().any(|protected| if protected.ends_with('/') {
+                    path.starts_with(protected.as_str())
+                } else {
+                    path == protected
+                })
+            }
+            None => false,
+        }
+    }
+
+    /// The violations of a PR with the `approved` commits and the
+    /// `commits`, where `contains` tells whether a commit is in the PR's
+    /// history and `files_changed` what a commit changes.
+    pub fn violations<C, F>(
+        &self,
+        approved: &[String],
+        commits: &[PullCommit],
+        contains: C,
+        files_changed: F,
+    ) -> Vec<Violation>
+    where
+        C: Fn(&str) -> bool,
+        F: Fn(&str) -> Vec<String>,
+    {
+        let mut violations: Vec<Violation> = vec![];
+
+        if self.reviewed_force_pushes == Some(true) {
+            let mut rewritten: Vec<String> = approved
+                .iter()
+                .filter(|commit| !contains(commit))
+                .cloned()
+                .collect();
+            rewritten.sort();
+            rewritten.dedup();
+            if rewritten.len() > 0 {
+                violations.push(Violation::RewroteReviewed(rewritten));
+            }
+        }
+
+        if self.signed_paths.is_some() {
+            for commit in commits.iter().filter(|commit| !commit.is_verified()) {
+                let paths: Vec<String> = files_changed(&commit.sha)
+                    .into_iter()
+                    .filter(|path| self.protects(path))
+                    .collect();
+                if paths.len() > 0 {
+                    violations.push(Violation::Unsigned {
+                        commit: commit.sha.clone(),
+                        paths: paths,
+                    });
+                }
+            }
+        }
+
+        return violations;
+    }
+}
+
+/// The commits reviewers approved.
+pub fn approved_commits(reviews: &[Review]) -> Vec<String> {
+    reviews
+        .iter()
+        .filter(|review| review.state == "APPROVED")
+        .filter_map(|review| review.commit_id.clone())
+        .collect()
+}
+
+pub fn comment(violations: &[Violation]) -> String {
+    let mut lines: Vec<String> = vec![
+        String::from(
+            "This push breaks the repository's push policy, so it wasn't evaluated:"
+        ),
+        String::new(),
+    ];
+
+    for violation in violations.iter() {
+        match violation {
+            &Violation::RewroteReviewed(ref commits) => {
+                lines.push(format!(
+                    "- A force push dropped approved commits: {}. \
+                     Please ask for another review.",
+                    commits.join(", ")
+                ));
+            }
+            &Violation::Unsigned {
+                ref commit,
+                ref paths,
+            } => {
+                lines.push(format!(
+                    "- {} changes {} without a verified signature.",
+                    commit,
+                    paths
+                        .iter()
+                        .map(|path| format!("`{}`", path))
+                        .collect::<Vec<String>>()
+                        .join(", ")
+                ));
+            }
+        }
+    }
+
+    return lines.join("\n");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ofborg::githubapi::{PullCommitDetails, Verification};
+
+    fn commit(sha: &str, verified: bool) -> PullCommit {
+        PullCommit {
+            sha: sha.to_owned(),
+            commit: PullCommitDetails {
+                verification: Some(Verification { verified: verified }),
+            },
+        }
+    }
+
+    #[test]
+    fn test_violations() {
+        let policy = PushPolicy {
+            reviewed_force_pushes: Some(true),
+            signed_paths: Some(vec![
+                String::from("pkgs/top-level/all-packages.nix"),
+                String::from("nixos/modules/security/"),
+            ]),
+        };
+        assert!(policy.protects("nixos/modules/security/sudo.nix"));
+        assert!(!policy.protects("nixos/modules/services/foo.nix"));
+        assert!(!policy.protects("pkgs/top-level/all-packages.nix.orig"));
+
+        let reviews = vec![
+            Review {
+                commit_id: Some(String::from("aaa")),
+                state: String::from("APPROVED"),
+            },
+            Review {
+                commit_id: Some(String::from("bbb")),
+                state: String::from("CHANGES_REQUESTED"),
+            },
+            Review {
+                commit_id: Some(String::from("ccc")),
+                state: String::from("APPROVED"),
+            },
+        ];
+        let approved = approved_commits(&reviews);
+        assert_eq!(approved, vec![String::from("aaa"), String::from("ccc")]);
+
+        let commits = vec![commit("ccc", false), commit("ddd", true), commit("eee", false)];
+        let files_changed = |sha: &str| match sha {
+            "ccc" => vec![String::from("pkgs/applications/hello.nix")],
+            "ddd" => vec![String::from("nixos/modules/security/sudo.nix")],
+            _ => vec![
+                String::from("README.md"),
+                String::from("nixos/modules/security/pam.nix"),
+            ],
+        };
+
+        assert_eq!(
+            policy.violations(&approved, &commits, |sha| sha != "aaa", &files_changed),
+            vec![
+                Violation::RewroteReviewed(vec![String::from("aaa")]),
+                Violation::Unsigned {
+                    commit: String::from("eee"),
+                    paths: vec![String::from("nixos/modules/security/pam.nix")],
+                },
+            ]
+        );
+
+        assert_eq!(
+            PushPolicy::default().violations(&approved, &commits, |_| false, &files_changed),
+            Vec::<Violation>::new()
+        );
+    }
+
+    #[test]
+    fn test_comment() {
+        let violations = vec![
+            Violation::RewroteReviewed(vec![String::from("aaa"), String::from("bbb")]),
+            Violation::Unsigned {
+                commit: String::from("eee"),
+                paths: vec![String::from("nixos/modules/security/pam.nix")],
+            },
+        ];
+
+        assert_eq!(
+            comment(&violations),
+            "This push breaks the repository's push policy, so it wasn't evaluated:
+
+- A force push dropped approved commits: aaa, bbb. Please ask for another review.
+- eee changes `nixos/modules/security/pam.nix` without a verified signature."
+        );
+    }
+}
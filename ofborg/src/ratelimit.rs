@@ -0,0 +1,256 @@
+use std::cmp;
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use ofborg::stats::Event;
+
+/// Retry failed requests at most this many times.
+pub const MAX_RETRIES: u32 = 4;
+
+/// Never back off for longer than this between retries.
+const MAX_BACKOFF_SECS: u64 = 60;
+
+/// Tracks the rate limit GitHub reports on each response, holding
+/// requests back once it is nearly used up instead of running into it.
+pub struct RateLimiter {
+    min_remaining: u64,
+    state: Mutex<State>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    remaining: Option<u64>,
+    reset: Option<u64>,
+    usage: RateLimitUsage,
+}
+
+/// GitHub API consumption since the usage was last taken.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct RateLimitUsage {
+    pub requests: u64,
+    pub retries: u64,
+    pub waited_secs: u64,
+}
+
+impl RateLimiter {
+    pub fn new(min_remaining: u64) -> RateLimiter {
+        RateLimiter {
+            min_remaining: min_remaining,
+            state: Mutex::new(State::default()),
+        }
+    }
+
+    /// Block until a request can be made without exhausting the limit.
+    pub fn wait(&self) {
+        let delay = {
+            let state = self.state.lock().unwrap();
+            self.delay(&state, now())
+        };
+
+        if let Some(delay) = delay {
+            warn!(
+                "GitHub rate limit nearly exhausted, waiting {}s for it to reset",
+                delay.as_secs()
+            );
+            thread::sleep(delay);
+            let mut state = self.state.lock().unwrap();
+            state.usage.waited_secs += delay.as_secs();
+            state.remaining = None;
+        }
+    }
+
+    fn delay(&self, state: &State, now: u64) -> Option<Duration> {
+        match (state.remaining, state.reset) {
+            (Some(remaining), Some(reset)) if remaining <= self.min_remaining && reset > now => {
+                // A little more, in case our clock is behind.
+                Some(Duration::from_secs(reset - now + 1))
+            }
+            _ => None,
+        }
+    }
+
+    /// Record the `X-RateLimit-Remaining` and `X-RateLimit-Reset`
+    /// headers of a response.
+    pub fn record(&self, remaining: Option<u64>, reset: Option<u64>) {
+        let mut state = self.state.lock().unwrap();
+        state.usage.requests += 1;
+        if remaining.is_some() {
+            state.remaining = remaining;
+            state.reset = reset;
+        }
+    }
+
+    /// How long until requests can be made again, if the limit is
+    /// nearly used up.
+    pub fn cooling_down(&self) -> Option<Duration> {
+        let state = self.state.lock().unwrap();
+        self.delay(&state, now())
+    }
+
+    /// Stop using this limit for `secs`, like after running into a
+    /// secondary rate limit which the headers don't tell us about.
+    pub fn cool_down(&self, secs: u64) {
+        let mut state = self.state.lock().unwrap();
+        let reset = now() + secs;
+        state.remaining = Some(0);
+        state.reset = Some(cmp::max(state.reset.unwrap_or(0), reset));
+    }
+
+    pub fn remaining(&self) -> Option<u64> {
+        self.state.lock().unwrap().remaining
+    }
+
+    pub fn retried(&self) {
+        self.state.lock().unwrap().usage.retries += 1;
+    }
+
+    pub fn take_usage(&self) -> RateLimitUsage {
+        let mut state = self.state.lock().unwrap();
+        return ::std::mem::replace(&mut state.usage, RateLimitUsage::default());
+    }
+}
+
+impl RateLimitUsage {
+    pub fn add(&mut self, other: &RateLimitUsage) {
+        self.requests += other.requests;
+        self.retries += other.retries;
+        self.waited_secs += other.waited_secs;
+    }
+
+    pub fn events(&self) -> Vec<Event> {
+        vec![
+            Event::GithubApiRequests(self.requests),
+            Event::GithubApiRetries(self.retries),
+            Event::GithubApiRateLimitWait(self.waited_secs),
+        ]
+    }
+}
+
+/// Whether a failed request is worth retrying: server errors, and
+/// 403s caused by the primary or secondary rate limit.
+pub fn should_retry(status: u16, remaining: Option<u64>, body: &str) -> bool {
+    if status >= 500 {
+        return true;
+    }
+
+    if status == 403 {
+        return remaining == Some(0) || body.to_lowercase().contains("rate limit");
+    }
+
+    return false;
+}
+
+/// Exponential backoff, with up to 50% jitter so concurrent workers
+/// don't retry in lockstep.
+pub fn backoff(attempt: u32, jitter_permille: u64) -> Duration {
+    let base_ms = cmp::min(1000 * 2u64.pow(attempt), MAX_BACKOFF_SECS * 1000);
+    let jitter_ms = base_ms * (jitter_permille % 1000) / 2000;
+
+    return Duration::from_millis(base_ms - jitter_ms);
+}
+
+/// Choose which of a pool of rate limits to use, going round-robin
+/// from `start` over those not cooling down. When all of them are,
+/// pick the one which resets first.
+pub fn pick(cooling_down: &[Option<Duration>], start: usize) -> usize {
+    let len = cooling_down.len();
+    for offset in 0..len {
+        let idx = (start + offset) % len;
+        if cooling_down[idx].is_none() {
+            return idx;
+        }
+    }
+
+    return (0..len).min_by_key(|&idx| cooling_down[idx]).unwrap_or(0);
+}
+
+pub fn jitter() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .subsec_nanos() as u64 % 1000
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_delay() {
+        let limiter = RateLimiter::new(10);
+        limiter.record(Some(11), Some(1000));
+        assert_eq!(limiter.delay(&limiter.state.lock().unwrap(), 900), None);
+
+        limiter.record(Some(10), Some(1000));
+        assert_eq!(
+            limiter.delay(&limiter.state.lock().unwrap(), 900),
+            Some(Duration::from_secs(101))
+        );
+        assert_eq!(limiter.delay(&limiter.state.lock().unwrap(), 1000), None);
+
+        // Responses without rate limit headers don't reset the tracking.
+        limiter.record(None, None);
+        assert_eq!(limiter.remaining(), Some(10));
+
+        assert_eq!(
+            limiter.take_usage(),
+            RateLimitUsage {
+                requests: 3,
+                retries: 0,
+                waited_secs: 0,
+            }
+        );
+        assert_eq!(limiter.take_usage().requests, 0);
+    }
+
+    #[test]
+    fn test_cool_down() {
+        let limiter = RateLimiter::new(10);
+        assert_eq!(limiter.cooling_down(), None);
+
+        limiter.cool_down(60);
+        assert_eq!(limiter.remaining(), Some(0));
+        assert!(limiter.cooling_down().unwrap() > Duration::from_secs(59));
+    }
+
+    #[test]
+    fn test_pick() {
+        let cold = Some(Duration::from_secs(30));
+        let colder = Some(Duration::from_secs(60));
+
+        assert_eq!(pick(&[None, None, None], 0), 0);
+        assert_eq!(pick(&[None, None, None], 4), 1);
+        assert_eq!(pick(&[None, cold, None], 1), 2);
+        assert_eq!(pick(&[None, cold, colder], 1), 0);
+        assert_eq!(pick(&[colder, cold, colder], 0), 1);
+        assert_eq!(pick(&[None], 7), 0);
+    }
+
+    #[test]
+    fn test_should_retry() {
+        assert!(should_retry(502, None, ""));
+        assert!(should_retry(403, Some(0), ""));
+        assert!(should_retry(
+            403,
+            Some(4000),
+            "You have exceeded a secondary rate limit",
+        ));
+        assert!(!should_retry(403, Some(4000), "Resource not accessible"));
+        assert!(!should_retry(404, None, ""));
+    }
+
+    #[test]
+    fn test_backoff() {
+        assert_eq!(backoff(0, 0), Duration::from_millis(1000));
+        assert_eq!(backoff(2, 0), Duration::from_millis(4000));
+        assert_eq!(backoff(2, 999), Duration::from_millis(2002));
+        assert_eq!(backoff(10, 0), Duration::from_secs(60));
+    }
+}
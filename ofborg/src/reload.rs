@@ -0,0 +1,250 @@
+//! Reload parts of the configuration on SIGHUP: the trusted and known
+//! users, the ban list and the command limits. The connection to
+//! RabbitMQ and the jobs in flight are left alone, everything else
+//! needs a restart.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, RwLock};
+use std::thread;
+use std::time::Duration;
+use signal_hook;
+use ofborg::acl::{ACL, Users};
+use ofborg::aclsource;
+use ofborg::banlist;
+use ofborg::commandlimit::{CommandLimiter, Limits};
+use ofborg::config::{self, Config};
+
+pub struct Reloadable {
+    users: Option<Arc<RwLock<Users>>>,
+    banned: Option<Arc<RwLock<Vec<String>>>>,
+    command_limits: Option<Arc<RwLock<Limits>>>,
+}
+
+impl Reloadable {
+    pub fn new() -> Reloadable {
+        return Reloadable {
+            users: None,
+            banned: None,
+            command_limits: None,
+        };
+    }
+
+    pub fn with_acl(mut self, acl: &ACL) -> Reloadable {
+        self.users = Some(acl.users());
+        self.banned = Some(acl.banned());
+        return self;
+    }
+
+    pub fn with_command_limiter(mut self, limiter: &Option<CommandLimiter>) -> Reloadable {
+        self.command_limits = limiter.as_ref().map(|limiter| limiter.limits());
+        return self;
+    }
+
+    /// Reload the configuration at `location` whenever we get SIGHUP.
+    pub fn on_sighup(self, location: config::Location) {
+        let hangup = Arc::new(AtomicBool::new(false));
+        if let Err(e) = signal_hook::flag::register(signal_hook::SIGHUP, hangup.clone()) {
+            warn!("Not reloading on SIGHUP, failed to handle it: {:?}", e);
+            return;
+        }
+
+        thread::spawn(move || loop {
+            thread::sleep(Duration::from_secs(1));
+            if !hangup.swap(false, Ordering::SeqCst) {
+                continue;
+            }
+
+            info!("Got SIGHUP, reloading {}", location.path.display());
+            match location.read() {
+                Ok((cfg, _)) => {
+                    let changes = self.apply(&cfg);
+                    if changes.len() == 0 {
+                        info!("Reloaded the configuration, nothing changed");
+                    }
+                    for change in changes {
+                        info!("Reloaded the configuration: {}", change);
+                    }
+                }
+                Err(problem) => {
+                    warn!("Keeping the current configuration, it's invalid: {}", problem);
+                }
+            }
+        });
+    }
+
+    /// Swap in the reloadable parts of `cfg`, describing what changed.
+    pub fn apply(&self, cfg: &Config) -> Vec<String> {
+        let mut changes: Vec<String> = vec![];
+
+        if let Some(ref users) = self.users {
+            match reloaded_users(cfg) {
+                Ok(loaded) => {
+                    let mut current = users.write().unwrap();
+                    changes.extend(changed(
+                        "trusted users",
+                        &current.trusted_users,
+                        &loaded.trusted_users,
+                    ));
+                    changes.extend(changed(
+                        "known users",
+                        &current.known_users,
+                        &loaded.known_users,
+                    ));
+                    *current = loaded;
+                }
+                Err(e) => warn!("Keeping the current users: {}", e),
+            }
+        }
+
+        if let Some(ref banned) = self.banned {
+            let loaded = match cfg.runner.ban_list {
+                Some(ref path) => banlist::load(path),
+                None => Ok(vec![]),
+            };
+            match loaded {
+                Ok(loaded) => {
+                    let mut current = banned.write().unwrap();
+                    changes.extend(changed("banned users", &current, &loaded));
+                    *current = loaded;
+                }
+                Err(e) => warn!("Keeping the current ban list: {}", e),
+            }
+        }
+
+        if let Some(ref limits) = self.command_limits {
+            if let Some(ref limit) = cfg.command_limit {
+                let loaded = Limits {
+                    max_commands: limit.max_commands,
+                    window_secs: limit.window_seconds.unwrap_or(60 * 60),
+                };
+                let mut current = limits.write().unwrap();
+                if *current != loaded {
+                    changes.push(format!(
+                        "command limit: {} per {}s, was {} per {}s",
+                        loaded.max_commands,
+                        loaded.window_secs,
+                        current.max_commands,
+                        current.window_secs
+                    ));
+                    *current = loaded;
+                }
+            }
+        }
+
+        return changes;
+    }
+}
+
+fn reloaded_users(cfg: &Config) -> Result<Users, String> {
+    if let Some(ref from) = cfg.runner.users_from {
+        return aclsource::load(&aclsource::Source::from_location(&from.location)?);
+    }
+
+    return match (&cfg.runner.trusted_users, &cfg.runner.known_users) {
+        (&Some(ref trusted), &Some(ref known)) => Ok(Users {
+            trusted_users: trusted.clone(),
+            known_users: known.clone(),
+        }),
+        _ => Err(String::from("runner.trusted_users or runner.known_users is missing")),
+    };
+}
+
+/// Like `trusted users: added alice, removed bob`, when any changed.
+fn changed(what: &str, current: &[String], loaded: &[String]) -> Option<String> {
+    let added: Vec<&str> = loaded
+        .iter()
+        .filter(|user| !current.contains(user))
+        .map(|user| user.as_str())
+        .collect();
+    let removed: Vec<&str> = current
+        .iter()
+        .filter(|user| !loaded.contains(user))
+        .map(|user| user.as_str())
+        .collect();
+
+    let mut parts: Vec<String> = vec![];
+    if added.len() > 0 {
+        parts.push(format!("added {}", added.join(", ")));
+    }
+    if removed.len() > 0 {
+        parts.push(format!("removed {}", removed.join(", ")));
+    }
+
+    if parts.len() == 0 {
+        return None;
+    }
+    return Some(format!("{}: {}", what, parts.join(", ")));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ofborg::config::Format;
+
+    fn users(names: Vec<&str>) -> Vec<String> {
+        names.into_iter().map(String::from).collect()
+    }
+
+    #[test]
+    fn test_changed() {
+        assert_eq!(
+            changed("trusted users", &users(vec!["alice", "bob"]), &users(vec!["alice", "carol"])),
+            Some(String::from("trusted users: added carol, removed bob"))
+        );
+        assert_eq!(
+            changed("known users", &users(vec!["alice"]), &users(vec!["alice"])),
+            None
+        );
+    }
+
+    #[test]
+    fn test_apply() {
+        let config = |trusted: &str, limit: usize| {
+            let contents = format!(
+                r#"{{
+  "runner": {{
+    "identity": "my-filter",
+    "repos": ["nixos/nixpkgs"],
+    "trusted_users": [{}],
+    "known_users": ["dave"]
+  }},
+  "checkout": {{ "root": "/var/lib/ofborg/checkout" }},
+  "nix": {{ "system": "x86_64-linux", "remote": "daemon", "build_timeout_seconds": 3600 }},
+  "rabbitmq": {{
+    "ssl": true,
+    "host": "events.nix.ci",
+    "username": "ofborg",
+    "password": "secret"
+  }},
+  "command_limit": {{ "max_commands": {} }}
+}}"#,
+                trusted,
+                limit
+            );
+            config::parse("ofborg.json", &contents, Format::Json, None).unwrap().0
+        };
+
+        let acl = ACL::new(
+            vec![String::from("nixos/nixpkgs")],
+            users(vec!["alice", "bob"]),
+            users(vec!["dave"]),
+        );
+        let limiter = Some(CommandLimiter::new(10, 3600));
+        let reloadable = Reloadable::new().with_acl(&acl).with_command_limiter(&limiter);
+
+        assert_eq!(
+            reloadable.apply(&config(r#""alice", "carol""#, 5)),
+            vec![
+                String::from("trusted users: added carol, removed bob"),
+                String::from("command limit: 5 per 3600s, was 10 per 3600s"),
+            ]
+        );
+        assert_eq!(acl.users().read().unwrap().trusted_users, users(vec!["alice", "carol"]));
+        assert_eq!(limiter.unwrap().max_commands(), 5);
+
+        assert_eq!(
+            reloadable.apply(&config(r#""alice", "carol""#, 5)),
+            Vec::<String>::new()
+        );
+    }
+}
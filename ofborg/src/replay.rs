@@ -0,0 +1,304 @@
+//! Dump messages out of a queue of dead letters, like those dropped as
+//! poison, and publish them again once they're fixed.
+//!
+//! Messages are dumped as JSON, one per line, so they can be filtered
+//! and edited with any tool before they're published again.
+
+use amqp;
+use amqp::protocol::basic::BasicProperties;
+use serde::de::DeserializeOwned;
+use serde_json::{self, Value};
+
+use ofborg::ghevent;
+use ofborg::message::envelope::{self, Encoding, Format, Versioned};
+use ofborg::message::{buildjob, buildlogmsg, buildresult, massrebuildjob};
+use ofborg::worker;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Dumped {
+    /// Where the message was first published, and is published again.
+    pub exchange: String,
+    pub routing_key: String,
+    /// Why it was dead lettered, like `rejected`.
+    #[serde(default)]
+    pub reason: Option<String>,
+    /// What the message decodes as, like `BuildJob`.
+    #[serde(default)]
+    pub decoded_as: Option<String>,
+    /// Why it doesn't decode as what's published to its exchange.
+    #[serde(default)]
+    pub error: Option<String>,
+    /// Whether the body is in the envelope, see `message::envelope`.
+    #[serde(default)]
+    pub enveloped: bool,
+    pub body: Value,
+}
+
+/// Where a dead letter was first published, from the `x-death` header
+/// RabbitMQ adds: (exchange, routing key, reason).
+pub fn origin(props: &BasicProperties) -> Option<(String, String, Option<String>)> {
+    let deaths = match props.headers.as_ref().and_then(|h| h.get("x-death")) {
+        Some(&amqp::TableEntry::FieldArray(ref deaths)) => deaths,
+        _ => return None,
+    };
+
+    // The first death is the latest, the last is where it started.
+    let death = match deaths.last() {
+        Some(&amqp::TableEntry::FieldTable(ref death)) => death,
+        _ => return None,
+    };
+
+    let string = |entry: Option<&amqp::TableEntry>| match entry {
+        Some(&amqp::TableEntry::LongString(ref s)) => Some(s.clone()),
+        _ => None,
+    };
+    let exchange = string(death.get("exchange"))?;
+    let routing_key = match death.get("routing-keys") {
+        Some(&amqp::TableEntry::FieldArray(ref keys)) => string(keys.first())?,
+        _ => return None,
+    };
+
+    return Some((exchange, routing_key, string(death.get("reason"))));
+}
+
+/// Decode `body` as `T`, to check it is one.
+fn decodes_as<T>(name: &'static str, body: &Value) -> Result<&'static str, String>
+where
+    T: DeserializeOwned,
+{
+    serde_json::from_value::<T>(body.clone())
+        .map(|_| name)
+        .map_err(|e| format!("not a {}: {}", name, e))
+}
+
+/// Decode `body` as the message `T`, in its envelope or from before it.
+fn decodes_message_as<T>(
+    name: &'static str,
+    enveloped: bool,
+    body: &Value,
+) -> Result<&'static str, String>
+where
+    T: Versioned + DeserializeOwned,
+{
+    envelope::from_value::<T>(body.clone(), enveloped)
+        .map(|_| name)
+        .map_err(|e| format!("not a {}: {}", name, e))
+}
+
+/// What a message published to `exchange` with `routing_key` decodes
+/// as. Messages of exchanges nothing is known about only need to be
+/// JSON.
+pub fn decode(
+    exchange: &str,
+    routing_key: &str,
+    enveloped: bool,
+    body: &Value,
+) -> Result<&'static str, String> {
+    match exchange {
+        "build-jobs" => decodes_message_as::<buildjob::BuildJob>("BuildJob", enveloped, body),
+        "build-results" => {
+            decodes_message_as::<buildresult::BuildResult>("BuildResult", enveloped, body)
+        }
+        "mass-rebuild-check-jobs" => {
+            decodes_message_as::<massrebuildjob::MassRebuildJob>("MassRebuildJob", enveloped, body)
+        }
+        "logs" => {
+            decodes_message_as::<buildlogmsg::BuildLogMsg>("BuildLogMsg", enveloped, body)
+                .or_else(|_| {
+                    decodes_message_as::<buildlogmsg::BuildLogStart>(
+                        "BuildLogStart",
+                        enveloped,
+                        body,
+                    )
+                })
+                .or_else(|_| {
+                    decodes_message_as::<buildresult::BuildResult>("BuildResult", enveloped, body)
+                })
+                .map_err(|_| String::from("not a log message, log start or build result"))
+        }
+        "github-events" => {
+            match routing_key.split('.').next().unwrap_or("") {
+                "issue_comment" => decodes_as::<ghevent::IssueComment>("IssueComment", body),
+                "pull_request" => {
+                    decodes_as::<ghevent::PullRequestEvent>("PullRequestEvent", body)
+                }
+                "pull_request_review_comment" => {
+                    decodes_as::<ghevent::PullRequestReviewCommentEvent>(
+                        "PullRequestReviewCommentEvent",
+                        body,
+                    )
+                }
+                "push" => decodes_as::<ghevent::PushEvent>("PushEvent", body),
+                "check_run" => decodes_as::<ghevent::CheckRunEvent>("CheckRunEvent", body),
+                "check_suite" => decodes_as::<ghevent::CheckSuiteEvent>("CheckSuiteEvent", body),
+                _ => Ok("Value"),
+            }
+        }
+        _ => Ok("Value"),
+    }
+}
+
+/// A dead letter, dumped. The body is kept as it is, even when it
+/// doesn't decode, for it to be fixed.
+pub fn dump(
+    exchange: String,
+    routing_key: String,
+    reason: Option<String>,
+    enveloped: bool,
+    body: &[u8],
+) -> Dumped {
+    let (body, decoded) = match serde_json::from_slice::<Value>(body) {
+        Ok(value) => {
+            let decoded = decode(&exchange, &routing_key, enveloped, &value);
+            (value, decoded)
+        }
+        Err(e) => {
+            (
+                Value::String(String::from_utf8_lossy(body).into_owned()),
+                Err(format!("not JSON: {}", e)),
+            )
+        }
+    };
+
+    return Dumped {
+        exchange: exchange,
+        routing_key: routing_key,
+        reason: reason,
+        decoded_as: decoded.clone().ok().map(String::from),
+        error: decoded.err(),
+        enveloped: enveloped,
+        body: body,
+    };
+}
+
+/// A message got from a queue, dumped as where it was first published
+/// when it's a dead letter.
+pub fn dump_got(
+    exchange: &str,
+    routing_key: &str,
+    props: &BasicProperties,
+    body: &[u8],
+) -> Dumped {
+    let (exchange, routing_key, reason) = match origin(props) {
+        Some(origin) => origin,
+        None => (exchange.to_owned(), routing_key.to_owned(), None),
+    };
+
+    let enveloped = Format::of(worker::content_type(props))
+        .map(|format| format.enveloped)
+        .unwrap_or(false);
+
+    return dump(exchange, routing_key, reason, enveloped, body);
+}
+
+impl Dumped {
+    /// Whether it's picked by a filter, which its exchange, routing key
+    /// or body contain.
+    pub fn matches(&self, filter: &Option<String>) -> bool {
+        match filter {
+            &Some(ref filter) => {
+                self.exchange.contains(filter.as_str()) ||
+                    self.routing_key.contains(filter.as_str()) ||
+                    self.body.to_string().contains(filter.as_str())
+            }
+            &None => true,
+        }
+    }
+
+    /// The body to publish again, once it decodes as what's published
+    /// to its exchange.
+    pub fn republishable(&self) -> Result<Vec<u8>, String> {
+        decode(&self.exchange, &self.routing_key, self.enveloped, &self.body)?;
+        return serde_json::to_vec(&self.body).map_err(|e| format!("{:?}", e));
+    }
+
+    /// The content type to publish it again with.
+    pub fn content_type(&self) -> &'static str {
+        let format = Format {
+            encoding: Encoding::Json,
+            enveloped: self.enveloped,
+        };
+        return format.content_type();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn death(exchange: &str, routing_key: &str, reason: &str) -> amqp::TableEntry {
+        let mut death: amqp::Table = amqp::Table::new();
+        death.insert(
+            String::from("exchange"),
+            amqp::TableEntry::LongString(String::from(exchange)),
+        );
+        death.insert(
+            String::from("routing-keys"),
+            amqp::TableEntry::FieldArray(vec![
+                amqp::TableEntry::LongString(String::from(routing_key)),
+            ]),
+        );
+        death.insert(
+            String::from("reason"),
+            amqp::TableEntry::LongString(String::from(reason)),
+        );
+        return amqp::TableEntry::FieldTable(death);
+    }
+
+    #[test]
+    fn test_origin() {
+        let mut headers: amqp::Table = amqp::Table::new();
+        headers.insert(
+            String::from("x-death"),
+            amqp::TableEntry::FieldArray(vec![
+                death("dead-letters", "build-inputs-x86_64-linux", "expired"),
+                death("build-jobs", "", "rejected"),
+            ]),
+        );
+        let props = BasicProperties {
+            headers: Some(headers),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            origin(&props),
+            Some((
+                String::from("build-jobs"),
+                String::new(),
+                Some(String::from("rejected")),
+            ))
+        );
+        assert_eq!(origin(&BasicProperties { ..Default::default() }), None);
+    }
+
+    #[test]
+    fn test_dump() {
+        let data = include_bytes!("../test-srcs/events/push-master.json");
+        let push = dump(
+            String::from("github-events"),
+            String::from("push.nixos/nixpkgs"),
+            None,
+            false,
+            data,
+        );
+        assert_eq!(push.decoded_as, Some(String::from("PushEvent")));
+        assert_eq!(push.error, None);
+        assert!(push.matches(&Some(String::from("nixos/nixpkgs"))));
+        assert!(!push.matches(&Some(String::from("no such thing"))));
+        assert!(push.republishable().is_ok());
+
+        let poison = dump(
+            String::from("build-jobs"),
+            String::new(),
+            Some(String::from("rejected")),
+            true,
+            br#"{"schema":"build-job","version":1,"payload":{"attrs":"hello"}}"#,
+        );
+        assert_eq!(poison.decoded_as, None);
+        assert!(poison.error.unwrap().starts_with("not a BuildJob"));
+
+        let garbage = dump(String::from("stats"), String::new(), None, false, b"{not json");
+        assert_eq!(garbage.body, Value::String(String::from("{not json")));
+        assert!(garbage.error.unwrap().starts_with("not JSON"));
+    }
+}
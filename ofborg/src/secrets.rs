@@ -0,0 +1,543 @@
+//! Secrets kept in HashiCorp Vault or AWS Secrets Manager instead of
+//! the configuration, referred to like `vault:secret/data/ofborg#token`
+//! or `aws:ofborg/rabbitmq#password`. They're fetched again as they
+//! expire: Vault's by their lease, the others every `refresh_seconds`.
+
+use std::env;
+use std::fmt;
+use std::fs::File;
+use std::io::Read;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use hyper::Client;
+use hyper::client::Response;
+use hyper::header::{Headers, UserAgent};
+use hyper::net::HttpsConnector;
+use hyper_native_tls::NativeTlsClient;
+use openssl::hash::{self, MessageDigest};
+use openssl::pkey::PKey;
+use openssl::sign::Signer;
+use serde_json;
+use ofborg::config::{AwsSecretsConfig, SecretsConfig, VaultConfig};
+use ofborg::timestamp;
+
+/// How long secrets without a lease are good for by default.
+pub const REFRESH_SECS: u64 = 15 * 60;
+
+const AWS_CONTENT_TYPE: &'static str = "application/x-amz-json-1.1";
+const AWS_TARGET: &'static str = "secretsmanager.GetSecretValue";
+const AWS_SIGNED_HEADERS: &'static str = "content-type;host;x-amz-date;x-amz-target";
+
+#[derive(Debug, PartialEq)]
+pub enum Backend {
+    Vault,
+    Aws,
+}
+
+/// Where a secret is: its provider, its name there and which of its
+/// keys, if it has several.
+#[derive(Debug, PartialEq)]
+pub struct Reference {
+    pub backend: Backend,
+    pub name: String,
+    pub key: Option<String>,
+}
+
+impl Reference {
+    pub fn parse(reference: &str) -> Result<Reference, String> {
+        let mut parts = reference.splitn(2, ':');
+        let backend = match parts.next() {
+            Some("vault") => Backend::Vault,
+            Some("aws") => Backend::Aws,
+            _ => {
+                return Err(format!(
+                    "{} doesn't start with a provider, vault: or aws:",
+                    reference
+                ))
+            }
+        };
+
+        let mut parts = parts.next().unwrap_or("").splitn(2, '#');
+        let name = parts.next().unwrap_or("");
+        if name.len() == 0 {
+            return Err(format!("{} doesn't name a secret", reference));
+        }
+
+        return Ok(Reference {
+            backend: backend,
+            name: name.to_owned(),
+            key: parts.next().map(String::from),
+        });
+    }
+}
+
+pub struct Secrets {
+    client: Client,
+    config: SecretsConfig,
+}
+
+impl Secrets {
+    pub fn new(config: &SecretsConfig) -> Secrets {
+        return Secrets {
+            client: Client::with_connector(HttpsConnector::new(NativeTlsClient::new().unwrap())),
+            config: config.clone(),
+        };
+    }
+
+    /// How long a secret without a lease is good for.
+    pub fn refresh(&self) -> Duration {
+        Duration::from_secs(self.config.refresh_seconds.unwrap_or(REFRESH_SECS))
+    }
+
+    /// The secret at `reference`, and its lease if it has one.
+    pub fn fetch(&self, reference: &Reference) -> Result<(String, Option<Duration>), String> {
+        match reference.backend {
+            Backend::Vault => {
+                let vault = self.config.vault.as_ref().ok_or(String::from(
+                    "secrets.vault isn't configured",
+                ))?;
+                self.fetch_vault(vault, reference)
+            }
+            Backend::Aws => {
+                let aws = self.config.aws_secrets_manager.as_ref().ok_or(String::from(
+                    "secrets.aws_secrets_manager isn't configured",
+                ))?;
+                self.fetch_aws(aws, reference)
+            }
+        }
+    }
+
+    fn fetch_vault(
+        &self,
+        vault: &VaultConfig,
+        reference: &Reference,
+    ) -> Result<(String, Option<Duration>), String> {
+        let token = match vault.token_file {
+            Some(ref path) => {
+                let mut token = String::new();
+                File::open(path)
+                    .and_then(|mut f| f.read_to_string(&mut token))
+                    .map_err(|e| format!("Failed to read the Vault token in {}: {}", path, e))?;
+                token.trim().to_owned()
+            }
+            None => {
+                env::var("VAULT_TOKEN").map_err(|_| {
+                    String::from("Vault needs a token_file or VAULT_TOKEN")
+                })?
+            }
+        };
+
+        let mut headers = Headers::new();
+        headers.set(UserAgent("github.com/grahamc/ofborg".to_owned()));
+        headers.set_raw("X-Vault-Token", vec![token.into_bytes()]);
+        if let Some(ref namespace) = vault.namespace {
+            headers.set_raw("X-Vault-Namespace", vec![namespace.clone().into_bytes()]);
+        }
+
+        let url = format!(
+            "{}/v1/{}",
+            vault.address.trim_right_matches('/'),
+            reference.name.trim_left_matches('/')
+        );
+        let response = self.client
+            .get(url.as_str())
+            .headers(headers)
+            .send()
+            .map_err(|e| format!("{:?}", e))?;
+        let body: serde_json::Value = serde_json::from_str(&read_response(response)?)
+            .map_err(|e| format!("{:?}", e))?;
+
+        return vault_value(&body, reference.key.as_ref().map(|key| key.as_str()));
+    }
+
+    fn fetch_aws(
+        &self,
+        aws: &AwsSecretsConfig,
+        reference: &Reference,
+    ) -> Result<(String, Option<Duration>), String> {
+        let credentials = AwsCredentials::from_env()?;
+        let endpoint = aws.endpoint.clone().unwrap_or(format!(
+            "https://secretsmanager.{}.amazonaws.com",
+            aws.region
+        ));
+        let endpoint = endpoint.trim_right_matches('/');
+        let host = endpoint.splitn(2, "://").nth(1).unwrap_or(endpoint);
+
+        let payload = serde_json::to_string(&GetSecretValue { secret_id: reference.name.clone() })
+            .unwrap();
+        let date = amz_date(timestamp::now());
+
+        let mut headers = Headers::new();
+        headers.set(UserAgent("github.com/grahamc/ofborg".to_owned()));
+        headers.set_raw("Content-Type", vec![AWS_CONTENT_TYPE.as_bytes().to_vec()]);
+        headers.set_raw("X-Amz-Date", vec![date.clone().into_bytes()]);
+        headers.set_raw("X-Amz-Target", vec![AWS_TARGET.as_bytes().to_vec()]);
+        headers.set_raw(
+            "Authorization",
+            vec![
+                sign_v4(&credentials, &aws.region, host, &date, &payload)?.into_bytes(),
+            ],
+        );
+        if let Some(ref session_token) = credentials.session_token {
+            headers.set_raw("X-Amz-Security-Token", vec![session_token.clone().into_bytes()]);
+        }
+
+        let url = format!("{}/", endpoint);
+        let response = self.client
+            .post(url.as_str())
+            .headers(headers)
+            .body(payload.as_str())
+            .send()
+            .map_err(|e| format!("{:?}", e))?;
+        let secret: SecretValue = serde_json::from_str(&read_response(response)?)
+            .map_err(|e| format!("{:?}", e))?;
+        let secret = secret.secret_string.ok_or(String::from(
+            "the secret is binary, only strings are supported",
+        ))?;
+
+        return match reference.key {
+            Some(ref key) => {
+                let keys: serde_json::Value = serde_json::from_str(&secret).map_err(|_| {
+                    format!("the secret isn't a JSON object to pick {} from", key)
+                })?;
+                pick(&keys, Some(key)).map(|value| (value, None))
+            }
+            None => Ok((secret, None)),
+        };
+    }
+}
+
+/// A secret which is fetched again when it's about to expire.
+pub struct Secret {
+    secrets: Arc<Secrets>,
+    reference: Reference,
+    source: String,
+    cached: Mutex<Option<(String, Instant, Duration)>>,
+}
+
+impl Secret {
+    pub fn new(secrets: Arc<Secrets>, reference: &str) -> Result<Secret, String> {
+        return Ok(Secret {
+            secrets: secrets,
+            reference: Reference::parse(reference)?,
+            source: reference.to_owned(),
+            cached: Mutex::new(None),
+        });
+    }
+
+    pub fn get(&self) -> Result<String, String> {
+        let mut cached = self.cached.lock().unwrap();
+        if let Some((ref value, fetched, lifetime)) = *cached {
+            if fetched.elapsed() < lifetime - lifetime / 5 {
+                return Ok(value.clone());
+            }
+        }
+
+        debug!("Fetching the secret {}", self.source);
+        let fetched = Instant::now();
+        let (value, lease) = self.secrets.fetch(&self.reference).map_err(|e| {
+            format!("Failed to fetch the secret {}: {}", self.source, e)
+        })?;
+        *cached = Some((
+            value.clone(),
+            fetched,
+            lease.unwrap_or(self.secrets.refresh()),
+        ));
+
+        return Ok(value);
+    }
+}
+
+impl fmt::Debug for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Secret({})", self.source)
+    }
+}
+
+struct AwsCredentials {
+    access_key_id: String,
+    secret_access_key: String,
+    session_token: Option<String>,
+}
+
+impl AwsCredentials {
+    fn from_env() -> Result<AwsCredentials, String> {
+        let var = |name: &str| {
+            env::var(name).map_err(|_| format!("AWS Secrets Manager needs {}", name))
+        };
+
+        return Ok(AwsCredentials {
+            access_key_id: var("AWS_ACCESS_KEY_ID")?,
+            secret_access_key: var("AWS_SECRET_ACCESS_KEY")?,
+            session_token: env::var("AWS_SESSION_TOKEN").ok(),
+        });
+    }
+}
+
+#[derive(Serialize, Debug)]
+struct GetSecretValue {
+    #[serde(rename = "SecretId")]
+    secret_id: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct SecretValue {
+    #[serde(rename = "SecretString")]
+    secret_string: Option<String>,
+}
+
+fn read_response(mut response: Response) -> Result<String, String> {
+    let mut contents = String::new();
+    response.read_to_string(&mut contents).map_err(
+        |e| format!("{:?}", e),
+    )?;
+    if !response.status.is_success() {
+        return Err(format!("{}: {}", response.status, contents));
+    }
+
+    return Ok(contents);
+}
+
+/// The secret in a response of Vault's, either KV engine version, and
+/// its lease.
+fn vault_value(
+    response: &serde_json::Value,
+    key: Option<&str>,
+) -> Result<(String, Option<Duration>), String> {
+    let lease = response
+        .get("lease_duration")
+        .and_then(|lease| lease.as_u64())
+        .and_then(|secs| if secs > 0 {
+            Some(Duration::from_secs(secs))
+        } else {
+            None
+        });
+
+    let data = response.get("data").ok_or(
+        String::from("Vault's response has no data"),
+    )?;
+    // Version 2 nests the secret's keys next to their metadata.
+    let keys = if data.get("metadata").is_some() {
+        data.get("data").unwrap_or(data)
+    } else {
+        data
+    };
+
+    return pick(keys, key).map(|value| (value, lease));
+}
+
+/// The `key` of a secret, or its only key.
+fn pick(keys: &serde_json::Value, key: Option<&str>) -> Result<String, String> {
+    let keys = keys.as_object().ok_or(
+        String::from("the secret isn't an object of keys"),
+    )?;
+
+    let (name, value) = match key {
+        Some(key) => {
+            (
+                key,
+                keys.get(key).ok_or_else(
+                    || format!("the secret has no key {}", key),
+                )?,
+            )
+        }
+        None => {
+            if keys.len() != 1 {
+                let names: Vec<&str> = keys.keys().map(|name| name.as_str()).collect();
+                return Err(format!(
+                    "the secret has several keys, pick one of {} with #",
+                    names.join(", ")
+                ));
+            }
+            let (name, value) = keys.iter().next().unwrap();
+            (name.as_str(), value)
+        }
+    };
+
+    return value.as_str().map(String::from).ok_or_else(|| {
+        format!("the secret's {} isn't a string", name)
+    });
+}
+
+/// Like `20150830T123600Z`, from seconds since the epoch.
+fn amz_date(now: u64) -> String {
+    let (year, month, day) = timestamp::civil_date(now / 86400);
+    let secs = now % 86400;
+
+    return format!(
+        "{:04}{:02}{:02}T{:02}{:02}{:02}Z",
+        year,
+        month,
+        day,
+        secs / 3600,
+        secs % 3600 / 60,
+        secs % 60
+    );
+}
+
+/// The `Authorization` of a `GetSecretValue` request, signed with
+/// AWS' Signature Version 4.
+fn sign_v4(
+    credentials: &AwsCredentials,
+    region: &str,
+    host: &str,
+    date: &str,
+    payload: &str,
+) -> Result<String, String> {
+    let canonical = format!(
+        "POST\n/\n\ncontent-type:{}\nhost:{}\nx-amz-date:{}\nx-amz-target:{}\n\n{}\n{}",
+        AWS_CONTENT_TYPE,
+        host,
+        date,
+        AWS_TARGET,
+        AWS_SIGNED_HEADERS,
+        hex(&sha256(payload.as_bytes())?)
+    );
+    let scope = format!("{}/{}/secretsmanager/aws4_request", &date[..8], region);
+    let to_sign = format!(
+        "AWS4-HMAC-SHA256\n{}\n{}\n{}",
+        date,
+        scope,
+        hex(&sha256(canonical.as_bytes())?)
+    );
+
+    let mut key = hmac(
+        format!("AWS4{}", credentials.secret_access_key).as_bytes(),
+        date[..8].as_bytes(),
+    )?;
+    for part in [region, "secretsmanager", "aws4_request"].iter() {
+        key = hmac(&key, part.as_bytes())?;
+    }
+
+    return Ok(format!(
+        "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
+        credentials.access_key_id,
+        scope,
+        AWS_SIGNED_HEADERS,
+        hex(&hmac(&key, to_sign.as_bytes())?)
+    ));
+}
+
+fn sha256(data: &[u8]) -> Result<Vec<u8>, String> {
+    hash::hash(MessageDigest::sha256(), data)
+        .map(|digest| digest.to_vec())
+        .map_err(|e| format!("{:?}", e))
+}
+
+fn hmac(key: &[u8], data: &[u8]) -> Result<Vec<u8>, String> {
+    let key = PKey::hmac(key).map_err(|e| format!("{:?}", e))?;
+    let mut signer = Signer::new(MessageDigest::sha256(), &key).map_err(
+        |e| format!("{:?}", e),
+    )?;
+    signer.update(data).map_err(|e| format!("{:?}", e))?;
+    return signer.finish().map_err(|e| format!("{:?}", e));
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect::<Vec<String>>()
+        .join("")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_reference() {
+        assert_eq!(
+            Reference::parse("vault:secret/data/ofborg#token"),
+            Ok(Reference {
+                backend: Backend::Vault,
+                name: String::from("secret/data/ofborg"),
+                key: Some(String::from("token")),
+            })
+        );
+        assert_eq!(
+            Reference::parse("aws:ofborg/rabbitmq"),
+            Ok(Reference {
+                backend: Backend::Aws,
+                name: String::from("ofborg/rabbitmq"),
+                key: None,
+            })
+        );
+        assert_eq!(
+            Reference::parse("gcp:ofborg"),
+            Err(String::from(
+                "gcp:ofborg doesn't start with a provider, vault: or aws:",
+            ))
+        );
+        assert_eq!(
+            Reference::parse("vault:#token"),
+            Err(String::from("vault:#token doesn't name a secret"))
+        );
+    }
+
+    #[test]
+    fn test_vault_value() {
+        let kv1: serde_json::Value = serde_json::from_str(
+            r#"{"lease_duration": 2764800, "data": {"password": "hunter2"}}"#,
+        ).unwrap();
+        assert_eq!(
+            vault_value(&kv1, None),
+            Ok((String::from("hunter2"), Some(Duration::from_secs(2764800))))
+        );
+
+        let kv2: serde_json::Value = serde_json::from_str(
+            r#"{
+  "lease_duration": 0,
+  "data": {
+    "data": {"token": "abc", "password": "hunter2"},
+    "metadata": {"version": 3}
+  }
+}"#,
+        ).unwrap();
+        assert_eq!(
+            vault_value(&kv2, Some("token")),
+            Ok((String::from("abc"), None))
+        );
+        assert_eq!(
+            vault_value(&kv2, None),
+            Err(String::from(
+                "the secret has several keys, pick one of password, token with #",
+            ))
+        );
+        assert_eq!(
+            vault_value(&kv2, Some("username")),
+            Err(String::from("the secret has no key username"))
+        );
+    }
+
+    #[test]
+    fn test_amz_date() {
+        assert_eq!(amz_date(1440938160), "20150830T123600Z");
+        assert_eq!(amz_date(1709251199), "20240229T235959Z");
+    }
+
+    #[test]
+    fn test_sign_v4() {
+        let credentials = AwsCredentials {
+            access_key_id: String::from("AKIDEXAMPLE"),
+            secret_access_key: String::from("wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY"),
+            session_token: None,
+        };
+
+        assert_eq!(
+            sign_v4(
+                &credentials,
+                "us-east-1",
+                "secretsmanager.us-east-1.amazonaws.com",
+                "20150830T123600Z",
+                r#"{"SecretId":"ofborg/rabbitmq"}"#,
+            ),
+            Ok(String::from(
+                "AWS4-HMAC-SHA256 \
+                 Credential=AKIDEXAMPLE/20150830/us-east-1/secretsmanager/aws4_request, \
+                 SignedHeaders=content-type;host;x-amz-date;x-amz-target, \
+                 Signature=2dca93cf4aedbd45a0642423f5c99359a18f3efe35044246d593a3001bd899aa",
+            ))
+        );
+    }
+}
@@ -0,0 +1,128 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+use serde_json;
+use ofborg::outpathdiff::PackageOutPaths;
+
+/// Known vulnerable versions of packages, keyed by the package's
+/// derivation name, like `{"openssl": ["1.0.2m", "1.0.2n"]}`.
+pub struct VulnerabilityFeed {
+    vulnerable: HashMap<String, Vec<String>>,
+}
+
+impl VulnerabilityFeed {
+    pub fn load(path: &Path) -> Result<VulnerabilityFeed, String> {
+        let mut contents = String::new();
+        File::open(path)
+            .and_then(|mut file| file.read_to_string(&mut contents))
+            .map_err(|e| format!("Failed to read {:?}: {:?}", path, e))?;
+
+        return VulnerabilityFeed::parse(&contents);
+    }
+
+    pub fn parse(contents: &str) -> Result<VulnerabilityFeed, String> {
+        let vulnerable = serde_json::from_str(contents)
+            .map_err(|e| format!("Failed to parse the vulnerability feed: {:?}", e))?;
+
+        return Ok(VulnerabilityFeed { vulnerable: vulnerable });
+    }
+
+    pub fn is_vulnerable(&self, name: &str, version: &str) -> bool {
+        match self.vulnerable.get(name) {
+            Some(versions) => versions.iter().any(|v| v == version),
+            None => false,
+        }
+    }
+
+    /// Attrs which were at a vulnerable version on the target branch
+    /// and whose version the PR changes.
+    pub fn security_updates(
+        &self,
+        before: &PackageOutPaths,
+        after: &PackageOutPaths,
+    ) -> Vec<String> {
+        let mut updates: Vec<String> = after
+            .iter()
+            .filter_map(|(pkgarch, after_path)| {
+                let before_path = before.get(pkgarch)?;
+                let (name, old_version) = name_and_version(before_path)?;
+                let (_, new_version) = name_and_version(after_path)?;
+
+                if old_version != new_version && self.is_vulnerable(&name, &old_version) {
+                    return Some(pkgarch.package.clone());
+                } else {
+                    return None;
+                }
+            })
+            .collect();
+        updates.sort();
+        updates.dedup();
+
+        return updates;
+    }
+}
+
+/// Split a store path's name in to its name and version, the same way
+/// `builtins.parseDrvName` does: the version starts at the first dash
+/// which is followed by a digit.
+pub fn name_and_version(out_path: &str) -> Option<(String, String)> {
+    let basename = out_path.rsplit('/').next()?;
+    let drvname = basename.splitn(2, '-').nth(1)?;
+
+    let split = drvname
+        .char_indices()
+        .find(|&(i, c)| {
+            c == '-' && drvname[i + 1..].chars().next().map(|n| n.is_digit(10)).unwrap_or(false)
+        })
+        .map(|(i, _)| i)?;
+
+    return Some((drvname[..split].to_owned(), drvname[split + 1..].to_owned()));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ofborg::outpathdiff::PackageArch;
+
+    #[test]
+    fn test_name_and_version() {
+        assert_eq!(
+            name_and_version("/nix/store/6djnw9s2z5iy0c741qa8yk0k2v6bxrra-pan-0.139"),
+            Some(("pan".to_owned(), "0.139".to_owned()))
+        );
+        assert_eq!(
+            name_and_version(
+                "/nix/store/rba0hbq6i4camvhpj9723dvs4b511ryn-python2.7-pyinotify-0.9.6"
+            ),
+            Some(("python2.7-pyinotify".to_owned(), "0.9.6".to_owned()))
+        );
+        assert_eq!(
+            name_and_version("/nix/store/rba0hbq6i4camvhpj9723dvs4b511ryn-hook"),
+            None
+        );
+    }
+
+    #[test]
+    fn test_security_updates() {
+        let feed = VulnerabilityFeed::parse("{\"openssl\": [\"1.0.2m\"], \"pan\": [\"0.139\"]}")
+            .unwrap();
+
+        let pkg = |name: &str| PackageArch {
+            package: name.to_owned(),
+            architecture: "x86_64-linux".to_owned(),
+        };
+
+        let mut before: PackageOutPaths = HashMap::new();
+        before.insert(pkg("openssl"), "/nix/store/aaaa-openssl-1.0.2m".to_owned());
+        before.insert(pkg("pan"), "/nix/store/aaaa-pan-0.139".to_owned());
+        before.insert(pkg("hello"), "/nix/store/aaaa-hello-2.10".to_owned());
+
+        let mut after: PackageOutPaths = HashMap::new();
+        after.insert(pkg("openssl"), "/nix/store/bbbb-openssl-1.0.2n".to_owned());
+        after.insert(pkg("pan"), "/nix/store/bbbb-pan-0.139".to_owned());
+        after.insert(pkg("hello"), "/nix/store/bbbb-hello-2.11".to_owned());
+
+        assert_eq!(feed.security_updates(&before, &after), vec!["openssl".to_owned()]);
+    }
+}
@@ -0,0 +1,114 @@
+//! Make up the webhooks GitHub would send, to exercise the comment
+//! filter and the evaluator without a repository on GitHub.
+
+use serde_json::{self, Value};
+
+use ofborg::webhook::{self, Delivery};
+
+fn repository(full_name: &str) -> Value {
+    let owner = full_name.split('/').next().unwrap_or(full_name);
+    let name = full_name.splitn(2, '/').nth(1).unwrap_or(full_name);
+
+    json!({
+        "name": name,
+        "full_name": full_name,
+        "owner": { "login": owner, "type": "Organization" },
+        "private": false,
+        "html_url": format!("https://github.com/{}", full_name),
+        "clone_url": format!("https://github.com/{}.git", full_name),
+        "default_branch": "master",
+    })
+}
+
+/// An `issue_comment` event of `author` commenting `body` on a PR.
+pub fn issue_comment(full_name: &str, number: u64, author: &str, body: &str) -> Value {
+    json!({
+        "action": "created",
+        "issue": {
+            "number": number,
+            "title": format!("Simulated PR #{}", number),
+            "state": "open",
+            "user": { "login": author },
+            "pull_request": {
+                "url": format!("https://api.github.com/repos/{}/pulls/{}", full_name, number),
+            },
+        },
+        "comment": {
+            "id": number * 1000 + 1,
+            "body": body,
+            "user": { "login": author },
+            "author_association": "MEMBER",
+        },
+        "repository": repository(full_name),
+        "sender": { "login": author },
+    })
+}
+
+/// A `pull_request` event, like `opened` or `synchronize`, of a PR
+/// whose head is `head_sha`, against `base_ref`.
+pub fn pull_request(
+    full_name: &str,
+    number: u64,
+    action: &str,
+    author: &str,
+    head_sha: &str,
+    base_ref: &str,
+) -> Value {
+    json!({
+        "action": action,
+        "number": number,
+        "pull_request": {
+            "number": number,
+            "state": "open",
+            "draft": false,
+            "title": format!("Simulated PR #{}", number),
+            "body": "",
+            "user": { "login": author },
+            "base": { "ref": base_ref, "sha": "0000000000000000000000000000000000000000" },
+            "head": { "ref": format!("simulated-{}", number), "sha": head_sha },
+        },
+        "repository": repository(full_name),
+        "sender": { "login": author },
+    })
+}
+
+/// A delivery of `event_type` with `payload`, checked like the webhook
+/// receiver checks what GitHub sends.
+pub fn delivery(event_type: &str, payload: &Value) -> Result<Delivery, String> {
+    let payload = serde_json::to_vec(payload).map_err(|e| format!("{:?}", e))?;
+    return webhook::normalize(event_type, &payload);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ofborg::ghevent;
+
+    #[test]
+    fn test_issue_comment() {
+        let payload = issue_comment("NixOS/nixpkgs", 12345, "someone", "@grahamcofborg build hello");
+        let delivery = delivery("issue_comment", &payload).unwrap();
+        assert_eq!(delivery.routing_key, "issue_comment.nixos/nixpkgs");
+
+        let event: ghevent::IssueComment = serde_json::from_slice(&delivery.body).unwrap();
+        assert_eq!(event.issue.number, 12345);
+        assert_eq!(event.comment.body, "@grahamcofborg build hello");
+        assert_eq!(event.repository.owner.login, "NixOS");
+        assert_eq!(event.repository.name, "nixpkgs");
+    }
+
+    #[test]
+    fn test_pull_request() {
+        let payload = pull_request("NixOS/nixpkgs", 12345, "opened", "someone", "abc", "staging");
+        let delivery = delivery("pull_request", &payload).unwrap();
+        assert_eq!(delivery.routing_key, "pull_request.nixos/nixpkgs");
+
+        let event: ghevent::PullRequestEvent = serde_json::from_slice(&delivery.body).unwrap();
+        assert_eq!(event.action, ghevent::PullRequestAction::Opened);
+        assert_eq!(event.pull_request.head.sha, "abc");
+        assert_eq!(event.pull_request.base.git_ref, "staging");
+
+        let payload = pull_request("NixOS/nixpkgs", 1, "exploded", "someone", "abc", "master");
+        assert!(delivery("pull_request", &payload).is_err());
+    }
+}
@@ -1,7 +1,20 @@
 use serde_json;
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{ErrorKind, Read, Write};
+use std::mem;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use amqp::Channel;
 use amqp::protocol::basic::BasicProperties;
-use amqp::Basic;
+use hyper::server::{Request, Response, Server};
+use ofborg;
+use ofborg::easyamqp;
+use ofborg::message::envelope::{self, Encoding, Format, Versioned};
+use ofborg::message::usagesummary::{self, Usage, UsageSummary};
+use ofborg::msgsign::Signer;
+use ofborg::worker::QueueMsg;
 
 include!(concat!(env!("OUT_DIR"), "/events.rs"));
 
@@ -11,8 +24,95 @@ mod macros {
     macro_rules! my_macro(() => (FooBar));
 }
 
+/// Upper bounds of the buckets durations fall in, in seconds.
+pub const DURATION_BUCKETS: [u64; 11] = [30, 60, 120, 300, 600, 1200, 1800, 3600, 7200, 14400, 28800];
+
+/// How many values fell at or below each of `DURATION_BUCKETS`, like
+/// Prometheus' histograms.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Histogram {
+    pub buckets: Vec<u64>,
+    pub sum: u64,
+    pub count: u64,
+}
+
+impl Histogram {
+    pub fn new() -> Histogram {
+        return Histogram {
+            buckets: vec![0; DURATION_BUCKETS.len()],
+            sum: 0,
+            count: 0,
+        };
+    }
+
+    pub fn observe(&mut self, value: u64) {
+        for (bucket, bound) in self.buckets.iter_mut().zip(DURATION_BUCKETS.iter()) {
+            if value <= *bound {
+                *bucket += 1;
+            }
+        }
+        self.sum += value;
+        self.count += 1;
+    }
+
+    /// The samples of the histogram `name` with `labels`, like
+    /// `system="x86_64-linux"`.
+    pub fn prometheus_output(&self, name: &str, labels: &[String]) -> String {
+        let with_bound = |bound: String| {
+            let mut labels = labels.to_vec();
+            labels.push(format!("le=\"{}\"", bound));
+            labels.join(",")
+        };
+
+        let mut lines: Vec<String> = self.buckets
+            .iter()
+            .zip(DURATION_BUCKETS.iter())
+            .map(|(count, bound)| {
+                format!("{}_bucket{{{}}} {}", name, with_bound(bound.to_string()), count)
+            })
+            .collect();
+        lines.push(format!(
+            "{}_bucket{{{}}} {}",
+            name,
+            with_bound(String::from("+Inf")),
+            self.count
+        ));
+        lines.push(format!("{}_sum{{{}}} {}", name, labels.join(","), self.sum));
+        lines.push(format!("{}_count{{{}}} {}", name, labels.join(","), self.count));
+
+        return lines.join("\n");
+    }
+}
+
+/// What builds of `attrs` are grouped by: their package set, like
+/// `python3Packages`, or `top-level` for top-level attrs. Builds of
+/// several package sets are `mixed`.
+pub fn attr_prefix(attrs: &[String]) -> String {
+    let mut prefixes: Vec<&str> = attrs
+        .iter()
+        .map(|attr| if attr.contains('.') {
+            attr.split('.').next().unwrap()
+        } else {
+            "top-level"
+        })
+        .collect();
+    prefixes.dedup();
+
+    return match prefixes.len() {
+        0 => String::from("none"),
+        1 => prefixes[0].to_owned(),
+        _ => String::from("mixed"),
+    };
+}
+
 pub trait SysEvents: Send {
     fn notify(&mut self, event: Event);
+
+    fn notify_all(&mut self, events: Vec<Event>) {
+        for event in events {
+            self.notify(event);
+        }
+    }
 }
 
 
@@ -22,32 +122,601 @@ pub struct EventMessage {
     pub events: Vec<Event>,
 }
 
+impl Versioned for EventMessage {
+    const SCHEMA: &'static str = "stats-events";
+    const VERSION: u32 = 1;
+}
+
 pub struct RabbitMQ {
     identity: String,
     channel: Channel,
+    signer: Option<Signer>,
 }
 
 impl RabbitMQ {
     pub fn new(identity: &str, channel: Channel) -> RabbitMQ {
-        RabbitMQ { identity: identity.to_owned(), channel: channel }
+        RabbitMQ { identity: identity.to_owned(), channel: channel, signer: None }
+    }
+
+    pub fn with_signer(mut self, signer: Option<Signer>) -> RabbitMQ {
+        self.signer = signer;
+        return self;
+    }
+}
+
+/// The measurements of one PR evaluation, sent together at its end.
+#[derive(Debug)]
+pub struct EvaluationStats {
+    pub branch: String,
+    pub duration_secs: u64,
+    pub peak_memory_mb: u64,
+    pub attrs: u64,
+    pub changed_outpaths: u64,
+    pub check_durations: Vec<(String, u64)>,
+}
+
+impl EvaluationStats {
+    pub fn events(&self) -> Vec<Event> {
+        let mut events = vec![
+            Event::EvaluationTotalDuration(self.branch.clone(), self.duration_secs),
+            Event::EvaluationTotalDurationCount(self.branch.clone()),
+            Event::EvaluationPeakMemory(self.branch.clone(), self.peak_memory_mb),
+            Event::EvaluationAttrs(self.branch.clone(), self.attrs),
+            Event::EvaluationChangedOutpaths(self.branch.clone(), self.changed_outpaths),
+        ];
+
+        for &(ref check, duration) in self.check_durations.iter() {
+            events.push(Event::EvalCheckDuration(check.clone(), duration));
+            events.push(Event::EvalCheckDurationCount(check.clone()));
+        }
+
+        return events;
     }
 }
 
 impl SysEvents for RabbitMQ {
     fn notify(&mut self, event: Event) {
-        let props = BasicProperties { ..Default::default() };
-        self.channel
-            .basic_publish(
-                String::from("stats"),
-                "".to_owned(),
-                false,
-                false,
-                props,
-                serde_json::to_string(&EventMessage {
-                    sender: self.identity.clone(),
-                    events: vec![event],
-                }).unwrap().into_bytes(),
+        self.notify_all(vec![event]);
+    }
+
+    fn notify_all(&mut self, events: Vec<Event>) {
+        let msg = QueueMsg {
+            exchange: Some(String::from("stats")),
+            routing_key: None,
+            mandatory: false,
+            immediate: false,
+            properties: Some(BasicProperties {
+                content_type: Some(Format::published(Encoding::Json).content_type().to_owned()),
+                ..Default::default()
+            }),
+            content: envelope::to_vec(&EventMessage {
+                sender: self.identity.clone(),
+                events: events,
+            }),
+        };
+        easyamqp::publish(&mut self.channel, &self.signer, msg).unwrap();
+    }
+}
+
+/// The values of a `MetricCollector`, kept on disk so they don't start
+/// over from zero whenever the collector restarts.
+#[derive(Serialize, Deserialize, Debug)]
+struct Snapshot {
+    /// Seconds since the epoch.
+    taken_at: u64,
+    metrics: HashMap<String, serde_json::Value>,
+}
+
+/// Load the snapshot at `path` into `collector`, if there's one.
+pub fn restore_snapshot(collector: &MetricCollector, path: &Path) -> Result<(), String> {
+    let mut contents = String::new();
+    match File::open(path) {
+        Ok(mut file) => {
+            file.read_to_string(&mut contents).map_err(|e| {
+                format!("Failed to read {}: {:?}", path.display(), e)
+            })?;
+        }
+        Err(ref e) if e.kind() == ErrorKind::NotFound => {
+            info!("No stats snapshot at {} to restore", path.display());
+            return Ok(());
+        }
+        Err(e) => return Err(format!("Failed to open {}: {:?}", path.display(), e)),
+    }
+
+    let snapshot: Snapshot = serde_json::from_str(&contents).map_err(|e| {
+        format!("Failed to parse {}: {:?}", path.display(), e)
+    })?;
+    collector.restore(&snapshot.metrics);
+    info!(
+        "Restored the stats of {} from {}",
+        snapshot.taken_at,
+        path.display()
+    );
+
+    return Ok(());
+}
+
+/// Write the values of `collector` to `path`, replacing it at once.
+pub fn save_snapshot(collector: &MetricCollector, path: &Path) -> Result<(), String> {
+    let snapshot = Snapshot {
+        taken_at: now_secs(),
+        metrics: collector.snapshot(),
+    };
+    let contents = serde_json::to_string(&snapshot).map_err(|e| format!("{:?}", e))?;
+
+    let partial = path.with_extension("partial");
+    File::create(&partial)
+        .and_then(|mut file| {
+            file.write_all(contents.as_bytes())?;
+            file.sync_all()
+        })
+        .and_then(|_| fs::rename(&partial, path))
+        .map_err(|e| format!("Failed to write {}: {:?}", path.display(), e))?;
+
+    return Ok(());
+}
+
+/// Save a snapshot of `collector` to `path` every `interval`.
+pub fn save_snapshots(collector: MetricCollector, path: PathBuf, interval: Duration) {
+    thread::spawn(move || loop {
+        thread::sleep(interval);
+        if let Err(e) = save_snapshot(&collector, &path) {
+            warn!("Failed to save the stats: {}", e);
+        }
+    });
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|now| now.as_secs())
+        .unwrap_or(0)
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+struct Tally {
+    builds: u64,
+    builder_secs: u64,
+    eval_secs: u64,
+}
+
+/// What was built and evaluated for whom since the last summary, by
+/// repository and user.
+#[derive(Clone)]
+pub struct UsageLedger {
+    since: Arc<Mutex<u64>>,
+    tallies: Arc<Mutex<BTreeMap<(String, String), Tally>>>,
+}
+
+impl UsageLedger {
+    pub fn new() -> UsageLedger {
+        return UsageLedger {
+            since: Arc::new(Mutex::new(now_secs())),
+            tallies: Arc::new(Mutex::new(BTreeMap::new())),
+        };
+    }
+
+    pub fn record(&self, event: &Event) {
+        let mut tallies = self.tallies.lock().unwrap();
+        match event {
+            &Event::UsageBuildRequested(ref repo, ref user) => {
+                tallies
+                    .entry((repo.clone(), user.clone()))
+                    .or_insert(Tally::default())
+                    .builds += 1;
+            }
+            &Event::UsageBuilderSeconds(ref repo, ref user, secs) => {
+                tallies
+                    .entry((repo.clone(), user.clone()))
+                    .or_insert(Tally::default())
+                    .builder_secs += secs;
+            }
+            &Event::UsageEvalSeconds(ref repo, ref user, secs) => {
+                tallies
+                    .entry((repo.clone(), user.clone()))
+                    .or_insert(Tally::default())
+                    .eval_secs += secs;
+            }
+            _ => {}
+        }
+    }
+
+    /// The usage since the last summary until `now`, starting over.
+    pub fn summarize(&self, now: u64) -> UsageSummary {
+        let tallies = mem::replace(&mut *self.tallies.lock().unwrap(), BTreeMap::new());
+        let from = mem::replace(&mut *self.since.lock().unwrap(), now);
+
+        let usage = tallies
+            .into_iter()
+            .map(|((repo, user), tally)| {
+                Usage {
+                    repo: repo,
+                    user: user,
+                    builds: tally.builds,
+                    builder_minutes: minutes(tally.builder_secs),
+                    eval_minutes: minutes(tally.eval_secs),
+                }
+            })
+            .collect();
+
+        return UsageSummary::new(from, now, usage);
+    }
+}
+
+/// Started minutes count in full.
+fn minutes(secs: u64) -> u64 {
+    (secs + 59) / 60
+}
+
+/// Publish a summary of `ledger` every `interval`, unless nothing was
+/// used.
+pub fn publish_usage_summaries(
+    ledger: UsageLedger,
+    mut channel: Channel,
+    interval: Duration,
+    signer: Option<Signer>,
+) {
+    thread::spawn(move || loop {
+        thread::sleep(interval);
+        let summary = ledger.summarize(now_secs());
+        if summary.usage.len() == 0 {
+            continue;
+        }
+
+        let msg = QueueMsg {
+            exchange: Some(String::from(usagesummary::EXCHANGE)),
+            routing_key: None,
+            mandatory: false,
+            immediate: false,
+            properties: Some(easyamqp::persistent_message()),
+            content: envelope::to_vec(&summary),
+        };
+        if let Err(e) = easyamqp::publish(&mut channel, &signer, msg) {
+            warn!("Failed to publish the usage summary: {:?}", e);
+        }
+    });
+}
+
+type Labels = Vec<(String, String)>;
+
+struct Family {
+    help: String,
+    kind: &'static str,
+    samples: BTreeMap<Labels, f64>,
+}
+
+/// The metrics a program exports itself for Prometheus to scrape,
+/// each labelled with its task, instance and system. Tasks register
+/// the metrics of their own through `register_metrics`.
+#[derive(Clone)]
+pub struct Registry {
+    common: Labels,
+    families: Arc<Mutex<BTreeMap<String, Family>>>,
+}
+
+impl Registry {
+    pub fn new(task: &str, instance: &str, system: &str) -> Registry {
+        let registry = Registry {
+            common: vec![
+                (String::from("task"), task.to_owned()),
+                (String::from("instance"), instance.to_owned()),
+                (String::from("system"), system.to_owned()),
+            ],
+            families: Arc::new(Mutex::new(BTreeMap::new())),
+        };
+        registry
+            .gauge("ofborg_build_info", "The version of ofborg running")
+            .set(&[("version", ofborg::VERSION)], 1.0);
+
+        return registry;
+    }
+
+    /// A metric which only goes up, like `ofborg_jobs_total`.
+    pub fn counter(&self, name: &str, help: &str) -> Counter {
+        return Counter { metric: self.metric(name, help, "counter") };
+    }
+
+    /// A metric which is set to its current value.
+    pub fn gauge(&self, name: &str, help: &str) -> Gauge {
+        return Gauge { metric: self.metric(name, help, "gauge") };
+    }
+
+    fn metric(&self, name: &str, help: &str, kind: &'static str) -> Metric {
+        self.families
+            .lock()
+            .unwrap()
+            .entry(name.to_owned())
+            .or_insert_with(|| {
+                Family {
+                    help: help.to_owned(),
+                    kind: kind,
+                    samples: BTreeMap::new(),
+                }
+            });
+
+        return Metric {
+            name: name.to_owned(),
+            common: self.common.clone(),
+            families: self.families.clone(),
+        };
+    }
+
+    /// Count the events passed on to `events` as well, by their name.
+    pub fn count_events<E: SysEvents>(&self, events: E) -> CountedEvents<E> {
+        return CountedEvents {
+            events: events,
+            counter: self.counter("ofborg_events_total", "Events sent to the stats collector"),
+        };
+    }
+
+    pub fn prometheus_output(&self) -> String {
+        let mut output = String::new();
+
+        for (name, family) in self.families.lock().unwrap().iter() {
+            output.push_str(&format!("# HELP {} {}\n", name, family.help));
+            output.push_str(&format!("# TYPE {} {}\n", name, family.kind));
+            for (labels, value) in family.samples.iter() {
+                let labels: Vec<String> = labels
+                    .iter()
+                    .map(|&(ref key, ref value)| format!("{}=\"{}\"", key, escape(value)))
+                    .collect();
+                output.push_str(&format!("{}{{{}}} {}\n", name, labels.join(","), value));
+            }
+        }
+
+        return output;
+    }
+
+    /// Serve the metrics on `listen`, like `0.0.0.0:9899`.
+    pub fn serve(&self, listen: &str) {
+        let registry = self.clone();
+        let listen = listen.to_owned();
+
+        thread::spawn(move || {
+            info!("Exporting metrics on {}", listen);
+            let server = match Server::http(listen.as_str()) {
+                Ok(server) => server,
+                Err(e) => {
+                    warn!("Not exporting metrics, failed to listen on {}: {:?}", listen, e);
+                    return;
+                }
+            };
+
+            let served = server.handle(move |_: Request, res: Response| {
+                let _ = res.send(registry.prometheus_output().as_bytes());
+            });
+            if let Err(e) = served {
+                warn!("Stopped exporting metrics: {:?}", e);
+            }
+        });
+    }
+}
+
+fn escape(value: &str) -> String {
+    value
+        .replace("\\", "\\\\")
+        .replace("\"", "\\\"")
+        .replace("\n", "\\n")
+}
+
+#[derive(Clone)]
+struct Metric {
+    name: String,
+    common: Labels,
+    families: Arc<Mutex<BTreeMap<String, Family>>>,
+}
+
+impl Metric {
+    fn update<F: FnOnce(&mut f64)>(&self, labels: &[(&str, &str)], update: F) {
+        let mut all = self.common.clone();
+        all.extend(labels.iter().map(
+            |&(key, value)| (key.to_owned(), value.to_owned()),
+        ));
+
+        let mut families = self.families.lock().unwrap();
+        if let Some(family) = families.get_mut(&self.name) {
+            update(family.samples.entry(all).or_insert(0.0));
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Counter {
+    metric: Metric,
+}
+
+impl Counter {
+    pub fn inc(&self, labels: &[(&str, &str)]) {
+        self.add(labels, 1.0);
+    }
+
+    pub fn add(&self, labels: &[(&str, &str)], value: f64) {
+        self.metric.update(labels, |sample| *sample += value);
+    }
+}
+
+#[derive(Clone)]
+pub struct Gauge {
+    metric: Metric,
+}
+
+impl Gauge {
+    pub fn set(&self, labels: &[(&str, &str)], value: f64) {
+        self.metric.update(labels, |sample| *sample = value);
+    }
+}
+
+pub struct CountedEvents<E> {
+    events: E,
+    counter: Counter,
+}
+
+impl<E: SysEvents> SysEvents for CountedEvents<E> {
+    fn notify(&mut self, event: Event) {
+        self.notify_all(vec![event]);
+    }
+
+    fn notify_all(&mut self, events: Vec<Event>) {
+        for event in events.iter() {
+            self.counter.inc(&[("event", &event_metric_name(event))]);
+        }
+        self.events.notify_all(events);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ofborg::test_scratch::TestScratch;
+
+    #[test]
+    fn test_histogram() {
+        let mut histogram = Histogram::new();
+        histogram.observe(45);
+        histogram.observe(300);
+        histogram.observe(40000);
+
+        assert_eq!(histogram.buckets, vec![0, 1, 1, 2, 2, 2, 2, 2, 2, 2, 2]);
+        assert_eq!(histogram.sum, 40345);
+        assert_eq!(histogram.count, 3);
+
+        let output = histogram.prometheus_output(
+            "ofborg_build_duration",
+            &[String::from("system=\"x86_64-linux\"")],
+        );
+        let lines: Vec<&str> = output.lines().collect();
+        assert_eq!(lines.len(), 14);
+        assert_eq!(
+            lines[1],
+            "ofborg_build_duration_bucket{system=\"x86_64-linux\",le=\"60\"} 1"
+        );
+        assert_eq!(
+            lines[11],
+            "ofborg_build_duration_bucket{system=\"x86_64-linux\",le=\"+Inf\"} 3"
+        );
+        assert_eq!(lines[12], "ofborg_build_duration_sum{system=\"x86_64-linux\"} 40345");
+        assert_eq!(lines[13], "ofborg_build_duration_count{system=\"x86_64-linux\"} 3");
+    }
+
+    #[test]
+    fn test_snapshots() {
+        let scratch = TestScratch::new_file("stats-snapshot");
+        let sorted = |output: String| -> Vec<String> {
+            let mut lines: Vec<String> = output.lines().map(String::from).collect();
+            lines.sort();
+            lines
+        };
+
+        let fresh = MetricCollector::new();
+        assert_eq!(restore_snapshot(&fresh, &scratch.path()), Ok(()));
+
+        let collector = MetricCollector::new();
+        collector.record(String::from("my-builder"), Event::JobReceived);
+        collector.record(String::from("my-builder"), Event::JobReceived);
+        collector.record(
+            String::from("my-builder"),
+            Event::BuildDuration(String::from("x86_64-linux"), String::from("top-level"), 90),
+        );
+        save_snapshot(&collector, &scratch.path()).unwrap();
+
+        assert_eq!(restore_snapshot(&fresh, &scratch.path()), Ok(()));
+        assert_eq!(
+            sorted(fresh.prometheus_output()),
+            sorted(collector.prometheus_output())
+        );
+
+        // Counting goes on from where the snapshot left off.
+        fresh.record(String::from("my-builder"), Event::JobReceived);
+        assert!(fresh.prometheus_output().contains(
+            "ofborg_job_received{instance=\"my-builder\"} 3",
+        ));
+    }
+
+    #[test]
+    fn test_usage_ledger() {
+        let ledger = UsageLedger::new();
+        let nixpkgs = String::from("NixOS/nixpkgs");
+        ledger.record(&Event::UsageBuildRequested(nixpkgs.clone(), String::from("alice")));
+        ledger.record(&Event::UsageBuilderSeconds(nixpkgs.clone(), String::from("alice"), 61));
+        ledger.record(&Event::UsageBuildRequested(nixpkgs.clone(), String::from("alice")));
+        ledger.record(&Event::UsageBuilderSeconds(nixpkgs.clone(), String::from("alice"), 30));
+        ledger.record(&Event::UsageEvalSeconds(nixpkgs.clone(), String::from("bob"), 600));
+        ledger.record(&Event::JobReceived);
+
+        let summary = ledger.summarize(2000000000);
+        assert_eq!(summary.to, 2000000000);
+        assert_eq!(
+            summary.usage,
+            vec![
+                Usage {
+                    repo: nixpkgs.clone(),
+                    user: String::from("alice"),
+                    builds: 2,
+                    builder_minutes: 2,
+                    eval_minutes: 0,
+                },
+                Usage {
+                    repo: nixpkgs.clone(),
+                    user: String::from("bob"),
+                    builds: 0,
+                    builder_minutes: 0,
+                    eval_minutes: 10,
+                },
+            ]
+        );
+
+        let next = ledger.summarize(2000003600);
+        assert_eq!(next.from, 2000000000);
+        assert_eq!(next.usage, Vec::<Usage>::new());
+    }
+
+    #[test]
+    fn test_attr_prefix() {
+        let attrs = |attrs: Vec<&str>| -> Vec<String> {
+            attrs.into_iter().map(String::from).collect()
+        };
+
+        assert_eq!(
+            attr_prefix(&attrs(vec!["python3Packages.requests", "python3Packages.flask"])),
+            "python3Packages"
+        );
+        assert_eq!(attr_prefix(&attrs(vec!["hello"])), "top-level");
+        assert_eq!(
+            attr_prefix(&attrs(vec!["hello", "haskellPackages.lens"])),
+            "mixed"
+        );
+        assert_eq!(attr_prefix(&attrs(vec![])), "none");
+    }
+
+    #[test]
+    fn test_prometheus_output() {
+        let registry = Registry::new("builder", "my-builder", "x86_64-linux");
+        let jobs = registry.counter("ofborg_jobs_total", "Jobs handled, by their outcome");
+        jobs.inc(&[("outcome", "ack")]);
+        jobs.inc(&[("outcome", "ack")]);
+        jobs.add(&[("outcome", "nack_dump")], 1.0);
+        registry
+            .gauge("ofborg_queue", "A \"quoted\" gauge")
+            .set(&[("queue", "build\\inputs")], 2.5);
+
+        let common = "task=\"builder\",instance=\"my-builder\",system=\"x86_64-linux\"";
+        assert_eq!(
+            registry.prometheus_output(),
+            format!(
+                "# HELP ofborg_build_info The version of ofborg running
+# TYPE ofborg_build_info gauge
+ofborg_build_info{{{common},version=\"{version}\"}} 1
+# HELP ofborg_jobs_total Jobs handled, by their outcome
+# TYPE ofborg_jobs_total counter
+ofborg_jobs_total{{{common},outcome=\"ack\"}} 2
+ofborg_jobs_total{{{common},outcome=\"nack_dump\"}} 1
+# HELP ofborg_queue A \"quoted\" gauge
+# TYPE ofborg_queue gauge
+ofborg_queue{{{common},queue=\"build\\\\inputs\"}} 2.5
+",
+                common = common,
+                version = ofborg::VERSION
             )
-            .unwrap();
+        );
     }
 }
@@ -1,6 +1,6 @@
 use ofborg::tasks;
 use ofborg::outpathdiff::PackageArch;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
 pub struct StdenvTagger {
     possible: Vec<String>,
@@ -100,39 +100,47 @@ impl PkgsAddedRemovedTagger {
 pub struct RebuildTagger {
     possible: Vec<String>,
     selected: Vec<String>,
+    thresholds: Vec<u64>,
 }
 
 impl RebuildTagger {
     pub fn new() -> RebuildTagger {
+        return RebuildTagger::with_thresholds(vec![10, 100, 500]);
+    }
+
+    /// Create a tagger whose buckets end at each of the provided
+    /// thresholds. For example, `[10, 100, 500]` produces the buckets
+    /// `0`, `1-10`, `11-100`, `101-500`, and `501+`.
+    pub fn with_thresholds(thresholds: Vec<u64>) -> RebuildTagger {
+        let mut thresholds: Vec<u64> = thresholds.into_iter().filter(|t| *t > 0).collect();
+        thresholds.sort();
+        thresholds.dedup();
+
         let mut t = RebuildTagger {
-            possible: vec![
-                String::from("10.rebuild-linux: 501+"),
-                String::from("10.rebuild-linux: 101-500"),
-                String::from("10.rebuild-linux: 11-100"),
-                String::from("10.rebuild-linux: 1-10"),
-                String::from("10.rebuild-linux: 0"),
-
-                String::from("10.rebuild-darwin: 501+"),
-                String::from("10.rebuild-darwin: 101-500"),
-                String::from("10.rebuild-darwin: 11-100"),
-                String::from("10.rebuild-darwin: 1-10"),
-                String::from("10.rebuild-darwin: 0"),
-            ],
+            possible: vec![],
             selected: vec![],
+            thresholds: thresholds,
         };
+
+        for bucket in t.buckets() {
+            t.possible.push(format!("10.rebuild-linux: {}", bucket));
+            t.possible.push(format!("10.rebuild-darwin: {}", bucket));
+        }
         t.possible.sort();
 
         return t;
     }
 
     pub fn parse_attrs(&mut self, attrs: Vec<PackageArch>) {
-        let mut counter_darwin = 0;
+        // A package rebuilding on both darwin architectures is only
+        // counted once.
+        let mut darwin: HashSet<String> = HashSet::new();
         let mut counter_linux = 0;
 
         for attr in attrs {
             match attr.architecture.as_ref() {
-                "x86_64-darwin" => {
-                    counter_darwin += 1;
+                "x86_64-darwin" | "aarch64-darwin" => {
+                    darwin.insert(attr.package);
                 }
                 "x86_64-linux" => {
                     counter_linux += 1;
@@ -144,6 +152,7 @@ impl RebuildTagger {
                 }
             }
         }
+        let counter_darwin = darwin.len() as u64;
 
         self.selected =
             vec![
@@ -164,12 +173,40 @@ impl RebuildTagger {
         }
     }
 
+    /// Select the largest bucket for each system whose stdenv changed,
+    /// without counting the individual rebuilds. Labels of other
+    /// systems are left alone, as their rebuild count is unknown.
+    pub fn treewide(&mut self, systems: Vec<tasks::massrebuilder::System>) {
+        let top = self.buckets().pop().unwrap();
+
+        self.selected = systems
+            .into_iter()
+            .map(|system| match system {
+                tasks::massrebuilder::System::X8664Darwin => {
+                    format!("10.rebuild-darwin: {}", top)
+                }
+                tasks::massrebuilder::System::X8664Linux => {
+                    format!("10.rebuild-linux: {}", top)
+                }
+            })
+            .collect();
+    }
+
     pub fn tags_to_add(&self) -> Vec<String> {
         self.selected.clone()
     }
 
     pub fn tags_to_remove(&self) -> Vec<String> {
-        let mut remove = self.possible.clone();
+        let prefixes: Vec<&str> = self.selected
+            .iter()
+            .map(|tag| tag.splitn(2, ": ").next().unwrap())
+            .collect();
+
+        let mut remove: Vec<String> = self.possible
+            .iter()
+            .filter(|tag| prefixes.contains(&tag.splitn(2, ": ").next().unwrap()))
+            .cloned()
+            .collect();
         for tag in &self.selected {
             let pos = remove.binary_search(&tag).unwrap();
             remove.remove(pos);
@@ -178,19 +215,33 @@ impl RebuildTagger {
         return remove;
     }
 
-    fn bucket(&self, count: u64) -> &str {
-        if count > 500 {
-            return "501+";
-        } else if count > 100 {
-            return "101-500";
-        } else if count > 10 {
-            return "11-100";
-        } else if count > 0 {
-            return "1-10";
-        } else {
-            return "0";
+    fn buckets(&self) -> Vec<String> {
+        let mut buckets = vec![String::from("0")];
+        let mut lower: u64 = 0;
+
+        for upper in &self.thresholds {
+            buckets.push(format!("{}-{}", lower + 1, upper));
+            lower = *upper;
         }
+        buckets.push(format!("{}+", lower + 1));
 
+        return buckets;
+    }
+
+    fn bucket(&self, count: u64) -> String {
+        if count == 0 {
+            return String::from("0");
+        }
+
+        let mut lower: u64 = 0;
+        for upper in &self.thresholds {
+            if count <= *upper {
+                return format!("{}-{}", lower + 1, upper);
+            }
+            lower = *upper;
+        }
+
+        return format!("{}+", lower + 1);
     }
 }
 
@@ -240,6 +291,94 @@ impl PathsTagger {
 mod tests {
     use super::*;
 
+    fn attrs(arch: &str, count: usize) -> Vec<PackageArch> {
+        (0..count)
+            .map(|i| PackageArch {
+                package: format!("pkg{}", i),
+                architecture: arch.to_owned(),
+            })
+            .collect()
+    }
+
+    #[test]
+    pub fn test_rebuild_tagger_default_buckets() {
+        let mut tagger = RebuildTagger::new();
+        let mut changed = attrs("x86_64-linux", 150);
+        changed.append(&mut attrs("x86_64-darwin", 10));
+        tagger.parse_attrs(changed);
+
+        assert_eq!(
+            tagger.tags_to_add(),
+            vec![
+                "10.rebuild-linux: 101-500".to_owned(),
+                "10.rebuild-darwin: 1-10".to_owned(),
+            ]
+        );
+        assert_eq!(tagger.tags_to_remove().len(), 8);
+    }
+
+    #[test]
+    pub fn test_rebuild_tagger_custom_thresholds() {
+        let mut tagger = RebuildTagger::with_thresholds(vec![5000, 500, 0, 500]);
+        let mut changed = attrs("x86_64-linux", 5001);
+        changed.append(&mut attrs("aarch64-linux", 3));
+        tagger.parse_attrs(changed);
+
+        assert_eq!(
+            tagger.tags_to_add(),
+            vec![
+                "10.rebuild-linux: 5001+".to_owned(),
+                "10.rebuild-darwin: 0".to_owned(),
+            ]
+        );
+        assert_eq!(
+            tagger.tags_to_remove(),
+            vec![
+                "10.rebuild-darwin: 1-500".to_owned(),
+                "10.rebuild-darwin: 5001+".to_owned(),
+                "10.rebuild-darwin: 501-5000".to_owned(),
+                "10.rebuild-linux: 0".to_owned(),
+                "10.rebuild-linux: 1-500".to_owned(),
+                "10.rebuild-linux: 501-5000".to_owned(),
+            ]
+        );
+    }
+
+    #[test]
+    pub fn test_rebuild_tagger_counts_darwin_packages_once() {
+        let mut tagger = RebuildTagger::new();
+        let mut changed = attrs("x86_64-darwin", 8);
+        changed.append(&mut attrs("aarch64-darwin", 12));
+        tagger.parse_attrs(changed);
+
+        assert_eq!(
+            tagger.tags_to_add(),
+            vec![
+                "10.rebuild-linux: 0".to_owned(),
+                "10.rebuild-darwin: 11-100".to_owned(),
+            ]
+        );
+    }
+
+    #[test]
+    pub fn test_rebuild_tagger_treewide() {
+        let mut tagger = RebuildTagger::with_thresholds(vec![500, 10000]);
+        tagger.treewide(vec![tasks::massrebuilder::System::X8664Linux]);
+
+        assert_eq!(
+            tagger.tags_to_add(),
+            vec!["10.rebuild-linux: 10001+".to_owned()]
+        );
+        assert_eq!(
+            tagger.tags_to_remove(),
+            vec![
+                "10.rebuild-linux: 0".to_owned(),
+                "10.rebuild-linux: 1-500".to_owned(),
+                "10.rebuild-linux: 501-10000".to_owned(),
+            ]
+        );
+    }
+
     #[test]
     pub fn test_files_changed_list() {
         let mut criteria: HashMap<String, Vec<String>> = HashMap::new();
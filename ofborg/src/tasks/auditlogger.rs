@@ -0,0 +1,87 @@
+extern crate amqp;
+extern crate env_logger;
+
+use serde_json;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+
+use ofborg::message::audit::{self, AuditEvent};
+use ofborg::worker;
+use amqp::protocol::basic::{Deliver, BasicProperties};
+
+/// Appends every audit event to a file, one JSON object per line, so
+/// operators can grep who started what.
+pub struct AuditLogger {
+    file: File,
+}
+
+impl AuditLogger {
+    pub fn new(path: &str) -> Result<AuditLogger, String> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .map_err(|e| format!("Failed to open {}: {:?}", path, e))?;
+
+        return Ok(AuditLogger { file: file });
+    }
+}
+
+impl worker::SimpleWorker for AuditLogger {
+    type J = AuditEvent;
+
+    fn msg_to_job(
+        &mut self,
+        _: &Deliver,
+        props: &BasicProperties,
+        body: &Vec<u8>,
+    ) -> Result<Self::J, String> {
+        return audit::from(body, worker::content_type(props))
+            .map_err(|e| format!("Failed to decode {:?}", e));
+    }
+
+    fn consumer(&mut self, job: &AuditEvent) -> worker::Actions {
+        let line = serde_json::to_string(job).unwrap();
+        if let Err(e) = writeln!(self.file, "{}", line).and_then(|_| self.file.sync_data()) {
+            // Leave it queued rather than lose it.
+            warn!("Failed to write the audit log: {:?}", e);
+            return vec![worker::Action::NackRequeue];
+        }
+
+        return vec![worker::Action::Ack];
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+    use ofborg::test_scratch::TestScratch;
+    use ofborg::worker::SimpleWorker;
+
+    #[test]
+    fn test_appends() {
+        let scratch = TestScratch::new_file("audit-log");
+        let event = AuditEvent::new(
+            String::from("grahamc"),
+            String::from("build hello --on x86_64-darwin"),
+            String::from("NixOS/nixpkgs"),
+            1,
+            audit::Decision::Allowed,
+            String::from("is Trusted, needs Known"),
+            1,
+        );
+
+        let mut logger = AuditLogger::new(scratch.string().as_ref()).unwrap();
+        logger.consumer(&event);
+        logger.consumer(&event);
+
+        let mut contents = String::new();
+        File::open(scratch.path())
+            .unwrap()
+            .read_to_string(&mut contents)
+            .unwrap();
+        assert_eq!(contents.lines().count(), 2);
+        assert!(contents.starts_with("{\"who\":\"grahamc\""));
+    }
+}
@@ -0,0 +1,306 @@
+extern crate amqp;
+extern crate env_logger;
+
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+use serde_json;
+
+use ofborg::acl;
+use ofborg::config::ReevaluationConfig;
+use ofborg::correlation::{Correlated, Correlation};
+use ofborg::ghevent;
+use ofborg::githubapi::{GithubApi, PullSummary, StatusSummary};
+use ofborg::message::{Repo, Pr, massrebuildjob};
+use ofborg::timestamp;
+use ofborg::worker;
+use amqp::protocol::basic::{Deliver, BasicProperties};
+
+/// How far a base branch moved since its PRs were last re-evaluated.
+#[derive(Debug)]
+struct BranchProgress {
+    commits: u64,
+    since: Instant,
+}
+
+impl BranchProgress {
+    fn new() -> BranchProgress {
+        BranchProgress {
+            commits: 0,
+            since: Instant::now(),
+        }
+    }
+
+    fn is_stale(&self, config: &ReevaluationConfig, now: Instant) -> bool {
+        if self.commits == 0 {
+            return false;
+        }
+
+        let by_commits = config.commits.map_or(false, |limit| self.commits >= limit);
+        let by_time = config.seconds.map_or(false, |limit| {
+            now.duration_since(self.since) >= Duration::from_secs(limit)
+        });
+
+        return by_commits || by_time;
+    }
+}
+
+/// The status whose time tells when a PR was last evaluated.
+const EVAL_CONTEXT: &'static str = "grahamcofborg-eval";
+
+/// Re-queues the open PRs of a base branch for evaluation once enough
+/// commits landed on it, keeping their rebuild labels accurate.
+pub struct BaseBranchWatcher {
+    acl: acl::ACL,
+    github_api: GithubApi,
+    config: ReevaluationConfig,
+    eval_queue: String,
+    progress: HashMap<String, BranchProgress>,
+}
+
+impl BaseBranchWatcher {
+    pub fn new(
+        acl: acl::ACL,
+        github_api: GithubApi,
+        config: ReevaluationConfig,
+        eval_queue: String,
+    ) -> BaseBranchWatcher {
+        return BaseBranchWatcher {
+            acl: acl,
+            github_api: github_api,
+            config: config,
+            eval_queue: eval_queue,
+            progress: HashMap::new(),
+        };
+    }
+
+    /// Record a push, returning true if the branch's PRs are now stale.
+    fn record_push(&mut self, key: String, commits: u64, now: Instant) -> bool {
+        let stale = {
+            let progress = self.progress.entry(key.clone()).or_insert_with(
+                BranchProgress::new,
+            );
+            progress.commits += commits;
+            progress.is_stale(&self.config, now)
+        };
+
+        if stale {
+            self.progress.insert(key, BranchProgress::new());
+        }
+
+        return stale;
+    }
+
+    fn needs_reevaluation(&self, repo: &str, pull: &PullSummary, pushed_at: u64) -> bool {
+        return match self.github_api.commit_statuses(repo, &pull.head.sha) {
+            Ok(statuses) => evaluated_before(&statuses, pushed_at),
+            Err(e) => {
+                info!("Failed to get the statuses of {}: {:?}", pull.number, e);
+                true
+            }
+        };
+    }
+
+    fn reevaluate(
+        &self,
+        job: &Correlated<ghevent::PushEvent>,
+        branch: &str,
+        pushed_at: u64,
+    ) -> worker::Actions {
+        let pulls = match self.github_api.open_pulls(&job.repository.full_name, branch) {
+            Ok(pulls) => pulls,
+            Err(e) => {
+                info!(
+                    "Failed to list open PRs of {} {}: {:?}",
+                    job.repository.full_name,
+                    branch,
+                    e
+                );
+                return vec![];
+            }
+        };
+
+        let open = pulls.len();
+        let pulls: Vec<PullSummary> = pulls
+            .into_iter()
+            .filter(|pull| {
+                self.needs_reevaluation(&job.repository.full_name, pull, pushed_at)
+            })
+            .collect();
+
+        info!(
+            "Re-evaluating {} of {} open PRs against {} {}",
+            pulls.len(),
+            open,
+            job.repository.full_name,
+            branch
+        );
+
+        let repo_msg = Repo {
+            clone_url: job.repository.clone_url.clone(),
+            full_name: job.repository.full_name.clone(),
+            owner: job.repository.owner.login.clone(),
+            name: job.repository.name.clone(),
+        };
+
+        return pulls
+            .into_iter()
+            .map(|pull| {
+                let pr = Pr {
+                    number: pull.number,
+                    head_sha: pull.head.sha,
+                    target_branch: Some(branch.to_owned()),
+                };
+                let msg = massrebuildjob::MassRebuildJob::new(repo_msg.clone(), pr)
+                    .correlated(&job.correlation);
+
+                worker::publish_serde_action(
+                    None,
+                    Some(self.eval_queue.clone()),
+                    &msg,
+                )
+            })
+            .collect();
+    }
+}
+
+impl worker::SimpleWorker for BaseBranchWatcher {
+    type J = Correlated<ghevent::PushEvent>;
+
+    fn msg_to_job(
+        &mut self,
+        _: &Deliver,
+        props: &BasicProperties,
+        body: &Vec<u8>,
+    ) -> Result<Self::J, String> {
+        return match serde_json::from_slice(body) {
+            Ok(e) => Ok(Correlated::new(Correlation::extract(props), e)),
+            Err(e) => {
+                Err(format!(
+                    "Failed to deserialize job {:?}: {:?}",
+                    e,
+                    String::from_utf8(body.clone())
+                ))
+            }
+        };
+    }
+
+    fn consumer(&mut self, job: &Correlated<ghevent::PushEvent>) -> worker::Actions {
+        if !self.acl.is_repo_eligible(&job.repository.full_name) {
+            info!("Repo not authorized ({})", job.repository.full_name);
+            return vec![worker::Action::Ack];
+        }
+
+        let branch = match job.branch() {
+            Some(branch) => branch.to_owned(),
+            None => {
+                info!("Not a branch push: {}", job.git_ref);
+                return vec![worker::Action::Ack];
+            }
+        };
+
+        let pushed_at = timestamp::now();
+        let key = format!("{}/{}", job.repository.full_name, branch);
+        if !self.record_push(key, job.commits.len() as u64, Instant::now()) {
+            return vec![worker::Action::Ack];
+        }
+
+        let mut response = self.reevaluate(job, &branch, pushed_at);
+        response.push(worker::Action::Ack);
+        return response;
+    }
+}
+
+/// Whether the PR's last evaluation finished before the base branch
+/// was pushed to at `pushed_at`, so it didn't see the new commits. PRs
+/// without a finished evaluation are evaluated again to be safe.
+fn evaluated_before(statuses: &[StatusSummary], pushed_at: u64) -> bool {
+    return match statuses.iter().find(|status| status.context == EVAL_CONTEXT) {
+        Some(status) if status.state != "pending" => {
+            timestamp::parse(&status.updated_at).map_or(true, |updated_at| updated_at < pushed_at)
+        }
+        _ => true,
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn watcher(config: ReevaluationConfig) -> BaseBranchWatcher {
+        BaseBranchWatcher::new(
+            acl::ACL::new(vec![], vec![], vec![]),
+            GithubApi::new(String::from("https://api.github.invalid"), String::new()),
+            config,
+            String::from("mass-rebuild-check-jobs"),
+        )
+    }
+
+    fn status(context: &str, state: &str, updated_at: &str) -> StatusSummary {
+        StatusSummary {
+            state: state.to_owned(),
+            context: context.to_owned(),
+            description: None,
+            target_url: None,
+            updated_at: updated_at.to_owned(),
+        }
+    }
+
+    #[test]
+    fn test_evaluated_before() {
+        // 2018-01-08T15:30:00Z
+        let pushed_at = 1515425400;
+
+        assert!(evaluated_before(&[], pushed_at));
+        assert!(evaluated_before(
+            &[status("grahamcofborg-eval", "success", "2018-01-08T15:00:00Z")],
+            pushed_at,
+        ));
+        assert!(!evaluated_before(
+            &[status("grahamcofborg-eval", "success", "2018-01-08T16:00:00Z")],
+            pushed_at,
+        ));
+        assert!(evaluated_before(
+            &[status("grahamcofborg-eval", "pending", "2018-01-08T16:00:00Z")],
+            pushed_at,
+        ));
+        assert!(evaluated_before(
+            &[status("travis-ci", "success", "2018-01-08T16:00:00Z")],
+            pushed_at,
+        ));
+    }
+
+    #[test]
+    fn test_stale_by_commits() {
+        let mut watcher = watcher(ReevaluationConfig {
+            commits: Some(10),
+            seconds: None,
+        });
+        let now = Instant::now();
+
+        assert!(!watcher.record_push(String::from("nixos/nixpkgs/master"), 6, now));
+        assert!(!watcher.record_push(String::from("nixos/nixpkgs/staging"), 6, now));
+        assert!(watcher.record_push(String::from("nixos/nixpkgs/master"), 4, now));
+        assert!(!watcher.record_push(String::from("nixos/nixpkgs/master"), 9, now));
+    }
+
+    #[test]
+    fn test_stale_by_time() {
+        let mut watcher = watcher(ReevaluationConfig {
+            commits: None,
+            seconds: Some(3600),
+        });
+        let now = Instant::now();
+
+        assert!(!watcher.record_push(String::from("nixos/nixpkgs/master"), 1, now));
+        assert!(watcher.record_push(
+            String::from("nixos/nixpkgs/master"),
+            0,
+            now + Duration::from_secs(3601),
+        ));
+        assert!(!watcher.record_push(
+            String::from("nixos/nixpkgs/master"),
+            0,
+            now + Duration::from_secs(7300),
+        ));
+    }
+}
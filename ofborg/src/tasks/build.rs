@@ -5,13 +5,24 @@ extern crate env_logger;
 use uuid::Uuid;
 
 use std::collections::VecDeque;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+use std::cmp::min;
+use std::u16;
+use std::time::Instant;
 use ofborg::asynccmd::AsyncCmd;
+use ofborg::attrpattern;
+use ofborg::cancellation::Cancellations;
 use ofborg::checkout;
 use ofborg::message::buildjob;
-use ofborg::message::buildresult;
+use ofborg::message::buildresult::{self, AttrResult, AttrStatus, LogExcerpt};
 use ofborg::message::buildlogmsg;
+use ofborg::message::envelope::Encoding;
 use ofborg::nix;
+use ofborg::nixpath;
 use ofborg::commentparser;
+use ofborg::stats;
+use ofborg::logging;
 
 use ofborg::worker;
 use ofborg::notifyworker;
@@ -24,6 +35,12 @@ pub struct BuildWorker {
     system: String,
     identity: String,
     full_logs: bool,
+    cancellations: Cancellations,
+    capabilities: nix::Capabilities,
+    pins: Option<nixpath::Pins>,
+    log_encoding: Encoding,
+    legacy_results: bool,
+    builds: Option<stats::Counter>,
 }
 
 impl BuildWorker {
@@ -33,6 +50,8 @@ impl BuildWorker {
         system: String,
         identity: String,
         full_logs: bool,
+        cancellations: Cancellations,
+        capabilities: nix::Capabilities,
     ) -> BuildWorker {
         return BuildWorker {
             cloner: cloner,
@@ -40,15 +59,42 @@ impl BuildWorker {
             system: system,
             identity: identity,
             full_logs: full_logs,
+            cancellations: cancellations,
+            capabilities: capabilities,
+            pins: None,
+            log_encoding: Encoding::Json,
+            legacy_results: false,
+            builds: None,
         };
     }
 
+    /// Build what isn't the pinned repository with the pinned nixpkgs.
+    pub fn with_pins(mut self, pins: Option<nixpath::Pins>) -> BuildWorker {
+        self.pins = pins;
+        return self;
+    }
+
+    /// Send the log lines encoded like so.
+    pub fn with_log_encoding(mut self, encoding: Encoding) -> BuildWorker {
+        self.log_encoding = encoding;
+        return self;
+    }
+
+    /// Publish results as version 1, for consumers which don't read the
+    /// results of each attr yet.
+    pub fn with_legacy_results(mut self, legacy_results: bool) -> BuildWorker {
+        self.legacy_results = legacy_results;
+        return self;
+    }
+
     fn actions<'a, 'b>(
         &self,
         job: &'b buildjob::BuildJob,
         receiver: &'a mut notifyworker::NotificationReceiver,
     ) -> JobActions<'a, 'b> {
         JobActions::new(&self.system, &self.identity, job, receiver)
+            .with_log_encoding(self.log_encoding)
+            .with_legacy_results(self.legacy_results)
     }
 }
 
@@ -61,8 +107,10 @@ pub struct JobActions<'a, 'b> {
     attempt_id: String,
     log_exchange: Option<String>,
     log_routing_key: Option<String>,
+    log_encoding: Encoding,
     result_exchange: Option<String>,
     result_routing_key: Option<String>,
+    legacy_results: bool,
 }
 
 impl<'a, 'b> JobActions<'a, 'b> {
@@ -92,11 +140,23 @@ impl<'a, 'b> JobActions<'a, 'b> {
             attempt_id: format!("{}", Uuid::new_v4()),
             log_exchange: log_exchange,
             log_routing_key: log_routing_key,
+            log_encoding: Encoding::Json,
             result_exchange: result_exchange,
             result_routing_key: result_routing_key,
+            legacy_results: false,
         };
     }
 
+    pub fn with_log_encoding(mut self, encoding: Encoding) -> JobActions<'a, 'b> {
+        self.log_encoding = encoding;
+        return self;
+    }
+
+    pub fn with_legacy_results(mut self, legacy_results: bool) -> JobActions<'a, 'b> {
+        self.legacy_results = legacy_results;
+        return self;
+    }
+
     pub fn commit_missing(&mut self) {
         self.tell(worker::Action::Ack);
     }
@@ -109,38 +169,75 @@ impl<'a, 'b> JobActions<'a, 'b> {
         self.tell(worker::Action::Ack);
     }
 
-    pub fn merge_failed(&mut self) {
-        let msg = buildresult::BuildResult {
-            repo: self.job.repo.clone(),
-            pr: self.job.pr.clone(),
-            system: self.system.clone(),
-            output: vec![String::from("Merge failed")],
-            attempt_id: self.attempt_id.clone(),
-            attempted_attrs: None,
-            skipped_attrs: None,
-            success: Some(false),
-        };
+    pub fn cancelled(&mut self) {
+        let attrs = self.attrs_with_status(&self.job.attrs, AttrStatus::Cancelled);
+        let msg = self.result(vec![String::from("Cancelled by request")], attrs, None);
+        self.publish_result(&msg, false);
+        self.tell(worker::Action::Ack);
+    }
+
+    /// The PR's head couldn't be fetched, or isn't the commit the job
+    /// is for anymore.
+    pub fn fetch_failed(&mut self, reason: &str) {
+        let attrs = self.attrs_with_status(&self.job.attrs, AttrStatus::Skipped);
+        let msg = self.result(
+            vec![format!("Not building, fetching the PR failed: {}", reason)],
+            attrs,
+            None,
+        );
+        self.publish_result(&msg, false);
+        self.tell(worker::Action::Ack);
+    }
 
-        let result_exchange = self.result_exchange.clone();
-        let result_routing_key = self.result_routing_key.clone();
+    pub fn expansion_failed(&mut self, reason: &str) {
+        let attrs = self.attrs_with_status(&self.job.attrs, AttrStatus::Skipped);
+        let msg = self.result(
+            vec![format!("Not building, expanding the attributes failed: {}", reason)],
+            attrs,
+            None,
+        );
+        self.publish_result(&msg, false);
+        self.tell(worker::Action::Ack);
+    }
 
+    pub fn missing_capabilities(&mut self, missing: Vec<String>) {
+        let attrs = self.attrs_with_status(&self.job.attrs, AttrStatus::Skipped);
+        let msg = self.result(
+            vec![format!(
+                "Not building, this builder's nix lacks: {}",
+                missing.join(", ")
+            )],
+            attrs,
+            None,
+        );
+        self.publish_result(&msg, false);
+        self.tell(worker::Action::Ack);
+    }
 
-        self.tell(worker::publish_serde_action(
-            result_exchange,
-            result_routing_key,
-            &msg,
-        ));
+    pub fn merge_failed(&mut self) {
+        let attrs = self.attrs_with_status(&self.job.attrs, AttrStatus::MergeFailed);
+        let mut msg = self.result(vec![String::from("Merge failed")], attrs, None);
+        msg.merge_failed = true;
+        self.publish_result(&msg, false);
         self.tell(worker::Action::Ack);
     }
 
-    pub fn log_started(&mut self, can_build: Vec<String>, cannot_build: Vec<String>) {
-        let msg = buildlogmsg::BuildLogStart {
-            identity: self.identity.clone(),
-            system: self.system.clone(),
-            attempt_id: self.attempt_id.clone(),
-            attempted_attrs: Some(can_build),
-            skipped_attrs: Some(cannot_build),
-        };
+    pub fn log_started(
+        &mut self,
+        can_build: Vec<String>,
+        cannot_build: Vec<String>,
+        capabilities: nix::Capabilities,
+        pin: Option<nixpath::Pin>,
+    ) {
+        let msg = buildlogmsg::BuildLogStart::new(
+            self.system.clone(),
+            self.identity.clone(),
+            self.attempt_id.clone(),
+            can_build,
+            cannot_build,
+        ).with_capabilities(capabilities)
+            .with_pin(pin)
+            .correlated(&self.job.correlation());
 
         let log_exchange = self.log_exchange.clone();
         let log_routing_key = self.log_routing_key.clone();
@@ -155,90 +252,125 @@ impl<'a, 'b> JobActions<'a, 'b> {
     pub fn log_line(&mut self, line: &str) {
         self.line_counter += 1;
 
-        let msg = buildlogmsg::BuildLogMsg {
-            identity: self.identity.clone(),
-            system: self.system.clone(),
-            attempt_id: self.attempt_id.clone(),
-            line_number: self.line_counter,
-            output: line.to_owned(),
-        };
+        let msg = buildlogmsg::BuildLogMsg::new(
+            self.system.clone(),
+            self.identity.clone(),
+            self.attempt_id.clone(),
+            self.line_counter,
+            line.to_owned(),
+        ).correlated(&self.job.correlation());
 
         let log_exchange = self.log_exchange.clone();
         let log_routing_key = self.log_routing_key.clone();
+        let log_encoding = self.log_encoding;
 
-        self.tell(worker::publish_serde_action(
+        self.tell(worker::publish_encoded_action(
             log_exchange,
             log_routing_key,
             &msg,
+            log_encoding,
         ));
     }
 
-    pub fn build_not_attempted(&mut self, not_attempted_attrs: Vec<String>,
+    pub fn build_not_attempted(&mut self, not_attempted_attrs: Vec<String>) {
+        let attrs = self.attrs_with_status(&not_attempted_attrs, AttrStatus::Skipped);
+        let msg = self.result(vec![], attrs, None);
+        self.publish_result(&msg, true);
+        self.tell(worker::Action::Ack);
+    }
 
-    ) {
-        let msg = buildresult::BuildResult {
-            repo: self.job.repo.clone(),
-            pr: self.job.pr.clone(),
-            system: self.system.clone(),
-            output: vec![],
-            attempt_id: self.attempt_id.clone(),
-            skipped_attrs: Some(not_attempted_attrs),
-            attempted_attrs: None,
-            success: None,
+    /// Tell the stats collector how long building `attrs` took, and
+    /// who to account it to.
+    pub fn build_stats(&mut self, attrs: &[String], duration_secs: u64) {
+        let repo = self.job.repo.full_name.clone();
+        let user = self.job.requested_by.clone().unwrap_or(String::from("unknown"));
+        let msg = stats::EventMessage {
+            sender: self.identity.clone(),
+            events: vec![
+                stats::Event::BuildDuration(
+                    self.system.clone(),
+                    stats::attr_prefix(attrs),
+                    duration_secs
+                ),
+                stats::Event::UsageBuildRequested(repo.clone(), user.clone()),
+                stats::Event::UsageBuilderSeconds(repo, user, duration_secs),
+            ],
         };
 
-        let result_exchange = self.result_exchange.clone();
-        let result_routing_key = self.result_routing_key.clone();
         self.tell(worker::publish_serde_action(
-            result_exchange,
-            result_routing_key,
-            &msg,
-        ));
-
-        let log_exchange = self.log_exchange.clone();
-        let log_routing_key = self.log_routing_key.clone();
-        self.tell(worker::publish_serde_action(
-            log_exchange,
-            log_routing_key,
+            Some("stats".to_owned()),
+            None,
             &msg,
         ));
+    }
 
+    pub fn build_finished(&mut self, lines: Vec<String>, attrs: Vec<buildresult::AttrResult>,
+                          realisations: Option<Vec<nix::Realisation>>,
+    ) {
+        let msg = self.result(lines, attrs, realisations);
+        self.publish_result(&msg, true);
         self.tell(worker::Action::Ack);
     }
 
-    pub fn build_finished(&mut self, success: bool, lines: Vec<String>,
-                          attempted_attrs: Vec<String>,
-                          not_attempted_attrs: Vec<String>,
+    /// The line the next log line will be.
+    pub fn next_log_line(&self) -> u64 {
+        return self.line_counter + 1;
+    }
 
-    ) {
-        let msg = buildresult::BuildResult {
+    /// The log lines sent since `first_line`, `None` if there were none.
+    pub fn log_since(&self, first_line: u64) -> Option<LogExcerpt> {
+        if self.line_counter < first_line {
+            return None;
+        }
+        return Some(LogExcerpt {
+            first_line: first_line,
+            last_line: self.line_counter,
+        });
+    }
+
+    fn attrs_with_status(&self, attrs: &[String], status: AttrStatus) -> Vec<AttrResult> {
+        return attrs
+            .iter()
+            .map(|attr| AttrResult::new(attr.clone(), status))
+            .collect();
+    }
+
+    fn result(&self, output: Vec<String>, attrs: Vec<AttrResult>,
+              realisations: Option<Vec<nix::Realisation>>,
+    ) -> buildresult::BuildResult {
+        return buildresult::BuildResult {
             repo: self.job.repo.clone(),
             pr: self.job.pr.clone(),
             system: self.system.clone(),
-            output: lines,
+            output: output,
             attempt_id: self.attempt_id.clone(),
-            success: Some(success),
-            attempted_attrs: Some(attempted_attrs),
-            skipped_attrs: Some(not_attempted_attrs),
+            attrs: attrs,
+            merge_failed: false,
+            passed_without_attrs: false,
+            realisations: realisations,
+            correlation_id: self.job.correlation_id.clone(),
+            caused_by: self.job.caused_by.clone(),
         };
+    }
 
-        let result_exchange = self.result_exchange.clone();
-        let result_routing_key = self.result_routing_key.clone();
-        self.tell(worker::publish_serde_action(
-            result_exchange,
-            result_routing_key,
-            &msg,
-        ));
-
-        let log_exchange = self.log_exchange.clone();
-        let log_routing_key = self.log_routing_key.clone();
-        self.tell(worker::publish_serde_action(
-            log_exchange,
-            log_routing_key,
-            &msg,
-        ));
+    /// Publish `msg` to the PR's results, and the log collector too
+    /// when `to_logs`, as version 1 if the consumers aren't upgraded.
+    fn publish_result(&mut self, msg: &buildresult::BuildResult, to_logs: bool) {
+        let mut destinations = vec![
+            (self.result_exchange.clone(), self.result_routing_key.clone()),
+        ];
+        if to_logs {
+            destinations.push((self.log_exchange.clone(), self.log_routing_key.clone()));
+        }
 
-        self.tell(worker::Action::Ack);
+        for (exchange, routing_key) in destinations {
+            let action = if self.legacy_results {
+                worker::publish_serde_action(exchange, routing_key, &msg.legacy())
+            } else {
+                worker::publish_serde_action(exchange, routing_key, msg)
+            };
+            self.tell(action);
+        }
     }
 
     fn tell(&mut self, action: worker::Action) {
@@ -249,17 +381,24 @@ impl<'a, 'b> JobActions<'a, 'b> {
 impl notifyworker::SimpleNotifyWorker for BuildWorker {
     type J = buildjob::BuildJob;
 
+    fn register_metrics(&mut self, registry: &stats::Registry) {
+        self.builds = Some(registry.counter(
+            "ofborg_builds_total",
+            "Builds run, by their result",
+        ));
+    }
+
     fn msg_to_job(
         &self,
         _: &Deliver,
-        _: &BasicProperties,
+        props: &BasicProperties,
         body: &Vec<u8>,
     ) -> Result<Self::J, String> {
-        println!("lmao I got a job?");
-        return match buildjob::from(body) {
+        debug!("Got a build job");
+        return match buildjob::from(body, worker::content_type(props)) {
             Ok(e) => Ok(e),
             Err(e) => {
-                println!("{:?}", String::from_utf8(body.clone()));
+                error!("Failed to decode the build job {:?}", String::from_utf8(body.clone()));
                 panic!("{:?}", e);
             }
         };
@@ -271,12 +410,36 @@ impl notifyworker::SimpleNotifyWorker for BuildWorker {
         notifier: &mut notifyworker::NotificationReceiver,
     ) {
         let mut actions = self.actions(&job, notifier);
+        let pr = format!("{}#{}", job.repo.full_name, job.pr.number);
+        let _fields = logging::fields(&[
+            ("attempt_id", actions.attempt_id.as_str()),
+            ("pr", pr.as_str()),
+        ]);
 
         if job.attrs.len() == 0 {
             actions.nothing_to_do();
             return;
         }
 
+        if job.flags.on.len() > 0 && !job.flags.on.contains(&self.system) {
+            info!("Not building {} on {}, only on {:?}", job.pr.number, self.system, job.flags.on);
+            actions.nothing_to_do();
+            return;
+        }
+
+        if self.cancellations.is_cancelled(&job.repo, &job.pr) {
+            info!("Skipping {}, it was cancelled", job.pr.number);
+            actions.cancelled();
+            return;
+        }
+
+        let missing = self.capabilities.missing(&job.requirements());
+        if missing.len() > 0 {
+            info!("Not building {}, missing {:?}", job.pr.number, missing);
+            actions.missing_capabilities(missing);
+            return;
+        }
+
         info!("Working on {}", job.pr.number);
         let project = self.cloner.project(
             job.repo.full_name.clone(),
@@ -291,6 +454,14 @@ impl notifyworker::SimpleNotifyWorker for BuildWorker {
             None => String::from("origin/master"),
         };
 
+        let pin = match self.pins {
+            Some(ref pins) if !pins.is_pinned_repo(&job.repo.full_name) => {
+                pins.get(&target_branch)
+            }
+            _ => None,
+        };
+        let pinned_nix = self.nix.with_pin(pin.clone());
+
         let buildfile = match job.subset {
             Some(commentparser::Subset::NixOS) => "./nixos/release.nix",
             _ => "./default.nix",
@@ -302,7 +473,11 @@ impl notifyworker::SimpleNotifyWorker for BuildWorker {
         }
 
         let refpath = co.checkout_origin_ref(target_branch.as_ref()).unwrap();
-        co.fetch_pr(job.pr.number).unwrap();
+        if let Err(e) = co.fetch_pr(job.pr.number, &job.pr.head_sha) {
+            warn!("Not building {}: {}", job.pr.head_sha, e);
+            actions.fetch_failed(&e.to_string());
+            return;
+        }
 
         if !co.commit_exists(job.pr.head_sha.as_ref()) {
             info!("Commit {} doesn't exist", job.pr.head_sha);
@@ -316,38 +491,79 @@ impl notifyworker::SimpleNotifyWorker for BuildWorker {
             return;
         }
 
-        println!("Got path: {:?}, determining which ones we can build ", refpath);
-        let (can_build, cannot_build) = self.nix.safely_partition_instantiable_attrs(
+        let expansion = attrpattern::expand(
+            job.attrs.clone(),
+            attrpattern::MAX_EXPANSION,
+            attrpattern::MAX_LOOKUPS,
+            |path| pinned_nix.attr_names(Path::new(&refpath), buildfile, path),
+        );
+        let expansion = match expansion {
+            Ok(expansion) => expansion,
+            Err(e) => {
+                info!("Not building {}: {}", job.pr.head_sha, e);
+                actions.expansion_failed(&e);
+                return;
+            }
+        };
+        for pattern in expansion.truncated.iter() {
+            info!(
+                "{} matches more than {} attributes, only building the first",
+                pattern,
+                attrpattern::MAX_EXPANSION
+            );
+        }
+
+        debug!("Got path: {:?}, determining which ones we can build", refpath);
+        let (can_build, mut cannot_build) = pinned_nix.safely_partition_instantiable_attrs(
             refpath.as_ref(),
             buildfile,
-            job.attrs.clone(),
+            expansion.attrs,
         );
+        // Note the patterns which weren't built in full.
+        cannot_build.extend(expansion.truncated);
 
-        println!("Can build: {}, Cannot build: {}",
-                 can_build.join(", "),
-                 cannot_build.join(", "));
+        info!("Can build: {}, Cannot build: {}",
+              can_build.join(", "),
+              cannot_build.join(", "));
 
         if can_build.len() == 0 {
             actions.build_not_attempted(cannot_build);
             return;
         }
 
-        let cmd = self.nix.safely_build_attrs_cmd(
+        let nix = match job.flags.timeout {
+            Some(timeout) => pinned_nix.with_build_timeout(min(timeout, u16::MAX as u64) as u16),
+            None => pinned_nix.clone(),
+        };
+        let mut cmd = nix.safely_build_attrs_cmd(
             refpath.as_ref(),
             buildfile,
             can_build.clone(),
         );
+        if job.flags.check {
+            cmd.arg("--check");
+        }
 
-        actions.log_started(can_build.clone(), cannot_build.clone());
+        actions.log_started(
+            can_build.clone(),
+            cannot_build.clone(),
+            self.capabilities.clone(),
+            pin,
+        );
+        let started = Instant::now();
         let acmd = AsyncCmd::new(cmd);
-        let mut spawned = acmd.spawn();
+        let spawned = acmd.spawn();
 
         let mut snippet_log = VecDeque::with_capacity(10);
+        let mut cancelled = false;
+        let mut cached_failures: Vec<String> = vec![];
+        let mut out_paths: Vec<String> = vec![];
 
 
         if !self.full_logs {
             actions.log_line("Full logs are disabled on this builder.");
         }
+        let first_log_line = actions.next_log_line();
 
         for line in spawned.lines().iter() {
             if self.full_logs {
@@ -359,26 +575,139 @@ impl notifyworker::SimpleNotifyWorker for BuildWorker {
             }
 
             snippet_log.push_back(line.to_owned());
+
+            if let Some(path) = out_path(&line) {
+                out_paths.push(path);
+            }
+
+            if let Some(drv) = cached_failure(&line) {
+                if !cached_failures.contains(&drv) {
+                    cached_failures.push(drv);
+                }
+            }
+
+            if !cancelled && self.cancellations.is_cancelled(&job.repo, &job.pr) {
+                info!("Stopping the build of {}, it was cancelled", job.pr.number);
+                spawned.kill();
+                cancelled = true;
+            }
+        }
+
+        if cancelled {
+            let _ = spawned.wait();
+            if let Some(ref builds) = self.builds {
+                builds.inc(&[("result", "cancelled")]);
+            }
+            actions.cancelled();
+            return;
         }
 
         let success = match spawned.wait() {
             Ok(Some(Ok(status))) => status.success(),
             e => {
-                println!("Failed on the interior command: {:?}", e);
+                warn!("Failed on the interior command: {:?}", e);
                 false
             }
         };
+        let duration_secs = started.elapsed().as_secs();
+        actions.build_stats(&can_build, duration_secs);
+
+        // The build failed without running, so its log only is where
+        // it failed before.
+        if !success {
+            for drv in cached_failures {
+                actions.log_line(&format!("ofborg: {} failed before, its log:", drv));
+                let lines: Vec<String> = match nix.log(&drv) {
+                    Ok(log) => BufReader::new(log).lines().filter_map(|l| l.ok()).collect(),
+                    Err(_) => vec![format!("ofborg: no log of {} is available", drv)],
+                };
+
+                for line in lines {
+                    if self.full_logs {
+                        actions.log_line(&line);
+                    }
+                    if snippet_log.len() >= 10 {
+                        snippet_log.pop_front();
+                    }
+                    snippet_log.push_back(line);
+                }
+            }
+        }
+
+        if let Some(ref builds) = self.builds {
+            builds.inc(&[("result", if success { "success" } else { "failure" })]);
+        }
 
-        println!("ok built ({:?}), building", success);
-        println!("Lines: {:?}", snippet_log);
+        info!("Built, success: {:?}", success);
+        debug!("Lines: {:?}", snippet_log);
 
         let last10lines: Vec<String> = snippet_log.into_iter().collect::<Vec<String>>();
 
-        actions.build_finished(success, last10lines.clone(), can_build, cannot_build);
-        println!("Done!");
+        let ca_derivations = self.capabilities.experimental_features.iter().any(
+            |feature| feature == "ca-derivations",
+        );
+        let realisations = if success && ca_derivations {
+            match nix.realisations(refpath.as_ref(), buildfile, can_build.clone()) {
+                Ok(realisations) => Some(realisations),
+                Err(_) => {
+                    info!("Failed to look up the realisations of {:?}", can_build);
+                    None
+                }
+            }
+        } else {
+            None
+        };
+
+        // nix-build prints the outputs of the attrs in order, if they
+        // each have just the one.
+        let one_output_each = success && out_paths.len() == can_build.len();
+        let log = if self.full_logs {
+            actions.log_since(first_log_line)
+        } else {
+            None
+        };
+        let mut attrs: Vec<AttrResult> = can_build
+            .into_iter()
+            .enumerate()
+            .map(|(i, attr)| AttrResult {
+                attr: attr,
+                status: if success { AttrStatus::Success } else { AttrStatus::Failure },
+                duration_secs: Some(duration_secs),
+                out_paths: if one_output_each { vec![out_paths[i].clone()] } else { vec![] },
+                log: log,
+            })
+            .collect();
+        attrs.extend(cannot_build.into_iter().map(
+            |attr| AttrResult::new(attr, AttrStatus::Skipped),
+        ));
+
+        actions.build_finished(last10lines.clone(), attrs, realisations);
+        debug!("Done!");
+    }
+}
+
+/// The derivation of a line saying it failed before and wasn't built
+/// again, like `error: build of '/nix/store/...-hello.drv' previously
+/// failed`.
+fn cached_failure(line: &str) -> Option<String> {
+    if !line.contains("cached failure") && !line.contains("previously failed") {
+        return None;
     }
+
+    let start = line.find("/nix/store/")?;
+    let end = line[start..].find(".drv")? + start + ".drv".len();
+    return Some(line[start..end].to_owned());
 }
 
+/// The store path a line is, like nix-build prints for each output it
+/// built.
+fn out_path(line: &str) -> Option<String> {
+    let line = line.trim();
+    if !line.starts_with("/nix/store/") || line.contains(char::is_whitespace) {
+        return None;
+    }
+    return Some(line.to_owned());
+}
 
 #[cfg(test)]
 mod tests {
@@ -407,6 +736,8 @@ mod tests {
             "x86_64-linux".to_owned(),
             "cargo-test-build".to_owned(),
             true,
+            Cancellations::new(),
+            nix::Capabilities::parse("nix (Nix) 2.4", "sandbox = true\n"),
         );
 
         return worker;
@@ -450,6 +781,31 @@ mod tests {
             ));
     }
 
+    #[test]
+    fn test_cached_failure() {
+        assert_eq!(
+            cached_failure("error: build of '/nix/store/abc-hello.drv' previously failed"),
+            Some(String::from("/nix/store/abc-hello.drv"))
+        );
+        assert_eq!(
+            cached_failure("error: cached failure of \u{2018}/nix/store/abc-hello.drv\u{2019}"),
+            Some(String::from("/nix/store/abc-hello.drv"))
+        );
+        assert_eq!(
+            cached_failure("builder for '/nix/store/abc-hello.drv' failed with exit code 1"),
+            None
+        );
+    }
+
+    #[test]
+    fn test_out_path() {
+        assert_eq!(
+            out_path("/nix/store/abc-hello-2.10"),
+            Some(String::from("/nix/store/abc-hello-2.10"))
+        );
+        assert_eq!(out_path("building '/nix/store/abc-hello.drv'..."), None);
+    }
+
     #[test]
     pub fn test_simple_build() {
         let p = TestScratch::new_dir("build-simple-build-working");
@@ -478,6 +834,11 @@ mod tests {
                 Some(String::from("build.log")),
             )),
             statusreport: Some((Some(String::from("build-results")), None)),
+            flags: commentparser::BuildFlags::default(),
+            requires: vec![],
+            requested_by: None,
+            correlation_id: None,
+            caused_by: None,
         };
 
         let mut dummyreceiver = notifyworker::DummyNotificationReceiver::new();
@@ -492,8 +853,9 @@ mod tests {
         assert_contains_job(&mut actions, "output\":\"2");
         assert_contains_job(&mut actions, "output\":\"3");
         assert_contains_job(&mut actions, "output\":\"4");
-        assert_contains_job(&mut actions, "success\":true"); // First one to the github poster
-        assert_contains_job(&mut actions, "success\":true"); // This one to the logs
+        assert_contains_job(&mut actions, "build-duration\":[\"x86_64-linux\",\"top-level\"");
+        assert_contains_job(&mut actions, "status\":\"success"); // First one to the github poster
+        assert_contains_job(&mut actions, "status\":\"success"); // This one to the logs
         assert_eq!(actions.next(), Some(worker::Action::Ack));
     }
 
@@ -526,6 +888,11 @@ mod tests {
                 Some(String::from("build.log")),
             )),
             statusreport: Some((Some(String::from("build-results")), None)),
+            flags: commentparser::BuildFlags::default(),
+            requires: vec![],
+            requested_by: None,
+            correlation_id: None,
+            caused_by: None,
         };
 
         let mut dummyreceiver = notifyworker::DummyNotificationReceiver::new();
@@ -534,8 +901,8 @@ mod tests {
 
         println!("Total actions: {:?}", dummyreceiver.actions.len());
         let mut actions = dummyreceiver.actions.into_iter();
-        assert_contains_job(&mut actions, "skipped_attrs\":[\"not-real"); // First one to the github poster
-        assert_contains_job(&mut actions, "skipped_attrs\":[\"not-real"); // This one to the logs
+        assert_contains_job(&mut actions, "not-real\",\"status\":\"skipped"); // First one to the github poster
+        assert_contains_job(&mut actions, "not-real\",\"status\":\"skipped"); // This one to the logs
         assert_eq!(actions.next(), Some(worker::Action::Ack));
     }
 }
@@ -0,0 +1,337 @@
+extern crate amqp;
+extern crate env_logger;
+
+use ofborg::ghevent;
+use ofborg::acl;
+use ofborg::correlation::{Correlated, Correlation};
+use serde_json;
+
+use ofborg::commentparser::Subset;
+use ofborg::message::{Repo, Pr, buildjob, massrebuildjob};
+use ofborg::worker;
+use amqp::protocol::basic::{Deliver, BasicProperties};
+
+/// Re-runs our checks when they're re-run from the GitHub UI: a whole
+/// check suite, or a single check run.
+pub struct CheckRunFilterWorker {
+    acl: acl::ACL,
+    eval_queue: String,
+}
+
+#[derive(Debug)]
+pub enum CheckEvent {
+    Run(ghevent::CheckRunEvent),
+    Suite(ghevent::CheckSuiteEvent),
+}
+
+/// The task behind a check run, stored in its `external_id` so it can
+/// be started again.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub enum RerunTarget {
+    Evaluation,
+    Build(Subset, Vec<String>),
+}
+
+impl RerunTarget {
+    pub fn external_id(&self) -> String {
+        serde_json::to_string(self).unwrap()
+    }
+
+    pub fn for_check_run(run: &ghevent::CheckRun) -> Option<RerunTarget> {
+        if let Some(ref external_id) = run.external_id {
+            if let Ok(target) = serde_json::from_str(external_id) {
+                return Some(target);
+            }
+        }
+
+        // Evaluation check runs predate external ids.
+        if run.name.starts_with("grahamcofborg-eval") {
+            return Some(RerunTarget::Evaluation);
+        }
+
+        return None;
+    }
+}
+
+impl CheckRunFilterWorker {
+    pub fn new(acl: acl::ACL, eval_queue: String) -> CheckRunFilterWorker {
+        return CheckRunFilterWorker {
+            acl: acl,
+            eval_queue: eval_queue,
+        };
+    }
+
+    fn rerun(
+        &self,
+        target: &RerunTarget,
+        repository: &ghevent::Repository,
+        sender: &ghevent::User,
+        head_sha: &str,
+        pull_requests: &Vec<ghevent::CheckRunPullRequest>,
+        correlation: &Correlation,
+    ) -> worker::Actions {
+        let repo_msg = Repo {
+            clone_url: repository.clone_url.clone(),
+            full_name: repository.full_name.clone(),
+            owner: repository.owner.login.clone(),
+            name: repository.name.clone(),
+        };
+
+        let build_destinations = self.acl.build_job_destinations_for_user_repo(
+            &sender.login,
+            &repository.full_name,
+        );
+
+        let mut response: worker::Actions = vec![];
+        for pr in pull_requests.iter().filter(|pr| pr.head.sha == head_sha) {
+            info!(
+                "Re-running {:?} of {}#{} on request of {}",
+                target,
+                repository.full_name,
+                pr.number,
+                sender.login
+            );
+
+            let pr_msg = Pr {
+                number: pr.number,
+                head_sha: pr.head.sha.clone(),
+                target_branch: Some(pr.base.git_ref.clone()),
+            };
+
+            match target {
+                &RerunTarget::Evaluation => {
+                    let msg = massrebuildjob::MassRebuildJob::new(repo_msg.clone(), pr_msg)
+                        .correlated(correlation);
+
+                    response.push(worker::publish_serde_action(
+                        None,
+                        Some(self.eval_queue.clone()),
+                        &msg,
+                    ));
+                }
+                &RerunTarget::Build(ref subset, ref attrs) => {
+                    if build_destinations.len() == 0 {
+                        info!("{} may not start builds", sender.login);
+                        continue;
+                    }
+
+                    let msg = buildjob::BuildJob::new(
+                        repo_msg.clone(),
+                        pr_msg,
+                        subset.clone(),
+                        attrs.clone(),
+                        None,
+                        None,
+                    ).requested_by(&sender.login)
+                        .correlated(correlation);
+
+                    for (exch, rk) in build_destinations.clone() {
+                        response.push(worker::publish_serde_action(exch, rk, &msg));
+                    }
+                }
+            }
+        }
+
+        return response;
+    }
+}
+
+impl worker::SimpleWorker for CheckRunFilterWorker {
+    type J = Correlated<CheckEvent>;
+
+    fn msg_to_job(
+        &mut self,
+        method: &Deliver,
+        props: &BasicProperties,
+        body: &Vec<u8>,
+    ) -> Result<Self::J, String> {
+        let event = if method.routing_key.starts_with("check_suite.") {
+            serde_json::from_slice(body).map(CheckEvent::Suite)
+        } else {
+            serde_json::from_slice(body).map(CheckEvent::Run)
+        };
+
+        let event = event.map_err(|e| {
+            format!(
+                "Failed to deserialize job {:?}: {:?}",
+                e,
+                String::from_utf8(body.clone())
+            )
+        })?;
+
+        return Ok(Correlated::new(Correlation::extract(props), event));
+    }
+
+    fn consumer(&mut self, job: &Correlated<CheckEvent>) -> worker::Actions {
+        let mut response = match job.event {
+            CheckEvent::Run(ref event) => self.consume_run(event, &job.correlation),
+            CheckEvent::Suite(ref event) => self.consume_suite(event, &job.correlation),
+        };
+
+        response.push(worker::Action::Ack);
+        return response;
+    }
+}
+
+impl CheckRunFilterWorker {
+    fn consume_run(
+        &self,
+        job: &ghevent::CheckRunEvent,
+        correlation: &Correlation,
+    ) -> worker::Actions {
+        if !self.acl.is_repo_eligible(&job.repository.full_name) {
+            info!("Repo not authorized ({})", job.repository.full_name);
+            return vec![];
+        }
+
+        let target = RerunTarget::for_check_run(&job.check_run);
+        match target {
+            Some(ref target) if job.action == ghevent::CheckRunAction::Rerequested => {
+                self.rerun(
+                    target,
+                    &job.repository,
+                    &job.sender,
+                    &job.check_run.head_sha,
+                    &job.check_run.pull_requests,
+                    correlation,
+                )
+            }
+            _ => {
+                info!(
+                    "Not interesting: {} {:?} on {}",
+                    job.check_run.name,
+                    job.action,
+                    job.repository.full_name
+                );
+                vec![]
+            }
+        }
+    }
+
+    /// Re-running the whole suite re-evaluates the PR, which schedules
+    /// its builds again too.
+    fn consume_suite(
+        &self,
+        job: &ghevent::CheckSuiteEvent,
+        correlation: &Correlation,
+    ) -> worker::Actions {
+        if !self.acl.is_repo_eligible(&job.repository.full_name) {
+            info!("Repo not authorized ({})", job.repository.full_name);
+            return vec![];
+        }
+
+        if job.action != ghevent::CheckSuiteAction::Rerequested {
+            info!(
+                "Not interesting: check suite {:?} on {}",
+                job.action,
+                job.repository.full_name
+            );
+            return vec![];
+        }
+
+        return self.rerun(
+            &RerunTarget::Evaluation,
+            &job.repository,
+            &job.sender,
+            &job.check_suite.head_sha,
+            &job.check_suite.pull_requests,
+            correlation,
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use worker::SimpleWorker;
+    use super::*;
+
+    fn eval_job() -> worker::Action {
+        worker::publish_serde_action(
+            None,
+            Some("mass-rebuild-check-jobs".to_owned()),
+            &massrebuildjob::MassRebuildJob {
+                repo: Repo {
+                    clone_url: String::from("https://github.com/NixOS/nixpkgs.git"),
+                    full_name: String::from("NixOS/nixpkgs"),
+                    owner: String::from("NixOS"),
+                    name: String::from("nixpkgs"),
+                },
+                pr: Pr {
+                    number: 33299,
+                    head_sha: String::from("887e8b460a7d45ddb3bbdebe01447b251b3229e8"),
+                    target_branch: Some(String::from("staging")),
+                },
+                memory_retries: 0,
+                correlation_id: None,
+                caused_by: None,
+            },
+        )
+    }
+
+    fn worker() -> CheckRunFilterWorker {
+        CheckRunFilterWorker::new(
+            acl::ACL::new(
+                vec!["nixos/nixpkgs".to_owned()],
+                vec![],
+                vec!["example".to_owned()],
+            ),
+            String::from("mass-rebuild-check-jobs"),
+        )
+    }
+
+    #[test]
+    fn rerequested() {
+        let data = include_str!("../../test-srcs/events/check-run-rerequested.json");
+
+        let job: ghevent::CheckRunEvent = serde_json::from_str(&data.to_string())
+            .expect("Should properly deserialize");
+
+        assert_eq!(
+            worker().consumer(&CheckEvent::Run(job).into()),
+            vec![eval_job(), worker::Action::Ack]
+        );
+    }
+
+    #[test]
+    fn suite_rerequested() {
+        let data = include_str!("../../test-srcs/events/check-suite-rerequested.json");
+
+        let job: ghevent::CheckSuiteEvent = serde_json::from_str(&data.to_string())
+            .expect("Should properly deserialize");
+
+        assert_eq!(
+            worker().consumer(&CheckEvent::Suite(job).into()),
+            vec![eval_job(), worker::Action::Ack]
+        );
+    }
+
+    #[test]
+    fn build_rerequested() {
+        let data = include_str!("../../test-srcs/events/check-run-rerequested.json");
+
+        let mut job: ghevent::CheckRunEvent = serde_json::from_str(&data.to_string())
+            .expect("Should properly deserialize");
+        job.check_run.name = String::from("grahamcofborg-build-x86_64-linux");
+        job.check_run.external_id = Some(
+            RerunTarget::Build(Subset::Nixpkgs, vec![String::from("hello")]).external_id(),
+        );
+
+        let actions = worker().consumer(&CheckEvent::Run(job).into());
+        assert_eq!(actions.len(), 3);
+        assert_eq!(actions[2], worker::Action::Ack);
+    }
+
+    #[test]
+    fn unrelated_check_run() {
+        let data = include_str!("../../test-srcs/events/check-run-rerequested.json");
+
+        let mut job: ghevent::CheckRunEvent = serde_json::from_str(&data.to_string())
+            .expect("Should properly deserialize");
+        job.check_run.name = String::from("travis-ci");
+
+        assert_eq!(
+            worker().consumer(&CheckEvent::Run(job).into()),
+            vec![worker::Action::Ack]
+        );
+    }
+}
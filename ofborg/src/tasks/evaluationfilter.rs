@@ -3,6 +3,7 @@ extern crate env_logger;
 
 use ofborg::ghevent;
 use ofborg::acl;
+use ofborg::correlation::{Correlated, Correlation};
 use serde_json;
 
 use ofborg::message::{Repo, Pr, massrebuildjob};
@@ -23,16 +24,16 @@ pub struct EvaluationFilterWorker {
 }
 
 impl worker::SimpleWorker for EvaluationFilterWorker {
-    type J = ghevent::PullRequestEvent;
+    type J = Correlated<ghevent::PullRequestEvent>;
 
     fn msg_to_job(
         &mut self,
         _: &Deliver,
-        _: &BasicProperties,
+        props: &BasicProperties,
         body: &Vec<u8>,
     ) -> Result<Self::J, String> {
         return match serde_json::from_slice(body) {
-            Ok(e) => Ok(e),
+            Ok(e) => Ok(Correlated::new(Correlation::extract(props), e)),
             Err(e) => {
                 Err(format!(
                     "Failed to deserialize job {:?}: {:?}",
@@ -43,7 +44,7 @@ impl worker::SimpleWorker for EvaluationFilterWorker {
         };
     }
 
-    fn consumer(&mut self, job: &ghevent::PullRequestEvent) -> worker::Actions {
+    fn consumer(&mut self, job: &Correlated<ghevent::PullRequestEvent>) -> worker::Actions {
         if !self.acl.is_repo_eligible(&job.repository.full_name) {
             info!("Repo not authorized ({})", job.repository.full_name);
             return vec![worker::Action::Ack];
@@ -58,6 +59,8 @@ impl worker::SimpleWorker for EvaluationFilterWorker {
             ghevent::PullRequestAction::Opened => true,
             ghevent::PullRequestAction::Synchronize => true,
             ghevent::PullRequestAction::Reopened => true,
+            // Draft PRs may have been skipped until now.
+            ghevent::PullRequestAction::ReadyForReview => true,
             ghevent::PullRequestAction::Edited => {
                 if let Some(ref changes) = job.changes {
                     changes.base.is_some()
@@ -94,10 +97,8 @@ impl worker::SimpleWorker for EvaluationFilterWorker {
             target_branch: Some(job.pull_request.base.git_ref.clone()),
         };
 
-        let msg = massrebuildjob::MassRebuildJob {
-            repo: repo_msg.clone(),
-            pr: pr_msg.clone(),
-        };
+        let msg = massrebuildjob::MassRebuildJob::new(repo_msg.clone(), pr_msg.clone())
+            .correlated(&job.correlation);
 
         return vec![
             worker::publish_serde_action(
@@ -130,7 +131,7 @@ mod tests {
         ));
 
         assert_eq!(
-            worker.consumer(&job),
+            worker.consumer(&job.into()),
             vec![
                 worker::publish_serde_action(
                     None,
@@ -147,10 +148,35 @@ mod tests {
                             head_sha: String::from("887e8b460a7d45ddb3bbdebe01447b251b3229e8"),
                             target_branch: Some(String::from("staging")),
                         },
+                        memory_retries: 0,
+                        correlation_id: None,
+                        caused_by: None,
                     }
                 ),
                 worker::Action::Ack,
             ]
         );
     }
+
+    #[test]
+    fn ready_for_review() {
+        let data = include_str!("../../test-srcs/events/pr-changed-base.json");
+
+        let mut event: serde_json::Value = serde_json::from_str(data).unwrap();
+        event["action"] = serde_json::Value::from("ready_for_review");
+        event["changes"] = serde_json::Value::Null;
+        let job: ghevent::PullRequestEvent = serde_json::from_value(event)
+            .expect("Should properly deserialize");
+        assert!(!job.pull_request.draft);
+
+        let mut worker = EvaluationFilterWorker::new(acl::ACL::new(
+            vec!["nixos/nixpkgs".to_owned()],
+            vec![],
+            vec![],
+        ));
+
+        let actions = worker.consumer(&job.into());
+        assert_eq!(actions.len(), 2);
+        assert_eq!(actions[1], worker::Action::Ack);
+    }
 }
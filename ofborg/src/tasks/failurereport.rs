@@ -0,0 +1,352 @@
+extern crate amqp;
+extern crate env_logger;
+
+use serde_json;
+use std::collections::{HashMap, VecDeque};
+use std::fs::{self, File};
+use std::io::{ErrorKind, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use ofborg::message::buildresult::{self, BuildResult};
+use ofborg::worker;
+use amqp::protocol::basic::{Deliver, BasicProperties};
+
+/// Collects the build results of a window of time, like the last week,
+/// and writes which attrs failed most often as `failures.json` and
+/// `failures.md`, to find what to fix in a zero hydra failures push.
+///
+/// Builds of several attrs don't tell which of them failed, so a
+/// failure counts against every attr built.
+pub struct FailureReporter {
+    dir: PathBuf,
+    window_secs: u64,
+    interval_secs: u64,
+    top: usize,
+    outcomes: VecDeque<Outcome>,
+    written_at: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+struct Outcome {
+    at: u64,
+    system: String,
+    attrs: Vec<String>,
+    success: bool,
+    /// Like `NixOS/nixpkgs#1234`.
+    pr: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Report {
+    /// Seconds since the epoch.
+    pub from: u64,
+    pub to: u64,
+    pub builds: u64,
+    pub failing: Vec<FailingAttr>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct FailingAttr {
+    pub attr: String,
+    pub system: String,
+    pub failures: u64,
+    pub builds: u64,
+    pub last_failed_in: String,
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|now| now.as_secs())
+        .unwrap_or(0)
+}
+
+impl FailureReporter {
+    pub fn new(dir: PathBuf, window_secs: u64, interval_secs: u64, top: usize) -> FailureReporter {
+        let mut reporter = FailureReporter {
+            dir: dir,
+            window_secs: window_secs,
+            interval_secs: interval_secs,
+            top: top,
+            outcomes: VecDeque::new(),
+            written_at: 0,
+        };
+
+        // Pick up the window where the last run left off.
+        match reporter.load_window() {
+            Ok(outcomes) => reporter.outcomes = outcomes,
+            Err(e) => warn!("Starting the failure report over: {}", e),
+        }
+
+        return reporter;
+    }
+
+    fn window_path(&self) -> PathBuf {
+        self.dir.join("failures-window.json")
+    }
+
+    fn load_window(&self) -> Result<VecDeque<Outcome>, String> {
+        let path = self.window_path();
+        let mut contents = String::new();
+        match File::open(&path) {
+            Ok(mut file) => {
+                file.read_to_string(&mut contents).map_err(|e| {
+                    format!("Failed to read {}: {:?}", path.display(), e)
+                })?;
+            }
+            Err(ref e) if e.kind() == ErrorKind::NotFound => return Ok(VecDeque::new()),
+            Err(e) => return Err(format!("Failed to open {}: {:?}", path.display(), e)),
+        }
+
+        return serde_json::from_str(&contents).map_err(|e| {
+            format!("Failed to parse {}: {:?}", path.display(), e)
+        });
+    }
+
+    pub fn record(&mut self, result: &BuildResult, now: u64) {
+        if let Some(success) = result.success() {
+            self.outcomes.push_back(Outcome {
+                at: now,
+                system: result.system.clone(),
+                attrs: result.attempted_attrs(),
+                success: success,
+                pr: format!("{}#{}", result.repo.full_name, result.pr.number),
+            });
+        }
+
+        while self.outcomes.front().map(|o| o.at + self.window_secs < now) == Some(true) {
+            self.outcomes.pop_front();
+        }
+    }
+
+    pub fn report(&self, now: u64) -> Report {
+        // (attr, system) -> (failures, builds, last failed in)
+        let mut tally: HashMap<(String, String), (u64, u64, String)> = HashMap::new();
+        for outcome in self.outcomes.iter() {
+            for attr in outcome.attrs.iter() {
+                let entry = tally
+                    .entry((attr.clone(), outcome.system.clone()))
+                    .or_insert((0, 0, String::new()));
+                entry.1 += 1;
+                if !outcome.success {
+                    entry.0 += 1;
+                    entry.2 = outcome.pr.clone();
+                }
+            }
+        }
+
+        let mut failing: Vec<FailingAttr> = tally
+            .into_iter()
+            .filter(|&(_, (failures, _, _))| failures > 0)
+            .map(|((attr, system), (failures, builds, last_failed_in))| {
+                FailingAttr {
+                    attr: attr,
+                    system: system,
+                    failures: failures,
+                    builds: builds,
+                    last_failed_in: last_failed_in,
+                }
+            })
+            .collect();
+        failing.sort_by(|a, b| {
+            b.failures
+                .cmp(&a.failures)
+                .then(a.builds.cmp(&b.builds))
+                .then(a.attr.cmp(&b.attr))
+                .then(a.system.cmp(&b.system))
+        });
+        failing.truncate(self.top);
+
+        return Report {
+            from: now.saturating_sub(self.window_secs),
+            to: now,
+            builds: self.outcomes.len() as u64,
+            failing: failing,
+        };
+    }
+
+    fn write(&mut self, now: u64) -> Result<(), String> {
+        let report = self.report(now);
+        let window: Vec<&Outcome> = self.outcomes.iter().collect();
+
+        write_atomically(
+            &self.dir.join("failures.json"),
+            &serde_json::to_string_pretty(&report).map_err(|e| format!("{:?}", e))?,
+        )?;
+        write_atomically(&self.dir.join("failures.md"), &markdown(&report))?;
+        write_atomically(
+            &self.window_path(),
+            &serde_json::to_string(&window).map_err(|e| format!("{:?}", e))?,
+        )?;
+
+        self.written_at = now;
+        return Ok(());
+    }
+}
+
+fn write_atomically(path: &Path, contents: &str) -> Result<(), String> {
+    let partial = path.with_extension("partial");
+    File::create(&partial)
+        .and_then(|mut file| file.write_all(contents.as_bytes()))
+        .and_then(|_| fs::rename(&partial, path))
+        .map_err(|e| format!("Failed to write {}: {:?}", path.display(), e))
+}
+
+pub fn markdown(report: &Report) -> String {
+    let mut lines: Vec<String> = vec![
+        String::from("# Most frequently failing attributes"),
+        String::new(),
+        format!(
+            "Over the last {} days, out of {} builds.",
+            (report.to - report.from) / 86400,
+            report.builds
+        ),
+        String::new(),
+    ];
+
+    if report.failing.len() == 0 {
+        lines.push(String::from("Nothing failed."));
+        return lines.join("\n") + "\n";
+    }
+
+    lines.push(String::from(
+        "| Attribute | System | Failures | Builds | Last failed in |",
+    ));
+    lines.push(String::from("| --- | --- | ---: | ---: | --- |"));
+    for failing in report.failing.iter() {
+        lines.push(format!(
+            "| `{}` | {} | {} | {} | {} |",
+            failing.attr,
+            failing.system,
+            failing.failures,
+            failing.builds,
+            failing.last_failed_in
+        ));
+    }
+
+    return lines.join("\n") + "\n";
+}
+
+impl worker::SimpleWorker for FailureReporter {
+    type J = BuildResult;
+
+    fn msg_to_job(
+        &mut self,
+        _: &Deliver,
+        props: &BasicProperties,
+        body: &Vec<u8>,
+    ) -> Result<Self::J, String> {
+        return buildresult::from(body, worker::content_type(props))
+            .map_err(|e| format!("Failed to decode {:?}", e));
+    }
+
+    fn consumer(&mut self, job: &BuildResult) -> worker::Actions {
+        let now = now();
+        self.record(job, now);
+
+        if now >= self.written_at + self.interval_secs {
+            if let Err(e) = self.write(now) {
+                warn!("Failed to write the failure report: {}", e);
+            }
+        }
+
+        return vec![worker::Action::Ack];
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ofborg::message::{Pr, Repo};
+    use ofborg::message::buildresult::{AttrResult, AttrStatus};
+    use ofborg::test_scratch::TestScratch;
+
+    fn result(number: u64, system: &str, attrs: Vec<&str>, success: bool) -> BuildResult {
+        let status = if success { AttrStatus::Success } else { AttrStatus::Failure };
+        BuildResult {
+            repo: Repo {
+                clone_url: String::from("https://github.com/NixOS/nixpkgs.git"),
+                full_name: String::from("NixOS/nixpkgs"),
+                owner: String::from("NixOS"),
+                name: String::from("nixpkgs"),
+            },
+            pr: Pr {
+                number: number,
+                head_sha: String::from("abc"),
+                target_branch: Some(String::from("master")),
+            },
+            system: String::from(system),
+            output: vec![],
+            attempt_id: String::from("attempt"),
+            attrs: attrs
+                .into_iter()
+                .map(|attr| AttrResult::new(String::from(attr), status))
+                .collect(),
+            merge_failed: false,
+            passed_without_attrs: false,
+            realisations: None,
+            correlation_id: None,
+            caused_by: None,
+        }
+    }
+
+    #[test]
+    fn test_report() {
+        let scratch = TestScratch::new_dir("failure-report");
+        fs::create_dir_all(scratch.path()).unwrap();
+        let week = 7 * 86400;
+        let mut reporter = FailureReporter::new(scratch.path(), week, 3600, 2);
+
+        reporter.record(&result(1, "x86_64-linux", vec!["hello"], false), 1000);
+        reporter.record(&result(2, "x86_64-linux", vec!["hello", "git"], false), 2000);
+        reporter.record(&result(3, "x86_64-linux", vec!["git"], true), 3000);
+        reporter.record(&result(4, "aarch64-linux", vec!["hello"], false), 4000);
+        reporter.record(&result(5, "x86_64-linux", vec!["curl"], true), 5000);
+
+        let report = reporter.report(week);
+        assert_eq!(report.builds, 5);
+        assert_eq!(
+            report.failing,
+            vec![
+                FailingAttr {
+                    attr: String::from("hello"),
+                    system: String::from("x86_64-linux"),
+                    failures: 2,
+                    builds: 2,
+                    last_failed_in: String::from("NixOS/nixpkgs#2"),
+                },
+                FailingAttr {
+                    attr: String::from("hello"),
+                    system: String::from("aarch64-linux"),
+                    failures: 1,
+                    builds: 1,
+                    last_failed_in: String::from("NixOS/nixpkgs#4"),
+                },
+            ]
+        );
+
+        assert_eq!(
+            markdown(&report),
+            "# Most frequently failing attributes
+
+Over the last 7 days, out of 5 builds.
+
+| Attribute | System | Failures | Builds | Last failed in |
+| --- | --- | ---: | ---: | --- |
+| `hello` | x86_64-linux | 2 | 2 | NixOS/nixpkgs#2 |
+| `hello` | aarch64-linux | 1 | 1 | NixOS/nixpkgs#4 |
+"
+        );
+
+        // The first failures age out of the window.
+        reporter.record(&result(6, "x86_64-linux", vec!["curl"], true), week + 2500);
+        assert_eq!(reporter.report(week + 2500).builds, 4);
+
+        // And the window survives a restart.
+        reporter.write(week + 2500).unwrap();
+        let restarted = FailureReporter::new(scratch.path(), week, 3600, 2);
+        assert_eq!(restarted.outcomes, reporter.outcomes);
+        assert!(scratch.path().join("failures.md").exists());
+    }
+}
@@ -4,110 +4,800 @@ extern crate env_logger;
 use ofborg::ghevent;
 use ofborg::acl;
 use serde_json;
+use std::time::{SystemTime, UNIX_EPOCH};
 
-use hubcaps;
+use ofborg::githubapi::{GithubApi, Reactable};
+use ofborg::prmetadata;
 use ofborg::message::{Repo, Pr, buildjob, massrebuildjob};
+use ofborg::message::cancellation::Cancellation;
+use ofborg::message::audit::{self, AuditEvent};
+use ofborg::cancellation;
+use ofborg::commandlimit::{CommandLimiter, Decision};
+use ofborg::buildreport;
+use ofborg::correlation::{Correlated, Correlation};
 use ofborg::worker;
-use ofborg::commentparser;
+use ofborg::commentparser::{self, LabelAction};
+use ofborg::tasks::massrebuilder::try_update_labels;
 use amqp::protocol::basic::{Deliver, BasicProperties};
 
 
 pub struct GitHubCommentWorker {
     acl: acl::ACL,
-    github: hubcaps::Github,
+    github_api: GithubApi,
+    controllable_labels: Vec<String>,
+    triggers: commentparser::Triggers,
+    command_limiter: Option<CommandLimiter>,
+}
+
+/// An event which can carry `@grahamcofborg` commands.
+#[derive(Debug)]
+pub enum CommandSource {
+    IssueComment(ghevent::IssueComment),
+    ReviewComment(ghevent::PullRequestReviewCommentEvent),
+    PullRequest(ghevent::PullRequestEvent),
+}
+
+const UNPARSED_REPLY: &'static str = "Sorry, I didn't understand that. I know \
+`@grahamcofborg build <attrs>`, `@grahamcofborg test <tests>` and `@grahamcofborg eval`, \
+`@grahamcofborg help` explains them.";
+
+/// Our replies are written for `@grahamcofborg`, replaced with the
+/// name we go by.
+const DEFAULT_NAME: &'static str = "@grahamcofborg";
+
+/// Marks our help reply, so it is only posted once per PR.
+const HELP_MARKER: &'static str = "<!-- ofborg-help -->";
+
+/// The commands `help` explains: the name their tier is configured
+/// by, how they are given and what they do.
+const HELP_COMMANDS: &'static [(&'static str, &'static str, &'static str)] = &[
+    ("eval", "`@grahamcofborg eval`", "Evaluate the PR again"),
+    (
+        "build",
+        "`@grahamcofborg build <attrs>`",
+        "Build the attributes, like `@grahamcofborg build hello python3Packages.requests`. \
+         Patterns like `python3Packages.requests*` or `haskellPackages.{aeson,lens}` build \
+         up to 50 matching attributes",
+    ),
+    (
+        "test",
+        "`@grahamcofborg test <tests>`",
+        "Run NixOS tests, like `@grahamcofborg test login`",
+    ),
+    (
+        "label",
+        "`@grahamcofborg label add <label>`",
+        "Add or remove one of the labels we look after, also `label remove`",
+    ),
+    (
+        "retry",
+        "`@grahamcofborg retry`",
+        "Evaluate or build again what failed on the PR's latest commit",
+    ),
+    (
+        "cancel",
+        "`@grahamcofborg cancel`",
+        "Stop the evaluations and builds of the PR's latest commit",
+    ),
+    ("help", "`@grahamcofborg help`", "Show this, once per PR"),
+];
+
+/// The flags builds and tests take, like `HELP_COMMANDS`.
+const HELP_FLAGS: &'static [(&'static str, &'static str, &'static str)] = &[
+    ("--on", "`--on <system>`", "Only build on that system"),
+    ("--keep-going", "`--keep-going`", "Keep building after a failure"),
+    ("--timeout", "`--timeout 2h`", "Raise how long a build may go without output"),
+    ("--check", "`--check`", "Check that the outputs are reproducible"),
+];
+
+/// Who belongs to `tier`, including the tiers above it.
+fn tier_description(tier: acl::Tier) -> &'static str {
+    match tier {
+        acl::Tier::Anyone => "everyone",
+        acl::Tier::Author => "the PR's author and known users",
+        acl::Tier::Known => "known users",
+        acl::Tier::Trusted => "trusted users",
+        acl::Tier::Admin => "admins",
+    }
+}
+
+/// The help for `user`, of `tier`, with the tiers configured for
+/// `repo`.
+fn help_text(bot: &str, user: &str, tier: acl::Tier, acl: &acl::ACL, repo: &str) -> String {
+    let permission = if acl.required_tier(repo, "build") > tier {
+        "You can't start builds yet, please ask a maintainer to do it for you."
+    } else if tier >= acl::Tier::Trusted {
+        "You are a trusted user, so your builds run on every platform."
+    } else {
+        "Your builds run on x86_64-linux and aarch64-linux."
+    };
+
+    let intro = format!("@{}, these are the commands I understand. {}", user, permission);
+
+    let table = |header: &str, rows: &[(&str, &str, &str)]| -> Vec<String> {
+        let mut lines = vec![
+            format!("| {} | Does | Who |", header),
+            String::from("| --- | --- | --- |"),
+        ];
+        lines.extend(rows.iter().map(|&(name, usage, does)| {
+            format!(
+                "| {} | {} | {} |",
+                usage,
+                does,
+                tier_description(acl.required_tier(repo, name))
+            )
+        }));
+        return lines;
+    };
+
+    let mut commands = vec![String::from("")];
+    commands.extend(table("Command", HELP_COMMANDS));
+    commands.push(String::from(""));
+    commands.push(String::from("Builds and tests take flags:"));
+    commands.push(String::from(""));
+    commands.extend(table("Flag", HELP_FLAGS));
+    commands.push(String::from(""));
+    commands.push(String::from(
+        "Commands go at the start of a line, several can be given in one comment.",
+    ));
+    let commands = commands.join("\n").replace(DEFAULT_NAME, bot);
+
+    return vec![HELP_MARKER, intro.as_str(), commands.as_str()].join("\n");
+}
+
+impl CommandSource {
+    pub fn from_event(routing_key: &str, body: &[u8]) -> Result<CommandSource, String> {
+        let event_type = routing_key.splitn(2, '.').next().unwrap_or("");
+        let source = match event_type {
+            "pull_request_review_comment" => {
+                serde_json::from_slice(body).map(CommandSource::ReviewComment)
+            }
+            "pull_request" => serde_json::from_slice(body).map(CommandSource::PullRequest),
+            _ => serde_json::from_slice(body).map(CommandSource::IssueComment),
+        };
+
+        return source.map_err(|e| {
+            format!(
+                "Failed to deserialize {} event {:?}: {:?}",
+                event_type,
+                String::from_utf8_lossy(body),
+                e
+            )
+        });
+    }
+
+    /// The text to look for commands in, if this event can issue any.
+    /// Editing a PR description only counts if it changed the commands,
+    /// so unrelated edits don't repeat them.
+    pub fn text(&self, triggers: &commentparser::Triggers) -> Option<&str> {
+        match self {
+            &CommandSource::IssueComment(ref event) => Some(&event.comment.body),
+            &CommandSource::ReviewComment(ref event) => {
+                if event.action == ghevent::PullRequestReviewCommentAction::Created {
+                    Some(&event.comment.body)
+                } else {
+                    None
+                }
+            }
+            &CommandSource::PullRequest(ref event) => {
+                let body = event.pull_request.body.as_ref().map(|b| b.as_str());
+                match event.action {
+                    ghevent::PullRequestAction::Opened => body,
+                    ghevent::PullRequestAction::Edited => {
+                        let previous = event.changes.as_ref().and_then(|c| c.body.as_ref());
+                        match (previous, body) {
+                            (Some(previous), Some(body)) => {
+                                if triggers.parse(&previous.from) != triggers.parse(body)
+                                {
+                                    Some(body)
+                                } else {
+                                    None
+                                }
+                            }
+                            _ => None,
+                        }
+                    }
+                    _ => None,
+                }
+            }
+        }
+    }
+
+    pub fn author(&self) -> &str {
+        match self {
+            &CommandSource::IssueComment(ref event) => &event.comment.user.login,
+            &CommandSource::ReviewComment(ref event) => &event.comment.user.login,
+            &CommandSource::PullRequest(ref event) => &event.sender.login,
+        }
+    }
+
+    pub fn repository(&self) -> &ghevent::Repository {
+        match self {
+            &CommandSource::IssueComment(ref event) => &event.repository,
+            &CommandSource::ReviewComment(ref event) => &event.repository,
+            &CommandSource::PullRequest(ref event) => &event.repository,
+        }
+    }
+
+    pub fn number(&self) -> u64 {
+        match self {
+            &CommandSource::IssueComment(ref event) => event.issue.number,
+            &CommandSource::ReviewComment(ref event) => event.pull_request.number,
+            &CommandSource::PullRequest(ref event) => event.number,
+        }
+    }
+
+    fn reactable(&self) -> Reactable {
+        match self {
+            &CommandSource::IssueComment(ref event) => Reactable::IssueComment(event.comment.id),
+            &CommandSource::ReviewComment(ref event) => Reactable::ReviewComment(event.comment.id),
+            &CommandSource::PullRequest(ref event) => Reactable::Issue(event.number),
+        }
+    }
 }
 
 impl GitHubCommentWorker {
-    pub fn new(acl: acl::ACL, github: hubcaps::Github) -> GitHubCommentWorker {
+    pub fn new(
+        acl: acl::ACL,
+        github_api: GithubApi,
+        controllable_labels: Vec<String>,
+        triggers: commentparser::Triggers,
+        command_limiter: Option<CommandLimiter>,
+    ) -> GitHubCommentWorker {
         return GitHubCommentWorker {
             acl: acl,
-            github: github,
+            github_api: github_api,
+            controllable_labels: controllable_labels,
+            triggers: triggers,
+            command_limiter: command_limiter,
+        };
+    }
+
+    /// The configured spelling of `label`, if we may change it.
+    fn controllable_label(&self, label: &str) -> Option<String> {
+        self.controllable_labels
+            .iter()
+            .find(|l| l.to_lowercase() == label.to_lowercase())
+            .cloned()
+    }
+
+    fn label(&self, job: &CommandSource, action: LabelAction, label: String) -> Result<(), String> {
+        let repository = job.repository();
+        let label = match self.controllable_label(&label) {
+            Some(label) => label,
+            None => return Err(format!("Label {:?} is not controllable", label)),
+        };
+
+        let github = self.github_api.hubcaps().map_err(|e| {
+            format!("No GitHub client: {:?}", e)
+        })?;
+        let issue_ref = github
+            .repo(repository.owner.login.clone(), repository.name.clone())
+            .issue(job.number());
+
+        let updated = match action {
+            LabelAction::Add => try_update_labels(&issue_ref, vec![label.clone()], vec![]),
+            LabelAction::Remove => try_update_labels(&issue_ref, vec![], vec![label.clone()]),
+        };
+
+        return updated.map_err(|e| format!("Failed to update {:?}: {:?}", label, e));
+    }
+
+    fn react(&self, job: &CommandSource, content: &str) {
+        if let Err(e) = self.github_api.add_reaction(
+            &job.repository().full_name,
+            &job.reactable(),
+            content,
+        )
+        {
+            info!("Failed to react to {:?}: {:?}", job.reactable(), e);
+        }
+    }
+
+    /// Whether `tier` may give `instruction`, including every flag
+    /// it was given with.
+    fn may(
+        &self,
+        job: &CommandSource,
+        instruction: &commentparser::Instruction,
+        tier: acl::Tier,
+    ) -> bool {
+        self.required_tier(job, instruction) <= tier
+    }
+
+    /// The tier needed on the repository the command was given on.
+    fn required_tier(
+        &self,
+        job: &CommandSource,
+        instruction: &commentparser::Instruction,
+    ) -> acl::Tier {
+        let repo = &job.repository().full_name;
+        command_names(instruction)
+            .iter()
+            .map(|command| self.acl.required_tier(repo, command))
+            .max()
+            .unwrap_or(acl::Tier::Anyone)
+    }
+
+    /// Audit whether `tier` may give `instruction`.
+    fn audit_instruction(
+        &self,
+        job: &Correlated<CommandSource>,
+        instruction: &commentparser::Instruction,
+        tier: acl::Tier,
+    ) -> worker::Action {
+        let required = self.required_tier(job, instruction);
+        let decision = if required <= tier {
+            audit::Decision::Allowed
+        } else {
+            audit::Decision::Denied
+        };
+
+        return self.audit(
+            job,
+            &describe(instruction),
+            decision,
+            &format!("is {:?}, needs {:?}", tier, required),
+        );
+    }
+
+    fn audit(
+        &self,
+        job: &Correlated<CommandSource>,
+        command: &str,
+        decision: audit::Decision,
+        reason: &str,
+    ) -> worker::Action {
+        let at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        let event = AuditEvent::new(
+            job.author().to_owned(),
+            command.to_owned(),
+            job.repository().full_name.clone(),
+            job.number(),
+            decision,
+            reason.to_owned(),
+            at,
+        ).correlated(&job.correlation);
+
+        return worker::publish_serde_action(Some(audit::EXCHANGE.to_owned()), None, &event);
+    }
+
+    fn reply_help(&self, job: &CommandSource) {
+        let repo = &job.repository().full_name;
+        let comments = match self.github_api.issue_comments(repo, job.number()) {
+            Ok(comments) => comments,
+            Err(e) => {
+                info!("Failed to list the comments of {}#{}: {:?}", repo, job.number(), e);
+                return;
+            }
+        };
+
+        if comments.iter().any(|c| c.body.starts_with(HELP_MARKER)) {
+            info!("Already explained the commands on {}#{}", repo, job.number());
+            self.react(job, "+1");
+            return;
+        }
+
+        self.react(job, "eyes");
+        if let Err(e) = self.github_api.create_issue_comment(
+            repo,
+            job.number(),
+            &help_text(
+                self.triggers.primary(),
+                job.author(),
+                self.acl.tier(job.author(), repo, false),
+                &self.acl,
+                repo,
+            ),
+        )
+        {
+            info!("Failed to reply to {:?}: {:?}", job.reactable(), e);
+        }
+    }
+
+    /// Whether one of our evaluation checks failed on `sha`.
+    fn evaluation_failed(&self, repo: &str, sha: &str) -> bool {
+        let failed_status = match self.github_api.commit_statuses(repo, sha) {
+            Ok(statuses) => {
+                statuses.iter().any(|status| {
+                    status.context.starts_with("grahamcofborg-eval") &&
+                        (status.state == "failure" || status.state == "error")
+                })
+            }
+            Err(e) => {
+                info!("Failed to fetch the statuses of {}: {:?}", sha, e);
+                false
+            }
+        };
+
+        let failed_check_run = match self.github_api.check_runs(repo, sha) {
+            Ok(runs) => {
+                runs.iter().any(|run| {
+                    run.name.starts_with("grahamcofborg-eval") &&
+                        run.conclusion.as_ref().map_or(false, |c| {
+                            c == "failure" || c == "timed_out"
+                        })
+                })
+            }
+            Err(e) => {
+                info!("Failed to fetch the check runs of {}: {:?}", sha, e);
+                false
+            }
         };
+
+        return failed_status || failed_check_run;
+    }
+
+    /// Queue again what failed in the last attempt on the PR's head:
+    /// the evaluation if one of its checks failed, and each platform's
+    /// failed builds on that platform only.
+    fn retries(
+        &self,
+        repo: &Repo,
+        pr: &Pr,
+        build_destinations: &Vec<(Option<String>, Option<String>)>,
+        requested_by: &str,
+        correlation: &Correlation,
+    ) -> Vec<worker::Action> {
+        let mut retries = vec![];
+
+        if self.evaluation_failed(&repo.full_name, &pr.head_sha) {
+            let msg = massrebuildjob::MassRebuildJob::new(repo.clone(), pr.clone())
+                .correlated(correlation);
+            retries.push(worker::publish_serde_action(
+                None,
+                Some("mass-rebuild-check-jobs".to_owned()),
+                &msg,
+            ));
+        }
+
+        let report = match buildreport::find(&self.github_api, &repo.full_name, pr.number) {
+            Some(report) => report,
+            None => return retries,
+        };
+
+        for (system, attrs) in report.failed_builds(&pr.head_sha) {
+            let on = vec![system];
+            let destinations = on_systems(build_destinations.clone(), &on);
+            let (tests, packages): (Vec<String>, Vec<String>) =
+                attrs.into_iter().partition(|attr| attr.starts_with("tests."));
+
+            for (subset, attrs) in vec![
+                (commentparser::Subset::NixOS, tests),
+                (commentparser::Subset::Nixpkgs, packages),
+            ]
+            {
+                if attrs.len() == 0 {
+                    continue;
+                }
+
+                let mut flags = commentparser::BuildFlags::default();
+                flags.on = on.clone();
+                let msg = buildjob::BuildJob::new(repo.clone(), pr.clone(), subset, attrs, None, None)
+                    .with_flags(flags)
+                    .requested_by(requested_by)
+                    .correlated(correlation);
+                for (exch, rk) in destinations.clone() {
+                    retries.push(worker::publish_serde_action(exch, rk, &msg));
+                }
+            }
+        }
+
+        return retries;
+    }
+
+    /// Whether the requester may start more work, trusted users are
+    /// never limited.
+    fn within_limit(&mut self, job: &CommandSource, tier: acl::Tier) -> Decision {
+        if tier >= acl::Tier::Trusted {
+            return Decision::Allowed;
+        }
+
+        return match self.command_limiter {
+            Some(ref mut limiter) => limiter.check(job.author()),
+            None => Decision::Allowed,
+        };
+    }
+
+    fn reply_limited(&self, job: &CommandSource) {
+        let limiter = match self.command_limiter {
+            Some(ref limiter) => limiter,
+            None => return,
+        };
+
+        let reply = format!(
+            "@{}, you have started {} evaluations and builds within {} minutes, which is \
+             as many as I can take on from one person. Please try again a bit later, \
+             or ask a maintainer to start them for you.",
+            job.author(),
+            limiter.max_commands(),
+            limiter.window_secs() / 60
+        );
+        if let Err(e) = self.github_api.create_issue_comment(
+            &job.repository().full_name,
+            job.number(),
+            &reply,
+        )
+        {
+            info!("Failed to reply to {:?}: {:?}", job.reactable(), e);
+        }
+    }
+
+    fn reply_partially_parsed(&self, job: &CommandSource, accepted: &[String], failed: &[String]) {
+        if let Err(e) = self.github_api.create_issue_comment(
+            &job.repository().full_name,
+            job.number(),
+            &partially_parsed_reply(self.triggers.primary(), accepted, failed),
+        )
+        {
+            info!("Failed to reply to {:?}: {:?}", job.reactable(), e);
+        }
     }
+
+    fn reply_unparsed(&self, job: &CommandSource) {
+        self.react(job, "confused");
+        if let Err(e) = self.github_api.create_issue_comment(
+            &job.repository().full_name,
+            job.number(),
+            &UNPARSED_REPLY.replace(DEFAULT_NAME, self.triggers.primary()),
+        )
+        {
+            info!("Failed to reply to {:?}: {:?}", job.reactable(), e);
+        }
+    }
+}
+
+fn partially_parsed_reply(bot: &str, accepted: &[String], failed: &[String]) -> String {
+    let mut reply = vec![String::from("I'm working on:"), String::from("")];
+    reply.extend(accepted.iter().map(|command| format!("- `{}`", command)));
+    reply.push(String::from(""));
+    reply.push(String::from("Sorry, I didn't understand:"));
+    reply.push(String::from(""));
+    reply.extend(failed.iter().map(|command| format!("- `{}`", command)));
+    reply.push(String::from(""));
+    reply.push(format!("`{} help` explains the commands I know.", bot));
+
+    return reply.join("\n");
+}
+
+/// The names permissions are configured by: the command, then each
+/// flag given to it.
+fn command_names(instruction: &commentparser::Instruction) -> Vec<String> {
+    let (command, flags) = match instruction {
+        &commentparser::Instruction::Build(commentparser::Subset::NixOS, _, ref flags) => {
+            ("test", flags.names())
+        }
+        &commentparser::Instruction::Build(_, _, ref flags) => ("build", flags.names()),
+        &commentparser::Instruction::Eval => ("eval", vec![]),
+        &commentparser::Instruction::Label(_, _) => ("label", vec![]),
+        &commentparser::Instruction::Cancel => ("cancel", vec![]),
+        &commentparser::Instruction::Retry => ("retry", vec![]),
+        &commentparser::Instruction::Help => ("help", vec![]),
+        &commentparser::Instruction::Ban(_) => ("admin", vec![]),
+    };
+
+    let mut names = vec![String::from(command)];
+    names.extend(flags.into_iter().map(String::from));
+    return names;
+}
+
+/// The instruction as it would be written in a comment.
+fn describe(instruction: &commentparser::Instruction) -> String {
+    let mut words = vec![command_names(instruction)[0].clone()];
+    match instruction {
+        &commentparser::Instruction::Build(_, ref attrs, ref flags) => {
+            words.extend(attrs.iter().cloned());
+            for system in flags.on.iter() {
+                words.push(format!("--on {}", system));
+            }
+            if let Some(timeout) = flags.timeout {
+                words.push(format!("--timeout {}s", timeout));
+            }
+            if flags.keep_going {
+                words.push(String::from("--keep-going"));
+            }
+            if flags.check {
+                words.push(String::from("--check"));
+            }
+        }
+        &commentparser::Instruction::Label(ref action, ref label) => {
+            words.push(format!("{:?}", action).to_lowercase());
+            words.push(label.clone());
+        }
+        &commentparser::Instruction::Ban(ref user) => {
+            words.push(String::from("ban"));
+            words.push(user.clone());
+        }
+        _ => {}
+    }
+
+    return words.join(" ");
+}
+
+/// Narrow the build destinations to the `--on` systems. Trusted
+/// users' builds go to every builder through the exchange, so they
+/// are sent to each system's queue instead.
+fn on_systems(
+    destinations: Vec<(Option<String>, Option<String>)>,
+    systems: &Vec<String>,
+) -> Vec<(Option<String>, Option<String>)> {
+    if systems.len() == 0 {
+        return destinations;
+    }
+
+    let everywhere = (Some(String::from("build-jobs")), None);
+    return systems
+        .iter()
+        .map(|system| (None, Some(format!("build-inputs-{}", system))))
+        .filter(|queue| destinations.contains(&everywhere) || destinations.contains(queue))
+        .collect();
 }
 
 impl worker::SimpleWorker for GitHubCommentWorker {
-    type J = ghevent::IssueComment;
+    type J = Correlated<CommandSource>;
 
     fn msg_to_job(
         &mut self,
-        _: &Deliver,
-        _: &BasicProperties,
+        method: &Deliver,
+        props: &BasicProperties,
         body: &Vec<u8>,
     ) -> Result<Self::J, String> {
-        return match serde_json::from_slice(body) {
-            Ok(e) => Ok(e),
-            Err(e) => {
-                println!(
-                    "Failed to deserialize IsssueComment: {:?}",
-                    String::from_utf8(body.clone())
-                );
-                panic!("{:?}", e);
-            }
-        };
+        return CommandSource::from_event(&method.routing_key, body)
+            .map(|source| Correlated::new(Correlation::extract(props), source));
     }
 
-    fn consumer(&mut self, job: &ghevent::IssueComment) -> worker::Actions {
-        let instructions = commentparser::parse(&job.comment.body);
-        if instructions == None {
+    fn consumer(&mut self, job: &Correlated<CommandSource>) -> worker::Actions {
+        let text = match job.text(&self.triggers) {
+            Some(text) => text,
+            None => return vec![worker::Action::Ack],
+        };
+
+        let parsed = self.triggers.parse_comment(text);
+        let (accepted, failed) = (parsed.accepted, parsed.failed);
+        let mut instructions = if parsed.instructions.len() > 0 {
+            Some(parsed.instructions)
+        } else {
+            None
+        };
+        if instructions == None && failed.len() == 0 && !self.triggers.mentions_bot(text) {
             return vec![worker::Action::Ack];
         }
 
-        let build_destinations = self.acl.build_job_destinations_for_user_repo(
-            &job.comment.user.login,
-            &job.repository.full_name,
+        if self.acl.is_banned(job.author()) {
+            info!("Ignoring {}, they are banned", job.author());
+            let command = if accepted.len() > 0 {
+                accepted.join("; ")
+            } else {
+                text.to_owned()
+            };
+            return vec![
+                self.audit(job, &command, audit::Decision::Denied, "banned"),
+                worker::Action::Ack,
+            ];
+        }
+
+        // Anyone may ask for help, it is only answered once per PR.
+        if let Some(parsed) = instructions {
+            if parsed.contains(&commentparser::Instruction::Help) {
+                self.reply_help(job);
+
+                let rest: Vec<commentparser::Instruction> = parsed
+                    .into_iter()
+                    .filter(|i| *i != commentparser::Instruction::Help)
+                    .collect();
+                if rest.len() == 0 {
+                    return vec![worker::Action::Ack];
+                }
+                instructions = Some(rest);
+            } else {
+                instructions = Some(parsed);
+            }
+        }
+
+        let repository = job.repository();
+        // Whether they wrote the PR is only known after fetching it,
+        // skip that for comments which couldn't do anything either way.
+        let most = ::std::cmp::max(
+            self.acl.tier(job.author(), &repository.full_name, false),
+            acl::Tier::Author,
         );
+        let possible = match instructions {
+            Some(ref parsed) => parsed.iter().any(|i| self.may(job, i, most)),
+            None => most > acl::Tier::Author,
+        };
+        if !possible {
+            info!("Nothing {} may do in: {:?}", job.author(), job);
+            let mut response: Vec<worker::Action> = instructions
+                .iter()
+                .flat_map(|parsed| parsed.iter())
+                .map(|i| self.audit_instruction(job, i, most))
+                .collect();
+            response.push(worker::Action::Ack);
+            return response;
+        }
 
-        if build_destinations.len() == 0 {
-            println!("No build destinations for: {:?}", job);
-            // Don't process comments if they can't build anything
+        if instructions == None {
+            self.reply_unparsed(job);
             return vec![worker::Action::Ack];
         }
 
-        println!("Got job: {:?}", job);
+        debug!("Got job: {:?}", job);
+        info!("Instructions: {:?}", instructions);
 
-        let instructions = commentparser::parse(&job.comment.body);
-        println!("Instructions: {:?}", instructions);
-
-        let pr = self.github
-            .repo(
-                job.repository.owner.login.clone(),
-                job.repository.name.clone(),
-            )
-            .pulls()
-            .get(job.issue.number)
-            .get();
+        let pr = prmetadata::fetch(
+            &self.github_api,
+            &repository.owner.login,
+            &repository.name,
+            job.number(),
+        );
 
         if let Err(x) = pr {
             info!(
-                "fetching PR {}#{} from GitHub yielded error {}",
-                job.repository.full_name,
-                job.issue.number,
+                "fetching PR {}#{} from GitHub yielded error {:?}",
+                repository.full_name,
+                job.number(),
                 x
             );
             return vec![worker::Action::Ack];
         }
 
         let pr = pr.unwrap();
+        let tier = self.acl.tier(
+            job.author(),
+            &repository.full_name,
+            job.author().to_lowercase() == pr.author.to_lowercase(),
+        );
+        let build_destinations = self.acl.build_job_destinations_for_tier(tier);
 
         let repo_msg = Repo {
-            clone_url: job.repository.clone_url.clone(),
-            full_name: job.repository.full_name.clone(),
-            owner: job.repository.owner.login.clone(),
-            name: job.repository.name.clone(),
+            clone_url: repository.clone_url.clone(),
+            full_name: repository.full_name.clone(),
+            owner: repository.owner.login.clone(),
+            name: repository.name.clone(),
         };
 
         let pr_msg = Pr {
-            number: job.issue.number.clone(),
-            head_sha: pr.head.sha.clone(),
-            target_branch: Some(pr.base.commit_ref.clone()),
+            number: job.number(),
+            head_sha: pr.head_sha.clone(),
+            target_branch: Some(pr.base_ref.clone()),
         };
-
         let mut response: Vec<worker::Action> = vec![];
+        let mut limited = false;
+        let mut reaction = "+1";
         if let Some(instructions) = instructions {
+            let cancels = instructions.contains(&commentparser::Instruction::Cancel);
+            // Where the first work the author may ask for is published.
+            let mut first_work: Option<usize> = None;
+
             for instruction in instructions {
+                response.push(self.audit_instruction(job, &instruction, tier));
+                if !self.may(job, &instruction, tier) {
+                    info!("{} ({:?}) may not use {:?}", job.author(), tier, instruction);
+                    reaction = "confused";
+                    continue;
+                }
+
                 match instruction {
-                    commentparser::Instruction::Build(subset, attrs) => {
+                    commentparser::Instruction::Build(subset, attrs, flags) => {
+                        let destinations = on_systems(build_destinations.clone(), &flags.on);
+                        if destinations.len() == 0 {
+                            reaction = "confused";
+                            continue;
+                        }
+
+                        match self.within_limit(job, tier) {
+                            Decision::Allowed => {}
+                            decision => {
+                                reaction = "confused";
+                                limited = limited || decision == Decision::Limited;
+                                continue;
+                            }
+                        }
+                        first_work = first_work.or(Some(response.len()));
+
                         let msg = buildjob::BuildJob::new(
                             repo_msg.clone(),
                             pr_msg.clone(),
@@ -115,17 +805,29 @@ impl worker::SimpleWorker for GitHubCommentWorker {
                             attrs,
                             None,
                             None,
-                        );
+                        ).with_flags(flags)
+                            .requested_by(job.author())
+                            .correlated(&job.correlation);
 
-                        for (exch, rk) in build_destinations.clone() {
+                        for (exch, rk) in destinations {
                             response.push(worker::publish_serde_action(exch, rk, &msg));
                         }
                     }
                     commentparser::Instruction::Eval => {
-                        let msg = massrebuildjob::MassRebuildJob {
-                            repo: repo_msg.clone(),
-                            pr: pr_msg.clone(),
-                        };
+                        match self.within_limit(job, tier) {
+                            Decision::Allowed => {}
+                            decision => {
+                                reaction = "confused";
+                                limited = limited || decision == Decision::Limited;
+                                continue;
+                            }
+                        }
+                        first_work = first_work.or(Some(response.len()));
+
+                        reaction = "rocket";
+                        let msg =
+                            massrebuildjob::MassRebuildJob::new(repo_msg.clone(), pr_msg.clone())
+                                .correlated(&job.correlation);
 
                         response.push(worker::publish_serde_action(
                             None,
@@ -133,12 +835,233 @@ impl worker::SimpleWorker for GitHubCommentWorker {
                             &msg,
                         ));
                     }
+                    commentparser::Instruction::Label(action, label) => {
+                        if let Err(e) = self.label(job, action, label) {
+                            info!("Not labeling {}#{}: {}", repo_msg.full_name, job.number(), e);
+                            reaction = "confused";
+                        }
+                    }
+                    commentparser::Instruction::Cancel => {
+                        let msg = Cancellation::new(repo_msg.clone(), pr_msg.clone(), job.author())
+                            .correlated(&job.correlation);
+                        response.push(worker::publish_serde_action(
+                            Some(cancellation::EXCHANGE.to_owned()),
+                            None,
+                            &msg,
+                        ));
+                    }
+                    commentparser::Instruction::Retry => {
+                        match self.within_limit(job, tier) {
+                            Decision::Allowed => {}
+                            decision => {
+                                reaction = "confused";
+                                limited = limited || decision == Decision::Limited;
+                                continue;
+                            }
+                        }
+
+                        let retries = self.retries(
+                            &repo_msg,
+                            &pr_msg,
+                            &build_destinations,
+                            job.author(),
+                            &job.correlation,
+                        );
+                        if retries.len() == 0 {
+                            info!("Nothing failed on {}#{}", repo_msg.full_name, pr_msg.number);
+                            reaction = "confused";
+                            continue;
+                        }
+                        first_work = first_work.or(Some(response.len()));
+                        reaction = "rocket";
+                        response.extend(retries);
+                    }
+                    commentparser::Instruction::Ban(user) => {
+                        if let Err(e) = self.acl.ban(&user) {
+                            warn!("Failed to ban {}: {}", user, e);
+                            reaction = "confused";
+                            continue;
+                        }
+
+                        info!("{} banned {}", job.author(), user);
+                    }
+                    commentparser::Instruction::Help => {}
+                }
+            }
 
+            // Asking for work again undoes an earlier cancel, once the
+            // author was allowed to.
+            if let Some(at) = first_work {
+                if !cancels {
+                    let msg = Cancellation::new(repo_msg.clone(), pr_msg.clone(), job.author())
+                        .lift()
+                        .correlated(&job.correlation);
+                    response.insert(
+                        at,
+                        worker::publish_serde_action(
+                            Some(cancellation::EXCHANGE.to_owned()),
+                            None,
+                            &msg,
+                        ),
+                    );
                 }
             }
         }
 
+        self.react(job, reaction);
+        if failed.len() > 0 {
+            self.reply_partially_parsed(job, &accepted, &failed);
+        }
+        if limited {
+            self.reply_limited(job);
+        }
+
         response.push(worker::Action::Ack);
         return response;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[test]
+    fn review_comment_commands() {
+        let data = include_bytes!("../../test-srcs/events/pr-review-comment.json");
+        let job = CommandSource::from_event("pull_request_review_comment.nixos/nixpkgs", data)
+            .unwrap();
+
+        assert_eq!(
+            job.text(&commentparser::Triggers::default()),
+            Some("Looks good.\r\n\r\n@grahamcofborg build hello")
+        );
+        assert_eq!(job.author(), "grahamc");
+        assert_eq!(job.number(), 33299);
+        assert_eq!(job.reactable(), Reactable::ReviewComment(167293013));
+    }
+
+    #[test]
+    fn unrelated_pr_edit() {
+        let data = include_bytes!("../../test-srcs/events/pr-changed-base.json");
+        let job = CommandSource::from_event("pull_request.nixos/nixpkgs", data).unwrap();
+
+        assert_eq!(job.text(&commentparser::Triggers::default()), None);
+        assert_eq!(job.author(), "grahamc");
+        assert_eq!(job.reactable(), Reactable::Issue(33299));
+    }
+
+    #[test]
+    fn help() {
+        let acl = acl::ACL::new(vec![String::from("nixos/nixpkgs")], vec![], vec![]);
+        let help = help_text("@grahamcofborg", "example", acl::Tier::Anyone, &acl, "NixOS/nixpkgs");
+        assert!(help.starts_with(HELP_MARKER));
+        assert!(help.contains("@example, these are the commands I understand. You can't"));
+        assert!(help.contains("| `@grahamcofborg build <attrs>` | Build the attributes, like"));
+        assert!(help.contains(
+            "| `--check` | Check that the outputs are reproducible | trusted users |",
+        ));
+    }
+
+    #[test]
+    fn help_follows_configured_tiers() {
+        let mut tiers = HashMap::new();
+        tiers.insert(String::from("build"), acl::Tier::Anyone);
+        tiers.insert(String::from("--check"), acl::Tier::Known);
+        let acl = acl::ACL::new(vec![String::from("nixos/nixpkgs")], vec![], vec![])
+            .with_command_tiers(tiers);
+
+        let help = help_text("@ofborg", "example", acl::Tier::Anyone, &acl, "NixOS/nixpkgs");
+        assert!(help.contains("| `@ofborg build <attrs>` | Build the attributes"));
+        assert!(help.contains("50 matching attributes | everyone |"));
+        assert!(help.contains(
+            "| `--check` | Check that the outputs are reproducible | known users |",
+        ));
+        assert!(help.contains("| `@ofborg eval` | Evaluate the PR again | known users |"));
+        assert!(!help.contains("You can't"));
+    }
+
+    #[test]
+    fn partially_parsed() {
+        assert_eq!(
+            partially_parsed_reply(
+                "@grahamcofborg",
+                &[String::from("@grahamcofborg eval")],
+                &[String::from("@grahamcofborg biuld hello")],
+            ),
+            "I'm working on:
+
+- `@grahamcofborg eval`
+
+Sorry, I didn't understand:
+
+- `@grahamcofborg biuld hello`
+
+`@grahamcofborg help` explains the commands I know."
+        );
+    }
+
+    #[test]
+    fn build_command_names() {
+        let mut flags = commentparser::BuildFlags::default();
+        flags.on = vec![String::from("x86_64-darwin")];
+        let instruction = commentparser::Instruction::Build(
+            commentparser::Subset::Nixpkgs,
+            vec![String::from("hello")],
+            flags,
+        );
+
+        assert_eq!(
+            command_names(&instruction),
+            vec![String::from("build"), String::from("--on")]
+        );
+        assert_eq!(
+            command_names(&commentparser::Instruction::Eval),
+            vec![String::from("eval")]
+        );
+        assert_eq!(describe(&instruction), "build hello --on x86_64-darwin");
+    }
+
+    #[test]
+    fn build_on_systems() {
+        let everywhere = vec![(Some(String::from("build-jobs")), None)];
+        let restricted = vec![
+            (None, Some(String::from("build-inputs-x86_64-linux"))),
+            (None, Some(String::from("build-inputs-aarch64-linux"))),
+        ];
+        let darwin = vec![String::from("x86_64-darwin")];
+
+        assert_eq!(on_systems(everywhere.clone(), &vec![]), everywhere);
+        assert_eq!(
+            on_systems(everywhere, &darwin),
+            vec![(None, Some(String::from("build-inputs-x86_64-darwin")))]
+        );
+        assert_eq!(on_systems(restricted.clone(), &darwin), vec![]);
+        assert_eq!(
+            on_systems(restricted, &vec![String::from("aarch64-linux")]),
+            vec![(None, Some(String::from("build-inputs-aarch64-linux")))]
+        );
+    }
+
+    #[test]
+    fn controllable_label() {
+        let worker = GitHubCommentWorker::new(
+            acl::ACL::new(vec![], vec![], vec![]),
+            GithubApi::new(String::from("https://api.github.com"), String::from("token")),
+            vec![String::from("backport release-18.03")],
+            commentparser::Triggers::default(),
+            None,
+        );
+
+        assert_eq!(
+            worker.controllable_label("Backport Release-18.03"),
+            Some(String::from("backport release-18.03"))
+        );
+        assert_eq!(worker.controllable_label("1.severity: security"), None);
+    }
+
+    #[test]
+    fn bogus_event() {
+        assert!(CommandSource::from_event("issue_comment.nixos/nixpkgs", b"{}").is_err());
+    }
+}
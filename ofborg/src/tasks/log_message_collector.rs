@@ -8,6 +8,8 @@ use std::fs::{OpenOptions, File};
 use std::path::{Component, PathBuf};
 use std::io::Write;
 
+use ofborg::logindex::{self, AttemptResult};
+use ofborg::message::envelope::{self, Format, Versioned};
 use ofborg::writetoline::LineWriter;
 use ofborg::message::buildlogmsg::{BuildLogStart, BuildLogMsg};
 use ofborg::message::buildresult::BuildResult;
@@ -48,7 +50,7 @@ fn validate_path_segment(segment: &PathBuf) -> Result<(), String> {
     if segment.components().all(|component| match component {
         Component::Normal(_) => true,
         e => {
-            println!("Invalid path component: {:?}", e);
+            warn!("Invalid path component: {:?}", e);
             false
         }
     })
@@ -85,6 +87,29 @@ impl LogMessageCollector {
         }
     }
 
+    pub fn write_result(&mut self, from: &LogFrom, data: &BuildResult) -> Result<(), String> {
+        let resultpath = self.path_for_result(&from)?;
+        let data = serde_json::to_string(&AttemptResult::from(data)).map_err(|e| {
+            format!("Failed to stringify result: {:?}", e)
+        })?;
+
+        // Written whole, unlike the log, in case a result is redelivered.
+        fs::create_dir_all(resultpath.parent().unwrap()).unwrap();
+        let mut fp = File::create(&resultpath).map_err(|e| {
+            format!("Failed to open the file for {:?}, err: {:?}", &resultpath, e)
+        })?;
+        fp.write_all(&data.as_bytes()).map_err(|e| {
+            format!("Failed to write result: {:?}", e)
+        })
+    }
+
+    /// Regenerates the `index.json` files the log viewer reads.
+    fn update_index(&self, from: &LogFrom) {
+        if let Err(e) = logindex::update(&self.log_root, &from.routing_key) {
+            warn!("Failed to update the index of {}: {}", from.routing_key, e);
+        }
+    }
+
     pub fn handle_for(&mut self, from: &LogFrom) -> Result<&mut LineWriter, String> {
         if self.handles.contains_key(&from) {
             return Ok(self.handles.get_mut(&from).expect(
@@ -111,6 +136,12 @@ impl LogMessageCollector {
         return Ok(path);
     }
 
+    fn path_for_result(&self, from: &LogFrom) -> Result<PathBuf, String> {
+        let mut path = self.path_for_log(from)?;
+        path.set_extension("result.json");
+        return Ok(path);
+    }
+
     fn path_for_log(&self, from: &LogFrom) -> Result<PathBuf, String> {
         let mut location = self.log_root.clone();
 
@@ -161,32 +192,44 @@ impl worker::SimpleWorker for LogMessageCollector {
     fn msg_to_job(
         &mut self,
         deliver: &Deliver,
-        _: &BasicProperties,
+        props: &BasicProperties,
         body: &Vec<u8>,
     ) -> Result<Self::J, String> {
-
-        let message: MsgType;
-        let attempt_id: String;
-
-        let decode_msg: Result<BuildLogMsg, _> = serde_json::from_slice(body);
-        if let Ok(msg) = decode_msg {
-            attempt_id = msg.attempt_id.clone();
-            message = MsgType::Msg(msg);
+        let format = Format::of(worker::content_type(props))?;
+        let value = envelope::parse(body, format.encoding)?;
+        let schema = if format.enveloped {
+            envelope::peek(&value).map(|(schema, _)| schema)
         } else {
-            let decode_msg: Result<BuildLogStart, _> = serde_json::from_slice(body);
-            if let Ok(msg) = decode_msg {
-                attempt_id = msg.attempt_id.clone();
-                message = MsgType::Start(msg);
-            } else {
-                let decode_msg: Result<BuildResult, _> = serde_json::from_slice(body);
-                if let Ok(msg) = decode_msg {
-                    attempt_id = msg.attempt_id.clone();
-                    message = MsgType::Finish(msg);
-                } else {
-                    return Err(format!("failed to decode job: {:?}", decode_msg));
-                }
+            None
+        };
+
+        let message = match schema {
+            Some(ref schema) if schema == BuildLogMsg::SCHEMA => {
+                envelope::from_value(value, true).map(MsgType::Msg)
             }
-        }
+            Some(ref schema) if schema == BuildLogStart::SCHEMA => {
+                envelope::from_value(value, true).map(MsgType::Start)
+            }
+            Some(ref schema) if schema == BuildResult::SCHEMA => {
+                envelope::from_value(value, true).map(MsgType::Finish)
+            }
+            Some(schema) => return Err(format!("unexpected {} message", schema)),
+            None if format.enveloped => return Err(String::from("an envelope without a schema")),
+            // From before the envelope, told apart by what they decode as.
+            None => {
+                envelope::from_value(value.clone(), false)
+                    .map(MsgType::Msg)
+                    .or_else(|_| envelope::from_value(value.clone(), false).map(MsgType::Start))
+                    .or_else(|_| envelope::from_value(value.clone(), false).map(MsgType::Finish))
+            }
+        };
+        let message = message.map_err(|e| format!("failed to decode job: {:?}", e))?;
+
+        let attempt_id = match message {
+            MsgType::Start(ref msg) => msg.attempt_id.clone(),
+            MsgType::Msg(ref msg) => msg.attempt_id.clone(),
+            MsgType::Finish(ref msg) => msg.attempt_id.clone(),
+        };
 
         return Ok(LogMessage {
             from: LogFrom {
@@ -201,6 +244,7 @@ impl worker::SimpleWorker for LogMessageCollector {
         match job.message {
             MsgType::Start(ref start) => {
                 self.write_metadata(&job.from, &start).expect("failed to write metadata");
+                self.update_index(&job.from);
             },
             MsgType::Msg(ref message) => {
                 let handle = self.handle_for(&job.from).unwrap();
@@ -208,7 +252,9 @@ impl worker::SimpleWorker for LogMessageCollector {
                 handle.write_to_line((message.line_number - 1) as usize,
                                      &message.output);
             },
-            MsgType::Finish(ref _finish) => {
+            MsgType::Finish(ref finish) => {
+                self.write_result(&job.from, &finish).expect("failed to write result");
+                self.update_index(&job.from);
             },
         }
 
@@ -336,13 +382,13 @@ mod tests {
 
     #[test]
     pub fn test_logs_collect() {
-        let mut logmsg = BuildLogMsg {
-            attempt_id: String::from("my-attempt-id"),
-            identity: String::from("my-identity"),
-            system: String::from("foobar-x8664"),
-            line_number: 1,
-            output: String::from("line-1"),
-        };
+        let mut logmsg = BuildLogMsg::new(
+            String::from("foobar-x8664"),
+            String::from("my-identity"),
+            String::from("my-attempt-id"),
+            1,
+            String::from("line-1"),
+        );
         let mut job = LogMessage {
             from: make_from("foo"),
             message: MsgType::Msg(logmsg.clone()),
@@ -356,13 +402,13 @@ mod tests {
                        worker.consumer(&
                                        LogMessage {
                                            from: make_from("foo"),
-                                           message: MsgType::Start(BuildLogStart {
-                                               attempt_id: String::from("my-attempt-id"),
-                                               identity: String::from("my-identity"),
-                                               system: String::from("foobar-x8664"),
-                                               attempted_attrs: Some(vec!["foo".to_owned()]),
-                                               skipped_attrs: Some(vec!["bar".to_owned()]),
-                                           })
+                                           message: MsgType::Start(BuildLogStart::new(
+                                               String::from("foobar-x8664"),
+                                               String::from("my-identity"),
+                                               String::from("my-attempt-id"),
+                                               vec!["foo".to_owned()],
+                                               vec!["bar".to_owned()],
+                                           ))
                                        }
                        )
             );
@@ -395,6 +441,9 @@ mod tests {
         File::open(pr).unwrap().read_to_string(&mut s).unwrap();
         assert_eq!(&s, "line-1\n\n\n\nline-5\n");
 
+        assert!(p.path().join("routing-key-foo/index.json").is_file());
+        assert!(p.path().join("index.json").is_file());
+
 
         let mut pr = p.path();
         let mut s = String::new();
@@ -3,68 +3,291 @@ extern crate env_logger;
 
 use std::collections::HashMap;
 use std::fs::File;
-use std::io::Read;
-use std::io::BufRead;
-use std::io::BufReader;
+use std::io::{ErrorKind, Read};
 use std::path::Path;
 use std::path::PathBuf;
 use ofborg::checkout;
+use ofborg::logging;
+use ofborg::cancellation::Cancellations;
 use ofborg::message::{massrebuildjob, buildjob};
-use std::time::Instant;
+use std::thread;
+use std::time::{Duration, Instant};
 use ofborg::nix;
 use ofborg::acl::ACL;
 use ofborg::stats;
 use ofborg::stats::Event;
 use ofborg::worker;
 use ofborg::tagger::{StdenvTagger, RebuildTagger, PathsTagger, PkgsAddedRemovedTagger};
-use ofborg::outpathdiff::{OutPaths, OutPathDiff};
+use ofborg::outpathdiff::{OutPaths, OutPathDiff, PackageArch, PackageOutPaths};
 use ofborg::evalchecker::EvalChecker;
 use ofborg::commitstatus::CommitStatus;
 use ofborg::commentparser::Subset;
+use ofborg::config::{DraftMode, FormatCheckConfig, MaintainerPingConfig, RepoConfig,
+                     SecurityFeedConfig};
+use ofborg::maintainers;
+use ofborg::maintainers::ImpactedMaintainers;
+use ofborg::metalint::MetaLint;
+use ofborg::aliascheck::AliasUsage;
+use ofborg::evalsummary::EvalSummary;
+use ofborg::buildreport;
+use ofborg::prmetadata;
+use ofborg::evalerrors;
+use ofborg::evalerrors::EvalError;
+use ofborg::githubapi::{GithubApi, NewReviewComment};
+use ofborg::evalcache::EvalCache;
+use ofborg::branchpolicy::{self, BranchPolicy};
+use ofborg::pushpolicy::{self, PushPolicy};
+use ofborg::securityfeed::VulnerabilityFeed;
+use ofborg::formatcheck::{self, FormatCheck};
+use ofborg::flakecheck;
 use amqp::protocol::basic::{Deliver, BasicProperties};
 use hubcaps;
 
+/// Linting meta of a treewide change is neither useful nor cheap.
+const MAX_META_LINT_ATTRS: usize = 500;
+
+/// Each attr is instantiated again, so only check small changes.
+const MAX_ALIAS_CHECK_ATTRS: usize = 100;
+
+/// How long to wait before handing back a job GitHub failed.
+const RETRY_DELAY_SECS: u64 = 10;
+
+const MERGE_CONFLICT_LABEL: &'static str = "2.status: merge conflict";
+
+/// Don't bury a PR under review comments if evaluation is very broken.
+const MAX_EVAL_ERROR_ANNOTATIONS: usize = 10;
+
 pub struct MassRebuildWorker<E> {
     cloner: checkout::CachedCloner,
     nix: nix::Nix,
-    github: hubcaps::Github,
+    github_api: GithubApi,
     acl: ACL,
     identity: String,
     events: E,
     tag_paths: HashMap<String, Vec<String>>,
+    branch_policies: Vec<BranchPolicy>,
+    maintainer_ping: Option<MaintainerPingConfig>,
+    eval_cache: EvalCache,
+    security_feed: Option<SecurityFeedConfig>,
+    high_memory_queue: Option<String>,
+    format_checks: Vec<FormatCheckConfig>,
+    outpath_parallelism: usize,
+    outpath_systems: Vec<String>,
+    checks_api: bool,
+    min_pending_interval: Duration,
+    draft_mode: DraftMode,
+    repositories: HashMap<String, RepoConfig>,
+    cancellations: Cancellations,
 }
 
 impl<E: stats::SysEvents> MassRebuildWorker<E> {
     pub fn new(
         cloner: checkout::CachedCloner,
         nix: nix::Nix,
-        github: hubcaps::Github,
+        github_api: GithubApi,
         acl: ACL,
         identity: String,
         events: E,
         tag_paths: HashMap<String, Vec<String>>,
+        branch_policies: Vec<BranchPolicy>,
+        maintainer_ping: Option<MaintainerPingConfig>,
+        eval_cache: EvalCache,
+        security_feed: Option<SecurityFeedConfig>,
+        high_memory_queue: Option<String>,
+        format_checks: Vec<FormatCheckConfig>,
+        outpath_parallelism: usize,
+        outpath_systems: Vec<String>,
+        checks_api: bool,
+        min_pending_interval: Duration,
+        draft_mode: DraftMode,
+        repositories: HashMap<String, RepoConfig>,
+        cancellations: Cancellations,
     ) -> MassRebuildWorker<E> {
         return MassRebuildWorker {
             cloner: cloner,
             nix: nix.without_limited_supported_systems(),
-            github: github,
+            github_api: github_api,
             acl: acl,
             identity: identity,
             events: events,
-            tag_paths: tag_paths
+            tag_paths: tag_paths,
+            branch_policies: branch_policies,
+            maintainer_ping: maintainer_ping,
+            eval_cache: eval_cache,
+            security_feed: security_feed,
+            high_memory_queue: high_memory_queue,
+            format_checks: format_checks,
+            outpath_parallelism: outpath_parallelism,
+            outpath_systems: outpath_systems,
+            checks_api: checks_api,
+            min_pending_interval: min_pending_interval,
+            draft_mode: draft_mode,
+            repositories: repositories,
+            cancellations: cancellations,
         };
     }
 
+    fn repo_runs_check(&self, repo: &str, name: &str) -> bool {
+        match self.repositories.get(&repo.to_lowercase()).and_then(
+            |repo| repo.checks.as_ref(),
+        ) {
+            Some(checks) => checks.iter().any(|check| check == name),
+            None => true,
+        }
+    }
+
+    fn is_flake(&self, repo: &str) -> bool {
+        self.repositories
+            .get(&repo.to_lowercase())
+            .and_then(|repo| repo.flake)
+            .unwrap_or(false)
+    }
+
+    /// Check a flake with `nix flake check`, then build each of its
+    /// checks for our system under a status of its own.
+    fn check_flake<'a>(
+        &self,
+        repo: &hubcaps::repositories::Repository<'a>,
+        gists: &hubcaps::gists::Gists<'a>,
+        job: &massrebuildjob::MassRebuildJob,
+        flake: &Path,
+        changed_lines: &HashMap<String, Vec<(u64, u64)>>,
+        overall_status: &mut CommitStatus,
+    ) {
+        overall_status.set_with_description(
+            "Running nix flake check",
+            hubcaps::statuses::State::Pending,
+        );
+
+        if let Err(mut out) = flakecheck::evaluate(&self.nix, flake) {
+            let output = file_to_str(&mut out);
+            let errors = evalerrors::parse(&output, &flake.to_string_lossy());
+            self.annotate_eval_errors(job, changed_lines, errors, overall_status);
+            overall_status.set_url(make_gist(
+                gists,
+                String::from("nix-flake-check"),
+                Some(String::from("Failure")),
+                output,
+            ));
+            overall_status.set_with_description(
+                "nix flake check failed",
+                hubcaps::statuses::State::Failure,
+            );
+            return;
+        }
+
+        let system = self.nix.system().to_owned();
+        let names = match flakecheck::check_names(&self.nix, flake, &system) {
+            Ok(names) => names,
+            Err(output) => {
+                overall_status.set_url(make_gist(
+                    gists,
+                    String::from("flake-checks"),
+                    Some(String::from("Failure")),
+                    output,
+                ));
+                overall_status.set_with_description(
+                    "Failed to list the flake's checks",
+                    hubcaps::statuses::State::Failure,
+                );
+                return;
+            }
+        };
+
+        let mut failed: Vec<String> = vec![];
+        for name in names {
+            if self.stop_if_cancelled(job, overall_status) {
+                return;
+            }
+
+            overall_status.set_with_description(
+                &format!("Building check {}", name),
+                hubcaps::statuses::State::Pending,
+            );
+
+            let mut status = self.commit_status(
+                repo,
+                &job.repo.full_name,
+                job.pr.head_sha.clone(),
+                flakecheck::context(&name),
+                format!("nix build .#checks.{}.{}", system, name),
+                None,
+            );
+            status.set(hubcaps::statuses::State::Pending);
+
+            match flakecheck::build(&self.nix, flake, &system, &name) {
+                Ok(_) => status.set(hubcaps::statuses::State::Success),
+                Err(mut out) => {
+                    status.set_url(make_gist(
+                        gists,
+                        flakecheck::context(&name),
+                        Some(String::from("Failure")),
+                        file_to_str(&mut out),
+                    ));
+                    status.set(hubcaps::statuses::State::Failure);
+                    failed.push(name);
+                }
+            }
+        }
+
+        if failed.len() > 0 {
+            overall_status.set_with_description(
+                &format!("Flake checks failed: {}", failed.join(", ")),
+                hubcaps::statuses::State::Failure,
+            );
+        } else {
+            overall_status.set_with_description("^.^!", hubcaps::statuses::State::Success);
+        }
+    }
+
     fn actions(&self) -> massrebuildjob::Actions {
         return massrebuildjob::Actions {};
     }
 
-    fn tag_from_title(&self, issue: &hubcaps::issues::IssueRef) {
-        let darwin = issue.get()
-            .map(|iss| iss.title.to_lowercase().contains("darwin"))
-            .unwrap_or(false);
+    /// A commit status, or a check run when the Checks API is enabled.
+    fn commit_status<'a>(
+        &'a self,
+        repo: &hubcaps::repositories::Repository<'a>,
+        full_name: &str,
+        sha: String,
+        context: String,
+        description: String,
+        url: Option<String>,
+    ) -> CommitStatus<'a> {
+        let mut status = if self.checks_api {
+            CommitStatus::check_run(
+                &self.github_api,
+                full_name.to_owned(),
+                sha,
+                context,
+                description,
+                url,
+            )
+        } else {
+            CommitStatus::new(repo.statuses(), sha, context, description, url)
+        };
+        status.set_min_pending_interval(self.min_pending_interval);
+
+        return status;
+    }
+
+    fn cached_out_paths(&self, tree: &Option<String>) -> Option<PackageOutPaths> {
+        let tree = tree.as_ref()?;
+        let paths: Vec<(PackageArch, String)> = self.eval_cache.get(tree, "outpaths")?;
+        return Some(paths.into_iter().collect());
+    }
 
-        if darwin {
+    fn cache_out_paths(&self, tree: &Option<String>, paths: &Option<PackageOutPaths>) {
+        if let (&Some(ref tree), &Some(ref paths)) = (tree, paths) {
+            // JSON objects can only have string keys, so store the
+            // map as a list of pairs.
+            let pairs: Vec<(&PackageArch, &String)> = paths.iter().collect();
+            self.eval_cache.put(tree, "outpaths", &pairs);
+        }
+    }
+
+    fn tag_from_title(&self, issue: &hubcaps::issues::IssueRef, title: &str) {
+        if title.to_lowercase().contains("darwin") {
             update_labels(
                 &issue,
                 vec![String::from("6.topic: darwin")],
@@ -73,6 +296,542 @@ impl<E: stats::SysEvents> MassRebuildWorker<E> {
         }
     }
 
+    fn rebuild_tagger(&self, policy: &BranchPolicy) -> RebuildTagger {
+        match policy.rebuild_label_thresholds {
+            Some(ref thresholds) => RebuildTagger::with_thresholds(thresholds.clone()),
+            None => RebuildTagger::new(),
+        }
+    }
+
+    /// Whether `@grahamcofborg cancel` stopped the evaluation, checked
+    /// between its steps.
+    fn stop_if_cancelled(
+        &self,
+        job: &massrebuildjob::MassRebuildJob,
+        overall_status: &mut CommitStatus,
+    ) -> bool {
+        if !self.cancellations.is_cancelled(&job.repo, &job.pr) {
+            return false;
+        }
+
+        info!("Stopping the evaluation of {}, it was cancelled", job.pr.number);
+        overall_status.set_with_description(
+            "Cancelled by request",
+            hubcaps::statuses::State::Error,
+        );
+        return true;
+    }
+
+    /// If the evaluation was killed for using too much memory, hand
+    /// the job to a high-memory evaluator once, or fail it outright.
+    fn handle_memory_limit(
+        &self,
+        job: &massrebuildjob::MassRebuildJob,
+        overall_status: &mut CommitStatus,
+        output: &str,
+    ) -> Option<worker::Actions> {
+        if !nix::exceeded_memory_limit(output) {
+            return None;
+        }
+
+        self.events.notify(Event::EvaluationExceededMemoryLimit);
+
+        match self.high_memory_queue {
+            Some(ref queue) if job.memory_retries == 0 => {
+                overall_status.set_with_description(
+                    "Evaluation exceeded memory limit, retrying with more memory",
+                    hubcaps::statuses::State::Pending,
+                );
+                let retry = worker::publish_serde_action(
+                    None,
+                    Some(queue.clone()),
+                    &job.memory_retry(),
+                );
+                return Some(self.actions().done(job, vec![retry]));
+            }
+            _ => {
+                overall_status.set_with_description(
+                    "Evaluation exceeded memory limit",
+                    hubcaps::statuses::State::Failure,
+                );
+                return Some(self.actions().skip(job));
+            }
+        }
+    }
+
+    fn report_merge_conflict(
+        &self,
+        issue: &hubcaps::issues::IssueRef,
+        labeled_conflicting: bool,
+        target_branch: &str,
+    ) {
+        if labeled_conflicting {
+            // The conflict was already reported for an earlier push.
+            return;
+        }
+
+        update_labels(issue, vec![String::from(MERGE_CONFLICT_LABEL)], vec![]);
+
+        let comment = hubcaps::comments::CommentOptions {
+            body: format!(
+                "This PR has merge conflicts with {}. Please rebase it, \
+                 it will be evaluated again once the conflicts are resolved.",
+                target_branch
+            ),
+        };
+        if let Err(e) = issue.comments().create(&comment) {
+            info!("Failed to comment about the merge conflict: {:?}", e);
+        }
+    }
+
+    fn push_policy(&self, repo: &str) -> Option<PushPolicy> {
+        self.repositories
+            .get(&repo.to_lowercase())
+            .and_then(|repo| repo.push_policy.clone())
+    }
+
+    /// Whether the PR's head breaks the repository's push policy, which
+    /// is labeled and commented on once. If the policy can't be checked,
+    /// it's evaluated anyway.
+    fn violates_push_policy(
+        &self,
+        issue: &hubcaps::issues::IssueRef,
+        labeled_violating: bool,
+        job: &massrebuildjob::MassRebuildJob,
+        co: &checkout::CachedProjectCo,
+    ) -> bool {
+        let policy = match self.push_policy(&job.repo.full_name) {
+            Some(policy) => policy,
+            None => return false,
+        };
+
+        let approved = match self.github_api.pull_reviews(&job.repo.full_name, job.pr.number) {
+            Ok(reviews) => pushpolicy::approved_commits(&reviews),
+            Err(e) => {
+                warn!("Failed to fetch the reviews, not checking the push policy: {:?}", e);
+                return false;
+            }
+        };
+        let commits = match self.github_api.pull_commits(&job.repo.full_name, job.pr.number) {
+            Ok(commits) => commits,
+            Err(e) => {
+                warn!("Failed to fetch the commits, not checking the push policy: {:?}", e);
+                return false;
+            }
+        };
+
+        let violations = policy.violations(
+            &approved,
+            &commits,
+            |commit| co.contains_commit(&job.pr.head_sha, commit),
+            |commit| co.files_changed_by(commit).unwrap_or(vec![]),
+        );
+
+        if violations.len() == 0 {
+            if labeled_violating {
+                update_labels(issue, vec![], vec![String::from(pushpolicy::LABEL)]);
+            }
+            return false;
+        }
+
+        info!("{} violates the push policy: {:?}", job.pr.head_sha, violations);
+        if !labeled_violating {
+            update_labels(issue, vec![String::from(pushpolicy::LABEL)], vec![]);
+
+            let comment = hubcaps::comments::CommentOptions {
+                body: pushpolicy::comment(&violations),
+            };
+            if let Err(e) = issue.comments().create(&comment) {
+                info!("Failed to comment about the push policy: {:?}", e);
+            }
+        }
+
+        return true;
+    }
+
+    fn security_updates(&self, rebuildsniff: &OutPathDiff) -> Vec<String> {
+        let settings = match self.security_feed {
+            Some(ref settings) => settings,
+            None => return vec![],
+        };
+
+        let feed = match VulnerabilityFeed::load(Path::new(&settings.path)) {
+            Ok(feed) => feed,
+            Err(e) => {
+                warn!("{}", e);
+                return vec![];
+            }
+        };
+
+        match (&rebuildsniff.original, &rebuildsniff.current) {
+            (&Some(ref before), &Some(ref after)) => feed.security_updates(before, after),
+            _ => vec![],
+        }
+    }
+
+    fn ping_maintainers(
+        &self,
+        job: &massrebuildjob::MassRebuildJob,
+        pr_author: &str,
+        checkout: &Path,
+        changed: Vec<String>,
+    ) {
+        let settings = match self.maintainer_ping {
+            Some(ref settings) => settings,
+            None => return,
+        };
+
+        if changed.len() == 0 || changed.len() > settings.max_changed_attrs {
+            info!(
+                "Not pinging maintainers, {} attrs changed (max: {})",
+                changed.len(),
+                settings.max_changed_attrs
+            );
+            return;
+        }
+
+        let impacted = match ImpactedMaintainers::calculate(&self.nix, checkout, &changed) {
+            Ok(impacted) => impacted,
+            Err(e) => {
+                info!("Failed to calculate impacted maintainers: {:?}", e);
+                return;
+            }
+        };
+
+        let comments = match self.github_api.issue_comments(&job.repo.full_name, job.pr.number) {
+            Ok(comments) => comments,
+            Err(e) => {
+                info!("Failed to fetch existing comments: {:?}", e);
+                return;
+            }
+        };
+
+        // Pings of later pushes go in the first ping's comment, rather
+        // than a comment of their own each.
+        let previous = comments.iter().find(
+            |comment| maintainers::is_ping_comment(&comment.body),
+        );
+
+        let mut participants: Vec<String> = vec![pr_author.to_owned()];
+        participants.extend(settings.opt_out.iter().cloned());
+        for comment in comments.iter() {
+            if maintainers::is_ping_comment(&comment.body) {
+                participants.extend(maintainers::previously_pinged(&comment.body));
+            } else if let Some(ref user) = comment.user {
+                participants.push(user.login.clone());
+            }
+        }
+
+        let to_ping = impacted.excluding(&participants);
+        if to_ping.is_empty() {
+            info!("All impacted maintainers are already participating");
+            return;
+        }
+
+        let result = match previous {
+            Some(comment) => {
+                self.github_api.update_issue_comment(
+                    &job.repo.full_name,
+                    comment.id,
+                    &maintainers::extend_ping_comment(&comment.body, &to_ping),
+                )
+            }
+            None => {
+                self.github_api.create_issue_comment(
+                    &job.repo.full_name,
+                    job.pr.number,
+                    &maintainers::ping_comment(&to_ping),
+                )
+            }
+        };
+        if let Err(e) = result {
+            info!("Failed to ping maintainers: {:?}", e);
+        }
+    }
+
+    fn lint_changed_meta<'a>(
+        &self,
+        repo: &hubcaps::repositories::Repository<'a>,
+        gists: &hubcaps::gists::Gists<'a>,
+        job: &massrebuildjob::MassRebuildJob,
+        checkout: &Path,
+        changed: &Vec<String>,
+    ) {
+        if changed.len() == 0 || changed.len() > MAX_META_LINT_ATTRS {
+            info!("Not linting meta, {} attrs changed", changed.len());
+            return;
+        }
+
+        let mut status = self.commit_status(
+            &repo,
+            &job.repo.full_name,
+            job.pr.head_sha.clone(),
+            String::from("grahamcofborg-eval-meta-lint"),
+            format!("Checking meta of {} changed attrs", changed.len()),
+            None,
+        );
+        status.set(hubcaps::statuses::State::Pending);
+
+        match MetaLint::check(&self.nix, checkout, changed) {
+            Ok(ref lint) if lint.is_clean() => {
+                status.set_with_description(
+                    "meta is complete for all changed attrs",
+                    hubcaps::statuses::State::Success,
+                );
+            }
+            Ok(lint) => {
+                status.set_url(make_gist(
+                    gists,
+                    String::from("Meta Lint"),
+                    Some(String::from("Meta problems in changed attrs")),
+                    lint.report(),
+                ));
+                status.set_summary(format!("```\n{}\n```", lint.report()));
+                status.set_annotations(lint.annotations(&checkout.to_string_lossy()));
+                status.set_with_description(
+                    &format!(
+                        "{} meta problems in {} attrs",
+                        lint.problem_count(),
+                        lint.attr_count()
+                    ),
+                    hubcaps::statuses::State::Failure,
+                );
+            }
+            Err(output) => {
+                status.set_url(make_gist(
+                    gists,
+                    String::from("Meta Lint"),
+                    Some(String::from("Meta evaluation failed")),
+                    output,
+                ));
+                status.set_with_description(
+                    "Failed to evaluate meta",
+                    hubcaps::statuses::State::Error,
+                );
+            }
+        }
+    }
+
+    fn check_aliases<'a>(
+        &self,
+        repo: &hubcaps::repositories::Repository<'a>,
+        gists: &hubcaps::gists::Gists<'a>,
+        job: &massrebuildjob::MassRebuildJob,
+        checkout: &Path,
+        project: &checkout::CachedProject,
+        base_commit: &Option<String>,
+        changed: &Vec<String>,
+        changed_files: &Vec<String>,
+    ) {
+        if changed.len() == 0 || changed.len() > MAX_ALIAS_CHECK_ATTRS {
+            info!("Not checking aliases, {} attrs changed", changed.len());
+            return;
+        }
+
+        let mut status = self.commit_status(
+            &repo,
+            &job.repo.full_name,
+            job.pr.head_sha.clone(),
+            String::from("grahamcofborg-eval-aliases"),
+            format!("Checking {} changed attrs for deprecated aliases", changed.len()),
+            None,
+        );
+        status.set(hubcaps::statuses::State::Pending);
+
+        let mut usage = AliasUsage::check(&self.nix, checkout, changed);
+        if !usage.is_clean() {
+            // Check the base in a checkout of its own, the PR's is used
+            // after this.
+            let base = base_commit.as_ref().and_then(|commit| {
+                project
+                    .clone_for("mr-est-base".to_string(), self.identity.clone())
+                    .and_then(|base_co| base_co.checkout_ref(commit.as_ref()))
+                    .ok()
+            });
+            match base {
+                Some(base) => usage = usage.excluding_failing_on(&self.nix, Path::new(&base)),
+                None => warn!("Failed to check out the base, not checking it for aliases"),
+            }
+        }
+        if usage.is_clean() {
+            status.set_with_description(
+                "No deprecated aliases used",
+                hubcaps::statuses::State::Success,
+            );
+            return;
+        }
+
+        let annotations: Vec<(String, u64, String)> = usage
+            .errors(&checkout.to_string_lossy())
+            .into_iter()
+            .map(|error| {
+                let body = format!("Uses a deprecated alias:\n\n```\n{}\n```", error.message);
+                (error.path, error.line, body)
+            })
+            .collect();
+
+        status.set_url(make_gist(
+            gists,
+            String::from("Alias Usage"),
+            Some(String::from("Changed attrs using deprecated aliases")),
+            usage.report(),
+        ));
+        status.set_summary(format!("```\n{}\n```", usage.report()));
+        status.set_annotations(annotations.clone());
+        status.set_with_description(
+            &format!("{} attrs use deprecated aliases", usage.attr_count()),
+            hubcaps::statuses::State::Failure,
+        );
+
+        if !status.is_check_run() {
+            self.annotate(job, changed_files, annotations);
+        }
+    }
+
+    /// Put the package set breakdown in the PR's report comment.
+    fn post_summary(&self, job: &massrebuildjob::MassRebuildJob, summary: &EvalSummary) {
+        buildreport::update(&self.github_api, &job.repo, &job.pr, |report| {
+            report.record_evaluation(&summary.head_sha, summary.render())
+        });
+    }
+
+    /// Annotate the errors on the lines in the hunks of the PR's diff,
+    /// and list the others in the status' summary, GitHub can't
+    /// annotate them.
+    fn annotate_eval_errors(
+        &self,
+        job: &massrebuildjob::MassRebuildJob,
+        changed_lines: &HashMap<String, Vec<(u64, u64)>>,
+        errors: Vec<EvalError>,
+        status: &mut CommitStatus,
+    ) {
+        let (annotatable, others) = evalerrors::partition_by_hunks(errors, changed_lines);
+        if others.len() > 0 {
+            status.set_summary(format!(
+                "Evaluation fails outside of the lines this PR changes:\n\n{}",
+                evalerrors::summary(&others)
+            ));
+        }
+
+        let annotations: Vec<(String, u64, String)> = annotatable
+            .into_iter()
+            .map(|error| {
+                let body = format!("Evaluation fails here:\n\n```\n{}\n```", error.message);
+                (error.path, error.line, body)
+            })
+            .collect();
+        status.set_annotations(annotations.clone());
+
+        if !status.is_check_run() {
+            let changed_files: Vec<String> = changed_lines.keys().cloned().collect();
+            self.annotate(job, &changed_files, annotations);
+        }
+    }
+
+    /// Post each `(path, line, body)` as a review comment on the PR,
+    /// unless it is outside the changed files or was already posted.
+    fn annotate(
+        &self,
+        job: &massrebuildjob::MassRebuildJob,
+        changed_files: &Vec<String>,
+        annotations: Vec<(String, u64, String)>,
+    ) {
+        let existing = match self.github_api.review_comments(&job.repo.full_name, job.pr.number) {
+            Ok(existing) => existing,
+            Err(e) => {
+                info!("Failed to fetch review comments: {:?}", e);
+                return;
+            }
+        };
+
+        let annotations = annotations
+            .into_iter()
+            .filter(|&(ref path, _, _)| changed_files.contains(path))
+            .take(MAX_EVAL_ERROR_ANNOTATIONS);
+
+        for (path, line, body) in annotations {
+            let already_posted = existing.iter().any(|comment| {
+                comment.path == path && comment.line == Some(line) && comment.body == body
+            });
+            if already_posted {
+                continue;
+            }
+
+            let comment = NewReviewComment {
+                body: body,
+                commit_id: job.pr.head_sha.clone(),
+                path: path.clone(),
+                line: line,
+                side: String::from("RIGHT"),
+            };
+
+            if let Err(e) = self.github_api.create_review_comment(
+                &job.repo.full_name,
+                job.pr.number,
+                &comment,
+            )
+            {
+                info!("Failed to annotate {}:{}: {:?}", path, line, e);
+            }
+        }
+    }
+
+    fn check_formatting<'a>(
+        &self,
+        repo: &hubcaps::repositories::Repository<'a>,
+        gists: &hubcaps::gists::Gists<'a>,
+        job: &massrebuildjob::MassRebuildJob,
+        checkout: &Path,
+        changed_files: &Vec<String>,
+    ) {
+        for config in self.format_checks.iter() {
+            let check = FormatCheck::new(config);
+            let files = check.files_to_check(checkout, changed_files);
+            if files.len() == 0 {
+                continue;
+            }
+
+            let mut status = self.commit_status(
+                &repo,
+                &job.repo.full_name,
+                job.pr.head_sha.clone(),
+                check.name(),
+                check.cli_cmd(),
+                None,
+            );
+            status.set(hubcaps::statuses::State::Pending);
+
+            match check.run(checkout, &files) {
+                Ok(()) => {
+                    status.set(hubcaps::statuses::State::Success);
+                }
+                Err(output) => {
+                    let annotations: Vec<(String, u64, String)> =
+                        formatcheck::parse_violations(&output)
+                            .into_iter()
+                            .map(|v| (v.path, v.line, v.message))
+                            .collect();
+                    status.set_summary(format!("```\n{}\n```", output));
+                    status.set_url(make_gist(
+                        gists,
+                        check.name(),
+                        Some(String::from("Formatting problems in changed files")),
+                        output,
+                    ));
+                    status.set_annotations(annotations.clone());
+                    status.set_with_description(
+                        &format!("Problems found in {} changed files", files.len()),
+                        hubcaps::statuses::State::Failure,
+                    );
+
+                    if !status.is_check_run() {
+                        self.annotate(job, changed_files, annotations);
+                    }
+                }
+            }
+        }
+    }
+
     fn tag_from_paths(&self, issue: &hubcaps::issues::IssueRef, paths: Vec<String>) {
         let mut tagger = PathsTagger::new(self.tag_paths.clone());
 
@@ -94,11 +853,11 @@ impl<E: stats::SysEvents + 'static> worker::SimpleWorker for MassRebuildWorker<E
     fn msg_to_job(
         &mut self,
         _: &Deliver,
-        _: &BasicProperties,
+        props: &BasicProperties,
         body: &Vec<u8>,
     ) -> Result<Self::J, String> {
         self.events.notify(Event::JobReceived);
-        return match massrebuildjob::from(body) {
+        return match massrebuildjob::from(body, worker::content_type(props)) {
             Ok(e) => {
                 self.events.notify(Event::JobDecodeSuccess);
                 Ok(e)
@@ -116,28 +875,61 @@ impl<E: stats::SysEvents + 'static> worker::SimpleWorker for MassRebuildWorker<E
     }
 
     fn consumer(&mut self, job: &massrebuildjob::MassRebuildJob) -> worker::Actions {
-        let repo = self.github.repo(
+        let pr = format!("{}#{}", job.repo.full_name, job.pr.number);
+        let _fields = logging::fields(&[("pr", pr.as_str())]);
+        let github = match self.github_api.hubcaps() {
+            Ok(github) => github,
+            Err(e) => {
+                // Most likely GitHub failing for a moment, don't retry
+                // right away.
+                warn!("No GitHub client, retrying the evaluation: {:?}", e);
+                thread::sleep(Duration::from_secs(RETRY_DELAY_SECS));
+                return vec![worker::Action::NackRequeue];
+            }
+        };
+        let repo = github.repo(
             job.repo.owner.clone(),
             job.repo.name.clone(),
         );
-        let gists = self.github.gists();
+        let gists = github.gists();
         let issue = repo.issue(job.pr.number);
 
         let auto_schedule_build_archs: Vec<buildjob::ExchangeQueue>;
-
-        match issue.get() {
-            Ok(iss) => {
-                if iss.state == "closed" {
+        let pr_author: String;
+        let labeled_conflicting: bool;
+        let labeled_violating: bool;
+        let draft_mode: DraftMode;
+
+        let pr_title: String;
+
+        match prmetadata::fetch(
+            &self.github_api,
+            &job.repo.owner,
+            &job.repo.name,
+            job.pr.number,
+        ) {
+            Ok(meta) => {
+                if !meta.is_open() {
                     self.events.notify(Event::IssueAlreadyClosed);
                     info!("Skipping {} because it is closed", job.pr.number);
                     return self.actions().skip(&job);
                 }
 
-                if issue_is_wip(&iss) {
+                pr_author = meta.author.clone();
+                pr_title = meta.title.clone();
+                labeled_conflicting = meta.has_label(MERGE_CONFLICT_LABEL);
+                labeled_violating = meta.has_label(pushpolicy::LABEL);
+                draft_mode = if meta.is_draft {
+                    self.draft_mode
+                } else {
+                    DraftMode::Evaluate
+                };
+
+                if meta.is_wip() || meta.is_draft {
                     auto_schedule_build_archs = vec![];
                 } else {
                     auto_schedule_build_archs = self.acl.build_job_destinations_for_user_repo(
-                        &iss.user.login,
+                        &meta.author,
                         &job.repo.full_name,
                     );
                 }
@@ -150,16 +942,37 @@ impl<E: stats::SysEvents + 'static> worker::SimpleWorker for MassRebuildWorker<E
             }
         }
 
-        self.tag_from_title(&issue);
+        self.tag_from_title(&issue, &pr_title);
 
-        let mut overall_status = CommitStatus::new(
-            repo.statuses(),
+        if draft_mode == DraftMode::Skip {
+            info!("Skipping draft PR {}", job.pr.number);
+            self.commit_status(
+                &repo,
+                &job.repo.full_name,
+                job.pr.head_sha.clone(),
+                "grahamcofborg-eval".to_owned(),
+                "".to_owned(),
+                None,
+            ).set_neutral("Draft PR, evaluation waits until it is ready for review");
+            return self.actions().skip(&job);
+        }
+
+        let evaluation_start = Instant::now();
+        nix::take_peak_memory_mb();
+
+        let mut overall_status = self.commit_status(
+            &repo,
+            &job.repo.full_name,
             job.pr.head_sha.clone(),
             "grahamcofborg-eval".to_owned(),
             "Starting".to_owned(),
             None,
         );
 
+        if self.stop_if_cancelled(&job, &mut overall_status) {
+            return self.actions().skip(&job);
+        }
+
         overall_status.set_with_description("Starting", hubcaps::statuses::State::Pending);
 
         let project = self.cloner.project(
@@ -179,12 +992,132 @@ impl<E: stats::SysEvents + 'static> worker::SimpleWorker for MassRebuildWorker<E
             None => String::from("master"),
         };
 
+        let policy = branchpolicy::policy_for(&self.branch_policies, &target_branch);
+        let auto_schedule_build_archs = if policy.auto_builds() {
+            auto_schedule_build_archs
+        } else {
+            info!("Not scheduling builds for PRs against {}", &target_branch);
+            vec![]
+        };
+
         overall_status.set_with_description(
             format!("Checking out {}", &target_branch).as_ref(),
             hubcaps::statuses::State::Pending,
         );
         info!("Checking out target branch {}", &target_branch);
         let refpath = co.checkout_origin_ref(target_branch.as_ref()).unwrap();
+        let base_tree = co.tree_hash("HEAD").ok();
+        let base_commit = co.commit_hash("HEAD").ok();
+
+        overall_status.set_with_description("Fetching PR", hubcaps::statuses::State::Pending);
+
+        if let Err(e) = co.fetch_pr(job.pr.number, &job.pr.head_sha) {
+            warn!("Not evaluating {}: {}", job.pr.head_sha, e);
+            let description = if e.kind() == ErrorKind::InvalidData {
+                "The PR's head moved on, not evaluating"
+            } else {
+                "Fetching the PR failed"
+            };
+            overall_status.set_with_description(description, hubcaps::statuses::State::Error);
+            return self.actions().skip(&job);
+        }
+
+        if self.stop_if_cancelled(&job, &mut overall_status) {
+            return self.actions().skip(&job);
+        }
+
+        if !co.commit_exists(job.pr.head_sha.as_ref()) {
+            overall_status.set_with_description(
+                "Commit not found",
+                hubcaps::statuses::State::Error,
+            );
+
+            info!("Commit {} doesn't exist", job.pr.head_sha);
+            return self.actions().skip(&job);
+        }
+
+        if self.violates_push_policy(&issue, labeled_violating, &job, &co) {
+            overall_status.set_with_description(
+                "Push policy violated, see the comment",
+                hubcaps::statuses::State::Failure,
+            );
+            return self.actions().skip(&job);
+        }
+
+        overall_status.set_with_description(
+            "Checking for merge conflicts",
+            hubcaps::statuses::State::Pending,
+        );
+
+        match co.can_merge(job.pr.head_sha.as_ref()) {
+            Ok(true) => {
+                if labeled_conflicting {
+                    update_labels(&issue, vec![], vec![String::from(MERGE_CONFLICT_LABEL)]);
+                }
+            }
+            Ok(false) => {
+                self.report_merge_conflict(&issue, labeled_conflicting, &target_branch);
+                overall_status.set_with_description(
+                    format!("Merge conflict with {}", &target_branch).as_ref(),
+                    hubcaps::statuses::State::Failure,
+                );
+
+                info!("{} conflicts with {}", job.pr.head_sha, &target_branch);
+                return self.actions().skip(&job);
+            }
+            Err(e) => {
+                info!("Failed to check for merge conflicts: {:?}", e);
+            }
+        }
+
+        if self.is_flake(&job.repo.full_name) {
+            let changed_lines = co.lines_changed_from_head(&job.pr.head_sha).unwrap_or(
+                HashMap::new(),
+            );
+            if let Err(_) = co.merge_commit(job.pr.head_sha.as_ref()) {
+                overall_status.set_with_description(
+                    "Failed to merge",
+                    hubcaps::statuses::State::Failure,
+                );
+                return self.actions().skip(&job);
+            }
+
+            self.check_flake(
+                &repo,
+                &gists,
+                &job,
+                Path::new(&refpath),
+                &changed_lines,
+                &mut overall_status,
+            );
+            self.events.notify_all(self.github_api.take_usage().events());
+            self.events.notify(Event::TaskEvaluationCheckComplete);
+            return self.actions().skip(&job);
+        }
+
+        if draft_mode == DraftMode::Quick {
+            // Everything below needs the expensive evaluations.
+            let changed_files = co.files_changed_from_head(&job.pr.head_sha).unwrap_or(vec![]);
+            self.tag_from_paths(&issue, changed_files.clone());
+
+            if let Err(_) = co.merge_commit(job.pr.head_sha.as_ref()) {
+                overall_status.set_with_description(
+                    "Failed to merge",
+                    hubcaps::statuses::State::Failure,
+                );
+                return self.actions().skip(&job);
+            }
+
+            self.check_formatting(&repo, &gists, &job, Path::new(&refpath), &changed_files);
+
+            info!("Only ran quick checks on draft PR {}", job.pr.number);
+            overall_status.set_neutral(
+                "Draft PR, only quick checks ran until it is ready for review",
+            );
+            self.events.notify_all(self.github_api.take_usage().events());
+            self.events.notify(Event::TaskEvaluationCheckComplete);
+            return self.actions().skip(&job);
+        }
 
         overall_status.set_with_description(
             "Checking original stdenvs",
@@ -195,7 +1128,9 @@ impl<E: stats::SysEvents + 'static> worker::SimpleWorker for MassRebuildWorker<E
         let mut stdenvs = Stdenvs::new(self.nix.clone(), PathBuf::from(&refpath));
         stdenvs.identify_before();
 
-        let mut rebuildsniff = OutPathDiff::new(self.nix.clone(), PathBuf::from(&refpath));
+        let mut rebuildsniff = OutPathDiff::new(self.nix.clone(), PathBuf::from(&refpath))
+            .with_parallelism(self.outpath_parallelism)
+            .with_systems(self.outpath_systems.clone());
 
         overall_status.set_with_description(
             "Checking original out paths",
@@ -204,14 +1139,22 @@ impl<E: stats::SysEvents + 'static> worker::SimpleWorker for MassRebuildWorker<E
 
         let target_branch_rebuild_sniff_start = Instant::now();
 
-        if let Err(mut output) = rebuildsniff.find_before() {
+        if let Some(paths) = self.cached_out_paths(&base_tree) {
+            info!("Reusing cached out paths of {}", &target_branch);
+            rebuildsniff.original = Some(paths);
+        } else if let Err(mut output) = rebuildsniff.find_before() {
+            let output = file_to_str(&mut output);
             overall_status.set_url(make_gist(
                 &gists,
                 "Output path comparison".to_owned(),
                 Some("".to_owned()),
-                file_to_str(&mut output),
+                output.clone(),
             ));
 
+            if let Some(actions) = self.handle_memory_limit(&job, &mut overall_status, &output) {
+                return actions;
+            }
+
             self.events.notify(Event::TargetBranchFailsEvaluation(target_branch.clone()));
             overall_status.set_with_description(
                 format!("Target branch {} doesn't evaluate!", &target_branch).as_ref(),
@@ -219,30 +1162,22 @@ impl<E: stats::SysEvents + 'static> worker::SimpleWorker for MassRebuildWorker<E
             );
 
             return self.actions().skip(&job);
-        }
-        self.events.notify(
-            Event::EvaluationDuration(
-                target_branch.clone(),
-                target_branch_rebuild_sniff_start.elapsed().as_secs(),
-            )
-        );
-        self.events.notify(
-            Event::EvaluationDurationCount(
-                target_branch.clone()
-            )
-        );
-
-        overall_status.set_with_description("Fetching PR", hubcaps::statuses::State::Pending);
-
-        co.fetch_pr(job.pr.number).unwrap();
-
-        if !co.commit_exists(job.pr.head_sha.as_ref()) {
-            overall_status.set_with_description(
-                "Commit not found",
-                hubcaps::statuses::State::Error,
+        } else {
+            self.cache_out_paths(&base_tree, &rebuildsniff.original);
+            self.events.notify(
+                Event::EvaluationDuration(
+                    target_branch.clone(),
+                    target_branch_rebuild_sniff_start.elapsed().as_secs(),
+                )
             );
+            self.events.notify(
+                Event::EvaluationDurationCount(
+                    target_branch.clone()
+                )
+            );
+        }
 
-            info!("Commit {} doesn't exist", job.pr.head_sha);
+        if self.stop_if_cancelled(&job, &mut overall_status) {
             return self.actions().skip(&job);
         }
 
@@ -251,9 +1186,11 @@ impl<E: stats::SysEvents + 'static> worker::SimpleWorker for MassRebuildWorker<E
                 vec!["".to_owned()],
             ));
 
-        self.tag_from_paths(
-            &issue,
-            co.files_changed_from_head(&job.pr.head_sha).unwrap_or(vec![])
+        let changed_files = co.files_changed_from_head(&job.pr.head_sha).unwrap_or(vec![]);
+        self.tag_from_paths(&issue, changed_files.clone());
+        // Before merging, while HEAD is still the target branch.
+        let changed_lines = co.lines_changed_from_head(&job.pr.head_sha).unwrap_or(
+            HashMap::new(),
         );
 
         overall_status.set_with_description("Merging PR", hubcaps::statuses::State::Pending);
@@ -268,6 +1205,8 @@ impl<E: stats::SysEvents + 'static> worker::SimpleWorker for MassRebuildWorker<E
             return self.actions().skip(&job);
         }
 
+        let merged_tree = co.tree_hash("HEAD").ok();
+
         overall_status.set_with_description(
             "Checking new stdenvs",
             hubcaps::statuses::State::Pending,
@@ -275,18 +1214,32 @@ impl<E: stats::SysEvents + 'static> worker::SimpleWorker for MassRebuildWorker<E
 
         stdenvs.identify_after();
 
+        // A changed stdenv rebuilds essentially everything, so the
+        // exact list of changed outputs isn't worth evaluating.
+        let treewide = !stdenvs.are_same();
+
         overall_status.set_with_description(
             "Checking new out paths",
             hubcaps::statuses::State::Pending,
         );
 
-        if let Err(mut output) = rebuildsniff.find_after() {
+        if treewide {
+            info!("stdenv changed, skipping the out path comparison");
+        } else if let Some(paths) = self.cached_out_paths(&merged_tree) {
+            info!("Reusing cached out paths of the merged PR");
+            rebuildsniff.current = Some(paths);
+        } else if let Err(mut output) = rebuildsniff.find_after() {
+            let output = file_to_str(&mut output);
             overall_status.set_url(make_gist(
                 &gists,
                 "Output path comparison".to_owned(),
                 Some("".to_owned()),
-                file_to_str(&mut output),
+                output.clone(),
             ));
+
+            if let Some(actions) = self.handle_memory_limit(&job, &mut overall_status, &output) {
+                return actions;
+            }
             overall_status.set_with_description(
                 format!(
                     "Failed to enumerate outputs after merging to {}",
@@ -295,109 +1248,105 @@ impl<E: stats::SysEvents + 'static> worker::SimpleWorker for MassRebuildWorker<E
                 hubcaps::statuses::State::Failure,
             );
             return self.actions().skip(&job);
+        } else {
+            self.cache_out_paths(&merged_tree, &rebuildsniff.current);
+        }
+
+        if self.stop_if_cancelled(&job, &mut overall_status) {
+            return self.actions().skip(&job);
         }
 
-        println!("Got path: {:?}, building", refpath);
+        let security_updates = self.security_updates(&rebuildsniff);
+        let attr_count = rebuildsniff.current.as_ref().map_or(0, |paths| paths.len() as u64);
+        let mut changed_outpath_count: u64 = 0;
+
+        self.check_formatting(&repo, &gists, &job, Path::new(&refpath), &changed_files);
+
+        debug!("Got path: {:?}, building", refpath);
         overall_status.set_with_description(
             "Beginning Evaluations",
             hubcaps::statuses::State::Pending,
         );
 
-        let eval_checks = vec![
-            EvalChecker::new(
-                "package-list",
-                nix::Operation::QueryPackagesJSON,
-                vec![
-                    String::from("--file"),
-                    String::from("."),
-                ],
-                self.nix.clone()
-            ),
-
-            EvalChecker::new(
-                "nixos-options",
-                nix::Operation::Instantiate,
-                vec![
-                    String::from("./nixos/release.nix"),
-                    String::from("-A"),
-                    String::from("options"),
-                ],
-                self.nix.clone()
-            ),
-
-            EvalChecker::new(
-                "nixos-manual",
-                nix::Operation::Instantiate,
-                vec![
-                    String::from("./nixos/release.nix"),
-                    String::from("-A"),
-                    String::from("manual"),
-                ],
-                self.nix.clone()
-            ),
-
-            EvalChecker::new(
-                "nixpkgs-manual",
-                nix::Operation::Instantiate,
-                vec![
-                    String::from("./pkgs/top-level/release.nix"),
-                    String::from("-A"),
-                    String::from("manual"),
-                ],
-                self.nix.clone()
-            ),
+        let mut eval_checks = eval_checks(&self.nix, &changed_files);
 
-            EvalChecker::new(
-                "nixpkgs-tarball",
-                nix::Operation::Instantiate,
-                vec![
-                    String::from("./pkgs/top-level/release.nix"),
-                    String::from("-A"),
-                    String::from("tarball"),
-                ],
-                self.nix.clone()
-            ),
-
-            EvalChecker::new(
-                "nixpkgs-unstable-jobset",
-                nix::Operation::Instantiate,
-                vec![
-                    String::from("./pkgs/top-level/release.nix"),
-                    String::from("-A"),
-                    String::from("unstable"),
-                ],
-                self.nix.clone()
-            ),
-        ];
+        eval_checks.retain(|check| {
+            policy.runs_check(&check.short_name()) &&
+                self.repo_runs_check(&job.repo.full_name, &check.short_name())
+        });
 
+        let mut eval_errors: Vec<EvalError> = vec![];
+        let mut eval_exceeded_memory: Option<String> = None;
+        let mut check_durations: Vec<(String, u64)> = vec![];
         let mut eval_results: bool = eval_checks
             .into_iter()
             .map(|check| {
-                let mut status = CommitStatus::new(
-                    repo.statuses(),
+                // The evaluations don't update it, so what it held
+                // back before them is sent in between.
+                overall_status.flush();
+
+                let mut status = self.commit_status(
+                    &repo,
+                    &job.repo.full_name,
                     job.pr.head_sha.clone(),
                     check.name(),
                     check.cli_cmd(),
                     None,
                 );
 
+                if self.cancellations.is_cancelled(&job.repo, &job.pr) {
+                    status.set_with_description(
+                        "Cancelled by request",
+                        hubcaps::statuses::State::Error,
+                    );
+                    return Err(());
+                }
+
                 status.set(hubcaps::statuses::State::Pending);
 
+                let cached = merged_tree.as_ref().and_then(|tree| {
+                    self.eval_cache.get::<bool>(tree, &check.name())
+                });
+
                 let state: hubcaps::statuses::State;
                 let gist_url: Option<String>;
-                match check.execute(Path::new(&refpath)) {
-                    Ok(_) => {
-                        state = hubcaps::statuses::State::Success;
-                        gist_url = None;
-                    }
-                    Err(mut out) => {
-                        state = hubcaps::statuses::State::Failure;
-                        gist_url = make_gist(
-                            &gists,
-                            check.name(),
-                            Some(format!("{:?}", state)),
-                            file_to_str(&mut out),
-                        );
+                if cached == Some(true) {
+                    info!("Reusing cached result of {}", check.name());
+                    state = hubcaps::statuses::State::Success;
+                    gist_url = None;
+                } else {
+                    let check_start = Instant::now();
+                    let result = check.execute(Path::new(&refpath));
+                    check_durations.push((check.short_name(), check_start.elapsed().as_secs()));
+
+                    match result {
+                        Ok(_) => {
+                            // Only successes are cached, so a flaky
+                            // failure is retried on the next evaluation.
+                            if let Some(ref tree) = merged_tree {
+                                self.eval_cache.put(tree, &check.name(), &true);
+                            }
+                            state = hubcaps::statuses::State::Success;
+                            gist_url = None;
+                        }
+                        Err(mut out) => {
+                            let output = file_to_str(&mut out);
+                            eval_errors.extend(evalerrors::parse(&output, &refpath));
+                            if nix::exceeded_memory_limit(&output) {
+                                status.set_description(
+                                    String::from("evaluation exceeded memory limit"),
+                                );
+                                eval_exceeded_memory = Some(output.clone());
+                            }
+
+                            state = hubcaps::statuses::State::Failure;
+                            gist_url = make_gist(
+                                &gists,
+                                check.name(),
+                                Some(format!("{:?}", state)),
+                                output,
+                            );
+                        }
                     }
                 }
 
@@ -412,12 +1361,27 @@ impl<E: stats::SysEvents + 'static> worker::SimpleWorker for MassRebuildWorker<E
             })
             .all(|status| status == Ok(()));
 
+        if let Some(output) = eval_exceeded_memory {
+            if let Some(actions) = self.handle_memory_limit(&job, &mut overall_status, &output) {
+                return actions;
+            }
+        }
+
+        if self.stop_if_cancelled(&job, &mut overall_status) {
+            return self.actions().skip(&job);
+        }
+
+        if eval_errors.len() > 0 {
+            self.annotate_eval_errors(&job, &changed_lines, eval_errors, &mut overall_status);
+        }
+
 
         let mut response: worker::Actions = vec![];
 
         if eval_results {
-            let mut status = CommitStatus::new(
-                repo.statuses(),
+            let mut status = self.commit_status(
+                &repo,
+                &job.repo.full_name,
                 job.pr.head_sha.clone(),
                 String::from("grahamcofborg-eval-check-meta"),
                 String::from("config.nix: checkMeta = true"),
@@ -429,7 +1393,9 @@ impl<E: stats::SysEvents + 'static> worker::SimpleWorker for MassRebuildWorker<E
             let state: hubcaps::statuses::State;
             let gist_url: Option<String>;
 
-            let checker = OutPaths::new(self.nix.clone(), PathBuf::from(&refpath), true);
+            let checker = OutPaths::new(self.nix.clone(), PathBuf::from(&refpath), true)
+                .with_parallelism(self.outpath_parallelism)
+                .with_systems(self.outpath_systems.clone());
             match checker.find() {
                 Ok(pkgs) => {
                     state = hubcaps::statuses::State::Success;
@@ -442,6 +1408,29 @@ impl<E: stats::SysEvents + 'static> worker::SimpleWorker for MassRebuildWorker<E
                     try_build.sort();
                     try_build.dedup();
 
+                    if security_updates.len() > 0 && security_updates.len() <= 10 {
+                        // Prioritized, so builders pick up the security
+                        // fixes before the other builds waiting.
+                        let msg = buildjob::BuildJob::new(
+                            job.repo.clone(),
+                            job.pr.clone(),
+                            Subset::Nixpkgs,
+                            security_updates.clone(),
+                            None,
+                            None,
+                        ).requested_by(&pr_author)
+                            .correlated(&job.correlation());
+                        for &(ref dest, ref rk) in auto_schedule_build_archs.iter() {
+                            response.push(worker::publish_prioritized_action(
+                                dest.clone(),
+                                rk.clone(),
+                                &msg,
+                                buildjob::MAX_PRIORITY,
+                            ));
+                        }
+                        try_build.retain(|pkg| !security_updates.contains(pkg));
+                    }
+
                     if try_build.len() > 0 && try_build.len() <= 10 {
                         // In the case of trying to merge master in to
                         // a stable branch, we don't want to do this.
@@ -454,7 +1443,8 @@ impl<E: stats::SysEvents + 'static> worker::SimpleWorker for MassRebuildWorker<E
                             try_build,
                             None,
                             None,
-                        );
+                        ).requested_by(&pr_author)
+                            .correlated(&job.correlation());
                         for (dest, rk) in auto_schedule_build_archs {
                             response.push(worker::publish_serde_action(dest, rk, &msg));
                         }
@@ -492,6 +1482,10 @@ impl<E: stats::SysEvents + 'static> worker::SimpleWorker for MassRebuildWorker<E
                 stdenvtagger.tags_to_remove(),
             );
 
+            if security_updates.len() > 0 {
+                update_labels(&issue, vec![String::from("security")], vec![]);
+            }
+
             if let Some((removed, added)) = rebuildsniff.package_diff() {
             let mut addremovetagger = PkgsAddedRemovedTagger::new();
                 addremovetagger.changed(removed, added);
@@ -502,8 +1496,16 @@ impl<E: stats::SysEvents + 'static> worker::SimpleWorker for MassRebuildWorker<E
                 );
             }
 
-            let mut rebuild_tags = RebuildTagger::new();
-            if let Some(attrs) = rebuildsniff.calculate_rebuild() {
+            let mut rebuild_tags = self.rebuild_tagger(&policy);
+            let mut changed_packages: Vec<String> = vec![];
+            if treewide {
+                rebuild_tags.treewide(stdenvs.changed());
+            } else if let Some(attrs) = rebuildsniff.calculate_rebuild() {
+                changed_packages = attrs.iter().map(|attr| attr.package.clone()).collect();
+                changed_packages.sort();
+                changed_packages.dedup();
+                changed_outpath_count = attrs.len() as u64;
+
                 if attrs.len() > 0 {
                     let gist_url = make_gist(
                         &gists,
@@ -517,6 +1519,8 @@ impl<E: stats::SysEvents + 'static> worker::SimpleWorker for MassRebuildWorker<E
                     );
 
                     overall_status.set_url(gist_url);
+
+                    self.post_summary(&job, &EvalSummary::new(job.pr.head_sha.clone(), &attrs));
                 }
 
                 rebuild_tags.parse_attrs(attrs);
@@ -528,6 +1532,27 @@ impl<E: stats::SysEvents + 'static> worker::SimpleWorker for MassRebuildWorker<E
                 rebuild_tags.tags_to_remove(),
             );
 
+            self.lint_changed_meta(
+                &repo,
+                &gists,
+                &job,
+                Path::new(&refpath),
+                &changed_packages,
+            );
+            self.check_aliases(
+                &repo,
+                &gists,
+                &job,
+                Path::new(&refpath),
+                &project,
+                &base_commit,
+                &changed_packages,
+                &changed_files,
+            );
+            if !treewide {
+                self.ping_maintainers(&job, &pr_author, Path::new(&refpath), changed_packages);
+            }
+
             overall_status.set_with_description("^.^!", hubcaps::statuses::State::Success);
 
         } else {
@@ -537,12 +1562,116 @@ impl<E: stats::SysEvents + 'static> worker::SimpleWorker for MassRebuildWorker<E
             );
         }
 
+        let evaluation_secs = evaluation_start.elapsed().as_secs();
+        self.events.notify_all(
+            stats::EvaluationStats {
+                branch: target_branch.clone(),
+                duration_secs: evaluation_secs,
+                peak_memory_mb: nix::take_peak_memory_mb(),
+                attrs: attr_count,
+                changed_outpaths: changed_outpath_count,
+                check_durations: check_durations,
+            }.events(),
+        );
+        self.events.notify_all(self.github_api.take_usage().events());
+        self.events.notify(Event::UsageEvalSeconds(
+            job.repo.full_name.clone(),
+            pr_author.clone(),
+            evaluation_secs,
+        ));
         self.events.notify(Event::TaskEvaluationCheckComplete);
 
         return self.actions().done(&job, response);
     }
 }
 
+/// The evaluations a PR must pass, by what it changes.
+pub fn eval_checks(nix: &nix::Nix, changed_files: &Vec<String>) -> Vec<EvalChecker> {
+    let mut eval_checks = vec![
+        EvalChecker::new(
+            "package-list",
+            nix::Operation::QueryPackagesJSON,
+            vec![
+                String::from("--file"),
+                String::from("."),
+            ],
+            nix.clone()
+        ),
+
+        EvalChecker::new(
+            "nixos-options",
+            nix::Operation::Instantiate,
+            vec![
+                String::from("./nixos/release.nix"),
+                String::from("-A"),
+                String::from("options"),
+            ],
+            nix.clone()
+        ),
+
+        EvalChecker::new(
+            "nixos-manual",
+            nix::Operation::Instantiate,
+            vec![
+                String::from("./nixos/release.nix"),
+                String::from("-A"),
+                String::from("manual"),
+            ],
+            nix.clone()
+        ),
+
+        EvalChecker::new(
+            "nixpkgs-manual",
+            nix::Operation::Instantiate,
+            vec![
+                String::from("./pkgs/top-level/release.nix"),
+                String::from("-A"),
+                String::from("manual"),
+            ],
+            nix.clone()
+        ),
+
+        EvalChecker::new(
+            "nixpkgs-tarball",
+            nix::Operation::Instantiate,
+            vec![
+                String::from("./pkgs/top-level/release.nix"),
+                String::from("-A"),
+                String::from("tarball"),
+            ],
+            nix.clone()
+        ),
+
+        EvalChecker::new(
+            "nixpkgs-unstable-jobset",
+            nix::Operation::Instantiate,
+            vec![
+                String::from("./pkgs/top-level/release.nix"),
+                String::from("-A"),
+                String::from("unstable"),
+            ],
+            nix.clone()
+        ),
+    ];
+
+    if touches_nixos(changed_files) {
+        // Instantiating the options isn't enough to catch broken
+        // option descriptions and defaults, only building them is.
+        eval_checks.push(EvalChecker::new(
+            "nixos-options-build",
+            nix::Operation::Build,
+            vec![
+                String::from("./nixos/release.nix"),
+                String::from("-A"),
+                String::from("options"),
+            ],
+            nix.clone()
+        ));
+    }
+
+    return eval_checks;
+}
+
 enum StdenvFrom {
     Before,
     After,
@@ -554,8 +1683,10 @@ pub enum System {
     X8664Linux,
 }
 
+/// The stdenvs of the base branch and of the merged PR, to tell
+/// whether the PR changes them.
 #[derive(Debug, PartialEq)]
-struct Stdenvs {
+pub struct Stdenvs {
     nix: nix::Nix,
     co: PathBuf,
 
@@ -567,7 +1698,7 @@ struct Stdenvs {
 }
 
 impl Stdenvs {
-    fn new(nix: nix::Nix, co: PathBuf) -> Stdenvs {
+    pub fn new(nix: nix::Nix, co: PathBuf) -> Stdenvs {
         return Stdenvs {
             nix: nix,
             co: co,
@@ -580,21 +1711,21 @@ impl Stdenvs {
         };
     }
 
-    fn identify_before(&mut self) {
+    pub fn identify_before(&mut self) {
         self.identify(System::X8664Linux, StdenvFrom::Before);
         self.identify(System::X8664Darwin, StdenvFrom::Before);
     }
 
-    fn identify_after(&mut self) {
+    pub fn identify_after(&mut self) {
         self.identify(System::X8664Linux, StdenvFrom::After);
         self.identify(System::X8664Darwin, StdenvFrom::After);
     }
 
-    fn are_same(&self) -> bool {
+    pub fn are_same(&self) -> bool {
         return self.changed().len() == 0;
     }
 
-    fn changed(&self) -> Vec<System> {
+    pub fn changed(&self) -> Vec<System> {
         let mut changed: Vec<System> = vec![];
 
         if self.linux_stdenv_before != self.linux_stdenv_after {
@@ -628,23 +1759,20 @@ impl Stdenvs {
     }
 
     fn evalstdenv(&self, system: &str) -> Option<String> {
-        let result = self.nix.with_system(system.to_owned()).safely(
-            nix::Operation::Instantiate,
+        let result: Result<String, File> = self.nix.with_system(system.to_owned()).evaluate_json(
             &self.co,
             vec![
-                String::from("."),
-                String::from("-A"),
-                String::from("stdenv"),
+                String::from("-E"),
+                String::from("{ ... }@args: (import ./. args).stdenv.drvPath"),
             ],
-            true,
         );
 
-        println!("{:?}", result);
+        debug!("The stdenv of {}: {:?}", system, result);
 
         return match result {
-            Ok(mut out) => file_to_drv(&mut out),
+            Ok(drv) => Some(drv),
             Err(mut out) => {
-                println!("{:?}", file_to_str(&mut out));
+                warn!("Failed to evaluate the stdenv of {}: {:?}", system, file_to_str(&mut out));
                 None
             }
         };
@@ -679,16 +1807,25 @@ fn make_gist<'a>(
 }
 
 pub fn update_labels(issue: &hubcaps::issues::IssueRef, add: Vec<String>, remove: Vec<String>) {
+    try_update_labels(issue, add, remove).expect("Failed to update labels");
+}
+
+/// Like `update_labels`, for callers which can't crash when GitHub
+/// refuses.
+pub fn try_update_labels(
+    issue: &hubcaps::issues::IssueRef,
+    add: Vec<String>,
+    remove: Vec<String>,
+) -> hubcaps::Result<()> {
     let l = issue.labels();
 
     let existing: Vec<String> = issue
-        .get()
-        .unwrap()
+        .get()?
         .labels
         .iter()
         .map(|l| l.name.clone())
         .collect();
-    println!("Already: {:?}", existing);
+    debug!("Already: {:?}", existing);
     let to_add = add
         .iter()
         .filter(|l| !existing.contains(l)) // Remove labels already on the issue
@@ -702,42 +1839,13 @@ pub fn update_labels(issue: &hubcaps::issues::IssueRef, add: Vec<String>, remove
         .collect();
     info!("Removing labels: {:?}", to_remove);
 
-    l.add(to_add).expect("Failed to add tags");
+    l.add(to_add)?;
 
     for label in to_remove {
-        l.remove(&label).expect("Failed to remove tag");
+        l.remove(&label)?;
     }
-}
-
-fn file_to_drv(f: &mut File) -> Option<String> {
-    let r = BufReader::new(f);
-    let matches: Vec<String>;
-    matches = r.lines()
-        .filter_map(|x| match x {
-            Ok(line) => {
-                if !line.starts_with("/nix/store/") {
-                    debug!("Skipping line, not /nix/store: {}", line);
-                    return None;
-                }
-
-                if !line.ends_with(".drv") {
-                    debug!("Skipping line, not .drv: {}", line);
-                    return None;
-                }
 
-                return Some(line);
-            }
-            Err(_) => None,
-        })
-        .collect();
-
-    if matches.len() == 1 {
-        return Some(matches.first().unwrap().clone());
-    } else {
-        info!("Got wrong number of matches: {}", matches.len());
-        info!("Matches: {:?}", matches);
-        return None;
-    }
+    return Ok(());
 }
 
 fn file_to_str(f: &mut File) -> String {
@@ -766,6 +1874,10 @@ fn parse_commit_messages(messages: Vec<String>) -> Vec<String> {
         .collect()
 }
 
+fn touches_nixos(changed_files: &Vec<String>) -> bool {
+    changed_files.iter().any(|path| path.starts_with("nixos/"))
+}
+
 #[cfg(test)]
 mod tests {
 
@@ -797,6 +1909,18 @@ mod tests {
         assert!(stdenv.are_same());
     }
 
+    #[test]
+    fn test_touches_nixos() {
+        assert!(touches_nixos(&vec![
+            "pkgs/top-level/all-packages.nix".to_owned(),
+            "nixos/modules/services/misc/foo.nix".to_owned(),
+        ]));
+        assert!(!touches_nixos(&vec![
+            "pkgs/os-specific/linux/nixos-rebuild/default.nix".to_owned(),
+        ]));
+        assert!(!touches_nixos(&vec![]));
+    }
+
     #[test]
     fn test_parse_commit_messages() {
         let expect: Vec<&str> = vec![
@@ -834,29 +1958,3 @@ mod tests {
         );
     }
 }
-
-fn issue_is_wip(issue: &hubcaps::issues::Issue) -> bool {
-    if issue.title.contains("[WIP]") {
-        return true;
-    }
-
-    if issue.title.starts_with("WIP:") {
-        return true;
-    }
-
-    issue.labels.iter().any(|label| indicates_wip(&label.name))
-}
-
-fn indicates_wip(text: &str) -> bool {
-    let text = text.to_lowercase();
-
-    if text.contains("work in progress") {
-        return true;
-    }
-
-    if text.contains("work-in-progress") {
-        return true;
-    }
-
-    return false;
-}
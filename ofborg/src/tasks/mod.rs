@@ -6,3 +6,9 @@ pub mod githubcommentposter;
 pub mod statscollector;
 pub mod log_message_collector;
 pub mod evaluationfilter;
+pub mod basewatcher;
+pub mod checkrunfilter;
+pub mod statusreaper;
+pub mod auditlogger;
+pub mod queuemonitor;
+pub mod failurereport;
@@ -0,0 +1,373 @@
+use std::collections::HashSet;
+use std::io::Read;
+use hyper::Client;
+use hyper::header::{Authorization, Basic, Headers, UserAgent};
+use hyper::net::HttpsConnector;
+use hyper_native_tls::NativeTlsClient;
+use serde_json;
+
+use ofborg::config::{QueueMonitorConfig, RabbitMQConfig};
+use ofborg::message::queuealert::QueueAlert;
+use ofborg::stats;
+
+/// Polls RabbitMQ's management API for how deep our queues are and
+/// how many consumers work on them, raising an alert once a queue
+/// backs up past its threshold. A queue is alerted about once, and
+/// again after it drained below the threshold in between.
+pub struct QueueMonitor {
+    client: Client,
+    url: String,
+    rabbitmq: RabbitMQConfig,
+    config: QueueMonitorConfig,
+    backlogged: HashSet<String>,
+    metrics: Option<QueueMetrics>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct QueueDepth {
+    pub name: String,
+    #[serde(default)]
+    pub messages: u64,
+    #[serde(default)]
+    pub messages_ready: u64,
+    #[serde(default)]
+    pub messages_unacknowledged: u64,
+    #[serde(default)]
+    pub consumers: u64,
+}
+
+struct QueueMetrics {
+    messages: stats::Gauge,
+    ready: stats::Gauge,
+    unacknowledged: stats::Gauge,
+    consumers: stats::Gauge,
+}
+
+impl QueueMonitor {
+    pub fn new(rabbitmq: &RabbitMQConfig, config: QueueMonitorConfig) -> QueueMonitor {
+        let management_url = config.management_url.clone().unwrap_or(format!(
+            "{}://{}:{}",
+            if rabbitmq.ssl { "https" } else { "http" },
+            rabbitmq.host,
+            if rabbitmq.ssl { 15671 } else { 15672 }
+        ));
+
+        return QueueMonitor {
+            client: Client::with_connector(HttpsConnector::new(NativeTlsClient::new().unwrap())),
+            url: queues_url(
+                &management_url,
+                rabbitmq.virtualhost.as_ref().map(|v| v.as_str()).unwrap_or("/"),
+            ),
+            rabbitmq: rabbitmq.clone(),
+            config: config,
+            backlogged: HashSet::new(),
+            metrics: None,
+        };
+    }
+
+    pub fn register_metrics(&mut self, registry: &stats::Registry) {
+        self.metrics = Some(QueueMetrics {
+            messages: registry.gauge("ofborg_queue_messages", "Messages in a queue"),
+            ready: registry.gauge(
+                "ofborg_queue_messages_ready",
+                "Messages in a queue waiting for a consumer",
+            ),
+            unacknowledged: registry.gauge(
+                "ofborg_queue_messages_unacknowledged",
+                "Messages of a queue being worked on",
+            ),
+            consumers: registry.gauge("ofborg_queue_consumers", "Consumers of a queue"),
+        });
+    }
+
+    /// The depths of our queues.
+    pub fn poll(&self) -> Result<Vec<QueueDepth>, String> {
+        let mut headers = Headers::new();
+        headers.set(UserAgent("github.com/grahamc/ofborg".to_owned()));
+        headers.set(Authorization(Basic {
+            username: self.rabbitmq.username.clone(),
+            password: Some(self.rabbitmq.current_password()),
+        }));
+
+        let mut response = self.client
+            .get(self.url.as_str())
+            .headers(headers)
+            .send()
+            .map_err(|e| format!("{:?}", e))?;
+
+        let mut contents = String::new();
+        response.read_to_string(&mut contents).map_err(
+            |e| format!("{:?}", e),
+        )?;
+        if !response.status.is_success() {
+            return Err(format!("{}: {}", response.status, contents));
+        }
+
+        let depths: Vec<QueueDepth> = serde_json::from_str(&contents).map_err(|e| {
+            format!("{:?}", e)
+        })?;
+
+        return Ok(depths.into_iter().filter(|depth| self.ours(&depth.name)).collect());
+    }
+
+    fn ours(&self, queue: &str) -> bool {
+        match self.config.queues {
+            Some(ref prefixes) => prefixes.iter().any(|prefix| queue.starts_with(prefix)),
+            None => true,
+        }
+    }
+
+    /// The alert threshold of `queue`, by the longest prefix matching.
+    fn threshold(&self, queue: &str) -> Option<u64> {
+        self.config.backlog_alerts.as_ref().and_then(|alerts| {
+            alerts
+                .iter()
+                .filter(|&(prefix, _)| queue.starts_with(prefix.as_str()))
+                .max_by_key(|&(prefix, _)| prefix.len())
+                .map(|(_, threshold)| *threshold)
+        })
+    }
+
+    pub fn record(&self, depths: &[QueueDepth]) {
+        if let Some(ref metrics) = self.metrics {
+            for depth in depths {
+                let labels = [("queue", depth.name.as_str())];
+                metrics.messages.set(&labels, depth.messages as f64);
+                metrics.ready.set(&labels, depth.messages_ready as f64);
+                metrics.unacknowledged.set(
+                    &labels,
+                    depth.messages_unacknowledged as f64,
+                );
+                metrics.consumers.set(&labels, depth.consumers as f64);
+            }
+        }
+    }
+
+    /// Alerts of the queues which newly backed up past their threshold.
+    pub fn alerts(&mut self, depths: &[QueueDepth], now: u64) -> Vec<QueueAlert> {
+        let mut alerts = vec![];
+
+        for depth in depths {
+            let threshold = match self.threshold(&depth.name) {
+                Some(threshold) => threshold,
+                None => continue,
+            };
+
+            if depth.messages_ready < threshold {
+                self.backlogged.remove(&depth.name);
+            } else if self.backlogged.insert(depth.name.clone()) {
+                alerts.push(QueueAlert::new(
+                    depth.name.clone(),
+                    depth.messages_ready,
+                    depth.consumers,
+                    threshold,
+                    now,
+                ));
+            }
+        }
+
+        return alerts;
+    }
+}
+
+/// The depths as a table, deepest first.
+pub fn table(depths: &[QueueDepth]) -> String {
+    let mut depths: Vec<&QueueDepth> = depths.iter().collect();
+    depths.sort_by(|a, b| {
+        b.messages_ready.cmp(&a.messages_ready).then(
+            a.name.cmp(&b.name),
+        )
+    });
+
+    let width = depths.iter().map(|d| d.name.len()).max().unwrap_or(0).max(5);
+    let mut lines: Vec<String> = vec![
+        format!(
+            "{:<width$} {:>8} {:>8} {:>8} {:>9}",
+            "queue",
+            "messages",
+            "ready",
+            "unacked",
+            "consumers",
+            width = width
+        ),
+    ];
+    for depth in depths {
+        lines.push(format!(
+            "{:<width$} {:>8} {:>8} {:>8} {:>9}",
+            depth.name,
+            depth.messages,
+            depth.messages_ready,
+            depth.messages_unacknowledged,
+            depth.consumers,
+            width = width
+        ));
+    }
+
+    return lines.join("\n");
+}
+
+/// The management API's list of the queues in `vhost`.
+fn queues_url(management_url: &str, vhost: &str) -> String {
+    let vhost: String = vhost
+        .bytes()
+        .map(|b| match b {
+            b'a'...b'z' | b'A'...b'Z' | b'0'...b'9' | b'-' | b'_' | b'.' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{:02X}", b),
+        })
+        .collect();
+
+    return format!(
+        "{}/api/queues/{}?columns=name,messages,messages_ready,messages_unacknowledged,consumers",
+        management_url.trim_right_matches('/'),
+        vhost
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn monitor(queues: Option<Vec<&str>>, alerts: Vec<(&str, u64)>) -> QueueMonitor {
+        let rabbitmq = RabbitMQConfig {
+            ssl: true,
+            host: String::from("events.nix.ci"),
+            virtualhost: Some(String::from("ofborg")),
+            username: String::from("ofborg"),
+            password: String::from("secret"),
+            password_file: None,
+            password_secret: None,
+            secret: None,
+            signing_key_file: None,
+            signing_key: None,
+            signature_max_age_secs: None,
+            legacy_messages: false,
+        };
+
+        QueueMonitor::new(
+            &rabbitmq,
+            QueueMonitorConfig {
+                management_url: None,
+                interval_seconds: None,
+                queues: queues.map(|queues| queues.into_iter().map(String::from).collect()),
+                backlog_alerts: Some(
+                    alerts
+                        .into_iter()
+                        .map(|(prefix, threshold)| (String::from(prefix), threshold))
+                        .collect::<HashMap<String, u64>>(),
+                ),
+            },
+        )
+    }
+
+    fn depth(name: &str, ready: u64) -> QueueDepth {
+        QueueDepth {
+            name: String::from(name),
+            messages: ready + 1,
+            messages_ready: ready,
+            messages_unacknowledged: 1,
+            consumers: 2,
+        }
+    }
+
+    #[test]
+    fn test_queues_url() {
+        assert_eq!(
+            queues_url("https://events.nix.ci:15671/", "/"),
+            "https://events.nix.ci:15671/api/queues/%2F?columns=name,messages,messages_ready,messages_unacknowledged,consumers"
+        );
+        assert_eq!(
+            monitor(None, vec![]).url,
+            "https://events.nix.ci:15671/api/queues/ofborg?columns=name,messages,messages_ready,messages_unacknowledged,consumers"
+        );
+    }
+
+    #[test]
+    fn test_parse_depths() {
+        let depths: Vec<QueueDepth> = serde_json::from_str(
+            r#"[
+  {"name": "build-inputs-x86_64-linux", "messages": 12, "messages_ready": 10,
+   "messages_unacknowledged": 2, "consumers": 2},
+  {"name": "github-events-unknown"}
+]"#,
+        ).unwrap();
+
+        assert_eq!(
+            depths,
+            vec![
+                QueueDepth {
+                    name: String::from("build-inputs-x86_64-linux"),
+                    messages: 12,
+                    messages_ready: 10,
+                    messages_unacknowledged: 2,
+                    consumers: 2,
+                },
+                QueueDepth {
+                    name: String::from("github-events-unknown"),
+                    messages: 0,
+                    messages_ready: 0,
+                    messages_unacknowledged: 0,
+                    consumers: 0,
+                },
+            ]
+        );
+
+        let monitor = monitor(Some(vec!["build-inputs-"]), vec![]);
+        assert!(monitor.ours("build-inputs-aarch64-linux"));
+        assert!(!monitor.ours("github-events-unknown"));
+
+        assert_eq!(
+            table(&depths),
+            "queue                     messages    ready  unacked consumers
+build-inputs-x86_64-linux       12       10        2         2
+github-events-unknown            0        0        0         0"
+        );
+    }
+
+    #[test]
+    fn test_alerts() {
+        let mut monitor = monitor(
+            None,
+            vec![("build-inputs-", 100), ("build-inputs-aarch64-", 20)],
+        );
+
+        assert_eq!(monitor.threshold("build-inputs-x86_64-linux"), Some(100));
+        assert_eq!(monitor.threshold("build-inputs-aarch64-linux"), Some(20));
+        assert_eq!(monitor.threshold("mass-rebuild-check-jobs"), None);
+
+        let alerts = monitor.alerts(
+            &[
+                depth("build-inputs-x86_64-linux", 50),
+                depth("build-inputs-aarch64-linux", 25),
+                depth("mass-rebuild-check-jobs", 5000),
+            ],
+            1000,
+        );
+        assert_eq!(
+            alerts,
+            vec![
+                QueueAlert::new(String::from("build-inputs-aarch64-linux"), 25, 2, 20, 1000),
+            ]
+        );
+
+        // Still backed up, which was alerted about already.
+        assert_eq!(
+            monitor.alerts(&[depth("build-inputs-aarch64-linux", 30)], 1060),
+            Vec::<QueueAlert>::new()
+        );
+
+        // Drained, and backed up again.
+        assert_eq!(
+            monitor.alerts(&[depth("build-inputs-aarch64-linux", 3)], 1120),
+            Vec::<QueueAlert>::new()
+        );
+        assert_eq!(
+            monitor
+                .alerts(&[depth("build-inputs-aarch64-linux", 40)], 1180)
+                .len(),
+            1
+        );
+    }
+}
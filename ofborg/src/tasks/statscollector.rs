@@ -2,6 +2,7 @@ extern crate amqp;
 extern crate env_logger;
 
 use serde_json;
+use ofborg::message::envelope;
 use ofborg::worker;
 use ofborg::stats;
 use amqp::protocol::basic::{Deliver, BasicProperties};
@@ -9,6 +10,7 @@ use amqp::protocol::basic::{Deliver, BasicProperties};
 pub struct StatCollectorWorker<E> {
     events: E,
     collector: stats::MetricCollector,
+    usage: Option<stats::UsageLedger>,
 }
 
 impl<E: stats::SysEvents + 'static> StatCollectorWorker<E> {
@@ -16,8 +18,15 @@ impl<E: stats::SysEvents + 'static> StatCollectorWorker<E> {
         StatCollectorWorker {
             events: events,
             collector: collector,
+            usage: None,
         }
     }
+
+    /// Account the usage events in `ledger` too.
+    pub fn with_usage(mut self, ledger: stats::UsageLedger) -> StatCollectorWorker<E> {
+        self.usage = Some(ledger);
+        return self;
+    }
 }
 
 impl<E: stats::SysEvents + 'static> worker::SimpleWorker for StatCollectorWorker<E> {
@@ -26,10 +35,10 @@ impl<E: stats::SysEvents + 'static> worker::SimpleWorker for StatCollectorWorker
     fn msg_to_job(
         &mut self,
         _: &Deliver,
-        _: &BasicProperties,
+        props: &BasicProperties,
         body: &Vec<u8>,
     ) -> Result<Self::J, String> {
-        return match serde_json::from_slice(body) {
+        return match envelope::from_slice(body, worker::content_type(props)) {
             Ok(e) => Ok(e),
             Err(_) => {
                 let mut modified_body: Vec<u8> = vec!["\"".as_bytes()[0]];
@@ -62,6 +71,9 @@ impl<E: stats::SysEvents + 'static> worker::SimpleWorker for StatCollectorWorker
 
         let sender = job.sender.clone();
         for event in job.events.iter() {
+            if let Some(ref usage) = self.usage {
+                usage.record(event);
+            }
             self.collector.record(sender.clone(), event.clone());
         }
 
@@ -0,0 +1,235 @@
+use std::path::PathBuf;
+
+use ofborg::config::StatusReaperConfig;
+use ofborg::correlation::Correlation;
+use ofborg::githubapi::{GithubApi, CheckRunOptions, CheckRunOutput, NewStatus, PullSummary};
+use ofborg::message::{Repo, Pr, massrebuildjob};
+use ofborg::logindex;
+use ofborg::timestamp;
+
+/// Only statuses with this prefix are ours to clean up.
+const CONTEXT_PREFIX: &'static str = "grahamcofborg-";
+
+/// Finds our commit statuses and check runs which stayed pending for
+/// much longer than any evaluation takes, left behind by a crashed
+/// worker or a lost message, and marks them as errored. As statuses
+/// are updated throughout an evaluation, going without an update for
+/// hours means no attempt is working on it anymore. Unless a build's
+/// log says otherwise, when there is a log storage to look in.
+pub struct StatusReaper {
+    github_api: GithubApi,
+    config: StatusReaperConfig,
+    checks_api: bool,
+    log_root: Option<PathBuf>,
+}
+
+impl StatusReaper {
+    pub fn new(github_api: GithubApi, config: StatusReaperConfig, checks_api: bool) -> StatusReaper {
+        return StatusReaper {
+            github_api: github_api,
+            config: config,
+            checks_api: checks_api,
+            log_root: None,
+        };
+    }
+
+    /// Leave the PRs alone which a build is still writing the log of
+    /// under `log_root`, see `logindex::has_live_attempt`.
+    pub fn with_log_root(mut self, log_root: PathBuf) -> StatusReaper {
+        self.log_root = Some(log_root);
+        return self;
+    }
+
+    fn has_live_attempt(&self, repo: &str, pull: &PullSummary, now: u64) -> bool {
+        match self.log_root {
+            Some(ref root) => {
+                let routing_key = format!("{}.{}", repo, pull.number).to_lowercase();
+                logindex::has_live_attempt(root, &routing_key, now.saturating_sub(self.max_age_secs()))
+            }
+            None => false,
+        }
+    }
+
+    fn max_age_secs(&self) -> u64 {
+        self.config.max_pending_hours * 60 * 60
+    }
+
+    fn description(&self) -> String {
+        format!(
+            "No progress for {} hours, the attempt was probably lost",
+            self.config.max_pending_hours
+        )
+    }
+
+    /// Reap the abandoned statuses on `repo`'s open PRs, returning the
+    /// evaluations to queue again.
+    pub fn reap(&self, repo: &str) -> Vec<massrebuildjob::MassRebuildJob> {
+        let pulls = match self.github_api.all_open_pulls(repo) {
+            Ok(pulls) => pulls,
+            Err(e) => {
+                warn!("Failed to list open PRs of {}: {:?}", repo, e);
+                return vec![];
+            }
+        };
+
+        let now = timestamp::now();
+        let mut requeue = vec![];
+        for pull in pulls {
+            if self.has_live_attempt(repo, &pull, now) {
+                info!("Not reaping {}#{}, a build is still going", repo, pull.number);
+                continue;
+            }
+
+            let reaped = if self.checks_api {
+                self.reap_check_runs(repo, &pull, now)
+            } else {
+                self.reap_statuses(repo, &pull, now)
+            };
+
+            let eval_reaped = reaped.iter().any(
+                |context| context.starts_with("grahamcofborg-eval"),
+            );
+            if eval_reaped && self.config.requeue.unwrap_or(false) {
+                requeue.push(evaluation_job(&pull));
+            }
+        }
+
+        return requeue;
+    }
+
+    fn reap_statuses(&self, repo: &str, pull: &PullSummary, now: u64) -> Vec<String> {
+        let statuses = match self.github_api.commit_statuses(repo, &pull.head.sha) {
+            Ok(statuses) => statuses,
+            Err(e) => {
+                warn!("Failed to fetch the statuses of {}: {:?}", pull.head.sha, e);
+                return vec![];
+            }
+        };
+
+        let description = self.description();
+        let mut reaped = vec![];
+        for status in statuses {
+            if status.state != "pending" ||
+                !is_abandoned(&status.context, &status.updated_at, now, self.max_age_secs())
+            {
+                continue;
+            }
+
+            info!("Reaping {} on {}#{}", status.context, repo, pull.number);
+            let result = self.github_api.create_status(
+                repo,
+                &pull.head.sha,
+                &NewStatus {
+                    state: "error",
+                    context: &status.context,
+                    description: &description,
+                    target_url: status.target_url.as_ref().map(|url| url.as_str()),
+                },
+            );
+
+            match result {
+                Ok(()) => reaped.push(status.context),
+                Err(e) => warn!("Failed to reap {}: {:?}", status.context, e),
+            }
+        }
+
+        return reaped;
+    }
+
+    fn reap_check_runs(&self, repo: &str, pull: &PullSummary, now: u64) -> Vec<String> {
+        let runs = match self.github_api.check_runs(repo, &pull.head.sha) {
+            Ok(runs) => runs,
+            Err(e) => {
+                warn!("Failed to fetch the check runs of {}: {:?}", pull.head.sha, e);
+                return vec![];
+            }
+        };
+
+        let description = self.description();
+        let mut reaped = vec![];
+        for run in runs {
+            let started_at = match run.started_at {
+                Some(ref started_at) => started_at.clone(),
+                None => continue,
+            };
+
+            if run.status == "completed" ||
+                !is_abandoned(&run.name, &started_at, now, self.max_age_secs())
+            {
+                continue;
+            }
+
+            info!("Reaping {} on {}#{}", run.name, repo, pull.number);
+            let result = self.github_api.update_check_run(
+                repo,
+                run.id,
+                &CheckRunOptions {
+                    status: String::from("completed"),
+                    conclusion: Some(String::from("cancelled")),
+                    output: Some(CheckRunOutput {
+                        title: description.clone(),
+                        summary: description.clone(),
+                        annotations: vec![],
+                    }),
+                    ..Default::default()
+                },
+            );
+
+            match result {
+                Ok(_) => reaped.push(run.name),
+                Err(e) => warn!("Failed to reap {}: {:?}", run.name, e),
+            }
+        }
+
+        return reaped;
+    }
+}
+
+/// Re-evaluating a PR starts a correlation of its own, like a webhook.
+fn evaluation_job(pull: &PullSummary) -> massrebuildjob::MassRebuildJob {
+    let repo = &pull.base.repo;
+
+    return massrebuildjob::MassRebuildJob::new(
+        Repo {
+            clone_url: repo.clone_url.clone(),
+            full_name: repo.full_name.clone(),
+            owner: repo.owner.login.clone(),
+            name: repo.name.clone(),
+        },
+        Pr {
+            number: pull.number,
+            head_sha: pull.head.sha.clone(),
+            target_branch: Some(pull.base.git_ref.clone()),
+        },
+    ).correlated(&Correlation::new(None));
+}
+
+fn is_abandoned(context: &str, updated_at: &str, now: u64, max_age_secs: u64) -> bool {
+    if !context.starts_with(CONTEXT_PREFIX) {
+        return false;
+    }
+
+    match timestamp::parse(updated_at) {
+        Some(updated_at) => now > updated_at && now - updated_at > max_age_secs,
+        None => {
+            warn!("Unparseable timestamp {:?} on {}", updated_at, context);
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_abandoned() {
+        let updated_at = "2018-01-08T15:30:00Z";
+        let hours = 60 * 60;
+
+        assert!(is_abandoned("grahamcofborg-eval", updated_at, 1515425400 + 7 * hours, 6 * hours));
+        assert!(!is_abandoned("grahamcofborg-eval", updated_at, 1515425400 + 5 * hours, 6 * hours));
+        assert!(!is_abandoned("travis-ci", updated_at, 1515425400 + 7 * hours, 6 * hours));
+        assert!(!is_abandoned("grahamcofborg-eval", "soon", 1515425400, 6 * hours));
+    }
+}
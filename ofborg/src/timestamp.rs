@@ -0,0 +1,90 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Seconds since the epoch of a timestamp like `2018-01-08T15:30:00Z`,
+/// as GitHub formats them.
+pub fn parse(timestamp: &str) -> Option<u64> {
+    let timestamp = timestamp.trim_right_matches('Z');
+    let mut parts = timestamp.splitn(2, 'T');
+    let date: Vec<u64> = parts
+        .next()?
+        .split('-')
+        .map(|part| part.parse().ok())
+        .collect::<Option<Vec<u64>>>()?;
+    let time: Vec<u64> = parts
+        .next()?
+        .split(':')
+        .map(|part| part.parse().ok())
+        .collect::<Option<Vec<u64>>>()?;
+
+    if date.len() != 3 || time.len() != 3 || date[0] < 1970 {
+        return None;
+    }
+
+    return Some(
+        days_since_epoch(date[0], date[1], date[2]) * 86400 + time[0] * 3600 + time[1] * 60 +
+            time[2],
+    );
+}
+
+/// Days from 1970-01-01 to the given date in the proleptic Gregorian
+/// calendar.
+fn days_since_epoch(year: u64, month: u64, day: u64) -> u64 {
+    let (year, month) = if month <= 2 {
+        (year - 1, month + 9)
+    } else {
+        (year, month - 3)
+    };
+    let era = year / 400;
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * month + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+
+    return era * 146097 + day_of_era - 719468;
+}
+
+/// The `(year, month, day)` `days` after 1970-01-01, the inverse of
+/// `days_since_epoch`, after Howard Hinnant's algorithm.
+pub fn civil_date(days: u64) -> (u64, u64, u64) {
+    let z = days + 719468;
+    let era = z / 146097;
+    let day_of_era = z - era * 146097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 -
+                           day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month + 2) / 5 + 1;
+    let month = if month < 10 { month + 3 } else { month - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    return (year, month, day);
+}
+
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(parse("1970-01-01T00:00:00Z"), Some(0));
+        assert_eq!(parse("2018-01-08T15:30:00Z"), Some(1515425400));
+        assert_eq!(parse("2020-02-29T23:59:59Z"), Some(1583020799));
+        assert_eq!(parse("yesterday"), None);
+    }
+
+    #[test]
+    fn test_civil_date() {
+        assert_eq!(civil_date(0), (1970, 1, 1));
+        assert_eq!(civil_date(1515425400 / 86400), (2018, 1, 8));
+        assert_eq!(civil_date(1583020799 / 86400), (2020, 2, 29));
+        for &(year, month, day) in [(2000, 3, 1), (2100, 12, 31), (1999, 2, 28)].iter() {
+            assert_eq!(civil_date(days_since_epoch(year, month, day)), (year, month, day));
+        }
+    }
+}
@@ -0,0 +1,385 @@
+//! Trace a PR event through the services handling it, from the webhook
+//! through evaluation and builds to posting its statuses, exported to
+//! an OpenTelemetry collector over OTLP/HTTP.
+//!
+//! Every job is a span. Its context travels to the jobs it publishes
+//! in their `traceparent` AMQP header, as W3C Trace Context, so their
+//! spans link up into the same trace.
+
+use std::collections::HashMap;
+use std::io::Read;
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use amqp;
+use amqp::protocol::basic::BasicProperties;
+use hyper::Client;
+use hyper::header::{Headers, UserAgent};
+use hyper::net::HttpsConnector;
+use hyper_native_tls::NativeTlsClient;
+use serde_json;
+use uuid::Uuid;
+
+use ofborg;
+use ofborg::config::TracingConfig;
+
+/// The AMQP header the context of the publishing span is carried in.
+pub const HEADER: &'static str = "traceparent";
+
+/// How long finished spans are collected before they're exported.
+const BATCH_SECS: u64 = 5;
+const MAX_BATCH: usize = 512;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TraceContext {
+    /// 32 hex digits.
+    pub trace_id: String,
+    /// 16 hex digits.
+    pub span_id: String,
+}
+
+impl TraceContext {
+    /// A context of a new trace.
+    pub fn new() -> TraceContext {
+        return TraceContext {
+            trace_id: format!("{}", Uuid::new_v4().simple()),
+            span_id: new_span_id(),
+        };
+    }
+
+    /// Like `00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01`.
+    pub fn parse(traceparent: &str) -> Option<TraceContext> {
+        let parts: Vec<&str> = traceparent.trim().split('-').collect();
+        if parts.len() != 4 || parts[0] != "00" {
+            return None;
+        }
+
+        let (trace_id, span_id) = (parts[1], parts[2]);
+        if !is_id(trace_id, 32) || !is_id(span_id, 16) {
+            return None;
+        }
+
+        return Some(TraceContext {
+            trace_id: trace_id.to_owned(),
+            span_id: span_id.to_owned(),
+        });
+    }
+
+    pub fn traceparent(&self) -> String {
+        format!("00-{}-{}-01", self.trace_id, self.span_id)
+    }
+}
+
+/// Valid IDs are lowercase hex digits, which aren't all zero.
+fn is_id(id: &str, len: usize) -> bool {
+    id.len() == len && id.chars().all(|c| c.is_digit(16) && !c.is_uppercase()) &&
+        id.chars().any(|c| c != '0')
+}
+
+fn new_span_id() -> String {
+    format!("{}", Uuid::new_v4().simple())[..16].to_owned()
+}
+
+/// The context a message was published in, if it carries one.
+pub fn extract(props: &BasicProperties) -> Option<TraceContext> {
+    match props.headers.as_ref().and_then(|headers| headers.get(HEADER)) {
+        Some(&amqp::TableEntry::LongString(ref traceparent)) => TraceContext::parse(traceparent),
+        _ => None,
+    }
+}
+
+/// Carry `context` in the headers of a message about to be published.
+pub fn inject(props: &mut BasicProperties, context: &TraceContext) {
+    let mut headers = props.headers.take().unwrap_or(amqp::Table::new());
+    headers.insert(
+        String::from(HEADER),
+        amqp::TableEntry::LongString(context.traceparent()),
+    );
+    props.headers = Some(headers);
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Kind {
+    /// Handling a request, like a webhook.
+    Server,
+    /// Handling a message from a queue.
+    Consumer,
+}
+
+impl Kind {
+    fn otlp(&self) -> u8 {
+        match self {
+            &Kind::Server => 2,
+            &Kind::Consumer => 5,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Span {
+    pub name: String,
+    pub kind: Kind,
+    pub context: TraceContext,
+    pub parent_span_id: Option<String>,
+    pub start_nanos: u64,
+    pub end_nanos: Option<u64>,
+    pub attributes: Vec<(String, String)>,
+    pub failed: bool,
+}
+
+impl Span {
+    pub fn attribute(&mut self, key: &str, value: &str) {
+        self.attributes.push((key.to_owned(), value.to_owned()));
+    }
+
+    pub fn fail(&mut self) {
+        self.failed = true;
+    }
+
+    fn otlp(&self) -> serde_json::Value {
+        let attributes: Vec<serde_json::Value> = self.attributes
+            .iter()
+            .map(|&(ref key, ref value)| attribute(key, value))
+            .collect();
+
+        let code = if self.failed { 2 } else { 1 };
+        let mut span = json!({
+            "traceId": self.context.trace_id,
+            "spanId": self.context.span_id,
+            "name": self.name,
+            "kind": self.kind.otlp(),
+            "startTimeUnixNano": self.start_nanos.to_string(),
+            "endTimeUnixNano": self.end_nanos.unwrap_or(self.start_nanos).to_string(),
+            "attributes": attributes,
+            "status": { "code": code },
+        });
+        if let Some(ref parent) = self.parent_span_id {
+            span["parentSpanId"] = json!(parent);
+        }
+
+        return span;
+    }
+}
+
+fn attribute(key: &str, value: &str) -> serde_json::Value {
+    json!({ "key": key, "value": { "stringValue": value } })
+}
+
+fn now_nanos() -> u64 {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or(
+        Duration::from_secs(0),
+    );
+    return now.as_secs() * 1_000_000_000 + now.subsec_nanos() as u64;
+}
+
+/// Starts the spans of a service, like `builder`, and exports them once
+/// they're finished. Without a collector to export to, contexts are
+/// still passed along so the services after this one can trace.
+#[derive(Clone)]
+pub struct Tracer {
+    service: String,
+    finished: Option<Arc<Mutex<Sender<Span>>>>,
+}
+
+impl Tracer {
+    pub fn disabled(service: &str) -> Tracer {
+        return Tracer {
+            service: service.to_owned(),
+            finished: None,
+        };
+    }
+
+    pub fn new(service: &str, instance: &str, config: &TracingConfig) -> Tracer {
+        let (tx, rx) = channel();
+        let resource = json!({
+            "attributes": [
+                attribute("service.name", service),
+                attribute("service.instance.id", instance),
+                attribute("service.version", ofborg::VERSION),
+            ],
+        });
+        let endpoint = format!("{}/v1/traces", config.otlp_endpoint.trim_right_matches('/'));
+        let headers = config.headers.clone().unwrap_or(HashMap::new());
+
+        info!("Exporting traces to {}", endpoint);
+        thread::spawn(move || export(endpoint, headers, resource, rx));
+
+        return Tracer {
+            service: service.to_owned(),
+            finished: Some(Arc::new(Mutex::new(tx))),
+        };
+    }
+
+    /// A span of this service, continuing the trace of `parent` if
+    /// there's one.
+    pub fn start(&self, kind: Kind, parent: Option<TraceContext>) -> Span {
+        let (context, parent_span_id) = match parent {
+            Some(parent) => (
+                TraceContext {
+                    trace_id: parent.trace_id,
+                    span_id: new_span_id(),
+                },
+                Some(parent.span_id),
+            ),
+            None => (TraceContext::new(), None),
+        };
+
+        return Span {
+            name: self.service.clone(),
+            kind: kind,
+            context: context,
+            parent_span_id: parent_span_id,
+            start_nanos: now_nanos(),
+            end_nanos: None,
+            attributes: vec![],
+            failed: false,
+        };
+    }
+
+    pub fn finish(&self, mut span: Span) {
+        span.end_nanos = Some(now_nanos());
+
+        if let Some(ref finished) = self.finished {
+            let _ = finished.lock().unwrap().send(span);
+        }
+    }
+}
+
+/// The OTLP/JSON request exporting `spans` of `resource`.
+fn otlp_request(resource: &serde_json::Value, spans: &[Span]) -> serde_json::Value {
+    let spans: Vec<serde_json::Value> = spans.iter().map(|span| span.otlp()).collect();
+
+    return json!({
+        "resourceSpans": [{
+            "resource": resource,
+            "scopeSpans": [{
+                "scope": { "name": "ofborg", "version": ofborg::VERSION },
+                "spans": spans,
+            }],
+        }],
+    });
+}
+
+fn export(
+    endpoint: String,
+    extra_headers: HashMap<String, String>,
+    resource: serde_json::Value,
+    finished: Receiver<Span>,
+) {
+    let client = Client::with_connector(HttpsConnector::new(NativeTlsClient::new().unwrap()));
+
+    while let Ok(span) = finished.recv() {
+        thread::sleep(Duration::from_secs(BATCH_SECS));
+        let mut batch = vec![span];
+        batch.extend(finished.try_iter().take(MAX_BATCH - 1));
+
+        let mut headers = Headers::new();
+        headers.set(UserAgent("github.com/grahamc/ofborg".to_owned()));
+        headers.set_raw("Content-Type", vec![b"application/json".to_vec()]);
+        for (name, value) in extra_headers.iter() {
+            headers.set_raw(name.clone(), vec![value.clone().into_bytes()]);
+        }
+
+        let body = otlp_request(&resource, &batch).to_string();
+        let sent = client
+            .post(endpoint.as_str())
+            .headers(headers)
+            .body(body.as_str())
+            .send();
+        match sent {
+            Ok(mut response) => {
+                if !response.status.is_success() {
+                    let mut contents = String::new();
+                    let _ = response.read_to_string(&mut contents);
+                    warn!(
+                        "Dropped {} spans, the collector said {}: {}",
+                        batch.len(),
+                        response.status,
+                        contents
+                    );
+                }
+            }
+            Err(e) => warn!("Dropped {} spans, failed to export them: {:?}", batch.len(), e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_traceparent() {
+        let context =
+            TraceContext::parse("00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01")
+                .unwrap();
+        assert_eq!(context.trace_id, "4bf92f3577b34da6a3ce929d0e0e4736");
+        assert_eq!(context.span_id, "00f067aa0ba902b7");
+        assert_eq!(
+            context.traceparent(),
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01"
+        );
+
+        assert_eq!(TraceContext::parse("00-4bf92f3577b34da6-00f067aa0ba902b7-01"), None);
+        assert_eq!(
+            TraceContext::parse("00-00000000000000000000000000000000-00f067aa0ba902b7-01"),
+            None
+        );
+        assert_eq!(
+            TraceContext::parse("00-4BF92F3577B34DA6A3CE929D0E0E4736-00f067aa0ba902b7-01"),
+            None
+        );
+
+        let new = TraceContext::new();
+        assert_eq!(TraceContext::parse(&new.traceparent()), Some(new));
+    }
+
+    #[test]
+    fn test_headers() {
+        let mut props = BasicProperties {
+            content_type: Some("application/json".to_owned()),
+            ..Default::default()
+        };
+        assert_eq!(extract(&props), None);
+
+        let context = TraceContext::new();
+        inject(&mut props, &context);
+        assert_eq!(extract(&props), Some(context));
+    }
+
+    #[test]
+    fn test_spans() {
+        let tracer = Tracer::disabled("builder");
+        let parent = TraceContext::new();
+
+        let mut span = tracer.start(Kind::Consumer, Some(parent.clone()));
+        assert_eq!(span.context.trace_id, parent.trace_id);
+        assert!(span.context.span_id != parent.span_id);
+        assert_eq!(span.parent_span_id, Some(parent.span_id.clone()));
+
+        span.attribute("messaging.destination", "build-jobs");
+        span.fail();
+        span.end_nanos = Some(span.start_nanos + 1000);
+
+        let request = otlp_request(&json!({}), &[span.clone()]);
+        let exported = &request["resourceSpans"][0]["scopeSpans"][0]["spans"][0];
+        assert_eq!(exported["traceId"], json!(parent.trace_id));
+        assert_eq!(exported["parentSpanId"], json!(parent.span_id));
+        assert_eq!(exported["name"], json!("builder"));
+        assert_eq!(exported["kind"], json!(5));
+        assert_eq!(
+            exported["endTimeUnixNano"],
+            json!((span.start_nanos + 1000).to_string())
+        );
+        assert_eq!(
+            exported["attributes"],
+            json!([{ "key": "messaging.destination", "value": { "stringValue": "build-jobs" } }])
+        );
+        assert_eq!(exported["status"], json!({ "code": 2 }));
+
+        let root = tracer.start(Kind::Server, None);
+        assert_eq!(root.parent_span_id, None);
+        assert!(root.otlp().get("parentSpanId").is_none());
+    }
+}
@@ -0,0 +1,376 @@
+//! Queue builds and evaluations of a PR from other tools, like
+//! nixpkgs-update, through the API. Requests are signed like GitHub signs
+//! webhooks, along with the time they were sent so they can't be
+//! replayed, and turned into the jobs a comment asking for the same
+//! would.
+
+use std::collections::HashMap;
+use serde_json;
+
+use ofborg::acl::{self, Tier};
+use ofborg::commentparser::{BuildFlags, Subset};
+use ofborg::correlation::Correlation;
+use ofborg::message::{Pr, Repo};
+use ofborg::message::buildjob::BuildJob;
+use ofborg::message::massrebuildjob::MassRebuildJob;
+use ofborg::prmetadata::PullRequestMetadata;
+use ofborg::webhook;
+use ofborg::worker;
+
+/// Requests signed longer ago than this, or as far ahead, are refused.
+pub const MAX_REQUEST_AGE_SECS: u64 = 5 * 60;
+
+/// A tool allowed to trigger jobs, and the key it signs with.
+#[derive(Debug, Clone)]
+pub struct Client {
+    pub name: String,
+    pub secret: String,
+    /// The repositories it may trigger on, any when `None`.
+    pub repos: Option<Vec<String>>,
+    pub trusted: bool,
+}
+
+impl Client {
+    fn may_trigger_on(&self, repo: &str) -> bool {
+        match self.repos {
+            Some(ref repos) => repos.iter().any(|r| r.to_lowercase() == repo.to_lowercase()),
+            None => true,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, PartialEq)]
+pub struct Request {
+    /// Like `NixOS/nixpkgs`.
+    pub repo: String,
+    pub pr: u64,
+    /// Attrs to build, if any.
+    #[serde(default)]
+    pub attrs: Vec<String>,
+    /// Build them as NixOS tests rather than nixpkgs attrs.
+    #[serde(default)]
+    pub nixos: bool,
+    #[serde(default)]
+    pub eval: bool,
+}
+
+/// The signatures of the requests accepted lately, so a request can't
+/// be replayed while it is recent enough to pass either.
+pub struct Replays {
+    seen: HashMap<String, u64>,
+}
+
+impl Replays {
+    pub fn new() -> Replays {
+        return Replays { seen: HashMap::new() };
+    }
+
+    /// Remember `signature`, false if it was already accepted.
+    fn remember(&mut self, signature: &str, signed_at: u64, now: u64) -> bool {
+        self.seen.retain(|_, signed_at| *signed_at + MAX_REQUEST_AGE_SECS >= now);
+        if self.seen.contains_key(signature) {
+            return false;
+        }
+
+        self.seen.insert(signature.to_owned(), signed_at);
+        return true;
+    }
+}
+
+/// The client named by `X-Ofborg-Client` and the request it signed in
+/// `X-Ofborg-Signature`, as `sha256=<HMAC of "<X-Ofborg-Timestamp>.<body>">`
+/// where the timestamp is in seconds since the epoch.
+pub fn authenticate<'a>(
+    clients: &'a [Client],
+    replays: &mut Replays,
+    client: Option<&str>,
+    timestamp: Option<&str>,
+    signature: Option<&str>,
+    body: &[u8],
+    now: u64,
+) -> Result<(&'a Client, Request), String> {
+    let name = client.ok_or(String::from("X-Ofborg-Client missing"))?;
+    let timestamp = timestamp.ok_or(String::from("X-Ofborg-Timestamp missing"))?;
+    let signature = signature.ok_or(String::from("X-Ofborg-Signature missing"))?;
+    let client = clients.iter().find(|c| c.name == name).ok_or(format!(
+        "Unknown client {}",
+        name
+    ))?;
+    let signed_at: u64 = timestamp.trim().parse().map_err(|_| {
+        format!("Invalid X-Ofborg-Timestamp {:?}", timestamp)
+    })?;
+    if !webhook::valid_signature(client.secret.as_bytes(), &signed(timestamp, body), signature) {
+        return Err(String::from("Failed to validate signature"));
+    }
+    if signed_at + MAX_REQUEST_AGE_SECS < now || signed_at > now + MAX_REQUEST_AGE_SECS {
+        return Err(format!("The request was signed at {}, too far from now", signed_at));
+    }
+    if !replays.remember(signature, signed_at, now) {
+        return Err(String::from("The request was already accepted"));
+    }
+
+    let request: Request = serde_json::from_slice(body).map_err(|e| {
+        format!("Failed to parse the request: {}", e)
+    })?;
+    if !client.may_trigger_on(&request.repo) {
+        return Err(format!("{} may not trigger on {}", client.name, request.repo));
+    }
+    if !request.eval && request.attrs.len() == 0 {
+        return Err(String::from("Nothing to do, give attrs or eval"));
+    }
+
+    return Ok((client, request));
+}
+
+fn signed(timestamp: &str, body: &[u8]) -> Vec<u8> {
+    let mut signed = format!("{}.", timestamp).into_bytes();
+    signed.extend_from_slice(body);
+    return signed;
+}
+
+/// The jobs the comment filter would publish for the same request, by
+/// `client`, on the PR `pr`. The request is correlated like a webhook.
+pub fn jobs(
+    client: &Client,
+    request: &Request,
+    pr: &PullRequestMetadata,
+    correlation: &Correlation,
+) -> Vec<worker::Action> {
+    let mut parts = request.repo.splitn(2, '/');
+    let owner = parts.next().unwrap_or("");
+    let name = parts.next().unwrap_or("");
+    let repo_msg = Repo {
+        clone_url: pr.clone_url.clone(),
+        full_name: request.repo.clone(),
+        owner: owner.to_owned(),
+        name: name.to_owned(),
+    };
+    let pr_msg = Pr {
+        number: request.pr,
+        head_sha: pr.head_sha.clone(),
+        target_branch: Some(pr.base_ref.clone()),
+    };
+
+    let mut actions: Vec<worker::Action> = vec![];
+    if request.eval {
+        let msg = MassRebuildJob::new(repo_msg.clone(), pr_msg.clone()).correlated(correlation);
+        actions.push(worker::publish_serde_action(
+            None,
+            Some("mass-rebuild-check-jobs".to_owned()),
+            &msg,
+        ));
+    }
+
+    if request.attrs.len() > 0 {
+        let subset = if request.nixos {
+            Subset::NixOS
+        } else {
+            Subset::Nixpkgs
+        };
+        let msg = BuildJob::new(
+            repo_msg,
+            pr_msg,
+            subset,
+            request.attrs.clone(),
+            None,
+            None,
+        ).with_flags(BuildFlags::default())
+            .requested_by(&client.name)
+            .correlated(correlation);
+
+        let tier = if client.trusted { Tier::Trusted } else { Tier::Anyone };
+        for (exchange, routing_key) in acl::build_job_destinations(tier) {
+            actions.push(worker::publish_serde_action(exchange, routing_key, &msg));
+        }
+    }
+
+    for action in actions.iter_mut() {
+        if let &mut worker::Action::Publish(ref mut msg) = action {
+            if let Some(ref mut props) = msg.properties {
+                correlation.inject(props);
+            }
+        }
+    }
+
+    return actions;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ofborg::message::buildjob;
+    use ofborg::prmetadata::PullRequestState;
+
+    fn clients() -> Vec<Client> {
+        vec![
+            Client {
+                name: String::from("nixpkgs-update"),
+                secret: String::from("secret"),
+                repos: Some(vec![String::from("NixOS/nixpkgs")]),
+                trusted: false,
+            },
+        ]
+    }
+
+    const NOW: u64 = 1515425400;
+
+    fn sign(body: &str) -> String {
+        webhook::sign(b"secret", &signed("1515425400", body.as_bytes())).unwrap()
+    }
+
+    fn check(client: Option<&str>, signature: Option<&str>, body: &[u8]) -> Result<(), String> {
+        return authenticate(
+            &clients(),
+            &mut Replays::new(),
+            client,
+            Some("1515425400"),
+            signature,
+            body,
+            NOW,
+        ).map(|_| ());
+    }
+
+    #[test]
+    fn test_authenticate() {
+        let clients = clients();
+        let body = r#"{"repo":"nixos/nixpkgs","pr":12345,"attrs":["hello"]}"#;
+
+        let (client, request) = authenticate(
+            &clients,
+            &mut Replays::new(),
+            Some("nixpkgs-update"),
+            Some("1515425400"),
+            Some(&sign(body)),
+            body.as_bytes(),
+            NOW,
+        ).unwrap();
+        assert_eq!(client.name, "nixpkgs-update");
+        assert_eq!(
+            request,
+            Request {
+                repo: String::from("nixos/nixpkgs"),
+                pr: 12345,
+                attrs: vec![String::from("hello")],
+                nixos: false,
+                eval: false,
+            }
+        );
+
+        assert!(check(Some("nixpkgs-update"), None, body.as_bytes()).is_err());
+        assert!(check(Some("someone"), Some(&sign(body)), body.as_bytes()).is_err());
+        assert!(
+            check(
+                Some("nixpkgs-update"),
+                Some(&sign(body)),
+                br#"{"repo":"nixos/nixpkgs","pr":1,"attrs":["hello"]}"#,
+            ).is_err()
+        );
+
+        let elsewhere = r#"{"repo":"NixOS/nix","pr":1,"eval":true}"#;
+        assert!(
+            check(Some("nixpkgs-update"), Some(&sign(elsewhere)), elsewhere.as_bytes()).is_err()
+        );
+
+        let nothing = r#"{"repo":"NixOS/nixpkgs","pr":1}"#;
+        assert!(check(Some("nixpkgs-update"), Some(&sign(nothing)), nothing.as_bytes()).is_err());
+    }
+
+    #[test]
+    fn test_authenticate_rejects_replays() {
+        let clients = clients();
+        let body = r#"{"repo":"NixOS/nixpkgs","pr":1,"eval":true}"#;
+        let mut replays = Replays::new();
+        let mut authenticate_at = |timestamp: &str, now: u64| {
+            let signature = webhook::sign(b"secret", &signed(timestamp, body.as_bytes())).unwrap();
+            authenticate(
+                &clients,
+                &mut replays,
+                Some("nixpkgs-update"),
+                Some(timestamp),
+                Some(&signature),
+                body.as_bytes(),
+                now,
+            ).map(|_| ())
+        };
+
+        assert!(authenticate_at("1515425400", NOW).is_ok());
+        // The same request again.
+        assert!(authenticate_at("1515425400", NOW + 60).is_err());
+        // Signed too long ago, or too far ahead.
+        assert!(authenticate_at("1515425000", NOW + 600).is_err());
+        assert!(authenticate_at("1515426000", NOW).is_err());
+        // Signed anew.
+        assert!(authenticate_at("1515425460", NOW + 60).is_ok());
+
+        // The timestamp is part of what is signed.
+        let signature = webhook::sign(b"secret", &signed("1515425400", body.as_bytes())).unwrap();
+        assert!(
+            authenticate(
+                &clients,
+                &mut Replays::new(),
+                Some("nixpkgs-update"),
+                Some("1515425460"),
+                Some(&signature),
+                body.as_bytes(),
+                NOW + 60,
+            ).is_err()
+        );
+    }
+
+    #[test]
+    fn test_jobs() {
+        let clients = clients();
+        let pr = PullRequestMetadata {
+            clone_url: String::from("https://github.example.com/NixOS/nixpkgs.git"),
+            state: PullRequestState::Open,
+            title: String::from("hello: 1.0 -> 2.0"),
+            base_ref: String::from("staging"),
+            head_sha: String::from("abc"),
+            mergeable: Some(true),
+            is_draft: false,
+            author: String::from("r-ryantm"),
+            author_association: String::from("CONTRIBUTOR"),
+            labels: vec![],
+            files: vec![],
+        };
+        let request = Request {
+            repo: String::from("NixOS/nixpkgs"),
+            pr: 12345,
+            attrs: vec![String::from("hello")],
+            nixos: false,
+            eval: true,
+        };
+
+        let correlation = Correlation::new(None);
+        let actions = jobs(&clients[0], &request, &pr, &correlation);
+        let destinations: Vec<(Option<String>, Option<String>)> = actions
+            .iter()
+            .map(|action| match action {
+                &worker::Action::Publish(ref msg) => (msg.exchange.clone(), msg.routing_key.clone()),
+                _ => panic!("Not a publish"),
+            })
+            .collect();
+        assert_eq!(
+            destinations,
+            vec![
+                (None, Some(String::from("mass-rebuild-check-jobs"))),
+                (None, Some(String::from("build-inputs-x86_64-linux"))),
+                (None, Some(String::from("build-inputs-aarch64-linux"))),
+            ]
+        );
+
+        let build: BuildJob = match actions[1] {
+            worker::Action::Publish(ref msg) => {
+                let props = msg.properties.as_ref().unwrap();
+                buildjob::from(&msg.content, worker::content_type(props)).unwrap()
+            }
+            _ => panic!("Not a publish"),
+        };
+        assert_eq!(build.attrs, vec![String::from("hello")]);
+        assert_eq!(build.repo.clone_url, "https://github.example.com/NixOS/nixpkgs.git");
+        assert_eq!(build.pr.head_sha, "abc");
+        assert_eq!(build.pr.target_branch, Some(String::from("staging")));
+        assert_eq!(build.requested_by, Some(String::from("nixpkgs-update")));
+        assert_eq!(build.correlation(), correlation);
+    }
+}
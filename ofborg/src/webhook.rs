@@ -0,0 +1,179 @@
+use openssl::hash::MessageDigest;
+use openssl::memcmp;
+use openssl::pkey::PKey;
+use openssl::sign::Signer;
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use serde_json;
+use ofborg::ghevent;
+
+/// A validated webhook, ready to publish to the `github-events` exchange.
+#[derive(Debug, PartialEq)]
+pub struct Delivery {
+    pub routing_key: String,
+    pub body: Vec<u8>,
+}
+
+#[derive(Deserialize, Debug)]
+struct AnyEvent {
+    repository: Option<ghevent::Repository>,
+}
+
+/// Check a `X-Hub-Signature` header, like `sha1=abc...`, against the
+/// payload and the webhook's secret.
+pub fn valid_signature(secret: &[u8], payload: &[u8], signature: &str) -> bool {
+    let mut parts = signature.trim().splitn(2, '=');
+    let digest = match parts.next() {
+        Some("sha1") => MessageDigest::sha1(),
+        Some("sha256") => MessageDigest::sha256(),
+        Some("sha512") => MessageDigest::sha512(),
+        algo => {
+            info!("Unsupported signature algorithm: {:?}", algo);
+            return false;
+        }
+    };
+    let provided = match parts.next() {
+        Some(provided) => provided.to_lowercase(),
+        None => return false,
+    };
+
+    let calculated = match hmac(digest, secret, payload) {
+        Ok(calculated) => calculated,
+        Err(e) => {
+            warn!("Failed to calculate the payload's HMAC: {}", e);
+            return false;
+        }
+    };
+
+    return provided.len() == calculated.len() &&
+        memcmp::eq(provided.as_bytes(), calculated.as_bytes());
+}
+
+/// Check the `X-GitHub-Enterprise-Host` header against the host we
+/// expect webhooks from, if any.
+pub fn valid_audience(audience: Option<&str>, enterprise_host: Option<&str>) -> bool {
+    match audience {
+        Some(audience) => {
+            enterprise_host
+                .map(|host| host.trim().to_lowercase() == audience.to_lowercase())
+                .unwrap_or(false)
+        }
+        None => true,
+    }
+}
+
+/// Sign `payload` like GitHub signs webhooks, as `sha256=abc...`.
+pub fn sign(secret: &[u8], payload: &[u8]) -> Result<String, String> {
+    return hmac(MessageDigest::sha256(), secret, payload).map(|hmac| format!("sha256={}", hmac));
+}
+
+fn hmac(digest: MessageDigest, secret: &[u8], payload: &[u8]) -> Result<String, String> {
+    let key = PKey::hmac(secret).map_err(|e| format!("{:?}", e))?;
+    let mut signer = Signer::new(digest, &key).map_err(|e| format!("{:?}", e))?;
+    signer.update(payload).map_err(|e| format!("{:?}", e))?;
+    let signature = signer.finish().map_err(|e| format!("{:?}", e))?;
+
+    return Ok(
+        signature
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect::<Vec<String>>()
+            .join(""),
+    );
+}
+
+/// Parse an event as one of the `ghevent` types the workers consume,
+/// so malformed events are rejected here instead of in every worker.
+/// Other event types are passed along untouched.
+pub fn normalize(event_type: &str, payload: &[u8]) -> Result<Delivery, String> {
+    let event: AnyEvent = serde_json::from_slice(payload).map_err(|e| {
+        format!("Failed to decode the JSON: {:?}", e)
+    })?;
+    let repository = event.repository.ok_or(String::from(
+        "Dataset does not have a repository",
+    ))?;
+
+    let body = match event_type {
+        "pull_request" => reencode::<ghevent::PullRequestEvent>(payload)?,
+        "issue_comment" => reencode::<ghevent::IssueComment>(payload)?,
+        "push" => reencode::<ghevent::PushEvent>(payload)?,
+        "check_run" => reencode::<ghevent::CheckRunEvent>(payload)?,
+        "check_suite" => reencode::<ghevent::CheckSuiteEvent>(payload)?,
+        _ => payload.to_vec(),
+    };
+
+    return Ok(Delivery {
+        routing_key: format!("{}.{}", event_type, repository.full_name.to_lowercase()),
+        body: body,
+    });
+}
+
+fn reencode<T>(payload: &[u8]) -> Result<Vec<u8>, String>
+where
+    T: Serialize + DeserializeOwned,
+{
+    let event: T = serde_json::from_slice(payload).map_err(|e| {
+        format!("Failed to decode the event: {:?}", e)
+    })?;
+
+    return Ok(serde_json::to_vec(&event).unwrap());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_valid_signature() {
+        assert!(valid_signature(
+            b"secret",
+            b"hello",
+            "sha1=5112055c05f944f85755efc5cd8970e194e9f45b",
+        ));
+        assert!(valid_signature(
+            b"secret",
+            b"hello",
+            "sha256=88aab3ede8d3adf94d26ab90d3bafd4a2083070c3bcce9c014ee04a443847c0b",
+        ));
+        assert!(!valid_signature(
+            b"secret",
+            b"hello!",
+            "sha1=5112055c05f944f85755efc5cd8970e194e9f45b",
+        ));
+        assert!(!valid_signature(
+            b"secret",
+            b"hello",
+            "md5=5112055c05f944f85755efc5cd8970e1",
+        ));
+        assert!(!valid_signature(b"secret", b"hello", "sha1"));
+
+        assert_eq!(
+            sign(b"secret", b"hello").unwrap(),
+            "sha256=88aab3ede8d3adf94d26ab90d3bafd4a2083070c3bcce9c014ee04a443847c0b"
+        );
+    }
+
+    #[test]
+    fn test_valid_audience() {
+        assert!(valid_audience(None, None));
+        assert!(valid_audience(None, Some("github.example.com")));
+        assert!(valid_audience(Some("github.example.com"), Some("GitHub.example.com")));
+        assert!(!valid_audience(Some("github.example.com"), Some("evil.example.com")));
+        assert!(!valid_audience(Some("github.example.com"), None));
+    }
+
+    #[test]
+    fn test_normalize() {
+        let data = include_bytes!("../test-srcs/events/push-master.json");
+
+        let delivery = normalize("push", data).unwrap();
+        assert_eq!(delivery.routing_key, "push.nixos/nixpkgs");
+
+        let push: ghevent::PushEvent = serde_json::from_slice(&delivery.body).unwrap();
+        assert_eq!(push.branch(), Some("master"));
+
+        assert!(normalize("pull_request", data).is_err());
+        assert_eq!(normalize("watch", data).unwrap().body, data.to_vec());
+        assert!(normalize("push", b"{}").is_err());
+    }
+}
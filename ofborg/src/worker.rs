@@ -3,10 +3,19 @@ use amqp::{Consumer, Channel};
 use amqp::protocol::basic::{Deliver, BasicProperties};
 use std::marker::Send;
 use serde::Serialize;
-use serde_json;
+use ofborg::correlation::Correlation;
+use ofborg::easyamqp;
+use ofborg::message::envelope::{self, Encoding, Format, Versioned};
+use ofborg::msgsign::Signer;
+use ofborg::stats;
+use ofborg::tracing;
+use ofborg::logging;
 
 pub struct Worker<T: SimpleWorker> {
     internal: T,
+    jobs: Option<stats::Counter>,
+    tracer: Option<tracing::Tracer>,
+    signer: Option<Signer>,
 }
 
 pub struct Response {}
@@ -31,16 +40,50 @@ pub struct QueueMsg {
     pub content: Vec<u8>,
 }
 
-pub fn publish_serde_action<T: ?Sized>(
+/// Publish `msg` in its envelope, see `message::envelope`.
+pub fn publish_serde_action<T>(
     exchange: Option<String>,
     routing_key: Option<String>,
     msg: &T,
 ) -> Action
 where
-    T: Serialize,
+    T: Versioned + Serialize,
 {
+    return publish_encoded_action(exchange, routing_key, msg, Encoding::Json);
+}
+
+/// Like `publish_serde_action`, taken before messages of a lower
+/// `priority` from a queue declared with `easyamqp::max_priority`.
+pub fn publish_prioritized_action<T>(
+    exchange: Option<String>,
+    routing_key: Option<String>,
+    msg: &T,
+    priority: u8,
+) -> Action
+where
+    T: Versioned + Serialize,
+{
+    let mut action = publish_serde_action(exchange, routing_key, msg);
+    if let Action::Publish(ref mut publish) = action {
+        if let Some(ref mut props) = publish.properties {
+            props.priority = Some(priority);
+        }
+    }
+    return action;
+}
+
+pub fn publish_encoded_action<T>(
+    exchange: Option<String>,
+    routing_key: Option<String>,
+    msg: &T,
+    encoding: Encoding,
+) -> Action
+where
+    T: Versioned + Serialize,
+{
+    let format = Format::published(encoding);
     let props = BasicProperties {
-        content_type: Some("application/json".to_owned()),
+        content_type: Some(format.content_type().to_owned()),
         ..Default::default()
     };
 
@@ -50,10 +93,16 @@ where
         mandatory: false,
         immediate: false,
         properties: Some(props),
-        content: serde_json::to_string(&msg).unwrap().into_bytes(),
+        content: envelope::encode(msg, format),
     });
 }
 
+/// The content type of a message, which says whether it's in the
+/// envelope, see `message::envelope::Format::of`.
+pub fn content_type(props: &BasicProperties) -> Option<&str> {
+    return props.content_type.as_ref().map(|t| t.as_str());
+}
+
 pub trait SimpleWorker: Send + 'static {
     type J: Send;
 
@@ -65,10 +114,86 @@ pub trait SimpleWorker: Send + 'static {
         headers: &BasicProperties,
         body: &Vec<u8>,
     ) -> Result<Self::J, String>;
+
+    /// Register the metrics of this task, if it has any.
+    fn register_metrics(&mut self, _registry: &stats::Registry) {}
 }
 
 pub fn new<T: SimpleWorker>(worker: T) -> Worker<T> {
-    return Worker { internal: worker };
+    return Worker {
+        internal: worker,
+        jobs: None,
+        tracer: None,
+        signer: None,
+    };
+}
+
+impl<T: SimpleWorker> Worker<T> {
+    /// Export how many jobs were handled and the task's own metrics.
+    pub fn with_metrics(mut self, registry: &stats::Registry) -> Worker<T> {
+        self.internal.register_metrics(registry);
+        self.jobs = Some(jobs_counter(registry));
+        return self;
+    }
+
+    /// Trace the jobs, continuing the traces of their messages.
+    pub fn with_tracer(mut self, tracer: &tracing::Tracer) -> Worker<T> {
+        self.tracer = Some(tracer.clone());
+        return self;
+    }
+
+    /// Drop messages which aren't signed by `signer`, and sign the ones
+    /// published.
+    pub fn with_signer(mut self, signer: Option<Signer>) -> Worker<T> {
+        self.signer = signer;
+        return self;
+    }
+}
+
+pub fn jobs_counter(registry: &stats::Registry) -> stats::Counter {
+    registry.counter("ofborg_jobs_total", "Jobs handled, by their outcome")
+}
+
+/// The span of the job of a delivery, named after the task.
+pub fn start_span(
+    tracer: &Option<tracing::Tracer>,
+    method: &Deliver,
+    headers: &BasicProperties,
+) -> Option<tracing::Span> {
+    tracer.as_ref().map(|tracer| {
+        let mut span = tracer.start(tracing::Kind::Consumer, tracing::extract(headers));
+        span.attribute("messaging.destination", &method.exchange);
+        span.attribute("messaging.rabbitmq.routing_key", &method.routing_key);
+        for (key, value) in Correlation::extract(headers).fields() {
+            span.attribute(&format!("ofborg.{}", key), value);
+        }
+        span
+    })
+}
+
+/// How a job ended, by the action which ended it.
+pub fn outcome(action: &Action) -> Option<&'static str> {
+    match action {
+        &Action::Ack => Some("ack"),
+        &Action::NackRequeue => Some("nack_requeue"),
+        &Action::NackDump => Some("nack_dump"),
+        &Action::Publish(_) => None,
+    }
+}
+
+/// What `action` does, for printing it instead of doing it.
+pub fn describe(action: &Action) -> String {
+    match action {
+        &Action::Publish(ref msg) => {
+            format!(
+                "publish to {:?} with routing key {:?}: {}",
+                msg.exchange.clone().unwrap_or(String::new()),
+                msg.routing_key.clone().unwrap_or(String::new()),
+                String::from_utf8_lossy(&msg.content)
+            )
+        }
+        _ => String::from(outcome(action).unwrap_or("")),
+    }
 }
 
 
@@ -81,15 +206,54 @@ impl<T: SimpleWorker + Send> Consumer for Worker<T> {
         headers: BasicProperties,
         body: Vec<u8>,
     ) {
+        let _fields = logging::fields(&[("routing_key", method.routing_key.as_str())]);
+        let correlation = Correlation::extract(&headers);
+        let _correlation_fields = logging::fields(&correlation.fields());
+        let mut span = start_span(&self.tracer, &method, &headers);
+        if let Some(ref signer) = self.signer {
+            let verified =
+                signer.verify(&headers, &method.exchange, &method.routing_key, &body);
+            if let Err(e) = verified {
+                error!("Dropping the message: {}", e);
+                if let Some(ref jobs) = self.jobs {
+                    jobs.inc(&[("outcome", "bad_signature")]);
+                }
+                if let (Some(tracer), Some(mut span)) = (self.tracer.as_ref(), span) {
+                    span.attribute("outcome", "bad_signature");
+                    span.fail();
+                    tracer.finish(span);
+                }
+                channel.basic_nack(method.delivery_tag, false, false).unwrap();
+                return;
+            }
+        }
+
         let job = self.internal.msg_to_job(&method, &headers, &body);
 
         if let Err(e) = job {
             error!("Error decoding job: {:?}", e);
+            if let Some(ref jobs) = self.jobs {
+                jobs.inc(&[("outcome", "decode_failure")]);
+            }
+            if let (Some(tracer), Some(mut span)) = (self.tracer.as_ref(), span) {
+                span.attribute("outcome", "decode_failure");
+                span.fail();
+                tracer.finish(span);
+            }
             channel.basic_ack(method.delivery_tag, false).unwrap();
             return;
         }
 
         for action in self.internal.consumer(&job.unwrap()) {
+            if let (Some(jobs), Some(ended)) = (self.jobs.as_ref(), outcome(&action)) {
+                jobs.inc(&[("outcome", ended)]);
+            }
+            if let (Some(span), Some(ended)) = (span.as_mut(), outcome(&action)) {
+                span.attribute("outcome", ended);
+                if ended == "nack_dump" {
+                    span.fail();
+                }
+            }
             match action {
                 Action::Ack => {
                     channel.basic_ack(method.delivery_tag, false).unwrap();
@@ -104,18 +268,22 @@ impl<T: SimpleWorker + Send> Consumer for Worker<T> {
                         .basic_nack(method.delivery_tag, false, false)
                         .unwrap();
                 }
-                Action::Publish(msg) => {
-                    let exch = msg.exchange.clone().unwrap_or("".to_owned());
-                    let key = msg.routing_key.clone().unwrap_or("".to_owned());
-
-                    let props = msg.properties.unwrap_or(
+                Action::Publish(mut msg) => {
+                    let mut props = msg.properties.take().unwrap_or(
                         BasicProperties { ..Default::default() },
                     );
-                    channel
-                        .basic_publish(exch, key, msg.mandatory, msg.immediate, props, msg.content)
-                        .unwrap();
+                    if let Some(ref span) = span {
+                        tracing::inject(&mut props, &span.context);
+                    }
+                    correlation.inject(&mut props);
+                    msg.properties = Some(props);
+                    easyamqp::publish(channel, &self.signer, msg).unwrap();
                 }
             }
         }
+
+        if let (Some(tracer), Some(span)) = (self.tracer.as_ref(), span) {
+            tracer.finish(span);
+        }
     }
 }
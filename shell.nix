@@ -31,6 +31,7 @@ let
       carnix
       openssl.dev
       pkgconfig
+      cmake
       git
     ] ++ (lib.optional stdenv.isDarwin pkgs.darwin.Security);
 